@@ -0,0 +1,327 @@
+//! Audit logging: request IDs, one summary line per request and redaction
+//!
+//! Every request gets an ID, taken from a well-formed `X-Request-Id` header or
+//! generated, which is echoed in the response and attached to everything logged
+//! while the request is handled. Bodies are only logged at debug level, and only
+//! after redaction: fields holding keys or mnemonics, and any string shaped like
+//! a private key, never reach the log. Failed requests log their error code,
+//! never the response body.
+
+use std::str::FromStr;
+use std::time::Instant;
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue, CONTENT_LENGTH};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage, HttpResponse};
+use serde_json::Value;
+use tracing::{Instrument, Level};
+use tracing_subscriber::EnvFilter;
+use uuid::Uuid;
+
+/// Header carrying the request ID, in both directions
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Longest client-supplied request ID kept; longer ones are replaced
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// Largest body logged at debug level
+const MAX_LOGGED_BODY: usize = 64 * 1024;
+
+/// Fields whose values are never logged, whatever they hold
+const SENSITIVE_FIELDS: [&str; 5] = ["private_key_wif", "private_key", "mnemonic", "passphrase", "seed"];
+
+const REDACTED: &str = "[redacted]";
+
+/// How log lines are written
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Invalid log format {:?}: expected text or json", s)),
+        }
+    }
+}
+
+/// Install the global logger; `RUST_LOG` sets the level, `info` by default
+///
+/// Records from the `log` crate, such as the library's, go through it too.
+pub fn init(format: LogFormat) {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt().with_env_filter(filter);
+    match format {
+        LogFormat::Text => builder.init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
+
+/// Transaction a request produced, in the response extensions
+#[derive(Debug, Clone)]
+pub struct AuditTxid(pub String);
+
+/// Code of the error a request failed with, in the response extensions
+#[derive(Debug, Clone, Copy)]
+pub struct AuditErrorCode(pub &'static str);
+
+/// Record `txid` on the response for the audit line
+pub fn with_txid(mut response: HttpResponse, txid: impl ToString) -> HttpResponse {
+    response.extensions_mut().insert(AuditTxid(txid.to_string()));
+    response
+}
+
+// Client IDs are kept when short and free of anything that could forge log lines
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_REQUEST_ID_LEN
+        && id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+}
+
+// Base58 strings the length of a WIF key, or extended private keys
+fn looks_like_private_key(s: &str) -> bool {
+    const BASE58: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+    let s = s.trim();
+    let base58 = s.chars().all(|c| BASE58.contains(c));
+    let wif = matches!(s.len(), 51 | 52) && s.starts_with(['5', 'K', 'L', '9', 'c']);
+    let xprv = s.len() == 111 && ["xprv", "tprv", "yprv", "zprv", "uprv", "vprv"].iter().any(|p| s.starts_with(p));
+    base58 && (wif || xprv)
+}
+
+/// Replace sensitive values in a JSON document, at any depth
+pub fn redact(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields {
+                if SENSITIVE_FIELDS.contains(&name.to_ascii_lowercase().as_str()) {
+                    *field = REDACTED.into();
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        Value::String(s) if looks_like_private_key(s) => *value = REDACTED.into(),
+        _ => {}
+    }
+}
+
+// A request body as it may be logged: redacted JSON, or only its size
+fn loggable_body(body: &[u8]) -> String {
+    match serde_json::from_slice::<Value>(body) {
+        Ok(mut value) => {
+            redact(&mut value);
+            value.to_string()
+        }
+        Err(_) => format!("<{} bytes, not JSON>", body.len()),
+    }
+}
+
+// Log the body at debug level and put it back for the handler
+async fn log_body(req: &mut ServiceRequest) {
+    let small = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok()?.parse::<usize>().ok())
+        .is_some_and(|len| len > 0 && len <= MAX_LOGGED_BODY);
+    if !small {
+        return;
+    }
+    if let Ok(body) = req.extract::<web::Bytes>().await {
+        tracing::debug!(body = %loggable_body(&body), "request body");
+        req.set_payload(Payload::from(body));
+    }
+}
+
+/// Middleware assigning request IDs and writing the audit line of each request
+pub async fn log_requests(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let id = req
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|id| id.to_str().ok())
+        .filter(|id| is_valid_request_id(id))
+        .map_or_else(|| Uuid::new_v4().to_string(), str::to_string);
+    let span = tracing::info_span!("request", request_id = %id);
+
+    let started = Instant::now();
+    let method = req.method().to_string();
+    // The route template, so addresses and txids in paths stay out of the log
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+    let peer = req.peer_addr().map(|addr| addr.ip().to_string());
+    if tracing::enabled!(Level::DEBUG) {
+        log_body(&mut req).instrument(span.clone()).await;
+    }
+
+    let result = next.call(req).instrument(span.clone()).await;
+    let _entered = span.enter();
+    let latency_ms = started.elapsed().as_millis() as u64;
+    match result {
+        Ok(mut res) => {
+            if let Ok(value) = HeaderValue::from_str(&id) {
+                res.headers_mut().insert(HeaderName::from_static("x-request-id"), value);
+            }
+            let response = res.response();
+            let txid = response.extensions().get::<AuditTxid>().map(|txid| txid.0.clone());
+            let error = response.extensions().get::<AuditErrorCode>().map(|code| code.0);
+            tracing::info!(
+                request_id = %id,
+                method,
+                route,
+                status = res.status().as_u16(),
+                latency_ms,
+                key = %crate::auth::logged_key_name(&res),
+                peer,
+                txid,
+                error,
+                "request"
+            );
+            Ok(res)
+        }
+        Err(e) => {
+            let status = e.as_response_error().status_code().as_u16();
+            tracing::info!(request_id = %id, method, route, status, latency_ms, peer, "request failed");
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{regtest_signing_request, state, LEGACY_WIF, SEGWIT_WIF};
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App};
+    use btcx_lib::BtcNetwork;
+    use serde_json::json;
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    // Log output collected in memory
+    #[derive(Clone, Default)]
+    struct Capture(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Capture {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Capture {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_sign_request_log_is_redacted() {
+        let capture = Capture::default();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_max_level(Level::DEBUG)
+            .with_writer({
+                let capture = capture.clone();
+                move || capture.clone()
+            })
+            .finish();
+        let _default = tracing::subscriber::set_default(subscriber);
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(state(BtcNetwork::Regtest, "http://127.0.0.1:9")))
+                .wrap(from_fn(log_requests))
+                .configure(crate::routes),
+        )
+        .await;
+
+        let req = test::TestRequest::post()
+            .uri("/sign_tx")
+            .insert_header((REQUEST_ID_HEADER, "audit-1"))
+            .set_json(regtest_signing_request())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(REQUEST_ID_HEADER).unwrap(), "audit-1");
+        let body: Value = test::read_body_json(resp).await;
+        let txid = body["txid"].as_str().unwrap();
+
+        // A failing request logs its code; the bad key is still redacted
+        let mut failing = regtest_signing_request();
+        failing["inputs"][1]["private_key_wif"] = "not-a-wif".into();
+        let req = test::TestRequest::post().uri("/sign_tx").set_json(failing).to_request();
+        let resp = test::call_service(&app, req).await;
+        let generated = resp.headers().get(REQUEST_ID_HEADER).unwrap().to_str().unwrap().to_string();
+        assert!(Uuid::parse_str(&generated).is_ok());
+
+        let log = capture.text();
+        assert!(!log.contains(LEGACY_WIF) && !log.contains(SEGWIT_WIF), "{}", log);
+        assert!(!log.contains("not-a-wif"), "{}", log);
+        let lines: Vec<Value> = log.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let summary = |id: &str| {
+            lines
+                .iter()
+                .find(|line| line["fields"]["message"] == "request" && line["fields"]["request_id"] == id)
+                .unwrap_or_else(|| panic!("no audit line for {} in {}", id, log))
+                .clone()
+        };
+        let signed = summary("audit-1");
+        assert_eq!(signed["fields"]["route"], "/sign_tx");
+        assert_eq!(signed["fields"]["status"], 200);
+        assert_eq!(signed["fields"]["txid"], txid);
+        assert_eq!(summary(&generated)["fields"]["error"], "invalid_private_key");
+
+        // The redacted body was logged under the request's span
+        let body = lines
+            .iter()
+            .find(|line| line["fields"]["message"] == "request body" && line["span"]["request_id"] == "audit-1")
+            .unwrap();
+        assert!(body["fields"]["body"].as_str().unwrap().contains(REDACTED));
+    }
+
+    #[actix_web::test]
+    async fn test_redact() {
+        let mut value = json!({
+            "mnemonic": "abandon abandon abandon",
+            "Private_Key_Wif": "anything",
+            "nested": [{ "note": SEGWIT_WIF, "address": "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080" }],
+            "amount": 5,
+        });
+        redact(&mut value);
+        assert_eq!(
+            value,
+            json!({
+                "mnemonic": REDACTED,
+                "Private_Key_Wif": REDACTED,
+                "nested": [{ "note": REDACTED, "address": "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080" }],
+                "amount": 5,
+            })
+        );
+        assert_eq!(loggable_body(LEGACY_WIF.as_bytes()), format!("<{} bytes, not JSON>", LEGACY_WIF.len()));
+    }
+
+    #[actix_web::test]
+    async fn test_request_ids() {
+        assert!(is_valid_request_id("3f2c9a1e-7b4d-4c1a-9e8f-0a1b2c3d4e5f"));
+        assert!(!is_valid_request_id(""));
+        assert!(!is_valid_request_id("forged\nline"));
+        assert!(!is_valid_request_id(&"a".repeat(MAX_REQUEST_ID_LEN + 1)));
+        assert_eq!("JSON".parse::<LogFormat>(), Ok(LogFormat::Json));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+}
@@ -0,0 +1,565 @@
+//! Startup configuration from command-line flags and the environment
+
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use btcx_lib::BtcNetwork;
+
+use crate::audit::LogFormat;
+use crate::auth::{parse_api_keys, ApiKey};
+use crate::cors::CorsPolicy;
+use crate::events::TxEventsConfig;
+use crate::idempotency::IdempotencyConfig;
+use crate::ratelimit::RateLimit;
+use crate::tls::TlsFiles;
+use crate::wallet::{parse_wallets, Wallet};
+
+/// Environment variable selecting the network when `--network` is not given
+pub const NETWORK_ENV: &str = "BTCX_NETWORK";
+/// Environment variable selecting the listen address when `--bind` is not given
+pub const BIND_ENV: &str = "BTCX_BIND";
+/// Environment variable capping broadcast transaction size when `--max-tx-vsize` is not given
+pub const MAX_TX_VSIZE_ENV: &str = "BTCX_MAX_TX_VSIZE";
+/// Environment variable capping /create_tx inputs when `--max-inputs` is not given
+pub const MAX_INPUTS_ENV: &str = "BTCX_MAX_INPUTS";
+/// Environment variable capping /create_tx outputs when `--max-outputs` is not given
+pub const MAX_OUTPUTS_ENV: &str = "BTCX_MAX_OUTPUTS";
+/// Environment variable capping /create_tx/batch items when `--max-batch-size` is not given
+pub const MAX_BATCH_SIZE_ENV: &str = "BTCX_MAX_BATCH_SIZE";
+/// Environment variable setting the shutdown drain time when `--drain-timeout` is not given
+pub const DRAIN_TIMEOUT_ENV: &str = "BTCX_DRAIN_TIMEOUT";
+/// Environment variable choosing `text` or `json` logs when `--log-format` is not given
+pub const LOG_FORMAT_ENV: &str = "BTCX_LOG_FORMAT";
+/// Environment variable holding comma-separated `name:key` API keys
+pub const API_KEYS_ENV: &str = "BTCX_API_KEYS";
+/// Environment variable naming a file of API keys when `--api-keys-file` is not given
+pub const API_KEYS_FILE_ENV: &str = "BTCX_API_KEYS_FILE";
+/// Environment variable limiting local endpoints when `--local-rate-limit` is not given
+pub const LOCAL_RATE_LIMIT_ENV: &str = "BTCX_LOCAL_RATE_LIMIT";
+/// Environment variable limiting backend-bound endpoints when `--upstream-rate-limit` is not given
+pub const UPSTREAM_RATE_LIMIT_ENV: &str = "BTCX_UPSTREAM_RATE_LIMIT";
+/// Environment variable set to `true` to identify clients by `X-Forwarded-For`
+pub const TRUST_FORWARDED_FOR_ENV: &str = "BTCX_TRUST_FORWARDED_FOR";
+/// Environment variable set to `true` to serve /metrics without an API key
+pub const PUBLIC_METRICS_ENV: &str = "BTCX_PUBLIC_METRICS";
+/// Environment variable set to `true` to serve Swagger UI at /docs
+pub const SWAGGER_UI_ENV: &str = "BTCX_SWAGGER_UI";
+/// Environment variable set to `true` to let requests choose another network
+pub const ALLOW_NETWORK_OVERRIDE_ENV: &str = "BTCX_ALLOW_NETWORK_OVERRIDE";
+/// Environment variable listing CORS origins when `--cors-origins` is not given
+pub const CORS_ORIGINS_ENV: &str = "BTCX_CORS_ORIGINS";
+/// Environment variable set to `true` to allow credentialed CORS requests
+pub const CORS_CREDENTIALS_ENV: &str = "BTCX_CORS_CREDENTIALS";
+/// Environment variable setting how often event streams poll the backend, in seconds
+pub const TX_EVENTS_POLL_INTERVAL_ENV: &str = "BTCX_TX_EVENTS_POLL_INTERVAL";
+/// Environment variable capping how long an event stream stays open, in seconds
+pub const TX_EVENTS_MAX_DURATION_ENV: &str = "BTCX_TX_EVENTS_MAX_DURATION";
+/// Environment variable capping how many event streams are open at once
+pub const TX_EVENTS_MAX_STREAMS_ENV: &str = "BTCX_TX_EVENTS_MAX_STREAMS";
+/// Environment variable setting how long idempotency keys are kept, in seconds
+pub const IDEMPOTENCY_TTL_ENV: &str = "BTCX_IDEMPOTENCY_TTL";
+/// Environment variable capping how many idempotency keys are kept
+pub const IDEMPOTENCY_MAX_KEYS_ENV: &str = "BTCX_IDEMPOTENCY_MAX_KEYS";
+/// Environment variable holding comma-separated `name:type:xpub[:branch]` wallets
+pub const WALLETS_ENV: &str = "BTCX_WALLETS";
+/// Environment variable naming the wallet index file when `--wallet-state` is not given
+pub const WALLET_STATE_ENV: &str = "BTCX_WALLET_STATE";
+/// Environment variable naming the PEM certificate chain when `--tls-cert` is not given
+pub const TLS_CERT_ENV: &str = "BTCX_TLS_CERT";
+/// Environment variable naming the PEM private key when `--tls-key` is not given
+pub const TLS_KEY_ENV: &str = "BTCX_TLS_KEY";
+
+const DEFAULT_BIND: &str = "0.0.0.0:8080";
+const DEFAULT_MAX_TX_VSIZE: u64 = 100_000;
+const DEFAULT_MAX_INPUTS: usize = 500;
+const DEFAULT_MAX_OUTPUTS: usize = 500;
+const DEFAULT_MAX_BATCH_SIZE: usize = 100;
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_LOCAL_RATE_LIMIT: RateLimit = RateLimit { per_minute: 600, burst: 60 };
+const DEFAULT_UPSTREAM_RATE_LIMIT: RateLimit = RateLimit { per_minute: 60, burst: 10 };
+
+/// Usage line printed on bad flags
+pub const USAGE: &str =
+    "Usage: bitcoin_tx_api [--network mainnet|testnet|signet|regtest] [--bind <ip:port>] [--workers <n>] [--max-tx-vsize <vbytes>]\n       \
+     [--max-inputs <n>] [--max-outputs <n>] [--max-batch-size <n>] [--drain-timeout <secs>]\n       \
+     [--log-format text|json] [--api-keys-file <path>]\n       \
+     [--local-rate-limit <per-minute>:<burst>|off] [--upstream-rate-limit <per-minute>:<burst>|off] [--trust-forwarded-for]\n       \
+     [--public-metrics] [--swagger-ui] [--allow-network-override]\n       \
+     [--cors-origins <origin,...>|*] [--cors-credentials]\n       \
+     [--tx-events-poll-interval <secs>] [--tx-events-max-duration <secs>] [--tx-events-max-streams <n>]\n       \
+     [--idempotency-ttl <secs>] [--idempotency-max-keys <n>]\n       \
+     [--wallet <name>:<type>:<xpub>[:<branch>]]... [--wallet-state <path>]\n       \
+     [--tls-cert <pem> --tls-key <pem>]";
+
+/// Effective server configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct Config {
+    /// Network the server builds transactions for
+    pub network: BtcNetwork,
+    /// Address to listen on
+    pub bind: SocketAddr,
+    /// Number of worker threads; `None` uses one per CPU core
+    pub workers: Option<usize>,
+    /// Largest transaction accepted for broadcast, in vbytes
+    pub max_tx_vsize: u64,
+    /// Most inputs accepted by /create_tx
+    pub max_inputs: usize,
+    /// Most outputs accepted by /create_tx
+    pub max_outputs: usize,
+    /// Most requests accepted by /create_tx/batch
+    pub max_batch_size: usize,
+    /// How long in-flight requests may run on after a shutdown signal
+    pub drain_timeout: Duration,
+    /// How log lines are written
+    pub log_format: LogFormat,
+    /// Keys clients must present; empty disables authentication
+    pub api_keys: Vec<ApiKey>,
+    /// Per-client limit on endpoints answered locally; `None` disables it
+    pub local_rate_limit: Option<RateLimit>,
+    /// Per-client limit on endpoints that call the backend; `None` disables it
+    pub upstream_rate_limit: Option<RateLimit>,
+    /// Identify clients without a key by the last `X-Forwarded-For` entry
+    pub trust_forwarded_for: bool,
+    /// Serve /metrics without an API key
+    pub public_metrics: bool,
+    /// Serve Swagger UI for /openapi.json at /docs
+    pub swagger_ui: bool,
+    /// Accept a `network` body field or `X-Btcx-Network` header naming another network
+    pub allow_network_override: bool,
+    /// Browser origins allowed to call the API; `None` sends no CORS headers
+    pub cors: Option<CorsPolicy>,
+    /// Backend polling and limits of the /tx event streams
+    pub tx_events: TxEventsConfig,
+    /// Retention of responses to requests with an idempotency key
+    pub idempotency: IdempotencyConfig,
+    /// Accounts served under /wallet/{name}
+    pub wallets: Vec<Wallet>,
+    /// File the next index of each wallet is kept in; `None` keeps it in memory
+    pub wallet_state: Option<PathBuf>,
+    /// Certificate and key to serve HTTPS with; `None` serves plain HTTP
+    pub tls: Option<TlsFiles>,
+}
+
+impl Config {
+    /// Read the configuration from the process arguments and environment
+    pub fn from_env() -> Result<Self, String> {
+        Self::parse(std::env::args().skip(1), |key| std::env::var(key).ok())
+    }
+
+    /// Parse flags, falling back to `lookup` for unset values
+    ///
+    /// Flags take precedence over environment variables.
+    pub fn parse<I, F>(args: I, lookup: F) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = String>,
+        F: Fn(&str) -> Option<String>,
+    {
+        let mut network = None;
+        let mut bind = None;
+        let mut workers = None;
+        let mut max_tx_vsize = None;
+        let mut max_inputs = None;
+        let mut max_outputs = None;
+        let mut max_batch_size = None;
+        let mut drain_timeout = None;
+        let mut log_format = None;
+        let mut api_keys_file = None;
+        let mut local_rate_limit = None;
+        let mut upstream_rate_limit = None;
+        let mut trust_forwarded_for = false;
+        let mut public_metrics = false;
+        let mut swagger_ui = false;
+        let mut allow_network_override = false;
+        let mut cors_origins = None;
+        let mut cors_credentials = false;
+        let mut tx_events_poll_interval = None;
+        let mut tx_events_max_duration = None;
+        let mut tx_events_max_streams = None;
+        let mut idempotency_ttl = None;
+        let mut idempotency_max_keys = None;
+        let mut wallets = Vec::new();
+        let mut wallet_state = None;
+        let mut tls_cert = None;
+        let mut tls_key = None;
+
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let mut value = |flag: &str| args.next().ok_or_else(|| format!("{} requires a value\n{}", flag, USAGE));
+            match arg.as_str() {
+                "--network" => network = Some(value("--network")?),
+                "--bind" => bind = Some(value("--bind")?),
+                "--workers" => workers = Some(value("--workers")?),
+                "--max-tx-vsize" => max_tx_vsize = Some(value("--max-tx-vsize")?),
+                "--max-inputs" => max_inputs = Some(value("--max-inputs")?),
+                "--max-outputs" => max_outputs = Some(value("--max-outputs")?),
+                "--max-batch-size" => max_batch_size = Some(value("--max-batch-size")?),
+                "--drain-timeout" => drain_timeout = Some(value("--drain-timeout")?),
+                "--log-format" => log_format = Some(value("--log-format")?),
+                "--api-keys-file" => api_keys_file = Some(value("--api-keys-file")?),
+                "--local-rate-limit" => local_rate_limit = Some(value("--local-rate-limit")?),
+                "--upstream-rate-limit" => upstream_rate_limit = Some(value("--upstream-rate-limit")?),
+                "--trust-forwarded-for" => trust_forwarded_for = true,
+                "--public-metrics" => public_metrics = true,
+                "--swagger-ui" => swagger_ui = true,
+                "--allow-network-override" => allow_network_override = true,
+                "--cors-origins" => cors_origins = Some(value("--cors-origins")?),
+                "--cors-credentials" => cors_credentials = true,
+                "--tx-events-poll-interval" => tx_events_poll_interval = Some(value("--tx-events-poll-interval")?),
+                "--tx-events-max-duration" => tx_events_max_duration = Some(value("--tx-events-max-duration")?),
+                "--tx-events-max-streams" => tx_events_max_streams = Some(value("--tx-events-max-streams")?),
+                "--idempotency-ttl" => idempotency_ttl = Some(value("--idempotency-ttl")?),
+                "--idempotency-max-keys" => idempotency_max_keys = Some(value("--idempotency-max-keys")?),
+                "--wallet" => wallets.push(value("--wallet")?),
+                "--wallet-state" => wallet_state = Some(value("--wallet-state")?),
+                "--tls-cert" => tls_cert = Some(value("--tls-cert")?),
+                "--tls-key" => tls_key = Some(value("--tls-key")?),
+                other => return Err(format!("Unknown argument: {}\n{}", other, USAGE)),
+            }
+        }
+
+        let network = match network.or_else(|| lookup(NETWORK_ENV)) {
+            Some(network) => network.parse().map_err(|e| format!("Invalid network: {}", e))?,
+            None => BtcNetwork::default(),
+        };
+        let bind = bind
+            .or_else(|| lookup(BIND_ENV))
+            .unwrap_or_else(|| DEFAULT_BIND.to_string());
+        let bind = bind
+            .parse()
+            .map_err(|e| format!("Invalid bind address {:?}: {}", bind, e))?;
+        let workers = match workers {
+            Some(workers) => match workers.parse::<usize>() {
+                Ok(n) if n > 0 => Some(n),
+                _ => return Err(format!("Invalid worker count {:?}: expected a positive integer", workers)),
+            },
+            None => None,
+        };
+
+        let max_tx_vsize = match max_tx_vsize.or_else(|| lookup(MAX_TX_VSIZE_ENV)) {
+            Some(vsize) => match vsize.parse::<u64>() {
+                Ok(n) if n > 0 => n,
+                _ => return Err(format!("Invalid max transaction size {:?}: expected a positive integer", vsize)),
+            },
+            None => DEFAULT_MAX_TX_VSIZE,
+        };
+        let max_inputs = match max_inputs.or_else(|| lookup(MAX_INPUTS_ENV)) {
+            Some(n) => positive_count("input", &n)?,
+            None => DEFAULT_MAX_INPUTS,
+        };
+        let max_outputs = match max_outputs.or_else(|| lookup(MAX_OUTPUTS_ENV)) {
+            Some(n) => positive_count("output", &n)?,
+            None => DEFAULT_MAX_OUTPUTS,
+        };
+        let max_batch_size = match max_batch_size.or_else(|| lookup(MAX_BATCH_SIZE_ENV)) {
+            Some(n) => positive_count("batch", &n)?,
+            None => DEFAULT_MAX_BATCH_SIZE,
+        };
+        let drain_timeout = match drain_timeout.or_else(|| lookup(DRAIN_TIMEOUT_ENV)) {
+            Some(secs) => match secs.parse::<u64>() {
+                Ok(n) => Duration::from_secs(n),
+                Err(_) => return Err(format!("Invalid drain timeout {:?}: expected a number of seconds", secs)),
+            },
+            None => DEFAULT_DRAIN_TIMEOUT,
+        };
+        let log_format = match log_format.or_else(|| lookup(LOG_FORMAT_ENV)) {
+            Some(format) => format.parse()?,
+            None => LogFormat::default(),
+        };
+
+        // Keys from the file and the environment are combined
+        let mut keys = lookup(API_KEYS_ENV).unwrap_or_default();
+        if let Some(path) = api_keys_file.or_else(|| lookup(API_KEYS_FILE_ENV)) {
+            let file = std::fs::read_to_string(&path)
+                .map_err(|e| format!("Cannot read API keys file {}: {}", path, e))?;
+            keys.push('\n');
+            keys.push_str(&file);
+        }
+        let api_keys = parse_api_keys(&keys)?;
+
+        let rate_limit = |value: Option<String>, default| match value.as_deref() {
+            Some("off") => Ok(None),
+            Some(limit) => limit.parse().map(Some),
+            None => Ok(Some(default)),
+        };
+        let local_rate_limit =
+            rate_limit(local_rate_limit.or_else(|| lookup(LOCAL_RATE_LIMIT_ENV)), DEFAULT_LOCAL_RATE_LIMIT)?;
+        let upstream_rate_limit =
+            rate_limit(upstream_rate_limit.or_else(|| lookup(UPSTREAM_RATE_LIMIT_ENV)), DEFAULT_UPSTREAM_RATE_LIMIT)?;
+        let trust_forwarded_for = trust_forwarded_for || env_flag(&lookup, TRUST_FORWARDED_FOR_ENV)?;
+        let public_metrics = public_metrics || env_flag(&lookup, PUBLIC_METRICS_ENV)?;
+        let swagger_ui = swagger_ui || env_flag(&lookup, SWAGGER_UI_ENV)?;
+        let allow_network_override = allow_network_override || env_flag(&lookup, ALLOW_NETWORK_OVERRIDE_ENV)?;
+
+        let cors_credentials = cors_credentials || env_flag(&lookup, CORS_CREDENTIALS_ENV)?;
+        let cors = match cors_origins.or_else(|| lookup(CORS_ORIGINS_ENV)) {
+            Some(origins) => Some(CorsPolicy::parse(&origins, cors_credentials)?),
+            None if cors_credentials => return Err("CORS credentials need --cors-origins".to_string()),
+            None => None,
+        };
+
+        let defaults = TxEventsConfig::default();
+        let seconds = |what: &str, value: Option<String>, default: Duration| match value {
+            Some(secs) => match secs.parse::<u64>() {
+                Ok(n) if n > 0 => Ok(Duration::from_secs(n)),
+                _ => Err(format!("Invalid {} {:?}: expected a positive number of seconds", what, secs)),
+            },
+            None => Ok(default),
+        };
+        let tx_events = TxEventsConfig {
+            poll_interval: seconds(
+                "event poll interval",
+                tx_events_poll_interval.or_else(|| lookup(TX_EVENTS_POLL_INTERVAL_ENV)),
+                defaults.poll_interval,
+            )?,
+            max_duration: seconds(
+                "event stream duration",
+                tx_events_max_duration.or_else(|| lookup(TX_EVENTS_MAX_DURATION_ENV)),
+                defaults.max_duration,
+            )?,
+            max_streams: match tx_events_max_streams.or_else(|| lookup(TX_EVENTS_MAX_STREAMS_ENV)) {
+                Some(n) => positive_count("event stream", &n)?,
+                None => defaults.max_streams,
+            },
+        };
+        let idempotency = IdempotencyConfig {
+            ttl: seconds(
+                "idempotency key lifetime",
+                idempotency_ttl.or_else(|| lookup(IDEMPOTENCY_TTL_ENV)),
+                IdempotencyConfig::default().ttl,
+            )?,
+            max_keys: match idempotency_max_keys.or_else(|| lookup(IDEMPOTENCY_MAX_KEYS_ENV)) {
+                Some(n) => positive_count("idempotency key", &n)?,
+                None => IdempotencyConfig::default().max_keys,
+            },
+        };
+
+        // Wallets from flags and the environment are combined; keys for another
+        // network stop startup rather than derive unusable addresses
+        let wallets = parse_wallets(&format!("{}\n{}", lookup(WALLETS_ENV).unwrap_or_default(), wallets.join("\n")), network)?;
+        let wallet_state = wallet_state.or_else(|| lookup(WALLET_STATE_ENV)).map(PathBuf::from);
+
+        let tls = match (tls_cert.or_else(|| lookup(TLS_CERT_ENV)), tls_key.or_else(|| lookup(TLS_KEY_ENV))) {
+            (Some(cert), Some(key)) => Some(TlsFiles { cert: cert.into(), key: key.into() }),
+            (None, None) => None,
+            _ => return Err("--tls-cert and --tls-key must be given together".to_string()),
+        };
+
+        Ok(Config {
+            network,
+            bind,
+            workers,
+            max_tx_vsize,
+            max_inputs,
+            max_outputs,
+            max_batch_size,
+            drain_timeout,
+            log_format,
+            api_keys,
+            local_rate_limit,
+            upstream_rate_limit,
+            trust_forwarded_for,
+            public_metrics,
+            swagger_ui,
+            allow_network_override,
+            cors,
+            tx_events,
+            idempotency,
+            wallets,
+            wallet_state,
+            tls,
+        })
+    }
+}
+
+// Parse a positive input, output or batch limit
+fn positive_count(kind: &str, value: &str) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("Invalid max {} count {:?}: expected a positive integer", kind, value)),
+    }
+}
+
+// Read a boolean environment variable, unset meaning false
+fn env_flag<F: Fn(&str) -> Option<String>>(lookup: &F, key: &str) -> Result<bool, String> {
+    match lookup(key).as_deref() {
+        None | Some("false") | Some("0") => Ok(false),
+        Some("true") | Some("1") => Ok(true),
+        Some(other) => Err(format!("Invalid {} value {:?}: expected true or false", key, other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cors::AllowedOrigins;
+    use std::collections::HashMap;
+
+    fn parse(args: &[&str], env: &[(&str, &str)]) -> Result<Config, String> {
+        let env: HashMap<String, String> = env.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        Config::parse(args.iter().map(|a| a.to_string()), |key| env.get(key).cloned())
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = parse(&[], &[]).unwrap();
+        assert_eq!(config.network, BtcNetwork::Bitcoin);
+        assert_eq!(config.bind, "0.0.0.0:8080".parse().unwrap());
+        assert_eq!(config.workers, None);
+        assert_eq!(config.max_tx_vsize, 100_000);
+        assert_eq!((config.max_inputs, config.max_outputs), (500, 500));
+        assert_eq!(config.max_batch_size, 100);
+        assert_eq!(config.drain_timeout, Duration::from_secs(30));
+        assert_eq!(config.log_format, LogFormat::Text);
+        assert!(config.api_keys.is_empty());
+        assert_eq!(config.local_rate_limit, Some(RateLimit { per_minute: 600, burst: 60 }));
+        assert_eq!(config.upstream_rate_limit, Some(RateLimit { per_minute: 60, burst: 10 }));
+        assert!(!config.trust_forwarded_for);
+        assert!(!config.public_metrics);
+        assert!(!config.swagger_ui);
+        assert!(!config.allow_network_override);
+        assert_eq!(config.cors, None);
+        assert_eq!(config.tx_events, TxEventsConfig::default());
+        assert_eq!(config.idempotency, IdempotencyConfig::default());
+        assert!(config.wallets.is_empty());
+        assert_eq!(config.wallet_state, None);
+        assert_eq!(config.tls, None);
+    }
+
+    #[test]
+    fn test_tls_needs_cert_and_key() {
+        let config = parse(&["--tls-cert", "cert.pem"], &[(TLS_KEY_ENV, "key.pem")]).unwrap();
+        assert_eq!(config.tls, Some(TlsFiles { cert: "cert.pem".into(), key: "key.pem".into() }));
+        assert!(parse(&["--tls-cert", "cert.pem"], &[]).unwrap_err().contains("must be given together"));
+        assert!(parse(&[], &[(TLS_KEY_ENV, "key.pem")]).is_err());
+    }
+
+    #[test]
+    fn test_cors() {
+        let config = parse(&["--cors-origins", "https://wallet.example.com"], &[(CORS_CREDENTIALS_ENV, "true")]).unwrap();
+        let cors = config.cors.unwrap();
+        assert!(cors.allow_credentials);
+        assert_eq!(cors.origins, AllowedOrigins::List(vec!["https://wallet.example.com".to_string()]));
+
+        // Bad origins stop startup instead of being skipped
+        assert!(parse(&[], &[(CORS_ORIGINS_ENV, "https://wallet.example.com,wallet2")]).unwrap_err().contains("Invalid CORS origin"));
+        assert!(parse(&["--cors-origins", "*", "--cors-credentials"], &[]).is_err());
+        assert!(parse(&["--cors-credentials"], &[]).is_err());
+    }
+
+    #[test]
+    fn test_rate_limits() {
+        let env = [(UPSTREAM_RATE_LIMIT_ENV, "30:5"), (TRUST_FORWARDED_FOR_ENV, "true")];
+        let config = parse(&["--local-rate-limit", "off"], &env).unwrap();
+        assert_eq!(config.local_rate_limit, None);
+        assert_eq!(config.upstream_rate_limit, Some(RateLimit { per_minute: 30, burst: 5 }));
+        assert!(config.trust_forwarded_for);
+
+        assert!(parse(&["--trust-forwarded-for"], &[]).unwrap().trust_forwarded_for);
+        assert!(parse(&["--public-metrics"], &[]).unwrap().public_metrics);
+        assert!(parse(&[], &[(PUBLIC_METRICS_ENV, "1")]).unwrap().public_metrics);
+        assert!(parse(&["--swagger-ui"], &[]).unwrap().swagger_ui);
+        assert!(parse(&[], &[(SWAGGER_UI_ENV, "true")]).unwrap().swagger_ui);
+        assert!(parse(&["--allow-network-override"], &[]).unwrap().allow_network_override);
+        assert!(parse(&[], &[(ALLOW_NETWORK_OVERRIDE_ENV, "true")]).unwrap().allow_network_override);
+        assert!(parse(&["--upstream-rate-limit", "30"], &[]).unwrap_err().contains("Invalid rate limit"));
+        assert!(parse(&[], &[(TRUST_FORWARDED_FOR_ENV, "yes")]).is_err());
+    }
+
+    #[test]
+    fn test_api_keys_from_env_and_file() {
+        let path = std::env::temp_dir().join(format!("btcx-api-keys-{}", std::process::id()));
+        std::fs::write(&path, "# deploy keys\nci:ci-0123456789abcdef\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        let config = parse(&["--api-keys-file", path], &[(API_KEYS_ENV, "web:web-0123456789abcdef")]).unwrap();
+        let names: Vec<_> = config.api_keys.iter().map(ApiKey::name).collect();
+        assert_eq!(names, ["web", "ci"]);
+        assert_eq!(parse(&[], &[(API_KEYS_FILE_ENV, path)]).unwrap().api_keys.len(), 1);
+        std::fs::remove_file(path).unwrap();
+
+        assert!(parse(&["--api-keys-file", path], &[]).unwrap_err().contains("Cannot read API keys file"));
+        assert!(parse(&[], &[(API_KEYS_ENV, "web:short")]).is_err());
+    }
+
+    #[test]
+    fn test_tx_events() {
+        let env = [(TX_EVENTS_POLL_INTERVAL_ENV, "30"), (TX_EVENTS_MAX_STREAMS_ENV, "5")];
+        let config = parse(&["--tx-events-poll-interval", "2", "--tx-events-max-duration", "600"], &env).unwrap();
+        let expected = TxEventsConfig { poll_interval: Duration::from_secs(2), max_duration: Duration::from_secs(600), max_streams: 5 };
+        assert_eq!(config.tx_events, expected);
+
+        assert!(parse(&["--tx-events-poll-interval", "0"], &[]).unwrap_err().contains("Invalid event poll interval"));
+        assert!(parse(&[], &[(TX_EVENTS_MAX_DURATION_ENV, "forever")]).is_err());
+        assert!(parse(&["--tx-events-max-streams", "0"], &[]).unwrap_err().contains("Invalid max event stream count"));
+    }
+
+    #[test]
+    fn test_idempotency() {
+        let config = parse(&["--idempotency-ttl", "600"], &[(IDEMPOTENCY_MAX_KEYS_ENV, "50")]).unwrap();
+        assert_eq!(config.idempotency, IdempotencyConfig { ttl: Duration::from_secs(600), max_keys: 50 });
+
+        assert!(parse(&["--idempotency-ttl", "0"], &[]).unwrap_err().contains("Invalid idempotency key lifetime"));
+        assert!(parse(&[], &[(IDEMPOTENCY_MAX_KEYS_ENV, "none")]).unwrap_err().contains("Invalid max idempotency key count"));
+    }
+
+    #[test]
+    fn test_wallets() {
+        // BIP 32 test vector 1, m/0'
+        let xpub = "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw";
+        let change = format!("change:p2pkh:{}:1", xpub);
+        let env = [(WALLETS_ENV, change.as_str()), (WALLET_STATE_ENV, "/var/lib/btcx/wallets.json")];
+        let config = parse(&["--wallet", &format!("shop:p2wpkh:{}", xpub)], &env).unwrap();
+        let names: Vec<_> = config.wallets.iter().map(Wallet::name).collect();
+        assert_eq!(names, ["change", "shop"]);
+        assert_eq!(config.wallet_state, Some(PathBuf::from("/var/lib/btcx/wallets.json")));
+
+        let error = parse(&["--network", "testnet", "--wallet", &format!("shop:p2wpkh:{}", xpub)], &[]).unwrap_err();
+        assert!(error.contains("Wallet \"shop\""), "{}", error);
+        assert!(parse(&["--wallet", "shop:p2wpkh:xpub-nonsense"], &[]).is_err());
+    }
+
+    #[test]
+    fn test_flags_override_env() {
+        let env = [(NETWORK_ENV, "signet"), (BIND_ENV, "127.0.0.1:9000")];
+        let config = parse(&[], &env).unwrap();
+        assert_eq!(config.network, BtcNetwork::Signet);
+        assert_eq!(config.bind, "127.0.0.1:9000".parse().unwrap());
+
+        let config = parse(&["--network", "testnet", "--bind", "127.0.0.1:8081", "--workers", "2"], &env).unwrap();
+        assert_eq!(config.network, BtcNetwork::Testnet);
+        assert_eq!(config.bind, "127.0.0.1:8081".parse().unwrap());
+        assert_eq!(config.workers, Some(2));
+
+        let config = parse(&["--max-tx-vsize", "400"], &[(MAX_TX_VSIZE_ENV, "500")]).unwrap();
+        assert_eq!(config.max_tx_vsize, 400);
+
+        let config = parse(&["--max-inputs", "20"], &[(MAX_INPUTS_ENV, "10"), (MAX_OUTPUTS_ENV, "5")]).unwrap();
+        assert_eq!((config.max_inputs, config.max_outputs), (20, 5));
+
+        let config = parse(&["--max-batch-size", "10"], &[(MAX_BATCH_SIZE_ENV, "50")]).unwrap();
+        assert_eq!(config.max_batch_size, 10);
+
+        // Zero aborts in-flight requests at once
+        let config = parse(&["--drain-timeout", "0"], &[(DRAIN_TIMEOUT_ENV, "60")]).unwrap();
+        assert_eq!(config.drain_timeout, Duration::ZERO);
+
+        let config = parse(&["--log-format", "json"], &[(LOG_FORMAT_ENV, "text")]).unwrap();
+        assert_eq!(config.log_format, LogFormat::Json);
+    }
+
+    #[test]
+    fn test_misconfiguration_is_rejected() {
+        assert!(parse(&["--network", "moonnet"], &[]).unwrap_err().contains("Invalid network"));
+        assert!(parse(&[], &[(NETWORK_ENV, "moonnet")]).is_err());
+        assert!(parse(&["--bind", "localhost"], &[]).unwrap_err().contains("Invalid bind address"));
+        assert!(parse(&["--workers", "0"], &[]).is_err());
+        assert!(parse(&[], &[(MAX_TX_VSIZE_ENV, "lots")]).is_err());
+        assert!(parse(&["--max-outputs", "0"], &[]).unwrap_err().contains("Invalid max output count"));
+        assert!(parse(&[], &[(MAX_BATCH_SIZE_ENV, "0")]).unwrap_err().contains("Invalid max batch count"));
+        assert!(parse(&[], &[(DRAIN_TIMEOUT_ENV, "-1")]).unwrap_err().contains("Invalid drain timeout"));
+        assert!(parse(&[], &[(LOG_FORMAT_ENV, "xml")]).unwrap_err().contains("Invalid log format"));
+        assert!(parse(&["--workers"], &[]).unwrap_err().contains("requires a value"));
+        assert!(parse(&["--verbose"], &[]).unwrap_err().contains("Unknown argument"));
+    }
+}
@@ -0,0 +1,257 @@
+//! Cross-origin resource sharing for browser clients
+//!
+//! Only configured origins get CORS headers; the wildcard must be asked for
+//! explicitly and cannot be combined with credentials.
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::header::{
+    HeaderMap, HeaderValue, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+    ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+
+use crate::error::ApiError;
+
+/// Methods the endpoints accept
+const ALLOWED_METHODS: &str = "GET, POST, OPTIONS";
+/// Request headers clients may send
+const ALLOWED_HEADERS: [&str; 4] = ["content-type", "x-api-key", "authorization", "x-btcx-network"];
+/// Response headers scripts may read
+const EXPOSED_HEADERS: &str = "Retry-After, X-Btcx-Network";
+/// How long browsers may cache a preflight answer, in seconds
+const MAX_AGE_SECS: u32 = 600;
+
+/// Origins allowed to call the API from a browser
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AllowedOrigins {
+    /// Any origin; meant for development
+    Any,
+    /// Exact `scheme://host[:port]` origins
+    List(Vec<String>),
+}
+
+/// CORS settings
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorsPolicy {
+    /// Origins to answer with CORS headers
+    pub origins: AllowedOrigins,
+    /// Let browsers send cookies and HTTP authentication
+    pub allow_credentials: bool,
+}
+
+impl CorsPolicy {
+    /// Parse a comma-separated origin list, or `*` alone for any origin
+    ///
+    /// Fails on anything that is not a bare `http` or `https` origin.
+    pub fn parse(origins: &str, allow_credentials: bool) -> Result<Self, String> {
+        let origins = if origins.trim() == "*" {
+            if allow_credentials {
+                return Err("CORS credentials cannot be allowed for the * origin".to_string());
+            }
+            AllowedOrigins::Any
+        } else {
+            let list = origins
+                .split(',')
+                .map(|origin| validate_origin(origin.trim()))
+                .collect::<Result<Vec<_>, _>>()?;
+            AllowedOrigins::List(list)
+        };
+        Ok(CorsPolicy { origins, allow_credentials })
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        match &self.origins {
+            AllowedOrigins::Any => true,
+            AllowedOrigins::List(list) => list.iter().any(|allowed| allowed.eq_ignore_ascii_case(origin)),
+        }
+    }
+
+    // Headers common to preflight and actual responses
+    fn add_headers(&self, headers: &mut HeaderMap, origin: &HeaderValue) {
+        match self.origins {
+            AllowedOrigins::Any => {
+                headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+            }
+            AllowedOrigins::List(_) => {
+                headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin.clone());
+                headers.append(VARY, HeaderValue::from_static("Origin"));
+            }
+        }
+        if self.allow_credentials {
+            headers.insert(ACCESS_CONTROL_ALLOW_CREDENTIALS, HeaderValue::from_static("true"));
+        }
+    }
+}
+
+// Check an origin is `http(s)://host[:port]` and normalize its case
+fn validate_origin(origin: &str) -> Result<String, String> {
+    let invalid = |reason: &str| format!("Invalid CORS origin {:?}: {}", origin, reason);
+    let rest = origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))
+        .ok_or_else(|| invalid("expected http:// or https://"))?;
+    if rest.contains(['/', '?', '#', '@', '*', ' ']) {
+        return Err(invalid("expected scheme, host and optional port only"));
+    }
+    // IPv6 hosts are bracketed and contain colons themselves
+    let (host, port) = match rest.strip_prefix('[') {
+        Some(v6) => {
+            let (host, after) = v6.split_once(']').ok_or_else(|| invalid("unclosed IPv6 bracket"))?;
+            match after {
+                "" => (host, None),
+                _ => (host, Some(after.strip_prefix(':').ok_or_else(|| invalid("bad port"))?)),
+            }
+        }
+        None => match rest.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (rest, None),
+        },
+    };
+    if port.is_some_and(|port| port.parse::<u16>().is_err()) {
+        return Err(invalid("bad port"));
+    }
+    if host.is_empty() {
+        return Err(invalid("missing host"));
+    }
+    Ok(origin.to_ascii_lowercase())
+}
+
+/// Middleware answering preflight requests and tagging responses for allowed origins
+///
+/// Runs before authentication, since browsers send preflights without keys.
+pub async fn cors(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let policy = req.app_data::<web::Data<CorsPolicy>>().cloned();
+    let origin = req.headers().get(ORIGIN).cloned();
+    let (Some(policy), Some(origin)) = (policy, origin) else {
+        return next.call(req).await.map(ServiceResponse::map_into_left_body);
+    };
+    let allowed = origin.to_str().is_ok_and(|o| policy.allows(o));
+
+    let preflight = req.method() == Method::OPTIONS && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD);
+    if preflight {
+        let method_ok = req
+            .headers()
+            .get(ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|m| m.to_str().ok())
+            .is_some_and(|m| m == "GET" || m == "POST");
+        let headers_ok = req
+            .headers()
+            .get(ACCESS_CONTROL_REQUEST_HEADERS)
+            .map_or(Some(true), |h| {
+                let h = h.to_str().ok()?;
+                Some(h.split(',').map(str::trim).filter(|h| !h.is_empty()).all(|h| {
+                    ALLOWED_HEADERS.iter().any(|allowed| allowed.eq_ignore_ascii_case(h))
+                }))
+            })
+            .unwrap_or(false);
+        if !(allowed && method_ok && headers_ok) {
+            let error = ApiError::new(StatusCode::FORBIDDEN, "cors_rejected", "Cross-origin request not allowed");
+            return Ok(req.error_response(error).map_into_right_body());
+        }
+        let mut response = HttpResponse::NoContent()
+            .insert_header((ACCESS_CONTROL_ALLOW_METHODS, ALLOWED_METHODS))
+            .insert_header((ACCESS_CONTROL_ALLOW_HEADERS, "Content-Type, X-Api-Key, Authorization"))
+            .insert_header((ACCESS_CONTROL_MAX_AGE, MAX_AGE_SECS))
+            .finish();
+        policy.add_headers(response.headers_mut(), &origin);
+        return Ok(req.into_response(response).map_into_right_body());
+    }
+
+    let mut response = next.call(req).await?;
+    if allowed {
+        let headers = response.headers_mut();
+        policy.add_headers(headers, &origin);
+        headers.insert(ACCESS_CONTROL_EXPOSE_HEADERS, HeaderValue::from_static(EXPOSED_HEADERS));
+    }
+    Ok(response.map_into_left_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App};
+
+    const WALLET: &str = "https://wallet.example.com";
+
+    macro_rules! app {
+        ($policy:expr) => {
+            test::init_service(
+                App::new()
+                    .app_data(web::Data::new($policy))
+                    .wrap(from_fn(cors))
+                    .route("/decode_tx", web::post().to(HttpResponse::Ok)),
+            )
+            .await
+        };
+    }
+
+    fn preflight(origin: &str) -> test::TestRequest {
+        test::TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/decode_tx")
+            .insert_header((ORIGIN, origin))
+            .insert_header((ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+            .insert_header((ACCESS_CONTROL_REQUEST_HEADERS, "content-type, x-api-key"))
+    }
+
+    #[actix_web::test]
+    async fn test_preflight() {
+        let app = app!(CorsPolicy::parse(&format!("{}, http://localhost:3000", WALLET), false).unwrap());
+
+        let resp = test::call_service(&app, preflight(WALLET).to_request()).await;
+        assert_eq!(resp.status(), 204);
+        let headers = resp.headers();
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), WALLET);
+        assert_eq!(headers.get(ACCESS_CONTROL_ALLOW_METHODS).unwrap(), ALLOWED_METHODS);
+        assert_eq!(headers.get(ACCESS_CONTROL_MAX_AGE).unwrap(), "600");
+        assert_eq!(headers.get(VARY).unwrap(), "Origin");
+        assert!(headers.get(ACCESS_CONTROL_ALLOW_CREDENTIALS).is_none());
+
+        let resp = test::call_service(&app, preflight("https://evil.example.com").to_request()).await;
+        assert_eq!(resp.status(), 403);
+        assert!(resp.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+        // The network header may be sent as well as read
+        let req = preflight(WALLET).insert_header((ACCESS_CONTROL_REQUEST_HEADERS, "content-type, X-Btcx-Network")).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 204);
+
+        // Headers outside the allow-list fail the preflight too
+        let req = preflight(WALLET).insert_header((ACCESS_CONTROL_REQUEST_HEADERS, "x-debug")).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 403);
+    }
+
+    #[actix_web::test]
+    async fn test_actual_requests() {
+        let app = app!(CorsPolicy::parse(WALLET, true).unwrap());
+        let req = test::TestRequest::post().uri("/decode_tx").insert_header((ORIGIN, WALLET)).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), WALLET);
+        assert_eq!(resp.headers().get(ACCESS_CONTROL_ALLOW_CREDENTIALS).unwrap(), "true");
+
+        let req = test::TestRequest::post().uri("/decode_tx").insert_header((ORIGIN, "http://other")).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).is_none());
+
+        let app = app!(CorsPolicy::parse("*", false).unwrap());
+        let req = preflight("http://anything.test").to_request();
+        assert_eq!(test::call_service(&app, req).await.headers().get(ACCESS_CONTROL_ALLOW_ORIGIN).unwrap(), "*");
+    }
+
+    #[actix_web::test]
+    async fn test_invalid_origins_are_rejected() {
+        for origins in ["wallet.example.com", "https://wallet.example.com/", "https://", "https://a:99999", "https://*.example.com", "*, https://a"] {
+            assert!(CorsPolicy::parse(origins, false).is_err(), "{}", origins);
+        }
+        assert!(CorsPolicy::parse("*", true).is_err());
+        assert!(CorsPolicy::parse("http://[::1]:8080, http://[::1], http://127.0.0.1:3000", false).is_ok());
+    }
+}
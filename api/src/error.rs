@@ -0,0 +1,239 @@
+//! Error responses shared by every endpoint
+//!
+//! Errors serialize as `{"error": {"code": ..., "message": ..., "details": ...}}`.
+//! Codes are stable and meant for clients to branch on; messages are for humans.
+
+use std::fmt;
+
+use actix_web::error::{JsonPayloadError, PathError, QueryPayloadError};
+use actix_web::http::header::RETRY_AFTER;
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use btcx_lib::{Amount, Error};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::audit::AuditErrorCode;
+
+/// Seconds clients are asked to wait when the backend is throttling us
+pub const UPSTREAM_RETRY_AFTER_SECS: u32 = 30;
+
+/// Error returned by the API handlers
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    details: Option<Value>,
+    retry_after: Option<u32>,
+}
+
+impl ApiError {
+    /// Error with an HTTP status, a stable code and a message
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code,
+            message: message.into(),
+            details: None,
+            retry_after: None,
+        }
+    }
+
+    /// 400 Bad Request
+    pub fn bad_request(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, code, message)
+    }
+
+    /// Attach structured details, such as the index of the failing input
+    ///
+    /// Fields are merged into any details already attached.
+    pub fn with_details(mut self, details: Value) -> Self {
+        match (&mut self.details, details) {
+            (Some(Value::Object(existing)), Value::Object(new)) => existing.extend(new),
+            (_, details) => self.details = Some(details),
+        }
+        self
+    }
+
+    /// Ask the client to retry after `secs` seconds
+    pub fn with_retry_after(mut self, secs: u32) -> Self {
+        self.retry_after = Some(secs);
+        self
+    }
+
+    /// HTTP status the error is reported with
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    fn detail(&self) -> ErrorDetail<'_> {
+        ErrorDetail {
+            code: self.code,
+            message: &self.message,
+            details: self.details.as_ref(),
+        }
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.code, self.message)
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody<'a> {
+    error: ErrorDetail<'a>,
+}
+
+#[derive(Serialize)]
+struct ErrorDetail<'a> {
+    code: &'a str,
+    message: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    details: Option<&'a Value>,
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status);
+        if let Some(secs) = self.retry_after {
+            response.insert_header((RETRY_AFTER, secs));
+        }
+        let mut response = response.json(ErrorBody { error: self.detail() });
+        response.extensions_mut().insert(AuditErrorCode(self.code));
+        response
+    }
+}
+
+// Serializes as the inner `{"code", "message", "details"}` object, for embedding in larger responses
+impl Serialize for ApiError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.detail().serialize(serializer)
+    }
+}
+
+impl From<Error> for ApiError {
+    fn from(e: Error) -> Self {
+        let message = e.to_string();
+        match e {
+            Error::InvalidAddress(_) => Self::bad_request("invalid_address", message),
+            Error::AmbiguousNetwork { candidates, .. } => {
+                Self::bad_request("ambiguous_network", message).with_details(json!({ "candidates": candidates }))
+            }
+            Error::InvalidNetwork(_) => Self::bad_request("invalid_network", message),
+            Error::InvalidTransaction(_) => Self::bad_request("invalid_transaction", message),
+            Error::TransactionDecode { offset, .. } => {
+                Self::bad_request("invalid_transaction_hex", message).with_details(json!({ "offset": offset }))
+            }
+            Error::SigningError(_) => Self::bad_request("signing_failed", message),
+            Error::InvalidParameter(_) => Self::bad_request("invalid_parameter", message),
+            Error::PsbtError(_) => Self::bad_request("invalid_psbt", message),
+            Error::InsufficientFunds { needed, available } => {
+                Self::new(StatusCode::UNPROCESSABLE_ENTITY, "insufficient_funds", message).with_details(json!({
+                    "needed": needed.to_sat(),
+                    "available": available.to_sat(),
+                    "shortfall": needed.checked_sub(available).unwrap_or(Amount::ZERO).to_sat(),
+                }))
+            }
+            Error::Http { status: 429, .. } => Self::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "upstream_rate_limited",
+                "Backend is rate limiting requests, retry later",
+            )
+            .with_retry_after(UPSTREAM_RETRY_AFTER_SECS),
+            Error::Http { status, .. } => {
+                log::warn!("Backend request failed: {}", message);
+                Self::new(StatusCode::BAD_GATEWAY, "upstream_error", format!("Backend unavailable: {}", message))
+                    .with_details(json!({ "upstream_status": status }))
+            }
+            Error::Network(_) | Error::UnsupportedByBackend(_) => {
+                log::warn!("Backend request failed: {}", message);
+                Self::new(StatusCode::BAD_GATEWAY, "upstream_unavailable", format!("Backend unavailable: {}", message))
+            }
+            _ => {
+                log::error!("Internal error: {}", message);
+                Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", "Internal server error")
+            }
+        }
+    }
+}
+
+/// Report malformed or oversized JSON bodies in the common error shape
+pub fn json_error(e: JsonPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    match e {
+        JsonPayloadError::Overflow { .. } | JsonPayloadError::OverflowKnownLength { .. } => {
+            ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", e.to_string()).into()
+        }
+        _ => ApiError::bad_request("invalid_request", e.to_string()).into(),
+    }
+}
+
+/// Report unparsable path segments in the common error shape
+pub fn path_error(e: PathError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::bad_request("invalid_request", e.to_string()).into()
+}
+
+/// Report unparsable query strings in the common error shape
+pub fn query_error(e: QueryPayloadError, _req: &HttpRequest) -> actix_web::Error {
+    ApiError::bad_request("invalid_request", e.to_string()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::body::to_bytes;
+    use btcx_lib::BtcNetwork;
+
+    async fn render(error: ApiError) -> (u16, Option<String>, Value) {
+        let response = error.error_response();
+        let status = response.status().as_u16();
+        let retry_after = response.headers().get(RETRY_AFTER).map(|v| v.to_str().unwrap().to_string());
+        let body = to_bytes(response.into_body()).await.unwrap();
+        (status, retry_after, serde_json::from_slice(&body).unwrap())
+    }
+
+    fn http(status: u16) -> Error {
+        Error::Http { status, body: "boom".to_string(), url: "http://backend/tx".to_string(), rpc_error: None }
+    }
+
+    #[actix_web::test]
+    async fn test_library_errors_map_to_codes() {
+        let cases = [
+            (Error::InvalidAddress("bad".to_string()), 400, "invalid_address"),
+            (Error::InsufficientFunds { needed: Amount::from_sat(2), available: Amount::ONE_SAT }, 422, "insufficient_funds"),
+            (Error::SigningError("no".to_string()), 400, "signing_failed"),
+            (http(429), 503, "upstream_rate_limited"),
+            (http(500), 502, "upstream_error"),
+            (Error::ConfigError("oops".to_string()), 500, "internal_error"),
+        ];
+        for (error, status, code) in cases {
+            let (actual_status, _, body) = render(error.into()).await;
+            assert_eq!(actual_status, status, "{}", code);
+            assert_eq!(body["error"]["code"], code);
+            assert!(body["error"]["message"].is_string());
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_details_and_retry_after() {
+        let (_, retry_after, body) = render(http(429).into()).await;
+        assert_eq!(retry_after.as_deref(), Some("30"));
+        assert!(body["error"].get("details").is_none());
+
+        let (_, _, body) = render(http(503).into()).await;
+        assert_eq!(body["error"]["details"], json!({ "upstream_status": 503 }));
+
+        let error = Error::AmbiguousNetwork {
+            address: "tb1q".to_string(),
+            candidates: vec![BtcNetwork::Testnet, BtcNetwork::Signet],
+        };
+        let (_, _, body) = render(error.into()).await;
+        assert_eq!(body["error"]["details"], json!({ "candidates": ["testnet", "signet"] }));
+    }
+}
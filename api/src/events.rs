@@ -0,0 +1,428 @@
+//! Server-sent events following a transaction until it confirms
+//!
+//! GET /tx/{txid}/events streams what the backend reports about a
+//! transaction: `seen_in_mempool`, `confirmed`, `reorged` and `dropped`,
+//! then `target_reached` once it has the requested confirmations. POST
+//! /tx/events broadcasts a transaction first and follows it the same way.
+//! The backend is polled at a fixed interval for as long as the client stays
+//! connected, up to a maximum duration.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use actix_web::http::header::CACHE_CONTROL;
+use actix_web::http::StatusCode;
+use actix_web::rt::time::{sleep, Instant};
+use actix_web::web::Bytes;
+use actix_web::{web, HttpRequest, HttpResponse};
+use btcx_lib::network::{BlockstreamClient, TxEvent, TxTracker};
+use bitcoin::Txid;
+use futures_util::stream;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::error::ApiError;
+use crate::network_override::RequestNetwork;
+use crate::{audit, broadcast_hex, AppState, BroadcastRequest};
+
+/// Confirmations waited for unless the client asks for another target
+const DEFAULT_CONFIRMATION_TARGET: u32 = 1;
+/// Deepest confirmation target a client may ask for, about a day of blocks
+const MAX_CONFIRMATION_TARGET: u32 = 144;
+
+/// Polling and connection limits of the event streams
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TxEventsConfig {
+    /// How often each stream asks the backend for news
+    pub poll_interval: Duration,
+    /// Longest a stream stays open before ending with `timeout`
+    pub max_duration: Duration,
+    /// Most streams open at once across all clients
+    pub max_streams: usize,
+}
+
+impl Default for TxEventsConfig {
+    fn default() -> Self {
+        TxEventsConfig {
+            poll_interval: Duration::from_secs(10),
+            max_duration: Duration::from_secs(3600),
+            max_streams: 100,
+        }
+    }
+}
+
+/// Limits and the count of open streams, shared by all workers
+#[derive(Debug)]
+pub struct TxEvents {
+    config: TxEventsConfig,
+    open: AtomicUsize,
+}
+
+impl TxEvents {
+    /// No streams open yet
+    pub fn new(config: TxEventsConfig) -> Self {
+        TxEvents { config, open: AtomicUsize::new(0) }
+    }
+
+    /// Take a stream slot, or answer 503 when every slot is in use
+    fn acquire(events: &web::Data<TxEvents>) -> Result<StreamSlot, ApiError> {
+        let max = events.config.max_streams;
+        events
+            .open
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |open| (open < max).then_some(open + 1))
+            .map_err(|_| {
+                ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "too_many_streams", "Too many open event streams, retry later")
+            })?;
+        Ok(StreamSlot(events.clone()))
+    }
+}
+
+// Released when the stream is dropped, which actix does when the client disconnects
+struct StreamSlot(web::Data<TxEvents>);
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        self.0.open.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+#[derive(Deserialize)]
+struct EventsQuery {
+    confirmations: Option<u32>,
+}
+
+impl EventsQuery {
+    fn target(&self) -> Result<u32, ApiError> {
+        match self.confirmations.unwrap_or(DEFAULT_CONFIRMATION_TARGET) {
+            target @ 1..=MAX_CONFIRMATION_TARGET => Ok(target),
+            target => Err(ApiError::bad_request(
+                "invalid_confirmation_target",
+                format!("Confirmation target {} is outside 1..={}", target, MAX_CONFIRMATION_TARGET),
+            )),
+        }
+    }
+}
+
+// One event in the text/event-stream format
+fn sse(event: &str, data: Value) -> Bytes {
+    Bytes::from(format!("event: {}\ndata: {}\n\n", event, data))
+}
+
+// Keeps proxies from timing out the connection, and lets a write notice a client that left
+fn keepalive() -> Bytes {
+    Bytes::from_static(b": keepalive\n\n")
+}
+
+fn tx_event(txid: &str, event: TxEvent) -> Bytes {
+    match event {
+        TxEvent::SeenInMempool => sse("seen_in_mempool", json!({ "txid": txid })),
+        TxEvent::Confirmed { height, block_hash } => {
+            sse("confirmed", json!({ "txid": txid, "height": height, "block_hash": block_hash }))
+        }
+        TxEvent::Reorged => sse("reorged", json!({ "txid": txid })),
+        TxEvent::Dropped => sse("dropped", json!({ "txid": txid })),
+    }
+}
+
+struct Tracking {
+    tracker: TxTracker,
+    client: BlockstreamClient,
+    target: u32,
+    poll_interval: Duration,
+    deadline: Instant,
+    polled: bool,
+    pending: VecDeque<Bytes>,
+    done: bool,
+    _slot: StreamSlot,
+}
+
+impl Tracking {
+    // Next chunk of the response, or `None` once the stream is over
+    async fn next(&mut self) -> Option<Bytes> {
+        loop {
+            if let Some(chunk) = self.pending.pop_front() {
+                return Some(chunk);
+            }
+            if self.done {
+                return None;
+            }
+            let now = Instant::now();
+            if now >= self.deadline {
+                self.done = true;
+                return Some(sse("timeout", json!({ "txid": self.tracker.txid() })));
+            }
+            if self.polled {
+                sleep(self.poll_interval.min(self.deadline - now)).await;
+            }
+            self.polled = true;
+            match self.tracker.poll(&self.client).await {
+                Ok(events) => {
+                    let txid = self.tracker.txid().to_string();
+                    self.pending.extend(events.into_iter().map(|event| tx_event(&txid, event)));
+                }
+                // Backend hiccups are retried at the next poll
+                Err(e) => log::warn!("Polling {} failed: {}", self.tracker.txid(), e),
+            }
+            if self.tracker.confirmations() >= self.target {
+                let reached = json!({ "txid": self.tracker.txid(), "confirmations": self.tracker.confirmations() });
+                self.pending.push_back(sse("target_reached", reached));
+                self.done = true;
+            }
+            if self.pending.is_empty() {
+                return Some(keepalive());
+            }
+        }
+    }
+}
+
+// Stream `first` events, then follow `txid` until the target, the deadline or a disconnect
+fn event_stream(client: &BlockstreamClient, slot: StreamSlot, txid: &str, target: u32, first: Vec<Bytes>) -> HttpResponse {
+    let config = slot.0.config;
+    let tracking = Tracking {
+        _slot: slot,
+        tracker: TxTracker::new(txid),
+        client: client.clone(),
+        target,
+        poll_interval: config.poll_interval,
+        deadline: Instant::now() + config.max_duration,
+        polled: false,
+        pending: first.into(),
+        done: false,
+    };
+    let body = stream::unfold(tracking, |mut tracking| async move {
+        let chunk = tracking.next().await?;
+        Some((Ok::<_, actix_web::Error>(chunk), tracking))
+    });
+    let response = HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header((CACHE_CONTROL, "no-cache"))
+        .streaming(body);
+    audit::with_txid(response, txid.to_string())
+}
+
+// Handler for /tx/{txid}/events: follow a transaction already broadcast
+async fn tx_events(
+    data: web::Data<AppState>,
+    events: web::Data<TxEvents>,
+    http: HttpRequest,
+    txid: web::Path<String>,
+    query: web::Query<EventsQuery>,
+) -> Result<HttpResponse, ApiError> {
+    RequestNetwork::require_default(&data, &http)?;
+    let txid: Txid = txid
+        .parse()
+        .map_err(|_| ApiError::bad_request("invalid_txid", "Expected a txid of 64 hex characters"))?;
+    let target = query.target()?;
+    Ok(event_stream(&data.client, TxEvents::acquire(&events)?, &txid.to_string(), target, Vec::new()))
+}
+
+// Handler for POST /tx/events: broadcast, then follow the transaction
+async fn broadcast_and_track(
+    data: web::Data<AppState>,
+    events: web::Data<TxEvents>,
+    http: HttpRequest,
+    query: web::Query<EventsQuery>,
+    req: web::Json<BroadcastRequest>,
+) -> Result<HttpResponse, ApiError> {
+    let target = query.target()?;
+    let network = RequestNetwork::resolve(&data, &http, req.network.as_deref())?;
+    // Refuse before broadcasting when no stream could follow it
+    let slot = TxEvents::acquire(&events)?;
+    let sent = broadcast_hex(&data, &network.client, &req.tx_hex).await?;
+    let mut first = json!({ "txid": sent.txid, "already_known": sent.already_known });
+    network.echo(&mut first);
+    Ok(event_stream(&network.client, slot, &sent.txid, target, vec![sse("broadcast", first)]))
+}
+
+/// Register the event stream endpoints
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/tx/{txid}/events", web::get().to(tx_events))
+        .route("/tx/events", web::post().to(broadcast_and_track));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{state, LEGACY_TX_HEX};
+    use actix_web::{test, App};
+    use btcx_lib::BtcNetwork;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+
+    const TXID: &str = "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16";
+
+    const FAST: TxEventsConfig = TxEventsConfig {
+        poll_interval: Duration::from_millis(10),
+        max_duration: Duration::from_secs(10),
+        max_streams: 2,
+    };
+
+    // A backend answer for /tx/{txid}: unknown, in the mempool or in a block
+    #[derive(Clone, Copy)]
+    enum Step {
+        Unknown,
+        Mempool,
+        Block(u32, char),
+    }
+
+    fn tx_json(step: Step) -> Option<String> {
+        let status = match step {
+            Step::Unknown => return None,
+            Step::Mempool => json!({ "confirmed": false }),
+            Step::Block(height, hash) => {
+                json!({ "confirmed": true, "block_height": height, "block_hash": hash.to_string().repeat(64) })
+            }
+        };
+        let tx = json!({
+            "txid": TXID, "version": 1, "locktime": 0, "vin": [], "vout": [],
+            "size": 100, "weight": 400, "fee": 0, "status": status,
+        });
+        Some(tx.to_string())
+    }
+
+    // Backend answering each /tx request with the next scripted step, the
+    // last one repeating, and the tip height that goes with the current step
+    fn scripted_backend(script: Vec<(Step, u32)>) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let seen = log.clone();
+        std::thread::spawn(move || {
+            let mut position = 0;
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut head = Vec::new();
+                let mut buf = [0; 1024];
+                // Bodies are ignored, so stop at the end of the headers
+                while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+                    match stream.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => head.extend_from_slice(&buf[..n]),
+                    }
+                }
+                let head = String::from_utf8_lossy(&head);
+                let path = head.split_whitespace().nth(1).unwrap_or_default().to_string();
+                let (status, body) = if path.starts_with("/tx/") {
+                    let (step, _) = script[position.min(script.len() - 1)];
+                    position += 1;
+                    match tx_json(step) {
+                        Some(body) => ("200 OK", body),
+                        None => ("404 Not Found", "Transaction not found".to_string()),
+                    }
+                } else if path == "/blocks/tip/height" {
+                    ("200 OK", script[position.saturating_sub(1).min(script.len() - 1)].1.to_string())
+                } else if path == "/tx" {
+                    ("200 OK", TXID.to_string())
+                } else {
+                    ("404 Not Found", String::new())
+                };
+                log.lock().unwrap().push(path);
+                let response = format!("HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body);
+                let _ = stream.write_all(response.as_bytes());
+            }
+        });
+        (url, seen)
+    }
+
+    macro_rules! app {
+        ($backend:expr) => {
+            app!($backend, web::Data::new(TxEvents::new(FAST)))
+        };
+        ($backend:expr, $events:expr) => {
+            test::init_service(
+                App::new()
+                    .app_data(web::Data::new(state(BtcNetwork::Bitcoin, &$backend)))
+                    .app_data($events)
+                    .configure(routes),
+            )
+            .await
+        };
+    }
+
+    // Event names and data in the order they were sent, skipping keepalives
+    fn parse_events(body: &[u8]) -> Vec<(String, Value)> {
+        String::from_utf8_lossy(body)
+            .split("\n\n")
+            .filter_map(|block| {
+                let event = block.strip_prefix("event: ")?;
+                let (name, data) = event.split_once("\ndata: ")?;
+                Some((name.to_string(), serde_json::from_str(data).unwrap()))
+            })
+            .collect()
+    }
+
+    #[actix_web::test]
+    async fn test_events_follow_backend_transitions() {
+        let script = vec![
+            (Step::Unknown, 0),
+            (Step::Mempool, 0),
+            (Step::Mempool, 0),
+            (Step::Block(100, 'a'), 100),
+            (Step::Mempool, 100),
+            (Step::Block(101, 'b'), 101),
+            (Step::Block(101, 'b'), 102),
+        ];
+        let (backend, _) = scripted_backend(script);
+        let app = app!(backend);
+        let req = test::TestRequest::get().uri(&format!("/tx/{}/events?confirmations=2", TXID)).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get("content-type").unwrap(), "text/event-stream");
+        let events = parse_events(&test::read_body(resp).await);
+
+        let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["seen_in_mempool", "confirmed", "reorged", "confirmed", "target_reached"]);
+        assert_eq!(events[1].1, json!({ "txid": TXID, "height": 100, "block_hash": "a".repeat(64) }));
+        assert_eq!(events[3].1["block_hash"], "b".repeat(64));
+        assert_eq!(events[4].1, json!({ "txid": TXID, "confirmations": 2 }));
+    }
+
+    #[actix_web::test]
+    async fn test_broadcast_then_track() {
+        let (backend, log) = scripted_backend(vec![(Step::Mempool, 0), (Step::Block(7, 'c'), 7)]);
+        let app = app!(backend);
+        let req = test::TestRequest::post()
+            .uri("/tx/events")
+            .set_json(json!({ "tx_hex": LEGACY_TX_HEX }))
+            .to_request();
+        let events = parse_events(&test::call_and_read_body(&app, req).await);
+        let names: Vec<&str> = events.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["broadcast", "seen_in_mempool", "confirmed", "target_reached"]);
+        assert_eq!(events[0].1, json!({ "txid": TXID, "already_known": false }));
+        assert_eq!(log.lock().unwrap()[0], "/tx");
+    }
+
+    #[actix_web::test]
+    async fn test_stream_limits_and_cleanup() {
+        let (backend, _) = scripted_backend(vec![(Step::Mempool, 0)]);
+        let events = web::Data::new(TxEvents::new(TxEventsConfig { max_duration: Duration::from_millis(50), ..FAST }));
+        let app = app!(backend, events.clone());
+        let uri = format!("/tx/{}/events", TXID);
+
+        // Streams ending at the deadline say so
+        let sent = parse_events(&test::call_and_read_body(&app, test::TestRequest::get().uri(&uri).to_request()).await);
+        let names: Vec<&str> = sent.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["seen_in_mempool", "timeout"]);
+
+        // Open streams hold their slot until dropped, as on a client disconnect
+        let first = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        let second = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        let third = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(third.status(), 503);
+        let body: Value = test::read_body_json(third).await;
+        assert_eq!(body["error"]["code"], "too_many_streams");
+        drop(first);
+        let fourth = test::call_service(&app, test::TestRequest::get().uri(&uri).to_request()).await;
+        assert_eq!(fourth.status(), 200);
+        assert_eq!(events.open.load(Ordering::SeqCst), 2);
+        drop((second, fourth));
+        assert_eq!(events.open.load(Ordering::SeqCst), 0);
+
+        let resp = test::call_service(&app, test::TestRequest::get().uri("/tx/abc/events").to_request()).await;
+        assert_eq!(resp.status(), 400);
+        let resp = test::call_service(&app, test::TestRequest::get().uri(&format!("{}?confirmations=0", uri)).to_request()).await;
+        assert_eq!(resp.status(), 400);
+    }
+}
@@ -0,0 +1,429 @@
+//! Idempotency keys for the endpoints that build or broadcast transactions
+//!
+//! A POST carrying an `Idempotency-Key` header has its response kept for a
+//! while, keyed by the key, the route and the caller's API key. Sending the
+//! same request again returns the kept response, marked with
+//! `Idempotent-Replayed: true`, instead of running it twice; reusing the key
+//! with a different body or network header is refused with 422. Server errors
+//! and rate limiting are not kept, so those requests can be retried as new.
+//! /broadcast additionally answers a transaction the backend already has as
+//! sent, so a retry succeeds even once its key has expired.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use actix_web::body::{self, BoxBody, MessageBody};
+use actix_web::dev::{Payload, ServiceRequest, ServiceResponse};
+use actix_web::http::header::{HeaderName, HeaderValue};
+use actix_web::http::{Method, StatusCode};
+use actix_web::middleware::Next;
+use actix_web::web::{self, Bytes, BytesMut};
+use actix_web::{HttpMessage, HttpResponse};
+use bitcoin::hashes::{sha256, Hash, HashEngine};
+use futures_util::StreamExt;
+
+use crate::audit::AuditTxid;
+use crate::auth::ApiKeyName;
+use crate::error::ApiError;
+use crate::NETWORK_HEADER;
+
+/// Request header carrying the client's idempotency key
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+/// Response header marking a replayed response
+pub const REPLAYED_HEADER: &str = "Idempotent-Replayed";
+
+/// Endpoints honouring idempotency keys; /sign_tx is left out so nothing derived from keys is kept
+pub const ROUTES: [&str; 6] = ["/create_tx", "/create_tx/batch", "/fund_tx", "/create_psbt", "/finalize_psbt", "/broadcast"];
+
+/// Longest idempotency key accepted
+const MAX_KEY_LEN: usize = 255;
+
+/// Largest request body fingerprinted, the /create_tx/batch limit
+const MAX_BODY: usize = crate::BATCH_BODY_LIMIT;
+
+/// How long responses are kept and how many
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdempotencyConfig {
+    /// Time a key stays bound to its response
+    pub ttl: Duration,
+    /// Most keys kept; the oldest are evicted beyond
+    pub max_keys: usize,
+}
+
+impl Default for IdempotencyConfig {
+    fn default() -> Self {
+        IdempotencyConfig { ttl: Duration::from_secs(24 * 3600), max_keys: 10_000 }
+    }
+}
+
+/// A response kept for replay
+#[derive(Debug, Clone)]
+pub struct StoredResponse {
+    pub status: StatusCode,
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+    pub body: Bytes,
+    pub txid: Option<String>,  // For the audit line of replays
+}
+
+/// Where a key stands when a request claims it
+#[derive(Debug)]
+pub enum Claim {
+    /// Unused: the request runs, then the key is completed or released
+    New,
+    /// Used by the same request, which finished with this response
+    Replay(StoredResponse),
+    /// Used by the same request, still running
+    InFlight,
+    /// Used by a different request
+    Mismatch,
+}
+
+/// Storage of idempotency keys, shared by all workers
+///
+/// `key` already combines the client, the route and the client's key, and
+/// `fingerprint` is a digest of what the request asked for.
+pub trait IdempotencyStore: Send + Sync {
+    /// Claim `key` for a request, or say how it was used
+    fn claim(&self, key: &str, fingerprint: sha256::Hash, now: Instant) -> Claim;
+    /// Bind a claimed key to the response it produced
+    fn complete(&self, key: &str, response: StoredResponse);
+    /// Free a claimed key whose request produced nothing worth keeping
+    fn release(&self, key: &str);
+}
+
+struct Entry {
+    fingerprint: sha256::Hash,
+    claimed_at: Instant,
+    response: Option<StoredResponse>,  // None while the request runs
+}
+
+#[derive(Default)]
+struct Entries {
+    by_key: HashMap<String, Entry>,
+    order: VecDeque<(String, Instant)>,  // Claims, oldest first; stale after a release
+}
+
+/// Keys kept in memory, evicted once expired or, beyond the limit, oldest first
+pub struct MemoryIdempotencyStore {
+    config: IdempotencyConfig,
+    entries: Mutex<Entries>,
+}
+
+impl MemoryIdempotencyStore {
+    pub fn new(config: IdempotencyConfig) -> Self {
+        MemoryIdempotencyStore { config, entries: Mutex::new(Entries::default()) }
+    }
+
+    // Drop expired keys and, to make room, the oldest; claims are ordered by time
+    fn evict(&self, entries: &mut Entries, now: Instant) {
+        let Entries { by_key, order } = entries;
+        while let Some((key, claimed_at)) = order.front() {
+            let current = by_key.get(key).is_some_and(|entry| entry.claimed_at == *claimed_at);
+            let expired = now.saturating_duration_since(*claimed_at) >= self.config.ttl;
+            if current && !expired && by_key.len() < self.config.max_keys {
+                break;
+            }
+            if current {
+                by_key.remove(key);
+            }
+            order.pop_front();
+        }
+        // Released claims left in the middle would otherwise pile up
+        if order.len() > 2 * self.config.max_keys {
+            order.retain(|(key, claimed_at)| by_key.get(key).is_some_and(|entry| entry.claimed_at == *claimed_at));
+        }
+    }
+}
+
+impl IdempotencyStore for MemoryIdempotencyStore {
+    fn claim(&self, key: &str, fingerprint: sha256::Hash, now: Instant) -> Claim {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        self.evict(&mut entries, now);
+        match entries.by_key.get(key) {
+            Some(entry) if entry.fingerprint != fingerprint => Claim::Mismatch,
+            Some(Entry { response: Some(response), .. }) => Claim::Replay(response.clone()),
+            Some(_) => Claim::InFlight,
+            None => {
+                entries.by_key.insert(key.to_string(), Entry { fingerprint, claimed_at: now, response: None });
+                entries.order.push_back((key.to_string(), now));
+                Claim::New
+            }
+        }
+    }
+
+    fn complete(&self, key: &str, response: StoredResponse) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(entry) = entries.by_key.get_mut(key) {
+            entry.response = Some(response);
+        }
+    }
+
+    fn release(&self, key: &str) {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        if entries.by_key.get(key).is_some_and(|entry| entry.response.is_none()) {
+            entries.by_key.remove(key);
+        }
+    }
+}
+
+/// The configured store, in the app data
+pub struct IdempotencyKeys {
+    store: Box<dyn IdempotencyStore>,
+}
+
+impl IdempotencyKeys {
+    pub fn new(store: Box<dyn IdempotencyStore>) -> Self {
+        IdempotencyKeys { store }
+    }
+}
+
+// Releases the key unless the request completed it, including when the client goes away
+struct ClaimGuard {
+    keys: web::Data<IdempotencyKeys>,
+    key: String,
+    completed: bool,
+}
+
+impl ClaimGuard {
+    fn complete(mut self, response: StoredResponse) {
+        self.keys.store.complete(&self.key, response);
+        self.completed = true;
+    }
+}
+
+impl Drop for ClaimGuard {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.keys.store.release(&self.key);
+        }
+    }
+}
+
+// Visible ASCII only, so keys cannot forge log lines or headers
+fn is_valid_key(key: &str) -> bool {
+    !key.is_empty() && key.len() <= MAX_KEY_LEN && key.bytes().all(|b| b.is_ascii_graphic())
+}
+
+// Read the whole body, then put it back for the handler
+async fn take_body(req: &mut ServiceRequest) -> Result<Bytes, ApiError> {
+    let too_large = || {
+        ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", format!("Request body exceeds {} bytes", MAX_BODY))
+    };
+    let mut payload = req.take_payload();
+    let mut body = BytesMut::new();
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| ApiError::bad_request("invalid_request", e.to_string()))?;
+        if body.len() + chunk.len() > MAX_BODY {
+            return Err(too_large());
+        }
+        body.extend_from_slice(&chunk);
+    }
+    let body = body.freeze();
+    req.set_payload(Payload::from(body.clone()));
+    Ok(body)
+}
+
+// What the request asks for: the network header, which changes the answer, and the body
+fn fingerprint(req: &ServiceRequest, body: &[u8]) -> sha256::Hash {
+    let mut engine = sha256::Hash::engine();
+    let network = req.headers().get(NETWORK_HEADER).map_or(&[][..], HeaderValue::as_bytes);
+    engine.input(&(network.len() as u64).to_le_bytes());
+    engine.input(network);
+    engine.input(body);
+    sha256::Hash::from_engine(engine)
+}
+
+// Failures worth retrying are not kept
+fn is_kept(status: StatusCode) -> bool {
+    !status.is_server_error() && status != StatusCode::TOO_MANY_REQUESTS
+}
+
+fn replay(req: ServiceRequest, stored: StoredResponse) -> ServiceResponse<BoxBody> {
+    let mut response = HttpResponse::build(stored.status);
+    for header in stored.headers {
+        response.append_header(header);
+    }
+    response.insert_header((REPLAYED_HEADER, "true"));
+    let mut response = response.body(stored.body);
+    if let Some(txid) = stored.txid {
+        response.extensions_mut().insert(AuditTxid(txid));
+    }
+    req.into_response(response)
+}
+
+/// Middleware keeping and replaying responses of requests with an idempotency key
+///
+/// Must run after authentication so keys are scoped by API key.
+pub async fn replay_responses(
+    mut req: ServiceRequest,
+    next: Next<impl MessageBody + 'static>,
+) -> Result<ServiceResponse<BoxBody>, actix_web::Error> {
+    let keys = req.app_data::<web::Data<IdempotencyKeys>>().cloned();
+    let header = req.headers().get(IDEMPOTENCY_KEY_HEADER).cloned();
+    let (keys, header) = match (keys, header) {
+        (Some(keys), Some(header)) if req.method() == Method::POST && ROUTES.contains(&req.path()) => (keys, header),
+        _ => return next.call(req).await.map(ServiceResponse::map_into_boxed_body),
+    };
+    let Some(key) = header.to_str().ok().filter(|key| is_valid_key(key)) else {
+        let message = format!("{} must be 1 to {} visible ASCII characters", IDEMPOTENCY_KEY_HEADER, MAX_KEY_LEN);
+        return Ok(req.error_response(ApiError::bad_request("invalid_idempotency_key", message)));
+    };
+    let body = match take_body(&mut req).await {
+        Ok(body) => body,
+        Err(error) => return Ok(req.error_response(error)),
+    };
+    let client = req.extensions().get::<ApiKeyName>().map_or_else(|| "-".to_string(), |name| name.0.clone());
+    let key = format!("{} {} {}", client, req.path(), key);
+
+    match keys.store.claim(&key, fingerprint(&req, &body), Instant::now()) {
+        Claim::New => {}
+        Claim::Replay(stored) => return Ok(replay(req, stored)),
+        Claim::InFlight => {
+            let error = ApiError::new(StatusCode::CONFLICT, "idempotency_key_in_use", "A request with this key is still running")
+                .with_retry_after(1);
+            return Ok(req.error_response(error));
+        }
+        Claim::Mismatch => {
+            let error = ApiError::new(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "idempotency_key_reused",
+                "This idempotency key was used with a different request",
+            );
+            return Ok(req.error_response(error));
+        }
+    }
+    let claim = ClaimGuard { keys, key, completed: false };
+
+    let res = next.call(req).await?;
+    if !is_kept(res.status()) {
+        return Ok(res.map_into_boxed_body());
+    }
+    let (req, res) = res.into_parts();
+    let (res, body) = res.into_parts();
+    let body = body::to_bytes(body).await.map_err(|e| {
+        let e: Box<dyn std::error::Error> = e.into();
+        actix_web::error::ErrorInternalServerError(e.to_string())
+    })?;
+    claim.complete(StoredResponse {
+        status: res.status(),
+        headers: res.headers().iter().map(|(name, value)| (name.clone(), value.clone())).collect(),
+        body: body.clone(),
+        txid: res.extensions().get::<AuditTxid>().map(|txid| txid.0.clone()),
+    });
+    Ok(ServiceResponse::new(req, res.set_body(body)).map_into_boxed_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{state, LEGACY_TX_HEX};
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App};
+    use btcx_lib::BtcNetwork;
+    use serde_json::json;
+
+    const TXID: &str = "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16";
+
+    macro_rules! app {
+        ($backend:expr) => {
+            test::init_service(
+                App::new()
+                    .app_data(web::Data::new(state(BtcNetwork::Bitcoin, &$backend)))
+                    .app_data(web::Data::new(IdempotencyKeys::new(Box::new(MemoryIdempotencyStore::new(
+                        IdempotencyConfig::default(),
+                    )))))
+                    .wrap(from_fn(replay_responses))
+                    .configure(crate::routes),
+            )
+            .await
+        };
+    }
+
+    fn broadcast(key: &str, body: serde_json::Value) -> test::TestRequest {
+        test::TestRequest::post().uri("/broadcast").insert_header((IDEMPOTENCY_KEY_HEADER, key)).set_json(body)
+    }
+
+    #[actix_web::test]
+    async fn test_broadcast_replays() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server.mock("POST", "/tx").with_body(TXID).expect(1).create_async().await;
+        let app = app!(server.url());
+        let body = json!({ "tx_hex": LEGACY_TX_HEX });
+
+        let resp = test::call_service(&app, broadcast("payout-1", body.clone()).to_request()).await;
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers().get(REPLAYED_HEADER).is_none());
+        let first: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(first, json!({ "txid": TXID, "already_known": false }));
+
+        // The same request is answered from the store, without a second broadcast
+        let resp = test::call_service(&app, broadcast("payout-1", body.clone()).to_request()).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get(REPLAYED_HEADER).unwrap(), "true");
+        assert_eq!(resp.headers().get("content-type").unwrap(), "application/json");
+        let replayed: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(replayed, first);
+        mock.assert_async().await;
+
+        // A mutated body under the same key is refused
+        let mutated = json!({ "tx_hex": LEGACY_TX_HEX, "network": "mainnet" });
+        let resp = test::call_service(&app, broadcast("payout-1", mutated).to_request()).await;
+        assert_eq!(resp.status(), 422);
+        let error: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(error["error"]["code"], "idempotency_key_reused");
+
+        // Badly formed keys are refused
+        let resp = test::call_service(&app, broadcast("tab\tseparated", body).to_request()).await;
+        assert_eq!(resp.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_failures_worth_retrying_are_not_kept() {
+        let mut server = mockito::Server::new_async().await;
+        let failing = server.mock("POST", "/tx").with_status(502).expect(1).create_async().await;
+        let app = app!(server.url());
+        let body = json!({ "tx_hex": LEGACY_TX_HEX });
+
+        let resp = test::call_service(&app, broadcast("payout-2", body.clone()).to_request()).await;
+        assert_eq!(resp.status(), 502);
+        failing.assert_async().await;
+        failing.remove_async().await;
+
+        // The retry runs again; the backend already has the transaction, which counts as sent
+        let known = r#"sendrawtransaction RPC error: {"code":-27,"message":"Transaction already in block chain"}"#;
+        let mock = server.mock("POST", "/tx").with_status(400).with_body(known).expect(1).create_async().await;
+        let resp = test::call_service(&app, broadcast("payout-2", body.clone()).to_request()).await;
+        assert_eq!(resp.status(), 200);
+        let sent: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(sent["already_known"], true);
+        let resp = test::call_service(&app, broadcast("payout-2", body).to_request()).await;
+        assert_eq!(resp.headers().get(REPLAYED_HEADER).unwrap(), "true");
+        mock.assert_async().await;
+    }
+
+    #[actix_web::test]
+    async fn test_memory_store_eviction() {
+        let store = MemoryIdempotencyStore::new(IdempotencyConfig { ttl: Duration::from_secs(60), max_keys: 2 });
+        let response = StoredResponse { status: StatusCode::OK, headers: Vec::new(), body: Bytes::from_static(b"{}"), txid: None };
+        let (a, b) = (sha256::Hash::hash(b"a"), sha256::Hash::hash(b"b"));
+        let start = Instant::now();
+
+        assert!(matches!(store.claim("k1", a, start), Claim::New));
+        assert!(matches!(store.claim("k1", a, start), Claim::InFlight));
+        assert!(matches!(store.claim("k1", b, start), Claim::Mismatch));
+        store.complete("k1", response.clone());
+        assert!(matches!(store.claim("k1", a, start), Claim::Replay(_)));
+
+        // Released keys are free again
+        assert!(matches!(store.claim("k2", a, start), Claim::New));
+        store.release("k2");
+        assert!(matches!(store.claim("k2", b, start), Claim::New));
+        store.complete("k2", response);
+
+        // A third key evicts the oldest, and everything expires after the TTL
+        assert!(matches!(store.claim("k3", a, start + Duration::from_secs(1)), Claim::New));
+        assert!(matches!(store.claim("k1", b, start + Duration::from_secs(1)), Claim::New));
+        assert!(matches!(store.claim("k3", b, start + Duration::from_secs(61)), Claim::New));
+    }
+}
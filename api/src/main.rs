@@ -1,17 +1,67 @@
-use actix_web::{web, App, HttpServer, Responder, HttpResponse};
-use bitcoin::consensus::encode::serialize;
-use bitcoin::util::amount::Amount;
-use bitcoin::{Address, Network, OutPoint, PackedLockTime, Sequence, Transaction, TxIn, TxOut, Txid};
-use hex;
+use actix_web::http::StatusCode;
+use actix_web::middleware::{from_fn, DefaultHeaders};
+use actix_web::{web, App, HttpRequest, HttpServer, HttpResponse};
+use bitcoin::bip32::{DerivationPath, Fingerprint, KeySource, Xpub};
+use bitcoin::consensus::encode::{serialize, serialize_hex};
+use bitcoin::psbt::Psbt;
+use bitcoin::{
+    absolute, secp256k1, transaction, Address, Amount, Network, NetworkKind, OutPoint, PrivateKey, ScriptBuf, Sequence,
+    Transaction, TxIn, TxOut, Txid,
+};
+use btcx_lib::network::{estimate_for_target, BlockstreamClient, BroadcastRejection};
+use btcx_lib::psbt::{build_psbt, PsbtInputData};
+use btcx_lib::signing::{sign_transaction, InputSigner};
+use btcx_lib::transaction_builder::{BuildReport, TransactionBuilder};
+use btcx_lib::utils::{
+    decode_transaction, estimate_vsize, inspect_address, parse_transaction_hex, AddressProblem, InputType, OutputType, INPUT_TYPES,
+    OUTPUT_TYPES,
+};
+use btcx_lib::{BtcNetwork, CoinSelectionStrategy, Error, OutputTarget, TxBuilderConfig, Utxo};
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
 use std::str::FromStr;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex, PoisonError, RwLock};
+use std::time::{Duration, Instant};
+
+mod audit;
+mod auth;
+mod config;
+mod cors;
+mod error;
+mod events;
+mod health;
+mod idempotency;
+mod metrics;
+mod network_override;
+mod openapi;
+mod ratelimit;
+mod shutdown;
+mod tls;
+mod wallet;
+
+use auth::ApiKeys;
+use config::Config;
+use error::{ApiError, UPSTREAM_RETRY_AFTER_SECS};
+use events::TxEvents;
+use health::Readiness;
+use idempotency::{IdempotencyKeys, MemoryIdempotencyStore};
+use metrics::{BroadcastOutcome, CacheLookup, Metrics, PublicMetrics};
+use network_override::RequestNetwork;
+use ratelimit::RateLimiter;
+use shutdown::Shutdown;
+use wallet::{FileIndexStore, IndexStore, MemoryIndexStore, Wallets};
+
+/// Header naming a network: every response states the server's (or the
+/// request's, when overridden), and a request may send it to pick another
+const NETWORK_HEADER: &str = "X-Btcx-Network";
 
 // Struct to represent an input in the transaction request
 #[derive(Deserialize)]
 struct TxInputRequest {
-    txid: String,  // Transaction ID as a string
-    vout: u32,     // Output index
+    txid: String,           // Transaction ID as a string
+    vout: u32,              // Output index
+    sequence: Option<u32>,  // nSequence, overriding the rbf choice for this input
 }
 
 // Struct to represent the complete transaction request
@@ -19,6 +69,13 @@ struct TxInputRequest {
 struct CreateTxRequest {
     inputs: Vec<TxInputRequest>,    // List of inputs
     outputs: Vec<TxOutputRequest>,  // List of outputs
+    #[serde(default)]
+    allow_dust: bool,               // Accept outputs below the dust threshold
+    version: Option<i32>,           // Transaction version, 1 (default) or 2
+    locktime: Option<u32>,          // nLockTime: a block height below 500000000, a unix time above
+    #[serde(default)]
+    rbf: bool,                      // Signal replaceability on inputs without their own sequence
+    network: Option<String>,        // Network to build for, with --allow-network-override
 }
 
 // Struct to represent an output in the transaction request
@@ -31,59 +88,436 @@ struct TxOutputRequest {
 // Struct to represent the transaction response
 #[derive(Serialize)]
 struct TxResponse {
-    tx_hex: String,   // Hex-encoded transaction
+    tx_hex: String,       // Hex-encoded transaction
+    version: i32,         // Effective transaction version
+    locktime: u32,        // Effective nLockTime
+    rbf: bool,            // Some input signals replaceability
+    sequences: Vec<u32>,  // Effective nSequence of each input
+}
+
+// nSequence the rbf flag gives inputs, the same as the library's transaction builder
+const RBF_SEQUENCE: Sequence = Sequence::ENABLE_RBF_NO_LOCKTIME;
+
+// Struct to represent the outcome of one /create_tx/batch item
+#[derive(Serialize)]
+struct BatchItemResult {
+    index: usize,                // Position in the request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_ref: Option<Value>,   // Echo of the item's client_ref
+    status: u16,                 // HTTP status /create_tx would have answered
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tx: Option<TxResponse>,      // The built transaction, on success
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ApiError>,     // Why the item failed
+}
+
+// Struct to represent the batch totals
+#[derive(Serialize)]
+struct BatchSummary {
+    total: usize,
+    succeeded: usize,
+    failed: usize,
+    elapsed_ms: u64,  // Time spent on the whole batch
+}
+
+// Struct to represent the /create_tx/batch response, results in request order
+#[derive(Serialize)]
+struct BatchResponse {
+    results: Vec<BatchItemResult>,
+    summary: BatchSummary,
+}
+
+// Struct to represent a candidate UTXO in the funding request
+#[derive(Deserialize)]
+struct FundUtxoRequest {
+    txid: String,     // Transaction ID as a string
+    vout: u32,        // Output index
+    amount: u64,      // Amount in satoshis
+    address: String,  // Address the output pays to
+    #[serde(default)]
+    bip32_derivation: Vec<Bip32DerivationRequest>,  // Key origins, for /create_psbt
+}
+
+// Struct to represent the BIP32 origin of a public key
+#[derive(Deserialize)]
+struct Bip32DerivationRequest {
+    pubkey: String,       // Hex-encoded public key
+    fingerprint: String,  // Master key fingerprint, 8 hex digits
+    path: String,         // Derivation path, e.g. m/84'/0'/0'/0/0
+}
+
+// Struct to represent the funding request
+#[derive(Deserialize)]
+struct FundTxRequest {
+    utxos: Option<Vec<FundUtxoRequest>>,  // Candidate UTXOs, or
+    address: Option<String>,              // an address whose UTXOs to fetch
+    outputs: Vec<TxOutputRequest>,        // Recipient outputs
+    fee_rate: Option<f64>,                // Fee rate in sat/vB, or
+    target_blocks: Option<u32>,           // a confirmation target to look the rate up for
+    change_address: String,               // Where the change goes
+    strategy: Option<String>,             // Coin selection strategy (default branch_and_bound)
+    #[serde(default)]
+    rbf: bool,                            // Signal replaceability
+    #[serde(default)]
+    allow_dust: bool,                     // Accept recipient outputs below the dust threshold
+    network: Option<String>,              // Network to fund on, with --allow-network-override
+}
+
+// Struct to represent the funding response
+#[derive(Serialize)]
+struct FundTxResponse {
+    tx_hex: String,        // Hex-encoded unsigned transaction
+    fee_rate: f64,         // Fee rate used, in sat/vB
+    #[serde(flatten)]
+    report: BuildReport,   // Selected inputs, fee, change and predicted vsize
+}
+
+// Struct to represent an extended public key to record in a PSBT
+#[derive(Deserialize)]
+struct XpubRequest {
+    xpub: String,         // Base58 extended public key
+    fingerprint: String,  // Master key fingerprint, 8 hex digits
+    path: String,         // Derivation path of the xpub
+}
+
+// Struct to represent the PSBT creation request: a funding request plus key origins
+#[derive(Deserialize)]
+struct CreatePsbtRequest {
+    #[serde(flatten)]
+    fund: FundTxRequest,
+    #[serde(default)]
+    bip32_derivation: Vec<Bip32DerivationRequest>,  // Key origins for UTXOs fetched by address
+    #[serde(default)]
+    xpubs: Vec<XpubRequest>,                        // Global xpubs
+}
+
+// Struct to represent the PSBT creation response
+#[derive(Serialize)]
+struct CreatePsbtResponse {
+    psbt: String,           // Base64-encoded PSBT
+    unsigned_txid: String,  // Txid of the unsigned transaction
+    fee_rate: f64,          // Fee rate used, in sat/vB
+    #[serde(flatten)]
+    report: BuildReport,    // Selected inputs, fee, change and predicted vsize
+}
+
+// Struct to represent the PSBT finalization request
+#[derive(Deserialize)]
+struct FinalizePsbtRequest {
+    psbt: String,  // Base64-encoded PSBT with every signature collected
+}
+
+// Struct to represent the PSBT finalization response
+#[derive(Serialize)]
+struct FinalizePsbtResponse {
+    tx_hex: String,  // Signed transaction ready to broadcast
+    txid: String,
+}
+
+// Struct to represent the key and spent output of one input to sign
+#[derive(Deserialize)]
+struct SignInputRequest {
+    private_key_wif: String,  // Private key in WIF format, never logged
+    address: String,          // Address of the spent output
+    amount: Option<u64>,      // Amount of the spent output in satoshis (required for segwit, and for all with taproot)
+}
+
+// Keep private keys out of any debug or log output
+impl std::fmt::Debug for SignInputRequest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SignInputRequest")
+            .field("private_key_wif", &"<redacted>")
+            .field("address", &self.address)
+            .field("amount", &self.amount)
+            .finish()
+    }
+}
+
+// Struct to represent the signing request, one signing input per transaction input
+#[derive(Debug, Deserialize)]
+struct SignTxRequest {
+    unsigned_tx_hex: String,
+    inputs: Vec<SignInputRequest>,
+    network: Option<String>,  // Network of the keys and addresses, with --allow-network-override
+}
+
+// Struct to represent the signing response
+#[derive(Serialize)]
+struct SignTxResponse {
+    signed_tx_hex: String,
+    txid: String,
+    vsize: u64,
+}
+
+// Struct to represent the broadcast request
+#[derive(Deserialize)]
+struct BroadcastRequest {
+    tx_hex: String,
+    network: Option<String>,  // Network to broadcast on, with --allow-network-override
 }
 
-// Application state to hold the Bitcoin network type
+// Struct to represent the broadcast response
+#[derive(Serialize)]
+struct BroadcastResponse {
+    txid: String,
+    already_known: bool,  // The backend already had the transaction
+}
+
+// Struct to represent the decode request
+#[derive(Deserialize)]
+struct DecodeTxRequest {
+    tx_hex: String,
+    network: Option<String>,  // Network to render addresses for, with --allow-network-override
+}
+
+// Check the hex size, then decode, so oversized input is never parsed
+fn parse_tx_hex(tx_hex: &str, max_tx_vsize: u64) -> Result<Transaction, ApiError> {
+    let too_large = |message: String| {
+        ApiError::new(StatusCode::PAYLOAD_TOO_LARGE, "transaction_too_large", message)
+            .with_details(json!({ "max_vsize": max_tx_vsize }))
+    };
+    // A transaction's serialized size never exceeds its weight (4 x vsize)
+    if tx_hex.len() as u64 > max_tx_vsize * 4 * 2 {
+        return Err(too_large(format!("Transaction exceeds the {} vB limit", max_tx_vsize)));
+    }
+    let tx = parse_transaction_hex(tx_hex)?;
+    if tx.vsize() as u64 > max_tx_vsize {
+        return Err(too_large(format!("Transaction is {} vB, above the {} vB limit", tx.vsize(), max_tx_vsize)));
+    }
+    Ok(tx)
+}
+
+// Response of the /address/{address}/validate endpoint; fields describing the
+// address are null and `reason` says why when it is invalid
+#[derive(Serialize)]
+struct AddressValidation {
+    valid: bool,
+    networks: Vec<BtcNetwork>,        // Networks the address is valid on
+    #[serde(rename = "type")]
+    address_type: Option<String>,     // p2pkh, p2sh, p2wpkh, p2wsh, p2tr or witness_v<n>
+    script_pubkey: Option<String>,    // Hex-encoded locking script
+    witness_version: Option<u8>,      // Segwit addresses only
+    is_ours_network: bool,            // Valid on the server's network, or the one the request named
+    reason: Option<AddressProblem>,   // Stable code for why the address is invalid
+    message: Option<String>,          // The reason, for humans
+}
+
+// Struct to represent the /address/{address}/utxos query parameters
+#[derive(Deserialize)]
+struct UtxoQuery {
+    #[serde(default)]
+    min_conf: u32,                     // Minimum confirmations
+    include_unconfirmed: Option<bool>, // Include mempool outputs (default true)
+}
+
+// Settings fixed at startup
+struct AppConfig {
+    network: BtcNetwork,
+    max_tx_vsize: u64,
+    max_inputs: usize,      // Most inputs /create_tx accepts
+    max_outputs: usize,     // Most outputs /create_tx accepts
+    max_batch_size: usize,  // Most requests /create_tx/batch accepts
+    allow_network_override: bool,  // Requests may name a network other than `network`
+}
+
+// Application state shared by every worker without a global lock; only the
+// fee cache and the overridden networks' clients change after startup
 struct AppState {
-    network: Network,
+    config: Arc<AppConfig>,
+    client: BlockstreamClient,         // Cheap to clone; clones share one connection pool
+    clients: Mutex<HashMap<BtcNetwork, BlockstreamClient>>,  // Other networks' clients, built on first use
+    fees: RwLock<Option<CachedFees>>,  // Last fee estimates fetched from the backend
+    metrics: Arc<Metrics>,             // Also observes the client's backend requests
+}
+
+impl AppState {
+    // Locks are held only to copy the cache in or out, never across a backend call.
+    // A panic cannot leave the cache half-written, so a poisoned lock is still usable.
+    fn cached_fees(&self) -> Option<CachedFees> {
+        self.fees.read().unwrap_or_else(PoisonError::into_inner).clone()
+    }
+
+    fn cache_fees(&self, estimates: BTreeMap<u32, f64>) {
+        *self.fees.write().unwrap_or_else(PoisonError::into_inner) = Some(CachedFees { fetched_at: Instant::now(), estimates });
+    }
+
+    // Backend client for `network`; other networks follow their BTCX_ESPLORA_* variables
+    fn client_for(&self, network: BtcNetwork) -> Result<BlockstreamClient, ApiError> {
+        if network == self.config.network {
+            return Ok(self.client.clone());
+        }
+        let mut clients = self.clients.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(client) = clients.get(&network) {
+            return Ok(client.clone());
+        }
+        let client = BlockstreamClient::builder(network).observer(self.metrics.clone()).build()?;
+        log::info!("Using Esplora backend {} for {} requests", client.display_url(), network);
+        clients.insert(network, client.clone());
+        Ok(client)
+    }
+}
+
+// Fee estimates with the time they were fetched
+#[derive(Clone)]
+struct CachedFees {
+    fetched_at: Instant,
+    estimates: BTreeMap<u32, f64>,
+}
+
+// Fee estimates are served from memory for this long
+const FEE_CACHE_TTL: Duration = Duration::from_secs(30);
+// Older estimates are still served, marked stale, while the backend is down
+const FEE_STALE_LIMIT: Duration = Duration::from_secs(600);
+
+// Struct to represent the fee presets derived from the estimates
+#[derive(Serialize)]
+struct FeePresets {
+    fastest: f64,    // Next block
+    half_hour: f64,  // 3 blocks
+    hour: f64,       // 6 blocks
+    economy: f64,    // 144 blocks
+}
+
+// Struct to represent the /fees response; rates are in sat/vB
+#[derive(Serialize)]
+struct FeesResponse {
+    unit: &'static str,
+    estimates: BTreeMap<u32, f64>,
+    presets: FeePresets,
+    stale: bool,
+}
+
+// Struct to represent the /fees/{target_blocks} response
+#[derive(Serialize)]
+struct FeeTargetResponse {
+    unit: &'static str,
+    target_blocks: u32,
+    fee_rate: f64,
+    stale: bool,
+}
+
+const FEE_UNIT: &str = "sat_vb";
+
+// One kind of input or output in an /estimate_size request
+#[derive(Deserialize)]
+struct SizeItem {
+    #[serde(rename = "type")]
+    kind: String,  // e.g. "p2wpkh" or "p2wsh_multisig:2of3"
+    #[serde(default = "default_count")]
+    count: usize,
+}
+
+fn default_count() -> usize {
+    1
+}
+
+// Struct to represent the /estimate_size request
+#[derive(Deserialize)]
+struct EstimateSizeRequest {
+    inputs: Vec<SizeItem>,
+    outputs: Vec<SizeItem>,
+    fee_rate: Option<f64>,     // sat/vB
+    target_blocks: Option<u32>,  // Resolved through the fee estimates instead of fee_rate
+    network: Option<String>,  // Network whose fee estimates to use, with --allow-network-override
+}
+
+// Struct to represent the /estimate_size response; the fee fields are null without a rate
+#[derive(Serialize)]
+struct EstimateSizeResponse {
+    vsize: u64,
+    weight: u64,
+    fee_at_rate: Option<u64>,  // Satoshis, rounded up
+    rate_used: Option<f64>,
+    rate_source: Option<&'static str>,  // "request", "estimates" or "stale_estimates"
 }
 
 // Handler for the /create_tx endpoint
-async fn create_tx(data: web::Data<Mutex<AppState>>, req: web::Json<CreateTxRequest>) -> impl Responder {
-    let network = data.lock().unwrap().network;
+async fn create_tx(data: web::Data<AppState>, http: HttpRequest, req: web::Json<CreateTxRequest>) -> Result<HttpResponse, ApiError> {
+    let network = RequestNetwork::resolve(&data, &http, req.network.as_deref())?;
+    let limits = TxLimits { network: network.network, ..TxLimits::from(&*data.config) };
+    Ok(network.respond(build_tx(&req, limits)?))
+}
+
+// Network and count limits a /create_tx request is checked against
+#[derive(Clone, Copy)]
+struct TxLimits {
+    network: BtcNetwork,
+    max_inputs: usize,
+    max_outputs: usize,
+}
+
+impl From<&AppConfig> for TxLimits {
+    fn from(config: &AppConfig) -> Self {
+        TxLimits { network: config.network, max_inputs: config.max_inputs, max_outputs: config.max_outputs }
+    }
+}
+
+// Validate a /create_tx request and build the unsigned transaction
+fn build_tx(req: &CreateTxRequest, limits: TxLimits) -> Result<TxResponse, ApiError> {
+    let TxLimits { network, max_inputs, max_outputs } = limits;
+
+    // Check the counts before doing any per-item work
+    check_counts(req.inputs.len(), max_inputs, req.outputs.len(), max_outputs)?;
+
+    let version = match req.version.unwrap_or(1) {
+        1 => transaction::Version::ONE,
+        2 => transaction::Version::TWO,
+        other => {
+            return Err(ApiError::bad_request("invalid_version", format!("Transaction version {} is not supported, use 1 or 2", other)))
+        }
+    };
+    let lock_time = absolute::LockTime::from_consensus(req.locktime.unwrap_or(0));
+    let default_sequence = if req.rbf { RBF_SEQUENCE } else { Sequence::MAX };
 
     // Process transaction inputs
     let mut inputs = Vec::new();
-    for input_req in &req.inputs {
-        let txid = match Txid::from_str(&input_req.txid) {
-            Ok(txid) => txid,
-            Err(_) => return HttpResponse::BadRequest().body("Invalid txid"),
-        };
+    let mut seen = HashMap::new();
+    for (i, input_req) in req.inputs.iter().enumerate() {
+        let txid = Txid::from_str(&input_req.txid).map_err(|_| {
+            ApiError::bad_request("invalid_txid", format!("Invalid txid for input {}", i)).with_details(json!({ "input": i }))
+        })?;
         let vout = input_req.vout;
+        // Spending an outpoint twice makes the transaction invalid
+        if let Some(first) = seen.insert(OutPoint { txid, vout }, i) {
+            return Err(ApiError::bad_request(
+                "duplicate_input",
+                format!("Input {} spends {}:{}, already spent by input {}", i, txid, vout, first),
+            )
+            .with_details(json!({ "input": i, "first": first })));
+        }
+        let sequence = input_req.sequence.map_or(default_sequence, Sequence);
+        // BIP 68 relative locktimes are only enforced from version 2
+        if sequence.is_relative_lock_time() && version == transaction::Version::ONE {
+            return Err(ApiError::bad_request(
+                "relative_locktime_needs_v2",
+                format!("Input {} sequence {:#010x} is a relative locktime, which needs version 2", i, sequence.0),
+            )
+            .with_details(json!({ "input": i, "sequence": sequence.0 })));
+        }
         let input = TxIn {
             previous_output: OutPoint { txid, vout },
-            script_sig: bitcoin::Script::new(),  // Empty script for an unsigned transaction
-            sequence: Sequence(0xffffffff),      // Default sequence number
+            script_sig: ScriptBuf::new(),        // Empty script for an unsigned transaction
+            sequence,
             witness: bitcoin::Witness::new(),    // Empty witness for non-segwit
         };
         inputs.push(input);
     }
 
     // Process transaction outputs
-    let mut outputs = Vec::new();
-    for output_req in &req.outputs {
-        let address = match Address::from_str(&output_req.address) {
-            Ok(addr) => addr,
-            Err(_) => return HttpResponse::BadRequest().body("Invalid address"),
-        };
-        // Check if the address network matches the app's network
-        if address.network != network {
-            return HttpResponse::BadRequest().body("Address network mismatch");
-        }
-        let amount = Amount::from_sat(output_req.amount);
-        let script_pubkey = address.script_pubkey();
-        let output = TxOut {
-            value: amount.to_sat(),  // Convert amount to satoshis
-            script_pubkey,           // Script public key derived from the address
-        };
-        outputs.push(output);
+    let outputs = parse_outputs(&req.outputs, network, req.allow_dust)?;
+
+    // Consensus ignores nLockTime when every input is final
+    if lock_time != absolute::LockTime::ZERO && inputs.iter().all(|input| input.sequence == Sequence::MAX) {
+        return Err(ApiError::bad_request(
+            "locktime_not_enforced",
+            "locktime has no effect while every input sequence is 0xffffffff; set rbf or a lower input sequence",
+        ));
     }
 
     // Build the transaction
     let tx = Transaction {
-        version: 1,          // Transaction version
-        lock_time: PackedLockTime(0),  // No lock time
+        version,             // Transaction version
+        lock_time,           // Block height or time before which the transaction is invalid
         input: inputs,       // List of inputs
         output: outputs,     // List of outputs
     };
@@ -92,26 +526,2017 @@ async fn create_tx(data: web::Data<Mutex<AppState>>, req: web::Json<CreateTxRequ
     let tx_bytes = serialize(&tx);
     let tx_hex = hex::encode(tx_bytes);
 
-    // Return the response as JSON
-    HttpResponse::Ok().json(TxResponse { tx_hex })
+    // Echo the values used
+    Ok(TxResponse {
+        tx_hex,
+        version: tx.version.0,
+        locktime: tx.lock_time.to_consensus_u32(),
+        rbf: tx.is_explicitly_rbf(),
+        sequences: tx.input.iter().map(|input| input.sequence.0).collect(),
+    })
+}
+
+// Handler for the /create_tx/batch endpoint: independent /create_tx requests built concurrently
+async fn create_tx_batch(data: web::Data<AppState>, http: HttpRequest, req: web::Json<Vec<Value>>) -> Result<HttpResponse, ApiError> {
+    let started = Instant::now();
+    // Only the header chooses the network, one for the whole batch
+    let network = RequestNetwork::resolve(&data, &http, None)?;
+    let limits = TxLimits { network: network.network, ..TxLimits::from(&*data.config) };
+    let max_batch_size = data.config.max_batch_size;
+    let items = req.into_inner();
+    if items.is_empty() {
+        return Err(ApiError::bad_request("empty_batch", "Batch has no requests"));
+    }
+    if items.len() > max_batch_size {
+        return Err(ApiError::new(
+            StatusCode::PAYLOAD_TOO_LARGE,
+            "batch_too_large",
+            format!("Batch has {} requests, above the limit of {}", items.len(), max_batch_size),
+        )
+        .with_details(json!({ "count": items.len(), "max": max_batch_size })));
+    }
+
+    let results = web::block(move || build_batch(&items, limits)).await.map_err(|e| {
+        log::error!("Batch worker failed: {}", e);
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", "Internal server error")
+    })?;
+    let failed = results.iter().filter(|result| result.error.is_some()).count();
+    let summary = BatchSummary {
+        total: results.len(),
+        succeeded: results.len() - failed,
+        failed,
+        elapsed_ms: started.elapsed().as_millis() as u64,
+    };
+    Ok(network.respond(BatchResponse { results, summary }))
+}
+
+// Build every batch item, split across threads, keeping request order
+fn build_batch(items: &[Value], limits: TxLimits) -> Vec<BatchItemResult> {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk_size = items.len().div_ceil(threads).max(1);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = items
+            .chunks(chunk_size)
+            .enumerate()
+            .map(|(n, chunk)| {
+                scope.spawn(move || {
+                    chunk.iter().enumerate().map(|(i, item)| build_batch_item(n * chunk_size + i, item, limits)).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+    })
+}
+
+// Build one batch item; a malformed or invalid item only fails itself
+fn build_batch_item(index: usize, item: &Value, limits: TxLimits) -> BatchItemResult {
+    let client_ref = item.get("client_ref").cloned();
+    let outcome = serde_json::from_value::<CreateTxRequest>(item.clone())
+        .map_err(|e| ApiError::bad_request("invalid_request", e.to_string()))
+        .and_then(|req| {
+            if let Some(named) = &req.network {
+                if BtcNetwork::from_str(named.trim()).ok() != Some(limits.network) {
+                    return Err(ApiError::bad_request(
+                        "network_mismatch",
+                        format!("Batch items are built for {}; choose the batch network with the X-Btcx-Network header", limits.network),
+                    )
+                    .with_details(json!({ "network": limits.network })));
+                }
+            }
+            build_tx(&req, limits)
+        });
+    match outcome {
+        Ok(tx) => BatchItemResult { index, client_ref, status: StatusCode::OK.as_u16(), tx: Some(tx), error: None },
+        Err(error) => BatchItemResult { index, client_ref, status: error.status().as_u16(), tx: None, error: Some(error) },
+    }
+}
+
+// Handler for the /fund_tx endpoint: coin selection, fee and change done server-side
+async fn fund_tx(data: web::Data<AppState>, http: HttpRequest, req: web::Json<FundTxRequest>) -> Result<HttpResponse, ApiError> {
+    let network = RequestNetwork::resolve(&data, &http, req.network.as_deref())?;
+    let (tx, report, fee_rate) = fund(&data, &network, &req).await?;
+    let response = network.respond(FundTxResponse { tx_hex: serialize_hex(&tx), fee_rate, report });
+    Ok(audit::with_txid(response, tx.compute_txid()))
+}
+
+// Select coins and build the unsigned transaction, returning it with the report and fee rate used
+async fn fund(
+    data: &web::Data<AppState>,
+    on: &RequestNetwork,
+    req: &FundTxRequest,
+) -> Result<(Transaction, BuildReport, f64), ApiError> {
+    let AppConfig { max_inputs, max_outputs, .. } = *data.config;
+    let (network, client) = (on.network, &on.client);
+
+    // Listed UTXOs count against the input limit up front, fetched ones once selected
+    let listed = req.utxos.as_ref().map_or(1, Vec::len);
+    check_counts(listed, max_inputs, req.outputs.len(), max_outputs)?;
+    parse_outputs(&req.outputs, network, req.allow_dust)?;
+    let change_address = parse_address(&req.change_address, network)
+        .map_err(|e| e.with_details(json!({ "field": "change_address" })))?;
+    let coin_selection = match &req.strategy {
+        Some(strategy) => CoinSelectionStrategy::from_str(strategy).map_err(|_| {
+            ApiError::bad_request(
+                "invalid_strategy",
+                format!("Unknown strategy {:?}; use smallest_first, largest_first, random or branch_and_bound", strategy),
+            )
+        })?,
+        None => CoinSelectionStrategy::default(),
+    };
+
+    let fee_rate = match (req.fee_rate, req.target_blocks) {
+        (Some(fee_rate), None) if fee_rate.is_finite() && fee_rate > 0.0 => fee_rate,
+        (Some(_), None) => return Err(ApiError::bad_request("invalid_fee_rate", "fee_rate must be a positive number of sat/vB")),
+        (None, Some(target_blocks)) => {
+            let (estimates, _) = fee_estimates(data, on).await?;
+            // The map is never empty here, so a rate always resolves
+            estimate_for_target(&estimates, target_blocks).map_or(1.0, |e| e.sat_per_vbyte)
+        }
+        _ => return Err(ApiError::bad_request("fee_rate_required", "Give exactly one of fee_rate and target_blocks")),
+    };
+
+    let utxos = match (&req.utxos, &req.address) {
+        (Some(utxos), None) => {
+            let mut parsed = Vec::with_capacity(utxos.len());
+            for (i, utxo) in utxos.iter().enumerate() {
+                let txid = Txid::from_str(&utxo.txid).map_err(|_| {
+                    ApiError::bad_request("invalid_txid", format!("Invalid txid for UTXO {}", i)).with_details(json!({ "utxo": i }))
+                })?;
+                let address = parse_address(&utxo.address, network).map_err(|e| e.with_details(json!({ "utxo": i })))?;
+                let amount = Amount::from_sat(utxo.amount);
+                parsed.push(Utxo::new(txid, utxo.vout, amount, address.script_pubkey(), Some(utxo.address.clone())));
+            }
+            parsed
+        }
+        (None, Some(address)) => {
+            parse_address(address, network)?;
+            client.get_spendable_utxos(address).await?
+        }
+        _ => return Err(ApiError::bad_request("utxo_source_required", "Give exactly one of utxos and address")),
+    };
+
+    let config = TxBuilderConfig {
+        network,
+        fee_rate: fee_rate as f32,
+        rbf: req.rbf,
+        coin_selection,
+        ..Default::default()
+    };
+    let mut builder = TransactionBuilder::new(network).with_config(config).with_utxos(utxos);
+    builder
+        .add_outputs(req.outputs.iter().map(|o| OutputTarget::new(o.address.clone(), Amount::from_sat(o.amount))).collect())
+        .set_change_address(change_address);
+    let (tx, report) = builder.build_with_report()?;
+    if report.selected.len() > max_inputs {
+        return Err(ApiError::bad_request(
+            "too_many_inputs",
+            format!("Funding needs {} inputs, at most {} allowed", report.selected.len(), max_inputs),
+        )
+        .with_details(json!({ "count": report.selected.len(), "max": max_inputs })));
+    }
+    Ok((tx, report, fee_rate))
+}
+
+// Parse the BIP32 origin of a key: master fingerprint and derivation path
+fn parse_key_source(fingerprint: &str, path: &str) -> Result<KeySource, String> {
+    let fingerprint = Fingerprint::from_str(fingerprint).map_err(|e| format!("Invalid fingerprint {}: {}", fingerprint, e))?;
+    let path = DerivationPath::from_str(path).map_err(|e| format!("Invalid derivation path {}: {}", path, e))?;
+    Ok((fingerprint, path))
+}
+
+// Parse key origins into the map a PSBT input carries
+fn parse_derivations(derivations: &[Bip32DerivationRequest]) -> Result<BTreeMap<secp256k1::PublicKey, KeySource>, String> {
+    derivations
+        .iter()
+        .map(|d| {
+            let pubkey = secp256k1::PublicKey::from_str(&d.pubkey).map_err(|e| format!("Invalid public key {}: {}", d.pubkey, e))?;
+            Ok((pubkey, parse_key_source(&d.fingerprint, &d.path)?))
+        })
+        .collect()
+}
+
+// Handler for the /create_psbt endpoint: funded like /fund_tx, returned as a PSBT for external signers
+async fn create_psbt(data: web::Data<AppState>, http: HttpRequest, req: web::Json<CreatePsbtRequest>) -> Result<HttpResponse, ApiError> {
+    let on = RequestNetwork::resolve(&data, &http, req.fund.network.as_deref())?;
+    let (network, client) = (on.network, &on.client);
+    let invalid_derivation = |message: String| ApiError::bad_request("invalid_bip32_derivation", message);
+
+    // Key origins of listed UTXOs by outpoint; fetched ones share the top-level origins
+    let default_origins = parse_derivations(&req.bip32_derivation).map_err(invalid_derivation)?;
+    let mut origins = HashMap::new();
+    for (i, utxo) in req.fund.utxos.iter().flatten().enumerate() {
+        let parsed = parse_derivations(&utxo.bip32_derivation).map_err(|e| invalid_derivation(e).with_details(json!({ "utxo": i })))?;
+        if let Ok(txid) = Txid::from_str(&utxo.txid) {
+            origins.insert(OutPoint { txid, vout: utxo.vout }, parsed);
+        }
+    }
+    let mut xpubs = BTreeMap::new();
+    for (i, entry) in req.xpubs.iter().enumerate() {
+        let invalid = |message: String| ApiError::bad_request("invalid_xpub", message).with_details(json!({ "xpub": i }));
+        let xpub = Xpub::from_str(&entry.xpub).map_err(|e| invalid(format!("Invalid xpub: {}", e)))?;
+        if xpub.network != NetworkKind::from(Network::from(network)) {
+            return Err(ApiError::bad_request("network_mismatch", format!("xpub {} is not a {} key", i, network))
+                .with_details(json!({ "network": network, "xpub": i })));
+        }
+        xpubs.insert(xpub, parse_key_source(&entry.fingerprint, &entry.path).map_err(invalid)?);
+    }
+
+    let (tx, report, fee_rate) = fund(&data, &on, &req.fund).await?;
+
+    // Legacy inputs are signed against the whole previous transaction
+    let mut inputs = Vec::with_capacity(report.selected.len());
+    for utxo in &report.selected {
+        let spent_output = TxOut { value: utxo.amount, script_pubkey: utxo.script_pubkey.clone() };
+        let previous_tx = if spent_output.script_pubkey.is_witness_program() {
+            None
+        } else {
+            let hex = client.get_transaction_hex(&utxo.txid.to_string()).await?;
+            let previous = parse_transaction_hex(hex.trim()).map_err(|e| {
+                ApiError::new(StatusCode::BAD_GATEWAY, "upstream_error", format!("Backend sent an undecodable {}: {}", utxo.txid, e))
+            })?;
+            Some(previous)
+        };
+        let bip32_derivation = origins.get(&utxo.outpoint()).unwrap_or(&default_origins).clone();
+        inputs.push(Some(PsbtInputData { spent_output, previous_tx, bip32_derivation }));
+    }
+
+    let unsigned_txid = tx.compute_txid().to_string();
+    let psbt = build_psbt(tx, inputs, xpubs)?;
+    let response = on.respond(CreatePsbtResponse { psbt: psbt.to_string(), unsigned_txid: unsigned_txid.clone(), fee_rate, report });
+    Ok(audit::with_txid(response, unsigned_txid))
+}
+
+// Handler for the /finalize_psbt endpoint
+async fn finalize_psbt(req: web::Json<FinalizePsbtRequest>) -> Result<HttpResponse, ApiError> {
+    let mut psbt = Psbt::from_str(req.psbt.trim())
+        .map_err(|e| ApiError::bad_request("invalid_psbt", format!("Invalid PSBT: {}", e)))?;
+    let incomplete = btcx_lib::psbt::finalize_psbt(&mut psbt);
+    if !incomplete.is_empty() {
+        return Err(ApiError::new(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "psbt_incomplete",
+            format!("{} of {} inputs lack the signatures to finalize", incomplete.len(), psbt.inputs.len()),
+        )
+        .with_details(json!({ "incomplete_inputs": incomplete })));
+    }
+    // Refuses absurd fees, like a node would
+    let tx = psbt
+        .extract_tx()
+        .map_err(|e| ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, "psbt_extract_failed", e.to_string()))?;
+    let txid = tx.compute_txid().to_string();
+    let response = HttpResponse::Ok().json(FinalizePsbtResponse { tx_hex: serialize_hex(&tx), txid: txid.clone() });
+    Ok(audit::with_txid(response, txid))
+}
+
+// Require at least one input and output, and no more than the configured maximums
+fn check_counts(inputs: usize, max_inputs: usize, outputs: usize, max_outputs: usize) -> Result<(), ApiError> {
+    let counts = [("input", "too_many_inputs", inputs, max_inputs), ("output", "too_many_outputs", outputs, max_outputs)];
+    for (kind, code, count, max) in counts {
+        if count == 0 {
+            return Err(ApiError::bad_request("empty_transaction", format!("At least one {} is required", kind)));
+        }
+        if count > max {
+            return Err(ApiError::bad_request(code, format!("{} {}s given, at most {} allowed", count, kind, max))
+                .with_details(json!({ "count": count, "max": max })));
+        }
+    }
+    Ok(())
+}
+
+// Check output addresses, amounts and dust, naming the failing output
+fn parse_outputs(requested: &[TxOutputRequest], network: BtcNetwork, allow_dust: bool) -> Result<Vec<TxOut>, ApiError> {
+    let mut outputs = Vec::new();
+    let mut total = Amount::ZERO;
+    for (i, output_req) in requested.iter().enumerate() {
+        // Check the address and that its network matches the app's network
+        let address = parse_address(&output_req.address, network)
+            .map_err(|e| e.with_details(json!({ "output": i })))?;
+        let output = TxOut {
+            value: Amount::from_sat(output_req.amount),  // Amount in satoshis
+            script_pubkey: address.script_pubkey(),      // Script public key derived from the address
+        };
+
+        // The running total never exceeds the 21M BTC supply
+        total = match total.checked_add(output.value) {
+            Some(total) if total <= Amount::MAX_MONEY => total,
+            _ => {
+                return Err(ApiError::bad_request(
+                    "amount_too_large",
+                    format!("Output {} brings the total above {}", i, Amount::MAX_MONEY),
+                )
+                .with_details(json!({ "output": i })))
+            }
+        };
+        let dust_threshold = output.script_pubkey.minimal_non_dust();
+        if output.value < dust_threshold && !allow_dust {
+            return Err(ApiError::bad_request(
+                "dust_output",
+                format!("Output {} is below the {} sat dust threshold; pass allow_dust to override", i, dust_threshold.to_sat()),
+            )
+            .with_details(json!({ "output": i, "amount": output.value.to_sat(), "dust_threshold": dust_threshold.to_sat() })));
+        }
+        outputs.push(output);
+    }
+    Ok(outputs)
+}
+
+// Parse an address, requiring it to belong to the server network
+fn parse_address(address: &str, network: BtcNetwork) -> Result<Address, ApiError> {
+    let address = Address::from_str(address)
+        .map_err(|e| ApiError::bad_request("invalid_address", format!("Invalid address {}: {}", address, e)))?;
+    address.require_network(Network::from(network)).map_err(|_| {
+        ApiError::bad_request("network_mismatch", format!("Address is not a {} address", network))
+            .with_details(json!({ "network": network }))
+    })
+}
+
+// Handler for the /sign_tx endpoint
+async fn sign_tx(data: web::Data<AppState>, http: HttpRequest, req: web::Json<SignTxRequest>) -> Result<HttpResponse, ApiError> {
+    let on = RequestNetwork::resolve(&data, &http, req.network.as_deref())?;
+    let network = on.network;
+
+    let mut tx = parse_transaction_hex(req.unsigned_tx_hex.trim())?;
+    if req.inputs.len() != tx.input.len() {
+        let message = format!(
+            "Input count mismatch: transaction has {} inputs, but {} signing inputs provided",
+            tx.input.len(),
+            req.inputs.len()
+        );
+        return Err(ApiError::bad_request("input_count_mismatch", message)
+            .with_details(json!({ "expected": tx.input.len(), "provided": req.inputs.len() })));
+    }
+
+    // Resolve each input's key and spent script, checking both against the network
+    let mut signers = Vec::with_capacity(req.inputs.len());
+    for (i, input) in req.inputs.iter().enumerate() {
+        // The parse error never contains the key itself
+        let private_key = PrivateKey::from_wif(input.private_key_wif.trim()).map_err(|e| {
+            ApiError::bad_request("invalid_private_key", format!("Invalid WIF for input {}: {}", i, e))
+                .with_details(json!({ "input": i }))
+        })?;
+        let address = parse_address(&input.address, network).map_err(|e| e.with_details(json!({ "input": i })))?;
+        signers.push(InputSigner {
+            private_key,
+            script_pubkey: address.script_pubkey(),
+            amount: input.amount.map(Amount::from_sat),
+            sighash_type: None,
+            redeem_script: None,
+        });
+    }
+
+    sign_transaction(&mut tx, &signers, network)?;
+
+    let txid = tx.compute_txid().to_string();
+    let response = on.respond(SignTxResponse {
+        signed_tx_hex: serialize_hex(&tx),
+        txid: txid.clone(),
+        vsize: tx.vsize() as u64,
+    });
+    Ok(audit::with_txid(response, txid))
+}
+
+// Handler for the /broadcast endpoint
+async fn broadcast(data: web::Data<AppState>, http: HttpRequest, req: web::Json<BroadcastRequest>) -> Result<HttpResponse, ApiError> {
+    let network = RequestNetwork::resolve(&data, &http, req.network.as_deref())?;
+    let response = broadcast_hex(&data, &network.client, &req.tx_hex).await?;
+    let txid = response.txid.clone();
+    Ok(audit::with_txid(network.respond(response), txid))
+}
+
+// Broadcast a raw transaction, counting a transaction the backend already has as sent
+async fn broadcast_hex(data: &AppState, client: &BlockstreamClient, tx_hex: &str) -> Result<BroadcastResponse, ApiError> {
+    let (max_tx_vsize, metrics) = (data.config.max_tx_vsize, &data.metrics);
+
+    // Reject oversized or undecodable transactions before any upstream call
+    let tx_hex = tx_hex.trim();
+    let tx = parse_tx_hex(tx_hex, max_tx_vsize)?;
+    let txid = tx.compute_txid().to_string();
+
+    match client.broadcast_transaction(tx_hex).await {
+        Ok(_) => {
+            metrics.record_broadcast(BroadcastOutcome::Accepted);
+            Ok(BroadcastResponse { txid, already_known: false })
+        }
+        Err(e) => match BroadcastRejection::from_error(&e) {
+            Some(BroadcastRejection::AlreadyKnown) => {
+                metrics.record_broadcast(BroadcastOutcome::AlreadyKnown);
+                Ok(BroadcastResponse { txid, already_known: true })
+            }
+            // The backend rejected the transaction itself: pass its message on
+            Some(rejection) if e.is_client_error() => {
+                metrics.record_broadcast(BroadcastOutcome::Rejected);
+                let message = match &e {
+                    Error::Http { body, rpc_error, .. } => {
+                        rpc_error.as_ref().map_or(body.clone(), |rpc| rpc.message.clone())
+                    }
+                    _ => e.to_string(),
+                };
+                let code = match rejection {
+                    BroadcastRejection::InsufficientFee => "insufficient_fee",
+                    BroadcastRejection::MissingInputs => "missing_inputs",
+                    _ => "transaction_rejected",
+                };
+                Err(ApiError::new(StatusCode::UNPROCESSABLE_ENTITY, code, message)
+                    .with_details(json!({ "upstream_status": e.status() })))
+            }
+            _ => {
+                metrics.record_broadcast(BroadcastOutcome::Failed);
+                Err(e.into())
+            }
+        },
+    }
+}
+
+// Handler for the /decode_tx endpoint
+async fn decode_tx(data: web::Data<AppState>, http: HttpRequest, req: web::Json<DecodeTxRequest>) -> Result<HttpResponse, ApiError> {
+    let network = RequestNetwork::resolve(&data, &http, req.network.as_deref())?;
+    let tx_hex = req.tx_hex.trim();
+    parse_tx_hex(tx_hex, data.config.max_tx_vsize)?;
+    Ok(network.respond(decode_transaction(tx_hex, network.network)?))
+}
+
+// Handler for the /address/{address}/validate endpoint: 200 whether or not the address is valid
+async fn validate_address(data: web::Data<AppState>, http: HttpRequest, address: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let network = RequestNetwork::resolve(&data, &http, None)?;
+    let validation = match inspect_address(&address) {
+        Ok(inspection) => AddressValidation {
+            valid: true,
+            is_ours_network: inspection.networks.contains(&network.network),
+            networks: inspection.networks,
+            address_type: Some(inspection.address_type),
+            script_pubkey: Some(inspection.script_pubkey.to_hex_string()),
+            witness_version: inspection.witness_version,
+            reason: None,
+            message: None,
+        },
+        Err(problem) => AddressValidation {
+            valid: false,
+            networks: Vec::new(),
+            address_type: None,
+            script_pubkey: None,
+            witness_version: None,
+            is_ours_network: false,
+            reason: Some(problem),
+            message: Some(problem.to_string()),
+        },
+    };
+    Ok(network.respond(validation))
+}
+
+// Handler for the /address/{address}/utxos endpoint
+async fn address_utxos(
+    data: web::Data<AppState>,
+    http: HttpRequest,
+    address: web::Path<String>,
+    query: web::Query<UtxoQuery>,
+) -> Result<HttpResponse, ApiError> {
+    // The response is a bare array, with nowhere to echo another network
+    RequestNetwork::require_default(&data, &http)?;
+    // The client checks the address against the server network before any request
+    let utxos = data.client.get_spendable_utxos(&address).await?;
+    let include_unconfirmed = query.include_unconfirmed.unwrap_or(true);
+    let utxos: Vec<_> = utxos
+        .into_iter()
+        .filter(|utxo| {
+            let confirmations = utxo.confirmations.unwrap_or(0);
+            confirmations >= query.min_conf && (include_unconfirmed || confirmations > 0)
+        })
+        .collect();
+    Ok(HttpResponse::Ok().json(utxos))
+}
+
+// Get fee estimates from the cache or the backend, with whether they are stale;
+// only the server's network is cached
+async fn fee_estimates(data: &web::Data<AppState>, on: &RequestNetwork) -> Result<(BTreeMap<u32, f64>, bool), ApiError> {
+    let unavailable = || {
+        ApiError::new(StatusCode::SERVICE_UNAVAILABLE, "fees_unavailable", "Fee estimates unavailable")
+            .with_retry_after(UPSTREAM_RETRY_AFTER_SECS)
+    };
+    if on.network != data.config.network {
+        return match on.client.get_fee_estimates().await {
+            Ok(estimates) if !estimates.is_empty() => Ok((estimates, false)),
+            result => {
+                if let Err(e) = &result {
+                    log::warn!("Fee estimates for {} unavailable: {}", on.network, e);
+                }
+                Err(unavailable())
+            }
+        };
+    }
+
+    let (cached, metrics) = (data.cached_fees(), &data.metrics);
+    if let Some(cached) = &cached {
+        if cached.fetched_at.elapsed() < FEE_CACHE_TTL {
+            metrics.record_fee_lookup(CacheLookup::Hit);
+            return Ok((cached.estimates.clone(), false));
+        }
+    }
+
+    metrics.record_fee_lookup(CacheLookup::Miss);
+    match data.client.get_fee_estimates().await {
+        Ok(estimates) if !estimates.is_empty() => {
+            data.cache_fees(estimates.clone());
+            Ok((estimates, false))
+        }
+        result => {
+            if let Err(e) = &result {
+                log::warn!("Fee estimates unavailable: {}", e);
+            }
+            match cached {
+                Some(cached) if cached.fetched_at.elapsed() < FEE_STALE_LIMIT => {
+                    metrics.record_fee_lookup(CacheLookup::Stale);
+                    Ok((cached.estimates, true))
+                }
+                _ => Err(unavailable()),
+            }
+        }
+    }
+}
+
+// Handler for the /fees endpoint
+async fn fees(data: web::Data<AppState>, http: HttpRequest) -> Result<HttpResponse, ApiError> {
+    let network = RequestNetwork::resolve(&data, &http, None)?;
+    let (estimates, stale) = fee_estimates(&data, &network).await?;
+    // The map is never empty here, so every preset resolves
+    let preset = |target| estimate_for_target(&estimates, target).map_or(0.0, |e| e.sat_per_vbyte);
+    let presets = FeePresets {
+        fastest: preset(1),
+        half_hour: preset(3),
+        hour: preset(6),
+        economy: preset(144),
+    };
+    Ok(network.respond(FeesResponse { unit: FEE_UNIT, estimates, presets, stale }))
+}
+
+// Handler for the /fees/{target_blocks} endpoint
+async fn fee_for_target(data: web::Data<AppState>, http: HttpRequest, target_blocks: web::Path<u32>) -> Result<HttpResponse, ApiError> {
+    let network = RequestNetwork::resolve(&data, &http, None)?;
+    let target_blocks = target_blocks.into_inner();
+    let (estimates, stale) = fee_estimates(&data, &network).await?;
+    match estimates.get(&target_blocks) {
+        Some(&fee_rate) => Ok(network.respond(FeeTargetResponse { unit: FEE_UNIT, target_blocks, fee_rate, stale })),
+        None => {
+            let available: Vec<u32> = estimates.keys().copied().collect();
+            let message = format!("No estimate for {} blocks", target_blocks);
+            Err(ApiError::new(StatusCode::NOT_FOUND, "unknown_fee_target", message)
+                .with_details(json!({ "available_targets": available })))
+        }
+    }
+}
+
+// Handler for the /estimate_size endpoint
+async fn estimate_size(data: web::Data<AppState>, http: HttpRequest, req: web::Json<EstimateSizeRequest>) -> Result<HttpResponse, ApiError> {
+    let network = RequestNetwork::resolve(&data, &http, req.network.as_deref())?;
+    let total = |items: &[SizeItem]| items.iter().fold(0usize, |sum, item| sum.saturating_add(item.count));
+    check_counts(total(&req.inputs), data.config.max_inputs, total(&req.outputs), data.config.max_outputs)?;
+    let inputs: Vec<InputType> = parse_size_items(&req.inputs, "input", &INPUT_TYPES)?;
+    let outputs: Vec<OutputType> = parse_size_items(&req.outputs, "output", &OUTPUT_TYPES)?;
+
+    let rate = match (req.fee_rate, req.target_blocks) {
+        (None, None) => None,
+        (Some(fee_rate), None) if fee_rate.is_finite() && fee_rate > 0.0 => Some((fee_rate, "request")),
+        (Some(_), None) => return Err(ApiError::bad_request("invalid_fee_rate", "fee_rate must be a positive number of sat/vB")),
+        (None, Some(target_blocks)) => {
+            let (estimates, stale) = fee_estimates(&data, &network).await?;
+            // The map is never empty here, so a rate always resolves
+            let fee_rate = estimate_for_target(&estimates, target_blocks).map_or(1.0, |e| e.sat_per_vbyte);
+            Some((fee_rate, if stale { "stale_estimates" } else { "estimates" }))
+        }
+        _ => return Err(ApiError::bad_request("fee_rate_required", "Give at most one of fee_rate and target_blocks")),
+    };
+
+    let size = estimate_vsize(&inputs, &outputs);
+    Ok(network.respond(EstimateSizeResponse {
+        vsize: size.vsize,
+        weight: size.weight,
+        fee_at_rate: rate.map(|(fee_rate, _)| (size.vsize as f64 * fee_rate).ceil() as u64),
+        rate_used: rate.map(|(fee_rate, _)| fee_rate),
+        rate_source: rate.map(|(_, source)| source),
+    }))
+}
+
+// Expand /estimate_size items into one type per input or output, naming the failing item
+fn parse_size_items<T>(items: &[SizeItem], kind: &str, supported: &[&str]) -> Result<Vec<T>, ApiError>
+where
+    T: FromStr<Err = Error> + Copy,
+{
+    let mut parsed = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let item_type = T::from_str(&item.kind).map_err(|e| {
+            let message = match e {
+                Error::InvalidParameter(message) => message,
+                e => e.to_string(),
+            };
+            ApiError::bad_request("invalid_script_type", message).with_details(json!({ kind: i, "supported": supported }))
+        })?;
+        parsed.extend(std::iter::repeat_n(item_type, item.count));
+    }
+    Ok(parsed)
+}
+
+// Largest body for /create_tx and the funding and PSBT endpoints, ample for the default input and output limits
+const CREATE_TX_BODY_LIMIT: usize = 512 * 1024;
+// Largest body for /create_tx/batch; batches of large transactions should be split
+const BATCH_BODY_LIMIT: usize = 8 * CREATE_TX_BODY_LIMIT;
+
+// Register the endpoints, reporting malformed paths and queries as API errors
+fn routes(cfg: &mut web::ServiceConfig) {
+    let create_tx_json = web::JsonConfig::default().limit(CREATE_TX_BODY_LIMIT).error_handler(error::json_error);
+    let batch_json = web::JsonConfig::default().limit(BATCH_BODY_LIMIT).error_handler(error::json_error);
+    cfg.app_data(web::PathConfig::default().error_handler(error::path_error))
+        .app_data(web::QueryConfig::default().error_handler(error::query_error))
+        .service(web::resource("/create_tx").app_data(create_tx_json.clone()).route(web::post().to(create_tx)))
+        .service(web::resource("/create_tx/batch").app_data(batch_json).route(web::post().to(create_tx_batch)))
+        .service(web::resource("/fund_tx").app_data(create_tx_json.clone()).route(web::post().to(fund_tx)))
+        .service(web::resource("/create_psbt").app_data(create_tx_json.clone()).route(web::post().to(create_psbt)))
+        .service(web::resource("/finalize_psbt").app_data(create_tx_json.clone()).route(web::post().to(finalize_psbt)))
+        .service(web::resource("/estimate_size").app_data(create_tx_json).route(web::post().to(estimate_size)))
+        .route("/sign_tx", web::post().to(sign_tx))
+        .route("/broadcast", web::post().to(broadcast))
+        .route("/decode_tx", web::post().to(decode_tx))
+        .route("/address/{address}/utxos", web::get().to(address_utxos))
+        .route("/address/{address}/validate", web::get().to(validate_address))
+        .route("/fees", web::get().to(fees))
+        .route("/fees/{target_blocks}", web::get().to(fee_for_target));
+}
+
+// Accept request bodies large enough for the biggest allowed transaction in hex
+fn json_config(max_tx_vsize: u64) -> web::JsonConfig {
+    let limit = usize::try_from(max_tx_vsize * 4 * 2).unwrap_or(usize::MAX).saturating_add(64 * 1024);
+    web::JsonConfig::default().limit(limit).error_handler(error::json_error)
 }
 
 // Main function to set up and run the server
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    // Initialize application state with Bitcoin mainnet
-    let app_state = web::Data::new(Mutex::new(AppState {
-        network: Network::Bitcoin,
-    }));
+    // Fail fast on bad flags or environment rather than at the first request
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    audit::init(config.log_format);
+    log::info!(
+        "Starting on {} for network {} with {} workers",
+        config.bind,
+        config.network,
+        config.workers.map_or_else(|| "default".to_string(), |n| n.to_string())
+    );
+
+    // Initialize application state with the configured network; the backend
+    // endpoint follows BTCX_ESPLORA_* like the command-line tools
+    let network = config.network;
+    let metrics = Arc::new(Metrics::default());
+    let client = match BlockstreamClient::builder(network).observer(metrics.clone()).build() {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(2);
+        }
+    };
+    log::info!("Using Esplora backend {}", client.display_url());
+    if config.allow_network_override {
+        log::info!("Requests may choose another network with a network field or the X-Btcx-Network header");
+    }
+    let max_tx_vsize = config.max_tx_vsize;
+    if config.api_keys.is_empty() {
+        log::warn!("No API keys configured; every endpoint is open");
+    } else {
+        log::info!("API key authentication enabled with {} keys", config.api_keys.len());
+    }
+    let api_keys = web::Data::new(ApiKeys(config.api_keys));
+    let cors_policy = config.cors.map(web::Data::new);
+    let public_metrics = config.public_metrics.then(|| web::Data::new(PublicMetrics));
+    let swagger_ui = config.swagger_ui;
+    let index_store: Box<dyn IndexStore> = match &config.wallet_state {
+        Some(path) => match FileIndexStore::open(path) {
+            Ok(store) => Box::new(store),
+            Err(e) => {
+                eprintln!("Error: Cannot read wallet state {}: {}", path.display(), e);
+                std::process::exit(2);
+            }
+        },
+        None => Box::new(MemoryIndexStore::default()),
+    };
+    if !config.wallets.is_empty() {
+        let names: Vec<&str> = config.wallets.iter().map(wallet::Wallet::name).collect();
+        log::info!("Serving wallets {}", names.join(", "));
+    }
+    let wallets = web::Data::new(Wallets::new(config.wallets, index_store));
+    let tx_events = web::Data::new(TxEvents::new(config.tx_events));
+    let idempotency_keys = web::Data::new(IdempotencyKeys::new(Box::new(MemoryIdempotencyStore::new(config.idempotency))));
+    // One limiter for all workers
+    let rate_limiter = web::Data::new(RateLimiter::new(
+        config.local_rate_limit,
+        config.upstream_rate_limit,
+        config.trust_forwarded_for,
+    ));
+
+    // Keep readiness fresh in the background so probes never wait on the backend
+    let readiness = web::Data::new(Readiness::default());
+    let shutdown = web::Data::new(Shutdown::default());
+    actix_web::rt::spawn({
+        let readiness = readiness.clone();
+        let client = client.clone();
+        let token = shutdown.token();
+        async move { token.run_until_cancelled(readiness.run(client)).await }
+    });
+
+    let app_state = web::Data::new(AppState {
+        config: Arc::new(AppConfig {
+            network,
+            max_tx_vsize,
+            max_inputs: config.max_inputs,
+            max_outputs: config.max_outputs,
+            max_batch_size: config.max_batch_size,
+            allow_network_override: config.allow_network_override,
+        }),
+        client,
+        clients: Mutex::new(HashMap::new()),
+        fees: RwLock::new(None),
+        metrics: metrics.clone(),
+    });
+    let metrics = web::Data::from(metrics);
 
     // Start the HTTP server
-    HttpServer::new(move || {
-        App::new()
-            .app_data(app_state.clone())  // Share the app state across requests
-            .route("/create_tx", web::post().to(create_tx))  // Define the endpoint
+    let mut server = HttpServer::new({
+        let readiness = readiness.clone();
+        let shutdown = shutdown.clone();
+        move || {
+            App::new()
+                .app_data(app_state.clone())  // Share the app state across requests
+                .app_data(readiness.clone())
+                .app_data(api_keys.clone())
+                .app_data(rate_limiter.clone())
+                .app_data(shutdown.clone())
+                .app_data(metrics.clone())
+                .app_data(wallets.clone())
+                .app_data(tx_events.clone())
+                .app_data(idempotency_keys.clone())
+                .configure(|cfg| {
+                    if let Some(policy) = &cors_policy {
+                        cfg.app_data(policy.clone());
+                    }
+                    if let Some(public) = &public_metrics {
+                        cfg.app_data(public.clone());
+                    }
+                })
+                .app_data(json_config(max_tx_vsize))
+                // Replays are scoped by API key and count toward the rate limit
+                .wrap(from_fn(idempotency::replay_responses))
+                // Limits apply after authentication so keyed clients are counted by key
+                .wrap(from_fn(ratelimit::limit_requests))
+                .wrap(from_fn(auth::require_api_key))
+                // Preflights are answered before authentication
+                .wrap(from_fn(cors::cors))
+                .wrap(DefaultHeaders::new().add((NETWORK_HEADER, network.to_string())))
+                // Logs the key name, never the key, and request bodies only redacted
+                .wrap(from_fn(audit::log_requests))
+                .wrap(from_fn(metrics::record_requests))
+                // Outermost, so requests refused by any layer count toward the drain report
+                .wrap(from_fn(shutdown::track_requests))
+                .configure(health::routes)
+                .configure(metrics::routes)
+                .configure(|cfg| openapi::routes(cfg, swagger_ui))
+                .configure(wallet::routes)
+                .configure(events::routes)
+                .configure(routes)
+        }
     })
-    // .bind("127.0.0.1:8080")?  // Bind to localhost:8080
-    .bind("0.0.0.0:8080")? // This ensures the server is accessible from outside the container.
-    .run()
-    .await  // Run the server
-}
\ No newline at end of file
+    .disable_signals()  // Handled below, to fail readiness before stopping
+    .shutdown_timeout(config.drain_timeout.as_secs());
+    if let Some(workers) = config.workers {
+        server = server.workers(workers);
+    }
+    let server = match config.tls {
+        Some(files) => {
+            // Unreadable or mismatched certificates stop startup
+            let loaded = tls::CertResolver::load(files)
+                .map(Arc::new)
+                .and_then(|resolver| Ok((resolver.server_config()?, resolver)));
+            let (tls_config, resolver) = match loaded {
+                Ok(loaded) => loaded,
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(2);
+                }
+            };
+            actix_web::rt::spawn(shutdown.token().run_until_cancelled_owned(resolver.reload_on_sighup()));
+            log::info!("Serving HTTPS; send SIGHUP to reload the certificate");
+            server.bind_rustls_0_23(config.bind, tls_config)?
+        }
+        None => server.bind(config.bind)?,
+    }
+    .run();
+
+    let handle = server.handle();
+    actix_web::rt::spawn({
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            shutdown::stop(handle, &readiness, &shutdown).await;
+        }
+    });
+    server.await?;  // Run the server
+
+    let (drained, aborted) = shutdown.counts();
+    log::info!("Stopped: {} requests drained, {} aborted", drained, aborted);
+    Ok(())
+}
+
+// Wait for Ctrl-C or, on Unix, SIGTERM
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use actix_web::rt::signal::unix::{signal, SignalKind};
+        let mut interrupt = signal(SignalKind::interrupt()).expect("cannot listen for SIGINT");
+        let mut terminate = signal(SignalKind::terminate()).expect("cannot listen for SIGTERM");
+        std::future::poll_fn(|cx| {
+            if interrupt.poll_recv(cx).is_ready() || terminate.poll_recv(cx).is_ready() {
+                std::task::Poll::Ready(())
+            } else {
+                std::task::Poll::Pending
+            }
+        })
+        .await;
+    }
+    #[cfg(not(unix))]
+    let _ = actix_web::rt::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::RETRY_AFTER;
+    use actix_web::test;
+    use bitcoin::consensus::encode::deserialize_hex;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const TXID: &str = "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16";
+
+    fn create_tx_body(address: &str) -> serde_json::Value {
+        serde_json::json!({
+            "inputs": [{ "txid": TXID, "vout": 0 }],
+            "outputs": [{ "address": address, "amount": 10_000 }]
+        })
+    }
+
+    pub(crate) fn state(network: BtcNetwork, backend: &str) -> AppState {
+        let metrics = Arc::new(Metrics::default());
+        AppState {
+            config: Arc::new(AppConfig {
+                network,
+                max_tx_vsize: 1_000,
+                max_inputs: 3,
+                max_outputs: 3,
+                max_batch_size: 4,
+                allow_network_override: false,
+            }),
+            client: BlockstreamClient::builder(network).base_url(backend).observer(metrics.clone()).build().unwrap(),
+            clients: Mutex::new(HashMap::new()),
+            fees: RwLock::new(None),
+            metrics,
+        }
+    }
+
+    macro_rules! app {
+        ($network:expr) => {
+            app!($network, "http://127.0.0.1:9")
+        };
+        ($network:expr, $backend:expr) => {
+            app!(@data web::Data::new(state($network, &$backend)))
+        };
+        (@data $data:expr) => {{
+            let data = $data;
+            let network = data.config.network;
+            test::init_service(
+                App::new()
+                    .app_data(data)
+                    .app_data(json_config(1_000))
+                    .wrap(DefaultHeaders::new().add((NETWORK_HEADER, network.to_string())))
+                    .configure(routes),
+            )
+            .await
+        }};
+    }
+
+    #[actix_web::test]
+    async fn test_create_tx_follows_configured_network() {
+        let app = app!(BtcNetwork::Bitcoin);
+
+        let req = test::TestRequest::post()
+            .uri("/create_tx")
+            .set_json(create_tx_body("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert!(resp.status().is_success());
+        assert_eq!(resp.headers().get(NETWORK_HEADER).unwrap(), "bitcoin");
+
+        let req = test::TestRequest::post()
+            .uri("/create_tx")
+            .set_json(create_tx_body("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "network_mismatch");
+        assert_eq!(body["error"]["details"], serde_json::json!({ "network": "bitcoin", "output": 0 }));
+    }
+
+    #[actix_web::test]
+    async fn test_create_tx_network_override() {
+        const TESTNET_ADDRESS: &str = "tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx";
+        let testnet_body = || {
+            let mut body = create_tx_body(TESTNET_ADDRESS);
+            body["network"] = "testnet".into();
+            body
+        };
+
+        // Off by default: naming another network is refused, naming the server's own is fine
+        let app = app!(BtcNetwork::Bitcoin);
+        let req = test::TestRequest::post().uri("/create_tx").set_json(testnet_body()).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "network_override_disabled");
+        assert_eq!(body["error"]["details"], serde_json::json!({ "network": "bitcoin", "requested": "testnet" }));
+
+        let mut body = create_tx_body("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq");
+        body["network"] = "mainnet".into();
+        let req = test::TestRequest::post().uri("/create_tx").set_json(body).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["network"], "bitcoin");
+
+        // Enabled: the testnet address is accepted and the network echoed
+        let mut data = state(BtcNetwork::Bitcoin, "http://127.0.0.1:9");
+        data.config = Arc::new(AppConfig { allow_network_override: true, ..*data.config });
+        let app = app!(@data web::Data::new(data));
+        let req = test::TestRequest::post().uri("/create_tx").set_json(testnet_body()).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers().get(NETWORK_HEADER).unwrap(), "testnet");
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["network"], "testnet");
+        assert!(body["tx_hex"].is_string());
+
+        // The header works alike, but must agree with the body
+        let req = test::TestRequest::post()
+            .uri("/create_tx")
+            .insert_header((NETWORK_HEADER, "testnet"))
+            .set_json(create_tx_body(TESTNET_ADDRESS))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        let req = test::TestRequest::post()
+            .uri("/create_tx")
+            .insert_header((NETWORK_HEADER, "signet"))
+            .set_json(testnet_body())
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "network_conflict");
+
+        // Without an override the server's network still applies
+        let req = test::TestRequest::post().uri("/create_tx").set_json(create_tx_body(TESTNET_ADDRESS)).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        assert!(resp.headers().get(NETWORK_HEADER).is_some_and(|network| network == "bitcoin"));
+        let req = test::TestRequest::post()
+            .uri("/create_tx")
+            .set_json(create_tx_body("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq"))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert!(body.get("network").is_none());
+
+        // Batch items cannot pick their own network
+        let req = test::TestRequest::post()
+            .uri("/create_tx/batch")
+            .insert_header((NETWORK_HEADER, "testnet"))
+            .set_json(serde_json::json!([testnet_body(), create_tx_body(TESTNET_ADDRESS)]))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["network"], "testnet");
+        assert_eq!(body["summary"]["succeeded"], 2);
+        let mut mainnet_item = create_tx_body(TESTNET_ADDRESS);
+        mainnet_item["network"] = "mainnet".into();
+        let req = test::TestRequest::post()
+            .uri("/create_tx/batch")
+            .insert_header((NETWORK_HEADER, "testnet"))
+            .set_json(serde_json::json!([mainnet_item]))
+            .to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["results"][0]["error"]["code"], "network_mismatch");
+    }
+
+    async fn create_tx_error(body: serde_json::Value) -> (u16, serde_json::Value) {
+        let app = app!(BtcNetwork::Bitcoin);
+        let req = test::TestRequest::post().uri("/create_tx").set_json(body).to_request();
+        let resp = test::call_service(&app, req).await;
+        let status = resp.status().as_u16();
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        (status, body["error"].clone())
+    }
+
+    #[actix_web::test]
+    async fn test_create_tx_limits() {
+        let input = |vout: u32| serde_json::json!({ "txid": TXID, "vout": vout });
+        let output = |amount: u64| serde_json::json!({ "address": ADDRESS, "amount": amount });
+
+        // Spending the same outpoint twice used to produce an invalid transaction
+        let body = serde_json::json!({ "inputs": [input(0), input(1), input(0)], "outputs": [output(10_000)] });
+        let (status, error) = create_tx_error(body).await;
+        assert_eq!(status, 400);
+        assert_eq!(error["code"], "duplicate_input");
+        assert_eq!(error["details"], serde_json::json!({ "input": 2, "first": 0 }));
+
+        let body = serde_json::json!({ "inputs": (0..4).map(input).collect::<Vec<_>>(), "outputs": [output(10_000)] });
+        let (status, error) = create_tx_error(body).await;
+        assert_eq!(status, 400);
+        assert_eq!(error["code"], "too_many_inputs");
+        assert_eq!(error["details"], serde_json::json!({ "count": 4, "max": 3 }));
+
+        let body = serde_json::json!({ "inputs": [input(0)], "outputs": vec![output(10_000); 4] });
+        assert_eq!(create_tx_error(body).await.1["code"], "too_many_outputs");
+
+        let body = serde_json::json!({ "inputs": [], "outputs": [output(10_000)] });
+        assert_eq!(create_tx_error(body).await.1["code"], "empty_transaction");
+    }
+
+    #[actix_web::test]
+    async fn test_create_tx_amounts() {
+        let app = app!(BtcNetwork::Bitcoin);
+        let body = |amounts: &[u64], allow_dust: bool| {
+            let outputs: Vec<_> = amounts.iter().map(|a| serde_json::json!({ "address": ADDRESS, "amount": a })).collect();
+            serde_json::json!({ "inputs": [{ "txid": TXID, "vout": 0 }], "outputs": outputs, "allow_dust": allow_dust })
+        };
+
+        // P2WPKH outputs are dust below 294 sat
+        let (status, error) = create_tx_error(body(&[10_000, 293], false)).await;
+        assert_eq!(status, 400);
+        assert_eq!(error["code"], "dust_output");
+        assert_eq!(error["details"], serde_json::json!({ "output": 1, "amount": 293, "dust_threshold": 294 }));
+        let req = test::TestRequest::post().uri("/create_tx").set_json(body(&[10_000, 293], true)).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        // 20M BTC twice is over the 21M BTC supply
+        let (status, error) = create_tx_error(body(&[2_000_000_000_000_000, 2_000_000_000_000_000], false)).await;
+        assert_eq!(status, 400);
+        assert_eq!(error["code"], "amount_too_large");
+        assert_eq!(error["details"]["output"], 1);
+        let (_, error) = create_tx_error(body(&[u64::MAX], false)).await;
+        assert_eq!(error["details"]["output"], 0);
+    }
+
+    #[actix_web::test]
+    async fn test_create_tx_version_locktime_and_sequences() {
+        let app = app!(BtcNetwork::Bitcoin);
+        let outputs = serde_json::json!([{ "address": ADDRESS, "amount": 10_000 }]);
+
+        // Defaults are unchanged: version 1, no locktime, final sequences
+        let req = test::TestRequest::post().uri("/create_tx").set_json(create_tx_body(ADDRESS)).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["version"], 1);
+        assert_eq!(body["locktime"], 0);
+        assert_eq!(body["rbf"], false);
+        assert_eq!(body["sequences"], serde_json::json!([0xffffffffu32]));
+
+        // rbf applies to inputs without their own sequence; 144 blocks relative on the second
+        let body = serde_json::json!({
+            "inputs": [{ "txid": TXID, "vout": 0 }, { "txid": TXID, "vout": 1, "sequence": 144 }],
+            "outputs": outputs,
+            "version": 2,
+            "locktime": 840_000,
+            "rbf": true
+        });
+        let req = test::TestRequest::post().uri("/create_tx").set_json(body).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let tx: Transaction = deserialize_hex(body["tx_hex"].as_str().unwrap()).unwrap();
+        assert_eq!(tx.version, transaction::Version::TWO);
+        assert_eq!(tx.lock_time, absolute::LockTime::from_height(840_000).unwrap());
+        assert_eq!(tx.input[0].sequence, Sequence(0xfffffffd));
+        assert_eq!(tx.input[1].sequence, Sequence(144));
+        assert_eq!(body["version"], 2);
+        assert_eq!(body["locktime"], 840_000);
+        assert_eq!(body["rbf"], true);
+        assert_eq!(body["sequences"], serde_json::json!([0xfffffffdu32, 144]));
+
+        // A unix time locktime enabled by a non-final sequence
+        let body = serde_json::json!({
+            "inputs": [{ "txid": TXID, "vout": 0, "sequence": 0xfffffffeu32 }],
+            "outputs": outputs,
+            "locktime": 1_700_000_000
+        });
+        let req = test::TestRequest::post().uri("/create_tx").set_json(body).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let tx: Transaction = deserialize_hex(body["tx_hex"].as_str().unwrap()).unwrap();
+        assert_eq!(tx.version, transaction::Version::ONE);
+        assert!(tx.lock_time.is_block_time());
+        assert_eq!(tx.lock_time.to_consensus_u32(), 1_700_000_000);
+        assert!(!tx.is_explicitly_rbf());
+    }
+
+    #[actix_web::test]
+    async fn test_create_tx_rejects_ineffective_locktimes() {
+        let outputs = serde_json::json!([{ "address": ADDRESS, "amount": 10_000 }]);
+
+        let body = serde_json::json!({ "inputs": [{ "txid": TXID, "vout": 0 }], "outputs": outputs, "locktime": 840_000 });
+        let (status, error) = create_tx_error(body).await;
+        assert_eq!(status, 400);
+        assert_eq!(error["code"], "locktime_not_enforced");
+
+        let body = serde_json::json!({ "inputs": [{ "txid": TXID, "vout": 0, "sequence": 144 }], "outputs": outputs });
+        let (status, error) = create_tx_error(body).await;
+        assert_eq!(status, 400);
+        assert_eq!(error["code"], "relative_locktime_needs_v2");
+        assert_eq!(error["details"], serde_json::json!({ "input": 0, "sequence": 144 }));
+
+        let body = serde_json::json!({ "inputs": [{ "txid": TXID, "vout": 0 }], "outputs": outputs, "version": 3 });
+        assert_eq!(create_tx_error(body).await.1["code"], "invalid_version");
+    }
+
+    #[actix_web::test]
+    async fn test_create_tx_body_limit() {
+        let mut body = create_tx_body(ADDRESS);
+        body["padding"] = "x".repeat(CREATE_TX_BODY_LIMIT).into();
+        let (status, error) = create_tx_error(body).await;
+        assert_eq!(status, 413);
+        assert_eq!(error["code"], "payload_too_large");
+    }
+
+    #[actix_web::test]
+    async fn test_create_tx_reports_failing_input() {
+        let app = app!(BtcNetwork::Bitcoin);
+        let mut body = create_tx_body("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq");
+        body["inputs"].as_array_mut().unwrap().push(serde_json::json!({ "txid": "xyz", "vout": 1 }));
+        let req = test::TestRequest::post().uri("/create_tx").set_json(body).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "invalid_txid");
+        assert_eq!(body["error"]["details"]["input"], 1);
+
+        // Malformed bodies share the error shape
+        let req = test::TestRequest::post()
+            .uri("/create_tx")
+            .insert_header(("content-type", "application/json"))
+            .set_payload("{")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "invalid_request");
+    }
+
+    #[actix_web::test]
+    async fn test_create_tx_batch_isolates_failures() {
+        let app = app!(BtcNetwork::Bitcoin);
+        let item = |client_ref: &str, address: &str| {
+            let mut item = create_tx_body(address);
+            item["client_ref"] = client_ref.into();
+            item
+        };
+        let batch = serde_json::json!([
+            item("payout-1", ADDRESS),
+            item("payout-2", "not-an-address"),
+            { "outputs": [] },
+            item("payout-4", ADDRESS),
+        ]);
+        let req = test::TestRequest::post().uri("/create_tx/batch").set_json(batch).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        let results = body["results"].as_array().unwrap();
+        assert_eq!(results.len(), 4);
+        for (i, result) in results.iter().enumerate() {
+            assert_eq!(result["index"], i);
+        }
+        assert_eq!(results[0]["client_ref"], "payout-1");
+        assert_eq!(results[0]["status"], 200);
+        assert!(results[0]["tx"]["tx_hex"].is_string());
+        assert!(results[0].get("error").is_none());
+        assert_eq!(results[1]["client_ref"], "payout-2");
+        assert_eq!(results[1]["status"], 400);
+        assert_eq!(results[1]["error"]["code"], "invalid_address");
+        assert!(results[1].get("tx").is_none());
+        // Items that do not deserialize fail alone too
+        assert!(results[2].get("client_ref").is_none());
+        assert_eq!(results[2]["error"]["code"], "invalid_request");
+        assert_eq!(results[3]["client_ref"], "payout-4");
+        assert_eq!(results[3]["tx"]["tx_hex"], results[0]["tx"]["tx_hex"]);
+        assert_eq!(body["summary"]["total"], 4);
+        assert_eq!(body["summary"]["succeeded"], 2);
+        assert_eq!(body["summary"]["failed"], 2);
+        assert!(body["summary"]["elapsed_ms"].is_u64());
+
+        // The whole batch is refused when above the limit, or empty
+        let batch: Vec<_> = (0..5).map(|i| item(&i.to_string(), ADDRESS)).collect();
+        let req = test::TestRequest::post().uri("/create_tx/batch").set_json(batch).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 413);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "batch_too_large");
+        assert_eq!(body["error"]["details"], serde_json::json!({ "count": 5, "max": 4 }));
+        let req = test::TestRequest::post().uri("/create_tx/batch").set_json(serde_json::json!([])).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+    }
+
+    // Regtest keys 0x11..11 (P2PKH) and 0x22..22 (P2WPKH) and their addresses
+    pub(crate) const LEGACY_WIF: &str = "cN9spWsvaxA8taS7DFMxnk1yJD2gaF2PX1npuTpy3vuZFJdwavaw";
+    pub(crate) const SEGWIT_WIF: &str = "cNj3zTdrLAMQtUhdFPPVJtRY7a3TdUF38ShW5MrJkVh1CVaeuEGU";
+
+    pub(crate) fn regtest_signing_request() -> serde_json::Value {
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let legacy = PrivateKey::from_wif(LEGACY_WIF).unwrap().public_key(&secp);
+        let segwit = bitcoin::CompressedPublicKey::from_private_key(&secp, &PrivateKey::from_wif(SEGWIT_WIF).unwrap()).unwrap();
+        let unsigned = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: (0..2)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint { txid: Txid::from_str(TXID).unwrap(), vout },
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: Amount::from_sat(140_000),
+                script_pubkey: ScriptBuf::new_p2wpkh(&segwit.wpubkey_hash()),
+            }],
+        };
+        serde_json::json!({
+            "unsigned_tx_hex": serialize_hex(&unsigned),
+            "inputs": [
+                { "private_key_wif": LEGACY_WIF, "address": Address::p2pkh(legacy, Network::Regtest).to_string() },
+                {
+                    "private_key_wif": SEGWIT_WIF,
+                    "address": Address::p2wpkh(&segwit, Network::Regtest).to_string(),
+                    "amount": 50_000
+                }
+            ]
+        })
+    }
+
+    #[actix_web::test]
+    async fn test_sign_tx() {
+        let app = app!(BtcNetwork::Regtest);
+        let req = test::TestRequest::post()
+            .uri("/sign_tx")
+            .set_json(regtest_signing_request())
+            .to_request();
+        let resp: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+
+        let signed: Transaction = deserialize_hex(resp["signed_tx_hex"].as_str().unwrap()).unwrap();
+        assert_eq!(resp["txid"], signed.compute_txid().to_string());
+        assert_eq!(resp["vsize"], signed.vsize() as u64);
+        assert!(!signed.input[0].script_sig.is_empty());
+        assert_eq!(signed.input[1].witness.len(), 2);
+    }
+
+    #[actix_web::test]
+    async fn test_sign_tx_rejects_bad_input() {
+        let app = app!(BtcNetwork::Regtest);
+
+        let mut body = regtest_signing_request();
+        body["inputs"].as_array_mut().unwrap().pop();
+        let req = test::TestRequest::post().uri("/sign_tx").set_json(body).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "input_count_mismatch");
+        assert_eq!(body["error"]["details"], serde_json::json!({ "expected": 2, "provided": 1 }));
+
+        let mut body = regtest_signing_request();
+        body["inputs"][1]["private_key_wif"] = "not-a-wif".into();
+        let req = test::TestRequest::post().uri("/sign_tx").set_json(body).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "invalid_private_key");
+        assert_eq!(body["error"]["details"]["input"], 1);
+        assert!(!body.to_string().contains("not-a-wif"));
+
+        // Keys and addresses must belong to the server's network
+        let app = app!(BtcNetwork::Bitcoin);
+        let req = test::TestRequest::post().uri("/sign_tx").set_json(regtest_signing_request()).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "network_mismatch");
+        assert_eq!(body["error"]["details"]["input"], 0);
+    }
+
+    async fn broadcast_with_backend(status: usize, body: &str) -> (u16, serde_json::Value) {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/tx")
+            .with_status(status)
+            .with_body(body)
+            .create_async()
+            .await;
+        let app = app!(BtcNetwork::Bitcoin, server.url());
+
+        let req = test::TestRequest::post()
+            .uri("/broadcast")
+            .set_json(serde_json::json!({ "tx_hex": LEGACY_TX_HEX }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        mock.assert_async().await;
+        let status = resp.status().as_u16();
+        (status, test::read_body_json(resp).await)
+    }
+
+    // Block 170: the first bitcoin transfer
+    pub(crate) const LEGACY_TX_HEX: &str = "0100000001c997a5e56e104102fa209c6a852dd90660a20b2d9c352423edce25857fcd3704000000004847304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd410220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d0901ffffffff0200ca9a3b00000000434104ae1a62fe09c5f51b13905f07f06b99a2f7159b2225f374cd378d71302fa28414e7aab37397f554a7df5f142c21c1b7303b8a0626f1baded5c72a704f7e6cd84cac00286bee0000000043410411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a5cb2e0eaddfb84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3ac00000000";
+
+    #[actix_web::test]
+    async fn test_broadcast() {
+        let (status, body) = broadcast_with_backend(200, TXID).await;
+        assert_eq!(status, 200);
+        assert_eq!(body, serde_json::json!({ "txid": TXID, "already_known": false }));
+
+        let (status, body) = broadcast_with_backend(
+            400,
+            r#"sendrawtransaction RPC error: {"code":-27,"message":"Transaction already in block chain"}"#,
+        )
+        .await;
+        assert_eq!(status, 200);
+        assert_eq!(body["already_known"], true);
+
+        let (status, body) = broadcast_with_backend(
+            400,
+            r#"sendrawtransaction RPC error: {"code":-25,"message":"bad-txns-inputs-missingorspent"}"#,
+        )
+        .await;
+        assert_eq!(status, 422);
+        assert_eq!(
+            body,
+            serde_json::json!({ "error": {
+                "code": "missing_inputs",
+                "message": "bad-txns-inputs-missingorspent",
+                "details": { "upstream_status": 400 }
+            }})
+        );
+    }
+
+    // A backend answering every request with an empty list after `delay`, one
+    // thread per connection; returns its URL and the most requests it held at once
+    fn slow_backend(delay: Duration) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (active, peak) = (Arc::new(AtomicUsize::new(0)), Arc::new(AtomicUsize::new(0)));
+        let seen = peak.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let (mut stream, active, peak) = (stream.unwrap(), active.clone(), peak.clone());
+                std::thread::spawn(move || {
+                    let mut head = Vec::new();
+                    let mut buf = [0; 1024];
+                    while !head.ends_with(b"\r\n\r\n") {
+                        match stream.read(&mut buf) {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => head.extend_from_slice(&buf[..n]),
+                        }
+                    }
+                    peak.fetch_max(active.fetch_add(1, Ordering::SeqCst) + 1, Ordering::SeqCst);
+                    std::thread::sleep(delay);
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    let _ = stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n[]");
+                });
+            }
+        });
+        (url, seen)
+    }
+
+    #[actix_web::test]
+    async fn test_requests_to_a_slow_backend_run_in_parallel() {
+        const REQUESTS: usize = 8;
+        const DELAY: Duration = Duration::from_millis(500);
+        let (backend, peak) = slow_backend(DELAY);
+        let app = Rc::new(app!(BtcNetwork::Bitcoin, backend));
+
+        let started = Instant::now();
+        let requests: Vec<_> = (0..REQUESTS)
+            .map(|_| {
+                let app = app.clone();
+                actix_web::rt::spawn(async move {
+                    let req = test::TestRequest::get()
+                        .uri("/address/bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq/utxos")
+                        .to_request();
+                    test::call_service(&*app, req).await.status()
+                })
+            })
+            .collect();
+        for request in requests {
+            assert_eq!(request.await.unwrap(), 200);
+        }
+
+        // Handled one at a time, the requests would take REQUESTS * DELAY
+        assert!(started.elapsed() < DELAY * 3, "took {:?}", started.elapsed());
+        assert_eq!(peak.load(Ordering::SeqCst), REQUESTS);
+    }
+
+    #[actix_web::test]
+    async fn test_broadcast_rejects_garbage_locally() {
+        // The unreachable default backend would fail any upstream call
+        let app = app!(BtcNetwork::Bitcoin);
+        let req = test::TestRequest::post()
+            .uri("/broadcast")
+            .set_json(serde_json::json!({ "tx_hex": "deadbeef" }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+
+        let req = test::TestRequest::post()
+            .uri("/broadcast")
+            .set_json(serde_json::json!({ "tx_hex": "00".repeat(8_001) }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 413);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "transaction_too_large");
+    }
+
+    // P2WPKH spend with placeholder signature and key bytes, signalling RBF
+    const SEGWIT_TX_HEX: &str = "02000000000101169e1e83e930853391bc6f35f605c6754cfead57cf8387639d3b4096c54f18f40100000000fdffffff0250c3000000000000160014333333333333333333333333333333333333333358980000000000001600144444444444444444444444444444444444444444024730441111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111012102222222222222222222222222222222222222222222222222222222222222222240d10c00";
+
+    async fn decode(tx_hex: &str) -> (u16, serde_json::Value) {
+        let app = app!(BtcNetwork::Bitcoin);
+        let req = test::TestRequest::post()
+            .uri("/decode_tx")
+            .set_json(serde_json::json!({ "tx_hex": tx_hex }))
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        (resp.status().as_u16(), test::read_body_json(resp).await)
+    }
+
+    #[actix_web::test]
+    async fn test_decode_tx_legacy() {
+        let (status, body) = decode(LEGACY_TX_HEX).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["txid"], TXID);
+        assert_eq!((body["version"].as_i64(), body["locktime"].as_u64()), (Some(1), Some(0)));
+        assert_eq!((body["size"].as_u64(), body["vsize"].as_u64(), body["weight"].as_u64()), (Some(275), Some(275), Some(1100)));
+        assert_eq!(body["rbf"], false);
+        assert_eq!(
+            body["inputs"][0],
+            serde_json::json!({
+                "txid": "0437cd7f8525ceed2324359c2d0ba26006d92d856a9c20fa0241106ee5a597c9",
+                "vout": 0,
+                "sequence": 4294967295u32,
+                "rbf": false,
+                "script_sig": "47304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd410220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d0901",
+                "witness": []
+            })
+        );
+        assert_eq!(body["outputs"][0]["value"], 1_000_000_000u64);
+        assert_eq!(body["outputs"][0]["script_type"], "p2pk");
+        assert_eq!(body["outputs"][0]["address"], serde_json::Value::Null);
+    }
+
+    #[actix_web::test]
+    async fn test_decode_tx_segwit() {
+        let (status, body) = decode(SEGWIT_TX_HEX).await;
+        assert_eq!(status, 200);
+        assert_eq!(body["txid"], "849df045ec7a18a2888bc2f85679b262a4ff890e2c6c912f4c970384cebd6bbe");
+        assert_eq!(body["weight"], 561);
+        assert_eq!(body["rbf"], true);
+        assert_eq!(body["inputs"][0]["rbf"], true);
+        assert_eq!(body["inputs"][0]["witness"].as_array().unwrap().len(), 2);
+        assert_eq!(
+            body["outputs"][1],
+            serde_json::json!({
+                "value": 39_000,
+                "script_pubkey": "00144444444444444444444444444444444444444444",
+                "script_type": "p2wpkh",
+                "address": "bc1qg3zyg3zyg3zyg3zyg3zyg3zyg3zyg3zy4tnddf"
+            })
+        );
+    }
+
+    #[actix_web::test]
+    async fn test_decode_tx_reports_offset() {
+        let (status, body) = decode(&LEGACY_TX_HEX[..100]).await;
+        assert_eq!(status, 400);
+        assert_eq!(body["error"]["code"], "invalid_transaction_hex");
+        assert_eq!(body["error"]["details"]["offset"], 50);
+        let (status, body) = decode("01000000zz").await;
+        assert_eq!(status, 400);
+        assert_eq!(body["error"]["details"]["offset"], 4);
+    }
+
+    const ADDRESS: &str = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+
+    fn utxo_json(vout: u32, value: u64, height: Option<u32>) -> serde_json::Value {
+        serde_json::json!({
+            "txid": TXID,
+            "vout": vout,
+            "value": value,
+            "status": match height {
+                Some(height) => serde_json::json!({ "confirmed": true, "block_height": height }),
+                None => serde_json::json!({ "confirmed": false }),
+            }
+        })
+    }
+
+    #[actix_web::test]
+    async fn test_address_utxos() {
+        let mut server = mockito::Server::new_async().await;
+        let utxos = serde_json::json!([
+            utxo_json(0, 10_000, Some(100)),
+            utxo_json(1, 20_000, Some(109)),
+            utxo_json(2, 30_000, None),
+        ]);
+        server
+            .mock("GET", format!("/address/{}/utxo", ADDRESS).as_str())
+            .with_body(utxos.to_string())
+            .create_async()
+            .await;
+        server.mock("GET", "/blocks/tip/height").with_body("109").create_async().await;
+        let app = app!(BtcNetwork::Bitcoin, server.url());
+
+        let uri = format!("/address/{}/utxos", ADDRESS);
+        let req = test::TestRequest::get().uri(&uri).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let body = body.as_array().unwrap();
+        assert_eq!(body.len(), 3);
+        assert_eq!(body[0]["confirmations"], 10);
+        assert_eq!(body[0]["script_pubkey"], "0014e8df018c7e326cc253faac7e46cdc51e68542c42");
+        assert_eq!(body[2]["confirmations"], 0);
+
+        let req = test::TestRequest::get().uri(&format!("{}?include_unconfirmed=false", uri)).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body.as_array().unwrap().len(), 2);
+
+        let req = test::TestRequest::get().uri(&format!("{}?min_conf=5", uri)).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body.as_array().unwrap().len(), 1);
+        assert_eq!(body[0]["amount"], 10_000);
+    }
+
+    // POST a /fund_tx body, returning the status and JSON response
+    macro_rules! fund_tx {
+        ($app:expr, $body:expr) => {{
+            let req = test::TestRequest::post().uri("/fund_tx").set_json($body).to_request();
+            let resp = test::call_service(&$app, req).await;
+            let status = resp.status().as_u16();
+            let body: serde_json::Value = test::read_body_json(resp).await;
+            (status, body)
+        }};
+    }
+
+    #[actix_web::test]
+    async fn test_fund_tx_exact_match() {
+        let mut server = mockito::Server::new_async().await;
+        let utxos = serde_json::json!([
+            utxo_json(0, 100_000, Some(100)),
+            utxo_json(1, 50_110, Some(100)),
+            utxo_json(2, 30_000, Some(100)),
+        ]);
+        server
+            .mock("GET", format!("/address/{}/utxo", ADDRESS).as_str())
+            .with_body(utxos.to_string())
+            .create_async()
+            .await;
+        server.mock("GET", "/blocks/tip/height").with_body("109").create_async().await;
+        let app = app!(BtcNetwork::Bitcoin, server.url());
+
+        // One P2WPKH input and output is 110 vB, so 50_110 sat pays 50_000 with no change
+        let body = serde_json::json!({
+            "address": ADDRESS,
+            "outputs": [{ "address": ADDRESS, "amount": 50_000 }],
+            "fee_rate": 1.0,
+            "change_address": ADDRESS
+        });
+        let (status, body) = fund_tx!(app, body);
+        assert_eq!(status, 200, "{}", body);
+        assert_eq!(body["fee"], 110);
+        assert_eq!(body["vsize"], 110);
+        assert_eq!(body["change"], serde_json::Value::Null);
+        assert_eq!(body["selected"].as_array().unwrap().len(), 1);
+        assert_eq!(body["selected"][0]["vout"], 1);
+        let tx: Transaction = deserialize_hex(body["tx_hex"].as_str().unwrap()).unwrap();
+        assert_eq!(tx.input[0].previous_output.vout, 1);
+        assert_eq!(tx.output.len(), 1);
+    }
+
+    #[actix_web::test]
+    async fn test_fund_tx_with_change() {
+        let mut server = mockito::Server::new_async().await;
+        server.mock("GET", "/fee-estimates").with_body(r#"{"1": 5.0, "6": 2.0}"#).create_async().await;
+        let app = app!(BtcNetwork::Bitcoin, server.url());
+
+        let body = serde_json::json!({
+            "utxos": [
+                { "txid": TXID, "vout": 0, "amount": 30_000, "address": ADDRESS },
+                { "txid": TXID, "vout": 1, "amount": 100_000, "address": ADDRESS }
+            ],
+            "outputs": [{ "address": ADDRESS, "amount": 50_000 }],
+            "target_blocks": 6,
+            "change_address": ADDRESS,
+            "strategy": "largest_first",
+            "rbf": true
+        });
+        let (status, body) = fund_tx!(app, body);
+        assert_eq!(status, 200, "{}", body);
+        // One input and two outputs is 141 vB at 2 sat/vB
+        assert_eq!(body["fee_rate"], 2.0);
+        assert_eq!(body["fee"], 282);
+        assert_eq!(body["change"], 100_000 - 50_000 - 282);
+        let tx: Transaction = deserialize_hex(body["tx_hex"].as_str().unwrap()).unwrap();
+        assert_eq!(tx.input.len(), 1);
+        assert_eq!(tx.input[0].previous_output.vout, 1);
+        assert_eq!(tx.output[1].value, Amount::from_sat(100_000 - 50_000 - 282));
+        assert!(tx.is_explicitly_rbf());
+    }
+
+    #[actix_web::test]
+    async fn test_fund_tx_insufficient_funds() {
+        let app = app!(BtcNetwork::Bitcoin);
+        let body = serde_json::json!({
+            "utxos": [
+                { "txid": TXID, "vout": 0, "amount": 20_000, "address": ADDRESS },
+                { "txid": TXID, "vout": 1, "amount": 10_000, "address": ADDRESS }
+            ],
+            "outputs": [{ "address": ADDRESS, "amount": 50_000 }],
+            "fee_rate": 1.0,
+            "change_address": ADDRESS
+        });
+        let (status, body) = fund_tx!(app, body);
+        assert_eq!(status, 422);
+        let details = &body["error"]["details"];
+        assert_eq!(body["error"]["code"], "insufficient_funds");
+        assert_eq!(details["available"], 30_000);
+        let needed = details["needed"].as_u64().unwrap();
+        assert!(needed > 50_000);
+        assert_eq!(details["shortfall"], needed - 30_000);
+    }
+
+    #[actix_web::test]
+    async fn test_fund_tx_rejects_bad_requests() {
+        let app = app!(BtcNetwork::Bitcoin);
+        let base = serde_json::json!({
+            "utxos": [{ "txid": TXID, "vout": 0, "amount": 100_000, "address": ADDRESS }],
+            "outputs": [{ "address": ADDRESS, "amount": 50_000 }],
+            "fee_rate": 1.0,
+            "change_address": ADDRESS
+        });
+        let cases = [
+            ("address", serde_json::json!(ADDRESS), "utxo_source_required"),
+            ("target_blocks", serde_json::json!(6), "fee_rate_required"),
+            ("fee_rate", serde_json::json!(0.0), "invalid_fee_rate"),
+            ("strategy", serde_json::json!("oldest_first"), "invalid_strategy"),
+            ("change_address", serde_json::json!("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"), "network_mismatch"),
+        ];
+        for (field, value, code) in cases {
+            let mut body = base.clone();
+            body[field] = value;
+            let (status, body) = fund_tx!(app, body);
+            assert_eq!(status, 400, "{}", field);
+            assert_eq!(body["error"]["code"], code);
+        }
+    }
+
+    // Key controlling the UTXOs of the PSBT tests, with its P2WPKH and P2PKH addresses
+    fn psbt_key() -> (PrivateKey, Address, Address) {
+        let key = PrivateKey::new(secp256k1::SecretKey::from_slice(&[5; 32]).unwrap(), NetworkKind::Main);
+        let public_key = key.public_key(&secp256k1::Secp256k1::new());
+        let segwit = Address::p2wpkh(&bitcoin::CompressedPublicKey(public_key.inner), Network::Bitcoin);
+        (key, segwit, Address::p2pkh(public_key, Network::Bitcoin))
+    }
+
+    // Sign input `index` of the PSBT's transaction and record the signature, as an external signer would
+    fn sign_psbt_input(psbt: &mut Psbt, index: usize, key: PrivateKey) {
+        let spent = psbt.spend_utxo(index).unwrap().clone();
+        let mut signed = psbt.unsigned_tx.clone();
+        let signers: Vec<_> = (0..signed.input.len())
+            .map(|_| InputSigner {
+                private_key: key,
+                script_pubkey: spent.script_pubkey.clone(),
+                amount: Some(spent.value),
+                sighash_type: None,
+                redeem_script: None,
+            })
+            .collect();
+        sign_transaction(&mut signed, &signers, BtcNetwork::Bitcoin).unwrap();
+        let signature = signed.input[index].witness.nth(0).unwrap();
+        let public_key = key.public_key(&secp256k1::Secp256k1::new());
+        psbt.inputs[index].partial_sigs.insert(public_key, bitcoin::ecdsa::Signature::from_slice(signature).unwrap());
+    }
+
+    #[actix_web::test]
+    async fn test_create_and_finalize_psbt() {
+        let app = app!(BtcNetwork::Bitcoin);
+        let (key, segwit, _) = psbt_key();
+        let public_key = key.public_key(&secp256k1::Secp256k1::new()).inner;
+        let xpub = "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8";
+        let body = serde_json::json!({
+            "utxos": [{
+                "txid": TXID, "vout": 0, "amount": 100_000, "address": segwit.to_string(),
+                "bip32_derivation": [{ "pubkey": public_key.to_string(), "fingerprint": "d34db33f", "path": "m/84'/0'/0'/0/0" }]
+            }],
+            "outputs": [{ "address": ADDRESS, "amount": 50_000 }],
+            "fee_rate": 1.0,
+            "change_address": segwit.to_string(),
+            "xpubs": [{ "xpub": xpub, "fingerprint": "d34db33f", "path": "m" }]
+        });
+        let req = test::TestRequest::post().uri("/create_psbt").set_json(body).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 200);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+
+        let mut psbt = Psbt::from_str(body["psbt"].as_str().unwrap()).unwrap();
+        assert_eq!(body["unsigned_txid"], psbt.unsigned_tx.compute_txid().to_string());
+        assert_eq!(body["fee"], 141);
+        assert_eq!(psbt.inputs[0].witness_utxo.as_ref().unwrap().value, Amount::from_sat(100_000));
+        assert!(psbt.inputs[0].non_witness_utxo.is_none());
+        let (fingerprint, path) = &psbt.inputs[0].bip32_derivation[&public_key];
+        assert_eq!((fingerprint.to_string().as_str(), path.to_string().as_str()), ("d34db33f", "84'/0'/0'/0/0"));
+        assert_eq!(psbt.xpub.len(), 1);
+
+        // Unsigned, the only input is reported incomplete
+        let req = test::TestRequest::post().uri("/finalize_psbt").set_json(serde_json::json!({ "psbt": psbt.to_string() })).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 422);
+        let error: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(error["error"]["code"], "psbt_incomplete");
+        assert_eq!(error["error"]["details"]["incomplete_inputs"], serde_json::json!([0]));
+
+        sign_psbt_input(&mut psbt, 0, key);
+        let req = test::TestRequest::post().uri("/finalize_psbt").set_json(serde_json::json!({ "psbt": psbt.to_string() })).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let tx: Transaction = deserialize_hex(body["tx_hex"].as_str().unwrap()).unwrap();
+        assert_eq!(body["txid"], tx.compute_txid().to_string());
+        assert_eq!(tx.compute_txid(), psbt.unsigned_tx.compute_txid());
+        assert_eq!(tx.input[0].witness.len(), 2);
+
+        let req = test::TestRequest::post().uri("/finalize_psbt").set_json(serde_json::json!({ "psbt": "cHNidP8=" })).to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 400);
+    }
+
+    #[actix_web::test]
+    async fn test_create_psbt_embeds_previous_tx_for_legacy_inputs() {
+        let (_, _, legacy) = psbt_key();
+        let previous = Transaction {
+            version: transaction::Version::ONE,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn { previous_output: OutPoint::null(), ..Default::default() }],
+            output: vec![TxOut { value: Amount::from_sat(100_000), script_pubkey: legacy.script_pubkey() }],
+        };
+        let txid = previous.compute_txid();
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", format!("/tx/{}/hex", txid).as_str())
+            .with_body(serialize_hex(&previous))
+            .create_async()
+            .await;
+        let app = app!(BtcNetwork::Bitcoin, server.url());
+
+        let body = serde_json::json!({
+            "utxos": [{ "txid": txid.to_string(), "vout": 0, "amount": 100_000, "address": legacy.to_string() }],
+            "outputs": [{ "address": ADDRESS, "amount": 50_000 }],
+            "fee_rate": 1.0,
+            "change_address": ADDRESS
+        });
+        let req = test::TestRequest::post().uri("/create_psbt").set_json(body).to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        let psbt = Psbt::from_str(body["psbt"].as_str().unwrap()).unwrap();
+        assert_eq!(psbt.inputs[0].non_witness_utxo.as_ref(), Some(&previous));
+        assert!(psbt.inputs[0].witness_utxo.is_none());
+    }
+
+    #[actix_web::test]
+    async fn test_address_utxos_rejects_invalid_address() {
+        // The unreachable default backend would fail any upstream call
+        let app = app!(BtcNetwork::Bitcoin);
+        let req = test::TestRequest::get()
+            .uri("/address/tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx/utxos")
+            .to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "invalid_address");
+
+        let req = test::TestRequest::get().uri(&format!("/address/{}/utxos?min_conf=-1", ADDRESS)).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "invalid_request");
+    }
+
+    #[actix_web::test]
+    async fn test_validate_address() {
+        // No backend call is made, so the unreachable default backend is fine
+        let app = app!(BtcNetwork::Testnet);
+        let validate = |address: &str| test::TestRequest::get().uri(&format!("/address/{}/validate", address)).to_request();
+
+        let cases = [
+            ("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn", "p2pkh", None),
+            ("2MzQwSSnBHWHqSAqtTVQ6v47XtaisrJa1Vc", "p2sh", None),
+            ("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx", "p2wpkh", Some(0)),
+            ("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sl5k7", "p2wsh", Some(0)),
+            ("tb1pqqqqp399et2xygdj5xreqhjjvcmzhxw4aywxecjdzew6hylgvsesf3hn0c", "p2tr", Some(1)),
+        ];
+        for (address, address_type, witness_version) in cases {
+            let body: serde_json::Value = test::call_and_read_body_json(&app, validate(address)).await;
+            assert_eq!(body["valid"], true, "{}", address);
+            assert_eq!(body["type"], address_type, "{}", address);
+            assert_eq!(body["witness_version"], serde_json::json!(witness_version), "{}", address);
+            assert_eq!(body["is_ours_network"], true, "{}", address);
+            assert!(body["networks"].as_array().unwrap().contains(&"testnet".into()), "{}", address);
+            assert_eq!(body["reason"], serde_json::Value::Null);
+        }
+        let body: serde_json::Value =
+            test::call_and_read_body_json(&app, validate("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx")).await;
+        assert_eq!(body["script_pubkey"], "0014751e76e8199196d454941c45d1b3a323f1433bd6");
+
+        // Valid, but for mainnet
+        let body: serde_json::Value = test::call_and_read_body_json(&app, validate(ADDRESS)).await;
+        assert_eq!((body["valid"].clone(), body["is_ours_network"].clone()), (true.into(), false.into()));
+        assert_eq!(body["networks"], serde_json::json!(["bitcoin"]));
+
+        // Invalid addresses are still 200
+        let resp = test::call_service(&app, validate("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsy")).await;
+        assert_eq!(resp.status(), 200);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["valid"], false);
+        assert_eq!(body["reason"], "invalid_checksum");
+        assert_eq!(body["type"], serde_json::Value::Null);
+        let body: serde_json::Value = test::call_and_read_body_json(
+            &app,
+            validate("tb1q0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq24jc47"),
+        )
+        .await;
+        assert_eq!(body["reason"], "bech32_required");
+        let body: serde_json::Value = test::call_and_read_body_json(&app, validate("tb1QW508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx")).await;
+        assert_eq!(body["reason"], "mixed_case");
+    }
+
+    #[actix_web::test]
+    async fn test_address_utxos_throttled_upstream() {
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("GET", format!("/address/{}/utxo", ADDRESS).as_str())
+            .with_status(429)
+            .create_async()
+            .await;
+        let app = app!(BtcNetwork::Bitcoin, server.url());
+
+        let req = test::TestRequest::get().uri(&format!("/address/{}/utxos", ADDRESS)).to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 503);
+        assert_eq!(resp.headers().get(RETRY_AFTER).unwrap(), "30");
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "upstream_rate_limited");
+    }
+
+    const ESTIMATES: &str = r#"{"1":15.2,"2":14.1,"3":11.0,"6":8.5,"144":1.9,"1008":1.0}"#;
+
+    #[actix_web::test]
+    async fn test_fees_are_cached() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("GET", "/fee-estimates")
+            .with_body(ESTIMATES)
+            .expect(1)
+            .create_async()
+            .await;
+        let app = app!(BtcNetwork::Bitcoin, server.url());
+
+        for _ in 0..3 {
+            let req = test::TestRequest::get().uri("/fees").to_request();
+            let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+            assert_eq!(body["unit"], "sat_vb");
+            assert_eq!(body["stale"], false);
+            assert_eq!(body["estimates"]["6"], 8.5);
+            assert_eq!(
+                body["presets"],
+                serde_json::json!({ "fastest": 15.2, "half_hour": 11.0, "hour": 8.5, "economy": 1.9 })
+            );
+        }
+        mock.assert_async().await;
+    }
+
+    #[actix_web::test]
+    async fn test_fees_fall_back_to_stale_estimates() {
+        let mut server = mockito::Server::new_async().await;
+        server.mock("GET", "/fee-estimates").with_status(500).create_async().await;
+        let data = web::Data::new(state(BtcNetwork::Bitcoin, &server.url()));
+        let app = app!(@data data.clone());
+
+        // Nothing cached yet
+        let req = test::TestRequest::get().uri("/fees").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 503);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "fees_unavailable");
+
+        // Expired but recent enough to serve while the backend is down
+        *data.fees.write().unwrap() = Some(CachedFees {
+            fetched_at: Instant::now() - Duration::from_secs(120),
+            estimates: serde_json::from_str(ESTIMATES).unwrap(),
+        });
+        let req = test::TestRequest::get().uri("/fees").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(body["stale"], true);
+        assert_eq!(body["presets"]["fastest"], 15.2);
+
+        // Too old to serve
+        data.fees.write().unwrap().as_mut().unwrap().fetched_at = Instant::now() - Duration::from_secs(900);
+        let req = test::TestRequest::get().uri("/fees").to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 503);
+    }
+
+    #[actix_web::test]
+    async fn test_fee_for_target() {
+        let mut server = mockito::Server::new_async().await;
+        server.mock("GET", "/fee-estimates").with_body(ESTIMATES).create_async().await;
+        let app = app!(BtcNetwork::Bitcoin, server.url());
+
+        let req = test::TestRequest::get().uri("/fees/144").to_request();
+        let body: serde_json::Value = test::call_and_read_body_json(&app, req).await;
+        assert_eq!(
+            body,
+            serde_json::json!({ "unit": "sat_vb", "target_blocks": 144, "fee_rate": 1.9, "stale": false })
+        );
+
+        let req = test::TestRequest::get().uri("/fees/4").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 404);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "unknown_fee_target");
+        assert_eq!(body["error"]["details"]["available_targets"], serde_json::json!([1, 2, 3, 6, 144, 1008]));
+
+        let req = test::TestRequest::get().uri("/fees/soon").to_request();
+        let resp = test::call_service(&app, req).await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "invalid_request");
+    }
+
+    #[actix_web::test]
+    async fn test_estimate_size() {
+        let mut server = mockito::Server::new_async().await;
+        server.mock("GET", "/fee-estimates").with_body(ESTIMATES).create_async().await;
+        let app = app!(BtcNetwork::Bitcoin, server.url());
+        let estimate = |body: serde_json::Value| test::TestRequest::post().uri("/estimate_size").set_json(body).to_request();
+
+        // Pure segwit, mixed, and multisig, against sizes worked out by hand
+        let cases = [
+            (
+                serde_json::json!({ "inputs": [{ "type": "p2wpkh" }], "outputs": [{ "type": "p2wpkh", "count": 2 }], "fee_rate": 2.5 }),
+                serde_json::json!({ "vsize": 141, "weight": 562, "fee_at_rate": 353, "rate_used": 2.5, "rate_source": "request" }),
+            ),
+            (
+                serde_json::json!({
+                    "inputs": [{ "type": "p2pkh" }, { "type": "p2wpkh" }],
+                    "outputs": [{ "type": "p2tr" }, { "type": "p2pkh" }],
+                }),
+                serde_json::json!({ "vsize": 304, "weight": 1215, "fee_at_rate": null, "rate_used": null, "rate_source": null }),
+            ),
+            (
+                serde_json::json!({
+                    "inputs": [{ "type": "p2wsh_multisig:2of3" }],
+                    "outputs": [{ "type": "p2wpkh" }, { "type": "p2wsh" }],
+                    "target_blocks": 6,
+                }),
+                serde_json::json!({ "vsize": 189, "weight": 756, "fee_at_rate": 1607, "rate_used": 8.5, "rate_source": "estimates" }),
+            ),
+        ];
+        for (request, expected) in cases {
+            let body: serde_json::Value = test::call_and_read_body_json(&app, estimate(request)).await;
+            assert_eq!(body, expected);
+        }
+
+        let unknown = serde_json::json!({ "inputs": [{ "type": "p2wpkh" }], "outputs": [{ "type": "p2wpkh" }, { "type": "p2pk" }] });
+        let resp = test::call_service(&app, estimate(unknown)).await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "invalid_script_type");
+        assert_eq!(body["error"]["details"]["output"], 1);
+        assert_eq!(body["error"]["details"]["supported"], serde_json::json!(OUTPUT_TYPES));
+
+        let both = serde_json::json!({
+            "inputs": [{ "type": "p2tr" }],
+            "outputs": [{ "type": "p2tr" }],
+            "fee_rate": 2.0,
+            "target_blocks": 6,
+        });
+        let resp = test::call_service(&app, estimate(both)).await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "fee_rate_required");
+
+        let too_many = serde_json::json!({ "inputs": [{ "type": "p2tr", "count": 10_000 }], "outputs": [{ "type": "p2tr" }] });
+        let resp = test::call_service(&app, estimate(too_many)).await;
+        assert_eq!(resp.status(), 400);
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "too_many_inputs");
+    }
+}
@@ -0,0 +1,322 @@
+//! Prometheus metrics
+//!
+//! Request latency per route and status, backend call latency per endpoint
+//! and status, broadcast outcomes and fee cache lookups, served at /metrics in
+//! the Prometheus text format. Labels carry route and endpoint templates such
+//! as `/address/{address}/utxos`, never addresses or txids.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpResponse};
+use btcx_lib::network::{ObservedRequest, RequestObserver};
+
+/// Path the metrics are served at
+pub const METRICS_PATH: &str = "/metrics";
+
+/// App data letting /metrics through without an API key
+#[derive(Debug)]
+pub struct PublicMetrics;
+
+/// Upper bounds of the latency histogram buckets, in seconds
+const BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Outcome of a transaction sent to the backend by /broadcast
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum BroadcastOutcome {
+    /// The backend accepted the transaction
+    Accepted,
+    /// The backend already had the transaction
+    AlreadyKnown,
+    /// The backend rejected the transaction
+    Rejected,
+    /// The backend could not be reached or failed
+    Failed,
+}
+
+impl BroadcastOutcome {
+    fn label(self) -> &'static str {
+        match self {
+            BroadcastOutcome::Accepted => "accepted",
+            BroadcastOutcome::AlreadyKnown => "already_known",
+            BroadcastOutcome::Rejected => "rejected",
+            BroadcastOutcome::Failed => "failed",
+        }
+    }
+}
+
+/// Result of looking up fee estimates in the server's cache
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CacheLookup {
+    /// Fresh estimates were served from memory
+    Hit,
+    /// The backend was asked
+    Miss,
+    /// The backend failed and old estimates were served; also counted as a miss
+    Stale,
+}
+
+impl CacheLookup {
+    fn label(self) -> &'static str {
+        match self {
+            CacheLookup::Hit => "hit",
+            CacheLookup::Miss => "miss",
+            CacheLookup::Stale => "stale",
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+struct Histogram {
+    buckets: [u64; BUCKETS.len()],  // Observations per bucket, not cumulative
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, elapsed: Duration) {
+        let secs = elapsed.as_secs_f64();
+        if let Some(i) = BUCKETS.iter().position(|&bound| secs <= bound) {
+            self.buckets[i] += 1;
+        }
+        self.count += 1;
+        self.sum += secs;
+    }
+}
+
+/// Metrics shared by all workers
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<(&'static str, String, u16), Histogram>>,  // method, route, status
+    upstream: Mutex<BTreeMap<(String, String, String), Histogram>>,     // method, endpoint, status
+    broadcasts: Mutex<BTreeMap<BroadcastOutcome, u64>>,
+    fee_cache: Mutex<BTreeMap<CacheLookup, u64>>,
+}
+
+impl Metrics {
+    /// Count a broadcast by outcome
+    pub fn record_broadcast(&self, outcome: BroadcastOutcome) {
+        *self.broadcasts.lock().unwrap().entry(outcome).or_default() += 1;
+    }
+
+    /// Count a fee cache lookup by result
+    pub fn record_fee_lookup(&self, lookup: CacheLookup) {
+        *self.fee_cache.lock().unwrap().entry(lookup).or_default() += 1;
+    }
+
+    fn record_request(&self, method: &'static str, route: String, status: u16, elapsed: Duration) {
+        self.requests.lock().unwrap().entry((method, route, status)).or_default().observe(elapsed);
+    }
+
+    /// Every series in the Prometheus text format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let requests = self.requests.lock().unwrap().clone();
+        write_histogram(
+            &mut out,
+            "btcx_http_request_duration_seconds",
+            "Time to answer API requests, by route template and status",
+            requests.iter().map(|((method, route, status), h)| {
+                (format!("method={},route={},status=\"{}\"", quote(method), quote(route), status), h)
+            }),
+        );
+        let upstream = self.upstream.lock().unwrap().clone();
+        write_histogram(
+            &mut out,
+            "btcx_upstream_request_duration_seconds",
+            "Time for the Esplora backend to answer, by endpoint template and status (error: no response)",
+            upstream.iter().map(|((method, endpoint, status), h)| {
+                (format!("method={},endpoint={},status={}", quote(method), quote(endpoint), quote(status)), h)
+            }),
+        );
+        let broadcasts = self.broadcasts.lock().unwrap().clone();
+        write_counter(
+            &mut out,
+            "btcx_broadcasts_total",
+            "Transactions sent to the backend by /broadcast, by outcome",
+            broadcasts.iter().map(|(outcome, n)| (format!("outcome={}", quote(outcome.label())), *n)),
+        );
+        let fee_cache = self.fee_cache.lock().unwrap().clone();
+        write_counter(
+            &mut out,
+            "btcx_fee_cache_lookups_total",
+            "Fee estimate lookups, by result",
+            fee_cache.iter().map(|(lookup, n)| (format!("result={}", quote(lookup.label())), *n)),
+        );
+        out
+    }
+}
+
+impl RequestObserver for Metrics {
+    fn observe(&self, request: &ObservedRequest<'_>) {
+        let status = request.status.map_or_else(|| "error".to_string(), |status| status.to_string());
+        let key = (request.method.to_string(), request.endpoint.to_string(), status);
+        self.upstream.lock().unwrap().entry(key).or_default().observe(request.elapsed);
+    }
+}
+
+fn write_histogram<'a>(
+    out: &mut String,
+    name: &str,
+    help: &str,
+    series: impl Iterator<Item = (String, &'a Histogram)>,
+) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} histogram", name, help, name);
+    for (labels, histogram) in series {
+        let mut cumulative = 0;
+        for (bound, n) in BUCKETS.iter().zip(histogram.buckets) {
+            cumulative += n;
+            let _ = writeln!(out, "{}_bucket{{{},le=\"{}\"}} {}", name, labels, bound, cumulative);
+        }
+        let _ = writeln!(out, "{}_bucket{{{},le=\"+Inf\"}} {}", name, labels, histogram.count);
+        let _ = writeln!(out, "{}_sum{{{}}} {}", name, labels, histogram.sum);
+        let _ = writeln!(out, "{}_count{{{}}} {}", name, labels, histogram.count);
+    }
+}
+
+fn write_counter(out: &mut String, name: &str, help: &str, series: impl Iterator<Item = (String, u64)>) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter", name, help, name);
+    for (labels, n) in series {
+        let _ = writeln!(out, "{}{{{}}} {}", name, labels, n);
+    }
+}
+
+// Quote a label value, escaping as the text format requires
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n"))
+}
+
+// Standard methods by name; anything else would let clients mint label values
+fn method_label(method: &Method) -> &'static str {
+    const KNOWN: [Method; 9] = [
+        Method::GET,
+        Method::POST,
+        Method::PUT,
+        Method::DELETE,
+        Method::HEAD,
+        Method::OPTIONS,
+        Method::CONNECT,
+        Method::PATCH,
+        Method::TRACE,
+    ];
+    KNOWN.iter().find(|known| *known == method).map_or("OTHER", |known| known.as_str())
+}
+
+/// Middleware timing every request by route template and status
+///
+/// Paths matching no route are labelled `unmatched`.
+pub async fn record_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let Some(metrics) = req.app_data::<web::Data<Metrics>>().cloned() else {
+        return next.call(req).await;
+    };
+    let started = Instant::now();
+    let method = method_label(req.method());
+    let route = req.match_pattern().unwrap_or_else(|| "unmatched".to_string());
+    let response = next.call(req).await;
+    let status = match &response {
+        Ok(res) => res.status(),
+        Err(e) => e.as_response_error().status_code(),
+    };
+    metrics.record_request(method, route, status.as_u16(), started.elapsed());
+    response
+}
+
+// Handler for the /metrics endpoint
+async fn metrics(metrics: web::Data<Metrics>) -> HttpResponse {
+    HttpResponse::Ok().content_type("text/plain; version=0.0.4; charset=utf-8").body(metrics.render())
+}
+
+/// Register the endpoint
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.route(METRICS_PATH, web::get().to(metrics));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{state, LEGACY_TX_HEX};
+    use actix_web::middleware::from_fn;
+    use actix_web::{test, App};
+    use btcx_lib::BtcNetwork;
+
+    const ADDRESS: &str = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+
+    #[actix_web::test]
+    async fn test_scrape_after_requests() {
+        let mut server = mockito::Server::new_async().await;
+        server.mock("GET", format!("/address/{}/utxo", ADDRESS).as_str()).with_body("[]").create_async().await;
+        server.mock("GET", "/blocks/tip/height").with_body("878309").create_async().await;
+        server.mock("POST", "/tx").with_body("ok").create_async().await;
+        server.mock("GET", "/fee-estimates").with_body(r#"{"1":20.0,"6":10.0}"#).create_async().await;
+
+        let data = web::Data::new(state(BtcNetwork::Bitcoin, &server.url()));
+        let metrics = web::Data::from(data.metrics.clone());
+        let app = test::init_service(
+            App::new()
+                .app_data(data)
+                .app_data(metrics)
+                .wrap(from_fn(record_requests))
+                .configure(routes)
+                .configure(crate::routes),
+        )
+        .await;
+
+        let get = |uri: &str| test::TestRequest::get().uri(uri).to_request();
+        for uri in [format!("/address/{}/utxos", ADDRESS), "/fees".to_string(), "/fees".to_string(), "/nowhere".to_string()] {
+            test::call_service(&app, get(&uri)).await;
+        }
+        let req = test::TestRequest::post()
+            .uri("/broadcast")
+            .set_json(serde_json::json!({ "tx_hex": LEGACY_TX_HEX }))
+            .to_request();
+        assert_eq!(test::call_service(&app, req).await.status(), 200);
+
+        let resp = test::call_service(&app, get("/metrics")).await;
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers().get("content-type").unwrap().to_str().unwrap().starts_with("text/plain"));
+        let body = String::from_utf8(test::read_body(resp).await.to_vec()).unwrap();
+        let expected = [
+            r#"btcx_http_request_duration_seconds_count{method="GET",route="/address/{address}/utxos",status="200"} 1"#,
+            r#"btcx_http_request_duration_seconds_count{method="GET",route="/fees",status="200"} 2"#,
+            r#"btcx_http_request_duration_seconds_count{method="GET",route="unmatched",status="404"} 1"#,
+            r#"btcx_http_request_duration_seconds_bucket{method="POST",route="/broadcast",status="200",le="+Inf"} 1"#,
+            r#"btcx_upstream_request_duration_seconds_count{method="GET",endpoint="/address/{address}/utxo",status="200"} 1"#,
+            r#"btcx_upstream_request_duration_seconds_count{method="GET",endpoint="/fee-estimates",status="200"} 1"#,
+            r#"btcx_upstream_request_duration_seconds_count{method="POST",endpoint="/tx",status="200"} 1"#,
+            r#"btcx_broadcasts_total{outcome="accepted"} 1"#,
+            r#"btcx_fee_cache_lookups_total{result="hit"} 1"#,
+            r#"btcx_fee_cache_lookups_total{result="miss"} 1"#,
+            "# TYPE btcx_http_request_duration_seconds histogram",
+        ];
+        for series in expected {
+            assert!(body.contains(series), "missing {}\n{}", series, body);
+        }
+        // No address or txid ends up in a label
+        assert!(!body.contains(ADDRESS) && !body.contains("nowhere"));
+    }
+
+    #[actix_web::test]
+    async fn test_histogram_buckets_are_cumulative() {
+        let mut histogram = Histogram::default();
+        histogram.observe(Duration::from_millis(3));
+        histogram.observe(Duration::from_millis(300));
+        histogram.observe(Duration::from_secs(60));
+        let mut out = String::new();
+        write_histogram(&mut out, "t", "test", [("a=\"b\"".to_string(), &histogram)].into_iter());
+        assert!(out.contains("t_bucket{a=\"b\",le=\"0.005\"} 1\n"));
+        assert!(out.contains("t_bucket{a=\"b\",le=\"0.5\"} 2\n"));
+        assert!(out.contains("t_bucket{a=\"b\",le=\"10\"} 2\n"));
+        assert!(out.contains("t_bucket{a=\"b\",le=\"+Inf\"} 3\n"));
+        assert!(out.contains("t_count{a=\"b\"} 3\n"));
+        assert_eq!(quote("a\"b\\"), r#""a\"b\\""#);
+    }
+}
@@ -0,0 +1,103 @@
+//! Per-request network override
+//!
+//! With `--allow-network-override`, a request may name another network in the
+//! `X-Btcx-Network` header or, on JSON endpoints, a `network` body field.
+//! Addresses are then checked against that network and backend calls go to
+//! its Esplora endpoint. A request that names a network gets it back in a
+//! `network` response field and the same `X-Btcx-Network` header, so an answer
+//! is never mistaken for one about the server's own network.
+
+use std::str::FromStr;
+
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, ResponseError};
+use btcx_lib::network::BlockstreamClient;
+use btcx_lib::BtcNetwork;
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::error::ApiError;
+use crate::{AppState, NETWORK_HEADER};
+
+/// Network and backend one request runs against
+pub struct RequestNetwork {
+    pub network: BtcNetwork,
+    pub client: BlockstreamClient,
+    named: bool,  // The request named a network, so responses echo it
+}
+
+impl RequestNetwork {
+    /// Network named by the header or `body`, the server's when neither names one
+    ///
+    /// Naming the server's own network is always allowed; any other needs
+    /// `--allow-network-override`.
+    pub fn resolve(data: &AppState, req: &HttpRequest, body: Option<&str>) -> Result<Self, ApiError> {
+        let header = match req.headers().get(NETWORK_HEADER) {
+            Some(value) => Some(parse(value.to_str().unwrap_or_default())?),
+            None => None,
+        };
+        let named = match (header, body.map(parse).transpose()?) {
+            (Some(header), Some(body)) if header != body => {
+                return Err(ApiError::bad_request(
+                    "network_conflict",
+                    format!("{} header names {}, the body {}", NETWORK_HEADER, header, body),
+                ))
+            }
+            (header, body) => header.or(body),
+        };
+        let default = data.config.network;
+        let Some(network) = named else {
+            return Ok(RequestNetwork { network: default, client: data.client.clone(), named: false });
+        };
+        if network != default && !data.config.allow_network_override {
+            return Err(ApiError::bad_request(
+                "network_override_disabled",
+                format!("This server runs on {}; requests for {} need --allow-network-override", default, network),
+            )
+            .with_details(json!({ "network": default, "requested": network })));
+        }
+        Ok(RequestNetwork { network, client: data.client_for(network)?, named: true })
+    }
+
+    /// Refuse a header naming another network on endpoints whose responses cannot echo it
+    pub fn require_default(data: &AppState, req: &HttpRequest) -> Result<(), ApiError> {
+        let resolved = RequestNetwork::resolve(data, req, None)?;
+        if resolved.network != data.config.network {
+            return Err(ApiError::bad_request(
+                "network_override_unsupported",
+                format!("This endpoint only serves {}", data.config.network),
+            )
+            .with_details(json!({ "network": data.config.network })));
+        }
+        Ok(())
+    }
+
+    /// Add the network to a JSON object when the request named one
+    pub fn echo(&self, body: &mut Value) {
+        if let (true, Value::Object(fields)) = (self.named, body) {
+            fields.insert("network".to_string(), json!(self.network));
+        }
+    }
+
+    /// 200 response with `body`, adding the network when the request named one
+    pub fn respond(&self, body: impl Serialize) -> HttpResponse {
+        if !self.named {
+            return HttpResponse::Ok().json(body);
+        }
+        let mut body = match serde_json::to_value(body) {
+            Ok(body) => body,
+            Err(e) => {
+                log::error!("Cannot serialize response: {}", e);
+                return ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", "Internal server error").error_response();
+            }
+        };
+        self.echo(&mut body);
+        HttpResponse::Ok().insert_header((NETWORK_HEADER, self.network.to_string())).json(body)
+    }
+}
+
+fn parse(name: &str) -> Result<BtcNetwork, ApiError> {
+    BtcNetwork::from_str(name.trim()).map_err(|_| {
+        ApiError::bad_request("invalid_network", format!("Unknown network {:?}; use mainnet, testnet, signet or regtest", name))
+    })
+}
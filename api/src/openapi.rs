@@ -0,0 +1,1019 @@
+//! OpenAPI 3 description of the API, served at /openapi.json
+//!
+//! Schemas mirror the request and response structs field for field; the tests
+//! check real responses against them so the two cannot drift apart silently.
+//! Amounts are integers in satoshis and fee rates are in sat/vB.
+
+use actix_web::{web, HttpResponse};
+use serde_json::{json, Map, Value};
+
+/// Path the document is served at
+pub const OPENAPI_PATH: &str = "/openapi.json";
+/// Path of the optional Swagger UI page
+pub const SWAGGER_UI_PATH: &str = "/docs";
+
+/// Swagger UI release the /docs page loads its assets from
+const SWAGGER_UI_VERSION: &str = "5.17.14";
+
+fn string(description: &str) -> Value {
+    json!({ "type": "string", "description": description })
+}
+
+fn integer(description: &str) -> Value {
+    json!({ "type": "integer", "minimum": 0, "description": description })
+}
+
+fn boolean(description: &str) -> Value {
+    json!({ "type": "boolean", "description": description })
+}
+
+// An amount: integer satoshis, never BTC
+fn sats(description: &str) -> Value {
+    json!({
+        "type": "integer",
+        "format": "int64",
+        "minimum": 0,
+        "description": format!("{}, in satoshis", description),
+        "x-unit": "sat",
+    })
+}
+
+fn fee_rate(description: &str) -> Value {
+    json!({ "type": "number", "minimum": 0, "description": format!("{}, in sat/vB", description), "x-unit": "sat/vB" })
+}
+
+fn array(items: Value, description: &str) -> Value {
+    json!({ "type": "array", "items": items, "description": description })
+}
+
+fn nullable(mut schema: Value) -> Value {
+    schema["nullable"] = true.into();
+    schema
+}
+
+fn schema_ref(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+// An object schema; fields listed in `required` must be present
+fn object(required: &[&str], properties: Vec<(&str, Value)>) -> Value {
+    let properties: Map<String, Value> = properties.into_iter().map(|(name, schema)| (name.to_string(), schema)).collect();
+    let mut schema = json!({ "type": "object", "properties": properties });
+    if !required.is_empty() {
+        schema["required"] = json!(required);
+    }
+    schema
+}
+
+// Merge the properties of object schemas, as `#[serde(flatten)]` does
+fn flatten(parts: &[Value]) -> Value {
+    let mut properties = Map::new();
+    let mut required = Vec::new();
+    for part in parts {
+        if let Some(props) = part["properties"].as_object() {
+            properties.extend(props.clone());
+        }
+        if let Some(names) = part["required"].as_array() {
+            required.extend(names.iter().cloned());
+        }
+    }
+    json!({ "type": "object", "properties": properties, "required": required })
+}
+
+// The optional body field choosing another network
+fn network_field() -> (&'static str, Value) {
+    ("network", string("Network to use instead of the server's; another network needs --allow-network-override"))
+}
+
+// Responses carrying the network when the request named one
+const NETWORK_ECHOES: [&str; 11] = [
+    "TxResponse",
+    "BatchResponse",
+    "FundTxResponse",
+    "CreatePsbtResponse",
+    "SignTxResponse",
+    "BroadcastResponse",
+    "DecodedTransaction",
+    "AddressValidation",
+    "FeesResponse",
+    "FeeTargetResponse",
+    "EstimateSizeResponse",
+];
+
+// Endpoints honouring the network override header
+const NETWORK_OVERRIDES: [&str; 12] = [
+    "/create_tx",
+    "/create_tx/batch",
+    "/fund_tx",
+    "/create_psbt",
+    "/sign_tx",
+    "/broadcast",
+    "/decode_tx",
+    "/tx/events",
+    "/address/{address}/validate",
+    "/fees",
+    "/fees/{target_blocks}",
+    "/estimate_size",
+];
+
+fn tx_output_request() -> Value {
+    object(&["address", "amount"], vec![("address", string("Bitcoin address")), ("amount", sats("Amount"))])
+}
+
+fn create_tx_request() -> Value {
+    object(
+        &["inputs", "outputs"],
+        vec![
+            (
+                "inputs",
+                array(
+                    object(
+                        &["txid", "vout"],
+                        vec![
+                            ("txid", string("Transaction ID")),
+                            ("vout", integer("Output index")),
+                            ("sequence", integer("nSequence, overriding the rbf choice for this input")),
+                        ],
+                    ),
+                    "Inputs",
+                ),
+            ),
+            ("outputs", array(schema_ref("TxOutputRequest"), "Outputs")),
+            ("allow_dust", boolean("Accept outputs below the dust threshold")),
+            ("version", integer("Transaction version, 1 (default) or 2")),
+            ("locktime", integer("nLockTime: a block height below 500000000, a unix time above")),
+            ("rbf", boolean("Signal replaceability on inputs without their own sequence")),
+            network_field(),
+        ],
+    )
+}
+
+fn bip32_derivation() -> Value {
+    object(
+        &["pubkey", "fingerprint", "path"],
+        vec![
+            ("pubkey", string("Hex-encoded public key")),
+            ("fingerprint", string("Master key fingerprint, 8 hex digits")),
+            ("path", string("Derivation path, e.g. m/84'/0'/0'/0/0")),
+        ],
+    )
+}
+
+fn fund_tx_request() -> Value {
+    let utxo = object(
+        &["txid", "vout", "amount", "address"],
+        vec![
+            ("txid", string("Transaction ID")),
+            ("vout", integer("Output index")),
+            ("amount", sats("Value of the output")),
+            ("address", string("Address the output pays to")),
+            ("bip32_derivation", array(schema_ref("Bip32Derivation"), "Key origins, for /create_psbt")),
+        ],
+    );
+    object(
+        &["outputs", "change_address"],
+        vec![
+            ("utxos", array(utxo, "Candidate UTXOs; give these or `address`")),
+            ("address", string("Address whose UTXOs to fetch; give this or `utxos`")),
+            ("outputs", array(schema_ref("TxOutputRequest"), "Recipient outputs")),
+            ("fee_rate", fee_rate("Fee rate; give this or `target_blocks`")),
+            ("target_blocks", integer("Confirmation target to look the fee rate up for")),
+            ("change_address", string("Where the change goes")),
+            ("strategy", json!({
+                "type": "string",
+                "enum": ["branch_and_bound", "largest_first", "smallest_first", "random"],
+                "description": "Coin selection strategy (default branch_and_bound)",
+            })),
+            ("rbf", boolean("Signal replaceability")),
+            ("allow_dust", boolean("Accept recipient outputs below the dust threshold")),
+            network_field(),
+        ],
+    )
+}
+
+fn estimate_size_request() -> Value {
+    let items = |supported: &[&str], description| {
+        let item = object(
+            &["type"],
+            vec![
+                ("type", string(&format!("One of {}", supported.join(", ")))),
+                ("count", integer("How many of this type (default 1)")),
+            ],
+        );
+        array(item, description)
+    };
+    object(
+        &["inputs", "outputs"],
+        vec![
+            ("inputs", items(&btcx_lib::utils::INPUT_TYPES, "Inputs, by what they spend")),
+            ("outputs", items(&btcx_lib::utils::OUTPUT_TYPES, "Outputs, by what they pay to")),
+            ("fee_rate", fee_rate("Fee rate to price the estimate at; give this, `target_blocks` or neither")),
+            ("target_blocks", integer("Confirmation target to look the fee rate up for")),
+            network_field(),
+        ],
+    )
+}
+
+fn build_report() -> Value {
+    object(
+        &["selected", "fee", "change", "vsize"],
+        vec![
+            ("selected", array(schema_ref("Utxo"), "UTXOs spent, in input order")),
+            ("fee", sats("Fee paid, including any excess too small to keep as change")),
+            ("change", nullable(sats("Value of the change output, the last output; null when none was added"))),
+            ("vsize", integer("Predicted virtual size once signed, in vbytes")),
+        ],
+    )
+}
+
+// Every named schema
+fn schemas() -> Map<String, Value> {
+    let tx_response = object(
+        &["tx_hex", "version", "locktime", "rbf", "sequences"],
+        vec![
+            ("tx_hex", string("Hex-encoded unsigned transaction")),
+            ("version", integer("Effective transaction version")),
+            ("locktime", integer("Effective nLockTime")),
+            ("rbf", boolean("Some input signals replaceability")),
+            ("sequences", array(integer("nSequence"), "Effective nSequence of each input")),
+        ],
+    );
+    let batch_item = flatten(&[
+        create_tx_request(),
+        object(&[], vec![("client_ref", json!({ "description": "Any JSON value, echoed in the result" }))]),
+    ]);
+    let batch_response = object(
+        &["results", "summary"],
+        vec![
+            (
+                "results",
+                array(
+                    object(
+                        &["index", "status"],
+                        vec![
+                            ("index", integer("Position in the request")),
+                            ("client_ref", json!({ "description": "Echo of the item's client_ref" })),
+                            ("status", integer("HTTP status /create_tx would have answered")),
+                            ("tx", schema_ref("TxResponse")),
+                            ("error", schema_ref("ErrorDetail")),
+                        ],
+                    ),
+                    "One result per item, in request order",
+                ),
+            ),
+            (
+                "summary",
+                object(
+                    &["total", "succeeded", "failed", "elapsed_ms"],
+                    vec![
+                        ("total", integer("Items in the batch")),
+                        ("succeeded", integer("Items built")),
+                        ("failed", integer("Items that failed")),
+                        ("elapsed_ms", integer("Time spent on the whole batch, in milliseconds")),
+                    ],
+                ),
+            ),
+        ],
+    );
+    let fund_tx_response = flatten(&[
+        object(
+            &["tx_hex", "fee_rate"],
+            vec![("tx_hex", string("Hex-encoded unsigned transaction")), ("fee_rate", fee_rate("Fee rate used"))],
+        ),
+        build_report(),
+    ]);
+    let create_psbt_request = flatten(&[
+        fund_tx_request(),
+        object(
+            &[],
+            vec![
+                ("bip32_derivation", array(schema_ref("Bip32Derivation"), "Key origins for UTXOs fetched by address")),
+                (
+                    "xpubs",
+                    array(
+                        object(
+                            &["xpub", "fingerprint", "path"],
+                            vec![
+                                ("xpub", string("Base58 extended public key")),
+                                ("fingerprint", string("Master key fingerprint, 8 hex digits")),
+                                ("path", string("Derivation path of the xpub")),
+                            ],
+                        ),
+                        "Global xpubs",
+                    ),
+                ),
+            ],
+        ),
+    ]);
+    let create_psbt_response = flatten(&[
+        object(
+            &["psbt", "unsigned_txid", "fee_rate"],
+            vec![
+                ("psbt", string("Base64-encoded PSBT")),
+                ("unsigned_txid", string("Txid of the unsigned transaction")),
+                ("fee_rate", fee_rate("Fee rate used")),
+            ],
+        ),
+        build_report(),
+    ]);
+    let sign_tx_request = object(
+        &["unsigned_tx_hex", "inputs"],
+        vec![
+            ("unsigned_tx_hex", string("Hex-encoded unsigned transaction")),
+            (
+                "inputs",
+                array(
+                    object(
+                        &["private_key_wif", "address"],
+                        vec![
+                            ("private_key_wif", json!({ "type": "string", "format": "password", "description": "Private key in WIF format, never logged" })),
+                            ("address", string("Address of the spent output")),
+                            ("amount", sats("Value of the spent output, required for segwit inputs, and for all with a taproot one")),
+                        ],
+                    ),
+                    "One signing input per transaction input",
+                ),
+            ),
+            network_field(),
+        ],
+    );
+    let decoded = object(
+        &["txid", "version", "locktime", "size", "vsize", "weight", "rbf", "inputs", "outputs"],
+        vec![
+            ("txid", string("Transaction ID")),
+            ("version", integer("Transaction version")),
+            ("locktime", integer("nLockTime")),
+            ("size", integer("Serialized size, in bytes")),
+            ("vsize", integer("Virtual size, in vbytes")),
+            ("weight", integer("Weight, in weight units")),
+            ("rbf", boolean("Some input signals replaceability")),
+            (
+                "inputs",
+                array(
+                    object(
+                        &["txid", "vout", "sequence", "rbf", "script_sig", "witness"],
+                        vec![
+                            ("txid", string("Transaction ID of the spent output")),
+                            ("vout", integer("Index of the spent output")),
+                            ("sequence", integer("nSequence")),
+                            ("rbf", boolean("The sequence signals replaceability")),
+                            ("script_sig", string("Unlocking script, hex")),
+                            ("witness", array(string("Witness item, hex"), "Witness items")),
+                        ],
+                    ),
+                    "Inputs",
+                ),
+            ),
+            (
+                "outputs",
+                array(
+                    object(
+                        &["value", "script_pubkey", "script_type", "address"],
+                        vec![
+                            ("value", sats("Amount")),
+                            ("script_pubkey", string("Locking script, hex")),
+                            ("script_type", string("Script template: p2pkh, p2wpkh, op_return, ...")),
+                            ("address", nullable(string("Address paid, when the script has one"))),
+                        ],
+                    ),
+                    "Outputs",
+                ),
+            ),
+        ],
+    );
+    let utxo = object(
+        &["txid", "vout", "amount", "script_pubkey", "spendable"],
+        vec![
+            ("txid", string("Transaction ID")),
+            ("vout", integer("Output index")),
+            ("amount", sats("Value of the output")),
+            ("script_pubkey", string("Locking script, hex")),
+            ("address", string("Address the output pays to")),
+            ("confirmations", integer("Confirmations; 0 while in the mempool")),
+            ("block_height", integer("Height of the block holding the output")),
+            ("spendable", boolean("Whether the output can be spent")),
+        ],
+    );
+    let address_validation = object(
+        &["valid", "networks", "type", "script_pubkey", "witness_version", "is_ours_network", "reason", "message"],
+        vec![
+            ("valid", boolean("Whether the string is an address on any network")),
+            ("networks", array(string("Network name"), "Networks the address is valid on; empty when invalid")),
+            ("type", nullable(string("p2pkh, p2sh, p2wpkh, p2wsh, p2tr or witness_v<n>"))),
+            ("script_pubkey", nullable(string("Locking script, hex"))),
+            ("witness_version", nullable(integer("Witness version of segwit addresses"))),
+            ("is_ours_network", boolean("Valid on the server's network, or the one the request named")),
+            (
+                "reason",
+                nullable(json!({
+                    "type": "string",
+                    "enum": [
+                        "empty",
+                        "mixed_case",
+                        "bech32m_required",
+                        "bech32_required",
+                        "invalid_checksum",
+                        "invalid_witness_program",
+                        "invalid_encoding",
+                    ],
+                    "description": "Why the address is invalid",
+                })),
+            ),
+            ("message", nullable(string("The reason, for humans"))),
+        ],
+    );
+    let fees = object(
+        &["unit", "estimates", "presets", "stale"],
+        vec![
+            ("unit", json!({ "type": "string", "enum": ["sat_vb"], "description": "Unit of every rate" })),
+            (
+                "estimates",
+                json!({
+                    "type": "object",
+                    "additionalProperties": fee_rate("Fee rate"),
+                    "description": "Fee rate by confirmation target in blocks",
+                }),
+            ),
+            (
+                "presets",
+                object(
+                    &["fastest", "half_hour", "hour", "economy"],
+                    vec![
+                        ("fastest", fee_rate("Next block")),
+                        ("half_hour", fee_rate("3 blocks")),
+                        ("hour", fee_rate("6 blocks")),
+                        ("economy", fee_rate("144 blocks")),
+                    ],
+                ),
+            ),
+            ("stale", boolean("The backend is down and these estimates are old")),
+        ],
+    );
+    let fee_target = object(
+        &["unit", "target_blocks", "fee_rate", "stale"],
+        vec![
+            ("unit", json!({ "type": "string", "enum": ["sat_vb"], "description": "Unit of the rate" })),
+            ("target_blocks", integer("Confirmation target, in blocks")),
+            ("fee_rate", fee_rate("Fee rate")),
+            ("stale", boolean("The backend is down and this estimate is old")),
+        ],
+    );
+    let estimate_size = object(
+        &["vsize", "weight", "fee_at_rate", "rate_used", "rate_source"],
+        vec![
+            ("vsize", integer("Predicted virtual size once signed, in vbytes, counting the largest signatures")),
+            ("weight", integer("Predicted weight, in weight units")),
+            ("fee_at_rate", nullable(sats("vsize times the rate, rounded up; null without a rate"))),
+            ("rate_used", nullable(fee_rate("Rate the fee is priced at"))),
+            (
+                "rate_source",
+                nullable(json!({
+                    "type": "string",
+                    "enum": ["request", "estimates", "stale_estimates"],
+                    "description": "Where the rate came from",
+                })),
+            ),
+        ],
+    );
+    let error_detail = object(
+        &["code", "message"],
+        vec![
+            ("code", string("Stable code for clients to branch on, e.g. invalid_address")),
+            ("message", string("Human-readable description")),
+            (
+                "details",
+                json!({
+                    "type": "object",
+                    "additionalProperties": true,
+                    "description": "Structured context, such as the failing input index",
+                }),
+            ),
+        ],
+    );
+
+    let entries = [
+        ("TxOutputRequest", tx_output_request()),
+        ("CreateTxRequest", create_tx_request()),
+        ("TxResponse", tx_response),
+        ("BatchItem", batch_item),
+        ("BatchResponse", batch_response),
+        ("Bip32Derivation", bip32_derivation()),
+        ("FundTxRequest", fund_tx_request()),
+        ("FundTxResponse", fund_tx_response),
+        ("CreatePsbtRequest", create_psbt_request),
+        ("CreatePsbtResponse", create_psbt_response),
+        ("FinalizePsbtRequest", object(&["psbt"], vec![("psbt", string("Base64-encoded PSBT with every signature collected"))])),
+        (
+            "FinalizePsbtResponse",
+            object(&["tx_hex", "txid"], vec![("tx_hex", string("Signed transaction, hex")), ("txid", string("Transaction ID"))]),
+        ),
+        ("SignTxRequest", sign_tx_request),
+        (
+            "SignTxResponse",
+            object(
+                &["signed_tx_hex", "txid", "vsize"],
+                vec![
+                    ("signed_tx_hex", string("Signed transaction, hex")),
+                    ("txid", string("Transaction ID")),
+                    ("vsize", integer("Virtual size, in vbytes")),
+                ],
+            ),
+        ),
+        ("TxHexRequest", object(&["tx_hex"], vec![("tx_hex", string("Raw transaction, hex")), network_field()])),
+        (
+            "BroadcastResponse",
+            object(
+                &["txid", "already_known"],
+                vec![("txid", string("Transaction ID")), ("already_known", boolean("The backend already had the transaction"))],
+            ),
+        ),
+        ("DecodedTransaction", decoded),
+        ("Utxo", utxo),
+        ("AddressValidation", address_validation),
+        (
+            "WalletAddress",
+            object(
+                &["wallet", "index", "address", "derivation_path", "script_pubkey", "type"],
+                vec![
+                    ("wallet", string("Wallet name")),
+                    ("index", integer("Index on the wallet's branch")),
+                    ("address", string("Derived address")),
+                    ("derivation_path", string("Path from the master key when the key origin is configured, else from the account key")),
+                    ("script_pubkey", string("Locking script, hex")),
+                    ("type", string("p2pkh, p2sh-p2wpkh, p2wpkh or p2tr")),
+                ],
+            ),
+        ),
+        ("FeesResponse", fees),
+        ("FeeTargetResponse", fee_target),
+        ("EstimateSizeRequest", estimate_size_request()),
+        ("EstimateSizeResponse", estimate_size),
+        ("Status", object(&["status"], vec![("status", string("ok, ready or not_ready"))])),
+        (
+            "Version",
+            object(
+                &["version", "git_commit", "network", "backend"],
+                vec![
+                    ("version", string("Crate version")),
+                    ("git_commit", string("Commit the binary was built from, or unknown")),
+                    ("network", string("Network the server operates on")),
+                    ("backend", string("Esplora base URL")),
+                ],
+            ),
+        ),
+        ("ErrorDetail", error_detail),
+        ("Error", object(&["error"], vec![("error", schema_ref("ErrorDetail"))])),
+    ];
+    let mut schemas: Map<String, Value> = entries.into_iter().map(|(name, schema)| (name.to_string(), schema)).collect();
+    for name in NETWORK_ECHOES {
+        schemas[name]["properties"]["network"] = string("Network the request named, present only when it named one");
+    }
+    schemas
+}
+
+fn json_body(schema: Value) -> Value {
+    json!({ "required": true, "content": { "application/json": { "schema": schema } } })
+}
+
+fn json_response(description: &str, schema: Value) -> Value {
+    json!({ "description": description, "content": { "application/json": { "schema": schema } } })
+}
+
+// An operation answering `schema` on success and the error shape otherwise
+fn operation(summary: &str, request: Option<Value>, response: Value) -> Value {
+    let mut op = json!({
+        "summary": summary,
+        "responses": {
+            "200": json_response("Success", response),
+            "default": json_response("Error", schema_ref("Error")),
+        },
+    });
+    if let Some(schema) = request {
+        op["requestBody"] = json_body(schema);
+    }
+    op
+}
+
+// An operation served without an API key
+fn public(mut op: Value) -> Value {
+    op["security"] = json!([]);
+    op
+}
+
+fn path_parameter(name: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "path", "required": true, "schema": schema })
+}
+
+fn header_parameter(name: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "header", "required": false, "schema": schema })
+}
+
+fn add_parameter(op: &mut Value, parameter: Value) {
+    match op["parameters"].as_array_mut() {
+        Some(parameters) => parameters.push(parameter),
+        None => op["parameters"] = json!([parameter]),
+    }
+}
+
+fn query_parameter(name: &str, schema: Value) -> Value {
+    json!({ "name": name, "in": "query", "required": false, "schema": schema })
+}
+
+// Every endpoint, as (path, method, operation)
+fn operations() -> Vec<(&'static str, &'static str, Value)> {
+    let mut utxos = operation("Spendable outputs of an address", None, array(schema_ref("Utxo"), "UTXOs"));
+    utxos["parameters"] = json!([
+        path_parameter("address", string("Address on the server's network")),
+        query_parameter("min_conf", integer("Minimum confirmations (default 0)")),
+        query_parameter("include_unconfirmed", boolean("Include mempool outputs (default true)")),
+    ]);
+    let mut validate = operation(
+        "Validate an address without a backend call; answers 200 with the reason when invalid",
+        None,
+        schema_ref("AddressValidation"),
+    );
+    validate["parameters"] = json!([path_parameter("address", string("Address on any network"))]);
+    let event_stream = json!({
+        "description": "text/event-stream of seen_in_mempool, confirmed {height, block_hash}, reorged and dropped events, \
+            ending with target_reached {confirmations} or timeout; a broadcast {txid, already_known} event comes first after an inline broadcast",
+        "content": { "text/event-stream": {} },
+    });
+    let target = query_parameter("confirmations", integer("Confirmations to wait for before closing, 1 to 144 (default 1)"));
+    let mut tx_events = operation("Stream state changes of a transaction until it confirms", None, json!({}));
+    tx_events["parameters"] = json!([path_parameter("txid", string("Transaction ID")), target]);
+    tx_events["responses"]["200"] = event_stream.clone();
+    let mut broadcast_events =
+        operation("Broadcast a transaction, then stream its state changes", Some(schema_ref("TxHexRequest")), json!({}));
+    broadcast_events["parameters"] = json!([target]);
+    broadcast_events["responses"]["200"] = event_stream;
+    let wallet = path_parameter("name", string("Configured wallet name"));
+    let mut next_address =
+        operation("Derive the wallet's next unissued receive address", None, schema_ref("WalletAddress"));
+    next_address["parameters"] = json!([wallet]);
+    let mut wallet_address = operation("Derive the wallet's address at an index", None, schema_ref("WalletAddress"));
+    wallet_address["parameters"] = json!([wallet, path_parameter("index", integer("Unhardened index"))]);
+    let mut fee_target = operation("Fee rate for a confirmation target", None, schema_ref("FeeTargetResponse"));
+    fee_target["parameters"] = json!([path_parameter("target_blocks", integer("Confirmation target, in blocks"))]);
+    let mut metrics = operation("Prometheus metrics", None, json!({}));
+    metrics["description"] = "Needs an API key when keys are configured, unless the server runs with --public-metrics".into();
+    metrics["responses"]["200"] = json!({ "description": "Prometheus text format", "content": { "text/plain": {} } });
+    let mut ready = public(operation("Whether the backend answered recently", None, schema_ref("Status")));
+    ready["responses"]["503"] = json_response("Not ready, or shutting down", schema_ref("Status"));
+
+    vec![
+        ("/health", "get", public(operation("Whether the process is up", None, schema_ref("Status")))),
+        ("/ready", "get", ready),
+        ("/version", "get", operation("Build and backend information", None, schema_ref("Version"))),
+        ("/metrics", "get", metrics),
+        (OPENAPI_PATH, "get", public(operation("This document", None, json!({ "type": "object" })))),
+        (
+            "/create_tx",
+            "post",
+            operation("Build an unsigned transaction", Some(schema_ref("CreateTxRequest")), schema_ref("TxResponse")),
+        ),
+        (
+            "/create_tx/batch",
+            "post",
+            operation(
+                "Build many unsigned transactions; one failing item does not fail the others",
+                Some(array(schema_ref("BatchItem"), "Independent /create_tx requests")),
+                schema_ref("BatchResponse"),
+            ),
+        ),
+        (
+            "/fund_tx",
+            "post",
+            operation(
+                "Select coins and build an unsigned transaction with fee and change",
+                Some(schema_ref("FundTxRequest")),
+                schema_ref("FundTxResponse"),
+            ),
+        ),
+        (
+            "/create_psbt",
+            "post",
+            operation("Fund a transaction as a PSBT", Some(schema_ref("CreatePsbtRequest")), schema_ref("CreatePsbtResponse")),
+        ),
+        (
+            "/finalize_psbt",
+            "post",
+            operation(
+                "Finalize a signed PSBT and extract the transaction",
+                Some(schema_ref("FinalizePsbtRequest")),
+                schema_ref("FinalizePsbtResponse"),
+            ),
+        ),
+        (
+            "/sign_tx",
+            "post",
+            operation(
+                "Sign P2PKH, P2WPKH, P2SH-P2WPKH and P2TR key-path inputs",
+                Some(schema_ref("SignTxRequest")),
+                schema_ref("SignTxResponse"),
+            ),
+        ),
+        (
+            "/broadcast",
+            "post",
+            operation("Broadcast a signed transaction", Some(schema_ref("TxHexRequest")), schema_ref("BroadcastResponse")),
+        ),
+        (
+            "/decode_tx",
+            "post",
+            operation("Decode a raw transaction", Some(schema_ref("TxHexRequest")), schema_ref("DecodedTransaction")),
+        ),
+        ("/tx/{txid}/events", "get", tx_events),
+        ("/tx/events", "post", broadcast_events),
+        ("/address/{address}/utxos", "get", utxos),
+        ("/address/{address}/validate", "get", validate),
+        ("/fees", "get", operation("Fee estimates and presets", None, schema_ref("FeesResponse"))),
+        ("/fees/{target_blocks}", "get", fee_target),
+        (
+            "/estimate_size",
+            "post",
+            operation(
+                "Predict the size of a transaction from its input and output types, and its fee at a rate",
+                Some(schema_ref("EstimateSizeRequest")),
+                schema_ref("EstimateSizeResponse"),
+            ),
+        ),
+        ("/wallet/{name}/address/next", "get", next_address),
+        ("/wallet/{name}/address/{index}", "get", wallet_address),
+    ]
+}
+
+/// The whole OpenAPI document
+pub fn document() -> Value {
+    let mut paths = Map::new();
+    for (path, method, mut op) in operations() {
+        if NETWORK_OVERRIDES.contains(&path) {
+            let schema = string(
+                "mainnet, testnet, signet or regtest; another network than the server's needs --allow-network-override. \
+                 Every response carries the same header naming the network it is about",
+            );
+            add_parameter(&mut op, header_parameter(crate::NETWORK_HEADER, schema));
+        }
+        if crate::idempotency::ROUTES.contains(&path) {
+            let schema = string("Replays the first response to this key, route and body instead of running the request again");
+            add_parameter(&mut op, header_parameter(crate::idempotency::IDEMPOTENCY_KEY_HEADER, schema));
+        }
+        let item = paths.entry(path).or_insert_with(|| json!({}));
+        item[method] = op;
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "btcx API",
+            "version": env!("CARGO_PKG_VERSION"),
+            "description": "Build, fund, sign, decode and broadcast Bitcoin transactions. Amounts are integers in satoshis; fee rates are in sat/vB.",
+        },
+        "paths": paths,
+        "components": {
+            "schemas": schemas(),
+            "securitySchemes": {
+                "ApiKey": { "type": "apiKey", "in": "header", "name": crate::auth::API_KEY_HEADER },
+                "BearerKey": { "type": "http", "scheme": "bearer", "description": "The API key as a bearer token" },
+            },
+        },
+        // Either scheme; only enforced when the server has keys configured
+        "security": [{ "ApiKey": [] }, { "BearerKey": [] }],
+    })
+}
+
+// Handler for the /openapi.json endpoint
+async fn openapi() -> HttpResponse {
+    HttpResponse::Ok().json(document())
+}
+
+// Handler for the /docs endpoint: Swagger UI pointed at /openapi.json
+async fn swagger_ui() -> HttpResponse {
+    let page = format!(
+        r##"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>btcx API</title>
+<link rel="stylesheet" href="https://unpkg.com/swagger-ui-dist@{version}/swagger-ui.css">
+</head>
+<body>
+<div id="swagger-ui"></div>
+<script src="https://unpkg.com/swagger-ui-dist@{version}/swagger-ui-bundle.js"></script>
+<script>SwaggerUIBundle({{ url: "{spec}", dom_id: "#swagger-ui" }});</script>
+</body>
+</html>
+"##,
+        version = SWAGGER_UI_VERSION,
+        spec = OPENAPI_PATH
+    );
+    HttpResponse::Ok().content_type("text/html; charset=utf-8").body(page)
+}
+
+/// Register /openapi.json, and /docs when `swagger_ui` is set
+pub fn routes(cfg: &mut web::ServiceConfig, swagger_ui: bool) {
+    cfg.route(OPENAPI_PATH, web::get().to(openapi));
+    if swagger_ui {
+        cfg.route(SWAGGER_UI_PATH, web::get().to(self::swagger_ui));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{TxEvents, TxEventsConfig};
+    use crate::health::Readiness;
+    use crate::metrics::Metrics;
+    use crate::tests::{state, LEGACY_TX_HEX};
+    use crate::wallet::{parse_wallets, MemoryIndexStore, Wallets};
+    use actix_web::{test, App};
+    use btcx_lib::BtcNetwork;
+
+    const ADDRESS: &str = "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq";
+    const TXID: &str = "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16";
+    // BIP 32 test vector 1, m/0'
+    const XPUB: &str = "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw";
+
+    macro_rules! app {
+        () => {
+            test::init_service(
+                App::new()
+                    .app_data(web::Data::new(state(BtcNetwork::Bitcoin, "http://127.0.0.1:9")))
+                    .app_data(web::Data::new(Readiness::default()))
+                    .app_data(web::Data::new(Metrics::default()))
+                    .app_data(web::Data::new(Wallets::new(
+                        parse_wallets(&format!("shop:p2wpkh:{}", XPUB), BtcNetwork::Bitcoin).unwrap(),
+                        Box::new(MemoryIndexStore::default()),
+                    )))
+                    .configure(crate::health::routes)
+                    .configure(crate::metrics::routes)
+                    .configure(|cfg| routes(cfg, true))
+                    .app_data(web::Data::new(TxEvents::new(TxEventsConfig::default())))
+                    .configure(crate::wallet::routes)
+                    .configure(crate::events::routes)
+                    .configure(crate::routes),
+            )
+            .await
+        };
+    }
+
+    // Check `value` against `schema`, following references into `doc`
+    fn conforms(doc: &Value, schema: &Value, value: &Value, at: &str) {
+        if let Some(reference) = schema["$ref"].as_str() {
+            let name = reference.trim_start_matches("#/components/schemas/");
+            return conforms(doc, &doc["components"]["schemas"][name], value, at);
+        }
+        if value.is_null() && schema["nullable"] == true {
+            return;
+        }
+        match schema["type"].as_str() {
+            Some("object") => {
+                let object = value.as_object().unwrap_or_else(|| panic!("{} is not an object", at));
+                for name in schema["required"].as_array().into_iter().flatten() {
+                    assert!(object.contains_key(name.as_str().unwrap()), "{} lacks {}", at, name);
+                }
+                for (name, field) in object {
+                    let at = format!("{}.{}", at, name);
+                    match schema["properties"].get(name).or(schema.get("additionalProperties")) {
+                        Some(property) => conforms(doc, property, field, &at),
+                        None => panic!("{} is not documented", at),
+                    }
+                }
+            }
+            Some("array") => {
+                let items = value.as_array().unwrap_or_else(|| panic!("{} is not an array", at));
+                for (i, item) in items.iter().enumerate() {
+                    conforms(doc, &schema["items"], item, &format!("{}[{}]", at, i));
+                }
+            }
+            Some("string") => assert!(value.is_string(), "{} is not a string", at),
+            Some("integer") => assert!(value.is_u64() || value.is_i64(), "{} is not an integer", at),
+            Some("number") => assert!(value.is_number(), "{} is not a number", at),
+            Some("boolean") => assert!(value.is_boolean(), "{} is not a boolean", at),
+            _ => {}
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_document_lists_every_route() {
+        let doc = document();
+        let mut documented: Vec<(String, String)> = Vec::new();
+        for (path, item) in doc["paths"].as_object().unwrap() {
+            for method in item.as_object().unwrap().keys() {
+                documented.push((method.to_uppercase(), path.clone()));
+            }
+        }
+        documented.sort();
+        let mut registered = [
+            ("GET", "/health"),
+            ("GET", "/ready"),
+            ("GET", "/version"),
+            ("GET", "/metrics"),
+            ("GET", "/openapi.json"),
+            ("POST", "/create_tx"),
+            ("POST", "/create_tx/batch"),
+            ("POST", "/fund_tx"),
+            ("POST", "/create_psbt"),
+            ("POST", "/finalize_psbt"),
+            ("POST", "/sign_tx"),
+            ("POST", "/broadcast"),
+            ("POST", "/decode_tx"),
+            ("GET", "/tx/{txid}/events"),
+            ("POST", "/tx/events"),
+            ("GET", "/address/{address}/utxos"),
+            ("GET", "/address/{address}/validate"),
+            ("GET", "/fees"),
+            ("GET", "/fees/{target_blocks}"),
+            ("POST", "/estimate_size"),
+            ("GET", "/wallet/{name}/address/next"),
+            ("GET", "/wallet/{name}/address/{index}"),
+        ]
+        .map(|(method, path)| (method.to_string(), path.to_string()));
+        registered.sort();
+        assert_eq!(documented, registered);
+
+        // Every documented operation reaches a handler, through the app's own routing
+        let app = app!();
+        for (method, path) in &documented {
+            let uri = path
+                .replace("{address}", ADDRESS)
+                .replace("{target_blocks}", "6")
+                .replace("{name}", "shop")
+                .replace("{index}", "0")
+                .replace("{txid}", TXID);
+            let req = match method.as_str() {
+                "GET" => test::TestRequest::get().uri(&uri),
+                _ => test::TestRequest::post().uri(&uri).set_json(json!({})),
+            };
+            let status = test::call_service(&app, req.to_request()).await.status();
+            assert!(status != 404 && status != 405, "{} {} answered {}", method, path, status);
+        }
+
+        // Only the probes and the document itself are open without a key
+        let open: Vec<&str> = documented
+            .iter()
+            .filter(|(method, path)| doc["paths"][path][method.to_lowercase()]["security"] == json!([]))
+            .map(|(_, path)| path.as_str())
+            .collect();
+        assert_eq!(open, ["/health", "/openapi.json", "/ready"]);
+    }
+
+    #[actix_web::test]
+    async fn test_responses_match_schemas() {
+        let app = app!();
+        let doc: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(OPENAPI_PATH).to_request()).await;
+        assert_eq!(doc, document());
+        let create_tx = json!({
+            "inputs": [{ "txid": TXID, "vout": 0 }],
+            "outputs": [{ "address": ADDRESS, "amount": 10_000 }],
+        });
+        let fund_tx = json!({
+            "utxos": [{ "txid": TXID, "vout": 0, "amount": 50_000, "address": ADDRESS }],
+            "outputs": [{ "address": ADDRESS, "amount": 10_000 }],
+            "fee_rate": 2.0,
+            "change_address": ADDRESS,
+        });
+        let estimate_size = json!({ "inputs": [{ "type": "p2wpkh" }], "outputs": [{ "type": "p2tr" }] });
+        let mut priced = estimate_size.clone();
+        priced["fee_rate"] = json!(2.5);
+        let cases = [
+            ("/create_tx", create_tx.clone(), "TxResponse"),
+            ("/create_tx/batch", json!([create_tx, { "client_ref": 7, "inputs": [] }]), "BatchResponse"),
+            ("/fund_tx", fund_tx.clone(), "FundTxResponse"),
+            ("/create_psbt", fund_tx, "CreatePsbtResponse"),
+            ("/decode_tx", json!({ "tx_hex": LEGACY_TX_HEX }), "DecodedTransaction"),
+            ("/decode_tx", json!({ "tx_hex": "zz" }), "Error"),
+            ("/estimate_size", estimate_size.clone(), "EstimateSizeResponse"),
+            ("/estimate_size", priced, "EstimateSizeResponse"),
+        ];
+        for (path, body, schema) in cases {
+            let resp = test::call_service(&app, test::TestRequest::post().uri(path).set_json(body).to_request()).await;
+            assert_eq!(resp.status().is_success(), schema != "Error", "{}", path);
+            let body: Value = test::read_body_json(resp).await;
+            conforms(&doc, &schema_ref(schema), &body, path);
+        }
+        let validate = format!("/address/{}/validate", ADDRESS);
+        let invalid = "/address/bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdr/validate";
+        let gets = [
+            ("/health", "Status"),
+            ("/version", "Version"),
+            (&validate, "AddressValidation"),
+            (invalid, "AddressValidation"),
+            ("/wallet/shop/address/next", "WalletAddress"),
+            ("/wallet/shop/address/7", "WalletAddress"),
+        ];
+        for (path, schema) in gets {
+            let body: Value = test::call_and_read_body_json(&app, test::TestRequest::get().uri(path).to_request()).await;
+            conforms(&doc, &schema_ref(schema), &body, path);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_amounts_are_documented_in_sats() {
+        let doc = document();
+        let schemas = &doc["components"]["schemas"];
+        for (schema, field) in [("TxOutputRequest", "amount"), ("Utxo", "amount"), ("FundTxResponse", "fee")] {
+            assert_eq!(schemas[schema]["properties"][field]["x-unit"], "sat", "{}.{}", schema, field);
+        }
+        let ui = test::call_service(&app!(), test::TestRequest::get().uri(SWAGGER_UI_PATH).to_request()).await;
+        assert_eq!(ui.status(), 200);
+    }
+}
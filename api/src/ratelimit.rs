@@ -0,0 +1,310 @@
+//! Per-client rate limiting
+//!
+//! Each client gets a token bucket per route group. Clients are identified by
+//! the API key they authenticated with, or by IP address without one. The
+//! limiter is shared by all workers.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use actix_web::body::{EitherBody, MessageBody};
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::middleware::Next;
+use actix_web::{web, HttpMessage};
+
+use crate::auth::ApiKeyName;
+use crate::error::ApiError;
+
+/// Header a trusted proxy appends the client address to
+const FORWARDED_FOR_HEADER: &str = "X-Forwarded-For";
+
+/// How often idle buckets are dropped
+const SWEEP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Endpoints sharing a limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RouteGroup {
+    /// Endpoints answered locally, such as /decode_tx
+    Local,
+    /// Endpoints that call the Esplora backend, such as /broadcast
+    Upstream,
+}
+
+impl RouteGroup {
+    /// Group of a request path; `None` for unlimited paths such as probes and metrics
+    fn for_path(path: &str) -> Option<Self> {
+        match path {
+            "/health" | "/ready" | "/metrics" => None,
+            "/broadcast" | "/fees" | "/fund_tx" | "/create_psbt" | "/estimate_size" => Some(RouteGroup::Upstream),
+            // Answered locally, unlike the other address endpoints
+            _ if path.starts_with("/address/") && path.ends_with("/validate") => Some(RouteGroup::Local),
+            _ if path.starts_with("/fees/") || path.starts_with("/address/") || path.starts_with("/tx/") => {
+                Some(RouteGroup::Upstream)
+            }
+            _ => Some(RouteGroup::Local),
+        }
+    }
+}
+
+/// Sustained rate and burst size of one route group
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    /// Requests per minute
+    pub per_minute: u32,
+    /// Requests allowed at once after a quiet period
+    pub burst: u32,
+}
+
+impl RateLimit {
+    fn per_second(&self) -> f64 {
+        f64::from(self.per_minute) / 60.0
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    /// Parse `<per-minute>:<burst>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid rate limit {:?}: expected <per-minute>:<burst> with positive integers", s);
+        let (per_minute, burst) = s.split_once(':').ok_or_else(invalid)?;
+        match (per_minute.trim().parse::<u32>(), burst.trim().parse::<u32>()) {
+            (Ok(per_minute), Ok(burst)) if per_minute > 0 && burst > 0 => Ok(RateLimit { per_minute, burst }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Debug)]
+struct Buckets {
+    buckets: HashMap<(RouteGroup, String), Bucket>,
+    last_sweep: Instant,
+}
+
+/// Token buckets for every client and route group
+#[derive(Debug)]
+pub struct RateLimiter {
+    local: Option<RateLimit>,
+    upstream: Option<RateLimit>,
+    trust_forwarded_for: bool,
+    state: Mutex<Buckets>,
+}
+
+impl RateLimiter {
+    /// Limiter with optional limits per group; `None` leaves a group unlimited
+    ///
+    /// With `trust_forwarded_for`, clients without a key are identified by
+    /// the last `X-Forwarded-For` entry, as appended by the proxy in front.
+    pub fn new(local: Option<RateLimit>, upstream: Option<RateLimit>, trust_forwarded_for: bool) -> Self {
+        RateLimiter {
+            local,
+            upstream,
+            trust_forwarded_for,
+            state: Mutex::new(Buckets { buckets: HashMap::new(), last_sweep: Instant::now() }),
+        }
+    }
+
+    fn limit(&self, group: RouteGroup) -> Option<RateLimit> {
+        match group {
+            RouteGroup::Local => self.local,
+            RouteGroup::Upstream => self.upstream,
+        }
+    }
+
+    /// Take a token for `client`, or return how long until one is available
+    fn check(&self, group: RouteGroup, client: &str, now: Instant) -> Result<(), Duration> {
+        let Some(limit) = self.limit(group) else {
+            return Ok(());
+        };
+        let mut state = self.state.lock().unwrap();
+        if now.saturating_duration_since(state.last_sweep) >= SWEEP_INTERVAL {
+            self.sweep(&mut state, now);
+        }
+
+        let bucket = state
+            .buckets
+            .entry((group, client.to_string()))
+            .or_insert(Bucket { tokens: f64::from(limit.burst), updated: now });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * limit.per_second()).min(f64::from(limit.burst));
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / limit.per_second()))
+        }
+    }
+
+    // Drop buckets that have refilled completely, as they match a new one
+    fn sweep(&self, state: &mut Buckets, now: Instant) {
+        state.buckets.retain(|(group, _), bucket| {
+            let limit = self.limit(*group).expect("buckets exist only for limited groups");
+            let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+            bucket.tokens + elapsed * limit.per_second() < f64::from(limit.burst)
+        });
+        state.last_sweep = now;
+    }
+
+    // API key name when authenticated, client address otherwise
+    fn client(&self, req: &ServiceRequest) -> String {
+        if let Some(name) = req.extensions().get::<ApiKeyName>() {
+            return format!("key:{}", name.0);
+        }
+        let forwarded = || -> Option<IpAddr> {
+            let header = req.headers().get(FORWARDED_FOR_HEADER)?.to_str().ok()?;
+            header.rsplit(',').next()?.trim().parse().ok()
+        };
+        let ip = self.trust_forwarded_for.then(forwarded).flatten();
+        match ip.or_else(|| req.peer_addr().map(|addr| addr.ip())) {
+            Some(ip) => format!("ip:{}", ip),
+            None => "ip:unknown".to_string(),
+        }
+    }
+}
+
+/// Middleware answering 429 with `Retry-After` once a client's bucket is empty
+///
+/// Must run after authentication so clients with a key are counted by key.
+pub async fn limit_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<EitherBody<impl MessageBody>>, actix_web::Error> {
+    let limiter = req.app_data::<web::Data<RateLimiter>>().cloned();
+    if let (Some(limiter), Some(group)) = (limiter, RouteGroup::for_path(req.path())) {
+        if let Err(wait) = limiter.check(group, &limiter.client(&req), Instant::now()) {
+            let retry_after = wait.as_secs_f64().ceil().max(1.0) as u32;
+            let error = ApiError::new(StatusCode::TOO_MANY_REQUESTS, "rate_limited", "Too many requests, retry later")
+                .with_retry_after(retry_after);
+            return Ok(req.error_response(error).map_into_right_body());
+        }
+    }
+    next.call(req).await.map(ServiceResponse::map_into_left_body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::http::header::RETRY_AFTER;
+    use actix_web::middleware::from_fn;
+    use actix_web::rt::time::sleep;
+    use actix_web::{test, App, HttpResponse};
+
+    // Three requests at once, then one every 50 ms
+    const FAST: RateLimit = RateLimit { per_minute: 1_200, burst: 3 };
+    const SLOW: RateLimit = RateLimit { per_minute: 1, burst: 1 };
+
+    macro_rules! app {
+        ($limiter:expr) => {
+            test::init_service(
+                App::new()
+                    .app_data(web::Data::new($limiter))
+                    .wrap(from_fn(limit_requests))
+                    .route("/decode_tx", web::post().to(HttpResponse::Ok))
+                    .route("/broadcast", web::post().to(HttpResponse::Ok))
+                    .route("/health", web::get().to(HttpResponse::Ok)),
+            )
+            .await
+        };
+    }
+
+    fn post(uri: &str, ip: &str) -> actix_web::test::TestRequest {
+        test::TestRequest::post().uri(uri).peer_addr(format!("{}:40000", ip).parse().unwrap())
+    }
+
+    #[actix_web::test]
+    async fn test_burst_then_429_then_recovery() {
+        let app = app!(RateLimiter::new(Some(FAST), Some(SLOW), false));
+
+        for _ in 0..FAST.burst {
+            let resp = test::call_service(&app, post("/decode_tx", "10.0.0.1").to_request()).await;
+            assert_eq!(resp.status(), 200);
+        }
+        let resp = test::call_service(&app, post("/decode_tx", "10.0.0.1").to_request()).await;
+        assert_eq!(resp.status(), 429);
+        assert_eq!(resp.headers().get(RETRY_AFTER).unwrap(), "1");
+        let body: serde_json::Value = test::read_body_json(resp).await;
+        assert_eq!(body["error"]["code"], "rate_limited");
+
+        // Other clients and other route groups have their own buckets
+        let resp = test::call_service(&app, post("/decode_tx", "10.0.0.2").to_request()).await;
+        assert_eq!(resp.status(), 200);
+        let resp = test::call_service(&app, post("/broadcast", "10.0.0.1").to_request()).await;
+        assert_eq!(resp.status(), 200);
+        let resp = test::call_service(&app, post("/broadcast", "10.0.0.1").to_request()).await;
+        assert_eq!(resp.status(), 429);
+        assert_eq!(resp.headers().get(RETRY_AFTER).unwrap(), "60");
+
+        sleep(Duration::from_millis(60)).await;
+        let resp = test::call_service(&app, post("/decode_tx", "10.0.0.1").to_request()).await;
+        assert_eq!(resp.status(), 200);
+
+        // Probes are never limited
+        for _ in 0..5 {
+            let req = test::TestRequest::get().uri("/health").to_request();
+            assert_eq!(test::call_service(&app, req).await.status(), 200);
+        }
+    }
+
+    #[actix_web::test]
+    async fn test_forwarded_for_only_when_trusted() {
+        let forwarded = |ip: &str| post("/broadcast", "10.0.0.9").insert_header((FORWARDED_FOR_HEADER, format!("1.2.3.4, {}", ip)));
+
+        // Behind a trusted proxy each forwarded client has its own bucket
+        let app = app!(RateLimiter::new(None, Some(SLOW), true));
+        assert_eq!(test::call_service(&app, forwarded("192.0.2.1").to_request()).await.status(), 200);
+        assert_eq!(test::call_service(&app, forwarded("192.0.2.2").to_request()).await.status(), 200);
+        assert_eq!(test::call_service(&app, forwarded("192.0.2.2").to_request()).await.status(), 429);
+
+        // Otherwise the header is ignored and the peer address counts
+        let app = app!(RateLimiter::new(None, Some(SLOW), false));
+        assert_eq!(test::call_service(&app, forwarded("192.0.2.1").to_request()).await.status(), 200);
+        assert_eq!(test::call_service(&app, forwarded("192.0.2.2").to_request()).await.status(), 429);
+    }
+
+    #[actix_web::test]
+    async fn test_full_buckets_are_evicted() {
+        let limiter = RateLimiter::new(Some(FAST), Some(SLOW), false);
+        let start = Instant::now();
+        limiter.check(RouteGroup::Local, "ip:10.0.0.1", start).unwrap();
+        limiter.check(RouteGroup::Upstream, "ip:10.0.0.1", start).unwrap();
+        assert_eq!(limiter.state.lock().unwrap().buckets.len(), 2);
+
+        // Buckets refilled by the next sweep are dropped; the upstream one checked late is kept
+        limiter.check(RouteGroup::Upstream, "ip:10.0.0.2", start + SWEEP_INTERVAL - Duration::from_secs(1)).unwrap();
+        let later = start + SWEEP_INTERVAL + Duration::from_millis(500);
+        limiter.check(RouteGroup::Local, "ip:10.0.0.3", later).unwrap();
+        let state = limiter.state.lock().unwrap();
+        let mut clients: Vec<_> = state.buckets.keys().map(|(group, client)| (*group, client.as_str())).collect();
+        clients.sort_by_key(|(_, client)| *client);
+        assert_eq!(clients, [(RouteGroup::Upstream, "ip:10.0.0.2"), (RouteGroup::Local, "ip:10.0.0.3")]);
+    }
+
+    #[actix_web::test]
+    async fn test_parse_rate_limit() {
+        assert_eq!("60:10".parse(), Ok(RateLimit { per_minute: 60, burst: 10 }));
+        assert!("60".parse::<RateLimit>().is_err());
+        assert!("0:10".parse::<RateLimit>().is_err());
+        assert!("fast:10".parse::<RateLimit>().is_err());
+    }
+
+    #[actix_web::test]
+    async fn test_route_groups() {
+        assert_eq!(RouteGroup::for_path("/health"), None);
+        assert_eq!(RouteGroup::for_path("/decode_tx"), Some(RouteGroup::Local));
+        assert_eq!(RouteGroup::for_path("/address/bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq/utxos"), Some(RouteGroup::Upstream));
+        assert_eq!(RouteGroup::for_path("/address/bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq/validate"), Some(RouteGroup::Local));
+        assert_eq!(RouteGroup::for_path("/tx/events"), Some(RouteGroup::Upstream));
+        assert_eq!(RouteGroup::for_path("/estimate_size"), Some(RouteGroup::Upstream));
+    }
+}
@@ -0,0 +1,180 @@
+//! Graceful shutdown with connection draining
+//!
+//! On SIGTERM or SIGINT the server fails readiness, stops accepting
+//! connections and lets in-flight requests run for up to the drain timeout;
+//! requests still running then are aborted. Background tasks run until the
+//! shared cancellation token fires so the process exits once the server stops.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServerHandle, ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web;
+use tokio_util::sync::CancellationToken;
+
+use crate::health::Readiness;
+
+/// Shutdown state shared by the signal handler, request tracking and background tasks
+#[derive(Debug, Default)]
+pub struct Shutdown {
+    token: CancellationToken,
+    in_flight: AtomicUsize,
+    drained: AtomicUsize,
+    aborted: AtomicUsize,
+}
+
+impl Shutdown {
+    /// Token cancelled when shutdown begins, for background tasks to stop on
+    pub fn token(&self) -> CancellationToken {
+        self.token.clone()
+    }
+
+    /// Whether shutdown has begun
+    pub fn is_draining(&self) -> bool {
+        self.token.is_cancelled()
+    }
+
+    /// Requests that finished and that were cut short after shutdown began
+    ///
+    /// Requests still running count as aborted.
+    pub fn counts(&self) -> (usize, usize) {
+        let aborted = self.aborted.load(Ordering::Relaxed) + self.in_flight.load(Ordering::Relaxed);
+        (self.drained.load(Ordering::Relaxed), aborted)
+    }
+}
+
+/// Fail readiness, cancel background tasks and stop the server
+///
+/// Returns once in-flight requests finished or the drain timeout aborted them.
+pub async fn stop(handle: ServerHandle, readiness: &Readiness, shutdown: &Shutdown) {
+    readiness.begin_shutdown();
+    shutdown.token.cancel();
+    log::info!("Shutting down, draining {} in-flight requests", shutdown.in_flight.load(Ordering::Relaxed));
+    handle.stop(true).await;
+}
+
+// Counts one request until it finishes or is dropped unfinished
+struct InFlight<'a> {
+    shutdown: &'a Shutdown,
+    finished: bool,
+}
+
+impl<'a> InFlight<'a> {
+    fn start(shutdown: &'a Shutdown) -> Self {
+        shutdown.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight { shutdown, finished: false }
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.shutdown.in_flight.fetch_sub(1, Ordering::Relaxed);
+        if self.shutdown.is_draining() {
+            let counter = if self.finished { &self.shutdown.drained } else { &self.shutdown.aborted };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Middleware tracking in-flight requests for the shutdown report
+pub async fn track_requests(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let Some(shutdown) = req.app_data::<web::Data<Shutdown>>().cloned() else {
+        return next.call(req).await;
+    };
+    let mut request = InFlight::start(&shutdown);
+    let response = next.call(req).await;
+    request.finished = true;
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{state, LEGACY_TX_HEX};
+    use actix_web::middleware::from_fn;
+    use actix_web::{App, HttpServer};
+    use btcx_lib::BtcNetwork;
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+    use std::time::Duration;
+
+    // POST on a fresh connection and read the whole response
+    fn post(addr: SocketAddr, path: &str, body: &str) -> std::io::Result<String> {
+        let mut stream = TcpStream::connect(addr)?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            path,
+            addr,
+            body.len(),
+            body
+        )?;
+        let mut response = String::new();
+        stream.read_to_string(&mut response)?;
+        Ok(response)
+    }
+
+    #[actix_web::test]
+    async fn test_shutdown_drains_in_flight_requests() {
+        // The backend takes a second to accept the broadcast
+        let mut upstream = mockito::Server::new_async().await;
+        upstream
+            .mock("POST", "/tx")
+            .with_chunked_body(|w| {
+                std::thread::sleep(Duration::from_secs(1));
+                w.write_all(b"ok")
+            })
+            .create_async()
+            .await;
+
+        let data = web::Data::new(state(BtcNetwork::Bitcoin, &upstream.url()));
+        let readiness = web::Data::new(Readiness::default());
+        let shutdown = web::Data::new(Shutdown::default());
+        let server = HttpServer::new({
+            let shutdown = shutdown.clone();
+            move || {
+                App::new()
+                    .app_data(data.clone())
+                    .app_data(shutdown.clone())
+                    .wrap(from_fn(track_requests))
+                    .configure(crate::routes)
+            }
+        })
+        .workers(1)
+        .disable_signals()
+        .shutdown_timeout(10)
+        .bind("127.0.0.1:0")
+        .unwrap();
+        let addr = server.addrs()[0];
+        let server = server.run();
+        let handle = server.handle();
+        let server = actix_web::rt::spawn(server);
+        let background = actix_web::rt::spawn(shutdown.token().run_until_cancelled_owned(std::future::pending::<()>()));
+
+        let body = serde_json::json!({ "tx_hex": LEGACY_TX_HEX }).to_string();
+        let slow = web::block(move || post(addr, "/broadcast", &body));
+        actix_web::rt::time::sleep(Duration::from_millis(300)).await;
+
+        let stopping = actix_web::rt::spawn({
+            let (readiness, shutdown) = (readiness.clone(), shutdown.clone());
+            async move { stop(handle, &readiness, &shutdown).await }
+        });
+        actix_web::rt::time::sleep(Duration::from_millis(200)).await;
+        assert!(!readiness.is_ready());
+        assert_eq!(background.await.unwrap(), None);
+
+        // New connections are refused while the broadcast still completes
+        assert!(web::block(move || post(addr, "/decode_tx", "{}")).await.unwrap().is_err());
+        let response = slow.await.unwrap().unwrap();
+        assert!(response.starts_with("HTTP/1.1 200"), "{}", response);
+        assert!(response.contains(r#""already_known":false"#));
+
+        stopping.await.unwrap();
+        server.await.unwrap().unwrap();
+        assert_eq!(shutdown.counts(), (1, 0));
+    }
+}
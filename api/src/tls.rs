@@ -0,0 +1,204 @@
+//! Optional HTTPS, with the certificate reloadable on SIGHUP
+//!
+//! The certificate and key are read from PEM files at startup. Sending the
+//! process SIGHUP reads them again; new connections use the new pair while
+//! open ones keep theirs. A pair that fails to load is logged and the old one
+//! stays in use.
+
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use rustls::crypto::aws_lc_rs;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert};
+use rustls::sign::CertifiedKey;
+use rustls::ServerConfig;
+
+/// PEM files holding the certificate chain and its private key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsFiles {
+    /// Certificate chain, leaf first
+    pub cert: PathBuf,
+    /// Private key of the leaf certificate
+    pub key: PathBuf,
+}
+
+/// Serves the most recently loaded certificate
+#[derive(Debug)]
+pub struct CertResolver {
+    files: TlsFiles,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl CertResolver {
+    /// Load the certificate and key, failing if either does not parse or they do not match
+    pub fn load(files: TlsFiles) -> Result<Self, String> {
+        let current = RwLock::new(Arc::new(load_certified_key(&files)?));
+        Ok(CertResolver { files, current })
+    }
+
+    /// Read the files again, keeping the current pair on failure
+    pub fn reload(&self) -> Result<(), String> {
+        let key = load_certified_key(&self.files)?;
+        *self.current.write().unwrap() = Arc::new(key);
+        Ok(())
+    }
+
+    /// rustls server configuration using this resolver
+    pub fn server_config(self: &Arc<Self>) -> Result<ServerConfig, String> {
+        let config = ServerConfig::builder_with_provider(Arc::new(aws_lc_rs::default_provider()))
+            .with_safe_default_protocol_versions()
+            .map_err(|e| format!("Cannot configure TLS: {}", e))?
+            .with_no_client_auth()
+            .with_cert_resolver(self.clone());
+        Ok(config)
+    }
+
+    /// Reload on every SIGHUP, forever
+    ///
+    /// Other platforms have no SIGHUP; restart the server to pick up a new certificate.
+    pub async fn reload_on_sighup(self: Arc<Self>) {
+        #[cfg(unix)]
+        {
+            use actix_web::rt::signal::unix::{signal, SignalKind};
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(e) => {
+                    log::error!("Cannot listen for SIGHUP, certificate reload disabled: {}", e);
+                    return;
+                }
+            };
+            while hangup.recv().await.is_some() {
+                match self.reload() {
+                    Ok(()) => log::info!("Reloaded TLS certificate from {}", self.files.cert.display()),
+                    Err(e) => log::error!("TLS certificate reload failed, keeping the current one: {}", e),
+                }
+            }
+        }
+    }
+}
+
+impl ResolvesServerCert for CertResolver {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+fn load_certified_key(files: &TlsFiles) -> Result<CertifiedKey, String> {
+    let cert_path = files.cert.display();
+    let certs = CertificateDer::pem_file_iter(&files.cert)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Cannot read TLS certificate {}: {}", cert_path, e))?;
+    if certs.is_empty() {
+        return Err(format!("No certificate found in {}", cert_path));
+    }
+    // The parse error describes the file, never the key material
+    let key = PrivateKeyDer::from_pem_file(&files.key)
+        .map_err(|e| format!("Cannot read TLS key {}: {}", files.key.display(), e))?;
+    CertifiedKey::from_der(certs, key, &aws_lc_rs::default_provider())
+        .map_err(|e| format!("TLS key {} does not fit certificate {}: {}", files.key.display(), cert_path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{web, App, HttpResponse, HttpServer};
+    use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+    use std::io::{Read, Write};
+    use std::net::{SocketAddr, TcpStream};
+
+    struct TestCert {
+        der: CertificateDer<'static>,
+        files: TlsFiles,
+    }
+
+    // Write a fresh self-signed localhost certificate under `name`
+    fn self_signed(name: &str) -> TestCert {
+        let generated = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let dir = std::env::temp_dir().join(format!("btcx-tls-{}-{}", std::process::id(), name));
+        std::fs::create_dir_all(&dir).unwrap();
+        let files = TlsFiles { cert: dir.join("cert.pem"), key: dir.join("key.pem") };
+        std::fs::write(&files.cert, generated.cert.pem()).unwrap();
+        std::fs::write(&files.key, generated.key_pair.serialize_pem()).unwrap();
+        TestCert { der: generated.cert.der().clone(), files }
+    }
+
+    // GET /health over TLS, trusting only `trusted`; returns the response and the served certificate
+    fn get_health(addr: SocketAddr, trusted: &[CertificateDer<'static>]) -> (String, CertificateDer<'static>) {
+        let mut roots = RootCertStore::empty();
+        for cert in trusted {
+            roots.add(cert.clone()).unwrap();
+        }
+        let config = ClientConfig::builder_with_provider(Arc::new(aws_lc_rs::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let conn = ClientConnection::new(Arc::new(config), "localhost".try_into().unwrap()).unwrap();
+        let mut stream = StreamOwned::new(conn, TcpStream::connect(addr).unwrap());
+        stream
+            .write_all(b"GET /health HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        // The server may close without a TLS close_notify once the response is sent
+        let _ = stream.read_to_string(&mut response);
+        let served = stream.conn.peer_certificates().unwrap()[0].clone().into_owned();
+        (response, served)
+    }
+
+    #[actix_web::test]
+    async fn test_https_and_reload() {
+        let first = self_signed("first");
+        let resolver = Arc::new(CertResolver::load(first.files.clone()).unwrap());
+        let server = HttpServer::new(|| App::new().route("/health", web::get().to(|| async { HttpResponse::Ok().body("ok") })))
+            .workers(1)
+            .disable_signals()
+            .bind_rustls_0_23("127.0.0.1:0", resolver.server_config().unwrap())
+            .unwrap();
+        let addr = server.addrs()[0];
+        let server = server.run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        let trusted = first.der.clone();
+        let (response, served) = web::block(move || get_health(addr, &[trusted])).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.ends_with("ok"));
+        assert_eq!(served, first.der);
+
+        // A new pair written over the old files is served after a reload
+        let second = self_signed("second");
+        std::fs::copy(&second.files.cert, &first.files.cert).unwrap();
+        std::fs::copy(&second.files.key, &first.files.key).unwrap();
+        resolver.reload().unwrap();
+        let trusted = [first.der.clone(), second.der.clone()];
+        let (_, served) = web::block(move || get_health(addr, &trusted)).await.unwrap();
+        assert_eq!(served, second.der);
+
+        // A broken file leaves the current pair in place
+        std::fs::write(&first.files.key, "not a key").unwrap();
+        assert!(resolver.reload().is_err());
+        let trusted = second.der.clone();
+        let (response, _) = web::block(move || get_health(addr, &[trusted])).await.unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+
+        handle.stop(false).await;
+    }
+
+    #[actix_web::test]
+    async fn test_load_errors() {
+        let first = self_signed("errors-first");
+        let second = self_signed("errors-second");
+
+        let missing = TlsFiles { cert: first.files.cert.with_extension("missing"), ..first.files.clone() };
+        assert!(CertResolver::load(missing).unwrap_err().contains("Cannot read TLS certificate"));
+
+        std::fs::write(second.files.cert.with_extension("empty"), "").unwrap();
+        let empty = TlsFiles { cert: second.files.cert.with_extension("empty"), ..second.files.clone() };
+        assert!(CertResolver::load(empty).unwrap_err().contains("No certificate found"));
+
+        let mismatched = TlsFiles { cert: first.files.cert.clone(), key: second.files.key.clone() };
+        assert!(CertResolver::load(mismatched).unwrap_err().contains("does not fit"));
+    }
+}
@@ -0,0 +1,336 @@
+//! Receive addresses derived from configured account xpubs
+//!
+//! Wallets are watch-only: each is an account xpub, the script type of its
+//! addresses and the branch to derive from. /wallet/{name}/address/next hands
+//! out every index once; an `IndexStore` decides where that counter lives.
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Mutex, PoisonError};
+
+use actix_web::http::StatusCode;
+use actix_web::{web, HttpResponse};
+use btcx_lib::hd::{AccountXpub, DerivedAddress};
+use btcx_lib::BtcNetwork;
+use serde::Serialize;
+
+use crate::error::ApiError;
+
+/// Branch receive addresses are derived from unless configured otherwise
+const RECEIVE_BRANCH: u32 = 0;
+
+/// A named account addresses are derived from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Wallet {
+    name: String,
+    account: AccountXpub,
+    branch: u32,
+}
+
+impl Wallet {
+    /// Name the wallet is addressed by in URLs
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn derive(&self, index: u32) -> Result<DerivedAddress, ApiError> {
+        self.account.derive(self.branch, index).map_err(|e| ApiError::bad_request("invalid_index", e.to_string()))
+    }
+}
+
+/// Parse `name:type:key[:branch]` entries separated by commas or newlines
+///
+/// The key is an xpub, optionally prefixed with its `[fingerprint/path]`
+/// origin, and must belong to `network`. Blank entries and lines starting
+/// with `#` are skipped.
+pub fn parse_wallets(text: &str, network: BtcNetwork) -> Result<Vec<Wallet>, String> {
+    let mut wallets: Vec<Wallet> = Vec::new();
+    let entries = text
+        .split(['\n', ','])
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty() && !entry.starts_with('#'));
+    for (i, entry) in entries.enumerate() {
+        let mut parts = entry.split(':').map(str::trim);
+        let (Some(name), Some(kind), Some(key)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(format!("Wallet entry {} is not in name:type:key[:branch] form", i + 1));
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(format!("Wallet entry {} has an invalid name", i + 1));
+        }
+        let branch = match (parts.next(), parts.next()) {
+            (None, _) => RECEIVE_BRANCH,
+            (Some(branch), None) => branch
+                .parse::<u32>()
+                .ok()
+                .filter(|&b| b < 1 << 31)
+                .ok_or_else(|| format!("Wallet {:?} has an invalid branch {:?}", name, branch))?,
+            _ => return Err(format!("Wallet entry {} is not in name:type:key[:branch] form", i + 1)),
+        };
+        let kind = kind.parse().map_err(|e| format!("Wallet {:?}: {}", name, e))?;
+        let account = AccountXpub::parse(key, kind, network).map_err(|e| format!("Wallet {:?}: {}", name, e))?;
+        if wallets.iter().any(|w| w.name == name) {
+            return Err(format!("Wallet name {:?} is used more than once", name));
+        }
+        wallets.push(Wallet { name: name.to_string(), account, branch });
+    }
+    Ok(wallets)
+}
+
+/// Where the next unissued index of each wallet is kept
+pub trait IndexStore: Send + Sync {
+    /// Return the next unissued index of `wallet` and mark it issued
+    fn reserve(&self, wallet: &str) -> io::Result<u32>;
+}
+
+/// Counters kept in memory; they restart at 0 with the process, so clients
+/// should record the index returned with each address
+#[derive(Debug, Default)]
+pub struct MemoryIndexStore(Mutex<HashMap<String, u32>>);
+
+impl IndexStore for MemoryIndexStore {
+    fn reserve(&self, wallet: &str) -> io::Result<u32> {
+        let mut next = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        let index = next.entry(wallet.to_string()).or_insert(0);
+        *index += 1;
+        Ok(*index - 1)
+    }
+}
+
+/// Counters persisted as a JSON object of wallet name to next index
+///
+/// The file is rewritten through a temporary file and a rename before an
+/// index is handed out, so a crash never reissues an address.
+#[derive(Debug)]
+pub struct FileIndexStore {
+    path: PathBuf,
+    next: Mutex<BTreeMap<String, u32>>,
+}
+
+impl FileIndexStore {
+    /// Load the counters from `path`, starting empty when it does not exist
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let next = match std::fs::read(&path) {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(FileIndexStore { path, next: Mutex::new(next) })
+    }
+}
+
+impl IndexStore for FileIndexStore {
+    fn reserve(&self, wallet: &str) -> io::Result<u32> {
+        let mut next = self.next.lock().unwrap_or_else(PoisonError::into_inner);
+        let index = next.get(wallet).copied().unwrap_or(0);
+        let mut updated = next.clone();
+        updated.insert(wallet.to_string(), index + 1);
+        let mut temp = self.path.clone().into_os_string();
+        temp.push(".tmp");
+        std::fs::write(&temp, serde_json::to_vec_pretty(&updated)?)?;
+        std::fs::rename(&temp, &self.path)?;
+        *next = updated;
+        Ok(index)
+    }
+}
+
+/// Configured wallets and their index counters
+pub struct Wallets {
+    wallets: Vec<Wallet>,
+    store: Box<dyn IndexStore>,
+}
+
+impl Wallets {
+    /// Wallets sharing one index store
+    pub fn new(wallets: Vec<Wallet>, store: Box<dyn IndexStore>) -> Self {
+        Wallets { wallets, store }
+    }
+
+    fn get(&self, name: &str) -> Result<&Wallet, ApiError> {
+        self.wallets.iter().find(|w| w.name == name).ok_or_else(|| {
+            ApiError::new(StatusCode::NOT_FOUND, "unknown_wallet", format!("No wallet named {:?} is configured", name))
+        })
+    }
+}
+
+#[derive(Serialize)]
+struct WalletAddressResponse {
+    wallet: String,
+    index: u32,
+    address: String,
+    derivation_path: String,
+    script_pubkey: String,
+    #[serde(rename = "type")]
+    address_type: String,
+}
+
+fn address_response(wallet: &Wallet, index: u32) -> Result<HttpResponse, ApiError> {
+    let derived = wallet.derive(index)?;
+    // Paths from a key with a known origin start at the master key, others at the account key
+    let derivation_path = match wallet.account.origin() {
+        Some(_) => format!("m/{}", derived.path),
+        None => derived.path.to_string(),
+    };
+    Ok(HttpResponse::Ok().json(WalletAddressResponse {
+        wallet: wallet.name.clone(),
+        index,
+        address: derived.address.to_string(),
+        derivation_path,
+        script_pubkey: derived.script_pubkey.to_hex_string(),
+        address_type: wallet.account.kind().to_string(),
+    }))
+}
+
+// Handler for /wallet/{name}/address/next: a fresh receive address
+async fn next_address(wallets: web::Data<Wallets>, name: web::Path<String>) -> Result<HttpResponse, ApiError> {
+    let wallet = wallets.get(&name)?;
+    let index = wallets.store.reserve(&wallet.name).map_err(|e| {
+        log::error!("Cannot record the next index of wallet {:?}: {}", wallet.name, e);
+        ApiError::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", "Internal server error")
+    })?;
+    address_response(wallet, index)
+}
+
+// Handler for /wallet/{name}/address/{index}: the address at a given index, again
+async fn address_at(wallets: web::Data<Wallets>, path: web::Path<(String, u32)>) -> Result<HttpResponse, ApiError> {
+    let (name, index) = path.into_inner();
+    address_response(wallets.get(&name)?, index)
+}
+
+/// Register the wallet endpoints; the literal `next` route comes first
+pub fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/wallet/{name}/address/next", web::get().to(next_address))
+        .route("/wallet/{name}/address/{index}", web::get().to(address_at));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use actix_web::{test, App};
+    use bitcoin::bip32::{ChildNumber, DerivationPath, Xpriv, Xpub};
+    use bitcoin::secp256k1::Secp256k1;
+    use bitcoin::{Address, Network, PublicKey};
+    use serde_json::Value;
+    use std::str::FromStr;
+
+    const SEED: &str = "000102030405060708090a0b0c0d0e0f";
+    const ACCOUNT_PATH: &str = "m/44'/0'/0'";
+
+    // Addresses the way scripts/generate_addresses derives them: private
+    // derivation along the whole path from the seed, then compressed P2PKH
+    fn generate_addresses(path: &str, count: u32) -> Vec<String> {
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(Network::Bitcoin, &hex::decode(SEED).unwrap()).unwrap();
+        let parent = master.derive_priv(&secp, &DerivationPath::from_str(path).unwrap()).unwrap();
+        (0..count)
+            .map(|index| {
+                let child = parent.derive_priv(&secp, &[ChildNumber::from_normal_idx(index).unwrap()]).unwrap();
+                let public_key = PublicKey::new(child.private_key.public_key(&secp));
+                Address::p2pkh(public_key, Network::Bitcoin).to_string()
+            })
+            .collect()
+    }
+
+    fn account_key() -> String {
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(Network::Bitcoin, &hex::decode(SEED).unwrap()).unwrap();
+        let account = master.derive_priv(&secp, &DerivationPath::from_str(ACCOUNT_PATH).unwrap()).unwrap();
+        format!("[{}/44'/0'/0']{}", master.fingerprint(&secp), Xpub::from_priv(&secp, &account))
+    }
+
+    macro_rules! app {
+        ($store:expr) => {{
+            let wallets = parse_wallets(&format!("main:p2pkh:{}", account_key()), BtcNetwork::Bitcoin).unwrap();
+            test::init_service(
+                App::new()
+                    .app_data(web::Data::new(Wallets::new(wallets, Box::new($store))))
+                    .app_data(web::PathConfig::default().error_handler(crate::error::path_error))
+                    .configure(routes),
+            )
+            .await
+        }};
+    }
+
+    // Status and JSON body of a GET
+    macro_rules! get {
+        ($app:expr, $uri:expr) => {{
+            let resp = test::call_service(&$app, test::TestRequest::get().uri(&$uri).to_request()).await;
+            let status = resp.status().as_u16();
+            (status, test::read_body_json::<Value, _>(resp).await)
+        }};
+    }
+
+    #[actix_web::test]
+    async fn test_addresses_match_generate_addresses() {
+        let expected = generate_addresses(&format!("{}/0", ACCOUNT_PATH), 4);
+        let app = app!(MemoryIndexStore::default());
+        for (index, address) in expected.iter().enumerate() {
+            let (status, next) = get!(app, "/wallet/main/address/next");
+            assert_eq!(status, 200);
+            assert_eq!(next["index"], index);
+            assert_eq!(next["address"], address.as_str());
+            assert_eq!(next["derivation_path"], format!("m/44'/0'/0'/0/{}", index));
+            assert_eq!(next["type"], "p2pkh");
+            let script = Address::from_str(address).unwrap().assume_checked().script_pubkey();
+            assert_eq!(next["script_pubkey"], script.to_hex_string());
+
+            // Re-deriving by index gives the same answer without consuming one
+            let (_, again) = get!(app, &format!("/wallet/main/address/{}", index));
+            assert_eq!(again, next);
+        }
+        assert_eq!(get!(app, "/wallet/main/address/next").1["index"], 4);
+    }
+
+    #[actix_web::test]
+    async fn test_bad_wallets_and_indexes() {
+        let app = app!(MemoryIndexStore::default());
+        let (status, body) = get!(app, "/wallet/savings/address/next");
+        assert_eq!((status, body["error"]["code"].as_str()), (404, Some("unknown_wallet")));
+        let (status, body) = get!(app, &format!("/wallet/main/address/{}", 1u32 << 31));
+        assert_eq!((status, body["error"]["code"].as_str()), (400, Some("invalid_index")));
+        let (status, body) = get!(app, "/wallet/main/address/first");
+        assert_eq!((status, body["error"]["code"].as_str()), (400, Some("invalid_request")));
+    }
+
+    #[actix_web::test]
+    async fn test_file_store_survives_restarts() {
+        let path = std::env::temp_dir().join(format!("btcx-wallet-state-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        {
+            let app = app!(FileIndexStore::open(&path).unwrap());
+            assert_eq!(get!(app, "/wallet/main/address/next").1["index"], 0);
+            assert_eq!(get!(app, "/wallet/main/address/next").1["index"], 1);
+        }
+        let app = app!(FileIndexStore::open(&path).unwrap());
+        assert_eq!(get!(app, "/wallet/main/address/next").1["index"], 2);
+        let saved: Value = serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+        assert_eq!(saved, serde_json::json!({ "main": 3 }));
+        std::fs::remove_file(&path).unwrap();
+
+        std::fs::write(&path, "not json").unwrap();
+        assert_eq!(FileIndexStore::open(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[actix_web::test]
+    async fn test_parse_wallets() {
+        let key = account_key();
+        let wallets = parse_wallets(&format!("# shop\nmain:p2pkh:{},change:p2pkh:{}:1\n", key, key), BtcNetwork::Bitcoin).unwrap();
+        let names: Vec<_> = wallets.iter().map(Wallet::name).collect();
+        assert_eq!(names, ["main", "change"]);
+        assert_eq!(wallets[1].branch, 1);
+
+        // A mainnet key fails on a test network instead of deriving wrong addresses
+        let error = parse_wallets(&format!("main:p2pkh:{}", key), BtcNetwork::Testnet).unwrap_err();
+        assert!(error.contains("Wallet \"main\""), "{}", error);
+        assert!(parse_wallets(&format!("main:p2sh:{}", key), BtcNetwork::Bitcoin).is_err());
+        assert!(parse_wallets(&format!("main:p2pkh:{}:2147483648", key), BtcNetwork::Bitcoin).is_err());
+        assert!(parse_wallets(&format!("main:p2pkh:{}:1:2", key), BtcNetwork::Bitcoin).is_err());
+        assert!(parse_wallets(&format!("main wallet:p2pkh:{}", key), BtcNetwork::Bitcoin).is_err());
+        assert!(parse_wallets(&format!("main:p2pkh:{},main:p2wpkh:{}", key, key), BtcNetwork::Bitcoin)
+            .unwrap_err()
+            .contains("more than once"));
+        assert!(parse_wallets("main:p2pkh", BtcNetwork::Bitcoin).is_err());
+    }
+}
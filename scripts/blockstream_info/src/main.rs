@@ -1,44 +1,65 @@
-use reqwest::blocking::get;
-use serde::Deserialize;
+use btcx_lib::network::BlockstreamClient;
+use btcx_lib::utils::network_for_address;
+use btcx_lib::{BtcNetwork, Error};
+use std::collections::hash_map::{Entry, HashMap};
 use std::env;
 use std::fs::File;
 use std::io::{self, BufRead, BufReader};
 
-#[derive(Deserialize)]
-struct AddressInfo {
-    chain_stats: ChainStats,
-}
-
-#[derive(Deserialize)]
-struct ChainStats {
-    funded_txo_sum: u64,
-    spent_txo_sum: u64,
-}
-
-fn main() -> io::Result<()> {
+#[tokio::main]
+async fn main() -> io::Result<()> {
     // Collect command-line arguments
     let args: Vec<String> = env::args().collect();
 
     // Handle help flag
     if args.len() > 1 && (args[1] == "--help" || args[1] == "-h") {
-        println!("Usage: {} [file_name]", args[0]);
+        println!("Usage: {} [--network <network>] [--esplora-url <url>] [--esplora-token <token>] [file_name]", args[0]);
         println!();
         println!("Reads Bitcoin addresses from file_name (one per line) and checks their balance.");
         println!("If no file_name is provided or file_name is '-', reads from stdin.");
+        println!("Without --network, each address is checked on the network it belongs to.");
         println!("Exits when a non-zero balance is found.");
+        println!();
+        println!("  --esplora-url: Esplora base URL (default: the network's from the environment or blockstream.info)");
+        println!("  --esplora-token: bearer token for the Esplora server (default: $BTCX_ESPLORA_TOKEN)");
         return Ok(());
     }
 
+    let mut network: Option<BtcNetwork> = None;
+    let mut esplora_url: Option<String> = None;
+    let mut esplora_token: Option<String> = None;
+    let mut file_name: Option<String> = None;
+    let mut i = 1;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--network" if i + 1 < args.len() => {
+                network = Some(args[i + 1].parse().map_err(invalid_input)?);
+                i += 2;
+            }
+            "--esplora-url" if i + 1 < args.len() => {
+                esplora_url = Some(args[i + 1].clone());
+                i += 2;
+            }
+            "--esplora-token" if i + 1 < args.len() => {
+                esplora_token = Some(args[i + 1].clone());
+                i += 2;
+            }
+            arg => {
+                file_name = Some(arg.to_string());
+                i += 1;
+            }
+        }
+    }
+
     // Determine input source: if no argument or argument is "-", read from stdin
-    let input_source: Box<dyn BufRead> = if args.len() < 2 || args[1] == "-" {
-        // Read from stdin
-        Box::new(BufReader::new(io::stdin()))
-    } else {
-        // Open the file specified by the argument
-        let file = File::open(&args[1])?;
-        Box::new(BufReader::new(file))
+    let input_source: Box<dyn BufRead> = match file_name.as_deref() {
+        None | Some("-") => Box::new(BufReader::new(io::stdin())),
+        Some(path) => Box::new(BufReader::new(File::open(path)?)),
     };
 
+    // One client per network the addresses belong to
+    let mut clients: HashMap<BtcNetwork, BlockstreamClient> = HashMap::new();
+
     // Loop over each line in the input source
     for line in input_source.lines() {
         let address = line?;
@@ -49,27 +70,48 @@ fn main() -> io::Result<()> {
             continue;
         }
 
-        // Construct the API URL
-        let url = format!("https://blockstream.info/api/address/{}", address);
+        // Use the network the address belongs to unless one was given
+        let address_network = match network.map_or_else(|| network_for_address(address), Ok) {
+            Ok(network) => network,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                continue;
+            }
+        };
+
+        let client = match clients.entry(address_network) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let mut builder = BlockstreamClient::builder(address_network);
+                if let Some(url) = &esplora_url {
+                    builder = builder.base_url(url);
+                }
+                if let Some(token) = &esplora_token {
+                    builder = builder.bearer_token(token);
+                }
+                entry.insert(builder.build().map_err(invalid_input)?)
+            }
+        };
 
         // Make the API request and handle errors
-        match get(&url) {
-            Ok(response) => {
-                match response.json::<AddressInfo>() {
-                    Ok(address_info) => {
-                        let balance = address_info.chain_stats.funded_txo_sum
-                            - address_info.chain_stats.spent_txo_sum;
-                        println!("Address: {}, Balance: {} satoshis", address, balance);
-                        if balance > 0 {
-                            break; // Stop processing further addresses
-                        }
-                    }
-                    Err(e) => eprintln!("Error parsing JSON for address {}: {}", address, e),
+        match client.get_address_info(address).await {
+            Ok(address_info) => {
+                let balance = address_info.balance().confirmed.to_sat();
+                println!("Address: {}, Balance: {} satoshis", address, balance);
+                if balance > 0 {
+                    break; // Stop processing further addresses
                 }
             }
+            Err(Error::Http { status, body, .. }) => {
+                eprintln!("Error: HTTP {} for address {}: {}", status, address, body.trim());
+            }
             Err(e) => eprintln!("Error fetching data for address {}: {}", address, e),
         }
     }
 
     Ok(())
 }
+
+fn invalid_input(e: btcx_lib::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, e.to_string())
+}
@@ -1,143 +1,185 @@
-use reqwest::blocking::get;
-use serde::Deserialize;
+use btcx_lib::network::BlockstreamClient;
+use btcx_lib::{BtcNetwork, Error};
 use std::env;
 use std::process;
+use std::str::FromStr;
 
-#[derive(Deserialize, serde::Serialize)]
-struct TxInput {
-    txid: String,
-    vout: u32,
-    prevout: Option<TxOutput>,
-    scriptsig: String,
-    scriptsig_asm: String,
-    witness: Option<Vec<String>>,
-    sequence: u64,
-}
-
-#[derive(Deserialize, serde::Serialize)]
-struct TxOutput {
-    scriptpubkey: String,
-    scriptpubkey_asm: String,
-    scriptpubkey_type: String,
-    scriptpubkey_address: Option<String>,
-    value: u64,
-}
+// Environment variable naming the network when --network is not given
+const NETWORK_ENV: &str = "BTCX_NETWORK";
 
-#[derive(Deserialize, serde::Serialize)]
-struct TransactionInfo {
-    txid: String,
-    version: u32,
-    locktime: u32,
-    vin: Vec<TxInput>,
-    vout: Vec<TxOutput>,
-    size: u32,
-    weight: u32,
-    fee: u64,
-    status: TxStatus,
+// Network named by --network, else by $BTCX_NETWORK, else mainnet
+fn resolve_network(flag: Option<&str>, env: Option<String>) -> Result<BtcNetwork, String> {
+    match flag.map(str::to_string).or(env) {
+        Some(name) => BtcNetwork::from_str(&name)
+            .map_err(|_| format!("Unknown network {:?}; use mainnet, testnet, signet or regtest", name)),
+        None => Ok(BtcNetwork::Bitcoin),
+    }
 }
 
-#[derive(Deserialize, serde::Serialize)]
-struct TxStatus {
-    confirmed: bool,
-    block_height: Option<u64>,
-    block_hash: Option<String>,
-    block_time: Option<u64>,
+fn print_usage(program: &str) {
+    eprintln!(
+        "Usage: {} [--network <mainnet|testnet|signet|regtest>] [--esplora-url <url>] [--esplora-token <token>] <txid>",
+        program
+    );
+    eprintln!("Example: {} abc123def456...", program);
+    eprintln!("  --network: network the transaction is on (default: ${} or mainnet)", NETWORK_ENV);
+    eprintln!("  --esplora-url: Esplora base URL (default: the network's from the environment or blockstream.info)");
+    eprintln!("  --esplora-token: bearer token for the Esplora server (default: $BTCX_ESPLORA_TOKEN)");
 }
 
-fn main() {
+#[tokio::main]
+async fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() < 2 {
-        eprintln!("Usage: {} <txid>", args[0]);
-        eprintln!("Example: {} abc123def456...", args[0]);
+    let mut network_flag: Option<&str> = None;
+    let mut esplora_url: Option<String> = None;
+    let mut esplora_token: Option<String> = None;
+    let mut positional: Vec<&String> = Vec::new();
+
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--network" | "--esplora-url" | "--esplora-token" => {
+                let Some(value) = rest.next() else {
+                    eprintln!("Error: {} requires a value", arg);
+                    process::exit(1);
+                };
+                match arg.as_str() {
+                    "--network" => network_flag = Some(value),
+                    "--esplora-url" => esplora_url = Some(value.clone()),
+                    _ => esplora_token = Some(value.clone()),
+                }
+            }
+            flag if flag.starts_with("--") => {
+                print_usage(&args[0]);
+                process::exit(1);
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    if positional.len() != 1 {
+        print_usage(&args[0]);
         process::exit(1);
     }
 
-    let txid = args[1].trim();
+    let txid = positional[0].trim();
 
     if txid.is_empty() {
         eprintln!("Error: txid cannot be empty");
         process::exit(1);
     }
 
-    // Construct the API URL
-    let url = format!("https://blockstream.info/api/tx/{}", txid);
-
-    // Make the API request
-    match get(&url) {
-        Ok(response) => {
-            if !response.status().is_success() {
-                eprintln!("Error: HTTP {} - Transaction not found or API error", response.status());
-                process::exit(1);
-            }
+    let network = match resolve_network(network_flag, env::var(NETWORK_ENV).ok()) {
+        Ok(network) => network,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
 
-            match response.json::<TransactionInfo>() {
-                Ok(tx_info) => {
-                    // Print transaction details in a readable format
-                    println!("Transaction ID: {}", tx_info.txid);
-                    println!("Version: {}", tx_info.version);
-                    println!("Locktime: {}", tx_info.locktime);
-                    println!("Size: {} bytes", tx_info.size);
-                    println!("Weight: {}", tx_info.weight);
-                    println!("Fee: {} satoshis", tx_info.fee);
-
-                    // Status information
-                    if tx_info.status.confirmed {
-                        println!("Status: Confirmed");
-                        if let Some(height) = tx_info.status.block_height {
-                            println!("Block Height: {}", height);
-                        }
-                        if let Some(ref block_hash) = tx_info.status.block_hash {
-                            println!("Block Hash: {}", block_hash);
-                        }
-                        if let Some(block_time) = tx_info.status.block_time {
-                            println!("Block Time: {}", block_time);
-                        }
-                    } else {
-                        println!("Status: Unconfirmed");
-                    }
+    let mut builder = BlockstreamClient::builder(network);
+    if let Some(url) = &esplora_url {
+        builder = builder.base_url(url);
+    }
+    if let Some(token) = &esplora_token {
+        builder = builder.bearer_token(token);
+    }
+    let client = match builder.build() {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Error creating client: {}", e);
+            process::exit(1);
+        }
+    };
 
-                    // Inputs
-                    println!("\nInputs ({}):", tx_info.vin.len());
-                    for (i, input) in tx_info.vin.iter().enumerate() {
-                        println!("  Input {}:", i);
-                        println!("    Previous TXID: {}", input.txid);
-                        println!("    Vout: {}", input.vout);
-                        println!("    Sequence: {}", input.sequence);
-                        if let Some(ref prevout) = input.prevout {
-                            if let Some(ref addr) = prevout.scriptpubkey_address {
-                                println!("    Address: {}", addr);
-                            }
-                            println!("    Amount: {} satoshis", prevout.value);
-                        }
-                    }
+    // Make the API request
+    match client.get_transaction(txid).await {
+        Ok(tx_info) => {
+            // Print transaction details in a readable format
+            println!("Transaction ID: {}", tx_info.txid);
+            println!("Version: {}", tx_info.version);
+            println!("Locktime: {}", tx_info.locktime);
+            println!("Size: {} bytes", tx_info.size);
+            println!("Weight: {}", tx_info.weight);
+            println!("Fee: {} satoshis", tx_info.fee);
 
-                    // Outputs
-                    println!("\nOutputs ({}):", tx_info.vout.len());
-                    for (i, output) in tx_info.vout.iter().enumerate() {
-                        println!("  Output {}:", i);
-                        if let Some(ref addr) = output.scriptpubkey_address {
-                            println!("    Address: {}", addr);
-                        }
-                        println!("    Amount: {} satoshis", output.value);
-                        println!("    Script Type: {}", output.scriptpubkey_type);
-                    }
+            // Status information
+            if tx_info.status.confirmed {
+                println!("Status: Confirmed");
+                if let Some(height) = tx_info.status.block_height {
+                    println!("Block Height: {}", height);
+                }
+                if let Some(ref block_hash) = tx_info.status.block_hash {
+                    println!("Block Hash: {}", block_hash);
+                }
+                if let Some(block_time) = tx_info.status.block_time {
+                    println!("Block Time: {}", block_time);
+                }
+            } else {
+                println!("Status: Unconfirmed");
+            }
 
-                    // Also output as JSON for programmatic use
-                    if let Ok(json) = serde_json::to_string_pretty(&tx_info) {
-                        println!("\nJSON Output:");
-                        println!("{}", json);
+            // Inputs
+            println!("\nInputs ({}):", tx_info.vin.len());
+            for (i, input) in tx_info.vin.iter().enumerate() {
+                println!("  Input {}:", i);
+                println!("    Previous TXID: {}", input.txid);
+                println!("    Vout: {}", input.vout);
+                println!("    Sequence: {}", input.sequence);
+                if let Some(ref prevout) = input.prevout {
+                    if let Some(ref addr) = prevout.scriptpubkey_address {
+                        println!("    Address: {}", addr);
                     }
+                    println!("    Amount: {} satoshis", prevout.value);
                 }
-                Err(e) => {
-                    eprintln!("Error parsing JSON response: {}", e);
-                    process::exit(1);
+            }
+
+            // Outputs
+            println!("\nOutputs ({}):", tx_info.vout.len());
+            for (i, output) in tx_info.vout.iter().enumerate() {
+                println!("  Output {}:", i);
+                if let Some(ref addr) = output.scriptpubkey_address {
+                    println!("    Address: {}", addr);
                 }
+                println!("    Amount: {} satoshis", output.value);
+                println!("    Script Type: {}", output.scriptpubkey_type);
+            }
+
+            // Also output as JSON for programmatic use
+            if let Ok(json) = serde_json::to_string_pretty(&tx_info) {
+                println!("\nJSON Output:");
+                println!("{}", json);
             }
         }
+        // A txid carries no network, so one from another chain is simply not found here
+        Err(e) if e.is_not_found() => {
+            eprintln!(
+                "Error: transaction {} not found on {}; use --network (or ${}) if it is on another network",
+                txid, network, NETWORK_ENV
+            );
+            process::exit(1);
+        }
+        Err(Error::Http { status, body, .. }) => {
+            eprintln!("Error: HTTP {} - Transaction not found or API error: {}", status, body.trim());
+            process::exit(1);
+        }
         Err(e) => {
             eprintln!("Error fetching transaction data: {}", e);
             process::exit(1);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_network() {
+        assert_eq!(resolve_network(None, None), Ok(BtcNetwork::Bitcoin));
+        assert_eq!(resolve_network(None, Some("signet".to_string())), Ok(BtcNetwork::Signet));
+        assert_eq!(resolve_network(Some("testnet"), Some("regtest".to_string())), Ok(BtcNetwork::Testnet));
+        assert!(resolve_network(Some("litecoin"), None).is_err());
+    }
+}
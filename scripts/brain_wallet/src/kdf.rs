@@ -0,0 +1,231 @@
+use argon2::{Algorithm, Argon2, Version};
+use bitcoin::hashes::{sha256, Hash};
+use pbkdf2::pbkdf2_hmac;
+use sha2::Sha256;
+use std::fmt;
+use zeroize::Zeroizing;
+
+/// PBKDF2-HMAC-SHA256 rounds, OWASP's recommendation for that hash.
+const PBKDF2_ITERATIONS: u64 = 600_000;
+/// scrypt's cost, 2^20, with r = 8 taking 1 GiB of memory.
+const SCRYPT_N: u64 = 1 << 20;
+const SCRYPT_R: u64 = 8;
+const SCRYPT_P: u64 = 1;
+/// Argon2id's memory in KiB, 512 MiB, and its passes over it.
+const ARGON2_M: u64 = 512 * 1024;
+const ARGON2_T: u64 = 3;
+const ARGON2_P: u64 = 1;
+
+/// How the passphrase becomes the 32-byte secret key. SHA-256 is the legacy brain wallet, whose
+/// keys have been swept for every phrase anyone thought of; the others are slow and salted, so a
+/// guess costs seconds and a gigabyte or so of memory, and has to be made for each salt.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kdf {
+    Sha256,
+    Pbkdf2 { iterations: u64 },
+    Scrypt { n: u64, r: u64, p: u64 },
+    Argon2id { m: u64, t: u64, p: u64 },
+}
+
+impl Kdf {
+    /// Picks the KDF by name with its default parameters, then applies the `key=value,...`
+    /// overrides of `--kdf-params`, and checks the result.
+    pub fn new(name: &str, params: Option<&str>) -> Result<Kdf, String> {
+        let mut kdf = match name {
+            "sha256" => Kdf::Sha256,
+            "pbkdf2" => Kdf::Pbkdf2 { iterations: PBKDF2_ITERATIONS },
+            "scrypt" => Kdf::Scrypt { n: SCRYPT_N, r: SCRYPT_R, p: SCRYPT_P },
+            "argon2id" => Kdf::Argon2id { m: ARGON2_M, t: ARGON2_T, p: ARGON2_P },
+            _ => return Err(format!("Unknown KDF {:?}; use sha256, pbkdf2, scrypt or argon2id", name)),
+        };
+
+        for pair in params.into_iter().flat_map(|params| params.split(',')) {
+            let (key, value) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("--kdf-params takes key=value pairs, not {:?}", pair))?;
+            let value: u64 = value
+                .parse()
+                .map_err(|_| format!("--kdf-params: {} must be a whole number, not {:?}", key, value))?;
+            let slot = match (&mut kdf, key) {
+                (Kdf::Pbkdf2 { iterations }, "iterations") => iterations,
+                (Kdf::Scrypt { n, .. }, "n") => n,
+                (Kdf::Scrypt { r, .. }, "r") => r,
+                (Kdf::Scrypt { p, .. }, "p") => p,
+                (Kdf::Argon2id { m, .. }, "m") => m,
+                (Kdf::Argon2id { t, .. }, "t") => t,
+                (Kdf::Argon2id { p, .. }, "p") => p,
+                _ => return Err(format!("{} has no parameter {:?}; it takes {}", name, key, kdf.keys())),
+            };
+            *slot = value;
+        }
+
+        kdf.check()?;
+        Ok(kdf)
+    }
+
+    /// The parameters `--kdf-params` takes for this KDF.
+    fn keys(&self) -> &'static str {
+        match self {
+            Kdf::Sha256 => "none",
+            Kdf::Pbkdf2 { .. } => "iterations",
+            Kdf::Scrypt { .. } => "n, r and p",
+            Kdf::Argon2id { .. } => "m (KiB), t and p",
+        }
+    }
+
+    /// Checks the parameters are ones the KDF can run with, before any passphrase is stretched.
+    fn check(&self) -> Result<(), String> {
+        match *self {
+            Kdf::Sha256 => Ok(()),
+            Kdf::Pbkdf2 { iterations } => match u32::try_from(iterations) {
+                Ok(1..) => Ok(()),
+                _ => Err(format!("pbkdf2: iterations must be from 1 to {}", u32::MAX)),
+            },
+            Kdf::Scrypt { n, r, p } => scrypt_params(n, r, p).map(drop),
+            Kdf::Argon2id { m, t, p } => argon2_params(m, t, p).map(drop),
+        }
+    }
+
+    /// Derives the 32-byte secret from the passphrase. The legacy SHA-256 takes no salt; the
+    /// others need a non-empty one, as an unsalted slow hash can still be attacked once for all.
+    pub fn derive(&self, passphrase: &str, salt: Option<&str>) -> Result<Zeroizing<[u8; 32]>, String> {
+        let mut secret = Zeroizing::new([0u8; 32]);
+        match (*self, salt) {
+            (Kdf::Sha256, None) => *secret = sha256::Hash::hash(passphrase.as_bytes()).to_byte_array(),
+            (Kdf::Sha256, Some(_)) => return Err("sha256 takes no salt; pick a stronger --kdf to use one".to_string()),
+            (_, None | Some("")) => return Err(format!("{} needs a non-empty --salt", self.name())),
+            (Kdf::Pbkdf2 { iterations }, Some(salt)) => {
+                // Checked by Kdf::new to fit
+                pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt.as_bytes(), iterations as u32, secret.as_mut_slice());
+            }
+            (Kdf::Scrypt { n, r, p }, Some(salt)) => {
+                scrypt::scrypt(passphrase.as_bytes(), salt.as_bytes(), &scrypt_params(n, r, p)?, secret.as_mut_slice())
+                    .map_err(|e| format!("scrypt: {}", e))?;
+            }
+            (Kdf::Argon2id { m, t, p }, Some(salt)) => {
+                Argon2::new(Algorithm::Argon2id, Version::V0x13, argon2_params(m, t, p)?)
+                    .hash_password_into(passphrase.as_bytes(), salt.as_bytes(), secret.as_mut_slice())
+                    .map_err(|e| format!("argon2id: {}", e))?;
+            }
+        }
+        Ok(secret)
+    }
+
+    /// The name `--kdf` takes.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kdf::Sha256 => "sha256",
+            Kdf::Pbkdf2 { .. } => "pbkdf2",
+            Kdf::Scrypt { .. } => "scrypt",
+            Kdf::Argon2id { .. } => "argon2id",
+        }
+    }
+}
+
+/// The name and parameters, written as `--kdf` and `--kdf-params` take them, so the wallet can be
+/// made again: `scrypt n=1048576,r=8,p=1`.
+impl fmt::Display for Kdf {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Kdf::Sha256 => write!(f, "sha256"),
+            Kdf::Pbkdf2 { iterations } => write!(f, "pbkdf2 iterations={}", iterations),
+            Kdf::Scrypt { n, r, p } => write!(f, "scrypt n={},r={},p={}", n, r, p),
+            Kdf::Argon2id { m, t, p } => write!(f, "argon2id m={},t={},p={}", m, t, p),
+        }
+    }
+}
+
+/// scrypt's parameters with a 32-byte output; N must be a power of two.
+fn scrypt_params(n: u64, r: u64, p: u64) -> Result<scrypt::Params, String> {
+    if n < 2 || !n.is_power_of_two() {
+        return Err("scrypt: n must be a power of two, at least 2".to_string());
+    }
+    let (r, p) = match (u32::try_from(r), u32::try_from(p)) {
+        (Ok(r), Ok(p)) => (r, p),
+        _ => return Err("scrypt: r and p are too large".to_string()),
+    };
+    scrypt::Params::new(n.trailing_zeros() as u8, r, p, 32).map_err(|e| format!("scrypt: {}", e))
+}
+
+/// Argon2's parameters with a 32-byte output: m KiB of memory, at least 8 per lane, t passes and
+/// p lanes.
+fn argon2_params(m: u64, t: u64, p: u64) -> Result<argon2::Params, String> {
+    match (u32::try_from(m), u32::try_from(t), u32::try_from(p)) {
+        (Ok(m), Ok(t), Ok(p)) => argon2::Params::new(m, t, p, Some(32)).map_err(|e| format!("argon2id: {}", e)),
+        _ => Err("argon2id: m, t and p are too large".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSPHRASE: &str = "correct horse battery staple";
+    const SALT: &str = "satoshi@example.com";
+
+    fn hex(secret: &[u8]) -> String {
+        secret.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn test_derive() {
+        // Checked against Python's hashlib and the cryptography package, with parameters cut down
+        // so the tests run quickly
+        let cases = [
+            ("sha256", None, None, "c4bbcb1fbec99d65bf59d85c8cb62ee2db963f0fe106f483d9afa73bd4e39a8a"),
+            ("pbkdf2", Some("iterations=1000"), Some(SALT), "0cc05071905622d59a77a88b6892e5af0cc3d86c391e561b68b68f685f109125"),
+            ("scrypt", Some("n=1024,r=8,p=1"), Some(SALT), "befdd972d8cebe64a669ca5e52102e039641e0bd895c5426923154e650e204e6"),
+            ("argon2id", Some("m=256,t=2,p=1"), Some(SALT), "696c652fab2737184ca181b9bee2609c583c24aee905c9704f54f590deef9bdb"),
+        ];
+        for (name, params, salt, expected) in cases {
+            let kdf = Kdf::new(name, params).unwrap();
+            assert_eq!(hex(kdf.derive(PASSPHRASE, salt).unwrap().as_slice()), expected, "{}", kdf);
+            assert_eq!(hex(kdf.derive(PASSPHRASE, salt).unwrap().as_slice()), expected, "{}", kdf);
+        }
+
+        // The salt is part of the key
+        let kdf = Kdf::new("scrypt", Some("n=1024")).unwrap();
+        assert_ne!(kdf.derive(PASSPHRASE, Some(SALT)).unwrap(), kdf.derive(PASSPHRASE, Some("other salt")).unwrap());
+
+        // Only sha256 goes without a salt, and it takes none
+        for kdf in [Kdf::new("pbkdf2", Some("iterations=1")).unwrap(), kdf] {
+            assert!(kdf.derive(PASSPHRASE, None).is_err());
+            assert!(kdf.derive(PASSPHRASE, Some("")).is_err());
+        }
+        assert!(Kdf::Sha256.derive(PASSPHRASE, Some(SALT)).is_err());
+    }
+
+    #[test]
+    fn test_params() {
+        // The defaults, printed as --kdf-params takes them
+        let default = |name| Kdf::new(name, None).unwrap().to_string();
+        assert_eq!(default("sha256"), "sha256");
+        assert_eq!(default("pbkdf2"), "pbkdf2 iterations=600000");
+        assert_eq!(default("scrypt"), "scrypt n=1048576,r=8,p=1");
+        assert_eq!(default("argon2id"), "argon2id m=524288,t=3,p=1");
+
+        // Overrides keep the other defaults, and what is printed reads back the same
+        let kdf = Kdf::new("argon2id", Some("t=4,p=2")).unwrap();
+        assert_eq!(kdf, Kdf::Argon2id { m: 524288, t: 4, p: 2 });
+        let (name, params) = kdf.to_string().split_once(' ').map(|(a, b)| (a.to_string(), b.to_string())).unwrap();
+        assert_eq!(Kdf::new(&name, Some(&params)).unwrap(), kdf);
+        assert_eq!(Kdf::new("scrypt", Some("p=2")).unwrap(), Kdf::Scrypt { n: 1 << 20, r: 8, p: 2 });
+
+        for (name, params) in [
+            ("md5", None),
+            ("sha256", Some("iterations=2")),
+            ("pbkdf2", Some("iterations=0")),
+            ("pbkdf2", Some("n=1024")),
+            ("pbkdf2", Some("iterations")),
+            ("pbkdf2", Some("iterations=lots")),
+            ("scrypt", Some("n=1000")),
+            ("scrypt", Some("n=1")),
+            ("scrypt", Some("r=0")),
+            ("argon2id", Some("m=4")),
+            ("argon2id", Some("t=0")),
+            ("argon2id", Some("m=5000000000")),
+        ] {
+            assert!(Kdf::new(name, params).is_err(), "{} {:?}", name, params);
+        }
+    }
+}
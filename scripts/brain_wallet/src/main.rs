@@ -1,96 +1,335 @@
-use sha2::{Digest, Sha256};
-use ripemd160::Ripemd160;
-use base58::ToBase58;
-use secp256k1::{Secp256k1, SecretKey, PublicKey};
+mod kdf;
+
+use bitcoin::secp256k1::SecretKey;
+use bitcoin::{Network, PrivateKey};
+use btcx_lib::types::{AddressType, BtcNetwork, KeyPair};
+use kdf::Kdf;
 use std::env;
 use std::process;
+use std::str::FromStr;
+
+// Environment variable giving the network when --network is not passed
+const NETWORK_ENV: &str = "BTCX_NETWORK";
+
+/// A brain wallet's key and address, with what is needed to make them again: the KDF and its
+/// salt, the address type, whether the public key is compressed and the network.
+struct BrainWallet {
+    kdf: Kdf,
+    salt: Option<String>,
+    wif: String,
+    address: String,
+    address_type: AddressType,
+    compressed: bool,
+    network: BtcNetwork,
+}
+
+/// The command line, checked.
+struct Options {
+    kdf: Kdf,
+    salt: Option<String>,
+    address_type: AddressType,
+    compressed: bool,
+    network_flag: Option<String>,
+    passphrase: String,
+}
 
 /// Generates a Bitcoin brain wallet from a passphrase, returning the WIF private key and address.
 ///
 /// # Arguments
 /// * `passphrase` - A string slice containing the passphrase.
+/// * `kdf` - How the passphrase becomes the secret key: the legacy SHA-256, or a salted slow KDF.
+/// * `salt` - The salt of a slow KDF; SHA-256 takes none.
+/// * `address_type` - The address to make from the key; p2tr uses the BIP86 tweak of its x-only half.
+/// * `compressed` - Whether the public key is compressed, as every modern wallet's is. Only
+///   p2pkh can take an uncompressed one, for wallets made before compressed keys were used.
+/// * `network` - The network whose version bytes, or bech32 prefix, the WIF and address take.
 ///
 /// # Returns
-/// A tuple containing:
-/// - WIF private key as a `String`
-/// - Bitcoin address as a `String`
+/// The WIF private key, with the 0x01 suffix when compressed, and the address.
 ///
-/// # Panics
-/// Panics if the private key is invalid (extremely unlikely with a 32-byte SHA-256 output).
-fn brain_wallet(passphrase: &str) -> (String, String) {
-    // Step 1: Generate private key from passphrase using SHA-256
-    let mut hasher = Sha256::new();
-    hasher.update(passphrase.as_bytes());
-    let private_key_bytes = hasher.finalize();
-    let private_key = SecretKey::from_slice(&private_key_bytes).expect("Invalid private key");
-
-    // Step 2: Create WIF private key
-    // Prepend 0x80 (mainnet private key version byte for uncompressed keys)
-    let mut extended_private_key = vec![0x80];
-    extended_private_key.extend_from_slice(&private_key_bytes);
-    // Compute checksum: double SHA-256 and take first 4 bytes
-    let mut hasher = Sha256::new();
-    hasher.update(&extended_private_key);
-    let hash1 = hasher.finalize();
-    let mut hasher = Sha256::new();
-    hasher.update(&hash1);
-    let hash2 = hasher.finalize();
-    let checksum = &hash2[0..4];
-    // Append checksum to extended private key
-    extended_private_key.extend_from_slice(checksum);
-    // Encode to Base58 to get WIF
-    let wif = extended_private_key.to_base58();
-
-    // Step 3: Generate public key (uncompressed)
-    let secp = Secp256k1::new();
-    let public_key = PublicKey::from_secret_key(&secp, &private_key);
-    let public_key_uncompressed = public_key.serialize_uncompressed(); // 65 bytes, starts with 0x04
-
-    // Step 4: Create Bitcoin address
-    // SHA-256 of public key
-    let mut hasher = Sha256::new();
-    hasher.update(&public_key_uncompressed);
-    let hash1 = hasher.finalize();
-    // RIPEMD-160 of the SHA-256 hash
-    let mut hasher = Ripemd160::new();
-    hasher.update(&hash1);
-    let hash2 = hasher.finalize(); // 20 bytes
-    // Prepend 0x00 (mainnet address version byte)
-    let mut address_bytes = vec![0x00];
-    address_bytes.extend_from_slice(&hash2);
-    // Compute checksum: double SHA-256 and take first 4 bytes
-    let mut hasher = Sha256::new();
-    hasher.update(&address_bytes);
-    let hash3 = hasher.finalize();
-    let mut hasher = Sha256::new();
-    hasher.update(&hash3);
-    let hash4 = hasher.finalize();
-    let checksum = &hash4[0..4];
-    // Append checksum
-    address_bytes.extend_from_slice(checksum);
-    // Encode to Base58 to get address
-    let address = address_bytes.to_base58();
-
-    (wif, address)
+/// # Errors
+/// Fails for a segwit or taproot address from an uncompressed key, for a salt the KDF cannot
+/// take, or if the KDF output is not a valid private key (extremely unlikely).
+fn brain_wallet(
+    passphrase: &str,
+    kdf: Kdf,
+    salt: Option<&str>,
+    address_type: AddressType,
+    compressed: bool,
+    network: BtcNetwork,
+) -> Result<BrainWallet, String> {
+    if !compressed && address_type != AddressType::P2pkh {
+        return Err(format!("{} addresses need a compressed public key; leave out --uncompressed", address_type));
+    }
+
+    // Step 1: Generate private key from passphrase using the KDF
+    let private_key_bytes = kdf.derive(passphrase, salt)?;
+    let private_key = SecretKey::from_slice(private_key_bytes.as_slice()).map_err(|e| format!("Invalid private key: {}", e))?;
+
+    // Step 2: Create WIF private key, whose version byte is the network's (0x80 mainnet, 0xef
+    // the test networks), with the 0x01 suffix for a compressed key
+    let private_key = if compressed {
+        PrivateKey::new(private_key, Network::from(network))
+    } else {
+        PrivateKey::new_uncompressed(private_key, Network::from(network))
+    };
+    let wif = private_key.to_wif();
+
+    // Step 3: Generate public key, 33 bytes compressed or 65 uncompressed, and the address: p2pkh
+    // hashes the key in the form the WIF says, with the network's version byte (0x00 mainnet,
+    // 0x6f the test networks), and segwit takes its bech32 prefix (bc, tb or bcrt)
+    let key_pair = KeyPair::from_private_key(private_key, network);
+    let address = key_pair
+        .address(&address_type)
+        .map_err(|e| format!("Cannot make a {} address: {}", address_type, e))?
+        .to_string();
+
+    Ok(BrainWallet { kdf, salt: salt.map(str::to_string), wif, address, address_type, compressed, network })
+}
+
+/// Parses the options, then takes every remaining argument as a word of the passphrase. `--`
+/// ends the options, for a passphrase whose first word starts with `--`.
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut address_type = AddressType::P2pkh;
+    let mut compressed = true;
+    let mut network_flag: Option<String> = None;
+    let mut kdf_name = "sha256";
+    let mut kdf_params: Option<&str> = None;
+    let mut salt: Option<String> = None;
+    let mut rest = args.iter().skip(1);
+    let mut words: Vec<&str> = Vec::new();
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--compressed" => compressed = true,
+            "--uncompressed" => compressed = false,
+            "--address-type" => {
+                let value = rest.next().ok_or("--address-type requires a value")?;
+                address_type = value
+                    .parse()
+                    .map_err(|_| "--address-type must be p2pkh, p2sh-p2wpkh, p2wpkh or p2tr".to_string())?;
+            }
+            "--kdf" => {
+                kdf_name = rest.next().ok_or("--kdf requires a value")?;
+            }
+            "--kdf-params" => {
+                kdf_params = Some(rest.next().ok_or("--kdf-params requires a value")?);
+            }
+            "--salt" => {
+                salt = Some(rest.next().ok_or("--salt requires a value")?.clone());
+            }
+            "--network" => {
+                network_flag = Some(rest.next().ok_or("--network requires a value")?.clone());
+            }
+            "--" => {
+                words.extend(rest.by_ref().map(String::as_str));
+            }
+            _ => {
+                words.push(arg);
+                words.extend(rest.by_ref().map(String::as_str));
+            }
+        }
+    }
+
+    // Check if at least one passphrase word is provided
+    if words.is_empty() {
+        return Err("Give the passphrase".to_string());
+    }
+
+    let kdf = Kdf::new(kdf_name, kdf_params)?;
+
+    // Join the words with spaces to form the passphrase
+    Ok(Options { kdf, salt, address_type, compressed, network_flag, passphrase: words.join(" ") })
+}
+
+/// The network from --network, else from the environment, else mainnet.
+fn resolve_network(flag: Option<&str>, env: Option<String>) -> Result<BtcNetwork, String> {
+    match flag.map(str::to_string).or(env) {
+        Some(name) => BtcNetwork::from_str(&name)
+            .map_err(|_| format!("Unknown network {:?}; use mainnet, testnet, signet or regtest", name)),
+        None => Ok(BtcNetwork::Bitcoin),
+    }
 }
 
 fn main() {
     // Collect command-line arguments
     let args: Vec<String> = env::args().collect();
+    let program = args.first().map_or("brain_wallet", String::as_str);
 
-    // Check if at least one passphrase word is provided
-    if args.len() < 2 {
-        eprintln!("Usage: {} <passphrase words...>", args[0]);
-        process::exit(1);
+    let wallet = parse_args(&args).and_then(|options| {
+        let network = resolve_network(options.network_flag.as_deref(), env::var(NETWORK_ENV).ok())?;
+        if options.kdf != Kdf::Sha256 {
+            eprintln!("Stretching the passphrase with {}; this can take a while...", options.kdf);
+        }
+        brain_wallet(
+            &options.passphrase,
+            options.kdf,
+            options.salt.as_deref(),
+            options.address_type,
+            options.compressed,
+            network,
+        )
+    });
+    let wallet = match wallet {
+        Ok(wallet) => wallet,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            eprintln!(
+                "Usage: {} [--kdf <sha256|pbkdf2|scrypt|argon2id>] [--kdf-params <key=value,...>] [--salt <salt>] \
+                 [--address-type <p2pkh|p2sh-p2wpkh|p2wpkh|p2tr>] [--compressed|--uncompressed] \
+                 [--network <mainnet|testnet|signet|regtest>] [--] <passphrase words...>",
+                program
+            );
+            eprintln!("  --kdf: how the passphrase becomes the key (default: sha256, the legacy brain wallet; crackable)");
+            eprintln!("  --kdf-params: pbkdf2 iterations=600000, scrypt n=1048576,r=8,p=1, argon2id m=524288,t=3,p=1 (m in KiB)");
+            eprintln!("  --salt: required, and not empty, for pbkdf2, scrypt and argon2id");
+            eprintln!("  --uncompressed: the legacy uncompressed public key, for p2pkh wallets made before compressed keys");
+            eprintln!("  --network: the network of the WIF and address (default: ${} or mainnet)", NETWORK_ENV);
+            process::exit(1);
+        }
+    };
+
+    // Print the results, with how they were made so the wallet can be recovered later
+    println!("KDF: {}", wallet.kdf);
+    if let Some(salt) = &wallet.salt {
+        println!("Salt: {}", salt);
+    }
+    println!("Address Type: {}", wallet.address_type);
+    println!("Public Key: {}", if wallet.compressed { "compressed" } else { "uncompressed" });
+    println!("Network: {}", wallet.network);
+    println!("WIF Private Key: {}", wallet.wif);
+    println!("Bitcoin Address: {}", wallet.address);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PASSPHRASE: &str = "correct horse battery staple";
+
+    #[test]
+    fn test_compression_and_address_types() {
+        // The well-known uncompressed wallet of this passphrase
+        let legacy = brain_wallet(PASSPHRASE, Kdf::Sha256, None, AddressType::P2pkh, false, BtcNetwork::Bitcoin).unwrap();
+        assert_eq!(legacy.wif, "5KJvsngHeMpm884wtkJNzQGaCErckhHJBGFsvd3VyK5qMZXj3hS");
+        assert_eq!(legacy.address, "1JwSSubhmg6iPtRjtyqhUYYH7bZg3Lfy1T");
+
+        // The same secret with its compressed public key
+        let compressed = brain_wallet(PASSPHRASE, Kdf::Sha256, None, AddressType::P2pkh, true, BtcNetwork::Bitcoin).unwrap();
+        assert_eq!(compressed.wif, "L3p8oAcQTtuokSCRHQ7i4MhjWc9zornvpJLfmg62sYpLRJF9woSu");
+        assert_eq!(compressed.address, "1C7zdTfnkzmr13HfA2vNm5SJYRK6nEKyq8");
+
+        // And the segwit and taproot addresses of that key; the bc1q address holds the same
+        // HASH160, 79fbfc3f…606c, as the compressed p2pkh one
+        let p2wpkh = brain_wallet(PASSPHRASE, Kdf::Sha256, None, AddressType::P2wpkh, true, BtcNetwork::Bitcoin).unwrap();
+        assert_eq!(p2wpkh.wif, compressed.wif);
+        assert_eq!(p2wpkh.address, "bc1q08alc0e5ua69scxhvyma568nvguqccrv4cc9n4");
+        let nested = brain_wallet(PASSPHRASE, Kdf::Sha256, None, AddressType::P2shP2wpkh, true, BtcNetwork::Bitcoin).unwrap();
+        assert!(nested.address.starts_with('3'));
+        let taproot = brain_wallet(PASSPHRASE, Kdf::Sha256, None, AddressType::P2tr, true, BtcNetwork::Bitcoin).unwrap();
+        assert!(taproot.address.starts_with("bc1p"));
+
+        // Only p2pkh takes an uncompressed key
+        for address_type in [AddressType::P2shP2wpkh, AddressType::P2wpkh, AddressType::P2tr] {
+            assert!(brain_wallet(PASSPHRASE, Kdf::Sha256, None, address_type, false, BtcNetwork::Bitcoin).is_err());
+        }
+    }
+
+    #[test]
+    fn test_networks() {
+        // The test networks share version bytes: 0xef for the WIF, 0x6f for p2pkh
+        for network in [BtcNetwork::Testnet, BtcNetwork::Signet, BtcNetwork::Regtest] {
+            let compressed = brain_wallet(PASSPHRASE, Kdf::Sha256, None, AddressType::P2pkh, true, network).unwrap();
+            assert!(compressed.wif.starts_with('c'), "{}", compressed.wif);
+            assert!(compressed.address.starts_with(['m', 'n']), "{}", compressed.address);
+            let legacy = brain_wallet(PASSPHRASE, Kdf::Sha256, None, AddressType::P2pkh, false, network).unwrap();
+            assert!(legacy.wif.starts_with('9'), "{}", legacy.wif);
+            assert!(legacy.address.starts_with(['m', 'n']), "{}", legacy.address);
+        }
+
+        // The same key on each network
+        let testnet = brain_wallet(PASSPHRASE, Kdf::Sha256, None, AddressType::P2pkh, true, BtcNetwork::Testnet).unwrap();
+        let key = |wif: &str| PrivateKey::from_wif(wif).unwrap().inner;
+        assert_eq!(key(&testnet.wif), key("L3p8oAcQTtuokSCRHQ7i4MhjWc9zornvpJLfmg62sYpLRJF9woSu"));
+
+        // And the bech32 prefix of each
+        let segwit = |network| brain_wallet(PASSPHRASE, Kdf::Sha256, None, AddressType::P2wpkh, true, network).unwrap().address;
+        assert!(segwit(BtcNetwork::Testnet).starts_with("tb1q"));
+        assert!(segwit(BtcNetwork::Signet).starts_with("tb1q"));
+        assert!(segwit(BtcNetwork::Regtest).starts_with("bcrt1q"));
+
+        assert_eq!(resolve_network(Some("testnet"), Some("regtest".to_string())), Ok(BtcNetwork::Testnet));
+        assert_eq!(resolve_network(None, Some("signet".to_string())), Ok(BtcNetwork::Signet));
+        assert_eq!(resolve_network(None, None), Ok(BtcNetwork::Bitcoin));
+        assert!(resolve_network(Some("litecoin"), None).is_err());
     }
 
-    // Join all arguments after the program name with spaces to form the passphrase
-    let passphrase = args[1..].join(" ");
+    #[test]
+    fn test_kdfs() {
+        // The same passphrase and salt give the same wallet every time, and each KDF its own
+        let salt = Some("satoshi@example.com");
+        let cases = [
+            ("pbkdf2", "iterations=1000", "0cc05071905622d59a77a88b6892e5af0cc3d86c391e561b68b68f685f109125"),
+            ("scrypt", "n=1024,r=8,p=1", "befdd972d8cebe64a669ca5e52102e039641e0bd895c5426923154e650e204e6"),
+            ("argon2id", "m=256,t=2,p=1", "696c652fab2737184ca181b9bee2609c583c24aee905c9704f54f590deef9bdb"),
+        ];
+        let legacy = brain_wallet(PASSPHRASE, Kdf::Sha256, None, AddressType::P2pkh, true, BtcNetwork::Bitcoin).unwrap();
+        for (name, params, secret) in cases {
+            let kdf = Kdf::new(name, Some(params)).unwrap();
+            let wallet = brain_wallet(PASSPHRASE, kdf, salt, AddressType::P2pkh, true, BtcNetwork::Bitcoin).unwrap();
+            let again = brain_wallet(PASSPHRASE, kdf, salt, AddressType::P2pkh, true, BtcNetwork::Bitcoin).unwrap();
+            assert_eq!(wallet.wif, again.wif);
+            assert_eq!(wallet.address, again.address);
+            assert_ne!(wallet.address, legacy.address);
+
+            let key = PrivateKey::from_wif(&wallet.wif).unwrap();
+            let hex: String = key.inner.secret_bytes().iter().map(|byte| format!("{:02x}", byte)).collect();
+            assert_eq!(hex, secret, "{}", kdf);
+            assert_eq!(wallet.kdf.to_string(), format!("{} {}", name, params));
+            assert_eq!(wallet.salt.as_deref(), salt);
+
+            assert!(brain_wallet(PASSPHRASE, kdf, None, AddressType::P2pkh, true, BtcNetwork::Bitcoin).is_err());
+            assert!(brain_wallet(PASSPHRASE, kdf, Some(""), AddressType::P2pkh, true, BtcNetwork::Bitcoin).is_err());
+        }
+    }
 
-    // Generate WIF private key and Bitcoin address
-    let (wif, bitcoin_address) = brain_wallet(&passphrase);
+    #[test]
+    fn test_parse_args() {
+        let args = |line: &str| line.split(' ').map(String::from).collect::<Vec<String>>();
+        let options = parse_args(&args("brain_wallet correct horse battery staple")).unwrap();
+        assert_eq!(options.passphrase, PASSPHRASE);
+        assert!(options.compressed);
+        assert_eq!(options.address_type, AddressType::P2pkh);
+        assert_eq!(options.kdf, Kdf::Sha256);
+        assert_eq!(options.salt, None);
 
-    // Print the results
-    println!("WIF Private Key: {}", wif);
-    println!("Bitcoin Address: {}", bitcoin_address);
-}
\ No newline at end of file
+        let options = parse_args(&args("brain_wallet --kdf scrypt --kdf-params n=1024 --salt pepper correct horse")).unwrap();
+        assert_eq!(options.kdf, Kdf::Scrypt { n: 1024, r: 8, p: 1 });
+        assert_eq!(options.salt.as_deref(), Some("pepper"));
+        assert_eq!(options.passphrase, "correct horse");
+
+        let options = parse_args(&args("brain_wallet --uncompressed --address-type p2wpkh --network testnet -- --correct horse")).unwrap();
+        assert!(!options.compressed);
+        assert_eq!(options.network_flag.as_deref(), Some("testnet"));
+        assert_eq!(options.address_type, AddressType::P2wpkh);
+        assert_eq!(options.passphrase, "--correct horse");
+
+        // Options end at the first word, which keeps the rest of the passphrase as given
+        let options = parse_args(&args("brain_wallet correct --uncompressed")).unwrap();
+        assert!(options.compressed);
+        assert_eq!(options.passphrase, "correct --uncompressed");
+
+        for line in [
+            "brain_wallet",
+            "brain_wallet --compressed",
+            "brain_wallet --address-type p2wsh words",
+            "brain_wallet --address-type",
+            "brain_wallet --network",
+            "brain_wallet --kdf scrypt2 words",
+            "brain_wallet --kdf-params n=1024 words",
+            "brain_wallet --kdf pbkdf2 --kdf-params rounds=5 words",
+            "brain_wallet --salt",
+        ] {
+            assert!(parse_args(&args(line)).is_err(), "{}", line);
+        }
+    }
+}
@@ -1,48 +1,99 @@
-use reqwest::blocking::Client;
+use btcx_lib::network::{BlockstreamClient, BroadcastRejection};
+use btcx_lib::{BtcNetwork, Error};
 use std::env;
 use std::process;
 
-fn main() {
+fn print_usage(program: &str) {
+    eprintln!("Usage: {} [--esplora-url <url>] [--esplora-token <token>] <signed_transaction_hex>", program);
+    eprintln!("  --esplora-url: Esplora base URL (default: $BTCX_ESPLORA_MAINNET_URL or blockstream.info)");
+    eprintln!("  --esplora-token: bearer token for the Esplora server (default: $BTCX_ESPLORA_TOKEN)");
+}
+
+#[tokio::main]
+async fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 2 {
-        eprintln!("Usage: {} <signed_transaction_hex>", args[0]);
+    let mut esplora_url: Option<String> = None;
+    let mut esplora_token: Option<String> = None;
+    let mut positional: Vec<&String> = Vec::new();
+
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--esplora-url" | "--esplora-token" => {
+                let Some(value) = rest.next() else {
+                    eprintln!("Error: {} requires a value", arg);
+                    process::exit(1);
+                };
+                if arg == "--esplora-url" {
+                    esplora_url = Some(value.clone());
+                } else {
+                    esplora_token = Some(value.clone());
+                }
+            }
+            flag if flag.starts_with("--") => {
+                print_usage(&args[0]);
+                process::exit(1);
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    if positional.len() != 1 {
+        print_usage(&args[0]);
         process::exit(1);
     }
 
-    let tx_hex = args[1].trim();
+    let tx_hex = positional[0].trim();
     if tx_hex.is_empty() {
         eprintln!("Error: transaction hex cannot be empty");
         process::exit(1);
     }
 
-    let client = Client::new();
-    let url = "https://blockstream.info/api/tx";
-
-    match client.post(url).body(tx_hex.to_string()).send() {
-        Ok(response) => {
-            if !response.status().is_success() {
-                eprintln!(
-                    "Error: HTTP {} - Failed to broadcast transaction: {}",
-                    response.status(),
-                    response.text().unwrap_or_else(|_| "unknown error".to_string())
-                );
-                process::exit(1);
-            }
+    let mut builder = BlockstreamClient::builder(BtcNetwork::Bitcoin);
+    if let Some(url) = &esplora_url {
+        builder = builder.base_url(url);
+    }
+    if let Some(token) = &esplora_token {
+        builder = builder.bearer_token(token);
+    }
+    let client = match builder.build() {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Error creating client: {}", e);
+            process::exit(1);
+        }
+    };
 
-            match response.text() {
-                Ok(txid) => {
-                    println!("Transaction broadcasted successfully!");
-                    println!("Transaction ID (txid): {}", txid);
+    match client.broadcast_transaction(tx_hex).await {
+        Ok(txid) => {
+            println!("Transaction broadcasted successfully!");
+            println!("Transaction ID (txid): {}", txid);
+        }
+        Err(e) => {
+            match BroadcastRejection::from_error(&e) {
+                Some(BroadcastRejection::InsufficientFee) => {
+                    eprintln!("Error: transaction rejected for insufficient fee");
+                    eprintln!("Raise the fee rate (or bump it with RBF) and try again.");
                 }
-                Err(e) => {
-                    eprintln!("Error reading response: {}", e);
-                    process::exit(1);
+                Some(BroadcastRejection::MissingInputs) => {
+                    eprintln!("Error: transaction rejected because inputs are missing or already spent");
+                    eprintln!("Check that every input's txid:vout exists and is unspent.");
+                }
+                Some(BroadcastRejection::AlreadyKnown) => {
+                    eprintln!("Transaction is already in the mempool or the chain.");
+                    return;
                 }
+                Some(BroadcastRejection::Other) | None => {}
+            }
+
+            match &e {
+                Error::Http { status, rpc_error: Some(rpc_error), .. } => eprintln!(
+                    "Error: HTTP {} - Failed to broadcast transaction: {} (RPC code {})",
+                    status, rpc_error.message, rpc_error.code
+                ),
+                _ => eprintln!("Error broadcasting transaction: {}", e),
             }
-        }
-        Err(e) => {
-            eprintln!("Error broadcasting transaction: {}", e);
             process::exit(1);
         }
     }
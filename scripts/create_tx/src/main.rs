@@ -1,16 +1,47 @@
-use bitcoin::consensus::encode::serialize;
-use bitcoin::util::amount::Amount;
-use bitcoin::{Address, Network, OutPoint, PackedLockTime, Sequence, Transaction, TxIn, TxOut, Txid};
-use hex;
-use serde::Deserialize;
+use bitcoin::absolute::LockTime;
+use bitcoin::amount::Denomination;
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::hex::FromHex;
+use bitcoin::script::PushBytesBuf;
+use bitcoin::transaction::{predict_weight, InputWeightPrediction, Version};
+use bitcoin::{Amount, OutPoint, Script, ScriptBuf, Sequence, Transaction, TxIn, TxOut, Txid, Witness};
+use btcx_lib::network::{BlockstreamClient, Utxo as EsploraUtxo};
+use btcx_lib::psbt::{build_psbt, PsbtInputData};
+use btcx_lib::transaction_builder::{BuildReport, TransactionBuilder};
+use btcx_lib::types::{BtcNetwork, CoinSelectionStrategy, TxBuilderConfig, Utxo};
+use btcx_lib::utils::{parse_address, parse_amount, parse_transaction_hex, InputType, INPUT_TYPES};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
 use std::io::{self, Read};
 use std::str::FromStr;
 
+// Environment variable giving the network when --network is not passed
+const NETWORK_ENV: &str = "BTCX_NETWORK";
+
+// Leftover sats above this go to a change output, or are refused without a change address; the
+// P2PKH dust threshold, the highest of the standard output types
+const DUST_LIMIT: Amount = Amount::from_sat(546);
+
+// Largest OP_RETURN payload relayed by default
+const MAX_DATA_LEN: usize = 80;
+
+// Assumed for size estimates when nothing gives an input's type: the largest single-key input
+const WORST_CASE_INPUT: InputType = InputType::P2pkh;
+
 // Struct to represent an input in the transaction request
-#[derive(Deserialize)]
+#[derive(Deserialize, Default)]
 struct TxInputRequest {
     txid: String,  // Transaction ID as a string
     vout: u32,     // Output index
+    #[serde(default, deserialize_with = "amount_sats")]
+    amount: Option<u64>,  // Value of the spent output in satoshis, to work out the fee and change
+    #[serde(rename = "type")]
+    input_type: Option<String>,  // What the spent output is (p2wpkh, p2tr, ...), to estimate the signed size
+    sequence: Option<u32>,  // nSequence override, e.g. a relative lock time for CSV
+    prev_tx_hex: Option<String>,  // Transaction holding the spent output, for the PSBT's non_witness_utxo
+    script_pubkey: Option<String>,  // Hex locking script of the spent output; with amount, a segwit input's witness_utxo
+    address: Option<String>,  // Address of the spent output, telling its type for size estimates
 }
 
 // Struct to represent the complete transaction request
@@ -18,19 +49,643 @@ struct TxInputRequest {
 struct CreateTxRequest {
     inputs: Vec<TxInputRequest>,    // List of inputs
     outputs: Vec<TxOutputRequest>,  // List of outputs
+    fee_rate: Option<f64>,  // Fee rate in sat/vB, or
+    #[serde(default, deserialize_with = "amount_sats")]
+    fee: Option<u64>,       // an absolute fee in satoshis, when the inputs have amounts
+    change_address: Option<String>,  // Where the inputs' remainder goes, if above dust
+    #[serde(default)]
+    rbf: bool,                // Signal replace-by-fee: sequences default to 0xfffffffd
+    locktime: Option<u32>,    // nLockTime, a block height below 500000000 and a unix time from it
+    version: Option<i32>,     // Transaction version, 1 (default) or 2
+    sweep_to: Option<String>,  // Send everything the inputs hold, less the fee, to this address alone
+}
+
+// Request for coins to be chosen from UTXOs, as fetch_utxos prints them
+#[derive(Deserialize)]
+struct SelectRequest {
+    utxos: Vec<EsploraUtxo>,  // fetch_utxos or Esplora /address/{address}/utxo output; extra fields are ignored
+    utxo_address: Option<String>,  // Address the UTXOs were fetched for, since Esplora does not repeat it
+    outputs: Vec<TxOutputRequest>,  // List of outputs
+    fee_rate: Option<f64>,  // Fee rate in sat/vB, here or from --fee-rate
+    change_address: Option<String>,  // Where the change goes; needed unless the selection is changeless
+    strategy: Option<String>,  // smallest_first, largest_first, random or branch_and_bound (default)
+}
+
+// Just enough of a request to tell its shape
+#[derive(Deserialize)]
+struct RequestShape {
+    utxos: Option<IgnoredAny>,
+}
+
+// Fee and change worked out from the input amounts
+#[derive(Debug, PartialEq)]
+struct FeeSummary {
+    fee: u64,             // Satoshis left to miners, including change too small to keep
+    change: Option<u64>,  // Value of the change output, when one was added
+    vsize: u64,           // Estimated virtual size once signed
+}
+
+impl std::fmt::Display for FeeSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Fee: {} sats ({:.2} sat/vB), change: {}, vsize: {} vB",
+            self.fee,
+            self.fee as f64 / self.vsize as f64,
+            self.change.map_or("none".to_string(), |change| format!("{} sats", change)),
+            self.vsize
+        )
+    }
+}
+
+// Projected size and fee of the signed transaction, for --verbose and --json
+#[derive(Serialize, Debug)]
+struct SizeReport {
+    vsize: u64,   // Estimated virtual size once signed
+    weight: u64,  // Estimated weight units once signed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    fee_rate: Option<f64>,  // Rate the projected fee is worked out at, in sat/vB
+    #[serde(skip_serializing_if = "Option::is_none")]
+    projected_fee: Option<u64>,  // Fee at that rate for the estimated vsize
+    inputs: Vec<InputSize>,
+    outputs: Vec<OutputMargin>,
+}
+
+#[derive(Serialize, Debug)]
+struct InputSize {
+    index: usize,
+    #[serde(rename = "type")]
+    input_type: String,  // The type the estimate counts the input as
+    assumed: bool,       // True when nothing gave the type and the worst case was used
+}
+
+#[derive(Serialize, Debug)]
+struct OutputMargin {
+    index: usize,
+    value: u64,
+    dust_limit: Option<u64>,   // Smallest value relayed for the output's script; None for OP_RETURN
+    dust_margin: Option<i64>,  // Satoshis above the dust limit, negative when below
 }
 
 // Struct to represent an output in the transaction request
 #[derive(Deserialize)]
 struct TxOutputRequest {
-    address: String,  // Bitcoin address
-    amount: u64,      // Amount in satoshis
+    address: Option<String>,  // Bitcoin address
+    #[serde(default, deserialize_with = "amount_sats")]
+    amount: Option<u64>,      // Amount in satoshis
+    data: Option<String>,     // Hex payload of a zero-value OP_RETURN output, in place of address and amount
+    script_hex: Option<String>,  // Raw hex scriptPubKey paid in place of an address, on any network
+}
+
+// Command-line switches that change what create_transaction accepts
+#[derive(Default)]
+struct CreateOptions {
+    allow_multiple_data: bool,  // Accept more than one OP_RETURN output, which is nonstandard
+    allow_nonstandard: bool,    // Accept script_hex outputs that match no standard template
+}
+
+// Amounts are satoshis as JSON numbers, or strings with a unit such as "0.015 BTC" or "1_500_000 sat"
+fn amount_sats<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u64>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Written {
+        Sats(u64),
+        Text(String),
+    }
+    match Option::<Written>::deserialize(deserializer)? {
+        Some(Written::Sats(sats)) => Ok(Some(sats)),
+        Some(Written::Text(text)) => parse_amount(&text).map(|amount| Some(amount.to_sat())).map_err(serde::de::Error::custom),
+        None => Ok(None),
+    }
+}
+
+// Every amount in the request in sats and BTC, for --verbose
+fn describe_amounts(inputs: &[TxInputRequest], outputs: &[TxOutputRequest], fee: Option<u64>) -> Vec<String> {
+    let both = |sats: u64| {
+        format!("{} sats ({})", sats, Amount::from_sat(sats).display_in(Denomination::Bitcoin).show_denomination())
+    };
+    let inputs = inputs.iter().enumerate().filter_map(|(index, input)| {
+        input.amount.map(|sats| format!("Input {} amount: {}", index, both(sats)))
+    });
+    let outputs = outputs.iter().enumerate().filter_map(|(index, output)| {
+        output.amount.map(|sats| format!("Output {} amount: {}", index, both(sats)))
+    });
+    inputs.chain(outputs).chain(fee.map(|sats| format!("Fee: {}", both(sats)))).collect()
+}
+
+// The network from --network, else from the environment, else mainnet
+fn resolve_network(flag: Option<&str>, env: Option<String>) -> Result<BtcNetwork, String> {
+    match flag.map(str::to_string).or(env) {
+        Some(name) => BtcNetwork::from_str(&name)
+            .map_err(|_| format!("Unknown network {:?}; use mainnet, testnet, signet or regtest", name)),
+        None => Ok(BtcNetwork::Bitcoin),
+    }
+}
+
+// What an input spending `script_pubkey` is, for size estimates; P2SH is taken to wrap P2WPKH
+fn script_input_type(script_pubkey: &ScriptBuf) -> Option<InputType> {
+    if script_pubkey.is_p2wpkh() {
+        Some(InputType::P2wpkh)
+    } else if script_pubkey.is_p2pkh() {
+        Some(InputType::P2pkh)
+    } else if script_pubkey.is_p2tr() {
+        Some(InputType::P2tr)
+    } else if script_pubkey.is_p2sh() {
+        Some(InputType::P2shP2wpkh)
+    } else {
+        None
+    }
+}
+
+// An input's type from its `type`, else from its address or the output it spends; None when
+// nothing says
+fn input_type_hint(
+    index: usize,
+    input: &TxInputRequest,
+    spent: Option<&TxOut>,
+    network: BtcNetwork,
+) -> Result<Option<InputType>, String> {
+    if let Some(input_type) = &input.input_type {
+        return InputType::from_str(input_type).map(Some).map_err(|e| format!("Input {}: {}", index, e));
+    }
+    let script_pubkey = match (&input.address, spent) {
+        (Some(address), _) => parse_address(address, network).map_err(|e| format!("Input {}: {}", index, e))?.script_pubkey(),
+        (None, Some(spent)) => spent.script_pubkey.clone(),
+        (None, None) => return Ok(None),
+    };
+    script_input_type(&script_pubkey)
+        .map(Some)
+        .ok_or_else(|| format!("Input {} spends a script whose size cannot be guessed; give its type", index))
+}
+
+// Each input's type for size estimates, falling back to the worst case when nothing says
+fn input_types(request: &CreateTxRequest, network: BtcNetwork) -> Result<Vec<(InputType, bool)>, String> {
+    let mut types = Vec::new();
+    for (index, input) in request.inputs.iter().enumerate() {
+        let (spent, _) = spent_output(index, input)?;
+        let hint = input_type_hint(index, input, spent.as_ref(), network)?;
+        types.push(hint.map_or((WORST_CASE_INPUT, true), |input_type| (input_type, false)));
+    }
+    Ok(types)
+}
+
+// Total of the input amounts with each input's predicted signed size, or None when neither a
+// fee nor a sweep is asked for and the outputs are taken as they are
+fn input_amounts(
+    request: &CreateTxRequest,
+    network: BtcNetwork,
+) -> Result<Option<(Amount, Vec<InputWeightPrediction>)>, String> {
+    if request.fee_rate.is_none() && request.fee.is_none() && request.sweep_to.is_none() {
+        if request.change_address.is_some() {
+            return Err("change_address needs a fee_rate or fee to work out the change".to_string());
+        }
+        return Ok(None);
+    }
+    let mut total = Amount::ZERO;
+    let mut predictions = Vec::new();
+    for (index, input) in request.inputs.iter().enumerate() {
+        // Both can come from the spent output, when prev_tx_hex or script_pubkey is given, and
+        // the type from the input's address too
+        let (spent, _) = spent_output(index, input)?;
+        let amount = input
+            .amount
+            .or(spent.as_ref().map(|output| output.value.to_sat()))
+            .ok_or_else(|| format!("Input {} has no amount to work out the fee from", index))?;
+        total = total
+            .checked_add(Amount::from_sat(amount))
+            .filter(|total| *total <= Amount::MAX_MONEY)
+            .ok_or_else(|| "Inputs add up to more than 21M BTC".to_string())?;
+        let input_type = input_type_hint(index, input, spent.as_ref(), network)?.ok_or_else(|| {
+            format!("Input {} has no type to estimate its size from; use one of {}", index, INPUT_TYPES.join(", "))
+        })?;
+        predictions.push(input_type.weight_prediction());
+    }
+    Ok(Some((total, predictions)))
 }
 
-fn create_transaction(request: CreateTxRequest, network: Network) -> Result<String, String> {
+// The requested fee rate, or None when an absolute fee is given instead
+fn requested_fee_rate(request: &CreateTxRequest) -> Result<Option<f64>, String> {
+    match (request.fee_rate, request.fee) {
+        (Some(_), Some(_)) => Err("Give either fee_rate or fee, not both".to_string()),
+        (None, None) => Err("fee_rate or fee is required".to_string()),
+        (Some(rate), None) if !rate.is_finite() || rate <= 0.0 => Err(format!("Fee rate must be positive, got {}", rate)),
+        (rate, _) => Ok(rate),
+    }
+}
+
+// Pay all the inputs hold, less the fee, to the sweep address; there is never change
+fn sweep_output(
+    request: &CreateTxRequest,
+    network: BtcNetwork,
+    total_in: Amount,
+    predictions: &[InputWeightPrediction],
+) -> Result<(TxOut, FeeSummary), String> {
+    let address = request.sweep_to.as_deref().unwrap_or_default();
+    let script_pubkey = parse_address(address, network).map_err(|e| format!("sweep_to: {}", e))?.script_pubkey();
+    let vsize = predict_weight(predictions.iter().copied(), [script_pubkey.len()]).to_vbytes_ceil();
+    let fee = match requested_fee_rate(request)? {
+        Some(rate) => Amount::from_sat((vsize as f64 * rate).ceil() as u64),
+        None => Amount::from_sat(request.fee.unwrap_or_default()),
+    };
+    let dust = script_pubkey.minimal_non_dust();
+    let value = total_in.checked_sub(fee).filter(|value| *value >= dust).ok_or_else(|| {
+        format!(
+            "Sweeping {} sats with a {} sat fee leaves less than the {} sat dust limit",
+            total_in.to_sat(),
+            fee.to_sat(),
+            dust.to_sat()
+        )
+    })?;
+    let summary = FeeSummary { fee: fee.to_sat(), change: None, vsize };
+    Ok((TxOut { value, script_pubkey }, summary))
+}
+
+// Work out the fee, adding a change output to `outputs` when the remainder is worth keeping
+fn settle_fee(
+    request: &CreateTxRequest,
+    network: BtcNetwork,
+    total_in: Amount,
+    predictions: &[InputWeightPrediction],
+    outputs: &mut Vec<TxOut>,
+) -> Result<FeeSummary, String> {
+    let fee_rate = requested_fee_rate(request)?;
+    let change_script = match &request.change_address {
+        Some(address) => Some(parse_address(address, network).map_err(|e| e.to_string())?.script_pubkey()),
+        None => None,
+    };
+    let target = outputs
+        .iter()
+        .try_fold(Amount::ZERO, |total, output| total.checked_add(output.value))
+        .filter(|total| *total <= Amount::MAX_MONEY)
+        .ok_or_else(|| "Outputs add up to more than 21M BTC".to_string())?;
+
+    let vsize = |change: Option<&ScriptBuf>| {
+        let output_lens = outputs.iter().map(|o| o.script_pubkey.len()).chain(change.map(|script| script.len()));
+        predict_weight(predictions.iter().copied(), output_lens).to_vbytes_ceil()
+    };
+    let fee_for = |vsize: u64| match fee_rate {
+        Some(rate) => Amount::from_sat((vsize as f64 * rate).ceil() as u64),
+        None => Amount::from_sat(request.fee.unwrap_or_default()),
+    };
+
+    let vsize_without_change = vsize(None);
+    let fee = fee_for(vsize_without_change);
+    let needed = target + fee;
+    if total_in < needed {
+        return Err(format!(
+            "Inputs total {} sats, short of the {} sats of outputs and {} sats fee by {} sats",
+            total_in.to_sat(),
+            target.to_sat(),
+            fee.to_sat(),
+            (needed - total_in).to_sat()
+        ));
+    }
+
+    let excess = total_in - needed;
+    let change = match change_script {
+        Some(script) => {
+            let vsize = vsize(Some(&script));
+            let value = total_in.checked_sub(target + fee_for(vsize)).unwrap_or(Amount::ZERO);
+            (value >= script.minimal_non_dust()).then_some((TxOut { value, script_pubkey: script }, vsize))
+        }
+        None if excess > DUST_LIMIT => {
+            return Err(format!(
+                "The inputs leave {} sats over the outputs and fee, which would go to miners; give a change_address",
+                excess.to_sat()
+            ))
+        }
+        None => None,
+    };
+    let (change, vsize) = match change {
+        Some((output, vsize)) => {
+            let value = output.value;
+            outputs.push(output);
+            (Some(value), vsize)
+        }
+        None => (None, vsize_without_change),
+    };
+    let fee = total_in - target - change.unwrap_or(Amount::ZERO);
+    Ok(FeeSummary { fee: fee.to_sat(), change: change.map(Amount::to_sat), vsize })
+}
+
+// The output an entry of the request describes
+// Whether nodes relay an output paying `script` by default: one of the address types, P2PK,
+// bare multisig, a witness program of any version, or an OP_RETURN pushing at most 80 bytes
+fn is_standard_script(script: &Script) -> bool {
+    if script.is_op_return() {
+        let payload = Script::from_bytes(&script.as_bytes()[1..]);
+        return script.len() <= MAX_DATA_LEN + 3 && payload.is_push_only();
+    }
+    script.is_p2pkh()
+        || script.is_p2sh()
+        || script.is_witness_program()
+        || script.is_p2pk()
+        || script.is_multisig()
+}
+
+fn build_output(
+    index: usize,
+    output_req: &TxOutputRequest,
+    network: BtcNetwork,
+    options: &CreateOptions,
+) -> Result<TxOut, String> {
+    if output_req.address.is_some() && output_req.script_hex.is_some() {
+        return Err(format!("Output {}: give either address or script_hex, not both", index));
+    }
+    match (&output_req.address, output_req.amount, &output_req.data) {
+        (None, Some(amount), None) if output_req.script_hex.is_some() => {
+            // Scripts have no network, so only their form is checked
+            let hex = output_req.script_hex.as_deref().unwrap_or_default().trim();
+            let script_pubkey =
+                ScriptBuf::from_hex(hex).map_err(|e| format!("Output {}: script_hex is not valid hex: {}", index, e))?;
+            if script_pubkey.is_empty() {
+                return Err(format!("Output {}: script_hex is empty", index));
+            }
+            if !is_standard_script(&script_pubkey) && !options.allow_nonstandard {
+                return Err(format!(
+                    "Output {}: script_hex {} matches no standard output type, so nodes will not relay it; \
+                     pass --allow-nonstandard to use it anyway",
+                    index, hex
+                ));
+            }
+            let value = Amount::from_sat(amount);
+            let dust = script_pubkey.minimal_non_dust();
+            if value < dust {
+                return Err(format!(
+                    "Output {}: {} sats is below the {} sat dust limit for its {} byte script",
+                    index,
+                    amount,
+                    dust.to_sat(),
+                    script_pubkey.len()
+                ));
+            }
+            Ok(TxOut { value, script_pubkey })
+        }
+        (Some(address), Some(amount), None) => {
+            // Also checks the address is for the requested network
+            let address = parse_address(address, network).map_err(|e| format!("Output {}: {}", index, e))?;
+            Ok(TxOut {
+                value: Amount::from_sat(amount),
+                script_pubkey: address.script_pubkey(),  // Script public key derived from the address
+            })
+        }
+        (None, None, Some(data)) => {
+            let payload = Vec::<u8>::from_hex(data.trim())
+                .map_err(|e| format!("Output {}: data is not valid hex: {}", index, e))?;
+            if payload.len() > MAX_DATA_LEN {
+                return Err(format!(
+                    "Output {}: data is {} bytes; OP_RETURN payloads are limited to {}",
+                    index,
+                    payload.len(),
+                    MAX_DATA_LEN
+                ));
+            }
+            let push = PushBytesBuf::try_from(payload).expect("at most 80 bytes");
+            Ok(TxOut { value: Amount::ZERO, script_pubkey: ScriptBuf::new_op_return(push) })
+        }
+        (_, _, Some(_)) if output_req.script_hex.is_some() => {
+            Err(format!("Output {}: data cannot be combined with script_hex", index))
+        }
+        (_, _, Some(_)) => Err(format!("Output {}: data cannot be combined with address or amount", index)),
+        (None, None, None) if output_req.script_hex.is_some() => Err(format!("Output {} has no amount", index)),
+        (Some(_), None, None) => Err(format!("Output {} has no amount", index)),
+        (None, Some(_), None) => Err(format!("Output {} has no address", index)),
+        (None, None, None) => Err(format!("Output {} needs an address and amount, or data", index)),
+    }
+}
+
+// The output an input spends, with the transaction holding it: all of it from prev_tx_hex,
+// or amount and script_pubkey without the transaction; None for inputs with neither
+fn spent_output(index: usize, input: &TxInputRequest) -> Result<(Option<TxOut>, Option<Transaction>), String> {
+    let script_pubkey = match &input.script_pubkey {
+        Some(hex) => Some(
+            ScriptBuf::from_hex(hex.trim()).map_err(|e| format!("Input {}: script_pubkey is not valid hex: {}", index, e))?,
+        ),
+        None => None,
+    };
+    let previous_tx = match &input.prev_tx_hex {
+        Some(hex) => Some(parse_transaction_hex(hex).map_err(|e| format!("Input {}: prev_tx_hex: {}", index, e))?),
+        None => None,
+    };
+    let spent = match (&previous_tx, input.amount, script_pubkey) {
+        (Some(previous_tx), amount, script_pubkey) => {
+            let output = previous_tx
+                .output
+                .get(input.vout as usize)
+                .cloned()
+                .ok_or_else(|| format!("Input {}: prev_tx_hex has no output {}", index, input.vout))?;
+            if amount.is_some_and(|amount| amount != output.value.to_sat())
+                || script_pubkey.is_some_and(|script| script != output.script_pubkey)
+            {
+                return Err(format!(
+                    "Input {}: amount or script_pubkey differs from output {} of prev_tx_hex",
+                    index, input.vout
+                ));
+            }
+            Some(output)
+        }
+        (None, Some(amount), Some(script_pubkey)) => Some(TxOut { value: Amount::from_sat(amount), script_pubkey }),
+        (None, None, Some(_)) => return Err(format!("Input {}: script_pubkey needs the amount too", index)),
+        (None, _, None) => None,
+    };
+    Ok((spent, previous_tx))
+}
+
+// What a PSBT records about the output each input spends: all of it from prev_tx_hex, or
+// amount and script_pubkey for a segwit input; None for inputs with neither
+fn psbt_inputs(request: &CreateTxRequest) -> Result<Vec<Option<PsbtInputData>>, String> {
+    let mut inputs = Vec::new();
+    for (index, input) in request.inputs.iter().enumerate() {
+        let data = match spent_output(index, input)? {
+            (Some(spent_output), previous_tx) => {
+                Some(PsbtInputData { spent_output, previous_tx, bip32_derivation: BTreeMap::new() })
+            }
+            (None, _) => None,
+        };
+        inputs.push(data);
+    }
+    Ok(inputs)
+}
+
+// Choose coins from the request's UTXOs with the library's builder, which works out fee and change
+fn select_coins(request: SelectRequest, network: BtcNetwork) -> Result<(Transaction, BuildReport), String> {
+    let fallback_script = match &request.utxo_address {
+        Some(address) => Some(parse_address(address, network).map_err(|e| format!("utxo_address: {}", e))?.script_pubkey()),
+        None => None,
+    };
+    let mut utxos = Vec::new();
+    for (index, utxo) in request.utxos.iter().enumerate() {
+        let txid = Txid::from_str(&utxo.txid).map_err(|_| format!("UTXO {}: invalid txid: {}", index, utxo.txid))?;
+        let script_pubkey = match (&utxo.script_pubkey, &utxo.address, &fallback_script) {
+            (Some(hex), _, _) => ScriptBuf::from_hex(hex.trim())
+                .map_err(|e| format!("UTXO {}: script_pubkey is not valid hex: {}", index, e))?,
+            (None, Some(address), _) => {
+                parse_address(address, network).map_err(|e| format!("UTXO {}: {}", index, e))?.script_pubkey()
+            }
+            (None, None, Some(script_pubkey)) => script_pubkey.clone(),
+            (None, None, None) => {
+                return Err(format!(
+                    "UTXO {} has no address or script_pubkey; give utxo_address, the address it was fetched for",
+                    index
+                ))
+            }
+        };
+        let mut typed = Utxo::new(txid, utxo.vout, Amount::from_sat(utxo.value), script_pubkey, utxo.address.clone());
+        typed.block_height = utxo.status.block_height;
+        utxos.push(typed);
+    }
+
+    let coin_selection = match &request.strategy {
+        Some(strategy) => CoinSelectionStrategy::from_str(strategy).map_err(|e| e.to_string())?,
+        None => CoinSelectionStrategy::default(),
+    };
+    let fee_rate = request.fee_rate.ok_or_else(|| "fee_rate is required to select coins".to_string())?;
+    let config = TxBuilderConfig { network, fee_rate: fee_rate as f32, coin_selection, ..Default::default() };
+    let mut builder = TransactionBuilder::new(network).with_config(config).with_utxos(utxos);
+    for (index, output) in request.outputs.iter().enumerate() {
+        let (Some(address), Some(amount), None, None) = (&output.address, output.amount, &output.data, &output.script_hex) else {
+            return Err(format!(
+                "Output {} needs an address and amount; data and script_hex outputs are not supported with utxos",
+                index
+            ));
+        };
+        let address = parse_address(address, network).map_err(|e| format!("Output {}: {}", index, e))?;
+        builder.add_output(address, Amount::from_sat(amount));
+    }
+    if let Some(address) = &request.change_address {
+        builder.set_change_address(parse_address(address, network).map_err(|e| format!("change_address: {}", e))?);
+    }
+    builder.build_with_report().map_err(|e| e.to_string())
+}
+
+// Version, lock time and input sequences, refusing combinations that would not take effect
+fn tx_parameters(request: &CreateTxRequest) -> Result<(Version, LockTime, Vec<Sequence>), String> {
+    let version = match request.version.unwrap_or(1) {
+        1 => Version::ONE,
+        2 => Version::TWO,
+        other => return Err(format!("Unsupported version {}; use 1 or 2", other)),
+    };
+    let lock_time = LockTime::from_consensus(request.locktime.unwrap_or(0));
+
+    // Without an override, a lock time gets the sequence that enables it without signalling RBF
+    let default_sequence = if request.rbf {
+        Sequence::ENABLE_RBF_NO_LOCKTIME
+    } else if lock_time != LockTime::ZERO {
+        Sequence::ENABLE_LOCKTIME_NO_RBF
+    } else {
+        Sequence::MAX
+    };
+    let sequences: Vec<Sequence> =
+        request.inputs.iter().map(|input| input.sequence.map_or(default_sequence, Sequence)).collect();
+
+    for (index, sequence) in sequences.iter().enumerate() {
+        if sequence.is_relative_lock_time() && version == Version::ONE {
+            return Err(format!(
+                "Input {}: sequence {:#010x} is a relative lock time, which needs version 2",
+                index,
+                sequence.to_consensus_u32()
+            ));
+        }
+    }
+    if request.rbf && !sequences.iter().any(|sequence| sequence.is_rbf()) {
+        return Err("rbf is set, but every input's sequence is 0xfffffffe or above, so none signals it".to_string());
+    }
+    if lock_time != LockTime::ZERO && sequences.iter().all(|sequence| *sequence == Sequence::MAX) {
+        return Err(format!(
+            "locktime {} would never apply: every input's sequence is final (0xffffffff)",
+            lock_time
+        ));
+    }
+    Ok((version, lock_time, sequences))
+}
+
+// Projected signed size of `tx` with inputs of the given types, its fee at `fee_rate`, and how
+// far each output is above dust
+fn size_report(tx: &Transaction, input_types: &[(InputType, bool)], fee_rate: Option<f64>) -> SizeReport {
+    let predictions = input_types.iter().map(|(input_type, _)| input_type.weight_prediction());
+    let weight = predict_weight(predictions, tx.output.iter().map(|output| output.script_pubkey.len()));
+    let vsize = weight.to_vbytes_ceil();
+    let inputs = input_types
+        .iter()
+        .enumerate()
+        .map(|(index, (input_type, assumed))| InputSize { index, input_type: input_type.to_string(), assumed: *assumed })
+        .collect();
+    let outputs = tx
+        .output
+        .iter()
+        .enumerate()
+        .map(|(index, output)| {
+            let dust_limit = (!output.script_pubkey.is_op_return()).then(|| output.script_pubkey.minimal_non_dust().to_sat());
+            OutputMargin {
+                index,
+                value: output.value.to_sat(),
+                dust_limit,
+                dust_margin: dust_limit.map(|limit| output.value.to_sat() as i64 - limit as i64),
+            }
+        })
+        .collect();
+    SizeReport {
+        vsize,
+        weight: weight.to_wu(),
+        fee_rate,
+        projected_fee: fee_rate.map(|rate| (vsize as f64 * rate).ceil() as u64),
+        inputs,
+        outputs,
+    }
+}
+
+// The size report as lines, for --verbose
+fn describe_size(report: &SizeReport) -> Vec<String> {
+    let mut lines = vec![format!("Estimated signed size: {} vB ({} WU)", report.vsize, report.weight)];
+    if let (Some(rate), Some(fee)) = (report.fee_rate, report.projected_fee) {
+        lines.push(format!("Projected fee: {} sats at {} sat/vB", fee, rate));
+    }
+    for input in &report.inputs {
+        let assumed = if input.assumed { " (assumed: no type, address or prevout given)" } else { "" };
+        lines.push(format!("Input {}: {}{}", input.index, input.input_type, assumed));
+    }
+    for output in &report.outputs {
+        lines.push(match (output.dust_limit, output.dust_margin) {
+            (Some(limit), Some(margin)) if margin >= 0 => {
+                format!("Output {}: {} sats, {} above the {} sat dust limit", output.index, output.value, margin, limit)
+            }
+            (Some(limit), Some(margin)) => {
+                format!("Output {}: {} sats, {} below the {} sat dust limit", output.index, output.value, -margin, limit)
+            }
+            _ => format!("Output {}: OP_RETURN, no dust limit", output.index),
+        });
+    }
+    lines
+}
+
+// One-line account of the version, lock time and sequences, for --verbose
+fn describe_parameters(tx: &Transaction) -> String {
+    let lock_time = match tx.lock_time {
+        LockTime::Blocks(height) => format!("{} (block height)", height),
+        LockTime::Seconds(time) => format!("{} (unix time)", time),
+    };
+    let sequences: Vec<String> =
+        tx.input.iter().map(|input| format!("{:#010x}", input.sequence.to_consensus_u32())).collect();
+    format!(
+        "Version: {}, locktime: {}, RBF: {}, sequences: {}",
+        tx.version.0,
+        lock_time,
+        if tx.is_explicitly_rbf() { "yes" } else { "no" },
+        sequences.join(", ")
+    )
+}
+
+fn create_transaction(
+    request: CreateTxRequest,
+    network: BtcNetwork,
+    options: &CreateOptions,
+) -> Result<(Transaction, Option<FeeSummary>), String> {
+    let amounts = input_amounts(&request, network)?;
+    let (version, lock_time, sequences) = tx_parameters(&request)?;
+
     // Process transaction inputs
     let mut inputs = Vec::new();
-    for input_req in &request.inputs {
+    for (input_req, sequence) in request.inputs.iter().zip(sequences) {
         let txid = match Txid::from_str(&input_req.txid) {
             Ok(txid) => txid,
             Err(_) => return Err(format!("Invalid txid: {}", input_req.txid)),
@@ -38,72 +693,316 @@ fn create_transaction(request: CreateTxRequest, network: Network) -> Result<Stri
         let vout = input_req.vout;
         let input = TxIn {
             previous_output: OutPoint { txid, vout },
-            script_sig: bitcoin::Script::new(),  // Empty script for an unsigned transaction
-            sequence: Sequence(0xffffffff),      // Default sequence number
-            witness: bitcoin::Witness::new(),    // Empty witness for non-segwit
+            script_sig: ScriptBuf::new(),  // Empty script for an unsigned transaction
+            sequence,                      // Final unless RBF, a lock time or an override asks otherwise
+            witness: Witness::new(),       // Empty witness for non-segwit
         };
         inputs.push(input);
     }
 
     // Process transaction outputs
     let mut outputs = Vec::new();
-    for output_req in &request.outputs {
-        let address = match Address::from_str(&output_req.address) {
-            Ok(addr) => addr,
-            Err(e) => return Err(format!("Invalid address {}: {}", output_req.address, e)),
-        };
-        // Check if the address network matches the requested network
-        if address.network != network {
-            return Err(format!("Address network mismatch: address is for {:?}, but requested {:?}", address.network, network));
-        }
-        let amount = Amount::from_sat(output_req.amount);
-        let script_pubkey = address.script_pubkey();
-        let output = TxOut {
-            value: amount.to_sat(),  // Convert amount to satoshis
-            script_pubkey,           // Script public key derived from the address
-        };
+    let mut data_outputs = 0;
+    for (index, output_req) in request.outputs.iter().enumerate() {
+        let output = build_output(index, output_req, network, options)?;
+        // script_hex OP_RETURNs count too
+        if output.script_pubkey.is_op_return() {
+            data_outputs += 1;
+            if data_outputs > 1 && !options.allow_multiple_data {
+                return Err(format!(
+                    "Output {}: only one data output is standard per transaction; pass --allow-multiple-data for more",
+                    index
+                ));
+            }
+        }
         outputs.push(output);
     }
 
+    // With input amounts, the fee is worked out and the remainder swept or sent to change
+    let summary = match amounts {
+        Some((total_in, predictions)) if request.sweep_to.is_some() => {
+            if !outputs.is_empty() || request.change_address.is_some() {
+                return Err("A sweep pays one address only; remove the outputs and change_address".to_string());
+            }
+            let (output, summary) = sweep_output(&request, network, total_in, &predictions)?;
+            outputs.push(output);
+            Some(summary)
+        }
+        Some((total_in, predictions)) => Some(settle_fee(&request, network, total_in, &predictions, &mut outputs)?),
+        None => None,
+    };
+
     // Build the transaction
     let tx = Transaction {
-        version: 1,          // Transaction version
-        lock_time: PackedLockTime(0),  // No lock time
-        input: inputs,       // List of inputs
-        output: outputs,     // List of outputs
+        version,                     // Transaction version
+        lock_time,                   // Lock time, zero unless requested
+        input: inputs,               // List of inputs
+        output: outputs,             // List of outputs
+    };
+
+    Ok((tx, summary))
+}
+
+// Print the transaction as hex, or as a PSBT when given its inputs' prevout data; with a size
+// report, as a JSON object holding both
+fn print_transaction(tx: Transaction, prevouts: Option<Vec<Option<PsbtInputData>>>, report: Option<SizeReport>) {
+    #[derive(Serialize)]
+    struct JsonOutput {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        hex: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        psbt: Option<String>,
+        #[serde(flatten)]
+        report: SizeReport,
+    }
+    let print = |hex: Option<String>, psbt: Option<String>| match report {
+        Some(report) => {
+            let output = JsonOutput { hex, psbt, report };
+            println!("{}", serde_json::to_string_pretty(&output).expect("output serializes"));
+        }
+        None => println!("{}", hex.or(psbt).unwrap_or_default()),
     };
+    let Some(prevouts) = prevouts else {
+        // Serialize the transaction and encode it to hex
+        print(Some(serialize_hex(&tx)), None);
+        return;
+    };
+    let missing: Vec<String> =
+        (0..prevouts.len()).filter(|&index| prevouts[index].is_none()).map(|index| index.to_string()).collect();
+    match build_psbt(tx, prevouts, BTreeMap::new()) {
+        Ok(psbt) => {
+            if !missing.is_empty() {
+                eprintln!(
+                    "Warning: input(s) {} have no prev_tx_hex or amount and script_pubkey; signers will likely reject the PSBT",
+                    missing.join(", ")
+                );
+            }
+            print(None, Some(psbt.to_string()));
+        }
+        Err(e) => {
+            eprintln!("Error creating PSBT: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
 
-    // Serialize the transaction to bytes and encode to hex
-    let tx_bytes = serialize(&tx);
-    let tx_hex = hex::encode(tx_bytes);
+// Fill in prev_tx_hex from the backend for every input without one
+fn fetch_prevouts(request: &mut CreateTxRequest, client: &BlockstreamClient) -> Result<(), String> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("Cannot start the runtime: {}", e))?;
+    for input in request.inputs.iter_mut().filter(|input| input.prev_tx_hex.is_none()) {
+        let hex = runtime
+            .block_on(client.get_transaction_hex(&input.txid))
+            .map_err(|e| format!("Cannot fetch previous transaction {}: {}", input.txid, e))?;
+        input.prev_tx_hex = Some(hex);
+    }
+    Ok(())
+}
 
-    Ok(tx_hex)
+fn print_usage(program: &str) {
+    eprintln!(
+        "Usage: {} [--network <mainnet|testnet|signet|regtest>] [--sweep <address>] [--fee-rate <sat/vB>] \
+         [--fetch-prevouts [--esplora-url <url>] [--esplora-token <token>]] [--allow-multiple-data] [--allow-nonstandard] [--psbt] [--verbose] [--json] [json_input]",
+        program
+    );
+    eprintln!("  --network: network the output addresses belong to (default: ${} or mainnet)", NETWORK_ENV);
+    eprintln!("  --sweep: send everything the inputs hold, less the fee, to this address with no other output (or \"sweep_to\")");
+    eprintln!("  --fee-rate: fee rate in sat/vB, overriding the request's fee_rate; otherwise only the rate of the projected fee");
+    eprintln!("  --fetch-prevouts: fetch each input's previous transaction, for its amount and type, from Esplora");
+    eprintln!("  --esplora-url: Esplora base URL for --fetch-prevouts (default: the network's from the environment)");
+    eprintln!("  --esplora-token: bearer token for the Esplora server (default: $BTCX_ESPLORA_TOKEN)");
+    eprintln!("  --allow-multiple-data: accept more than one {{\"data\": \"<hex>\"}} OP_RETURN output, which nodes do not relay");
+    eprintln!("  --allow-nonstandard: accept {{\"script_hex\": \"<hex>\", \"amount\": <sats>}} outputs matching no standard script type");
+    eprintln!("  --psbt: print a base64 PSBT carrying each input's prev_tx_hex, or amount and script_pubkey, instead of hex");
+    eprintln!("  --verbose: print the version, lock time, input sequences, each amount in sats and BTC, the projected signed");
+    eprintln!("             size and fee, and each output's dust margin on stderr");
+    eprintln!("  --json: print {{\"hex\" (or \"psbt\"), \"vsize\", \"weight\", \"projected_fee\", \"inputs\", \"outputs\"}} instead of the hex alone");
+    eprintln!("Inputs without a type, address or prevout are sized as {}, the largest single-key input", WORST_CASE_INPUT);
+    eprintln!("Example JSON:");
+    eprintln!(r#"{{"inputs": [{{"txid": "abc123...", "vout": 0}}], "outputs": [{{"address": "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa", "amount": 1000}}]}}"#);
+    eprintln!("With a fee rate and input amounts and types, the fee and change are worked out:");
+    eprintln!(r#"{{"inputs": [{{"txid": "abc123...", "vout": 0, "amount": 5000, "type": "p2wpkh"}}], "outputs": [...], "fee_rate": 2.0, "change_address": "bc1q..."}}"#);
+    eprintln!("Or every input is swept to one address:");
+    eprintln!(r#"{{"inputs": [{{"txid": "abc123...", "vout": 0, "amount": 5000, "type": "p2wpkh"}}], "fee_rate": 2.0, "sweep_to": "bc1q..."}}"#);
+    eprintln!("Or inputs are chosen from fetch_utxos output:");
+    eprintln!(r#"{{"utxos": [...], "utxo_address": "bc1q...", "outputs": [...], "fee_rate": 2.0, "change_address": "bc1q...", "strategy": "largest_first"}}"#);
 }
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
 
+    let mut network_flag: Option<&str> = None;
+    let mut options = CreateOptions::default();
+    let mut verbose = false;
+    let mut psbt = false;
+    let mut json = false;
+    let mut sweep_to: Option<&str> = None;
+    let mut fee_rate: Option<f64> = None;
+    let mut fetch = false;
+    let mut esplora_url: Option<&str> = None;
+    let mut esplora_token: Option<&str> = None;
+    let mut json_arg: Option<&str> = None;
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--network" => match rest.next() {
+                Some(value) => network_flag = Some(value),
+                None => {
+                    eprintln!("Error: --network requires a value");
+                    std::process::exit(1);
+                }
+            },
+            "--sweep" => match rest.next() {
+                Some(value) => sweep_to = Some(value),
+                None => {
+                    eprintln!("Error: --sweep requires an address");
+                    std::process::exit(1);
+                }
+            },
+            "--fee-rate" => match rest.next().map(|value| value.parse::<f64>()) {
+                Some(Ok(value)) => fee_rate = Some(value),
+                Some(Err(e)) => {
+                    eprintln!("Error: invalid --fee-rate: {}", e);
+                    std::process::exit(1);
+                }
+                None => {
+                    eprintln!("Error: --fee-rate requires a value");
+                    std::process::exit(1);
+                }
+            },
+            "--fetch-prevouts" => fetch = true,
+            "--esplora-url" => match rest.next() {
+                Some(value) => esplora_url = Some(value),
+                None => {
+                    eprintln!("Error: --esplora-url requires a value");
+                    std::process::exit(1);
+                }
+            },
+            "--esplora-token" => match rest.next() {
+                Some(value) => esplora_token = Some(value),
+                None => {
+                    eprintln!("Error: --esplora-token requires a value");
+                    std::process::exit(1);
+                }
+            },
+            "--allow-multiple-data" => options.allow_multiple_data = true,
+            "--allow-nonstandard" => options.allow_nonstandard = true,
+            "--psbt" => psbt = true,
+            "--verbose" | "-v" => verbose = true,
+            "--json" => json = true,
+            "--help" | "-h" => {
+                print_usage(&args[0]);
+                return;
+            }
+            _ if json_arg.is_none() => json_arg = Some(arg),
+            _ => {
+                eprintln!("Error: unexpected argument {:?}", arg);
+                print_usage(&args[0]);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let network = match resolve_network(network_flag, std::env::var(NETWORK_ENV).ok()) {
+        Ok(network) => network,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
     // Determine if we should read from stdin or use command-line argument
-    let json_input = if args.len() > 1 {
-        // Use command-line argument
-        args[1].clone()
-    } else {
-        // Read from stdin
-        let mut buffer = String::new();
-        io::stdin()
-            .read_to_string(&mut buffer)
-            .expect("Failed to read from stdin");
-        buffer
+    let json_input = match json_arg {
+        Some(json) => json.to_string(),
+        None => {
+            // Read from stdin
+            let mut buffer = String::new();
+            io::stdin()
+                .read_to_string(&mut buffer)
+                .expect("Failed to read from stdin");
+            buffer
+        }
     };
 
+    // UTXOs to choose from, as fetch_utxos prints them, instead of chosen inputs
+    if let Ok(RequestShape { utxos: Some(_) }) = serde_json::from_str(&json_input) {
+        if sweep_to.is_some() || fetch {
+            eprintln!("Error: --sweep and --fetch-prevouts take chosen inputs, not utxos");
+            std::process::exit(1);
+        }
+        let mut request: SelectRequest = match serde_json::from_str(&json_input) {
+            Ok(req) => req,
+            Err(e) => {
+                eprintln!("Error parsing JSON: {}", e);
+                print_usage(&args[0]);
+                std::process::exit(1);
+            }
+        };
+        if fee_rate.is_some() {
+            request.fee_rate = fee_rate;
+        }
+        if verbose {
+            for line in describe_amounts(&[], &request.outputs, None) {
+                eprintln!("{}", line);
+            }
+        }
+        let projection_rate = request.fee_rate;
+        match select_coins(request, network) {
+            Ok((tx, report)) => {
+                if verbose {
+                    eprintln!("{}", describe_parameters(&tx));
+                }
+                for utxo in &report.selected {
+                    eprintln!("Selected {} ({} sats)", utxo.outpoint(), utxo.amount.to_sat());
+                }
+                let summary = FeeSummary {
+                    fee: report.fee.to_sat(),
+                    change: report.change.map(Amount::to_sat),
+                    vsize: report.vsize,
+                };
+                eprintln!("{}", summary);
+                let prevouts = psbt.then(|| {
+                    let spent = |utxo: &Utxo| TxOut { value: utxo.amount, script_pubkey: utxo.script_pubkey.clone() };
+                    report
+                        .selected
+                        .iter()
+                        .map(|utxo| {
+                            Some(PsbtInputData { spent_output: spent(utxo), previous_tx: None, bip32_derivation: BTreeMap::new() })
+                        })
+                        .collect()
+                });
+                let size = (verbose || json).then(|| {
+                    let input_types: Vec<(InputType, bool)> = report
+                        .selected
+                        .iter()
+                        .map(|utxo| match script_input_type(&utxo.script_pubkey) {
+                            Some(input_type) => (input_type, false),
+                            None => (WORST_CASE_INPUT, true),
+                        })
+                        .collect();
+                    size_report(&tx, &input_types, projection_rate)
+                });
+                if let Some(size) = size.as_ref().filter(|_| verbose) {
+                    describe_size(size).iter().for_each(|line| eprintln!("{}", line));
+                }
+                print_transaction(tx, prevouts, size.filter(|_| json));
+            }
+            Err(e) => {
+                eprintln!("Error creating transaction: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     // Parse JSON input
-    let request: CreateTxRequest = match serde_json::from_str(&json_input) {
+    let mut request: CreateTxRequest = match serde_json::from_str(&json_input) {
         Ok(req) => req,
         Err(e) => {
             eprintln!("Error parsing JSON: {}", e);
-            eprintln!("Usage: {} [json_input]", args[0]);
-            eprintln!("Example JSON:");
-            eprintln!(r#"{{"inputs": [{{"txid": "abc123...", "vout": 0}}], "outputs": [{{"address": "1A1zP1eP5QGefi2DMPTfTL5SLmv7DivfNa", "amount": 1000}}]}}"#);
+            print_usage(&args[0]);
             std::process::exit(1);
         }
     };
@@ -114,18 +1013,82 @@ fn main() {
         std::process::exit(1);
     }
 
-    if request.outputs.is_empty() {
+    if sweep_to.is_some() {
+        request.sweep_to = sweep_to.map(str::to_string);
+    }
+    // --fee-rate replaces the request's rate where it sets the fee; otherwise it is only the rate
+    // the projected fee is worked out at
+    if fee_rate.is_some() && (request.fee_rate.is_some() || (request.sweep_to.is_some() && request.fee.is_none())) {
+        request.fee_rate = fee_rate;
+    }
+    let projection_rate = fee_rate.or(request.fee_rate);
+
+    if request.outputs.is_empty() && request.sweep_to.is_none() {
         eprintln!("Error: At least one output is required");
         std::process::exit(1);
     }
 
-    // Use Bitcoin mainnet (can be extended to support testnet/regtest if needed)
-    let network = Network::Bitcoin;
+    if fetch {
+        let mut builder = BlockstreamClient::builder(network);
+        if let Some(url) = esplora_url {
+            builder = builder.base_url(url);
+        }
+        if let Some(token) = esplora_token {
+            builder = builder.bearer_token(token);
+        }
+        let fetched = builder.build().map_err(|e| e.to_string()).and_then(|client| fetch_prevouts(&mut request, &client));
+        if let Err(e) = fetched {
+            eprintln!("Error fetching previous transactions from {}: {}", network, e);
+            std::process::exit(1);
+        }
+    }
+
+    if verbose {
+        for line in describe_amounts(&request.inputs, &request.outputs, request.fee) {
+            eprintln!("{}", line);
+        }
+    }
+
+    // Prevout data is checked before the transaction is built
+    let prevouts = if psbt {
+        match psbt_inputs(&request) {
+            Ok(prevouts) => Some(prevouts),
+            Err(e) => {
+                eprintln!("Error creating PSBT: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+
+    // Input types are read before the request is consumed
+    let input_types = if verbose || json {
+        match input_types(&request, network) {
+            Ok(input_types) => input_types,
+            Err(e) => {
+                eprintln!("Error estimating the size: {}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        Vec::new()
+    };
 
     // Create the transaction
-    match create_transaction(request, network) {
-        Ok(tx_hex) => {
-            println!("{}", tx_hex);
+    match create_transaction(request, network, &options) {
+        Ok((tx, summary)) => {
+            if verbose {
+                eprintln!("{}", describe_parameters(&tx));
+            }
+            if let Some(summary) = summary {
+                eprintln!("{}", summary);
+            }
+            let size = (verbose || json).then(|| size_report(&tx, &input_types, projection_rate));
+            if let Some(size) = size.as_ref().filter(|_| verbose) {
+                describe_size(size).iter().for_each(|line| eprintln!("{}", line));
+            }
+            print_transaction(tx, prevouts, size.filter(|_| json));
         }
         Err(e) => {
             eprintln!("Error creating transaction: {}", e);
@@ -133,3 +1096,587 @@ fn main() {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use bitcoin::{CompressedPublicKey, Network, PrivateKey, Psbt};
+    use btcx_lib::psbt::finalize_psbt;
+    use btcx_lib::signing::{sign_transaction, verify_transaction, InputSigner, SpentOutput};
+
+    const TXID: &str = "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16";
+
+    fn request(address: &str) -> CreateTxRequest {
+        CreateTxRequest {
+            inputs: vec![TxInputRequest { txid: TXID.to_string(), vout: 1, ..Default::default() }],
+            outputs: vec![TxOutputRequest { address: Some(address.to_string()), amount: Some(50_000), data: None, script_hex: None }],
+            fee_rate: None,
+            fee: None,
+            change_address: None,
+            rbf: false,
+            locktime: None,
+            version: None,
+            sweep_to: None,
+        }
+    }
+
+    fn create(request: CreateTxRequest, network: BtcNetwork) -> Result<(Transaction, Option<FeeSummary>), String> {
+        create_transaction(request, network, &CreateOptions::default())
+    }
+
+    const CHANGE: &str = "bcrt1q6rz28mcfaxtmd6v789l9rrlrusdprr9pz3cppk";
+
+    // A regtest request spending one 100000 sat P2WPKH output, paying `amount` at 2 sat/vB
+    fn funded_request(amount: u64) -> CreateTxRequest {
+        let mut request = request("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080");
+        request.inputs[0].amount = Some(100_000);
+        request.inputs[0].input_type = Some("p2wpkh".to_string());
+        request.outputs[0].amount = Some(amount);
+        request.fee_rate = Some(2.0);
+        request.change_address = Some(CHANGE.to_string());
+        request
+    }
+
+    #[test]
+    fn test_adds_change() {
+        let (tx, summary) = create(funded_request(70_000), BtcNetwork::Regtest).unwrap();
+        // One P2WPKH input and two P2WPKH outputs: 141 vB at 2 sat/vB
+        assert_eq!(summary, Some(FeeSummary { fee: 282, change: Some(29_718), vsize: 141 }));
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[1].value, Amount::from_sat(29_718));
+        assert_eq!(tx.output[1].script_pubkey, parse_address(CHANGE, BtcNetwork::Regtest).unwrap().script_pubkey());
+
+        // An absolute fee is taken as is
+        let mut request = funded_request(70_000);
+        request.fee_rate = None;
+        request.fee = Some(1_000);
+        let (_, summary) = create(request, BtcNetwork::Regtest).unwrap();
+        assert_eq!(summary, Some(FeeSummary { fee: 1_000, change: Some(29_000), vsize: 141 }));
+    }
+
+    #[test]
+    fn test_drops_change_below_dust() {
+        // 280 sats over the 220 sat fee, but a change output would cost 62 more and leave 218, under dust
+        let (tx, summary) = create(funded_request(99_500), BtcNetwork::Regtest).unwrap();
+        assert_eq!(summary, Some(FeeSummary { fee: 500, change: None, vsize: 110 }));
+        assert_eq!(tx.output.len(), 1);
+
+        // Without a change address, a remainder above dust is refused rather than given to miners
+        let mut request = funded_request(70_000);
+        request.change_address = None;
+        let error = create(request, BtcNetwork::Regtest).unwrap_err();
+        assert!(error.contains("29780 sats over"), "{}", error);
+    }
+
+    #[test]
+    fn test_reports_shortfall() {
+        let error = create(funded_request(99_900), BtcNetwork::Regtest).unwrap_err();
+        assert_eq!(error, "Inputs total 100000 sats, short of the 99900 sats of outputs and 220 sats fee by 120 sats");
+
+        let mut request = funded_request(50_000);
+        request.inputs.push(TxInputRequest { txid: TXID.to_string(), vout: 2, ..Default::default() });
+        let error = create(request, BtcNetwork::Regtest).unwrap_err();
+        assert!(error.starts_with("Input 1 has no amount"), "{}", error);
+    }
+
+    #[test]
+    fn test_creates_regtest_transaction() {
+        let address = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+        let (tx, summary) = create(request(address), BtcNetwork::Regtest).unwrap();
+        assert_eq!(summary, None);
+        assert_eq!(tx.input[0].previous_output, OutPoint { txid: Txid::from_str(TXID).unwrap(), vout: 1 });
+        assert_eq!(tx.output[0].value, Amount::from_sat(50_000));
+        assert_eq!(
+            tx.output[0].script_pubkey,
+            parse_address(address, BtcNetwork::Regtest).unwrap().script_pubkey()
+        );
+
+        // Base58 test addresses are shared by testnet, signet and regtest
+        assert!(create(request("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn"), BtcNetwork::Regtest).is_ok());
+        assert!(create(request("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"), BtcNetwork::Signet).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_address_from_other_network() {
+        let error = create(request("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq"), BtcNetwork::Regtest)
+            .unwrap_err();
+        assert!(error.contains("is a bitcoin address, not regtest"), "{}", error);
+    }
+
+    #[test]
+    fn test_data_output() {
+        let data = |hex: &str| TxOutputRequest { address: None, amount: None, data: Some(hex.to_string()), script_hex: None };
+        let hash = "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d";
+        let mut anchored = funded_request(70_000);
+        anchored.outputs.push(data(hash));
+        let (tx, summary) = create(anchored, BtcNetwork::Regtest).unwrap();
+                // OP_RETURN, then a single 32-byte push
+        assert_eq!(tx.output[1].script_pubkey.to_hex_string(), format!("6a20{}", hash));
+        assert_eq!(tx.output[1].value, Amount::ZERO);
+        // The value, length and 34-byte script add 43 vB to the estimate
+        assert_eq!(summary, Some(FeeSummary { fee: 368, change: Some(29_632), vsize: 184 }));
+
+        let regtest = || request("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080");
+        let mut oversized = regtest();
+        oversized.outputs.push(data(&"00".repeat(81)));
+        let error = create(oversized, BtcNetwork::Regtest).unwrap_err();
+        assert_eq!(error, "Output 1: data is 81 bytes; OP_RETURN payloads are limited to 80");
+
+        let mut not_hex = regtest();
+        not_hex.outputs.push(data("abcz"));
+        let error = create(not_hex, BtcNetwork::Regtest).unwrap_err();
+        assert!(error.starts_with("Output 1: data is not valid hex"), "{}", error);
+
+        let mut mixed = regtest();
+        mixed.outputs[0].data = Some(hash.to_string());
+        let error = create(mixed, BtcNetwork::Regtest).unwrap_err();
+        assert_eq!(error, "Output 0: data cannot be combined with address or amount");
+
+        let two_data = || {
+            let mut two_data = regtest();
+            two_data.outputs.extend([data("01"), data("02")]);
+            two_data
+        };
+        assert!(create(two_data(), BtcNetwork::Regtest).is_err());
+        let options = CreateOptions { allow_multiple_data: true, ..Default::default() };
+        assert!(create_transaction(two_data(), BtcNetwork::Regtest, &options).is_ok());
+    }
+
+    #[test]
+    fn test_script_hex_output() {
+        let script = |hex: &str, amount: u64| TxOutputRequest {
+            address: None,
+            amount: Some(amount),
+            data: None,
+            script_hex: Some(hex.to_string()),
+        };
+        let p2sh = "a914751e76e8199196d454941c45d1b3a323f1433bd687";
+        let commitment = "6a24aa21a9ede2f61c3f71d1defd3fa999dfa36953755c690689799962b48bebd836974e8cf9";
+        // Scripts are not checked against the network
+        let mut mainnet = request("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq");
+        mainnet.outputs = vec![script(p2sh, 10_000), script(commitment, 0)];
+        let (tx, _) = create(mainnet, BtcNetwork::Regtest).unwrap();
+        let decoded = parse_transaction_hex(&serialize_hex(&tx)).unwrap();
+        assert_eq!(decoded.output[0].script_pubkey.to_hex_string(), p2sh);
+        assert_eq!(decoded.output[0].value.to_sat(), 10_000);
+        assert_eq!(decoded.output[1].script_pubkey.to_hex_string(), commitment);
+        assert_eq!(decoded.output[1].value, Amount::ZERO);
+
+        // OP_TRUE is spendable by anyone and matches no template
+        let op_true = |amount: u64| {
+            let mut op_true = request("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq");
+            op_true.outputs = vec![script("51", amount)];
+            op_true
+        };
+        let error = create(op_true(1_000), BtcNetwork::Regtest).unwrap_err();
+        assert!(error.contains("pass --allow-nonstandard"), "{}", error);
+        let options = CreateOptions { allow_nonstandard: true, ..Default::default() };
+        let (tx, _) = create_transaction(op_true(1_000), BtcNetwork::Regtest, &options).unwrap();
+        assert_eq!(parse_transaction_hex(&serialize_hex(&tx)).unwrap().output[0].script_pubkey.as_bytes(), [0x51]);
+        let error = create_transaction(op_true(100), BtcNetwork::Regtest, &options).unwrap_err();
+        assert!(error.contains("below the 474 sat dust limit for its 1 byte script"), "{}", error);
+
+        for (output, expected) in [
+            (script("", 1_000), "Output 0: script_hex is empty"),
+            (script("a9z", 1_000), "Output 0: script_hex is not valid hex"),
+            (TxOutputRequest { address: Some(CHANGE.to_string()), ..script(p2sh, 1_000) }, "Output 0: give either address"),
+            (TxOutputRequest { amount: None, ..script(p2sh, 1_000) }, "Output 0 has no amount"),
+        ] {
+            let mut invalid = request(CHANGE);
+            invalid.outputs = vec![output];
+            let error = create(invalid, BtcNetwork::Regtest).unwrap_err();
+            assert!(error.starts_with(expected), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_tx_parameters() {
+        let regtest = || request("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080");
+        // Version, then the sequence after the outpoint and empty scriptSig, then the lock time
+        let fields = |request: CreateTxRequest| {
+            let tx_hex = serialize_hex(&create(request, BtcNetwork::Regtest).unwrap().0);
+            vec![tx_hex[..8].to_string(), tx_hex[84..92].to_string(), tx_hex[tx_hex.len() - 8..].to_string()]
+        };
+        assert_eq!(fields(regtest()), ["01000000", "ffffffff", "00000000"]);
+
+        let mut rbf = regtest();
+        rbf.rbf = true;
+        assert_eq!(fields(rbf), ["01000000", "fdffffff", "00000000"]);
+
+        // Anti-fee-sniping: a height lock time enables itself without signalling RBF
+        let mut height = regtest();
+        height.version = Some(2);
+        height.locktime = Some(800_000);
+        assert_eq!(fields(height), ["02000000", "feffffff", "00350c00"]);
+
+        // A relative lock time of 144 blocks
+        let mut csv = regtest();
+        csv.version = Some(2);
+        csv.inputs[0].sequence = Some(144);
+        assert_eq!(fields(csv), ["02000000", "90000000", "00000000"]);
+
+        let mut time = regtest();
+        time.locktime = Some(1_700_000_000);
+        time.rbf = true;
+        let (tx, _) = create(time, BtcNetwork::Regtest).unwrap();
+        assert_eq!(
+            describe_parameters(&tx),
+            "Version: 1, locktime: 1700000000 (unix time), RBF: yes, sequences: 0xfffffffd"
+        );
+    }
+
+    #[test]
+    fn test_rejects_ineffective_parameters() {
+        let regtest = || request("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080");
+        let mut version = regtest();
+        version.version = Some(3);
+        assert_eq!(create(version, BtcNetwork::Regtest).unwrap_err(), "Unsupported version 3; use 1 or 2");
+
+        let mut csv = regtest();
+        csv.inputs[0].sequence = Some(144);
+        assert_eq!(
+            create(csv, BtcNetwork::Regtest).unwrap_err(),
+            "Input 0: sequence 0x00000090 is a relative lock time, which needs version 2"
+        );
+
+        let mut final_sequence = regtest();
+        final_sequence.locktime = Some(800_000);
+        final_sequence.inputs[0].sequence = Some(0xffffffff);
+        let error = create(final_sequence, BtcNetwork::Regtest).unwrap_err();
+        assert!(error.starts_with("locktime 800000 would never apply"), "{}", error);
+
+        let mut no_signal = regtest();
+        no_signal.rbf = true;
+        no_signal.inputs[0].sequence = Some(0xfffffffe);
+        let error = create(no_signal, BtcNetwork::Regtest).unwrap_err();
+        assert!(error.starts_with("rbf is set"), "{}", error);
+    }
+
+    // A regtest transaction paying two P2WPKH outputs to the test key
+    fn funding() -> (PrivateKey, Transaction) {
+        let key = PrivateKey::new(SecretKey::from_slice(&[9; 32]).unwrap(), Network::Regtest);
+        let public_key = CompressedPublicKey::from_private_key(&Secp256k1::new(), &key).unwrap();
+        let script_pubkey = ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash());
+        let tx = Transaction {
+            version: Version::TWO,
+            lock_time: LockTime::ZERO,
+            input: vec![TxIn::default()],
+            output: vec![
+                TxOut { value: Amount::from_sat(60_000), script_pubkey: script_pubkey.clone() },
+                TxOut { value: Amount::from_sat(40_000), script_pubkey },
+            ],
+        };
+        (key, tx)
+    }
+
+    #[test]
+    fn test_psbt_round_trip() {
+        let (key, funding) = funding();
+        let txid = funding.compute_txid().to_string();
+        let mut request = request("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080");
+        request.outputs[0].amount = Some(99_000);
+        request.inputs = vec![
+            TxInputRequest { txid: txid.clone(), vout: 0, prev_tx_hex: Some(serialize_hex(&funding)), ..Default::default() },
+            TxInputRequest {
+                txid,
+                vout: 1,
+                amount: Some(40_000),
+                script_pubkey: Some(funding.output[1].script_pubkey.to_hex_string()),
+                ..Default::default()
+            },
+        ];
+        let prevouts = psbt_inputs(&request).unwrap();
+        let (tx, _) = create(request, BtcNetwork::Regtest).unwrap();
+        let psbt = build_psbt(tx, prevouts, BTreeMap::new()).unwrap();
+
+        let mut psbt = Psbt::from_str(&psbt.to_string()).unwrap();
+        assert_eq!(psbt.inputs[0].witness_utxo.as_ref(), Some(&funding.output[0]));
+        assert_eq!(psbt.inputs[0].non_witness_utxo.as_ref(), Some(&funding));
+        assert_eq!(psbt.inputs[1].witness_utxo.as_ref(), Some(&funding.output[1]));
+        assert!(psbt.inputs[1].non_witness_utxo.is_none());
+
+        // Sign as an external signer would, then finalize and check the result
+        let signers: Vec<InputSigner> = funding
+            .output
+            .iter()
+            .map(|output| InputSigner {
+                private_key: key,
+                script_pubkey: output.script_pubkey.clone(),
+                amount: Some(output.value),
+                sighash_type: None,
+                redeem_script: None,
+            })
+            .collect();
+        let mut signed = psbt.unsigned_tx.clone();
+        sign_transaction(&mut signed, &signers, BtcNetwork::Regtest).unwrap();
+        for (input, signed_input) in psbt.inputs.iter_mut().zip(&signed.input) {
+            let signature = bitcoin::ecdsa::Signature::from_slice(signed_input.witness.nth(0).unwrap()).unwrap();
+            let public_key = bitcoin::PublicKey::from_slice(signed_input.witness.nth(1).unwrap()).unwrap();
+            input.partial_sigs.insert(public_key, signature);
+        }
+        assert!(finalize_psbt(&mut psbt).is_empty());
+        let tx = psbt.extract_tx().unwrap();
+        verify_transaction(&tx, &signers.iter().map(SpentOutput::from).collect::<Vec<_>>()).unwrap();
+    }
+
+    #[test]
+    fn test_psbt_inputs() {
+        let (_, funding) = funding();
+        // Without prevout data the PSBT is still made, for the caller to warn about
+        let bare = request("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080");
+        let prevouts = psbt_inputs(&bare).unwrap();
+        assert!(prevouts[0].is_none());
+        let (tx, _) = create(bare, BtcNetwork::Regtest).unwrap();
+        assert!(build_psbt(tx, prevouts, BTreeMap::new()).is_ok());
+
+        let mut request = request("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080");
+        request.inputs[0] = TxInputRequest {
+            txid: funding.compute_txid().to_string(),
+            vout: 0,
+            amount: Some(50_000),
+            prev_tx_hex: Some(serialize_hex(&funding)),
+            ..Default::default()
+        };
+        assert_eq!(
+            psbt_inputs(&request).unwrap_err(),
+            "Input 0: amount or script_pubkey differs from output 0 of prev_tx_hex"
+        );
+        request.inputs[0].amount = None;
+        request.inputs[0].vout = 2;
+        assert_eq!(psbt_inputs(&request).unwrap_err(), "Input 0: prev_tx_hex has no output 2");
+    }
+
+    // fetch_utxos output for the test key's regtest address, with a field it does not know about
+    #[test]
+    fn test_sweep() {
+        let (key, funding) = funding();
+        let txid = funding.compute_txid().to_string();
+        let destination = "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080";
+        let mut sweep = request(destination);
+        sweep.outputs.clear();
+        sweep.fee_rate = Some(3.0);
+        sweep.sweep_to = Some(destination.to_string());
+        // Amounts and types come from the previous transaction
+        sweep.inputs = (0..2)
+            .map(|vout| TxInputRequest {
+                txid: txid.clone(),
+                vout,
+                prev_tx_hex: Some(serialize_hex(&funding)),
+                ..Default::default()
+            })
+            .collect();
+        let (mut tx, summary) = create(sweep, BtcNetwork::Regtest).unwrap();
+        let summary = summary.unwrap();
+        assert_eq!(tx.output.len(), 1);
+        assert_eq!(summary.change, None);
+        assert_eq!(tx.output[0].value.to_sat(), 100_000 - summary.fee);
+
+        // The fee matches the rate on the signed size
+        let signers: Vec<InputSigner> = funding
+            .output
+            .iter()
+            .map(|output| InputSigner {
+                private_key: key,
+                script_pubkey: output.script_pubkey.clone(),
+                amount: Some(output.value),
+                sighash_type: None,
+                redeem_script: None,
+            })
+            .collect();
+        sign_transaction(&mut tx, &signers, BtcNetwork::Regtest).unwrap();
+        let rate = summary.fee as f64 / tx.vsize() as f64;
+        assert!((rate - 3.0).abs() <= 1.0, "{} sat/vB", rate);
+
+        let sweep_request = |fee_rate: f64| {
+            let mut sweep = request(destination);
+            sweep.inputs[0].amount = Some(1_000);
+            sweep.inputs[0].input_type = Some("p2wpkh".to_string());
+            sweep.outputs.clear();
+            sweep.fee_rate = Some(fee_rate);
+            sweep.sweep_to = Some(destination.to_string());
+            sweep
+        };
+        let error = create(sweep_request(10.0), BtcNetwork::Regtest).unwrap_err();
+        assert!(error.contains("less than the 294 sat dust limit"), "{}", error);
+        let mut with_outputs = sweep_request(1.0);
+        with_outputs.outputs = request(destination).outputs;
+        assert!(create(with_outputs, BtcNetwork::Regtest).unwrap_err().starts_with("A sweep pays one address only"));
+        let mut with_change = sweep_request(1.0);
+        with_change.change_address = Some(CHANGE.to_string());
+        assert!(create(with_change, BtcNetwork::Regtest).unwrap_err().starts_with("A sweep pays one address only"));
+    }
+
+    #[test]
+    fn test_size_report() {
+        let (key, funding) = funding();
+        let txid = funding.compute_txid().to_string();
+        let public_key = CompressedPublicKey::from_private_key(&Secp256k1::new(), &key).unwrap();
+        let mut spend = request("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080");
+        spend.outputs[0].amount = Some(99_000);
+        // One input typed by its prevout, the other by its address
+        spend.inputs = vec![
+            TxInputRequest { txid: txid.clone(), vout: 0, prev_tx_hex: Some(serialize_hex(&funding)), ..Default::default() },
+            TxInputRequest {
+                txid,
+                vout: 1,
+                address: Some(bitcoin::Address::p2wpkh(&public_key, Network::Regtest).to_string()),
+                ..Default::default()
+            },
+        ];
+        let types = input_types(&spend, BtcNetwork::Regtest).unwrap();
+        assert_eq!(types, vec![(InputType::P2wpkh, false), (InputType::P2wpkh, false)]);
+        let (mut tx, _) = create(spend, BtcNetwork::Regtest).unwrap();
+        let report = size_report(&tx, &types, Some(2.0));
+        assert_eq!(report.projected_fee, Some(report.vsize * 2));
+        assert_eq!(report.outputs[0].dust_limit, Some(294));
+        assert_eq!(report.outputs[0].dust_margin, Some(98_706));
+
+        // The projection holds once the transaction is signed
+        let signers: Vec<InputSigner> = funding
+            .output
+            .iter()
+            .map(|output| InputSigner {
+                private_key: key,
+                script_pubkey: output.script_pubkey.clone(),
+                amount: Some(output.value),
+                sighash_type: None,
+                redeem_script: None,
+            })
+            .collect();
+        sign_transaction(&mut tx, &signers, BtcNetwork::Regtest).unwrap();
+        assert!(report.weight >= tx.weight().to_wu() && report.weight - tx.weight().to_wu() <= 4, "{} WU", report.weight);
+        assert!(report.vsize >= tx.vsize() as u64 && report.vsize - tx.vsize() as u64 <= 1, "{} vB", report.vsize);
+
+        // Untyped inputs are sized as the worst case, and outputs below dust show a negative margin
+        let mut bare = request("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080");
+        bare.outputs[0].amount = Some(200);
+        let types = input_types(&bare, BtcNetwork::Regtest).unwrap();
+        assert_eq!(types, vec![(WORST_CASE_INPUT, true)]);
+        let (tx, _) = create(bare, BtcNetwork::Regtest).unwrap();
+        let report = size_report(&tx, &types, None);
+        assert_eq!(report.projected_fee, None);
+        assert_eq!(report.outputs[0].dust_margin, Some(-94));
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["inputs"][0], serde_json::json!({"index": 0, "type": "p2pkh", "assumed": true}));
+        assert!(json.get("fee_rate").is_none());
+    }
+
+    const FETCHED_UTXOS: &str = r#"[
+  {
+    "txid": "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+    "vout": 0,
+    "status": {
+      "confirmed": true,
+      "block_height": 120,
+      "block_hash": "0f9188f13cb7b2c71f2a335e3a4fc328bf5beb436012afca590b1a11466e2206",
+      "block_time": 1296688602
+    },
+    "value": 10000,
+    "address": null,
+    "script_pubkey": null
+  },
+  {
+    "txid": "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16",
+    "vout": 1,
+    "status": { "confirmed": true, "block_height": 120 },
+    "value": 25110,
+    "address": null,
+    "script_pubkey": null
+  },
+  {
+    "txid": "0437cd7f8525ceed2324359c2d0ba26006d92d856a9c20fa0241106ee5a597c9",
+    "vout": 0,
+    "status": { "confirmed": false },
+    "value": 60000,
+    "valuecommitment": null
+  }
+]"#;
+
+    fn select_request(amount: u64) -> SelectRequest {
+        let (key, _) = funding();
+        let public_key = CompressedPublicKey::from_private_key(&Secp256k1::new(), &key).unwrap();
+        let json = format!(
+            r#"{{"utxos": {}, "utxo_address": "{}", "outputs": [{{"address": "{}", "amount": {}}}], "fee_rate": 1.0, "change_address": "{}"}}"#,
+            FETCHED_UTXOS,
+            bitcoin::Address::p2wpkh(&public_key, Network::Regtest),
+            "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080",
+            amount,
+            CHANGE
+        );
+        assert!(matches!(serde_json::from_str(&json), Ok(RequestShape { utxos: Some(_) })));
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn test_selects_exact_match() {
+        // 25110 sats pay 25000 and the 110 vB at 1 sat/vB with nothing left
+        let (tx, report) = select_coins(select_request(25_000), BtcNetwork::Regtest).unwrap();
+        assert_eq!(report.selected.len(), 1);
+        assert_eq!(report.selected[0].amount, Amount::from_sat(25_110));
+        assert_eq!((report.fee, report.change, report.vsize), (Amount::from_sat(110), None, 110));
+        assert_eq!(tx.output.len(), 1);
+    }
+
+    #[test]
+    fn test_selects_with_change() {
+        // No changeless combination pays 50000, so the largest UTXO is spent with change
+        let (tx, report) = select_coins(select_request(50_000), BtcNetwork::Regtest).unwrap();
+        assert_eq!(report.selected.len(), 1);
+        assert_eq!(report.selected[0].amount, Amount::from_sat(60_000));
+        assert_eq!((report.fee, report.change, report.vsize), (Amount::from_sat(141), Some(Amount::from_sat(9_859)), 141));
+        assert_eq!(tx.output[1].script_pubkey, parse_address(CHANGE, BtcNetwork::Regtest).unwrap().script_pubkey());
+
+        let mut request = select_request(50_000);
+        request.utxo_address = None;
+        let error = select_coins(request, BtcNetwork::Regtest).unwrap_err();
+        assert!(error.starts_with("UTXO 0 has no address or script_pubkey"), "{}", error);
+
+        assert!(select_coins(select_request(100_000), BtcNetwork::Regtest).is_err());
+    }
+
+    #[test]
+    fn test_amount_strings() {
+        let json = r#"{
+            "inputs": [
+                {"txid": "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16", "vout": 0, "amount": "0.001 BTC", "type": "p2wpkh"},
+                {"txid": "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16", "vout": 1, "amount": 50000, "type": "p2wpkh"}
+            ],
+            "outputs": [
+                {"address": "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080", "amount": "1_000 sat"},
+                {"address": "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080", "amount": "0.0014"},
+                {"address": "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080", "amount": 2000}
+            ],
+            "fee": "0.5 mBTC"
+        }"#;
+        let request: CreateTxRequest = serde_json::from_str(json).unwrap();
+        assert_eq!(request.inputs.iter().map(|i| i.amount).collect::<Vec<_>>(), [Some(100_000), Some(50_000)]);
+        assert_eq!(request.outputs.iter().map(|o| o.amount).collect::<Vec<_>>(), [Some(1_000), Some(140_000), Some(2_000)]);
+        assert_eq!(request.fee, Some(50_000));
+        assert_eq!(
+            describe_amounts(&request.inputs, &request.outputs[..1], request.fee),
+            [
+                "Input 0 amount: 100000 sats (0.001 BTC)",
+                "Input 1 amount: 50000 sats (0.0005 BTC)",
+                "Output 0 amount: 1000 sats (0.00001 BTC)",
+                "Fee: 50000 sats (0.0005 BTC)",
+            ]
+        );
+        // 150000 in, 143000 out and 50000 fee is 43000 short
+        let error = create(request, BtcNetwork::Regtest).unwrap_err();
+        assert!(error.ends_with("by 43000 sats"), "{}", error);
+
+        // A whole number in a string could be BTC or sats, so it needs a unit
+        let bare = json.replace(r#""1_000 sat""#, r#""1000""#);
+        let error = serde_json::from_str::<CreateTxRequest>(&bare).err().unwrap().to_string();
+        assert!(error.contains("give a unit, such as BTC or sat"), "{}", error);
+    }
+
+    #[test]
+    fn test_resolve_network() {
+        assert_eq!(resolve_network(Some("regtest"), Some("testnet".to_string())), Ok(BtcNetwork::Regtest));
+        assert_eq!(resolve_network(None, Some("signet".to_string())), Ok(BtcNetwork::Signet));
+        assert_eq!(resolve_network(None, None), Ok(BtcNetwork::Bitcoin));
+        assert!(resolve_network(Some("litecoin"), None).is_err());
+    }
+}
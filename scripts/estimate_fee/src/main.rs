@@ -1,66 +1,120 @@
-use reqwest::blocking::get;
-use serde::Deserialize;
-use std::collections::HashMap;
+use btcx_lib::network::{BlockstreamClient, MEMPOOL_SPACE_URL};
+use btcx_lib::BtcNetwork;
 use std::env;
 use std::process;
 
-#[derive(Deserialize, Debug)]
-struct FeeEstimates {
-    #[serde(flatten)]
-    estimates: HashMap<String, f64>,
+fn print_usage(program: &str) {
+    eprintln!("Usage: {} [--recommended] [--esplora-url <url>] [--esplora-token <token>] [blocks]", program);
+    eprintln!("  blocks: (optional) target confirmation blocks (e.g., 1, 3, 6, 12, 25)");
+    eprintln!("  --recommended: use mempool.space fee recommendations instead of Esplora estimates");
+    eprintln!("  --esplora-url: Esplora base URL (default: $BTCX_ESPLORA_MAINNET_URL or blockstream.info;");
+    eprintln!("                mempool.space with --recommended)");
+    eprintln!("  --esplora-token: bearer token for the Esplora server (default: $BTCX_ESPLORA_TOKEN)");
 }
 
-fn main() {
+fn build_client(base_url: Option<&str>, token: Option<&str>) -> BlockstreamClient {
+    let mut builder = BlockstreamClient::builder(BtcNetwork::Bitcoin);
+    if let Some(base_url) = base_url {
+        builder = builder.base_url(base_url);
+    }
+    if let Some(token) = token {
+        builder = builder.bearer_token(token);
+    }
+    match builder.build() {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Error creating client: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() > 2 {
-        eprintln!("Usage: {} [blocks]", args[0]);
-        eprintln!("  blocks: (optional) target confirmation blocks (e.g., 1, 3, 6, 12, 25)");
+    let mut recommended = false;
+    let mut esplora_url: Option<String> = None;
+    let mut esplora_token: Option<String> = None;
+    let mut positional: Vec<&String> = Vec::new();
+
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--recommended" => recommended = true,
+            "--esplora-url" | "--esplora-token" => {
+                let Some(value) = rest.next() else {
+                    eprintln!("Error: {} requires a value", arg);
+                    process::exit(1);
+                };
+                if arg == "--esplora-url" {
+                    esplora_url = Some(value.clone());
+                } else {
+                    esplora_token = Some(value.clone());
+                }
+            }
+            flag if flag.starts_with("--") => {
+                print_usage(&args[0]);
+                process::exit(1);
+            }
+            _ => positional.push(arg),
+        }
+    }
+
+    if positional.len() > 1 {
+        print_usage(&args[0]);
         process::exit(1);
     }
 
-    let target_blocks: Option<u32> = args.get(1).and_then(|s| s.parse().ok());
+    if recommended {
+        if !positional.is_empty() {
+            eprintln!("Error: --recommended does not take a blocks target");
+            process::exit(1);
+        }
+
+        let client = build_client(
+            Some(esplora_url.as_deref().unwrap_or(MEMPOOL_SPACE_URL)),
+            esplora_token.as_deref(),
+        );
 
-    let url = "https://blockstream.info/api/fee-estimates";
-    match get(url) {
-        Ok(response) => {
-            if !response.status().is_success() {
-                eprintln!(
-                    "Error: HTTP {} - Failed to fetch fee estimates",
-                    response.status()
-                );
+        match client.get_recommended_fees().await {
+            Ok(fees) => {
+                println!("Recommended fees (sat/vByte):");
+                println!("  fastest:   {:.1}", fees.fastest_fee);
+                println!("  half hour: {:.1}", fees.half_hour_fee);
+                println!("  hour:      {:.1}", fees.hour_fee);
+                println!("  economy:   {:.1}", fees.economy_fee);
+                println!("  minimum:   {:.1}", fees.minimum_fee);
+            }
+            Err(e) => {
+                eprintln!("Error fetching recommended fees: {}", e);
                 process::exit(1);
             }
+        }
+        return;
+    }
 
-            match response.json::<FeeEstimates>() {
-                Ok(fee_estimates) => {
-                    if let Some(target) = target_blocks {
-                        // Show fee for a specific target
-                        let target_key = target.to_string();
-                        if let Some(fee_rate) = fee_estimates.estimates.get(&target_key) {
-                            // Convert from sat/vByte (the API returns sat/vByte)
-                            println!("Fee estimate for {} blocks: {:.1} sat/vByte", target, fee_rate);
-                        } else {
-                            eprintln!("No fee estimate available for {} blocks", target);
-                            process::exit(1);
-                        }
-                    } else {
-                        // Show all fee estimates
-                        println!("Fee estimates (sat/vByte):");
-                        let mut sorted_keys: Vec<&String> = fee_estimates.estimates.keys().collect();
-                        sorted_keys.sort_by_key(|k| k.parse::<u32>().unwrap_or(0));
-                        for key in sorted_keys {
-                            if let Ok(blocks) = key.parse::<u32>() {
-                                let fee_rate = fee_estimates.estimates.get(key).unwrap();
-                                println!("  {:>3} blocks: {:.1} sat/vByte", blocks, fee_rate);
-                            }
-                        }
-                    }
-                }
-                Err(e) => {
-                    eprintln!("Error parsing fee estimates: {}", e);
+    let target_blocks: Option<u32> = positional.first().and_then(|s| s.parse().ok());
+
+    let client = build_client(esplora_url.as_deref(), esplora_token.as_deref());
+
+    match client.get_fee_estimates().await {
+        Ok(estimates) => {
+            if let Some(target) = target_blocks {
+                // Show fee for a specific target
+                if let Some(fee_rate) = estimates.get(&target) {
+                    // The API returns sat/vByte
+                    println!("Fee estimate for {} blocks: {:.1} sat/vByte", target, fee_rate);
+                } else {
+                    eprintln!("No fee estimate available for {} blocks", target);
                     process::exit(1);
                 }
+            } else {
+                // Show all fee estimates
+                println!("Fee estimates (sat/vByte):");
+                for (blocks, fee_rate) in &estimates {
+                    println!("  {:>4} blocks: {:.1} sat/vByte", blocks, fee_rate);
+                }
             }
         }
         Err(e) => {
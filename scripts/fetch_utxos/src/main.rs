@@ -1,66 +1,96 @@
-use reqwest::blocking::get;
-use serde::{Deserialize, Serialize};
+use btcx_lib::network::BlockstreamClient;
+use btcx_lib::utils::network_for_address;
+use btcx_lib::{BtcNetwork, Error};
 use std::env;
-use std::io;
 use std::process;
 
-#[derive(Deserialize, Serialize, Debug)]
-struct Utxo {
-    txid: String,
-    vout: u32,
-    status: UtxoStatus,
-    value: u64,
-    #[serde(default)]
-    address: Option<String>,
-    #[serde(default)]
-    script_pubkey: Option<String>,
+fn print_usage(program: &str) {
+    eprintln!("Usage: {} [--network <network>] [--esplora-url <url>] [--esplora-token <token>] <address>", program);
+    eprintln!("  --esplora-url: Esplora base URL (default: the network's from the environment or blockstream.info)");
+    eprintln!("  --esplora-token: bearer token for the Esplora server (default: $BTCX_ESPLORA_TOKEN)");
 }
 
-#[derive(Deserialize, Serialize, Debug)]
-struct UtxoStatus {
-    confirmed: bool,
-    #[serde(default)]
-    block_height: Option<u64>,
-    #[serde(default)]
-    block_hash: Option<String>,
-    #[serde(default)]
-    block_time: Option<u64>,
-}
-
-fn main() -> io::Result<()> {
+#[tokio::main]
+async fn main() {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() != 2 {
-        eprintln!("Usage: {} <address>", args[0]);
+    let mut network: Option<BtcNetwork> = None;
+    let mut esplora_url: Option<String> = None;
+    let mut esplora_token: Option<String> = None;
+    let mut positional = Vec::new();
+
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--network" | "--esplora-url" | "--esplora-token" => {
+                let Some(value) = rest.next() else {
+                    eprintln!("Error: {} requires a value", arg);
+                    process::exit(1);
+                };
+                match arg.as_str() {
+                    "--network" => {
+                        network = match value.parse() {
+                            Ok(network) => Some(network),
+                            Err(e) => {
+                                eprintln!("Error: {}", e);
+                                process::exit(1);
+                            }
+                        }
+                    }
+                    "--esplora-url" => esplora_url = Some(value.clone()),
+                    _ => esplora_token = Some(value.clone()),
+                }
+            }
+            flag if flag.starts_with("--") => {
+                print_usage(&args[0]);
+                process::exit(1);
+            }
+            _ => positional.push(arg.as_str()),
+        }
+    }
+
+    if positional.len() != 1 {
+        print_usage(&args[0]);
         process::exit(1);
     }
 
-    let address = args[1].trim();
-    let url = format!("https://blockstream.info/api/address/{}/utxo", address);
+    let address = positional[0].trim();
+    // Without --network, pick the network the address belongs to
+    let network = match network.map_or_else(|| network_for_address(address), Ok) {
+        Ok(network) => network,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(1);
+        }
+    };
 
-    match get(&url) {
-        Ok(response) => {
-            if !response.status().is_success() {
-                eprintln!("Error: HTTP {} - Failed to fetch UTXOs for address {}", response.status(), address);
-                process::exit(1);
-            }
+    let mut builder = BlockstreamClient::builder(network);
+    if let Some(url) = &esplora_url {
+        builder = builder.base_url(url);
+    }
+    if let Some(token) = &esplora_token {
+        builder = builder.bearer_token(token);
+    }
+    let client = match builder.build() {
+        Ok(client) => client,
+        Err(e) => {
+            eprintln!("Error creating client: {}", e);
+            process::exit(1);
+        }
+    };
 
-            match response.json::<Vec<Utxo>>() {
-                Ok(utxos) => {
-                    let json = serde_json::to_string_pretty(&utxos).unwrap();
-                    println!("{}", json);
-                }
-                Err(e) => {
-                    eprintln!("Error parsing JSON for address {}: {}", address, e);
-                    process::exit(1);
-                }
-            }
+    match client.get_utxos(address).await {
+        Ok(utxos) => {
+            let json = serde_json::to_string_pretty(&utxos).unwrap();
+            println!("{}", json);
+        }
+        Err(Error::Http { status, body, .. }) => {
+            eprintln!("Error: HTTP {} - Failed to fetch UTXOs for address {}: {}", status, address, body.trim());
+            process::exit(1);
         }
         Err(e) => {
             eprintln!("Error fetching data for address {}: {}", address, e);
             process::exit(1);
         }
     }
-
-    Ok(())
 }
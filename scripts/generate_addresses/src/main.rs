@@ -1,102 +1,2234 @@
-use bip39::{Mnemonic, Language};
-use bip32::{ExtendedPrivateKey, ChildNumber, DerivationPath};
-use bitcoin::{Network, PrivateKey, PublicKey, Address};
-use hex;
-use k256::ecdsa::SigningKey;
-use bitcoin::secp256k1::SecretKey;
+use std::borrow::Cow;
+use std::env;
+use std::fmt::{self, Write as _};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write as _};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use bitcoin::{Amount, Denomination, Network, PrivateKey, PublicKey};
+use bitcoin::bip32::{ChildNumber, DerivationPath, Xpriv, Xpub};
+use bitcoin::secp256k1::{All, Secp256k1};
+use bitcoin::{Address, CompressedPublicKey, NetworkKind};
+use btcx_lib::hd::{self, ScriptKind};
+use btcx_lib::mnemonic::{self, Language};
+use btcx_lib::multisig::MultisigScript;
+use btcx_lib::network::{AddressInfo, BlockstreamClient};
+use btcx_lib::types::{AddressType, BtcNetwork, KeyPair};
+use futures::future::join_all;
+use serde::Serialize;
+use zeroize::Zeroizing;
 
-fn main() {
-    // Collect command-line arguments
-    let args: Vec<String> = std::env::args().collect();
-    
-    // Validate argument count
-    if args.len() < 3 || args.len() > 4 {
-        println!("Usage: {} <mnemonic_phrase> <derivation_path> [passphrase]", args[0]);
-        return;
-    }
-    
-    // Parse arguments
-    let mnemonic_phrase = args[1].trim();
-    let derivation_path_str = args[2].trim();
-    let passphrase = if args.len() == 4 { args[3].as_str() } else { "" };
-
-    // Display inputs for verification
-    println!("Mnemonic Phrase: {}", mnemonic_phrase);
-    println!("Derivation Path: {}", derivation_path_str);
-    println!("Passphrase: {}", if passphrase.is_empty() { "<empty>" } else { "<redacted>" });
-
-    // Parse mnemonic
-    let mnemonic = match Mnemonic::parse_in(Language::English, mnemonic_phrase) {
-        Ok(m) => m,
-        Err(e) => {
-            println!("Error: Invalid mnemonic phrase provided: {}", e);
-            return;
+#[cfg(feature = "qr")]
+mod qr;
+
+// Environment variable giving the network when --network is not passed
+const NETWORK_ENV: &str = "BTCX_NETWORK";
+
+// Extended keys taken in place of a mnemonic: public ones by any SLIP-132 prefix, private ones
+// only as xprv/tprv; the other private prefixes are recognised so they can be refused unechoed
+const XPUB_PREFIXES: [&str; 6] = ["xpub", "ypub", "zpub", "tpub", "upub", "vpub"];
+const XPRV_PREFIXES: [&str; 6] = ["xprv", "yprv", "zprv", "tprv", "uprv", "vprv"];
+
+// One derived child, as printed; an xpub gives no private key. The secrets are wiped on drop
+struct DerivedAddress {
+    path: String,
+    address: String,
+    public_key: String,
+    private_key: Option<Zeroizing<String>>,
+    wif: Option<Zeroizing<String>>,
+}
+
+// Which children of the parent path to derive
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Children {
+    start: u32,
+    count: u32,
+    hardened: bool,
+}
+
+// Child indexes from 2^31 up are the hardened ones, so a range must end below that
+const HARDENED_START: u64 = 1 << 31;
+
+impl Children {
+    // Function to check that the range is not empty and stays below 2^31
+    fn new(start: u32, count: u32, hardened: bool) -> Result<Self, String> {
+        if count == 0 {
+            return Err("--count must be at least 1".to_string());
+        }
+        if start as u64 + count as u64 > HARDENED_START {
+            return Err(format!(
+                "--start-index {} with --count {} goes past index {}; child indexes must stay below 2^31",
+                start,
+                count,
+                HARDENED_START - 1
+            ));
+        }
+        Ok(Children { start, count, hardened })
+    }
+}
+
+impl Default for Children {
+    fn default() -> Self {
+        Children { start: 0, count: 10, hardened: false }
+    }
+}
+
+// The index of a hardened step, e.g. 84 for 84'
+fn hardened_index(child: &ChildNumber) -> Option<u32> {
+    match *child {
+        ChildNumber::Hardened { index } => Some(index),
+        ChildNumber::Normal { .. } => None,
+    }
+}
+
+// The address type whose standard BIP43 purpose this is: 44' (BIP44), 49' (BIP49), 84' (BIP84)
+// or 86' (BIP86)
+fn purpose_address_type(purpose: u32) -> Option<AddressType> {
+    match purpose {
+        44 => Some(AddressType::P2pkh),
+        49 => Some(AddressType::P2shP2wpkh),
+        84 => Some(AddressType::P2wpkh),
+        86 => Some(AddressType::P2tr),
+        _ => None,
+    }
+}
+
+// Function to warn when the path starts with the standard purpose of another address type, as
+// p2pkh addresses from m/84'/0'/0' are not the ones a wallet restoring that path will find
+fn purpose_warning(derivation_path: &DerivationPath, address_type: AddressType) -> Option<String> {
+    let purpose = derivation_path.into_iter().next().and_then(hardened_index)?;
+    let expected = purpose_address_type(purpose)?;
+    (expected != address_type).then(|| {
+        format!(
+            "WARNING: {}' is the standard purpose for {} addresses, not {}; pass --address-type {} to match it",
+            purpose,
+            expected,
+            address_type,
+            expected
+        )
+    })
+}
+
+// Function to warn when the coin type of a standard path, the level after the purpose, is for
+// another network: 0' is mainnet's and 1' that of every test network
+fn coin_type_warning(derivation_path: &DerivationPath, network: BtcNetwork) -> Option<String> {
+    let mut levels = derivation_path.into_iter();
+    purpose_address_type(levels.next().and_then(hardened_index)?)?;
+    let coin_type = levels.next().and_then(hardened_index)?;
+    let expected = if network == BtcNetwork::Bitcoin { 0 } else { 1 };
+    (coin_type <= 1 && coin_type != expected).then(|| {
+        let meant = if coin_type == 0 { "mainnet" } else { "test networks" };
+        format!(
+            "WARNING: coin type {}' is for {}, but the network is {}; {}' is the coin type for {}",
+            coin_type, meant, network, expected, network
+        )
+    })
+}
+
+// The network from --network, else from the environment, else mainnet
+fn resolve_network(flag: Option<&str>, env: Option<String>) -> Result<BtcNetwork, String> {
+    match flag.map(str::to_string).or(env) {
+        Some(name) => BtcNetwork::from_str(&name)
+            .map_err(|_| format!("Unknown network {:?}; use mainnet, testnet, signet or regtest", name)),
+        None => Ok(BtcNetwork::Bitcoin),
+    }
+}
+
+// The key the addresses are derived from, the master key or the xprv given, wiped on drop
+struct RootKey(Xpriv);
+
+impl Drop for RootKey {
+    fn drop(&mut self) {
+        self.0.private_key.non_secure_erase();
+    }
+}
+
+// Function to derive addresses of the given type for a range of children of the parent path.
+// The parent key is derived once, and a failure names the child it happened at
+fn derive_addresses(
+    secp: &Secp256k1<All>,
+    master: &Xpriv,
+    derivation_path_str: &str,
+    address_type: AddressType,
+    children: Children,
+    network: BtcNetwork,
+) -> Result<Vec<DerivedAddress>, String> {
+    // Parse the derivation path
+    let derivation_path = derivation_path_str
+        .parse::<DerivationPath>()
+        .map_err(|e| format!("Invalid derivation path: {}", e))?;
+
+    // Derive parent key
+    let mut parent_xprv = master
+        .derive_priv(secp, &derivation_path)
+        .map_err(|e| format!("Cannot derive {}: {}", derivation_path_str, e))?;
+
+    let mut derived = Vec::with_capacity(children.count as usize);
+    for index in children.start..children.start + children.count {
+        let mut path = String::with_capacity(derivation_path_str.len() + 12);
+        let _ = write!(path, "{}/{}{}", derivation_path_str, index, if children.hardened { "'" } else { "" });
+        let failed = |e: &dyn std::fmt::Display| format!("Cannot derive {}: {}", path, e);
+        let child_number = if children.hardened {
+            ChildNumber::from_hardened_idx(index)
+        } else {
+            ChildNumber::from_normal_idx(index)
+        };
+        let child_number = child_number.map_err(|e| failed(&e))?;
+        let mut child_xprv = parent_xprv.derive_priv(secp, &[child_number]).map_err(|e| failed(&e))?;
+
+        // Extract private key; the copies of it here are wiped
+        let privkey = PrivateKey::new(child_xprv.private_key, Network::from(network));
+        child_xprv.private_key.non_secure_erase();
+        let private_key_hex = Zeroizing::new(hex::encode(Zeroizing::new(privkey.inner.secret_bytes())));
+        let wif = Zeroizing::new(privkey.to_wif());
+
+        // Derive public key (compressed)
+        let public_key = privkey.public_key(secp);
+        let public_key_hex = public_key.to_string();
+
+        // Generate the address from the compressed key; p2tr commits to the BIP86 tweak of its
+        // x-only half, with no script tree
+        let mut key_pair = KeyPair { private_key: privkey, public_key, network };
+        let address = key_pair.address(&address_type);
+        key_pair.private_key.inner.non_secure_erase();
+        let address = address.map_err(|e| format!("Cannot make a {} address: {}", address_type, e))?;
+
+        derived.push(DerivedAddress {
+            path,
+            address: address.to_string(),
+            public_key: public_key_hex,
+            private_key: Some(private_key_hex),
+            wif: Some(wif),
+        });
+    }
+    parent_xprv.private_key.non_secure_erase();
+    Ok(derived)
+}
+
+// The address type a SLIP-132 prefix implies
+fn address_type_of(kind: ScriptKind) -> AddressType {
+    match kind {
+        ScriptKind::P2pkh => AddressType::P2pkh,
+        ScriptKind::P2shP2wpkh => AddressType::P2shP2wpkh,
+        ScriptKind::P2wpkh => AddressType::P2wpkh,
+        ScriptKind::P2tr => AddressType::P2tr,
+    }
+}
+
+// The script type of an address type, for descriptors
+fn script_kind_of(address_type: AddressType) -> ScriptKind {
+    match address_type {
+        AddressType::P2pkh => ScriptKind::P2pkh,
+        AddressType::P2shP2wpkh => ScriptKind::P2shP2wpkh,
+        AddressType::P2wpkh => ScriptKind::P2wpkh,
+        AddressType::P2tr => ScriptKind::P2tr,
+    }
+}
+
+// The account a derivation runs through: its key as a plain xpub/tpub, never a SLIP-132
+// ypub/zpub, and the receive (/0/*) and change (/1/*) descriptors a node or wallet imports
+struct Account {
+    xpub: Xpub,
+    receive: String,
+    change: String,
+}
+
+// Function to write the account's descriptors; `origin` is its key origin, e.g.
+// [73c5da0a/84h/0h/0h], or empty when not known
+fn account_descriptors(xpub: Xpub, origin: &str, address_type: AddressType) -> Result<Account, String> {
+    let kind = script_kind_of(address_type);
+    let descriptor = |branch: u32| kind.descriptor(&format!("{}{}/{}/*", origin, xpub, branch)).map_err(|e| e.to_string());
+    Ok(Account { receive: descriptor(0)?, change: descriptor(1)?, xpub })
+}
+
+// Function to find the account of a path below `root`: the key at its last hardened step, e.g.
+// m/84'/0'/0' for m/84'/0'/0'/0, with the root's fingerprint as the key origin
+fn account_of(root: &Xpriv, derivation_path_str: &str, address_type: AddressType) -> Result<Account, String> {
+    let derivation_path = derivation_path_str
+        .parse::<DerivationPath>()
+        .map_err(|e| format!("Invalid derivation path: {}", e))?;
+    let steps: &[ChildNumber] = derivation_path.as_ref();
+    let depth = steps.iter().rposition(|step| step.is_hardened()).map_or(0, |last| last + 1);
+    let account_path = DerivationPath::from(&steps[..depth]);
+    let secp = Secp256k1::new();
+    let account = root
+        .derive_priv(&secp, &account_path)
+        .map_err(|e| format!("Cannot derive the account key at {}: {}", account_path, e))?;
+    let mut account = account;
+    let xpub = Xpub::from_priv(&secp, &account);
+    account.private_key.non_secure_erase();
+    let origin = hd::key_origin(root.fingerprint(&secp), &account_path);
+    account_descriptors(xpub, &origin, address_type)
+}
+
+// Function to derive addresses from an extended public key, watch-only. The path is relative to
+// the key, e.g. m/0 for an account's receive addresses, and only normal children can be derived
+fn derive_watch_only(
+    secp: &Secp256k1<All>,
+    xpub: &Xpub,
+    derivation_path_str: &str,
+    address_type: AddressType,
+    children: Children,
+    network: BtcNetwork,
+) -> Result<Vec<DerivedAddress>, String> {
+    if xpub.network != NetworkKind::from(Network::from(network)) {
+        let kind = if xpub.network == NetworkKind::Main { "mainnet" } else { "the test networks" };
+        return Err(format!("The extended public key is for {}, but the network is {}; pass --network to match it", kind, network));
+    }
+    if children.hardened {
+        return Err("--hardened needs the private key; an extended public key derives only normal children".to_string());
+    }
+    let derivation_path = derivation_path_str
+        .parse::<DerivationPath>()
+        .map_err(|e| format!("Invalid derivation path: {}", e))?;
+    if let Some(child) = derivation_path.into_iter().find(|child| child.is_hardened()) {
+        return Err(format!(
+            "{} has the hardened step {}, which an extended public key cannot derive; give the path below the key, e.g. m/0",
+            derivation_path_str, child
+        ));
+    }
+
+    let network = Network::from(network);
+    let parent = xpub
+        .derive_pub(secp, &derivation_path)
+        .map_err(|e| format!("Cannot derive {}: {}", derivation_path_str, e))?;
+    let mut derived = Vec::with_capacity(children.count as usize);
+    for index in children.start..children.start + children.count {
+        let path = format!("{}/{}", derivation_path_str, index);
+        let child_number = ChildNumber::from_normal_idx(index).map_err(|e| format!("Cannot derive {}: {}", path, e))?;
+        let child = parent.ckd_pub(secp, child_number).map_err(|e| format!("Cannot derive {}: {}", path, e))?;
+        let public_key = CompressedPublicKey(child.public_key);
+        let address = match address_type {
+            AddressType::P2pkh => Address::p2pkh(public_key, network),
+            AddressType::P2shP2wpkh => Address::p2shwpkh(&public_key, network),
+            AddressType::P2wpkh => Address::p2wpkh(&public_key, network),
+            AddressType::P2tr => Address::p2tr(secp, child.public_key.x_only_public_key().0, None, network),
+        };
+        derived.push(DerivedAddress {
+            path,
+            address: address.to_string(),
+            public_key: public_key.to_string(),
+            private_key: None,
+            wif: None,
+        });
+    }
+    Ok(derived)
+}
+
+// How the derived addresses are written to stdout or the --output file
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Format {
+    Text,
+    Json,
+    Jsonl,
+    Csv,
+    // Set by --export electrum
+    Electrum,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::Jsonl),
+            "csv" => Ok(Format::Csv),
+            _ => Err(format!("Unknown format {:?}; use json, jsonl, csv or text", s)),
+        }
+    }
+}
+
+// The wallet software --export writes for
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Export {
+    Electrum,
+}
+
+impl FromStr for Export {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "electrum" => Ok(Export::Electrum),
+            _ => Err(format!("Unknown export {:?}; use electrum", s)),
+        }
+    }
+}
+
+// The script type Electrum takes in front of an imported WIF, which it otherwise reads as p2pkh.
+// It has no p2tr import
+fn electrum_prefix(address_type: AddressType) -> Result<&'static str, String> {
+    match address_type {
+        AddressType::P2pkh => Ok("p2pkh"),
+        AddressType::P2shP2wpkh => Ok("p2wpkh-p2sh"),
+        AddressType::P2wpkh => Ok("p2wpkh"),
+        AddressType::P2tr => Err(
+            "Electrum cannot import p2tr private keys; leave out --show-secrets to export the addresses watch-only".to_string(),
+        ),
+    }
+}
+
+// A failure, and whether the usage should follow it
+#[derive(Debug, PartialEq)]
+enum CliError {
+    Usage(String),
+    Failed(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Usage(message) | CliError::Failed(message) => write!(f, "Error: {}", message),
+        }
+    }
+}
+
+impl From<String> for CliError {
+    fn from(message: String) -> Self {
+        CliError::Failed(message)
+    }
+}
+
+// Settings of --qr and --qr-file
+#[cfg_attr(not(feature = "qr"), allow(dead_code))]
+struct QrOptions<'a> {
+    // Draw the codes after the addresses
+    terminal: bool,
+    // The directory the SVG files go in
+    dir: Option<&'a str>,
+    // Set by --amount, making each code a BIP21 URI
+    amount: Option<Amount>,
+    // Set by --qr-secrets: the WIFs get codes too
+    secrets: bool,
+}
+
+// The command line, checked
+struct Options<'a> {
+    program: &'a str,
+    help: bool,
+    // The mnemonic, or an extended public key
+    source: &'a str,
+    derivation_path: &'a str,
+    passphrase: &'a str,
+    address_type: Option<AddressType>,
+    children: Children,
+    network_flag: Option<&'a str>,
+    format: Format,
+    // The file written in place of stdout
+    output: Option<&'a str>,
+    parallel: bool,
+    show_secrets: bool,
+    show_seed: bool,
+    show_xpub: bool,
+    show_descriptor: bool,
+    // Set by --scan
+    scan: Option<ScanSettings>,
+    // Set by --multisig, which takes only the path
+    multisig: Option<MultisigOptions<'a>>,
+    // Set by --qr or --qr-file
+    qr: Option<QrOptions<'a>>,
+}
+
+fn print_usage(program: &str, out: &mut dyn io::Write) -> io::Result<()> {
+    writeln!(
+        out,
+        "Usage: {} [--address-type <p2pkh|p2sh-p2wpkh|p2wpkh|p2tr>] [--count <n>] [--start-index <i>] [--hardened] \
+         [--network <mainnet|testnet|signet|regtest>] [--format <json|jsonl|csv|text>] [--output <file>] [--parallel] [--export electrum] [--qr] [--qr-file <dir>] [--amount <btc>] [--qr-secrets] [--show-secrets] [--show-seed] [--show-xpub] \
+         [--show-descriptor] [--scan [--gap-limit <n>] [--concurrency <n>]] <mnemonic_phrase> <derivation_path> [passphrase]",
+        program
+    )?;
+    writeln!(out, "       {} [options] <xprv|tprv> <derivation_path>", program)?;
+    writeln!(out, "       {} [options] <xpub|ypub|zpub|tpub|upub|vpub> <derivation_path>", program)?;
+    writeln!(
+        out,
+        "       {} [options] --multisig <m> --xpub <xpub> --xpub <xpub>... [--script-type <p2wsh|p2sh-p2wsh>] <derivation_path>",
+        program
+    )?;
+    writeln!(out, "  An extended private key in place of the mnemonic is the root the path starts from")?;
+    writeln!(out, "  An extended public key in place of the mnemonic derives watch-only: addresses and public keys,")?;
+    writeln!(out, "  along a path of normal children below the key (e.g. m/0)")?;
+    writeln!(out, "  --address-type: the addresses to generate (default: the type a ypub/zpub/upub/vpub implies, else p2pkh)")?;
+    writeln!(out, "  --count: how many children to derive (default: 10); --start-index: the first (default: 0)")?;
+    writeln!(out, "  --hardened: derive hardened children (i') instead of normal ones")?;
+    writeln!(out, "  --network: the network of the addresses and WIFs (default: ${} or mainnet)", NETWORK_ENV)?;
+    writeln!(out, "  --format: json (an array of objects), jsonl (one object per line), csv (a header row, then one row per")?;
+    writeln!(out, "  address) or text (default)")?;
+    writeln!(out, "  --output: write to this file instead of stdout, created readable by its owner only with --show-secrets;")?;
+    writeln!(out, "  jsonl, csv and text are written {} addresses at a time, json only once all are derived", STREAM_CHUNK)?;
+    writeln!(out, "  --parallel: derive on every core, the addresses still written in order")?;
+    writeln!(out, "  --export electrum: one entry per line for Electrum's import, in place of --format: with --show-secrets")?;
+    writeln!(out, "  each WIF behind its script type (p2pkh:, p2wpkh-p2sh: or p2wpkh:), else the addresses, watch-only")?;
+    writeln!(out, "  --qr: draw each address's QR code after the addresses, with --format text; --qr-file: write each")?;
+    writeln!(out, "  as <index>.svg into the directory. A bech32 address is encoded in upper case, for a smaller code")?;
+    writeln!(out, "  --amount: encode a BIP21 URI asking for this many BTC instead of the bare address")?;
+    writeln!(out, "  --qr-secrets: with --show-secrets, also encode each WIF (<index>-wif.svg); never done otherwise")?;
+    writeln!(out, "  --show-secrets: include each private key (hex) and WIF, in any format")?;
+    writeln!(out, "  --show-seed: print the BIP39 seed (hex) to stderr; the mnemonic itself is never printed")?;
+    writeln!(out, "  --show-xpub: print the account's extended public key, the key at the path's last hardened step")?;
+    writeln!(out, "  --show-descriptor: print the account's receive (/0/*) and change (/1/*) descriptors")?;
+    writeln!(out, "  Both go to stdout before the addresses with --format text, and to stderr with json or csv")?;
+    writeln!(out, "  --scan: check each address from --start-index on with Esplora, printing its tx_count and balance, the")?;
+    writeln!(out, "  totals and the next unused index; stops after --gap-limit never-used addresses in a row (default: 20)")?;
+    writeln!(out, "  --concurrency: addresses checked at a time while scanning (default: 4)")?;
+    writeln!(out, "  --multisig: m-of-n addresses from the n --xpub account keys, along normal children below them, with")?;
+    writeln!(out, "  the keys sorted as sortedmulti() does; prints each witness script and descriptor (default: p2wsh)")?;
+    Ok(())
+}
+
+fn parse_args(args: &[String]) -> Result<Options<'_>, CliError> {
+    let usage = |message: String| Err(CliError::Usage(message));
+    let mut address_type: Option<AddressType> = None;
+    let mut start: u32 = 0;
+    let mut count: u32 = 10;
+    let mut hardened = false;
+    let mut network_flag: Option<&str> = None;
+    let mut format: Option<Format> = None;
+    let mut export: Option<Export> = None;
+    let mut output: Option<&str> = None;
+    let mut parallel = false;
+    let mut qr_terminal = false;
+    let mut qr_dir: Option<&str> = None;
+    let mut amount: Option<&str> = None;
+    let mut qr_secrets = false;
+    let mut show_secrets = false;
+    let mut show_seed = false;
+    let mut show_xpub = false;
+    let mut show_descriptor = false;
+    let mut scan = false;
+    let mut gap_limit: Option<u32> = None;
+    let mut concurrency: Option<u32> = None;
+    let mut required: Option<usize> = None;
+    let mut xpubs: Vec<&str> = Vec::new();
+    let mut script_type: Option<MultisigType> = None;
+    let mut help = false;
+    let mut positional: Vec<&str> = Vec::new();
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--address-type" | "--count" | "--start-index" | "--network" | "--format" | "--gap-limit" | "--concurrency"
+            | "--multisig" | "--xpub" | "--script-type" | "--output" | "--export" | "--qr-file" | "--amount" => {
+                let Some(value) = rest.next() else {
+                    return usage(format!("{} requires a value", arg));
+                };
+                match arg.as_str() {
+                    "--address-type" => match value.parse::<AddressType>() {
+                        Ok(parsed) => address_type = Some(parsed),
+                        Err(_) => return usage("--address-type must be p2pkh, p2sh-p2wpkh, p2wpkh or p2tr".to_string()),
+                    },
+                    "--count" | "--start-index" | "--gap-limit" | "--concurrency" => {
+                        let Ok(value) = value.parse::<u32>() else {
+                            return usage(format!("{} requires a number", arg));
+                        };
+                        match arg.as_str() {
+                            "--count" => count = value,
+                            "--start-index" => start = value,
+                            "--gap-limit" => gap_limit = Some(value),
+                            _ => concurrency = Some(value),
+                        }
+                    }
+                    "--network" => network_flag = Some(value),
+                    "--output" => output = Some(value),
+                    "--qr-file" => qr_dir = Some(value),
+                    "--amount" => amount = Some(value),
+                    "--export" => export = Some(value.parse().map_err(CliError::Usage)?),
+                    "--multisig" => match value.parse::<usize>() {
+                        Ok(value) => required = Some(value),
+                        Err(_) => return usage("--multisig requires a number".to_string()),
+                    },
+                    "--xpub" => xpubs.push(value.trim()),
+                    "--script-type" => script_type = Some(value.parse().map_err(CliError::Usage)?),
+                    _ => format = Some(value.parse().map_err(CliError::Usage)?),
+                }
+            }
+            "--hardened" => hardened = true,
+            "--parallel" => parallel = true,
+            "--qr" => qr_terminal = true,
+            "--qr-secrets" => qr_secrets = true,
+            "--show-secrets" => show_secrets = true,
+            "--show-seed" => show_seed = true,
+            "--show-xpub" => show_xpub = true,
+            "--show-descriptor" => show_descriptor = true,
+            "--scan" => scan = true,
+            "--help" | "-h" => help = true,
+            _ => positional.push(arg),
+        }
+    }
+
+    let multisig = match required {
+        Some(required) => {
+            if xpubs.is_empty() || required == 0 || required > xpubs.len() {
+                return usage(format!("--multisig {} needs at least that many --xpub keys, and at least 1", required));
+            }
+            if address_type.is_some() || scan || parallel || export.is_some() || show_secrets || show_seed || show_xpub || show_descriptor {
+                return usage("--multisig takes none of --address-type, --scan, --parallel, --export and the --show-* flags".to_string());
+            }
+            Some(MultisigOptions { required, xpubs, script_type: script_type.unwrap_or(MultisigType::P2wsh) })
+        }
+        None if !xpubs.is_empty() || script_type.is_some() => {
+            return usage("--xpub and --script-type apply only with --multisig".to_string());
+        }
+        None => None,
+    };
+
+    // Validate argument count; --multisig takes only the path
+    if multisig.is_some() {
+        if !help && positional.len() != 1 {
+            return usage("Give --multisig the derivation path below the xpubs, e.g. m/0, and nothing else".to_string());
+        }
+        positional.insert(0, "");
+    } else if !help && (positional.len() < 2 || positional.len() > 3) {
+        return usage("Give the mnemonic or extended public key, then the derivation path".to_string());
+    }
+    let children = Children::new(start, count, hardened).map_err(CliError::Usage)?;
+    let format = match (export, format) {
+        (Some(Export::Electrum), None) => Format::Electrum,
+        (Some(_), Some(_)) => return usage("--export writes its own format; leave out --format".to_string()),
+        (None, format) => format.unwrap_or(Format::Text),
+    };
+    let scan = match (scan, gap_limit, concurrency) {
+        (false, None, None) => None,
+        (false, _, _) => return usage("--gap-limit and --concurrency apply only with --scan".to_string()),
+        (true, Some(0), _) | (true, _, Some(0)) => return usage("--gap-limit and --concurrency must be at least 1".to_string()),
+        (true, _, _) if matches!(format, Format::Csv | Format::Jsonl | Format::Electrum) => {
+            return usage("--scan writes text or json, and has no --export".to_string())
+        }
+        (true, _, _) if parallel => return usage("--parallel applies to deriving, not to --scan".to_string()),
+        (true, gap_limit, concurrency) => {
+            let defaults = ScanSettings::default();
+            Some(ScanSettings {
+                gap_limit: gap_limit.unwrap_or(defaults.gap_limit),
+                concurrency: concurrency.unwrap_or(defaults.concurrency),
+                ..defaults
+            })
         }
     };
+    let qr = match (qr_terminal, qr_dir) {
+        (false, None) if amount.is_some() || qr_secrets => {
+            return usage("--amount and --qr-secrets apply only with --qr or --qr-file".to_string())
+        }
+        (false, None) => None,
+        _ if cfg!(not(feature = "qr")) => return usage("This build has no QR codes; build it with the qr feature".to_string()),
+        _ if scan.is_some() || multisig.is_some() || export.is_some() || parallel => {
+            return usage("--qr and --qr-file take none of --scan, --multisig, --export and --parallel".to_string())
+        }
+        (true, _) if format != Format::Text => return usage("--qr draws in the terminal, so only with --format text".to_string()),
+        _ if qr_secrets && !show_secrets => return usage("--qr-secrets needs --show-secrets as well".to_string()),
+        (terminal, dir) => {
+            let amount = match amount {
+                Some(value) => match Amount::from_str_in(value, Denomination::Bitcoin) {
+                    Ok(amount) if amount > Amount::ZERO => Some(amount),
+                    _ => return usage(format!("--amount {} is not an amount in BTC, e.g. 0.001", value)),
+                },
+                None => None,
+            };
+            Some(QrOptions { terminal, dir, amount, secrets: qr_secrets })
+        }
+    };
+    Ok(Options {
+        program: args.first().map_or("generate_addresses", String::as_str),
+        help,
+        source: positional.first().map_or("", |source| source.trim()),
+        derivation_path: positional.get(1).map_or("", |path| path.trim()),
+        passphrase: positional.get(2).copied().unwrap_or(""),
+        address_type,
+        children,
+        network_flag,
+        format,
+        output,
+        parallel,
+        show_secrets,
+        show_seed,
+        show_xpub,
+        show_descriptor,
+        scan,
+        multisig,
+        qr,
+    })
+}
+
+// Function to quote a CSV field when it holds a comma, quote or line break, doubling its quotes.
+// Other fields, keys among them, are not copied
+fn csv_field(field: &str) -> Cow<'_, str> {
+    if field.contains([',', '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+// Room for one rendered address, secrets included, in any format
+const RECORD_CAPACITY: usize = 512;
+
+// One row of --format json
+#[derive(Serialize)]
+struct AddressRecord<'a> {
+    path: &'a str,
+    address: &'a str,
+    public_key: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    private_key: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    wif: Option<&'a str>,
+}
+
+// Function to write out the derived addresses in the format asked for, with their private keys
+// and WIFs only when `show_secrets` is set
+fn render(
+    derived: &[DerivedAddress],
+    address_type: AddressType,
+    format: Format,
+    show_secrets: bool,
+) -> Result<Zeroizing<String>, CliError> {
+    // Sized up front so growing the buffer leaves no unwiped copies of the secrets behind
+    let mut out = Zeroizing::new(String::with_capacity(derived.len() * RECORD_CAPACITY));
+    render_into(&mut out, derived, address_type, format, show_secrets, true)?;
+    Ok(out)
+}
+
+// Function to append the derived addresses to `out`, which must have room for them so no copy
+// of the secrets is left behind. `header` is the CSV header row, written before the first batch
+fn render_into(
+    out: &mut String,
+    derived: &[DerivedAddress],
+    address_type: AddressType,
+    format: Format,
+    show_secrets: bool,
+    header: bool,
+) -> Result<(), CliError> {
+    fn shown(value: &Option<Zeroizing<String>>, show_secrets: bool) -> Option<&str> {
+        value.as_deref().map(String::as_str).filter(|_| show_secrets)
+    }
+    fn record(child: &DerivedAddress, show_secrets: bool) -> AddressRecord<'_> {
+        AddressRecord {
+            path: &child.path,
+            address: &child.address,
+            public_key: &child.public_key,
+            private_key: shown(&child.private_key, show_secrets),
+            wif: shown(&child.wif, show_secrets),
+        }
+    }
+    let secret = |value| shown(value, show_secrets);
+    match format {
+        Format::Json => {
+            let records: Vec<AddressRecord> = derived.iter().map(|child| record(child, show_secrets)).collect();
+            let mut json = Zeroizing::new(Vec::with_capacity(derived.len() * RECORD_CAPACITY));
+            serde_json::to_writer_pretty(&mut *json, &records).map_err(|e| e.to_string())?;
+            out.push_str(std::str::from_utf8(&json).map_err(|e| e.to_string())?);
+            out.push('\n');
+        }
+        Format::Jsonl => {
+            let mut json = Zeroizing::new(Vec::with_capacity(RECORD_CAPACITY));
+            for child in derived {
+                json.clear();
+                serde_json::to_writer(&mut *json, &record(child, show_secrets)).map_err(|e| e.to_string())?;
+                out.push_str(std::str::from_utf8(&json).map_err(|e| e.to_string())?);
+                out.push('\n');
+            }
+        }
+        Format::Csv => {
+            let with_secrets = show_secrets && derived.iter().any(|child| child.private_key.is_some());
+            if header {
+                out.push_str(if with_secrets { "path,address,public_key,private_key,wif\n" } else { "path,address,public_key\n" });
+            }
+            for child in derived {
+                let mut fields = [child.path.as_str(), child.address.as_str(), child.public_key.as_str(), "", ""];
+                let count = if with_secrets {
+                    fields[3] = secret(&child.private_key).unwrap_or("");
+                    fields[4] = secret(&child.wif).unwrap_or("");
+                    5
+                } else {
+                    3
+                };
+                for (i, field) in fields[..count].iter().enumerate() {
+                    if i > 0 {
+                        out.push(',');
+                    }
+                    out.push_str(&csv_field(field));
+                }
+                out.push('\n');
+            }
+        }
+        // Electrum reads every whitespace-separated word as a key or an address and refuses the
+        // whole list for any other, so no comment line can go with them
+        Format::Electrum => {
+            for child in derived {
+                match secret(&child.wif) {
+                    Some(wif) => {
+                        out.push_str(electrum_prefix(address_type)?);
+                        out.push(':');
+                        out.push_str(wif);
+                    }
+                    None => out.push_str(&child.address),
+                }
+                out.push('\n');
+            }
+        }
+        Format::Text => {
+            for child in derived {
+                let _ = write!(
+                    out,
+                    "{{\n  derivation_path: {}\n  address_type: {}\n  address: {}\n  public_key: {}\n",
+                    child.path, address_type, child.address, child.public_key
+                );
+                for (label, value) in [("  private_key: ", &child.private_key), ("  wif: ", &child.wif)] {
+                    if let Some(value) = secret(value) {
+                        out.push_str(label);
+                        out.push_str(value);
+                        out.push('\n');
+                    }
+                }
+                out.push_str("}\n");
+            }
+        }
+    }
+    Ok(())
+}
 
-    // Generate seed
-    let seed = mnemonic.to_seed(passphrase);
-    println!("Seed (hex): {}", hex::encode(&seed));
+// The derivation of a range of children, shareable across threads
+type DeriveFn<'a> = dyn Fn(Children) -> Result<Vec<DerivedAddress>, String> + Sync + 'a;
 
-    // Derive master extended private key
-    let network = Network::Bitcoin;
-    let xprv = match ExtendedPrivateKey::<SigningKey>::new(&seed) {
-        Ok(key) => key,
-        Err(e) => {
-            println!("An unexpected error occurred: {}", e);
-            return;
+// Children derived and written at a time, per thread, so a large batch is never all in memory
+const STREAM_CHUNK: u32 = 1000;
+
+// Function to derive a range of children split across `threads` threads, keeping their order.
+// Every child is derived from the same parent, so the parts are independent
+fn derive_parallel(derive: &DeriveFn, children: Children, threads: usize) -> Result<Vec<DerivedAddress>, String> {
+    let end = children.start + children.count;
+    let chunk_size = children.count.div_ceil(threads as u32).max(1);
+    std::thread::scope(|scope| {
+        let workers: Vec<_> = (children.start..end)
+            .step_by(chunk_size as usize)
+            .map(|start| {
+                let part = Children { start, count: chunk_size.min(end - start), hardened: children.hardened };
+                scope.spawn(move || derive(part))
+            })
+            .collect();
+        let mut derived = Vec::with_capacity(children.count as usize);
+        for worker in workers {
+            derived.extend(worker.join().unwrap()?);
         }
+        Ok(derived)
+    })
+}
+
+// Function to derive the children and write them to `out` in batches of STREAM_CHUNK per thread,
+// each rendered into one reused buffer and written whole. A JSON array is rendered once complete
+fn write_addresses(
+    out: &mut dyn io::Write,
+    derivation: &Derivation,
+    children: Children,
+    format: Format,
+    show_secrets: bool,
+    threads: usize,
+) -> Result<(), CliError> {
+    let derive_batch = |batch| match threads {
+        1 => (derivation.derive)(batch),
+        _ => derive_parallel(&*derivation.derive, batch, threads),
     };
+    let write_failed = |e: io::Error| CliError::Failed(format!("Cannot write the addresses: {}", e));
+    if format == Format::Json {
+        let derived = derive_batch(children)?;
+        let rendered = render(&derived, derivation.address_type, format, show_secrets)?;
+        out.write_all(rendered.as_bytes()).map_err(write_failed)?;
+        return out.flush().map_err(write_failed);
+    }
+    let batch_size = STREAM_CHUNK.saturating_mul(threads as u32);
+    let end = children.start + children.count;
+    let mut buffer = Zeroizing::new(String::with_capacity(batch_size.min(children.count) as usize * RECORD_CAPACITY));
+    for start in (children.start..end).step_by(batch_size as usize) {
+        let batch = Children { start, count: batch_size.min(end - start), hardened: children.hardened };
+        let derived = derive_batch(batch)?;
+        buffer.clear();
+        render_into(&mut buffer, &derived, derivation.address_type, format, show_secrets, start == children.start)?;
+        out.write_all(buffer.as_bytes()).map_err(write_failed)?;
+    }
+    out.flush().map_err(write_failed)
+}
 
-    // Parse the derivation path
-    let derivation_path = match derivation_path_str.parse::<DerivationPath>() {
-        Ok(path) => path,
-        Err(e) => {
-            println!("Error: Invalid derivation path: {}", e);
-            return;
+// Function to create the --output file, replacing any there; one that will hold secrets is
+// created readable by its owner only
+fn create_output(path: &Path, show_secrets: bool) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    if show_secrets {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = show_secrets;
+    options.open(path)
+}
+
+// Function to draw each address's QR code, and with --qr-secrets its WIF's, after the addresses,
+// and to write them as <index>.svg and <index>-wif.svg into the --qr-file directory, the index
+// being the path's last step, e.g. 5 or 5h
+#[cfg(feature = "qr")]
+fn write_qr_codes(out: &mut dyn io::Write, derived: &[DerivedAddress], address_type: AddressType, options: &QrOptions) -> Result<(), CliError> {
+    let bech32 = matches!(address_type, AddressType::P2wpkh | AddressType::P2tr);
+    if let Some(dir) = options.dir {
+        std::fs::create_dir_all(dir).map_err(|e| format!("Cannot create {}: {}", dir, e))?;
+    }
+    for child in derived {
+        let index = child.path.rsplit('/').next().unwrap_or_default().replace('\'', "h");
+        let payload = qr::address_payload(&child.address, bech32, options.amount);
+        let mut codes = vec![(format!("{} {}", child.path, child.address), format!("{}.svg", index), qr::encode(&payload)?, false)];
+        if let Some(wif) = child.wif.as_ref().filter(|_| options.secrets) {
+            codes.push((format!("{} wif", child.path), format!("{}-wif.svg", index), qr::encode(wif)?, true));
+        }
+        for (label, name, code, secret) in codes {
+            if options.terminal {
+                let drawn = qr::to_terminal(&code);
+                writeln!(out, "{}\n{}", label, *drawn).map_err(|e| format!("Cannot write the QR codes: {}", e))?;
+            }
+            if let Some(dir) = options.dir {
+                let path = Path::new(dir).join(name);
+                create_output(&path, secret)
+                    .and_then(|mut file| file.write_all(qr::to_svg(&code).as_bytes()))
+                    .map_err(|e| format!("Cannot write {}: {}", path.display(), e))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// The script a --multisig address pays to
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum MultisigType {
+    P2wsh,
+    P2shP2wsh,
+}
+
+impl FromStr for MultisigType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s.to_ascii_lowercase().as_str() {
+            "p2wsh" => Ok(MultisigType::P2wsh),
+            "p2sh-p2wsh" => Ok(MultisigType::P2shP2wsh),
+            _ => Err(format!("Unknown script type {:?}; use p2wsh or p2sh-p2wsh", s)),
         }
+    }
+}
+
+impl fmt::Display for MultisigType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MultisigType::P2wsh => "p2wsh",
+            MultisigType::P2shP2wsh => "p2sh-p2wsh",
+        })
+    }
+}
+
+// Settings of --multisig: the signatures needed, and the account keys, as given
+#[derive(Debug, PartialEq)]
+struct MultisigOptions<'a> {
+    required: usize,
+    xpubs: Vec<&'a str>,
+    script_type: MultisigType,
+}
+
+// One derived multisig address
+#[derive(Serialize)]
+struct MultisigAddress {
+    path: String,
+    address: String,
+    witness_script: String,
+    descriptor: String,
+}
+
+// Function to parse the --xpub keys, which must all be for `network`
+fn parse_cosigners(xpubs: &[&str], network: BtcNetwork) -> Result<Vec<Xpub>, String> {
+    let expected = NetworkKind::from(Network::from(network));
+    let mut parsed = Vec::with_capacity(xpubs.len());
+    for (i, encoded) in xpubs.iter().enumerate() {
+        let (xpub, _) = hd::parse_slip132(encoded).map_err(|e| format!("--xpub {}: {}", i + 1, e))?;
+        if xpub.network != expected {
+            let kind = if xpub.network == NetworkKind::Main { "mainnet" } else { "the test networks" };
+            return Err(format!("--xpub {} is for {}, but the network is {}; every key must be for the same network", i + 1, kind, network));
+        }
+        parsed.push(xpub);
+    }
+    Ok(parsed)
+}
+
+// Function to derive multisig addresses: the child key at each index of every xpub, sorted into a
+// sortedmulti script. The path is relative to the xpubs, and the descriptor names each key by
+// xpub and path in the order given, as sortedmulti() leaves the sorting to the script
+fn derive_multisig(
+    xpubs: &[Xpub],
+    required: usize,
+    script_type: MultisigType,
+    derivation_path_str: &str,
+    children: Children,
+    network: BtcNetwork,
+) -> Result<Vec<MultisigAddress>, String> {
+    if children.hardened {
+        return Err("--hardened needs the private keys; --multisig derives only normal children".to_string());
+    }
+    let derivation_path = derivation_path_str
+        .parse::<DerivationPath>()
+        .map_err(|e| format!("Invalid derivation path: {}", e))?;
+    if let Some(child) = derivation_path.into_iter().find(|child| child.is_hardened()) {
+        return Err(format!("{} has the hardened step {}, which an extended public key cannot derive", derivation_path_str, child));
+    }
+    let relative: String = derivation_path.into_iter().map(|step| format!("/{}", step)).collect();
+
+    let secp = Secp256k1::verification_only();
+    let parents = xpubs
+        .iter()
+        .map(|xpub| xpub.derive_pub(&secp, &derivation_path))
+        .collect::<Result<Vec<Xpub>, _>>()
+        .map_err(|e| format!("Cannot derive {}: {}", derivation_path_str, e))?;
+    let mut derived = Vec::with_capacity(children.count as usize);
+    for index in children.start..children.start + children.count {
+        let path = format!("{}/{}", derivation_path_str, index);
+        let failed = |e: &dyn fmt::Display| format!("Cannot derive {}: {}", path, e);
+        let child_number = ChildNumber::from_normal_idx(index).map_err(|e| failed(&e))?;
+        let pubkeys = parents
+            .iter()
+            .map(|parent| parent.ckd_pub(&secp, child_number).map(|child| PublicKey::new(child.public_key)))
+            .collect::<Result<Vec<PublicKey>, _>>()
+            .map_err(|e| failed(&e))?;
+        let witness_script = MultisigScript::sorted(required, pubkeys).map_err(|e| e.to_string())?.to_script();
+
+        let keys: Vec<String> = xpubs.iter().map(|xpub| format!("{}{}/{}", xpub, relative, index)).collect();
+        let sortedmulti = format!("sortedmulti({},{})", required, keys.join(","));
+        let (address, descriptor) = match script_type {
+            MultisigType::P2wsh => (Address::p2wsh(&witness_script, Network::from(network)), format!("wsh({})", sortedmulti)),
+            MultisigType::P2shP2wsh => (Address::p2shwsh(&witness_script, Network::from(network)), format!("sh(wsh({}))", sortedmulti)),
+        };
+        let checksum = hd::descriptor_checksum(&descriptor).map_err(|e| e.to_string())?;
+        derived.push(MultisigAddress {
+            path,
+            address: address.to_string(),
+            witness_script: witness_script.to_hex_string(),
+            descriptor: format!("{}#{}", descriptor, checksum),
+        });
+    }
+    Ok(derived)
+}
+
+// Function to write out the multisig addresses in the format asked for
+fn render_multisig(derived: &[MultisigAddress], format: Format) -> Result<String, CliError> {
+    let mut out = String::new();
+    match format {
+        Format::Json => {
+            out = serde_json::to_string_pretty(derived).map_err(|e| e.to_string())?;
+            out.push('\n');
+        }
+        Format::Jsonl => {
+            for child in derived {
+                out.push_str(&serde_json::to_string(child).map_err(|e| e.to_string())?);
+                out.push('\n');
+            }
+        }
+        Format::Electrum => return Err(CliError::Usage("--export does not apply to --multisig".to_string())),
+        Format::Csv => {
+            out.push_str("path,address,witness_script,descriptor\n");
+            for child in derived {
+                let fields = [&child.path, &child.address, &child.witness_script, &child.descriptor];
+                let row: Vec<Cow<str>> = fields.into_iter().map(|field| csv_field(field)).collect();
+                out.push_str(&row.join(","));
+                out.push('\n');
+            }
+        }
+        Format::Text => {
+            for child in derived {
+                out.push_str("{\n");
+                out.push_str(&format!("  derivation_path: {}\n", child.path));
+                out.push_str(&format!("  address: {}\n", child.address));
+                out.push_str(&format!("  witness_script: {}\n", child.witness_script));
+                out.push_str(&format!("  descriptor: {}\n", child.descriptor));
+                out.push_str("}\n");
+            }
+        }
+    }
+    Ok(out)
+}
+
+// Settings of --scan
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct ScanSettings {
+    // Consecutive never-used addresses after which the scan stops
+    gap_limit: u32,
+    // Addresses queried at a time
+    concurrency: u32,
+    // Retries of a rate-limited or failed request, waiting `backoff`, then twice as long each time
+    retries: u32,
+    backoff: Duration,
+}
+
+impl Default for ScanSettings {
+    fn default() -> Self {
+        ScanSettings { gap_limit: 20, concurrency: 4, retries: 5, backoff: Duration::from_secs(1) }
+    }
+}
+
+// One address the scan checked
+#[derive(Debug, Serialize)]
+struct ScannedAddress {
+    path: String,
+    address: String,
+    tx_count: u64,
+    // Balance in satoshis once pending transactions confirm
+    balance: u64,
+}
+
+// What --scan found
+#[derive(Debug, Serialize)]
+struct ScanReport {
+    addresses: Vec<ScannedAddress>,
+    total_tx_count: u64,
+    total_balance: u64,
+    // The first index after the last used address, or the start when none was used
+    next_unused_index: u32,
+    gap_limit: u32,
+}
+
+// Function to look an address up, retrying what may pass: rate limiting (waiting twice as long),
+// server errors and lost connections
+async fn address_info(client: &BlockstreamClient, address: &str, settings: &ScanSettings) -> Result<AddressInfo, String> {
+    let mut delay = settings.backoff;
+    let mut attempt = 0;
+    loop {
+        attempt += 1;
+        match client.get_address_info(address).await {
+            Ok(info) => return Ok(info),
+            Err(e) if e.is_transient() && attempt <= settings.retries => {
+                let wait = if e.is_rate_limited() { delay * 2 } else { delay };
+                eprintln!("Warning: checking {} failed ({}); retry {} of {} in {:?}", address, e, attempt, settings.retries, wait);
+                tokio::time::sleep(wait).await;
+                delay *= 2;
+            }
+            Err(e) => return Err(format!("Cannot check {}: {}", address, e)),
+        }
+    }
+}
+
+// Function to scan a wallet: derive from `first` on, `concurrency` addresses at a time, until
+// `gap_limit` consecutive addresses have never been used. Addresses past the gap in the last
+// batch are dropped from the report
+async fn scan(
+    client: &BlockstreamClient,
+    derive: &dyn Fn(Children) -> Result<Vec<DerivedAddress>, String>,
+    first: Children,
+    settings: &ScanSettings,
+) -> Result<ScanReport, String> {
+    let mut report = ScanReport {
+        addresses: Vec::new(),
+        total_tx_count: 0,
+        total_balance: 0,
+        next_unused_index: first.start,
+        gap_limit: settings.gap_limit,
     };
-    let parent_path: Vec<ChildNumber> = derivation_path.into_iter().collect();
+    let mut gap = 0;
+    let mut next = first.start;
+    while (next as u64) < HARDENED_START {
+        let count = settings.concurrency.min((HARDENED_START - next as u64) as u32);
+        let batch = derive(Children::new(next, count, first.hardened)?)?;
+        let infos = join_all(batch.iter().map(|child| address_info(client, &child.address, settings))).await;
+        for ((index, child), info) in (next..).zip(batch).zip(infos) {
+            let info = info?;
+            let tx_count = info.chain_stats.tx_count + info.mempool_stats.tx_count;
+            let balance = info.balance().total().to_sat();
+            report.total_tx_count += tx_count;
+            report.total_balance += balance;
+            report.addresses.push(ScannedAddress { path: child.path, address: child.address, tx_count, balance });
+            if tx_count > 0 {
+                gap = 0;
+                report.next_unused_index = index + 1;
+            } else {
+                gap += 1;
+                if gap == settings.gap_limit {
+                    return Ok(report);
+                }
+            }
+        }
+        next += count;
+    }
+    Ok(report)
+}
 
-    // Derive parent key
-    let mut current_key = xprv;
-    for &child in &parent_path {
-        current_key = current_key.derive_child(child).expect("Failed to derive child key");
+// Function to write the scan's addresses and totals
+fn render_scan(report: &ScanReport, format: Format) -> Result<String, CliError> {
+    if format == Format::Json {
+        let mut out = serde_json::to_string_pretty(report).map_err(|e| e.to_string())?;
+        out.push('\n');
+        return Ok(out);
     }
-    let parent_xprv = current_key;
+    let mut out = String::new();
+    for scanned in &report.addresses {
+        out.push_str(&format!(
+            "{} {} tx_count: {} balance: {} sat\n",
+            scanned.path, scanned.address, scanned.tx_count, scanned.balance
+        ));
+    }
+    out.push_str(&format!("total_tx_count: {}\n", report.total_tx_count));
+    out.push_str(&format!("total_balance: {} sat\n", report.total_balance));
+    out.push_str(&format!("next_unused_index: {}\n", report.next_unused_index));
+    Ok(out)
+}
 
-    // Generate 100 addresses
-    for index in 0..10 {
-        // Define child number for current index (non-hardened)
-        let child_number = ChildNumber::new(index, false).unwrap();
-        let child_xprv = parent_xprv.derive_child(child_number).expect("Failed to derive child key");
+// Function to write the account's key and descriptors, as far as asked for
+fn render_account(account: &Account, show_xpub: bool, show_descriptor: bool) -> String {
+    let mut out = String::new();
+    if show_xpub {
+        out.push_str(&format!("account_xpub: {}\n", account.xpub));
+    }
+    if show_descriptor {
+        out.push_str(&format!("receive_descriptor: {}\n", account.receive));
+        out.push_str(&format!("change_descriptor: {}\n", account.change));
+    }
+    out
+}
 
-        // Extract private key
-        let signing_key = child_xprv.private_key();
-        let private_key_hex = hex::encode(signing_key.to_bytes());
+// The addresses a run derives, as any range of children of the path
+struct Derivation<'a> {
+    derive: Box<DeriveFn<'a>>,
+    address_type: AddressType,
+    account: Option<Account>,
+}
 
-        // Derive public key (compressed)
-        let verifying_key = signing_key.verifying_key();
-        let encoded_point = verifying_key.to_encoded_point(true);
-        let pub_key_bytes = encoded_point.as_bytes();
-        let public_key = PublicKey::from_slice(pub_key_bytes).expect("Invalid public key");
-        let public_key_hex = hex::encode(pub_key_bytes);
-
-        // Generate WIF
-        let secret_key = SecretKey::from_slice(&signing_key.to_bytes()).expect("Invalid private key");
-        let privkey = PrivateKey::new(secret_key, network);
-        let wif = privkey.to_wif();
-
-        // Generate P2PKH address
-        let address = Address::p2pkh(&public_key, network).to_string();
-
-        // Print structured output
-        println!("{{");
-        println!("  derivation_path: {}/{}", derivation_path_str, index);
-        println!("  address: {}", address);
-        println!("  public_key: {}", public_key_hex);
-        println!("  private_key: {}", private_key_hex);
-        println!("  wif: {}", wif);
-        println!("}}");
-    }
-}
\ No newline at end of file
+// Function to derive the addresses from an extended private key, the root of the path. A key
+// below the master makes a key origin that starts at it, which is said on stderr
+fn derive_from_xprv<'a>(options: &Options<'a>, network: BtcNetwork) -> Result<Derivation<'a>, CliError> {
+    if !options.source.starts_with("xprv") && !options.source.starts_with("tprv") {
+        return Err(CliError::Failed("SLIP-132 extended private keys are not accepted; give the key as an xprv or tprv".to_string()));
+    }
+    if !options.passphrase.is_empty() {
+        return Err(CliError::Usage("A passphrase only applies to a mnemonic, not an extended private key".to_string()));
+    }
+    // Neither parser's errors include the key
+    let root = RootKey(Xpriv::from_str(options.source).map_err(|e| format!("Invalid extended private key: {}", e))?);
+    if root.0.network != NetworkKind::from(Network::from(network)) {
+        let kind = if root.0.network == NetworkKind::Main { "mainnet" } else { "the test networks" };
+        return Err(CliError::Failed(format!("The extended private key is for {}, but the network is {}; pass --network to match it", kind, network)));
+    }
+    let address_type = options.address_type.unwrap_or(AddressType::P2pkh);
+
+    eprintln!("Extended Private Key: <redacted>");
+    eprintln!("Derivation Path: {}", options.derivation_path);
+    eprintln!("Address Type: {}", address_type);
+    eprintln!("Network: {}", network);
+    if root.0.depth > 0 && (options.show_xpub || options.show_descriptor) {
+        eprintln!(
+            "Warning: the extended private key is at depth {}, not a master key; the key origin starts at it, with its own fingerprint",
+            root.0.depth
+        );
+    }
+    print_warnings(options.derivation_path, address_type, network);
+
+    let account = if options.show_xpub || options.show_descriptor {
+        Some(account_of(&root.0, options.derivation_path, address_type)?)
+    } else {
+        None
+    };
+    let path = options.derivation_path;
+    let secp = Secp256k1::new();
+    let derive = move |children| derive_addresses(&secp, &root.0, path, address_type, children, network);
+    Ok(Derivation { derive: Box::new(derive), address_type, account })
+}
+
+// Function to warn on stderr about a path whose purpose or coin type does not fit
+fn print_warnings(derivation_path_str: &str, address_type: AddressType, network: BtcNetwork) {
+    if let Ok(derivation_path) = derivation_path_str.parse::<DerivationPath>() {
+        let warnings = [purpose_warning(&derivation_path, address_type), coin_type_warning(&derivation_path, network)];
+        for warning in warnings.into_iter().flatten() {
+            eprintln!("{}", warning);
+        }
+    }
+}
+
+// Function to derive the addresses from the mnemonic or extended key. What was given goes to
+// stderr, so stdout holds only the addresses
+fn derive<'a>(options: &Options<'a>, network: BtcNetwork) -> Result<Derivation<'a>, CliError> {
+    let extended = XPRV_PREFIXES.iter().chain(&XPUB_PREFIXES).any(|prefix| options.source.starts_with(prefix));
+    if extended && options.show_seed {
+        return Err(CliError::Usage("--show-seed needs a mnemonic; an extended key has no seed".to_string()));
+    }
+
+    // An extended key in place of the mnemonic: public ones derive watch-only, private ones are
+    // the root of the path
+    if XPRV_PREFIXES.iter().any(|prefix| options.source.starts_with(prefix)) {
+        return derive_from_xprv(options, network);
+    }
+    if XPUB_PREFIXES.iter().any(|prefix| options.source.starts_with(prefix)) {
+        if !options.passphrase.is_empty() {
+            return Err(CliError::Usage("A passphrase only applies to a mnemonic, not an extended public key".to_string()));
+        }
+        let (xpub, implied) = hd::parse_slip132(options.source).map_err(|e| e.to_string())?;
+        let address_type = options.address_type.or(implied.map(address_type_of)).unwrap_or(AddressType::P2pkh);
+        eprintln!("Extended Public Key: {}", options.source);
+        eprintln!("Derivation Path: {}", options.derivation_path);
+        eprintln!("Address Type: {}", address_type);
+        eprintln!("Network: {}", network);
+        let path = options.derivation_path;
+        let secp = Secp256k1::new();
+        let derive = move |children| derive_watch_only(&secp, &xpub, path, address_type, children, network);
+        // The key is taken for the account; where it sits below its master is not known
+        let account = if options.show_xpub || options.show_descriptor {
+            Some(account_descriptors(xpub, "", address_type)?)
+        } else {
+            None
+        };
+        return Ok(Derivation { derive: Box::new(derive), address_type, account });
+    }
+    let address_type = options.address_type.unwrap_or(AddressType::P2pkh);
+
+    // Parse mnemonic; its errors name a word by position, never the word
+    let mnemonic = mnemonic::validate(options.source, Language::English).map_err(|e| e.to_string())?;
+
+    // Display inputs for verification, all but the secrets
+    eprintln!("Mnemonic: {} words", mnemonic.word_count());
+    eprintln!("Derivation Path: {}", options.derivation_path);
+    eprintln!("Passphrase: {}", if options.passphrase.is_empty() { "<empty>" } else { "<redacted>" });
+    eprintln!("Address Type: {}", address_type);
+    eprintln!("Network: {}", network);
+
+    // Generate seed, wiped on drop
+    let seed = mnemonic.to_seed(options.passphrase);
+    if options.show_seed {
+        let seed_hex = Zeroizing::new(hex::encode(&seed[..]));
+        eprintln!("Seed (hex): {}", *seed_hex);
+    }
+    print_warnings(options.derivation_path, address_type, network);
+
+    let master = RootKey(Xpriv::new_master(Network::from(network), &seed[..]).map_err(|e| format!("An unexpected error occurred: {}", e))?);
+
+    // The account key and descriptors, with the master key's fingerprint as their origin
+    let account = if options.show_xpub || options.show_descriptor {
+        Some(account_of(&master.0, options.derivation_path, address_type)?)
+    } else {
+        None
+    };
+
+    // Generate the addresses
+    let path = options.derivation_path;
+    let secp = Secp256k1::new();
+    let derive = move |children| derive_addresses(&secp, &master.0, path, address_type, children, network);
+    Ok(Derivation { derive: Box::new(derive), address_type, account })
+}
+
+// Function to check an Electrum export before anything is written, and to say on stderr what it
+// holds and the paths it runs over, as the file itself can carry no comment
+fn export_header(derivation: &Derivation, options: &Options, network: BtcNetwork) -> Result<(), CliError> {
+    let keys = options.show_secrets && !XPUB_PREFIXES.iter().any(|prefix| options.source.starts_with(prefix));
+    if keys {
+        electrum_prefix(derivation.address_type)?;
+    }
+    let children = options.children;
+    let hardened = if children.hardened { "'" } else { "" };
+    eprintln!(
+        "# For Electrum's {}: {} on {}, {}/{}{} to {}/{}{}",
+        if keys { "\"Import private keys\"" } else { "\"Import Bitcoin addresses\", watch-only" },
+        derivation.address_type,
+        network,
+        options.derivation_path,
+        children.start,
+        hardened,
+        options.derivation_path,
+        children.start + children.count - 1,
+        hardened
+    );
+    Ok(())
+}
+
+fn run(options: Options) -> Result<(), CliError> {
+    if options.help {
+        return print_usage(options.program, &mut io::stdout()).map_err(|e| CliError::Failed(e.to_string()));
+    }
+    let network = resolve_network(options.network_flag, env::var(NETWORK_ENV).ok())?;
+    let open_output = || -> Result<Box<dyn io::Write>, CliError> {
+        Ok(match options.output {
+            Some(path) => Box::new(create_output(Path::new(path), options.show_secrets).map_err(|e| format!("Cannot create {}: {}", path, e))?),
+            None => Box::new(io::stdout().lock()),
+        })
+    };
+    let write_failed = |e: io::Error| CliError::Failed(format!("Cannot write the addresses: {}", e));
+    if let Some(multisig) = &options.multisig {
+        let xpubs = parse_cosigners(&multisig.xpubs, network)?;
+        eprintln!("Multisig: {} of {}, {}", multisig.required, xpubs.len(), multisig.script_type);
+        eprintln!("Derivation Path: {}", options.derivation_path);
+        eprintln!("Network: {}", network);
+        let derived = derive_multisig(&xpubs, multisig.required, multisig.script_type, options.derivation_path, options.children, network)?;
+        let mut out = open_output()?;
+        return out.write_all(render_multisig(&derived, options.format)?.as_bytes()).map_err(write_failed);
+    }
+    let derivation = derive(&options, network)?;
+    if options.format == Format::Electrum {
+        export_header(&derivation, &options, network)?;
+    }
+    let mut out = open_output()?;
+    // The account lines would break a JSON array or CSV table, so those formats send them to stderr
+    if let Some(account) = &derivation.account {
+        let lines = render_account(account, options.show_xpub, options.show_descriptor);
+        match options.format {
+            Format::Text => out.write_all(lines.as_bytes()).map_err(write_failed)?,
+            Format::Json | Format::Jsonl | Format::Csv | Format::Electrum => eprint!("{}", lines),
+        }
+    }
+    match &options.scan {
+        Some(settings) => {
+            let client = BlockstreamClient::new(network).map_err(|e| format!("Cannot create the Esplora client: {}", e))?;
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .map_err(|e| format!("Cannot start the runtime: {}", e))?;
+            let report = runtime.block_on(scan(&client, &derivation.derive, options.children, settings))?;
+            out.write_all(render_scan(&report, options.format)?.as_bytes()).map_err(write_failed)?;
+        }
+        None => {
+            let threads = if options.parallel { std::thread::available_parallelism().map_or(1, |n| n.get()) } else { 1 };
+            match &options.qr {
+                #[cfg(feature = "qr")]
+                Some(qr) => {
+                    let derived = (derivation.derive)(options.children)?;
+                    let rendered = render(&derived, derivation.address_type, options.format, options.show_secrets)?;
+                    out.write_all(rendered.as_bytes()).map_err(write_failed)?;
+                    write_qr_codes(&mut out, &derived, derivation.address_type, qr)?;
+                }
+                _ => write_addresses(&mut out, &derivation, options.children, options.format, options.show_secrets, threads)?,
+            }
+        }
+    }
+    out.flush().map_err(write_failed)
+}
+
+fn main() {
+    // Collect command-line arguments
+    let args: Vec<String> = env::args().collect();
+    if let Err(e) = parse_args(&args).and_then(run) {
+        eprintln!("{}", e);
+        if let CliError::Usage(_) = e {
+            let _ = print_usage(args.first().map_or("generate_addresses", String::as_str), &mut io::stderr());
+        }
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The seed of "abandon abandon ... about", with no passphrase
+    fn abandon_seed() -> Vec<u8> {
+        mnemonic::from_entropy(&[0; 16], Language::English).unwrap().to_seed("").to_vec()
+    }
+
+    fn abandon_master() -> Xpriv {
+        Xpriv::new_master(Network::Bitcoin, &abandon_seed()).unwrap()
+    }
+
+    fn addresses(path: &str, address_type: AddressType) -> Vec<String> {
+        derive_addresses(&Secp256k1::new(), &abandon_master(), path, address_type, Children::default(), BtcNetwork::Bitcoin)
+            .unwrap()
+            .into_iter()
+            .map(|child| child.address)
+            .collect()
+    }
+
+    #[test]
+    fn test_reference_vectors() {
+        // The first receive addresses of the BIP44, BIP49, BIP84 and BIP86 test vectors
+        let p2pkh = addresses("m/44'/0'/0'/0", AddressType::P2pkh);
+        assert_eq!(p2pkh[0], "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA");
+        assert_eq!(p2pkh[1], "1Ak8PffB2meyfYnbXZR9EGfLfFZVpzJvQP");
+        let p2sh = addresses("m/49'/0'/0'/0", AddressType::P2shP2wpkh);
+        assert_eq!(p2sh[0], "37VucYSaXLCAsxYyAPfbSi9eh4iEcbShgf");
+        let p2wpkh = addresses("m/84'/0'/0'/0", AddressType::P2wpkh);
+        assert_eq!(p2wpkh[0], "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu");
+        assert_eq!(p2wpkh[1], "bc1qnjg0jd8228aq7egyzacy8cys3knf9xvrerkf9g");
+        let p2tr = addresses("m/86'/0'/0'/0", AddressType::P2tr);
+        assert_eq!(p2tr[0], "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr");
+        assert_eq!(p2tr[1], "bc1p4qhjn9zdvkux4e44uhx8tc55attvtyu358kutcqkudyccelu0was9fqzwh");
+        assert_eq!(p2tr.len(), 10);
+    }
+
+    #[test]
+    fn test_purpose_warning() {
+        let path = |path: &str| path.parse::<DerivationPath>().unwrap();
+        let warning = purpose_warning(&path("m/84'/0'/0'/0"), AddressType::P2pkh).unwrap();
+        assert!(warning.contains("--address-type p2wpkh"));
+        assert!(purpose_warning(&path("m/84'/0'/0'/0"), AddressType::P2wpkh).is_none());
+        assert!(purpose_warning(&path("m/86'/0'/0'"), AddressType::P2wpkh).unwrap().contains("p2tr"));
+        // Paths outside BIP44/49/84/86 say nothing
+        assert!(purpose_warning(&path("m/0'/0"), AddressType::P2tr).is_none());
+        assert!(purpose_warning(&path("m/84/0"), AddressType::P2pkh).is_none());
+    }
+
+    #[test]
+    fn test_children() {
+        // Derived independently with the bitcoin crate's BIP32
+        let secp = bitcoin::secp256k1::Secp256k1::new();
+        let master = Xpriv::new_master(Network::Bitcoin, &abandon_seed()).unwrap();
+        let expected = |path: &str| {
+            let path = path.parse::<DerivationPath>().unwrap();
+            let key = master.derive_priv(&secp, &path).unwrap().to_priv();
+            bitcoin::Address::p2wpkh(&bitcoin::CompressedPublicKey::from_private_key(&secp, &key).unwrap(), Network::Bitcoin).to_string()
+        };
+
+        let children = Children::new(5, 3, false).unwrap();
+        let derived = derive_addresses(&Secp256k1::new(), &abandon_master(), "m/84'/0'/0'/0", AddressType::P2wpkh, children, BtcNetwork::Bitcoin).unwrap();
+        let paths: Vec<&str> = derived.iter().map(|child| child.path.as_str()).collect();
+        assert_eq!(paths, ["m/84'/0'/0'/0/5", "m/84'/0'/0'/0/6", "m/84'/0'/0'/0/7"]);
+        for child in &derived {
+            assert_eq!(child.address, expected(&child.path));
+        }
+
+        let children = Children::new(2, 2, true).unwrap();
+        let derived = derive_addresses(&Secp256k1::new(), &abandon_master(), "m/84'/0'/0'", AddressType::P2wpkh, children, BtcNetwork::Bitcoin).unwrap();
+        let paths: Vec<&str> = derived.iter().map(|child| child.path.as_str()).collect();
+        assert_eq!(paths, ["m/84'/0'/0'/2'", "m/84'/0'/0'/3'"]);
+        for child in &derived {
+            assert_eq!(child.address, expected(&child.path));
+        }
+
+        // The range must be non-empty and end at index 2^31 - 1 at the latest
+        assert_eq!(Children::new(0, 10, false), Ok(Children::default()));
+        assert!(Children::new(0, 0, false).is_err());
+        assert!(Children::new((1 << 31) - 1, 1, false).is_ok());
+        assert!(Children::new((1 << 31) - 1, 2, false).unwrap_err().contains("below 2^31"));
+        assert!(Children::new(u32::MAX, 1, false).is_err());
+    }
+
+    #[test]
+    fn test_testnet() {
+        // The first receive key of the BIP49 test vector, which is on testnet
+        let testnet = |path: &str, address_type| {
+            let children = Children::new(0, 1, false).unwrap();
+            derive_addresses(&Secp256k1::new(), &abandon_master(), path, address_type, children, BtcNetwork::Testnet).unwrap().remove(0)
+        };
+        let child = testnet("m/49'/1'/0'/0", AddressType::P2shP2wpkh);
+        assert_eq!(child.address, "2Mww8dCYPUpKHofjgcXcBCEGmniw9CoaiD2");
+        assert_eq!(child.wif.as_deref().map(String::as_str), Some("cULrpoZGXiuC19Uhvykx7NugygA3k86b3hmdCeyvHYQZSxojGyXJ"));
+        assert_eq!(child.public_key, "03a1af804ac108a8a51782198c2d034b28bf90c8803f5a53f76276fa69a4eae77f");
+
+        // The same key's other testnet encodings
+        assert!(testnet("m/49'/1'/0'/0", AddressType::P2wpkh).address.starts_with("tb1q"));
+        assert!(testnet("m/49'/1'/0'/0", AddressType::P2tr).address.starts_with("tb1p"));
+        let p2pkh = testnet("m/49'/1'/0'/0", AddressType::P2pkh).address;
+        assert!(p2pkh.starts_with('m') || p2pkh.starts_with('n'));
+        let children = Children::new(0, 1, false).unwrap();
+        let regtest = derive_addresses(&Secp256k1::new(), &abandon_master(), "m/84'/1'/0'/0", AddressType::P2wpkh, children, BtcNetwork::Regtest).unwrap();
+        assert!(regtest[0].address.starts_with("bcrt1q"));
+
+        // Coin type 0' is mainnet's, 1' the test networks'
+        let path = |path: &str| path.parse::<DerivationPath>().unwrap();
+        assert!(coin_type_warning(&path("m/84'/0'/0'"), BtcNetwork::Testnet).unwrap().contains("1'"));
+        assert!(coin_type_warning(&path("m/84'/1'/0'"), BtcNetwork::Bitcoin).is_some());
+        assert!(coin_type_warning(&path("m/84'/1'/0'"), BtcNetwork::Signet).is_none());
+        assert!(coin_type_warning(&path("m/84'/0'/0'"), BtcNetwork::Bitcoin).is_none());
+        assert!(coin_type_warning(&path("m/0'/0'"), BtcNetwork::Testnet).is_none());
+
+        assert_eq!(resolve_network(None, None), Ok(BtcNetwork::Bitcoin));
+        assert_eq!(resolve_network(Some("testnet"), Some("regtest".to_string())), Ok(BtcNetwork::Testnet));
+        assert_eq!(resolve_network(None, Some("signet".to_string())), Ok(BtcNetwork::Signet));
+        assert!(resolve_network(Some("moon"), None).is_err());
+    }
+
+    #[test]
+    fn test_watch_only() {
+        // The BIP84 account key as Electrum shows it, and its first receive addresses
+        let zpub = "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs";
+        let (xpub, implied) = hd::parse_slip132(zpub).unwrap();
+        let address_type = implied.map(address_type_of).unwrap();
+        assert_eq!(address_type, AddressType::P2wpkh);
+        let children = Children::new(0, 3, false).unwrap();
+        let derived = derive_watch_only(&Secp256k1::new(), &xpub, "m/0", address_type, children, BtcNetwork::Bitcoin).unwrap();
+        let addresses: Vec<&str> = derived.iter().map(|child| child.address.as_str()).collect();
+        assert_eq!(
+            addresses,
+            [
+                "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu",
+                "bc1qnjg0jd8228aq7egyzacy8cys3knf9xvrerkf9g",
+                "bc1qp59yckz4ae5c4efgw2s5wfyvrz0ala7rgvuz8z",
+            ]
+        );
+        assert!(derived.iter().all(|child| child.private_key.is_none() && child.wif.is_none()));
+
+        // The same public keys as derivation from the mnemonic, at the same start index
+        let children = Children::new(4, 2, false).unwrap();
+        let watch_only = derive_watch_only(&Secp256k1::new(), &xpub, "m/1", AddressType::P2tr, children, BtcNetwork::Bitcoin).unwrap();
+        let full = derive_addresses(&Secp256k1::new(), &abandon_master(), "m/84'/0'/0'/1", AddressType::P2tr, children, BtcNetwork::Bitcoin).unwrap();
+        for (watched, derived) in watch_only.iter().zip(&full) {
+            assert_eq!((&watched.address, &watched.public_key), (&derived.address, &derived.public_key));
+        }
+
+        // Hardened steps, a network mismatch and --hardened are refused
+        let error = derive_watch_only(&Secp256k1::new(), &xpub, "m/0'", address_type, Children::default(), BtcNetwork::Bitcoin).err().unwrap();
+        assert!(error.contains("hardened step"));
+        assert!(derive_watch_only(&Secp256k1::new(), &xpub, "m/0", address_type, Children::default(), BtcNetwork::Testnet).is_err());
+        let hardened = Children::new(0, 1, true).unwrap();
+        assert!(derive_watch_only(&Secp256k1::new(), &xpub, "m/0", address_type, hardened, BtcNetwork::Bitcoin).is_err());
+    }
+
+    // Function to split a CSV line back into its fields, undoing csv_field's quoting
+    fn parse_csv_row(line: &str) -> Vec<String> {
+        let mut fields = vec![String::new()];
+        let mut quoted = false;
+        let mut chars = line.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' if quoted && chars.peek() == Some(&'"') => {
+                    chars.next();
+                    fields.last_mut().unwrap().push('"');
+                }
+                '"' => quoted = !quoted,
+                ',' if !quoted => fields.push(String::new()),
+                _ => fields.last_mut().unwrap().push(c),
+            }
+        }
+        fields
+    }
+
+    fn bip84() -> Vec<DerivedAddress> {
+        derive_addresses(&Secp256k1::new(), &abandon_master(), "m/84'/0'/0'/0", AddressType::P2wpkh, Children::new(0, 2, false).unwrap(), BtcNetwork::Bitcoin).unwrap()
+    }
+
+    #[test]
+    fn test_json_format() {
+        let json = render(&bip84(), AddressType::P2wpkh, Format::Json, false).unwrap();
+        let records: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0]["path"], "m/84'/0'/0'/0/0");
+        assert_eq!(records[0]["address"], "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu");
+        assert_eq!(records[1]["address"], "bc1qnjg0jd8228aq7egyzacy8cys3knf9xvrerkf9g");
+        assert_eq!(records[0]["public_key"].as_str().unwrap().len(), 66);
+        // Secrets only with --show-secrets
+        assert!(records[0].get("private_key").is_none() && records[0].get("wif").is_none());
+
+        let json = render(&bip84(), AddressType::P2wpkh, Format::Json, true).unwrap();
+        let records: Vec<serde_json::Value> = serde_json::from_str(&json).unwrap();
+        assert_eq!(records[0]["wif"], "KyZpNDKnfs94vbrwhJneDi77V6jF64PWPF8x5cdJb8ifgg2DUc9d");
+        assert_eq!(records[0]["private_key"].as_str().unwrap().len(), 64);
+    }
+
+    #[test]
+    fn test_csv_format() {
+        let csv = render(&bip84(), AddressType::P2wpkh, Format::Csv, true).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(parse_csv_row(lines[0]), ["path", "address", "public_key", "private_key", "wif"]);
+        let derived = bip84();
+        let row = parse_csv_row(lines[1]);
+        let child = &derived[0];
+        assert_eq!(row, [&child.path, &child.address, &child.public_key, child.private_key.as_ref().unwrap(), child.wif.as_ref().unwrap()].map(String::clone));
+
+        // Without --show-secrets the columns are gone, not empty
+        let csv = render(&bip84(), AddressType::P2wpkh, Format::Csv, false).unwrap();
+        assert_eq!(csv.lines().next(), Some("path,address,public_key"));
+        assert!(!csv.contains(derived[0].wif.as_ref().unwrap().as_str()));
+
+        // Fields holding commas or quotes survive the round trip
+        let fields = ["m/0,1", "say \"hi\"", "plain"];
+        let line: Vec<String> = fields.iter().map(|field| csv_field(field).into_owned()).collect();
+        assert_eq!(line.join(","), "\"m/0,1\",\"say \"\"hi\"\"\",plain");
+        assert_eq!(parse_csv_row(&line.join(",")), fields);
+    }
+
+    #[test]
+    fn test_text_format_and_args() {
+        let text = render(&bip84(), AddressType::P2wpkh, Format::Text, false).unwrap();
+        assert!(text.contains("  address: bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu\n"));
+        assert!(!text.contains("wif:") && !text.contains("private_key:"));
+        assert!(render(&bip84(), AddressType::P2wpkh, Format::Text, true).unwrap().contains("  wif: "));
+
+        let args = |line: &str| line.split(' ').map(String::from).collect::<Vec<String>>();
+        let parsed = args("generate_addresses --format CSV --show-secrets zpub m/0");
+        let options = parse_args(&parsed).unwrap();
+        assert_eq!(options.format, Format::Csv);
+        assert!(options.show_secrets);
+        assert!(matches!(parse_args(&args("generate_addresses --format xml zpub m/0")), Err(CliError::Usage(_))));
+        assert!(matches!(parse_args(&args("generate_addresses --format")), Err(CliError::Usage(_))));
+    }
+
+    #[test]
+    fn test_account_descriptors() {
+        // The BIP84 test vector's account, whose zpub is the same key as this xpub; the
+        // checksum algorithm is checked against Bitcoin Core's in btcx_lib::hd
+        let xpub = "xpub6CatWdiZiodmUeTDp8LT5or8nmbKNcuyvz7WyksVFkKB4RHwCD3XyuvPEbvqAQY3rAPshWcMLoP2fMFMKHPJ4ZeZXYVUhLv1VMrjPC7PW6V";
+        let zpub = "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs";
+        assert_eq!(hd::parse_slip132(zpub).unwrap().0.to_string(), xpub);
+        let receive = format!("wpkh([73c5da0a/84h/0h/0h]{}/0/*)#afwvtk2s", xpub);
+        let change = format!("wpkh([73c5da0a/84h/0h/0h]{}/1/*)#vatdkr6g", xpub);
+
+        // From the mnemonic's seed and from the master xprv alike
+        let root = Xpriv::new_master(Network::Bitcoin, &abandon_seed()).unwrap();
+        for root in [root, Xpriv::from_str(&root.to_string()).unwrap()] {
+            let account = account_of(&root, "m/84'/0'/0'/0", AddressType::P2wpkh).unwrap();
+            assert_eq!(account.xpub.to_string(), xpub);
+            assert_eq!((account.receive.as_str(), account.change.as_str()), (receive.as_str(), change.as_str()));
+        }
+
+        // The descriptor's first address, as deriveaddresses gives it, is the first derived
+        let key = receive.split_once(']').unwrap().1.split('/').next().unwrap();
+        let watched = hd::AccountXpub::parse(key, ScriptKind::P2wpkh, BtcNetwork::Bitcoin).unwrap();
+        assert_eq!(watched.derive(0, 0).unwrap().address.to_string(), "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu");
+        assert_eq!(addresses("m/84'/0'/0'/0", AddressType::P2wpkh)[0], "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu");
+
+        // A zpub is written as the plain xpub, with no origin
+        let watch_only = account_descriptors(hd::parse_slip132(zpub).unwrap().0, "", AddressType::P2wpkh).unwrap();
+        assert_eq!(watch_only.receive, format!("wpkh({}/0/*)#kj7aqcx6", xpub));
+
+        // Test networks get a tpub, and the script type picks the descriptor
+        let testnet = Xpriv::new_master(Network::Testnet, &abandon_seed()).unwrap();
+        let account = account_of(&testnet, "m/86'/1'/0'/0", AddressType::P2tr).unwrap();
+        assert!(account.xpub.to_string().starts_with("tpub"));
+        assert!(account.receive.starts_with("tr([73c5da0a/86h/1h/0h]tpub"));
+        assert!(account.change.contains("/1/*)#"));
+    }
+
+    #[test]
+    fn test_xprv_source() {
+        let root = Xpriv::new_master(Network::Bitcoin, &abandon_seed()).unwrap().to_string();
+        let args = |source: &str, network: &str| -> Vec<String> {
+            ["generate_addresses", "--address-type", "p2wpkh", "--count", "2", "--show-descriptor", "--network", network, source, "m/84'/0'/0'/0"]
+                .map(String::from)
+                .to_vec()
+        };
+        let parsed = args(&root, "mainnet");
+        let options = parse_args(&parsed).unwrap();
+        let derivation = derive(&options, BtcNetwork::Bitcoin).unwrap();
+        let derived = (derivation.derive)(options.children).unwrap();
+        assert_eq!(derived[1].address, "bc1qnjg0jd8228aq7egyzacy8cys3knf9xvrerkf9g");
+        assert!(derivation.account.unwrap().receive.ends_with("#afwvtk2s"));
+
+        // A mainnet key on testnet, and SLIP-132 private keys, are refused without echoing them
+        let parsed = args(&root, "testnet");
+        let error = derive(&parse_args(&parsed).unwrap(), BtcNetwork::Testnet).err().unwrap();
+        assert!(error.to_string().contains("mainnet") && !error.to_string().contains(&root[4..]));
+        let zprv = format!("zprv{}", &root[4..]);
+        let parsed = args(&zprv, "mainnet");
+        let error = derive(&parse_args(&parsed).unwrap(), BtcNetwork::Bitcoin).err().unwrap();
+        assert!(!error.to_string().contains(&root[4..]));
+    }
+
+    // Esplora's /address answer for an address with `tx_count` confirmed transactions, each
+    // paying it 1000 sat
+    fn address_body(address: &str, tx_count: u64) -> String {
+        let stats = |count: u64| {
+            format!(
+                r#"{{"funded_txo_count":{0},"funded_txo_sum":{1},"spent_txo_count":0,"spent_txo_sum":0,"tx_count":{0}}}"#,
+                count,
+                count * 1000
+            )
+        };
+        format!(r#"{{"address":"{}","chain_stats":{},"mempool_stats":{}}}"#, address, stats(tx_count), stats(0))
+    }
+
+    #[tokio::test]
+    async fn test_scan_stops_at_gap_limit() {
+        // A wallet that used indexes 0-4 and 9; checking index 2 fails once with a 503
+        let derive = |children| derive_addresses(&Secp256k1::new(), &abandon_master(), "m/84'/0'/0'/0", AddressType::P2wpkh, children, BtcNetwork::Bitcoin);
+        let derived = derive(Children::new(0, 40, false).unwrap()).unwrap();
+        let settings = ScanSettings { concurrency: 4, retries: 2, backoff: Duration::from_millis(1), ..ScanSettings::default() };
+        for concurrency in [4, 1] {
+            let mut server = mockito::Server::new_async().await;
+            let mut past_gap = Vec::new();
+            for (index, child) in derived.iter().enumerate() {
+                let path = format!("/address/{}", child.address);
+                if index == 2 {
+                    server.mock("GET", path.as_str()).with_status(503).expect(1).create_async().await;
+                }
+                let used = matches!(index, 0..=4 | 9);
+                let mock = server
+                    .mock("GET", path.as_str())
+                    .with_body(address_body(&child.address, used as u64 + (index == 3) as u64))
+                    .create_async()
+                    .await;
+                if index >= 32 || (concurrency == 1 && index >= 30) {
+                    past_gap.push(mock.expect(0));
+                }
+            }
+            let client = BlockstreamClient::with_base_url(BtcNetwork::Bitcoin, &server.url()).unwrap();
+            let settings = ScanSettings { concurrency, ..settings };
+            let report = scan(&client, &derive, Children::new(0, 1, false).unwrap(), &settings).await.unwrap();
+
+            // Twenty never-used addresses after 9 end the scan at 29
+            assert_eq!(report.addresses.len(), 30);
+            assert_eq!(report.addresses[29].path, "m/84'/0'/0'/0/29");
+            assert_eq!(report.next_unused_index, 10);
+            assert_eq!(report.total_tx_count, 7);
+            assert_eq!(report.total_balance, 7000);
+            assert_eq!((report.addresses[3].tx_count, report.addresses[3].balance), (2, 2000));
+            assert_eq!(report.addresses[2].tx_count, 1);
+            for mock in past_gap {
+                mock.assert_async().await;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scan_gives_up_after_retries() {
+        let derive = |children| derive_addresses(&Secp256k1::new(), &abandon_master(), "m/84'/0'/0'/0", AddressType::P2wpkh, children, BtcNetwork::Bitcoin);
+        let mut server = mockito::Server::new_async().await;
+        let limited = server.mock("GET", mockito::Matcher::Any).with_status(429).expect(3).create_async().await;
+        let client = BlockstreamClient::with_base_url(BtcNetwork::Bitcoin, &server.url()).unwrap();
+        let settings = ScanSettings { concurrency: 1, retries: 2, backoff: Duration::from_millis(1), ..ScanSettings::default() };
+        let error = scan(&client, &derive, Children::default(), &settings).await.unwrap_err();
+        assert!(error.contains("429"), "{}", error);
+        limited.assert_async().await;
+
+        let text = render_scan(
+            &ScanReport { addresses: Vec::new(), total_tx_count: 0, total_balance: 0, next_unused_index: 0, gap_limit: 20 },
+            Format::Text,
+        )
+        .unwrap();
+        assert!(text.contains("next_unused_index: 0\n"));
+        let args = |line: &str| line.split(' ').map(String::from).collect::<Vec<String>>();
+        let parsed = args("generate_addresses --scan --gap-limit 5 zpub m/0");
+        assert_eq!(parse_args(&parsed).unwrap().scan.map(|scan| scan.gap_limit), Some(5));
+        assert!(matches!(parse_args(&args("generate_addresses --gap-limit 5 zpub m/0")), Err(CliError::Usage(_))));
+        assert!(matches!(parse_args(&args("generate_addresses --scan --concurrency 0 zpub m/0")), Err(CliError::Usage(_))));
+    }
+
+    // Whether a word has the shape of a WIF: base58, 51 characters starting 5 or 9, or 52
+    // starting K, L or c
+    fn looks_like_wif(word: &str) -> bool {
+        let base58 = word.chars().all(|c| c.is_ascii_alphanumeric() && !"0OIl".contains(c));
+        let prefix = |prefixes: &str| word.chars().next().is_some_and(|c| prefixes.contains(c));
+        base58 && ((word.len() == 51 && prefix("59")) || (word.len() == 52 && prefix("KLc")))
+    }
+
+    #[test]
+    fn test_default_output_has_no_secrets() {
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        for network in ["mainnet", "testnet"] {
+            for format in ["text", "json", "csv"] {
+                let parsed: Vec<String> = ["generate_addresses", "--network", network, "--format", format, phrase, "m/84'/0'/0'/0"]
+                    .map(String::from)
+                    .to_vec();
+                let options = parse_args(&parsed).unwrap();
+                let network = resolve_network(options.network_flag, None).unwrap();
+                let derivation = derive(&options, network).unwrap();
+                let derived = (derivation.derive)(options.children).unwrap();
+                let out = render(&derived, derivation.address_type, options.format, options.show_secrets).unwrap();
+
+                let words: Vec<&str> = out.split(|c: char| !c.is_ascii_alphanumeric()).collect();
+                assert!(!words.iter().any(|word| looks_like_wif(word)), "{}", *out);
+                for child in &derived {
+                    assert!(!out.contains(child.private_key.as_ref().unwrap().as_str()));
+                    assert!(!out.contains(child.wif.as_ref().unwrap().as_str()));
+                }
+                assert!(!out.contains("abandon"));
+
+                // The same derivation with --show-secrets does print them
+                let shown = render(&derived, derivation.address_type, options.format, true).unwrap();
+                let words: Vec<&str> = shown.split(|c: char| !c.is_ascii_alphanumeric()).collect();
+                assert_eq!(words.iter().filter(|word| looks_like_wif(word)).count(), 10);
+            }
+        }
+        assert!(looks_like_wif("KyZpNDKnfs94vbrwhJneDi77V6jF64PWPF8x5cdJb8ifgg2DUc9d"));
+
+        // There is no seed to show for an extended key
+        let args = |line: &str| line.split(' ').map(String::from).collect::<Vec<String>>();
+        let parsed = args("generate_addresses --show-seed zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs m/0");
+        let error = derive(&parse_args(&parsed).unwrap(), BtcNetwork::Bitcoin).err().unwrap();
+        assert!(matches!(error, CliError::Usage(_)));
+    }
+
+    #[test]
+    fn test_multisig() {
+        // Three cosigners' BIP48 p2wsh account keys
+        let secp = Secp256k1::new();
+        let path = DerivationPath::from_str("m/48'/0'/0'/2'").unwrap();
+        let accounts: Vec<Xpriv> = (0..3u8)
+            .map(|byte| {
+                let seed = mnemonic::from_entropy(&[byte; 16], Language::English).unwrap().to_seed("");
+                Xpriv::new_master(Network::Bitcoin, &seed[..]).unwrap().derive_priv(&secp, &path).unwrap()
+            })
+            .collect();
+        let xpubs: Vec<Xpub> = accounts.iter().map(|account| Xpub::from_priv(&secp, account)).collect();
+
+        // The script built by hand: OP_2, the keys sorted by their hex, OP_3 OP_CHECKMULTISIG
+        let expected_script = |index: u32| {
+            let child = DerivationPath::from_str(&format!("m/0/{}", index)).unwrap();
+            let mut keys: Vec<String> = accounts
+                .iter()
+                .map(|account| account.derive_priv(&secp, &child).unwrap().to_priv().public_key(&secp).to_string())
+                .collect();
+            keys.sort();
+            let keys: String = keys.iter().map(|key| format!("21{}", key)).collect();
+            bitcoin::ScriptBuf::from_hex(&format!("52{}53ae", keys)).unwrap()
+        };
+
+        let derived = derive_multisig(&xpubs, 2, MultisigType::P2wsh, "m/0", Children::new(0, 3, false).unwrap(), BtcNetwork::Bitcoin).unwrap();
+        for (index, child) in (0..).zip(&derived) {
+            let script = expected_script(index);
+            assert_eq!(child.path, format!("m/0/{}", index));
+            assert_eq!(child.witness_script, script.to_hex_string());
+            assert_eq!(child.address, Address::p2wsh(&script, Network::Bitcoin).to_string());
+            let keys: Vec<String> = xpubs.iter().map(|xpub| format!("{}/0/{}", xpub, index)).collect();
+            let descriptor = format!("wsh(sortedmulti(2,{}))", keys.join(","));
+            assert_eq!(child.descriptor, format!("{}#{}", descriptor, hd::descriptor_checksum(&descriptor).unwrap()));
+        }
+        assert!(derived[0].address.starts_with("bc1q") && derived[0].address.len() == 62);
+
+        // The order of the keys given changes the descriptor, not the address
+        let reversed: Vec<Xpub> = xpubs.iter().rev().copied().collect();
+        let children = Children::new(1, 1, false).unwrap();
+        let nested = derive_multisig(&reversed, 2, MultisigType::P2shP2wsh, "m/0", children, BtcNetwork::Bitcoin).unwrap();
+        assert_eq!(nested[0].address, Address::p2shwsh(&expected_script(1), Network::Bitcoin).to_string());
+        assert!(nested[0].descriptor.starts_with(&format!("sh(wsh(sortedmulti(2,{}/0/1,", reversed[0])));
+        let csv = render_multisig(&nested, Format::Csv).unwrap();
+        assert_eq!(parse_csv_row(csv.lines().nth(1).unwrap())[3], nested[0].descriptor);
+
+        // Keys for another network are refused, naming the key
+        let testnet = Xpriv::new_master(Network::Testnet, &abandon_seed()).unwrap();
+        let tpub = Xpub::from_priv(&secp, &testnet).to_string();
+        let xpub = xpubs[0].to_string();
+        let error = parse_cosigners(&[&xpub, &tpub], BtcNetwork::Bitcoin).unwrap_err();
+        assert!(error.starts_with("--xpub 2 is for the test networks"), "{}", error);
+        assert!(parse_cosigners(&[&tpub], BtcNetwork::Signet).is_ok());
+
+        let args = |line: &str| line.split(' ').map(String::from).collect::<Vec<String>>();
+        let parsed = args(&format!("generate_addresses --multisig 2 --xpub {0} --xpub {0} --script-type p2sh-p2wsh m/0", xpub));
+        let multisig = parse_args(&parsed).unwrap().multisig.unwrap();
+        assert_eq!((multisig.required, multisig.xpubs.len(), multisig.script_type), (2, 2, MultisigType::P2shP2wsh));
+        for line in [
+            format!("generate_addresses --multisig 3 --xpub {0} --xpub {0} m/0", xpub),
+            format!("generate_addresses --xpub {} m/0", xpub),
+            format!("generate_addresses --multisig 1 --xpub {} m/0 extra", xpub),
+        ] {
+            assert!(matches!(parse_args(&args(&line)), Err(CliError::Usage(_))), "{}", line);
+        }
+    }
+
+    // The addresses, public keys, private keys and WIFs of the abandon mnemonic for every address
+    // type, on mainnet and testnet, for normal and hardened children, as CSV
+    fn regression_output(derive: &dyn Fn(AddressType, Children, BtcNetwork) -> Vec<DerivedAddress>) -> String {
+        let mut out = String::new();
+        for network in [BtcNetwork::Bitcoin, BtcNetwork::Testnet] {
+            for address_type in [AddressType::P2pkh, AddressType::P2shP2wpkh, AddressType::P2wpkh, AddressType::P2tr] {
+                for hardened in [false, true] {
+                    let derived = derive(address_type, Children::new(0, 5, hardened).unwrap(), network);
+                    out.push_str(&render(&derived, address_type, Format::Csv, true).unwrap());
+                }
+            }
+        }
+        out
+    }
+
+    // The output from before derivation moved to the bitcoin crate's BIP32, byte for byte
+    #[test]
+    fn test_regression_output() {
+        let out = regression_output(&|address_type, children, network| {
+            derive_addresses(&Secp256k1::new(), &abandon_master(), "m/84'/0'/0'/0", address_type, children, network).unwrap()
+        });
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 96);
+        assert_eq!(
+            lines[1],
+            "m/84'/0'/0'/0/0,1JaUQDVNRdhfNsVncGkXedaPSM5Gc54Hso,0330d54fd0dd420a6e5f8d3624f5f3482cae350f79d5f0753bf5beef9c2d91af3c,4604b4b710fe91f584fff084e1a9159fe4f8408fff380596a604948474ce4fa3,KyZpNDKnfs94vbrwhJneDi77V6jF64PWPF8x5cdJb8ifgg2DUc9d"
+        );
+        assert_eq!(
+            lines[80],
+            "m/84'/0'/0'/0/1',tb1qxddz3r05vresrk5x0p25duyzp8vwear7wpvw2z,03bfa4a7eb0fa1ccbaa7596aeeb3ac1198c9e5eb7cfd958f396fe5843b4b79e7ca,a301124610b80f94854a8f66b640461a1fe3c64201c8bf8bbeb145b33172bcd0,cT3ZThCmBx7vwxdeZz3nMXxEgAYsvD71pr3Wp9967RafjiSk6ax1"
+        );
+        let digest = <bitcoin::hashes::sha256::Hash as bitcoin::hashes::Hash>::hash(out.as_bytes());
+        assert_eq!(digest.to_string(), "edf3b1ce7e2de9e1f60d83081425e25fbc8304645be8e617bb4851cc5a8909ab");
+    }
+
+    // BIP32 test vectors 1 and 2: the master key is the vector's, and each child, derived as the
+    // last step below its parent's path, has the keys of the vector's xprv and xpub
+    #[test]
+    fn test_bip32_vectors() {
+        // A chain's paths, each with its xprv and xpub
+        type Chain = &'static [(&'static str, &'static str, &'static str)];
+        let vectors: [(&str, Chain); 2] = [
+            (
+                "000102030405060708090a0b0c0d0e0f",
+                &[
+                    (
+                        "m",
+                        "xprv9s21ZrQH143K3QTDL4LXw2F7HEK3wJUD2nW2nRk4stbPy6cq3jPPqjiChkVvvNKmPGJxWUtg6LnF5kejMRNNU3TGtRBeJgk33yuGBxrMPHi",
+                        "xpub661MyMwAqRbcFtXgS5sYJABqqG9YLmC4Q1Rdap9gSE8NqtwybGhePY2gZ29ESFjqJoCu1Rupje8YtGqsefD265TMg7usUDFdp6W1EGMcet8",
+                    ),
+                    (
+                        "m/0'",
+                        "xprv9uHRZZhk6KAJC1avXpDAp4MDc3sQKNxDiPvvkX8Br5ngLNv1TxvUxt4cV1rGL5hj6KCesnDYUhd7oWgT11eZG7XnxHrnYeSvkzY7d2bhkJ7",
+                        "xpub68Gmy5EdvgibQVfPdqkBBCHxA5htiqg55crXYuXoQRKfDBFA1WEjWgP6LHhwBZeNK1VTsfTFUHCdrfp1bgwQ9xv5ski8PX9rL2dZXvgGDnw",
+                    ),
+                    (
+                        "m/0'/1",
+                        "xprv9wTYmMFdV23N2TdNG573QoEsfRrWKQgWeibmLntzniatZvR9BmLnvSxqu53Kw1UmYPxLgboyZQaXwTCg8MSY3H2EU4pWcQDnRnrVA1xe8fs",
+                        "xpub6ASuArnXKPbfEwhqN6e3mwBcDTgzisQN1wXN9BJcM47sSikHjJf3UFHKkNAWbWMiGj7Wf5uMash7SyYq527Hqck2AxYysAA7xmALppuCkwQ",
+                    ),
+                    (
+                        "m/0'/1/2'",
+                        "xprv9z4pot5VBttmtdRTWfWQmoH1taj2axGVzFqSb8C9xaxKymcFzXBDptWmT7FwuEzG3ryjH4ktypQSAewRiNMjANTtpgP4mLTj34bhnZX7UiM",
+                        "xpub6D4BDPcP2GT577Vvch3R8wDkScZWzQzMMUm3PWbmWvVJrZwQY4VUNgqFJPMM3No2dFDFGTsxxpG5uJh7n7epu4trkrX7x7DogT5Uv6fcLW5",
+                    ),
+                    (
+                        "m/0'/1/2'/2",
+                        "xprvA2JDeKCSNNZky6uBCviVfJSKyQ1mDYahRjijr5idH2WwLsEd4Hsb2Tyh8RfQMuPh7f7RtyzTtdrbdqqsunu5Mm3wDvUAKRHSC34sJ7in334",
+                        "xpub6FHa3pjLCk84BayeJxFW2SP4XRrFd1JYnxeLeU8EqN3vDfZmbqBqaGJAyiLjTAwm6ZLRQUMv1ZACTj37sR62cfN7fe5JnJ7dh8zL4fiyLHV",
+                    ),
+                    (
+                        "m/0'/1/2'/2/1000000000",
+                        "xprvA41z7zogVVwxVSgdKUHDy1SKmdb533PjDz7J6N6mV6uS3ze1ai8FHa8kmHScGpWmj4WggLyQjgPie1rFSruoUihUZREPSL39UNdE3BBDu76",
+                        "xpub6H1LXWLaKsWFhvm6RVpEL9P4KfRZSW7abD2ttkWP3SSQvnyA8FSVqNTEcYFgJS2UaFcxupHiYkro49S8yGasTvXEYBVPamhGW6cFJodrTHy",
+                    ),
+                ],
+            ),
+            (
+                "fffcf9f6f3f0edeae7e4e1dedbd8d5d2cfccc9c6c3c0bdbab7b4b1aeaba8a5a29f9c999693908d8a8784817e7b7875726f6c696663605d5a5754514e4b484542",
+                &[
+                    (
+                        "m",
+                        "xprv9s21ZrQH143K31xYSDQpPDxsXRTUcvj2iNHm5NUtrGiGG5e2DtALGdso3pGz6ssrdK4PFmM8NSpSBHNqPqm55Qn3LqFtT2emdEXVYsCzC2U",
+                        "xpub661MyMwAqRbcFW31YEwpkMuc5THy2PSt5bDMsktWQcFF8syAmRUapSCGu8ED9W6oDMSgv6Zz8idoc4a6mr8BDzTJY47LJhkJ8UB7WEGuduB",
+                    ),
+                    (
+                        "m/0",
+                        "xprv9vHkqa6EV4sPZHYqZznhT2NPtPCjKuDKGY38FBWLvgaDx45zo9WQRUT3dKYnjwih2yJD9mkrocEZXo1ex8G81dwSM1fwqWpWkeS3v86pgKt",
+                        "xpub69H7F5d8KSRgmmdJg2KhpAK8SR3DjMwAdkxj3ZuxV27CprR9LgpeyGmXUbC6wb7ERfvrnKZjXoUmmDznezpbZb7ap6r1D3tgFxHmwMkQTPH",
+                    ),
+                    (
+                        "m/0/2147483647'",
+                        "xprv9wSp6B7kry3Vj9m1zSnLvN3xH8RdsPP1Mh7fAaR7aRLcQMKTR2vidYEeEg2mUCTAwCd6vnxVrcjfy2kRgVsFawNzmjuHc2YmYRmagcEPdU9",
+                        "xpub6ASAVgeehLbnwdqV6UKMHVzgqAG8Gr6riv3Fxxpj8ksbH9ebxaEyBLZ85ySDhKiLDBrQSARLq1uNRts8RuJiHjaDMBU4Zn9h8LZNnBC5y4a",
+                    ),
+                    (
+                        "m/0/2147483647'/1",
+                        "xprv9zFnWC6h2cLgpmSA46vutJzBcfJ8yaJGg8cX1e5StJh45BBciYTRXSd25UEPVuesF9yog62tGAQtHjXajPPdbRCHuWS6T8XA2ECKADdw4Ef",
+                        "xpub6DF8uhdarytz3FWdA8TvFSvvAh8dP3283MY7p2V4SeE2wyWmG5mg5EwVvmdMVCQcoNJxGoWaU9DCWh89LojfZ537wTfunKau47EL2dhHKon",
+                    ),
+                    (
+                        "m/0/2147483647'/1/2147483646'",
+                        "xprvA1RpRA33e1JQ7ifknakTFpgNXPmW2YvmhqLQYMmrj4xJXXWYpDPS3xz7iAxn8L39njGVyuoseXzU6rcxFLJ8HFsTjSyQbLYnMpCqE2VbFWc",
+                        "xpub6ERApfZwUNrhLCkDtcHTcxd75RbzS1ed54G1LkBUHQVHQKqhMkhgbmJbZRkrgZw4koxb5JaHWkY4ALHY2grBGRjaDMzQLcgJvLJuZZvRcEL",
+                    ),
+                    (
+                        "m/0/2147483647'/1/2147483646'/2",
+                        "xprvA2nrNbFZABcdryreWet9Ea4LvTJcGsqrMzxHx98MMrotbir7yrKCEXw7nadnHM8Dq38EGfSh6dqA9QWTyefMLEcBYJUuekgW4BYPJcr9E7j",
+                        "xpub6FnCn6nSzZAw5Tw7cgR9bi15UV96gLZhjDstkXXxvCLsUXBGXPdSnLFbdpq8p9HmGsApME5hQTZ3emM2rnY5agb9rXpVGyy3bdW6EEgAtqt",
+                    ),
+                ],
+            ),
+        ];
+        for (seed, chain) in vectors {
+            let master = Xpriv::new_master(Network::Bitcoin, &hex::decode(seed).unwrap()).unwrap();
+            for &(path, xprv, xpub) in chain {
+                let xprv = Xpriv::from_str(xprv).unwrap();
+                let xpub = Xpub::from_str(xpub).unwrap();
+                let Some((parent, last)) = path.rsplit_once('/') else {
+                    assert_eq!(master, xprv);
+                    continue;
+                };
+                let hardened = last.ends_with('\'');
+                let index = last.trim_end_matches('\'').parse().unwrap();
+                let children = Children::new(index, 1, hardened).unwrap();
+                let child = derive_addresses(&Secp256k1::new(), &master, parent, AddressType::P2pkh, children, BtcNetwork::Bitcoin).unwrap().remove(0);
+                assert_eq!(child.path, path);
+                assert_eq!(child.private_key.as_deref().map(String::as_str), Some(hex::encode(xprv.private_key.secret_bytes()).as_str()), "{}", path);
+                assert_eq!(child.public_key, xpub.public_key.to_string(), "{}", path);
+                let wif = PrivateKey::new(xprv.private_key, Network::Bitcoin).to_wif();
+                assert_eq!(child.wif.as_deref().map(String::as_str), Some(wif.as_str()), "{}", path);
+            }
+        }
+    }
+
+    // 10k addresses stream out in batches, serially and on 4 threads alike, and in the order and
+    // with the one header that rendering them all at once gives
+    #[test]
+    fn test_streaming_and_parallel() {
+        let secp = Secp256k1::new();
+        let master = abandon_master();
+        let derivation = Derivation {
+            derive: Box::new(|children| derive_addresses(&secp, &master, "m/84'/0'/0'/0", AddressType::P2wpkh, children, BtcNetwork::Bitcoin)),
+            address_type: AddressType::P2wpkh,
+            account: None,
+        };
+        let children = Children::new(5, 10_000, false).unwrap();
+        let started = std::time::Instant::now();
+        let mut serial = Vec::new();
+        write_addresses(&mut serial, &derivation, children, Format::Csv, true, 1).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(120), "{:?}", started.elapsed());
+        let mut parallel = Vec::new();
+        write_addresses(&mut parallel, &derivation, children, Format::Csv, true, 4).unwrap();
+        assert!(serial == parallel);
+
+        let serial = String::from_utf8(serial).unwrap();
+        let lines: Vec<&str> = serial.lines().collect();
+        assert_eq!(lines.len(), 10_001);
+        assert_eq!(lines[0], "path,address,public_key,private_key,wif");
+        assert!(lines[1].starts_with("m/84'/0'/0'/0/5,bc1q"));
+        assert!(lines[10_000].starts_with("m/84'/0'/0'/0/10004,bc1q"));
+        let whole = render(&(derivation.derive)(children).unwrap(), AddressType::P2wpkh, Format::Csv, true).unwrap();
+        assert_eq!(serial, *whole);
+
+        // JSON Lines, one object per line, and a JSON array, written once whole, alike
+        let children = Children::new(0, 2_500, false).unwrap();
+        for format in [Format::Jsonl, Format::Json] {
+            let mut serial = Vec::new();
+            write_addresses(&mut serial, &derivation, children, format, false, 1).unwrap();
+            let mut parallel = Vec::new();
+            write_addresses(&mut parallel, &derivation, children, format, false, 3).unwrap();
+            assert!(serial == parallel);
+        }
+        let mut jsonl = Vec::new();
+        write_addresses(&mut jsonl, &derivation, children, Format::Jsonl, false, 3).unwrap();
+        let records: Vec<serde_json::Value> =
+            String::from_utf8(jsonl).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(records.len(), 2_500);
+        assert_eq!(records[0]["address"], "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu");
+        assert_eq!(records[2_499]["path"], "m/84'/0'/0'/0/2499");
+        assert!(records[0].get("wif").is_none());
+    }
+
+    #[test]
+    fn test_output_file() {
+        let path = env::temp_dir().join(format!("generate_addresses_{}.jsonl", std::process::id()));
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let mut parsed: Vec<String> = "generate_addresses --address-type p2wpkh --format jsonl --parallel --count 3 --show-secrets --output"
+            .split(' ')
+            .map(String::from)
+            .collect();
+        parsed.extend([path.to_str().unwrap(), phrase, "m/84'/0'/0'/0"].map(String::from));
+        let options = parse_args(&parsed).unwrap();
+        assert_eq!(options.output, Some(path.to_str().unwrap()));
+        assert!(options.parallel);
+        run(options).unwrap();
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        let metadata = std::fs::metadata(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written.lines().count(), 3);
+        let first: serde_json::Value = serde_json::from_str(written.lines().next().unwrap()).unwrap();
+        assert_eq!(first["address"], "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu");
+        assert!(looks_like_wif(first["wif"].as_str().unwrap()));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o600);
+        }
+        #[cfg(not(unix))]
+        let _ = metadata;
+
+        let args = |line: &str| line.split(' ').map(String::from).collect::<Vec<String>>();
+        for line in [
+            "generate_addresses --output",
+            "generate_addresses --scan --parallel zpub m/0",
+            "generate_addresses --scan --format jsonl zpub m/0",
+            "generate_addresses --multisig 1 --xpub xpub --parallel m/0",
+        ] {
+            assert!(matches!(parse_args(&args(line)), Err(CliError::Usage(_))), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_electrum_export() {
+        // The same key, m/84'/0'/0'/0/0, behind each type's prefix
+        let wif = "KyZpNDKnfs94vbrwhJneDi77V6jF64PWPF8x5cdJb8ifgg2DUc9d";
+        for (address_type, prefix) in
+            [(AddressType::P2pkh, "p2pkh:"), (AddressType::P2shP2wpkh, "p2wpkh-p2sh:"), (AddressType::P2wpkh, "p2wpkh:")]
+        {
+            let derived = derive_addresses(&Secp256k1::new(), &abandon_master(), "m/84'/0'/0'/0", address_type, Children::default(), BtcNetwork::Bitcoin).unwrap();
+            let export = render(&derived, address_type, Format::Electrum, true).unwrap();
+            let lines: Vec<&str> = export.lines().collect();
+            assert_eq!(lines.len(), 10);
+            assert_eq!(lines[0], format!("{}{}", prefix, wif));
+            for (line, child) in lines.iter().zip(&derived) {
+                assert_eq!(line.strip_prefix(prefix), child.wif.as_deref().map(String::as_str));
+            }
+
+            // Without --show-secrets, the addresses alone
+            let watch_only = render(&derived, address_type, Format::Electrum, false).unwrap();
+            let addresses: Vec<&str> = derived.iter().map(|child| child.address.as_str()).collect();
+            assert_eq!(watch_only.lines().collect::<Vec<&str>>(), addresses);
+        }
+        assert_eq!(
+            addresses("m/84'/0'/0'/0", AddressType::P2wpkh)[0],
+            render(&bip84(), AddressType::P2wpkh, Format::Electrum, false).unwrap().lines().next().unwrap()
+        );
+
+        // Testnet keys, and no p2tr keys, which Electrum cannot import
+        let children = Children::new(0, 1, false).unwrap();
+        let testnet = derive_addresses(&Secp256k1::new(), &abandon_master(), "m/84'/1'/0'/0", AddressType::P2wpkh, children, BtcNetwork::Testnet).unwrap();
+        assert!(render(&testnet, AddressType::P2wpkh, Format::Electrum, true).unwrap().starts_with("p2wpkh:c"));
+        let taproot = derive_addresses(&Secp256k1::new(), &abandon_master(), "m/86'/0'/0'/0", AddressType::P2tr, children, BtcNetwork::Bitcoin).unwrap();
+        assert!(render(&taproot, AddressType::P2tr, Format::Electrum, true).is_err());
+        assert!(render(&taproot, AddressType::P2tr, Format::Electrum, false).unwrap().starts_with("bc1p"));
+
+        let args = |line: &str| line.split(' ').map(String::from).collect::<Vec<String>>();
+        let parsed = args("generate_addresses --export Electrum --show-secrets zpub m/0");
+        assert_eq!(parse_args(&parsed).unwrap().format, Format::Electrum);
+        for line in [
+            "generate_addresses --export electrum --format csv zpub m/0",
+            "generate_addresses --export bitcoin-core zpub m/0",
+            "generate_addresses --export electrum --scan zpub m/0",
+            "generate_addresses --multisig 1 --xpub xpub --export electrum m/0",
+        ] {
+            assert!(matches!(parse_args(&args(line)), Err(CliError::Usage(_))), "{}", line);
+        }
+
+        // A p2tr key export is refused before anything is written
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let parsed: Vec<String> =
+            ["generate_addresses", "--export", "electrum", "--show-secrets", "--address-type", "p2tr", phrase, "m/86'/0'/0'/0"]
+                .map(String::from)
+                .to_vec();
+        let options = parse_args(&parsed).unwrap();
+        let derivation = derive(&options, BtcNetwork::Bitcoin).unwrap();
+        assert!(export_header(&derivation, &options, BtcNetwork::Bitcoin).is_err());
+    }
+
+    #[cfg(feature = "qr")]
+    #[test]
+    fn test_qr_files() {
+        let dir = env::temp_dir().join(format!("generate_addresses_qr_{}", std::process::id()));
+        let phrase = "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+        let qr_args = |flags: &str, path: &str| {
+            let mut parsed: Vec<String> = format!("generate_addresses --address-type p2wpkh --count 2 --start-index 4 {}", flags)
+                .split(' ')
+                .map(String::from)
+                .collect();
+            parsed.extend(["--qr-file", dir.to_str().unwrap(), phrase, path].map(String::from));
+            parsed
+        };
+        let parsed = qr_args("--amount 0.001", "m/84'/0'/0'/0");
+        let options = parse_args(&parsed).unwrap();
+        assert_eq!(options.qr.as_ref().unwrap().amount, Some(Amount::from_sat(100_000)));
+        run(options).unwrap();
+        let mut names: Vec<String> = std::fs::read_dir(&dir).unwrap().map(|entry| entry.unwrap().file_name().into_string().unwrap()).collect();
+        names.sort();
+        assert_eq!(names, ["4.svg", "5.svg"]);
+        assert!(std::fs::read_to_string(dir.join("4.svg")).unwrap().contains("<svg"));
+
+        // The WIFs only with --qr-secrets, and readable by the owner alone
+        let parsed = qr_args("--show-secrets --qr-secrets --hardened", "m/84'/0'/0'/0");
+        run(parse_args(&parsed).unwrap()).unwrap();
+        let wif = std::fs::metadata(dir.join("4h-wif.svg")).unwrap();
+        assert!(dir.join("5h.svg").exists() && dir.join("5h-wif.svg").exists());
+        std::fs::remove_dir_all(&dir).unwrap();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(wif.permissions().mode() & 0o777, 0o600);
+        }
+        #[cfg(not(unix))]
+        let _ = wif;
+
+        let args = |line: &str| line.split(' ').map(String::from).collect::<Vec<String>>();
+        for line in [
+            "generate_addresses --amount 0.1 zpub m/0",
+            "generate_addresses --qr-secrets --show-secrets zpub m/0",
+            "generate_addresses --qr --qr-secrets zpub m/0",
+            "generate_addresses --qr --format json zpub m/0",
+            "generate_addresses --qr --amount -1 zpub m/0",
+            "generate_addresses --qr --amount 0 zpub m/0",
+            "generate_addresses --qr --amount 1sat zpub m/0",
+            "generate_addresses --qr --parallel zpub m/0",
+            "generate_addresses --qr-file dir --export electrum zpub m/0",
+            "generate_addresses --qr-file dir --scan zpub m/0",
+        ] {
+            assert!(matches!(parse_args(&args(line)), Err(CliError::Usage(_))), "{}", line);
+        }
+        assert!(parse_args(&args("generate_addresses --qr-file dir --format csv zpub m/0")).is_ok());
+    }
+}
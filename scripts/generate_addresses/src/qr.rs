@@ -0,0 +1,201 @@
+// QR codes of derived addresses, for paper backups and receiving in person: drawn in the
+// terminal with half blocks, two rows of modules to a line, or written out as SVG
+
+use bitcoin::{Amount, Denomination};
+use qrcode::render::{svg, unicode};
+use qrcode::{EcLevel, QrCode};
+use zeroize::Zeroizing;
+
+// Pixels a side of an SVG code, at the least
+const SVG_SIZE: u32 = 256;
+
+// Function to make the text an address's code holds: the address, or a BIP21 URI when an amount
+// is asked for. A bech32 address is written in upper case, which it may be as a whole, so the
+// code can use the alphanumeric mode, at 5.5 bits a character rather than 8
+pub fn address_payload(address: &str, bech32: bool, amount: Option<Amount>) -> String {
+    let address = if bech32 { address.to_ascii_uppercase() } else { address.to_string() };
+    match amount {
+        Some(amount) => {
+            let scheme = if bech32 { "BITCOIN" } else { "bitcoin" };
+            format!("{}:{}?amount={}", scheme, address, amount.to_string_in(Denomination::Bitcoin))
+        }
+        None => address,
+    }
+}
+
+// Function to encode the text at error correction level M, which survives a smudged or folded
+// paper copy while keeping an address to a version 3 or 4 code
+pub fn encode(payload: &str) -> Result<QrCode, String> {
+    QrCode::with_error_correction_level(payload, EcLevel::M).map_err(|e| format!("Cannot make a QR code: {}", e))
+}
+
+// Function to draw the code for a terminal with a dark background: dark modules are left blank
+// and light ones drawn, with the quiet zone around them
+pub fn to_terminal(code: &QrCode) -> Zeroizing<String> {
+    Zeroizing::new(
+        code.render::<unicode::Dense1x2>()
+            .dark_color(unicode::Dense1x2::Light)
+            .light_color(unicode::Dense1x2::Dark)
+            .build(),
+    )
+}
+
+// Function to write the code as an SVG image, black on white
+pub fn to_svg(code: &QrCode) -> Zeroizing<String> {
+    Zeroizing::new(code.render::<svg::Color>().min_dimensions(SVG_SIZE, SVG_SIZE).build())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use qrcode::Color;
+
+    const ALPHANUMERIC: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+    // Function to read a code back, for the versions 1 to 4 at level M that addresses and their
+    // URIs take: it undoes the mask the format bits name, follows the codewords' zigzag, takes
+    // the data codewords out of the blocks and reads the numeric, alphanumeric and byte segments
+    fn decode(code: &QrCode) -> String {
+        let size = code.width();
+        let colors = code.to_colors();
+        let dark = |x: usize, y: usize| colors[y * size + x] == Color::Dark;
+        let version = (size - 17) / 4;
+        assert!((1..=4).contains(&version), "version {}", version);
+
+        // The first copy of the format bits, around the top left finder
+        let mut format = 0;
+        for i in 0..15 {
+            let (x, y) = match i {
+                0..=5 => (8, i),
+                6 => (8, 7),
+                7 => (8, 8),
+                8 => (7, 8),
+                _ => (14 - i, 8),
+            };
+            format |= (dark(x, y) as usize) << i;
+        }
+        format ^= 0x5412;
+        assert_eq!(format >> 13, 0, "level M");
+        let mask = (format >> 10) & 7;
+
+        // The finders with their separators and the format bits, the timing patterns, and from
+        // version 2 the one alignment pattern, 7 modules in from the bottom right
+        let function = |x: usize, y: usize| {
+            (y < 9 && (x < 9 || x >= size - 8))
+                || (x < 9 && y >= size - 8)
+                || x == 6
+                || y == 6
+                || (version >= 2 && (size - 9..size - 4).contains(&x) && (size - 9..size - 4).contains(&y))
+        };
+        let masked = |x: usize, y: usize| match mask {
+            0 => (x + y).is_multiple_of(2),
+            1 => y.is_multiple_of(2),
+            2 => x.is_multiple_of(3),
+            3 => (x + y).is_multiple_of(3),
+            4 => (x / 3 + y / 2).is_multiple_of(2),
+            5 => x * y % 2 + x * y % 3 == 0,
+            6 => (x * y % 2 + x * y % 3).is_multiple_of(2),
+            _ => ((x + y) % 2 + x * y % 3).is_multiple_of(2),
+        };
+        let mut bits = Vec::new();
+        let mut right = size - 1;
+        while right >= 1 {
+            if right == 6 {
+                right = 5;
+            }
+            for vert in 0..size {
+                for j in 0..2 {
+                    let x = right - j;
+                    let y = if (right + 1) & 2 == 0 { size - 1 - vert } else { vert };
+                    if !function(x, y) {
+                        bits.push(dark(x, y) != masked(x, y));
+                    }
+                }
+            }
+            if right < 2 {
+                break;
+            }
+            right -= 2;
+        }
+        let codewords: Vec<u8> =
+            bits.chunks_exact(8).map(|byte| byte.iter().fold(0, |acc, &bit| acc << 1 | bit as u8)).collect();
+
+        // Level M's blocks and their error correction codewords; data codewords are interleaved
+        let (blocks, ecc) = [(1, 10), (1, 16), (1, 26), (2, 18)][version - 1];
+        let per_block = codewords.len() / blocks - ecc;
+        let mut data = Vec::new();
+        for block in 0..blocks {
+            data.extend((0..per_block).map(|i| codewords[i * blocks + block]));
+        }
+
+        let data_bits: Vec<bool> = data.iter().flat_map(|byte| (0..8).rev().map(move |i| byte >> i & 1 == 1)).collect();
+        let mut reader = data_bits.into_iter();
+        let mut read = |n: usize| (0..n).fold(0, |acc, _| acc << 1 | reader.next().unwrap() as usize);
+        let mut text = String::new();
+        loop {
+            match read(4) {
+                0 => break,
+                1 => {
+                    let mut count = read(10);
+                    while count > 0 {
+                        let digits = count.min(3);
+                        text.push_str(&format!("{:01$}", read([4, 7, 10][digits - 1]), digits));
+                        count -= digits;
+                    }
+                }
+                2 => {
+                    let count = read(9);
+                    for _ in 0..count / 2 {
+                        let pair = read(11);
+                        text.push(ALPHANUMERIC.as_bytes()[pair / 45] as char);
+                        text.push(ALPHANUMERIC.as_bytes()[pair % 45] as char);
+                    }
+                    if count % 2 == 1 {
+                        text.push(ALPHANUMERIC.as_bytes()[read(6)] as char);
+                    }
+                }
+                4 => {
+                    let count = read(8);
+                    let bytes: Vec<u8> = (0..count).map(|_| read(8) as u8).collect();
+                    text.push_str(std::str::from_utf8(&bytes).unwrap());
+                }
+                mode => panic!("unexpected mode {}", mode),
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn test_codes_decode_to_the_address() {
+        // The BIP84 test vector's first address, and BIP86's
+        let p2wpkh = "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu";
+        let p2tr = "bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr";
+        for address in [p2wpkh, p2tr] {
+            let payload = address_payload(address, true, None);
+            assert_eq!(payload, address.to_ascii_uppercase());
+            assert!(payload.chars().all(|c| ALPHANUMERIC.contains(c)));
+            assert_eq!(decode(&encode(&payload).unwrap()).to_ascii_lowercase(), address);
+        }
+
+        // A base58 address keeps its case, and goes in byte mode
+        let p2pkh = "1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA";
+        assert_eq!(address_payload(p2pkh, false, None), p2pkh);
+        assert_eq!(decode(&encode(p2pkh).unwrap()), p2pkh);
+
+        // BIP21 URIs, with the amount in BTC
+        let amount = Amount::from_sat(100_000);
+        let uri = address_payload(p2wpkh, true, Some(amount));
+        assert_eq!(uri, "BITCOIN:BC1QCR8TE4KR609GCAWUTMRZA0J4XV80JY8Z306FYU?amount=0.001");
+        assert_eq!(decode(&encode(&uri).unwrap()), uri);
+        let uri = address_payload(p2pkh, false, Some(Amount::from_sat(150_000_000)));
+        assert_eq!(uri, "bitcoin:1LqBGSKuX5yYUonjxT5qGfpUsXKYYWeabA?amount=1.5");
+        assert_eq!(decode(&encode(&uri).unwrap()), uri);
+
+        // The terminal drawing has a line for every two rows, quiet zone included, and the SVG the
+        // modules' squares
+        let code = encode(&address_payload(p2wpkh, true, None)).unwrap();
+        assert_eq!(to_terminal(&code).lines().count(), (code.width() + 8).div_ceil(2));
+        let svg = to_svg(&code);
+        assert!(svg.starts_with("<?xml") && svg.contains("<svg"));
+    }
+}
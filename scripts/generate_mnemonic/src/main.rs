@@ -1,135 +1,898 @@
 use std::env;
-use std::fs::File;
-use std::io::{self, BufRead, BufReader};
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io::{self, Read};
 use std::path::Path;
-use std::collections::HashMap;
+use std::str::FromStr;
+use bitcoin::bip32::Xpriv;
+use bitcoin::hex::{DisplayHex, FromHex};
+use bitcoin::Network;
+use btcx_lib::mnemonic::{self, Language, MnemonicError};
+use btcx_lib::types::BtcNetwork;
 use rand::rngs::OsRng;
-use rand::RngCore;
+use rand::TryRngCore;
+use serde::Serialize;
 use sha2::{Sha256, Digest};
 use bitvec::prelude::*;
+use zeroize::Zeroizing;
+
+mod slip39;
+
+// Environment variable giving the network when --network is not passed
+const NETWORK_ENV: &str = "BTCX_NETWORK";
+
+// The wordlist in use: a built-in one, or the words of a --wordlist file
+enum Wordlist {
+    BuiltIn(Language),
+    File(Vec<String>),
+}
+
+impl Wordlist {
+    fn words(&self) -> Vec<&str> {
+        match self {
+            Wordlist::BuiltIn(language) => language.wordlist().to_vec(),
+            Wordlist::File(words) => words.iter().map(String::as_str).collect(),
+        }
+    }
+}
 
 // Function to read the wordlist from a file
 fn read_wordlist<P: AsRef<Path>>(path: P) -> io::Result<Vec<String>> {
-    let file = File::open(path)?;
-    let reader = BufReader::new(file);
-    let wordlist: Vec<String> = reader.lines().collect::<io::Result<Vec<String>>>()?;
-    if wordlist.len() != 2048 {
-        return Err(io::Error::new(
-            io::ErrorKind::InvalidData,
-            "Wordlist must contain exactly 2048 words",
-        ));
+    let text = fs::read_to_string(path)?;
+    let words = mnemonic::parse_wordlist(&text).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    Ok(words.into_iter().map(str::to_string).collect())
+}
+
+// Function to check entropy supplied as hex, which must be 16, 20, 24, 28 or 32 bytes. The
+// hex is a secret, so errors do not repeat it
+fn parse_entropy_hex(entropy_hex: &str) -> Result<Zeroizing<Vec<u8>>, String> {
+    let entropy = Zeroizing::new(Vec::<u8>::from_hex(entropy_hex.trim()).map_err(|_| "--entropy is not valid hex".to_string())?);
+    if ![16, 20, 24, 28, 32].contains(&entropy.len()) {
+        return Err(format!("--entropy is {} bytes; it must be 16, 20, 24, 28 or 32", entropy.len()));
     }
-    Ok(wordlist)
+    Ok(entropy)
 }
 
-fn main() -> io::Result<()> {
-    // Collect command-line arguments
-    let args: Vec<String> = env::args().collect();
-    if args.len() != 3 {
-        println!("Usage: {} <wordlist_path> <number_of_words>", args[0]);
-        return Ok(());
+// Physical randomness the user supplies in place of, or alongside, OsRng
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum RollKind {
+    Dice,
+    Coins,
+}
+
+// Function to turn dice rolls or coin flips into unbiased bits; whitespace is ignored.
+// Coins: H is 1 and T is 0, one bit per flip.
+// Dice: a roll holds log2(6) ≈ 2.58 bits, which do not split into whole bits, and reading the
+// rolls as one base-6 number and keeping its low bits would favour some values over others.
+// Instead each roll gives whole, unbiased bits: 1-4 give two bits (00, 01, 10, 11), each as
+// likely as the others, and 5-6 give one (0, 1). That averages 5/3 bits a roll, so 128 bits
+// of entropy take about 77 rolls and 256 bits about 154
+fn roll_bits(rolls: &str, kind: RollKind) -> Result<BitVec<u8, Msb0>, String> {
+    let mut bits = BitVec::<u8, Msb0>::new();
+    for (index, roll) in rolls.chars().filter(|c| !c.is_whitespace()).enumerate() {
+        match (kind, roll.to_ascii_uppercase()) {
+            (RollKind::Dice, '1'..='4') => {
+                let value = roll as u8 - b'1';
+                bits.push(value & 2 != 0);
+                bits.push(value & 1 != 0);
+            }
+            (RollKind::Dice, '5' | '6') => bits.push(roll == '6'),
+            (RollKind::Coins, 'H' | 'T') => bits.push(roll.eq_ignore_ascii_case(&'H')),
+            (RollKind::Dice, _) => return Err(format!("Roll {} is not a digit from 1 to 6", index + 1)),
+            (RollKind::Coins, _) => return Err(format!("Flip {} is not H or T", index + 1)),
+        }
+    }
+    Ok(bits)
+}
+
+// Function to take `ent_bits` of entropy from the rolls, saying how many more are needed when
+// they fall short; bits past the first `ent_bits` are not used
+fn roll_entropy(rolls: &str, kind: RollKind, ent_bits: usize) -> Result<Zeroizing<Vec<u8>>, String> {
+    let mut bits = roll_bits(rolls, kind)?;
+    if bits.len() < ent_bits {
+        let missing = ent_bits - bits.len();
+        return Err(match kind {
+            RollKind::Dice => format!(
+                "The rolls give {} of the {} bits needed; roll at least {} more dice, up to {} if they come up 5 or 6",
+                bits.len(),
+                ent_bits,
+                missing.div_ceil(2),
+                missing
+            ),
+            RollKind::Coins => {
+                format!("The flips give {} of the {} bits needed; flip {} more coins", bits.len(), ent_bits, missing)
+            }
+        });
+    }
+    let entropy = Zeroizing::new(bits[..ent_bits].to_bitvec().into_vec());
+    bits.fill(false);
+    Ok(entropy)
+}
+
+// Function to mix the user's entropy with OsRng's, so the result is no weaker than either:
+// the first bytes of SHA-256 over both
+fn mix_entropy(user: &[u8], os: &[u8]) -> Zeroizing<Vec<u8>> {
+    let mut hasher = Sha256::new();
+    hasher.update(user);
+    hasher.update(os);
+    let mut digest = hasher.finalize();
+    let mixed = Zeroizing::new(digest[..user.len()].to_vec());
+    digest.fill(0);
+    mixed
+}
+
+// BIP32 master extended private key for the seed, as xprv on mainnet and tprv elsewhere
+fn master_xprv(seed: &[u8], network: BtcNetwork) -> io::Result<Zeroizing<String>> {
+    let xprv = Xpriv::new_master(Network::from(network), seed).map_err(io::Error::other)?;
+    Ok(Zeroizing::new(xprv.to_string()))
+}
+
+// Function to read the passphrase twice without echoing it, refusing a mismatch
+fn prompt_passphrase(mut prompt: impl FnMut(&str) -> io::Result<String>) -> io::Result<Zeroizing<String>> {
+    let passphrase = Zeroizing::new(prompt("Passphrase: ")?);
+    let repeated = Zeroizing::new(prompt("Repeat passphrase: ")?);
+    if passphrase != repeated {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "The passphrases do not match"));
+    }
+    Ok(passphrase)
+}
+
+// The network from --network, else from the environment, else mainnet
+fn resolve_network(flag: Option<&str>, env: Option<String>) -> Result<BtcNetwork, String> {
+    match flag.map(str::to_string).or(env) {
+        Some(name) => BtcNetwork::from_str(&name)
+            .map_err(|_| format!("Unknown network {:?}; use mainnet, testnet, signet or regtest", name)),
+        None => Ok(BtcNetwork::Bitcoin),
+    }
+}
+
+// What --json prints; the seed and xprv only when asked for
+#[derive(Serialize)]
+struct MnemonicOutput<'a> {
+    mnemonic: &'a str,
+    entropy: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    xprv: Option<&'a str>,
+}
+
+// What --json prints with --slip39: the shares of each group
+#[derive(Serialize)]
+struct Slip39Output<'a> {
+    groups: Vec<Vec<&'a str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    seed: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    xprv: Option<&'a str>,
+}
+
+// Function to take a secret argument as given, or from stdin when it is -, so it stays out of
+// shell history
+fn read_secret_arg(value: &str) -> io::Result<Zeroizing<String>> {
+    if value == "-" {
+        let mut buffer = Zeroizing::new(String::new());
+        io::stdin().read_to_string(&mut buffer)?;
+        Ok(buffer)
+    } else {
+        Ok(Zeroizing::new(value.to_string()))
+    }
+}
+
+fn print_usage(program: &str, out: &mut dyn io::Write) -> io::Result<()> {
+    let names: Vec<&str> = Language::ALL.iter().map(|language| language.name()).collect();
+    writeln!(
+        out,
+        "Usage: {} [--language <language>] [--wordlist <path>] \
+         [--entropy <hex> | --dice <rolls> | --coins <flips> [--entropy-only]] \
+         [--passphrase <passphrase> | --prompt-passphrase] \
+         [--show-seed] [--show-xprv] [--network <mainnet|testnet|signet|regtest>] [--json] <number_of_words>",
+        program
+    )?;
+    writeln!(out, "       {} validate [--language <language>] [--wordlist <path>] [mnemonic]", program)?;
+    writeln!(out, "       {} --to-entropy [--language <language>] [--wordlist <path>] [mnemonic]", program)?;
+    writeln!(
+        out,
+        "       {} --slip39 --threshold <t> --shares <n> [--groups <g> --group-threshold <gt>] \
+         [--iteration-exponent <e>] [other options] <number_of_words>",
+        program
+    )?;
+    writeln!(out, "       {} recover [--passphrase <passphrase> | --prompt-passphrase] [--show-xprv] [share...]", program)?;
+    writeln!(out, "  <number_of_words>: 12, 15, 18, 21 or 24")?;
+    writeln!(out, "  validate: check a mnemonic from the argument or stdin, exiting non-zero with the reason if it is invalid;")?;
+    writeln!(out, "            the language is detected from the words unless --language or --wordlist is given")?;
+    writeln!(out, "  --language: {} (default: english)", names.join(", "))?;
+    writeln!(out, "  --wordlist: use this 2048-word list instead of the built-in list for the language; a list")?;
+    writeln!(out, "              that is not the official one gives mnemonics other wallets will not restore")?;
+    writeln!(out, "  --show-seed: also print the BIP39 seed (hex); --show-xprv: also print the BIP32 master key")?;
+    writeln!(out, "  --passphrase: BIP39 passphrase for the seed (default: empty); --prompt-passphrase asks for it")?;
+    writeln!(out, "                without echoing it or leaving it in shell history")?;
+    writeln!(out, "  --to-entropy: print the entropy (hex) of a valid mnemonic from the argument or stdin")?;
+    writeln!(out, "  --entropy <hex>: make the mnemonic from exactly these 16, 20, 24, 28 or 32 bytes instead of OsRng;")?;
+    writeln!(out, "                   <number_of_words> may be left out, and must match the length if given. - reads stdin")?;
+    writeln!(out, "  --dice <rolls>: mix dice rolls (digits 1-6) into the entropy, 1-4 giving two bits and 5-6 one;")?;
+    writeln!(out, "                  at least 77 rolls for 12 words and 154 for 24 are typical. - reads them from stdin")?;
+    writeln!(out, "  --coins <flips>: mix coin flips (H or T, one bit each) into the entropy; - reads them from stdin")?;
+    writeln!(out, "  --entropy-only: use only the dice or coins, without OsRng, so the rolls alone decide the mnemonic")?;
+    writeln!(out, "  --slip39: print SLIP-39 Shamir shares of a master secret as strong as a <number_of_words> mnemonic,")?;
+    writeln!(out, "            encrypted with the passphrase; --groups groups of --shares shares each, any --threshold")?;
+    writeln!(out, "            of which restore a group, and any --group-threshold groups (default: 1) the secret")?;
+    writeln!(out, "  --iteration-exponent: PBKDF2 work of the passphrase encryption, 10000 << e rounds (default: 1)")?;
+    writeln!(out, "  recover: combine SLIP-39 shares, one per argument or one per line of stdin, and print the master secret")?;
+    writeln!(out, "  --network: network of the master key, xprv or tprv (default: ${} or mainnet)", NETWORK_ENV)?;
+    writeln!(out, "  --json: print {{\"mnemonic\", \"entropy\"}} and any requested \"seed\" and \"xprv\" as JSON")
+}
+
+// Why a run failed. Every failure exits with status 1 and its message on stderr; usage errors
+// are followed by the usage
+#[derive(Debug, PartialEq)]
+enum CliError {
+    Usage(String),
+    Failed(String),
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CliError::Usage(message) | CliError::Failed(message) => write!(f, "{}", message),
+        }
     }
+}
 
-    // Read the wordlist from the specified file path
-    let wordlist = read_wordlist(&args[1])?;
+impl From<io::Error> for CliError {
+    fn from(e: io::Error) -> Self {
+        CliError::Failed(e.to_string())
+    }
+}
+
+impl From<fmt::Error> for CliError {
+    fn from(e: fmt::Error) -> Self {
+        CliError::Failed(e.to_string())
+    }
+}
+
+// What to run. The phrase and share modes hold the words given after them, if any
+#[derive(Debug, PartialEq)]
+enum Mode<'a> {
+    Generate { words: Option<usize> },
+    Validate(Vec<&'a str>),
+    ToEntropy(Vec<&'a str>),
+    Recover(Vec<&'a str>),
+    Help,
+}
+
+#[derive(Debug, PartialEq)]
+struct Slip39Params {
+    groups: u8,
+    group_threshold: u8,
+    threshold: u8,
+    count: u8,
+    iteration_exponent: u8,
+}
+
+// The command line, checked for conflicts. Values that may be - are read from stdin when the
+// command runs, not here
+struct Options<'a> {
+    program: &'a str,
+    mode: Mode<'a>,
+    wordlist_path: Option<&'a str>,
+    language: Option<Language>,
+    passphrase: Option<Zeroizing<String>>,
+    prompt: bool,
+    show_seed: bool,
+    show_xprv: bool,
+    network_flag: Option<&'a str>,
+    json: bool,
+    rolls: Option<(RollKind, &'a str)>,
+    entropy_only: bool,
+    entropy_hex: Option<&'a str>,
+    slip39: Option<Slip39Params>,
+}
 
-    // Parse the number of words
-    let words: usize = match args[2].parse() {
-        Ok(n) => n,
-        Err(_) => {
-            println!("Invalid number of words: {}", args[2]);
-            return Ok(());
+impl Options<'_> {
+    // Function to get the passphrase, asking for it when --prompt-passphrase is given
+    fn passphrase(&self) -> io::Result<Zeroizing<String>> {
+        if self.prompt {
+            return prompt_passphrase(|prompt| rpassword::prompt_password(prompt));
         }
+        Ok(self.passphrase.clone().unwrap_or_default())
+    }
+}
+
+fn parse_args(args: &[String]) -> Result<Options<'_>, CliError> {
+    let usage = |message: String| Err(CliError::Usage(message));
+    let mut wordlist_path: Option<&str> = None;
+    let mut language: Option<Language> = None;
+    let mut passphrase: Option<Zeroizing<String>> = None;
+    let mut prompt = false;
+    let mut show_seed = false;
+    let mut show_xprv = false;
+    let mut network_flag: Option<&str> = None;
+    let mut json = false;
+    let mut rolls: Option<(RollKind, &str)> = None;
+    let mut entropy_only = false;
+    let mut entropy_hex: Option<&str> = None;
+    let mut to_entropy = false;
+    let mut slip39 = false;
+    let mut groups: u8 = 1;
+    let mut group_threshold: u8 = 1;
+    let mut member_threshold: Option<u8> = None;
+    let mut member_count: Option<u8> = None;
+    let mut iteration_exponent: u8 = 1;
+    let mut help = false;
+    let mut positional: Vec<&str> = Vec::new();
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--wordlist" | "--language" | "--passphrase" | "--network" | "--entropy" | "--dice" | "--coins" => {
+                let Some(value) = rest.next() else {
+                    return usage(format!("{} requires a value", arg));
+                };
+                match arg.as_str() {
+                    "--wordlist" => wordlist_path = Some(value),
+                    "--language" => match value.parse::<Language>() {
+                        Ok(found) => language = Some(found),
+                        Err(_) => return usage(format!("Unknown language: {}", value)),
+                    },
+                    "--passphrase" => passphrase = Some(Zeroizing::new(value.clone())),
+                    "--network" => network_flag = Some(value),
+                    "--entropy" => entropy_hex = Some(value),
+                    _ => {
+                        if rolls.is_some() {
+                            return usage("Give either --dice or --coins, once".to_string());
+                        }
+                        let kind = if arg == "--dice" { RollKind::Dice } else { RollKind::Coins };
+                        rolls = Some((kind, value));
+                    }
+                }
+            }
+            "--groups" | "--group-threshold" | "--threshold" | "--shares" | "--iteration-exponent" => {
+                let Some(Ok(value)) = rest.next().map(|value| value.parse::<u8>()) else {
+                    return usage(format!("{} requires a number", arg));
+                };
+                match arg.as_str() {
+                    "--groups" => groups = value,
+                    "--group-threshold" => group_threshold = value,
+                    "--threshold" => member_threshold = Some(value),
+                    "--shares" => member_count = Some(value),
+                    _ => iteration_exponent = value,
+                }
+            }
+            "--entropy-only" => entropy_only = true,
+            "--to-entropy" => to_entropy = true,
+            "--slip39" => slip39 = true,
+            "--prompt-passphrase" => prompt = true,
+            "--show-seed" => show_seed = true,
+            "--show-xprv" => show_xprv = true,
+            "--json" => json = true,
+            "--help" | "-h" => help = true,
+            _ => positional.push(arg),
+        }
+    }
+
+    // The mode, and for generation the number of words. The older `<wordlist_path>
+    // <number_of_words>` form still works
+    let mode = match positional.as_slice() {
+        _ if help => Mode::Help,
+        ["validate", ..] if to_entropy => return usage("Give either validate or --to-entropy, not both".to_string()),
+        ["validate", phrase @ ..] => Mode::Validate(phrase.to_vec()),
+        ["recover", shares @ ..] => Mode::Recover(shares.to_vec()),
+        phrase if to_entropy => Mode::ToEntropy(phrase.to_vec()),
+        [] if entropy_hex.is_some() => Mode::Generate { words: None },
+        [words] | [_, words] => {
+            if let [path, _] = positional.as_slice() {
+                if wordlist_path.is_some() {
+                    return usage("Give the wordlist either with --wordlist or before the number of words".to_string());
+                }
+                wordlist_path = Some(path);
+            }
+            let Ok(words) = words.parse::<usize>() else {
+                return usage(format!("Invalid number of words: {}", words));
+            };
+            if ![12, 15, 18, 21, 24].contains(&words) {
+                return usage("Number of words must be 12, 15, 18, 21, or 24".to_string());
+            }
+            Mode::Generate { words: Some(words) }
+        }
+        [] => return usage("Give the number of words".to_string()),
+        _ => return usage("Too many arguments".to_string()),
+    };
+    let generating = matches!(mode, Mode::Generate { .. });
+    let recovering = matches!(mode, Mode::Recover(_));
+
+    // The passphrase only changes the seed, so it must be for something that is printed, unless
+    // it encrypts SLIP-39 shares
+    if (passphrase.is_some() || prompt) && !(show_seed || show_xprv || slip39 || recovering) {
+        return Err(CliError::Failed("A passphrase only changes the seed; add --show-seed or --show-xprv".to_string()));
+    }
+    let slip39 = match (slip39, member_threshold, member_count) {
+        (true, _, _) if !generating => {
+            return usage("--slip39 makes shares; use recover to combine them".to_string());
+        }
+        (true, Some(threshold), Some(count)) => {
+            Some(Slip39Params { groups, group_threshold, threshold, count, iteration_exponent })
+        }
+        (true, _, _) => return usage("--slip39 needs --threshold and --shares".to_string()),
+        (false, None, None) => None,
+        (false, _, _) => return usage("--threshold and --shares only apply with --slip39".to_string()),
     };
+    if entropy_only && rolls.is_none() {
+        return usage("--entropy-only needs --dice or --coins to take the entropy from".to_string());
+    }
+    if entropy_hex.is_some() && rolls.is_some() {
+        return usage("Give either --entropy or --dice/--coins, not both".to_string());
+    }
+    if passphrase.is_some() && prompt {
+        return usage("Give either --passphrase or --prompt-passphrase, not both".to_string());
+    }
+
+    Ok(Options {
+        program: args.first().map_or("generate_mnemonic", String::as_str),
+        mode,
+        wordlist_path,
+        language,
+        passphrase,
+        prompt,
+        show_seed,
+        show_xprv,
+        network_flag,
+        json,
+        rolls,
+        entropy_only,
+        entropy_hex,
+        slip39,
+    })
+}
 
-    // Validate the number of words
-    if ![12, 15, 18, 21, 24].contains(&words) {
-        println!("Number of words must be 12, 15, 18, 21, or 24");
-        return Ok(());
+// Function to load the wordlist: the built-in one for the language unless another is given,
+// which is checked against the official one
+fn load_wordlist(path: Option<&str>, language: Language) -> Result<Wordlist, CliError> {
+    let Some(path) = path else {
+        return Ok(Wordlist::BuiltIn(language));
+    };
+    let wordlist = Wordlist::File(read_wordlist(path)?);
+    let digest = mnemonic::wordlist_digest(&wordlist.words());
+    if digest != language.sha256() {
+        eprintln!("WARNING: {} is not the official BIP39 {} wordlist", path, language);
+        eprintln!("WARNING: its SHA-256 is {}, not {}", digest, language.sha256());
+        eprintln!("WARNING: other wallets will not restore mnemonics made from it; drop --wordlist to use the built-in list");
     }
+    Ok(wordlist)
+}
+
+// Function to gather the entropy for a mnemonic of `words` words, or of the supplied entropy's
+// length: the supplied bytes, else OsRng, with any rolls mixed in or, with `entropy_only`, alone
+fn gather_entropy(
+    words: Option<usize>,
+    supplied: Option<Zeroizing<Vec<u8>>>,
+    rolls: Option<(RollKind, &str)>,
+    entropy_only: bool,
+) -> Result<Zeroizing<Vec<u8>>, String> {
+    // Supplied entropy sets the number of words when none is given
+    let words = match (words, &supplied) {
+        (Some(words), _) => words,
+        (None, Some(entropy)) => entropy.len() * 3 / 4,
+        (None, None) => return Err("Give the number of words".to_string()),
+    };
 
     // Calculate entropy size in bits and bytes
     let ent_bits = (words / 3) * 32;
     let ent_bytes = ent_bits / 8;
 
-    // Generate random entropy
-    let mut entropy = vec![0u8; ent_bytes];
-    OsRng.fill_bytes(&mut entropy);
+    let mut entropy = Zeroizing::new(vec![0u8; ent_bytes]);
+    if let Some(supplied) = supplied {
+        if supplied.len() != ent_bytes {
+            return Err(format!("--entropy is {} bytes, but {} words take {}", supplied.len(), words, ent_bytes));
+        }
+        entropy = supplied;
+    } else if !entropy_only {
+        OsRng.try_fill_bytes(&mut entropy).map_err(|e| e.to_string())?;
+    }
+    if let Some((kind, rolls)) = rolls {
+        let user = roll_entropy(rolls, kind, ent_bits)?;
+        entropy = if entropy_only { user } else { mix_entropy(&user, &entropy) };
+    }
+    Ok(entropy)
+}
+
+// Function to turn entropy into a phrase, reading the words back from the same list before it
+// is shown, so a list that cannot give the entropy back fails rather than printing
+fn make_mnemonic(entropy: &[u8], wordlist: &[&str], separator: &str) -> Result<Zeroizing<String>, MnemonicError> {
+    let phrase = Zeroizing::new(mnemonic::entropy_to_words(entropy, wordlist)?.join(separator));
+    let recovered = mnemonic::words_to_entropy(&phrase, wordlist)?;
+    if recovered.as_slice() != entropy {
+        return Err(MnemonicError::Checksum);
+    }
+    Ok(phrase)
+}
+
+// Function to read a phrase or shares from stdin when none were given as arguments
+fn read_stdin() -> io::Result<Zeroizing<String>> {
+    let mut buffer = Zeroizing::new(String::new());
+    io::stdin().read_to_string(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn print_json(output: &impl Serialize) -> Result<(), CliError> {
+    let text = Zeroizing::new(serde_json::to_string_pretty(output).map_err(io::Error::other)?);
+    println!("{}", *text);
+    Ok(())
+}
+
+// Function to combine SLIP-39 shares and print the master secret
+fn run_recover(options: &Options, given: &[&str], network: BtcNetwork) -> Result<(), CliError> {
+    // The shares come from the arguments, else one per line of stdin
+    let input = if given.is_empty() { read_stdin()? } else { Zeroizing::new(given.join("\n")) };
+    let shares: Vec<&str> = input.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).collect();
+    let passphrase = options.passphrase()?;
+    let master_secret = slip39::combine_shares(&shares, &passphrase)
+        .map_err(|e| CliError::Failed(format!("Cannot recover the secret: {}", e)))?;
+    println!("{}", *Zeroizing::new(master_secret.to_lower_hex_string()));
+    if options.show_xprv {
+        println!("Master key: {}", *master_xprv(&master_secret, network)?);
+    }
+    Ok(())
+}
+
+// Function to validate a phrase, or print its entropy, from the arguments or stdin
+fn run_phrase(options: &Options, given: &[&str]) -> Result<(), CliError> {
+    let phrase = if given.is_empty() { read_stdin()? } else { Zeroizing::new(given.join(" ")) };
+    let language = match options.language {
+        Some(language) => language,
+        None if options.wordlist_path.is_none() => Language::detect(&phrase),
+        None => Language::English,
+    };
+    let wordlist = load_wordlist(options.wordlist_path, language)?;
+    let entropy = mnemonic::words_to_entropy(&phrase, &wordlist.words())
+        .map_err(|e| CliError::Failed(format!("Invalid mnemonic: {}", e)))?;
+    if let Mode::ToEntropy(_) = options.mode {
+        println!("{}", *Zeroizing::new(entropy.to_lower_hex_string()));
+    } else {
+        println!("Valid {}-word {} mnemonic", phrase.split_whitespace().count(), language);
+    }
+    Ok(())
+}
+
+// Function to print SLIP-39 shares of the entropy, as the master secret, in place of the
+// mnemonic. The master secret is itself the BIP32 seed
+fn run_slip39(options: &Options, params: &Slip39Params, entropy: &[u8], network: BtcNetwork) -> Result<(), CliError> {
+    let passphrase = options.passphrase()?;
+    let group_specs = vec![(params.threshold, params.count); params.groups as usize];
+    let mut rng = OsRng.unwrap_err();
+    let shares =
+        slip39::generate_shares(entropy, &passphrase, params.group_threshold, &group_specs, params.iteration_exponent, &mut rng)
+            .map_err(|e| CliError::Failed(format!("Cannot make SLIP-39 shares: {}", e)))?;
+    let seed_hex = options.show_seed.then(|| Zeroizing::new(entropy.to_lower_hex_string()));
+    let xprv = if options.show_xprv { Some(master_xprv(entropy, network)?) } else { None };
+
+    if options.json {
+        return print_json(&Slip39Output {
+            groups: shares.iter().map(|group| group.iter().map(|share| share.as_str()).collect()).collect(),
+            seed: seed_hex.as_deref().map(String::as_str),
+            xprv: xprv.as_deref().map(String::as_str),
+        });
+    }
+    for (index, group) in shares.iter().enumerate() {
+        if shares.len() > 1 {
+            if index > 0 {
+                println!();
+            }
+            println!("# Group {}: any {} of these {} shares", index + 1, params.threshold, params.count);
+        }
+        for share in group {
+            println!("{}", **share);
+        }
+    }
+    if let Some(seed_hex) = &seed_hex {
+        println!("Seed: {}", **seed_hex);
+    }
+    if let Some(xprv) = &xprv {
+        println!("Master key: {}", **xprv);
+    }
+    Ok(())
+}
+
+// Function to generate a mnemonic, or SLIP-39 shares, and print it with any seed and master key
+fn run_generate(options: &Options, words: Option<usize>, network: BtcNetwork) -> Result<(), CliError> {
+    let supplied = match options.entropy_hex {
+        Some(value) => Some(parse_entropy_hex(&read_secret_arg(value)?).map_err(CliError::Failed)?),
+        None => None,
+    };
+    let rolls = match options.rolls {
+        Some((kind, value)) => Some((kind, read_secret_arg(value)?)),
+        None => None,
+    };
+    let rolls_ref = rolls.as_ref().map(|(kind, value)| (*kind, value.as_str()));
+    let entropy = gather_entropy(words, supplied, rolls_ref, options.entropy_only).map_err(CliError::Failed)?;
+    if let Some(params) = &options.slip39 {
+        return run_slip39(options, params, &entropy, network);
+    }
+    print!("{}", *render_mnemonic(options, &entropy, network)?);
+    Ok(())
+}
+
+// Function to write out the mnemonic for the entropy, with any seed and master key, as text or
+// JSON
+fn render_mnemonic(options: &Options, entropy: &[u8], network: BtcNetwork) -> Result<Zeroizing<String>, CliError> {
+    let language = options.language.unwrap_or(Language::English);
+    let wordlist = load_wordlist(options.wordlist_path, language)?;
+    let mnemonic_phrase = make_mnemonic(entropy, &wordlist.words(), language.separator())
+        .map_err(|e| CliError::Failed(format!("Generated mnemonic failed verification: {}", e)))?;
 
-    // Compute SHA-256 hash of the entropy
-    let hash = Sha256::digest(&entropy);
+    // The seed and master key, only when asked for
+    let passphrase = options.passphrase()?;
+    let seed = (options.show_seed || options.show_xprv).then(|| mnemonic::mnemonic_to_seed(&mnemonic_phrase, &passphrase));
+    let seed_hex = seed.as_ref().filter(|_| options.show_seed).map(|seed| Zeroizing::new(seed.to_lower_hex_string()));
+    let xprv = match seed.as_ref().filter(|_| options.show_xprv) {
+        Some(seed) => Some(master_xprv(seed.as_ref(), network)?),
+        None => None,
+    };
 
-    // Calculate checksum size in bits
-    let cs_bits = ent_bits / 32;
+    let mut out = Zeroizing::new(String::new());
+    if options.json {
+        let entropy_hex = Zeroizing::new(entropy.to_lower_hex_string());
+        let output = MnemonicOutput {
+            mnemonic: &mnemonic_phrase,
+            entropy: &entropy_hex,
+            seed: seed_hex.as_deref().map(String::as_str),
+            xprv: xprv.as_deref().map(String::as_str),
+        };
+        out = Zeroizing::new(serde_json::to_string_pretty(&output).map_err(io::Error::other)?);
+        out.push('\n');
+        return Ok(out);
+    }
+    writeln!(out, "{}", *mnemonic_phrase)?;
+    if let Some(seed_hex) = &seed_hex {
+        writeln!(out, "Seed: {}", **seed_hex)?;
+    }
+    if let Some(xprv) = &xprv {
+        writeln!(out, "Master key: {}", **xprv)?;
+    }
+    Ok(out)
+}
 
-    // Convert entropy and hash to bit vectors
-    let entropy_bits = BitVec::<u8, Msb0>::from_slice(&entropy);
-    let hash_bits = BitVec::<u8, Msb0>::from_slice(&hash);
-    let checksum_bits = &hash_bits[0..cs_bits];
+fn run(options: Options) -> Result<(), CliError> {
+    let network = resolve_network(options.network_flag, env::var(NETWORK_ENV).ok()).map_err(CliError::Failed)?;
+    match &options.mode {
+        Mode::Help => Ok(print_usage(options.program, &mut io::stdout())?),
+        Mode::Recover(shares) => run_recover(&options, shares, network),
+        Mode::Validate(phrase) | Mode::ToEntropy(phrase) => run_phrase(&options, phrase),
+        Mode::Generate { words } => run_generate(&options, *words, network),
+    }
+}
 
-    // Combine entropy and checksum bits
-    let mut total_bits = entropy_bits;
-    total_bits.extend_from_bitslice(checksum_bits);
+fn main() {
+    // Collect command-line arguments
+    let args: Vec<String> = env::args().collect();
+    if let Err(e) = parse_args(&args).and_then(run) {
+        eprintln!("{}", e);
+        if let CliError::Usage(_) = e {
+            let _ = print_usage(args.first().map_or("generate_mnemonic", String::as_str), &mut io::stderr());
+        }
+        std::process::exit(1);
+    }
+}
 
-    // Generate the mnemonic by splitting into 11-bit chunks
-    let mut mnemonic = Vec::new();
-    for i in 0..words {
-        let start = i * 11;
-        let end = start + 11;
-        let chunk = &total_bits[start..end];
-        let index = chunk.load_be::<u16>() as usize;
-        mnemonic.push(&wordlist[index]);
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn english() -> Vec<&'static str> {
+        Language::English.wordlist().to_vec()
     }
 
-    // Join the mnemonic words into a phrase and print it
-    let mnemonic_phrase: String = mnemonic.iter().map(|s| s.as_str()).collect::<Vec<&str>>().join(" ");
-    println!("{}", mnemonic_phrase);
+    fn entropy(entropy_hex: &str) -> Vec<u8> {
+        Vec::from_hex(entropy_hex).unwrap()
+    }
 
-    // Verification: Create a word-to-index mapping
-    let word_to_index: HashMap<&str, usize> = wordlist
-        .iter()
-        .enumerate()
-        .map(|(i, word)| (word.as_str(), i))
-        .collect();
+    #[test]
+    fn test_parse_entropy_hex() {
+        for entropy_hex in ["7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f", "6610b25967cdcca9d59875f5cb50b0ea75433311869e930b"] {
+            assert_eq!(*parse_entropy_hex(entropy_hex).unwrap(), entropy(entropy_hex));
+        }
 
-    // Split the mnemonic phrase back into words
-    let mnemonic_words: Vec<&str> = mnemonic_phrase.split_whitespace().collect();
+        // Only whole BIP39 entropy lengths, and errors do not echo the hex
+        assert_eq!(parse_entropy_hex(" 00000000000000000000000000000000\n").unwrap().len(), 16);
+        assert!(parse_entropy_hex(&"00".repeat(17)).unwrap_err().contains("17 bytes"));
+        assert!(parse_entropy_hex(&"00".repeat(33)).is_err());
+        assert_eq!(parse_entropy_hex("7f7f7f7f7f7f7f7f7f7f7f7f7f7f7fzz").unwrap_err(), "--entropy is not valid hex");
+    }
 
-    // Recover the bits from the mnemonic words
-    let mut recovered_bits = BitVec::<u8, Msb0>::new();
-    for &word in &mnemonic_words {
-        let index = *word_to_index.get(word).expect("Word not found in wordlist");
-        let index_u16 = index as u16;
-        for bit_pos in (0..11).rev() {
-            let bit = (index_u16 >> bit_pos) & 1;
-            recovered_bits.push(bit != 0);
+    #[test]
+    fn test_seed_and_xprv() {
+        // Master keys from the BIP39 test vectors, all with the passphrase "TREZOR"
+        let vectors = [
+            (
+                "00000000000000000000000000000000",
+                "xprv9s21ZrQH143K3h3fDYiay8mocZ3afhfULfb5GX8kCBdno77K4HiA15Tg23wpbeF1pLfs1c5SPmYHrEpTuuRhxMwvKDwqdKiGJS9XFKzUsAF",
+            ),
+            (
+                "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+                "xprv9s21ZrQH143K2gA81bYFHqU68xz1cX2APaSq5tt6MFSLeXnCKV1RVUJt9FWNTbrrryem4ZckN8k4Ls1H6nwdvDTvnV7zEXs2HgPezuVccsq",
+            ),
+            (
+                "80808080808080808080808080808080",
+                "xprv9s21ZrQH143K2shfP28KM3nr5Ap1SXjz8gc2rAqqMEynmjt6o1qboCDpxckqXavCwdnYds6yBHZGKHv7ef2eTXy461PXUjBFQg6PrwY4Gzq",
+            ),
+            (
+                "ffffffffffffffffffffffffffffffff",
+                "xprv9s21ZrQH143K2V4oox4M8Zmhi2Fjx5XK4Lf7GKRvPSgydU3mjZuKGCTg7UPiBUD7ydVPvSLtg9hjp7MQTYsW67rZHAXeccqYqrsx8LcXnyd",
+            ),
+            (
+                "68a79eaca2324873eacc50cb9c6eca8cc68ea5d936f98787c60c7ebc74e6ce7c",
+                "xprv9s21ZrQH143K2XTAhys3pMNcGn261Fi5Ta2Pw8PwaVPhg3D8DWkzWQwjTJfskj8ofb81i9NP2cUNKxwjueJHHMQAnxtivTA75uUFqPFeWzk",
+            ),
+        ];
+        for (entropy_hex, xprv) in vectors {
+            let seed = mnemonic::from_entropy(&entropy(entropy_hex), Language::English).unwrap().to_seed("TREZOR");
+            assert_eq!(*master_xprv(seed.as_ref(), BtcNetwork::Bitcoin).unwrap(), xprv);
         }
+
+        // The Japanese vector, on regtest
+        let mnemonic = mnemonic::from_entropy(&[0; 16], Language::Japanese).unwrap();
+        let seed = mnemonic.to_seed("㍍ガバヴァぱばぐゞちぢ十人十色");
+        assert_eq!(
+            *master_xprv(seed.as_ref(), BtcNetwork::Regtest).unwrap(),
+            "tprv8ZgxMBicQKsPctNFqHMtKziHraZuZgB1h2xHRtk8Sjq35oUHH1nUhfj399Hr28gLcmcktLajbVh3dVeZXJn9tN9jyaVt1AaD3opfFT3wEEg"
+        );
     }
 
-    // Split recovered bits into entropy and checksum
-    let recovered_ent_bits = words * 11 - cs_bits;
-    let entropy_recovered = &recovered_bits[0..recovered_ent_bits];
-    let checksum_recovered = &recovered_bits[recovered_ent_bits..recovered_ent_bits + cs_bits];
+    #[test]
+    fn test_prompt_passphrase() {
+        let mut answers = vec!["TREZOR".to_string(), "TREZOR".to_string()].into_iter();
+        assert_eq!(*prompt_passphrase(|_| Ok(answers.next().unwrap())).unwrap(), "TREZOR");
+        let mut answers = vec!["TREZOR".to_string(), "TREZ0R".to_string()].into_iter();
+        assert!(prompt_passphrase(|_| Ok(answers.next().unwrap())).is_err());
+    }
 
-    // Convert recovered entropy bits back to bytes
-    let mut entropy_bytes = vec![0u8; ent_bytes];
-    for (i, bit) in entropy_recovered.iter().enumerate() {
-        if *bit {
-            let byte_index = i / 8;
-            let bit_index = 7 - (i % 8);
-            entropy_bytes[byte_index] |= 1 << bit_index;
+    #[test]
+    fn test_parse_args() {
+        fn args(line: &str) -> Vec<String> {
+            line.split_whitespace().map(str::to_string).collect()
+        }
+        fn parse(args: &[String]) -> Result<Mode<'_>, String> {
+            parse_args(args).map(|options| options.mode).map_err(|e| e.to_string())
         }
+        assert_eq!(parse(&args("gm 24")), Ok(Mode::Generate { words: Some(24) }));
+        assert_eq!(parse(&args("gm --entropy -")), Ok(Mode::Generate { words: None }));
+        assert_eq!(parse(&args("gm validate zoo zoo")), Ok(Mode::Validate(vec!["zoo", "zoo"])));
+        assert_eq!(parse(&args("gm --to-entropy")), Ok(Mode::ToEntropy(vec![])));
+        assert_eq!(parse(&args("gm --help 13")), Ok(Mode::Help));
+
+        // Every mistake is an error, so the process exits non-zero
+        assert_eq!(parse(&args("gm")), Err("Give the number of words".to_string()));
+        assert_eq!(parse(&args("gm 13")), Err("Number of words must be 12, 15, 18, 21, or 24".to_string()));
+        assert_eq!(parse(&args("gm twelve")), Err("Invalid number of words: twelve".to_string()));
+        assert_eq!(parse(&args("gm 12 --wordlist")), Err("--wordlist requires a value".to_string()));
+        assert_eq!(parse(&args("gm --language klingon 12")), Err("Unknown language: klingon".to_string()));
+        assert_eq!(parse(&args("gm --shares 5 12")), Err("--threshold and --shares only apply with --slip39".to_string()));
+        assert!(parse(&args("gm --passphrase x 12")).is_err());
+        assert!(parse(&args("gm --entropy-only 12")).is_err());
+        assert!(parse(&args("gm validate --to-entropy")).is_err());
+        assert!(parse(&args("gm list.txt 12 24")).is_err());
+
+        // Only usage mistakes are followed by the usage
+        assert!(matches!(parse_args(&args("gm 13")).err(), Some(CliError::Usage(_))));
+        assert!(matches!(parse_args(&args("gm --passphrase x 12")).err(), Some(CliError::Failed(_))));
     }
 
-    // Recompute the hash and checksum from recovered entropy
-    let hash_recovered = Sha256::digest(&entropy_bytes);
-    let hash_bits_recovered = BitVec::<u8, Msb0>::from_slice(&hash_recovered);
-    let checksum_computed = &hash_bits_recovered[0..cs_bits];
+    #[test]
+    fn test_make_mnemonic() {
+        let wordlist = english();
+        let zeros = entropy("00000000000000000000000000000000");
+        let phrase = make_mnemonic(&zeros, &wordlist, " ").unwrap();
+        assert_eq!(*phrase, "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about");
+        assert_eq!(gather_entropy(None, Some(Zeroizing::new(zeros.clone())), None, false).unwrap().len(), 16);
+        assert!(gather_entropy(Some(24), Some(Zeroizing::new(zeros.clone())), None, false).is_err());
+        assert_eq!(gather_entropy(Some(18), None, None, false).unwrap().len(), 24);
 
-    // Verify the checksum
-    if checksum_computed != checksum_recovered {
-        println!("Checksum is invalid");
+        // A list in the wrong order gives a phrase the official list rejects
+        let mut reordered = wordlist.clone();
+        reordered.swap(0, 3);
+        let phrase = make_mnemonic(&zeros, &reordered, " ").unwrap();
+        assert_eq!(mnemonic::words_to_entropy(&phrase, &wordlist).map(|_| ()), Err(MnemonicError::Checksum));
+
+        // A list whose words cannot be read back to the same indexes fails generation outright
+        let mut duplicated = wordlist.clone();
+        duplicated[1] = duplicated[0];
+        assert_eq!(make_mnemonic(&zeros, &duplicated, " ").map(|_| ()), Err(MnemonicError::Checksum));
     }
 
-    Ok(())
-}
\ No newline at end of file
+    #[test]
+    fn test_render_mnemonic() {
+        // The output for fixed entropy, as generate_mnemonic printed it before the BIP39 code moved
+        // into the library
+        let render = |line: &str| {
+            let args: Vec<String> = line.split_whitespace().map(str::to_string).collect();
+            let options = parse_args(&args).unwrap();
+            let entropy = parse_entropy_hex(options.entropy_hex.unwrap()).unwrap();
+            let network = resolve_network(options.network_flag, None).unwrap();
+            render_mnemonic(&options, &entropy, network).map(|out| out.to_string()).map_err(|e| e.to_string())
+        };
+        // The words are written as the built-in lists store them, NFKD
+        let fixed = "gm --entropy 9e885d952ad362caeb4efe34a8e91bd2 --passphrase TREZOR --show-seed --show-xprv";
+        for (language, expected) in [
+            (
+                "english",
+                "ozone drill grab fiber curtain grace pudding thank cruise elder eight picnic\n\
+                 Seed: 274ddc525802f7c828d8ef7ddbcdc5304e87ac3535913611fbbfa986d0c9e5476c91689f9c8a54fd55bd38606aa6a8595ad213d4c9c9f9aca3fb217069a41028\n\
+                 Master key: xprv9s21ZrQH143K2oZ9stBYpoaZ2ktHj7jLz7iMqpgg1En8kKFTXJHsjxry1JbKH19YrDTicVwKPehFKTbmaxgVEc5TpHdS1aYhB2s9aFJBeJH\n",
+            ),
+            (
+                "japanese",
+                "ておくれ　げざん　しねま　こりる　きぼう　しねん　ななおし　ほんやく　きない　けむり　けまり　てんない\n\
+                 Seed: b80f83f27ec3a6cbe804be0661e9bcc30583484dbbd37f689d4952bdf4ad29d9b9f5774fc4c87b733169416418b81f272a3eab37feb22f5c8f6deea6bb08f8c1\n\
+                 Master key: xprv9s21ZrQH143K3VLugmPmzLDwfFeo8cgoZ2ajCQwWhGKfXut8C1XhdSNuAnXjz5W2bbbNPmr87yMHAaPMaUhE6MNnfGc21WRc5Lj94Tbbnnh\n",
+            ),
+            (
+                "spanish",
+                "obra diadema gorila farmacia colgar gorra pausa talar cocina duda dragón optar\n\
+                 Seed: fcf6ebfc7d9eebab56ca868cbd2d5d05a6f2142ba903c52855dad4ab8c0c2cf6b4e047a2dd97cf382ae717dc18d155a45fc798e6f0a0b89971a4224e2a285701\n\
+                 Master key: xprv9s21ZrQH143K3pxsjjkbjzEu1f9qaeGb9wTLZ39rUF4CoP125zApXELXDspSNV1Cn8vjvXKNQymm8iwSRpJr9AZyKvHTVVbg1Tq6kY48iTV\n",
+            ),
+            (
+                "chinese_traditional",
+                "蒙 台 脫 紀 構 硫 漿 黴 感 僅 魚 湯\n\
+                 Seed: 27ca577f0318b6c6067acce7aefacd12bc9fbbc8e365fdc16bfc0ffd76379b0768dc56877f19eee4c1222dfb5a94a5516c5707e6a6ad070af9a0fe7f7799ac5e\n\
+                 Master key: xprv9s21ZrQH143K3FtWQPZHP7Gpf5qgbvXqPNo5iCSfkGhWnATeqM5FuQ3YTx4sSciJx1MjVnbM3XQ16N83x5gNwcsVG7PTf1cRDhvyGZ45EY5\n",
+            ),
+            (
+                "czech",
+                "pokoj jogurt malovat kroupa holub malvice rachot uznat hnout kasa karamel potupa\n\
+                 Seed: f3922b8086d559436ba2d04bc2aae4174e6504d7d4d451f7282d0b41a1b8cc958b45a896985e0b9316ad09c62f7d62dac85bc3d3e2e2423bcad3336412fd33f8\n\
+                 Master key: xprv9s21ZrQH143K3gt3UuAosyvKpAmRpGfWuM19LVhrpWshTnv8o9Z2SPx7PxfgPQSZtm7U34cWo2NUrUtWCtXLhKDFZxg9pjM9KRde9dLwsZC\n",
+            ),
+        ] {
+            assert_eq!(render(&format!("{} --language {}", fixed, language)).unwrap(), expected, "{}", language);
+        }
+        assert_eq!(
+            render("gm --entropy 68a79eaca2324873eacc50cb9c6eca8cc68ea5d936f98787c60c7ebc74e6ce7c --network testnet --passphrase x --show-xprv --json").unwrap(),
+            r#"{
+  "mnemonic": "hamster diagram private dutch cause delay private meat slide toddler razor book happy fancy gospel tennis maple dilemma loan word shrug inflict delay length",
+  "entropy": "68a79eaca2324873eacc50cb9c6eca8cc68ea5d936f98787c60c7ebc74e6ce7c",
+  "xprv": "tprv8ZgxMBicQKsPerAPysntfmsc3uYAgJPqNuD3wSTU8q9XC8a19jYTktEbvvHy6bNfyX9rszXL6Wrm5qAMyWouZWJxobi7kEEG4LRAHwAAxTm"
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_roll_entropy() {
+        let wordlist = english();
+        let mnemonic = |entropy: &[u8]| mnemonic::entropy_to_words(entropy, &wordlist).unwrap().join(" ");
+
+        // HT repeated is 1010..., 0xaa bytes
+        let flips = "HT".repeat(64);
+        assert_eq!(
+            mnemonic(&roll_entropy(&flips, RollKind::Coins, 128).unwrap()),
+            "primary fetch primary fetch primary fetch primary fetch primary fetch primary fever"
+        );
+        // 1, 2, 3, 4 give 00 01 10 11, 0x1b bytes
+        let rolls = "1234".repeat(40);
+        assert_eq!(
+            mnemonic(&roll_entropy(&rolls, RollKind::Dice, 256).unwrap()),
+            "brand sugar short curtain random mirror hockey gloom brand sugar short curtain random mirror hockey gloom \
+             brand sugar short curtain random mirror hockey guard"
+        );
+        // 5 and 6 give one bit each; spaces and extra rolls are ignored
+        let rolls = "5612 ".repeat(22);
+        assert_eq!(
+            mnemonic(&roll_entropy(&rolls, RollKind::Dice, 128).unwrap()),
+            "eagle penalty eyebrow chunk begin mechanic eagle penalty eyebrow chunk begin member"
+        );
+
+        let error = roll_entropy("123456", RollKind::Dice, 128).unwrap_err();
+        assert_eq!(error, "The rolls give 10 of the 128 bits needed; roll at least 59 more dice, up to 118 if they come up 5 or 6");
+        let error = roll_entropy(&"H".repeat(100), RollKind::Coins, 128).unwrap_err();
+        assert_eq!(error, "The flips give 100 of the 128 bits needed; flip 28 more coins");
+        assert_eq!(roll_entropy("1270", RollKind::Dice, 128).unwrap_err(), "Roll 3 is not a digit from 1 to 6");
+        assert_eq!(roll_entropy("hTx", RollKind::Coins, 128).unwrap_err(), "Flip 3 is not H or T");
+
+        // Mixing keeps the length and depends on both sides
+        let user = roll_entropy(&flips, RollKind::Coins, 128).unwrap();
+        let mixed = mix_entropy(&user, &[1; 16]);
+        assert_eq!(mixed.len(), 16);
+        assert_ne!(*mixed, *user);
+        assert_ne!(*mixed, *mix_entropy(&user, &[2; 16]));
+    }
+}
@@ -0,0 +1,669 @@
+// SLIP-39 Shamir backup: the master secret is encrypted with the passphrase, split into groups,
+// each group split into member shares, and every share written as words from a 1024-word list
+// with an RS1024 checksum. https://github.com/satoshilabs/slips/blob/master/slip-0039.md
+
+use std::collections::BTreeMap;
+use std::fmt;
+use bitcoin::hashes::{hmac, sha256, Hash, HashEngine};
+use pbkdf2::pbkdf2_hmac;
+use rand::CryptoRng;
+use sha2::Sha256;
+use zeroize::Zeroizing;
+
+const WORDLIST: &str = include_str!("../../../wordlists/slip39.txt");
+
+// The PBKDF2 work per Feistel round is 2500 << iteration_exponent
+const BASE_ITERATION_COUNT: u32 = 10000;
+const ROUND_COUNT: u8 = 4;
+const MAX_SHARE_COUNT: u8 = 16;
+const DIGEST_INDEX: u8 = 254;
+const SECRET_INDEX: u8 = 255;
+const DIGEST_LENGTH: usize = 4;
+const CHECKSUM_WORDS: usize = 3;
+const HEADER_WORDS: usize = 4;
+const MIN_SECRET_BYTES: usize = 16;
+
+// Shares of one secret as (x coordinate, value)
+type Points = Vec<(u8, Zeroizing<Vec<u8>>)>;
+
+// Generator of the RS1024 code over GF(1024), one entry per bit of the overflowing word
+const RS1024_GEN: [u32; 10] = [
+    0xE0E040, 0x1C1C080, 0x3838100, 0x7070200, 0xE0E0009, 0x1C0C2412, 0x38086C24, 0x3090FC48, 0x21B1F890, 0x3F3F120,
+];
+
+// Why shares could not be made or combined. Shares and groups are numbered from 1 in the order
+// given
+#[derive(Debug, PartialEq)]
+pub enum Slip39Error {
+    Parameters(String),
+    Passphrase,
+    NoShares,
+    WordCount { share: usize, words: usize },
+    UnknownWord { share: usize, index: usize },
+    Checksum { share: usize },
+    Padding { share: usize },
+    Identifier { share: usize, found: u16, expected: u16 },
+    Mismatch { share: usize },
+    MemberThreshold { share: usize, group: u8 },
+    DuplicateMember { share: usize, group: u8, member: u8 },
+    TooFewShares { group: u8, have: usize, need: u8 },
+    TooFewGroups { have: usize, need: u8, partial: Vec<(u8, usize, u8)> },
+    Digest,
+}
+
+impl fmt::Display for Slip39Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Slip39Error::Parameters(reason) => write!(f, "{}", reason),
+            Slip39Error::Passphrase => write!(f, "a SLIP-39 passphrase may only hold printable ASCII characters"),
+            Slip39Error::NoShares => write!(f, "no shares were given"),
+            Slip39Error::WordCount { share, words } => {
+                write!(f, "share {} has {} words; a SLIP-39 share has 20 or more", share, words)
+            }
+            Slip39Error::UnknownWord { share, index } => {
+                write!(f, "word {} of share {} is not in the SLIP-39 wordlist", index + 1, share)
+            }
+            Slip39Error::Checksum { share } => {
+                write!(f, "share {} fails its checksum; a word is wrong or the words are out of order", share)
+            }
+            Slip39Error::Padding { share } => write!(f, "share {} has an invalid length or padding", share),
+            Slip39Error::Identifier { share, found, expected } => write!(
+                f,
+                "share {} has identifier {} but share 1 has {}; they are from different backups",
+                share, found, expected
+            ),
+            Slip39Error::Mismatch { share } => write!(
+                f,
+                "share {} has a different iteration exponent, group threshold, group count or length than share 1",
+                share
+            ),
+            Slip39Error::MemberThreshold { share, group } => {
+                write!(f, "share {} has a different member threshold than the other shares of group {}", share, group)
+            }
+            Slip39Error::DuplicateMember { share, group, member } => {
+                write!(f, "share {} repeats member {} of group {} with a different value", share, member, group)
+            }
+            Slip39Error::TooFewShares { group, have, need } => {
+                write!(f, "group {} has {} of the {} shares it needs", group, have, need)
+            }
+            Slip39Error::TooFewGroups { have, need, partial } => {
+                write!(f, "{} of the {} groups needed are complete", have, need)?;
+                for (group, have, need) in partial {
+                    write!(f, "; group {} has {} of the {} shares it needs", group, have, need)?;
+                }
+                Ok(())
+            }
+            Slip39Error::Digest => {
+                write!(f, "the shares do not combine to the secret; the digest does not match, so a share is wrong")
+            }
+        }
+    }
+}
+
+// One decoded share. The value is a point on the polynomial of its group
+struct Share {
+    identifier: u16,
+    extendable: bool,
+    iteration_exponent: u8,
+    group_index: u8,
+    group_threshold: u8,
+    group_count: u8,
+    member_index: u8,
+    member_threshold: u8,
+    value: Zeroizing<Vec<u8>>,
+}
+
+impl Share {
+    // Function to check that two shares belong to the same split of the same secret
+    fn same_split(&self, other: &Share) -> bool {
+        self.extendable == other.extendable
+            && self.iteration_exponent == other.iteration_exponent
+            && self.group_threshold == other.group_threshold
+            && self.group_count == other.group_count
+            && self.value.len() == other.value.len()
+    }
+}
+
+// Function to build the GF(256) exponent and logarithm tables, with 3 as the generator and the
+// Rijndael polynomial x^8 + x^4 + x^3 + x + 1
+const fn gf256_tables() -> ([u8; 255], [u8; 256]) {
+    let mut exp = [0u8; 255];
+    let mut log = [0u8; 256];
+    let mut poly: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = poly as u8;
+        log[poly as usize] = i as u8;
+        poly ^= poly << 1;
+        if poly & 0x100 != 0 {
+            poly ^= 0x11b;
+        }
+        i += 1;
+    }
+    (exp, log)
+}
+
+const GF256: ([u8; 255], [u8; 256]) = gf256_tables();
+
+// Function to evaluate, at x, the polynomial of lowest degree through the given points.
+// Values are interpolated byte by byte; the x coordinates must be distinct
+fn interpolate(points: &[(u8, &[u8])], x: u8) -> Zeroizing<Vec<u8>> {
+    let (exp, log) = &GF256;
+    if let Some((_, value)) = points.iter().find(|(xi, _)| *xi == x) {
+        return Zeroizing::new(value.to_vec());
+    }
+    let log_product: u32 = points.iter().map(|(xi, _)| log[(xi ^ x) as usize] as u32).sum();
+    let mut result = Zeroizing::new(vec![0u8; points[0].1.len()]);
+    for (xi, value) in points {
+        let others: u32 = points.iter().filter(|(xj, _)| xj != xi).map(|(xj, _)| log[(xj ^ xi) as usize] as u32).sum();
+        let log_basis = (log_product + 255 * 255 - log[(xi ^ x) as usize] as u32 - others) % 255;
+        for (out, byte) in result.iter_mut().zip(value.iter()) {
+            if *byte != 0 {
+                *out ^= exp[((log[*byte as usize] as u32 + log_basis) % 255) as usize];
+            }
+        }
+    }
+    result
+}
+
+// Function to compute the 4-byte digest kept in the digest share, keyed by the rest of it
+fn secret_digest(random: &[u8], secret: &[u8]) -> [u8; DIGEST_LENGTH] {
+    let mut engine = hmac::HmacEngine::<sha256::Hash>::new(random);
+    engine.input(secret);
+    let mac = hmac::Hmac::<sha256::Hash>::from_engine(engine);
+    let mut digest = [0u8; DIGEST_LENGTH];
+    digest.copy_from_slice(&mac.as_byte_array()[..DIGEST_LENGTH]);
+    digest
+}
+
+// Function to split a secret into `count` shares, any `threshold` of which give it back. Past
+// a threshold of one, the polynomial runs through random shares, a digest share at 254 and the
+// secret at 255
+fn split_secret(
+    threshold: u8,
+    count: u8,
+    secret: &[u8],
+    rng: &mut impl CryptoRng,
+) -> Result<Points, Slip39Error> {
+    if threshold < 1 || threshold > count || count > MAX_SHARE_COUNT {
+        return Err(Slip39Error::Parameters(format!(
+            "a threshold of {} of {} shares is not possible; it must be between 1 and the share count, at most {}",
+            threshold, count, MAX_SHARE_COUNT
+        )));
+    }
+    if threshold == 1 {
+        return Ok((0..count).map(|index| (index, Zeroizing::new(secret.to_vec()))).collect());
+    }
+
+    let mut shares: Points = Vec::new();
+    for index in 0..threshold - 2 {
+        let mut value = Zeroizing::new(vec![0u8; secret.len()]);
+        rng.fill_bytes(&mut value);
+        shares.push((index, value));
+    }
+    let mut digest_share = Zeroizing::new(vec![0u8; secret.len()]);
+    rng.fill_bytes(&mut digest_share[DIGEST_LENGTH..]);
+    let digest = secret_digest(&digest_share[DIGEST_LENGTH..], secret);
+    digest_share[..DIGEST_LENGTH].copy_from_slice(&digest);
+
+    let mut base: Vec<(u8, &[u8])> = shares.iter().map(|(index, value)| (*index, value.as_slice())).collect();
+    base.push((DIGEST_INDEX, &digest_share));
+    base.push((SECRET_INDEX, secret));
+    let derived: Points = (threshold - 2..count).map(|index| (index, interpolate(&base, index))).collect();
+    shares.extend(derived);
+    Ok(shares)
+}
+
+// Function to recover a secret from `threshold` shares, checking it against the digest share
+fn recover_secret(threshold: u8, points: &[(u8, &[u8])]) -> Result<Zeroizing<Vec<u8>>, Slip39Error> {
+    if threshold == 1 {
+        return Ok(Zeroizing::new(points[0].1.to_vec()));
+    }
+    let secret = interpolate(points, SECRET_INDEX);
+    let digest_share = interpolate(points, DIGEST_INDEX);
+    if digest_share[..DIGEST_LENGTH] != secret_digest(&digest_share[DIGEST_LENGTH..], &secret) {
+        return Err(Slip39Error::Digest);
+    }
+    Ok(secret)
+}
+
+// Function to run the four-round Feistel network that encrypts the master secret, or with the
+// rounds reversed decrypts it. Non-extendable backups salt each round with the identifier
+fn feistel(
+    secret: &[u8],
+    passphrase: &[u8],
+    iteration_exponent: u8,
+    identifier: u16,
+    extendable: bool,
+    decrypt: bool,
+) -> Zeroizing<Vec<u8>> {
+    let half = secret.len() / 2;
+    let mut left = Zeroizing::new(secret[..half].to_vec());
+    let mut right = Zeroizing::new(secret[half..].to_vec());
+    let mut salt = Zeroizing::new(Vec::new());
+    if !extendable {
+        salt.extend_from_slice(b"shamir");
+        salt.extend_from_slice(&identifier.to_be_bytes());
+    }
+    let salt_prefix = salt.len();
+    let iterations = (BASE_ITERATION_COUNT << iteration_exponent) / ROUND_COUNT as u32;
+
+    for step in 0..ROUND_COUNT {
+        let round = if decrypt { ROUND_COUNT - 1 - step } else { step };
+        let mut password = Zeroizing::new(vec![round]);
+        password.extend_from_slice(passphrase);
+        salt.truncate(salt_prefix);
+        salt.extend_from_slice(&right);
+        let mut round_key = Zeroizing::new(vec![0u8; half]);
+        pbkdf2_hmac::<Sha256>(&password, &salt, iterations, &mut round_key);
+        let mixed: Vec<u8> = left.iter().zip(round_key.iter()).map(|(l, k)| l ^ k).collect();
+        left = std::mem::replace(&mut right, Zeroizing::new(mixed));
+    }
+
+    let mut output = Zeroizing::new(right.to_vec());
+    output.extend_from_slice(&left);
+    output
+}
+
+fn rs1024_polymod(values: &[u32]) -> u32 {
+    let mut checksum: u32 = 1;
+    for value in values {
+        let top = checksum >> 20;
+        checksum = ((checksum & 0xFFFFF) << 10) ^ value;
+        for (i, generator) in RS1024_GEN.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+fn customization(extendable: bool) -> Vec<u32> {
+    let string: &[u8] = if extendable { b"shamir_extendable" } else { b"shamir" };
+    string.iter().map(|&byte| byte as u32).collect()
+}
+
+// Function to compute the three checksum words for the share words before them
+fn rs1024_checksum(words: &[u32], extendable: bool) -> [u32; CHECKSUM_WORDS] {
+    let mut values = customization(extendable);
+    values.extend_from_slice(words);
+    values.extend_from_slice(&[0; CHECKSUM_WORDS]);
+    let polymod = rs1024_polymod(&values) ^ 1;
+    [(polymod >> 20) & 1023, (polymod >> 10) & 1023, polymod & 1023]
+}
+
+fn rs1024_verify(words: &[u32], extendable: bool) -> bool {
+    let mut values = customization(extendable);
+    values.extend_from_slice(words);
+    rs1024_polymod(&values) == 1
+}
+
+// Function to write a share as words: a 40-bit header, the value left-padded to a multiple of
+// 10 bits, then the checksum
+fn encode_share(share: &Share, wordlist: &[&str]) -> Zeroizing<String> {
+    let header: u64 = ((share.identifier as u64) << 25)
+        | ((share.extendable as u64) << 24)
+        | ((share.iteration_exponent as u64) << 20)
+        | ((share.group_index as u64) << 16)
+        | (((share.group_threshold - 1) as u64) << 12)
+        | (((share.group_count - 1) as u64) << 8)
+        | ((share.member_index as u64) << 4)
+        | (share.member_threshold - 1) as u64;
+    let mut words = Zeroizing::new(Vec::new());
+    words.extend((0..HEADER_WORDS).rev().map(|i| ((header >> (10 * i)) & 1023) as u32));
+
+    // Bits are taken ten at a time from the value, after zero bits that pad it
+    let value_words = (share.value.len() * 8).div_ceil(10);
+    let padding = value_words * 10 - share.value.len() * 8;
+    let bit = |position: usize| -> u32 {
+        if position < padding {
+            return 0;
+        }
+        let position = position - padding;
+        ((share.value[position / 8] >> (7 - position % 8)) & 1) as u32
+    };
+    for word in 0..value_words {
+        words.push((0..10).fold(0, |acc, i| (acc << 1) | bit(word * 10 + i)));
+    }
+    let checksum = rs1024_checksum(&words, share.extendable);
+    words.extend_from_slice(&checksum);
+    Zeroizing::new(words.iter().map(|&index| wordlist[index as usize]).collect::<Vec<_>>().join(" "))
+}
+
+// Function to read a share back from its words. `number` is its 1-based place among the
+// shares given, for errors
+fn decode_share(mnemonic: &str, wordlist: &[&str], number: usize) -> Result<Share, Slip39Error> {
+    let typed: Vec<String> = mnemonic.split_whitespace().map(str::to_lowercase).collect();
+    let min_words = HEADER_WORDS + (MIN_SECRET_BYTES * 8).div_ceil(10) + CHECKSUM_WORDS;
+    if typed.len() < min_words {
+        return Err(Slip39Error::WordCount { share: number, words: typed.len() });
+    }
+    let mut words = Zeroizing::new(Vec::with_capacity(typed.len()));
+    for (index, word) in typed.iter().enumerate() {
+        match wordlist.binary_search(&word.as_str()) {
+            Ok(position) => words.push(position as u32),
+            Err(_) => return Err(Slip39Error::UnknownWord { share: number, index }),
+        }
+    }
+
+    let header = words[..HEADER_WORDS].iter().fold(0u64, |acc, &word| (acc << 10) | word as u64);
+    let extendable = (header >> 24) & 1 == 1;
+    if !rs1024_verify(&words, extendable) {
+        return Err(Slip39Error::Checksum { share: number });
+    }
+
+    // The value is a whole, even number of bytes after at most 8 bits of zero padding
+    let value_words = &words[HEADER_WORDS..words.len() - CHECKSUM_WORDS];
+    let value_bits = value_words.len() * 10;
+    let value_bytes = value_bits / 16 * 2;
+    let padding = value_bits - value_bytes * 8;
+    if padding > 8 || value_bytes < MIN_SECRET_BYTES {
+        return Err(Slip39Error::Padding { share: number });
+    }
+    let bit = |position: usize| (value_words[position / 10] >> (9 - position % 10)) & 1;
+    if (0..padding).any(|position| bit(position) == 1) {
+        return Err(Slip39Error::Padding { share: number });
+    }
+    let mut value = Zeroizing::new(vec![0u8; value_bytes]);
+    for (i, byte) in value.iter_mut().enumerate() {
+        *byte = (0..8).fold(0, |acc, j| (acc << 1) | bit(padding + i * 8 + j) as u8);
+    }
+
+    let group_threshold = ((header >> 12) & 15) as u8 + 1;
+    let group_count = ((header >> 8) & 15) as u8 + 1;
+    if group_threshold > group_count {
+        return Err(Slip39Error::Parameters(format!("share {} has a group threshold above its group count", number)));
+    }
+    Ok(Share {
+        identifier: (header >> 25) as u16,
+        extendable,
+        iteration_exponent: ((header >> 20) & 15) as u8,
+        group_index: ((header >> 16) & 15) as u8,
+        group_threshold,
+        group_count,
+        member_index: ((header >> 4) & 15) as u8,
+        member_threshold: (header & 15) as u8 + 1,
+        value,
+    })
+}
+
+pub fn parse_wordlist() -> Vec<&'static str> {
+    WORDLIST.lines().map(str::trim).filter(|word| !word.is_empty()).collect()
+}
+
+fn check_passphrase(passphrase: &str) -> Result<(), Slip39Error> {
+    if passphrase.bytes().all(|byte| (32..=126).contains(&byte)) {
+        Ok(())
+    } else {
+        Err(Slip39Error::Passphrase)
+    }
+}
+
+// Function to split a master secret into shares: `groups` gives each group's (threshold,
+// count) and `group_threshold` how many groups recover the secret. Returns the shares of each
+// group as phrases
+pub fn generate_shares(
+    master_secret: &[u8],
+    passphrase: &str,
+    group_threshold: u8,
+    groups: &[(u8, u8)],
+    iteration_exponent: u8,
+    rng: &mut impl CryptoRng,
+) -> Result<Vec<Vec<Zeroizing<String>>>, Slip39Error> {
+    if master_secret.len() < MIN_SECRET_BYTES || !master_secret.len().is_multiple_of(2) {
+        return Err(Slip39Error::Parameters(format!(
+            "the master secret is {} bytes; it must be an even number, at least {}",
+            master_secret.len(),
+            MIN_SECRET_BYTES
+        )));
+    }
+    if iteration_exponent > 15 {
+        return Err(Slip39Error::Parameters("the iteration exponent must be 0 to 15".to_string()));
+    }
+    if groups.is_empty() || groups.len() > MAX_SHARE_COUNT as usize || group_threshold as usize > groups.len() {
+        return Err(Slip39Error::Parameters(format!(
+            "a group threshold of {} of {} groups is not possible; it must be between 1 and the group count, at most {}",
+            group_threshold,
+            groups.len(),
+            MAX_SHARE_COUNT
+        )));
+    }
+    if groups.iter().any(|&(threshold, count)| threshold == 1 && count > 1) {
+        return Err(Slip39Error::Parameters(
+            "a threshold of 1 with several shares only copies the secret; use 1 of 1 instead".to_string(),
+        ));
+    }
+    check_passphrase(passphrase)?;
+
+    // Extendable backups are salted without the identifier, so more groups can be made later
+    let mut identifier_bytes = [0u8; 2];
+    rng.fill_bytes(&mut identifier_bytes);
+    let identifier = u16::from_be_bytes(identifier_bytes) & 0x7FFF;
+    let extendable = true;
+    let encrypted = feistel(master_secret, passphrase.as_bytes(), iteration_exponent, identifier, extendable, false);
+
+    let wordlist = parse_wordlist();
+    let group_secrets = split_secret(group_threshold, groups.len() as u8, &encrypted, rng)?;
+    let mut output = Vec::new();
+    for ((group_index, group_secret), &(member_threshold, count)) in group_secrets.iter().zip(groups) {
+        let members = split_secret(member_threshold, count, group_secret, rng)?;
+        let phrases = members
+            .into_iter()
+            .map(|(member_index, value)| {
+                let share = Share {
+                    identifier,
+                    extendable,
+                    iteration_exponent,
+                    group_index: *group_index,
+                    group_threshold,
+                    group_count: groups.len() as u8,
+                    member_index,
+                    member_threshold,
+                    value,
+                };
+                encode_share(&share, &wordlist)
+            })
+            .collect();
+        output.push(phrases);
+    }
+    Ok(output)
+}
+
+// Function to recover the master secret from shares: each must decode and agree with the
+// first, enough groups must be complete, and the shares must reproduce the digest
+pub fn combine_shares(mnemonics: &[&str], passphrase: &str) -> Result<Zeroizing<Vec<u8>>, Slip39Error> {
+    if mnemonics.is_empty() {
+        return Err(Slip39Error::NoShares);
+    }
+    check_passphrase(passphrase)?;
+    let wordlist = parse_wordlist();
+    let shares = mnemonics
+        .iter()
+        .enumerate()
+        .map(|(i, mnemonic)| decode_share(mnemonic, &wordlist, i + 1))
+        .collect::<Result<Vec<Share>, Slip39Error>>()?;
+
+    let first = &shares[0];
+    let mut groups: BTreeMap<u8, Vec<&Share>> = BTreeMap::new();
+    for (i, share) in shares.iter().enumerate() {
+        if share.identifier != first.identifier {
+            return Err(Slip39Error::Identifier { share: i + 1, found: share.identifier, expected: first.identifier });
+        }
+        if !share.same_split(first) {
+            return Err(Slip39Error::Mismatch { share: i + 1 });
+        }
+        let members = groups.entry(share.group_index).or_default();
+        if members.first().is_some_and(|member| member.member_threshold != share.member_threshold) {
+            return Err(Slip39Error::MemberThreshold { share: i + 1, group: share.group_index + 1 });
+        }
+        // The same share given twice is harmless; the same member with another value is not
+        match members.iter().find(|member| member.member_index == share.member_index) {
+            Some(member) if member.value != share.value => {
+                return Err(Slip39Error::DuplicateMember {
+                    share: i + 1,
+                    group: share.group_index + 1,
+                    member: share.member_index + 1,
+                });
+            }
+            Some(_) => {}
+            None => members.push(share),
+        }
+    }
+
+    // Recover each complete group's secret, then the encrypted master secret from those
+    let mut group_secrets: Points = Vec::new();
+    let mut partial = Vec::new();
+    for (&group_index, members) in &groups {
+        let threshold = members[0].member_threshold;
+        if members.len() < threshold as usize {
+            partial.push((group_index + 1, members.len(), threshold));
+            continue;
+        }
+        if group_secrets.len() == first.group_threshold as usize {
+            continue;
+        }
+        let points: Vec<(u8, &[u8])> = members
+            .iter()
+            .take(threshold as usize)
+            .map(|member| (member.member_index, member.value.as_slice()))
+            .collect();
+        group_secrets.push((group_index, recover_secret(threshold, &points)?));
+    }
+    if group_secrets.len() < first.group_threshold as usize {
+        if first.group_count == 1 {
+            let (group, have, need) = partial[0];
+            return Err(Slip39Error::TooFewShares { group, have, need });
+        }
+        return Err(Slip39Error::TooFewGroups { have: group_secrets.len(), need: first.group_threshold, partial });
+    }
+    let points: Vec<(u8, &[u8])> = group_secrets.iter().map(|(index, value)| (*index, value.as_slice())).collect();
+    let encrypted = recover_secret(first.group_threshold, &points)?;
+
+    Ok(feistel(&encrypted, passphrase.as_bytes(), first.iteration_exponent, first.identifier, first.extendable, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hex::{DisplayHex, FromHex};
+    use rand::rngs::OsRng;
+    use rand::TryRngCore;
+
+    #[test]
+    fn test_wordlist() {
+        let wordlist = parse_wordlist();
+        assert_eq!(wordlist.len(), 1024);
+        assert_eq!((wordlist[0], wordlist[1023]), ("academic", "zero"));
+        // Sorted, so shares decode by binary search, and told apart by their first four letters
+        assert!(wordlist.windows(2).all(|pair| pair[0] < pair[1]));
+        let prefixes: std::collections::HashSet<&str> = wordlist.iter().map(|word| &word[..4]).collect();
+        assert_eq!(prefixes.len(), 1024);
+    }
+
+    #[test]
+    fn test_slip39_vectors() {
+        // From the SLIP-39 test vectors, all with the passphrase "TREZOR"
+        let vectors: [(&[&str], &str); 5] = [
+            (
+                &["duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision keyboard"],
+                "bb54aac4b89dc868ba37d9cc21b2cece",
+            ),
+            (
+                &[
+                    "shadow pistol academic always adequate wildlife fancy gross oasis cylinder mustang wrist rescue view short owner flip making coding armed",
+                    "shadow pistol academic acid actress prayer class unknown daughter sweater depict flip twice unkind craft early superior advocate guest smoking",
+                ],
+                "b43ceb7e57a0ea8766221624d01b0864",
+            ),
+            (
+                &[
+                    "eraser senior beard romp adorn nuclear spill corner cradle style ancient family general leader ambition exchange unusual garlic promise voice",
+                    "eraser senior ceramic snake clay various huge numb argue hesitate auction category timber browser greatest hanger petition script leaf pickup",
+                    "eraser senior ceramic shaft dynamic become junior wrist silver peasant force math alto coal amazing segment yelp velvet image paces",
+                    "eraser senior ceramic round column hawk trust auction smug shame alive greatest sheriff living perfect corner chest sled fumes adequate",
+                ],
+                "7c3397a292a5941682d7a4ae2d898d11",
+            ),
+            (
+                &["theory painting academic academic armed sweater year military elder discuss acne wildlife boring employer fused large satoshi bundle carbon diagnose anatomy hamster leaves tracks paces beyond phantom capital marvel lips brave detect luck"],
+                "989baf9dcaad5b10ca33dfd8cc75e42477025dce88ae83e75a230086a0e00e92",
+            ),
+            (
+                &[
+                    "humidity disease academic always aluminum jewelry energy woman receiver strategy amuse duckling lying evidence network walnut tactics forget hairy rebound impulse brother survive clothes stadium mailman rival ocean reward venture always armed unwrap",
+                    "humidity disease academic agency actress jacket gross physics cylinder solution fake mortgage benefit public busy prepare sharp friar change work slow purchase ruler again tricycle involve viral wireless mixture anatomy desert cargo upgrade",
+                ],
+                "c938b319067687e990e05e0da0ecce1278f75ff58d9853f19dcaeed5de104aae",
+            ),
+        ];
+        for (mnemonics, secret) in vectors {
+            assert_eq!(combine_shares(mnemonics, "TREZOR").unwrap().to_lower_hex_string(), secret);
+        }
+
+        // The invalid-checksum vector, and a single share of the 2-of-3 vector
+        let bad_checksum = "duckling enlarge academic academic agency result length solution fridge kidney coal piece deal husband erode duke ajar critical decision kidney";
+        assert_eq!(combine_shares(&[bad_checksum], "TREZOR"), Err(Slip39Error::Checksum { share: 1 }));
+        assert_eq!(
+            combine_shares(&[vectors[1].0[0]], "TREZOR"),
+            Err(Slip39Error::TooFewShares { group: 1, have: 1, need: 2 })
+        );
+        assert_eq!(
+            combine_shares(&vectors[2].0[..3], "TREZOR"),
+            Err(Slip39Error::TooFewGroups { have: 1, need: 2, partial: vec![(3, 2, 3)] })
+        );
+        assert!(matches!(
+            combine_shares(&[vectors[1].0[0], vectors[2].0[0]], "TREZOR"),
+            Err(Slip39Error::Identifier { share: 2, .. })
+        ));
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let secret = Vec::<u8>::from_hex("0c94c76d3d2ab0fbeaf2c1d5d5a8cd9ef5e0f3ba1c1c8c0c06f8e5e1a2b3c4d5").unwrap();
+        let mut rng = OsRng.unwrap_err();
+
+        // 3 of 5 in one group, recovered from several subsets in any order
+        let groups = generate_shares(&secret, "passphrase", 1, &[(3, 5)], 0, &mut rng).unwrap();
+        assert_eq!(groups.len(), 1);
+        let shares: Vec<&str> = groups[0].iter().map(|share| share.as_str()).collect();
+        assert_eq!(shares.len(), 5);
+        assert!(shares.iter().all(|share| share.split_whitespace().count() == 33));
+        for subset in [[0, 1, 2], [4, 2, 0], [1, 3, 4]] {
+            let chosen: Vec<&str> = subset.iter().map(|&i| shares[i]).collect();
+            assert_eq!(*combine_shares(&chosen, "passphrase").unwrap(), secret);
+        }
+        assert_eq!(
+            combine_shares(&shares[..2], "passphrase"),
+            Err(Slip39Error::TooFewShares { group: 1, have: 2, need: 3 })
+        );
+        // Any passphrase decrypts to some secret; only the right one to this one
+        assert_ne!(*combine_shares(&shares[..3], "").unwrap(), secret);
+
+        // Two of three groups, with a 1-of-1 group
+        let groups = generate_shares(&secret[..16], "", 2, &[(1, 1), (2, 3), (3, 5)], 1, &mut rng).unwrap();
+        let chosen = [groups[0][0].as_str(), groups[2][4].as_str(), groups[2][1].as_str(), groups[2][0].as_str()];
+        assert_eq!(*combine_shares(&chosen, "").unwrap(), secret[..16]);
+
+        // Shares from another backup, or with a changed word
+        let other = generate_shares(&secret, "passphrase", 1, &[(3, 5)], 0, &mut rng).unwrap();
+        assert!(matches!(
+            combine_shares(&[shares[0], other[0][1].as_str(), shares[2]], "passphrase"),
+            Err(Slip39Error::Identifier { share: 2, .. }) | Err(Slip39Error::DuplicateMember { .. })
+        ));
+        let mut words: Vec<&str> = shares[1].split_whitespace().collect();
+        words[6] = if words[6] == "academic" { "acid" } else { "academic" };
+        let altered = words.join(" ");
+        assert_eq!(
+            combine_shares(&[shares[0], &altered, shares[2]], "passphrase"),
+            Err(Slip39Error::Checksum { share: 2 })
+        );
+
+        // Parameters that cannot make a valid backup
+        assert!(generate_shares(&secret, "", 1, &[(4, 3)], 0, &mut rng).is_err());
+        assert!(generate_shares(&secret, "", 1, &[(1, 3)], 0, &mut rng).is_err());
+        assert!(generate_shares(&secret, "", 2, &[(2, 3)], 0, &mut rng).is_err());
+        assert!(generate_shares(&secret[..15], "", 1, &[(2, 3)], 0, &mut rng).is_err());
+        assert!(matches!(generate_shares(&secret, "caf\u{e9}", 1, &[(2, 3)], 0, &mut rng), Err(Slip39Error::Passphrase)));
+    }
+}
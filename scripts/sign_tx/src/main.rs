@@ -1,166 +1,2370 @@
-use bitcoin::consensus::encode::{deserialize, serialize};
-use bitcoin::util::key::PrivateKey;
-use bitcoin::{Address, Network, Script, Transaction};
-use bitcoin_hashes::Hash;
-use hex;
-use secp256k1::{Message, Secp256k1};
-use serde::Deserialize;
+use bitcoin::bip32::DerivationPath;
+use bitcoin::consensus::encode::serialize_hex;
+use bitcoin::hex::{DisplayHex, FromHex};
+use bitcoin::hashes::Hash;
+use bitcoin::script::{Builder, Instruction, PushBytesBuf};
+use bitcoin::secp256k1::{self, schnorr, Message, Secp256k1, SecretKey, XOnlyPublicKey};
+use bitcoin::sighash::{EcdsaSighashType, Prevouts, SighashCache, TapSighashType};
+use bitcoin::transaction::predict_weight;
+use bitcoin::{
+    ecdsa, taproot, Address, Amount, CompressedPublicKey, Network, NetworkKind, PrivateKey, PublicKey, Script, ScriptBuf,
+    Transaction, TxIn, TxOut, Witness,
+};
+use btcx_lib::hd::{ScriptKind, SigningXpriv};
+use btcx_lib::multisig::{MultisigInput, MultisigScript};
+use btcx_lib::network::BlockstreamClient;
+use btcx_lib::signing::{self, parse_sighash_type, InputSigner, SpentOutput};
+use btcx_lib::utils::{parse_transaction_hex, InputType};
+use btcx_lib::BtcNetwork;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
 use std::io::{self, Read};
 use std::str::FromStr;
+use zeroize::Zeroizing;
+
+// Environment variable naming the network when --network is not given
+const NETWORK_ENV: &str = "BTCX_NETWORK";
+
+// Why a WIF did not parse, without the parser's error, which can quote the key's characters
+const WIF_HINT: &str = "expected 51 or 52 base58 characters with a valid checksum";
 
 #[derive(Deserialize)]
 struct SignInput {
-    private_key_wif: String,
+    private_key_wif: Option<Zeroizing<String>>, // Either this, private_key_hex, key_index or derivation_path
+    private_key_hex: Option<Zeroizing<String>>, // Raw 64-character hex key, in place of a WIF
+    key_index: Option<usize>, // Position of the input's key in a batch request's shared keys
+    derivation_path: Option<String>, // Path of the input's key below the request's xprv, e.g. m/84'/1'/0'/0/5
+    compressed: Option<bool>, // Public key form; defaults to the WIF's, or compressed for hex keys
     address: String, // Address corresponding to the input (used to derive scriptPubKey)
+    amount: Option<u64>, // Value of the spent output in satoshis, required for segwit inputs, and for all with a P2TR one
+    sighash: Option<String>, // ALL (default), NONE or SINGLE, optionally with |ANYONECANPAY
+    prev_tx_hex: Option<String>, // Transaction holding the spent output, to check the address and take the amount from
+    redeem_script: Option<String>, // Hex multisig script behind a P2SH address; signatures accumulate in the scriptSig
+    pubkey: Option<String>, // Hex public key signing the input, for --emit-sighashes when the key is elsewhere
 }
 
 #[derive(Deserialize)]
 struct SignTxRequest {
     unsigned_tx_hex: String,
+    xprv: Option<Zeroizing<String>>, // Extended private key for inputs that give a derivation_path
     inputs: Vec<SignInput>, // One entry per input in the transaction
 }
 
-// Helper function to push data onto a script (manual script building)
-fn push_data(bytes: &[u8]) -> Vec<u8> {
-    let mut result = Vec::new();
-    let len = bytes.len();
-    if len < 76 {
-        result.push(len as u8);
-    } else if len < 256 {
-        result.push(76u8); // OP_PUSHDATA1
-        result.push(len as u8);
-    } else {
-        result.push(77u8); // OP_PUSHDATA2
-        result.push((len & 0xff) as u8);
-        result.push((len >> 8) as u8);
+#[derive(Deserialize)]
+struct CollectInput {
+    redeem_script: Option<String>, // Hex multisig script; inputs without one keep the first signature found
+}
+
+#[derive(Deserialize)]
+struct BatchRequest {
+    #[serde(default)]
+    keys: Vec<Zeroizing<String>>, // WIF or hex keys, parsed once, that inputs refer to by key_index
+    transactions: Vec<SignTxRequest>, // Signed independently, each as a single request would be
+}
+
+// Enough of a request to tell a batch from a single transaction
+#[derive(Deserialize)]
+struct RequestShape {
+    transactions: Option<serde::de::IgnoredAny>,
+}
+
+// A signature made elsewhere over a sighash from --emit-sighashes
+#[derive(Deserialize)]
+struct DetachedSignature {
+    input_index: usize,
+    signature_der_hex: Option<String>, // DER ECDSA signature, without the sighash byte
+    schnorr_signature_hex: Option<String>, // 64-byte BIP340 signature, for a taproot input
+    pubkey_hex: String, // Key the signature verifies under; the x-only output key for taproot
+}
+
+#[derive(Deserialize)]
+struct AssembleRequest {
+    fragment_hexes: Vec<String>, // Transactions signed with --contribute, each with some contributors' inputs
+}
+
+#[derive(Deserialize)]
+struct CollectRequest {
+    partial_tx_hexes: Vec<String>, // Copies of one transaction, each holding some of the signatures
+    inputs: Vec<CollectInput>, // One entry per input in the transaction
+}
+
+// Network named by --network, else by $BTCX_NETWORK, else mainnet
+fn resolve_network(flag: Option<&str>, env: Option<String>) -> Result<BtcNetwork, String> {
+    match flag.map(str::to_string).or(env) {
+        Some(name) => BtcNetwork::from_str(&name)
+            .map_err(|_| format!("Unknown network {:?}; use mainnet, testnet, signet or regtest", name)),
+        None => Ok(BtcNetwork::Bitcoin),
+    }
+}
+
+// Which networks a key's WIF version byte is valid for
+fn key_networks(kind: NetworkKind) -> &'static str {
+    match kind {
+        NetworkKind::Main => "mainnet",
+        NetworkKind::Test => "testnet, signet or regtest",
     }
-    result.extend_from_slice(bytes);
-    result
 }
 
-fn sign_transaction(request: SignTxRequest, network: Network) -> Result<String, String> {
+fn sign_transaction(request: SignTxRequest, network: BtcNetwork, verify: bool, low_r: bool) -> Result<String, String> {
+    let (tx, _) = sign_request(request, &[], network, verify, low_r)?;
+    Ok(serialize_hex(&tx))
+}
+
+// The signed transaction with the signers that produced it, one per input; `shared_keys` are
+// those of a batch request
+fn sign_request(
+    request: SignTxRequest,
+    shared_keys: &[PrivateKey],
+    network: BtcNetwork,
+    verify: bool,
+    low_r: bool,
+) -> Result<(Transaction, Vec<InputSigner>), String> {
     // Deserialize the unsigned transaction
-    let tx_bytes = hex::decode(&request.unsigned_tx_hex)
-        .map_err(|e| format!("Invalid hex: {}", e))?;
-    let mut tx: Transaction = deserialize(&tx_bytes)
+    let mut tx = parse_transaction_hex(&request.unsigned_tx_hex)
         .map_err(|e| format!("Failed to deserialize transaction: {}", e))?;
+    check_input_count(&tx, request.inputs.len())?;
 
-    // Validate input count matches
-    if request.inputs.len() != tx.input.len() {
+    // Signet, testnet and regtest keys share a WIF version byte
+    let network_kind = NetworkKind::from(Network::from(network));
+    let xpriv = match &request.xprv {
+        Some(xprv) => Some(SigningXpriv::parse(xprv, network).map_err(|e| format!("{}; pass --network to change it", e))?),
+        None => None,
+    };
+    let mut signers = Vec::with_capacity(request.inputs.len());
+    for (i, sign_input) in request.inputs.iter().enumerate() {
+        let script_pubkey = address_script(&sign_input.address, i, network)?;
+
+        let redeem_script = match &sign_input.redeem_script {
+            Some(hex) => Some(parse_redeem_script(hex, i)?),
+            None => None,
+        };
+
+        if key_sources(sign_input) > 1 {
+            return Err(format!(
+                "Input {} has more than one of private_key_wif, private_key_hex, key_index and derivation_path; give one",
+                i
+            ));
+        }
+        let private_key = if let Some(wif) = &sign_input.private_key_wif {
+            // Parse the private key from WIF, never echoing it
+            let private_key =
+                PrivateKey::from_wif(wif.trim()).map_err(|_| format!("Invalid WIF for input {} ({}): {}", i, redact(wif), WIF_HINT))?;
+
+            // Verify network matches
+            if private_key.network != network_kind {
+                return Err(format!(
+                    "Private key for input {} is a {} key, but signing for {}; pass --network to change it",
+                    i,
+                    key_networks(private_key.network),
+                    network
+                ));
+            }
+            private_key
+        } else if let Some(hex) = &sign_input.private_key_hex {
+            let secret_key = SecretKey::from_str(hex.trim())
+                .map_err(|_| format!("Invalid private_key_hex for input {}: expected 64 hex characters of a valid key", i))?;
+            PrivateKey::new(secret_key, network_kind)
+        } else if let Some(index) = sign_input.key_index {
+            *shared_keys.get(index).ok_or_else(|| {
+                format!("Input {} uses key_index {}, but the request has {} shared keys", i, index, shared_keys.len())
+            })?
+        } else if let Some(path) = &sign_input.derivation_path {
+            let xpriv = xpriv.as_ref().ok_or_else(|| format!("Input {} has a derivation_path, but the request has no xprv", i))?;
+            derive_key(xpriv, path, i, &script_pubkey, redeem_script.is_some())?
+        } else {
+            return Err(format!(
+                "Input {} needs a private_key_wif, private_key_hex or key_index, or a derivation_path and a top-level xprv",
+                i
+            ));
+        };
+        let private_key = match sign_input.compressed {
+            Some(compressed) => PrivateKey { compressed, ..private_key },
+            None => private_key,
+        };
+        let private_key = match_key_form(private_key, &script_pubkey, redeem_script.as_ref(), i)?;
+
+        let sighash_type = match &sign_input.sighash {
+            Some(sighash) => Some(parse_sighash_type(sighash).map_err(|e| format!("Invalid sighash for input {}: {}", i, e))?),
+            None => None,
+        };
+
+        // Segwit inputs get a BIP143 signature in the witness, P2PKH ones a legacy scriptSig
+        let amount = spent_amount(&tx, i, sign_input, &script_pubkey)?;
+        signers.push(InputSigner { private_key, script_pubkey, amount, sighash_type, redeem_script });
+    }
+
+    signing::sign_transaction_with(&mut tx, &signers, network, low_r).map_err(|e| e.to_string())?;
+
+    // Catch script errors here rather than at broadcast
+    if verify {
+        let spent: Vec<SpentOutput> = signers.iter().map(SpentOutput::from).collect();
+        signing::verify_transaction(&tx, &spent).map_err(|e| e.to_string())?;
+        #[cfg(feature = "bitcoinconsensus")]
+        verify_with_consensus(&tx, &spent)?;
+    }
+
+    Ok((tx, signers))
+}
+
+#[derive(Debug, Serialize)]
+struct SignedInput {
+    index: usize,
+    #[serde(rename = "type")]
+    script_type: &'static str,
+    sighash: String, // Sighash type the input was signed with
+}
+
+#[derive(Debug, Serialize)]
+struct SignedTx {
+    signed_tx_hex: String,
+    txid: String,
+    size: usize,
+    vsize: u64,
+    weight: u64,
+    fee: Option<u64>, // Satoshis; null unless every input's amount is known
+    fee_rate: Option<f64>, // Satoshis per vbyte
+    inputs: Vec<SignedInput>,
+}
+
+// Describe a signed transaction for --json
+fn signed_output(tx: &Transaction, signers: &[InputSigner]) -> SignedTx {
+    let vsize = tx.vsize() as u64;
+    let spent = signers.iter().try_fold(Amount::ZERO, |total, signer| total.checked_add(signer.amount?));
+    let sent = tx.output.iter().try_fold(Amount::ZERO, |total, output| total.checked_add(output.value));
+    let fee = spent.zip(sent).and_then(|(spent, sent)| spent.checked_sub(sent));
+    let inputs = signers
+        .iter()
+        .enumerate()
+        .map(|(index, signer)| {
+            let script_type = signer_type(signer);
+            let sighash = match signer.sighash_type {
+                Some(sighash_type) => sighash_type.to_string(),
+                None if script_type == "p2tr" => TapSighashType::Default.to_string(),
+                None => EcdsaSighashType::All.to_string(),
+            };
+            SignedInput { index, script_type, sighash }
+        })
+        .collect();
+    SignedTx {
+        signed_tx_hex: serialize_hex(tx),
+        txid: tx.compute_txid().to_string(),
+        size: tx.total_size(),
+        vsize,
+        weight: tx.weight().to_wu(),
+        fee: fee.map(Amount::to_sat),
+        // Two decimal places are plenty for a rate
+        fee_rate: fee.map(|fee| (fee.to_sat() as f64 / vsize as f64 * 100.0).round() / 100.0),
+        inputs,
+    }
+}
+
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum BatchResult {
+    Signed(SignedTx),
+    Failed { error: String, input_index: Option<usize> },
+}
+
+// Sign each transaction of a batch, its shared keys parsed once; with `fail_fast` the first
+// failure ends the batch, otherwise it is reported in that transaction's place
+fn sign_batch(
+    request: BatchRequest,
+    network: BtcNetwork,
+    verify: bool,
+    low_r: bool,
+    fail_fast: bool,
+) -> Result<Vec<BatchResult>, String> {
+    let network_kind = NetworkKind::from(Network::from(network));
+    let mut keys = Vec::with_capacity(request.keys.len());
+    for (n, key) in request.keys.iter().enumerate() {
+        keys.push(parse_shared_key(key, n, network_kind, network)?);
+    }
+
+    let mut results = Vec::with_capacity(request.transactions.len());
+    let mut failure = None;
+    for (n, transaction) in request.transactions.into_iter().enumerate() {
+        match sign_request(transaction, &keys, network, verify, low_r) {
+            Ok((tx, signers)) => results.push(BatchResult::Signed(signed_output(&tx, &signers))),
+            Err(e) if fail_fast => {
+                failure = Some(format!("Transaction {}: {}", n, e));
+                break;
+            }
+            Err(e) => results.push(BatchResult::Failed { input_index: input_index(&e), error: e }),
+        }
+    }
+    for key in &mut keys {
+        key.inner.non_secure_erase();
+    }
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(results),
+    }
+}
+
+// A shared key of a batch: 64 hex characters are a raw key, anything else a WIF
+fn parse_shared_key(key: &str, n: usize, network_kind: NetworkKind, network: BtcNetwork) -> Result<PrivateKey, String> {
+    let key = key.trim();
+    if key.len() == 64 && key.bytes().all(|b| b.is_ascii_hexdigit()) {
+        let secret_key = SecretKey::from_str(key).map_err(|_| format!("Invalid shared key {}: not a valid private key", n))?;
+        return Ok(PrivateKey::new(secret_key, network_kind));
+    }
+    let private_key = PrivateKey::from_wif(key).map_err(|_| format!("Invalid shared key {} ({}): {}", n, redact(key), WIF_HINT))?;
+    if private_key.network != network_kind {
+        return Err(format!(
+            "Shared key {} is a {} key, but signing for {}; pass --network to change it",
+            n,
+            key_networks(private_key.network),
+            network
+        ));
+    }
+    Ok(private_key)
+}
+
+// Script type of a signed input, named as in the --dry-run plan
+fn signer_type(signer: &InputSigner) -> &'static str {
+    let script = &signer.script_pubkey;
+    if signer.redeem_script.is_some() {
+        "p2sh-multisig"
+    } else if script.is_p2pkh() {
+        "p2pkh"
+    } else if script.is_p2wpkh() {
+        "p2wpkh"
+    } else if script.is_p2sh() {
+        "p2sh-p2wpkh"
+    } else {
+        "p2tr"
+    }
+}
+
+// The input an error message is about, as in "Input 2 ..." or "... for input 2"
+fn input_index(message: &str) -> Option<usize> {
+    let message = message.to_lowercase();
+    message.match_indices("input ").find_map(|(at, found)| {
+        let digits: String = message[at + found.len()..].chars().take_while(char::is_ascii_digit).collect();
+        digits.parse().ok()
+    })
+}
+
+// Print an error, as JSON naming the input it concerns with --json, and exit
+fn fail(json: bool, context: &str, message: &str) -> ! {
+    fail_all(json, context, &[message.to_string()])
+}
+
+// Print several errors, one line or JSON object each, and exit
+fn fail_all(json: bool, context: &str, messages: &[String]) -> ! {
+    for message in messages {
+        if json {
+            eprintln!("{}", serde_json::json!({ "error": message, "input_index": input_index(message) }));
+        } else {
+            eprintln!("Error {}: {}", context, message);
+        }
+    }
+    std::process::exit(1);
+}
+
+// One signing input per transaction input, checked before any input is looked at by index
+fn check_input_count(tx: &Transaction, signing_inputs: usize) -> Result<(), String> {
+    if signing_inputs != tx.input.len() {
         return Err(format!(
             "Input count mismatch: transaction has {} inputs, but {} signing inputs provided",
             tx.input.len(),
-            request.inputs.len()
+            signing_inputs
         ));
     }
+    Ok(())
+}
 
-    let secp = Secp256k1::new();
+// Parse the address of input `i` to get the scriptPubKey it spends
+fn address_script(address: &str, i: usize, network: BtcNetwork) -> Result<ScriptBuf, String> {
+    let address = Address::from_str(address)
+        .map_err(|e| format!("Invalid address for input {}: {}", i, e))?
+        .require_network(Network::from(network))
+        .map_err(|_| format!("Address for input {} is not a {} address; pass --network to change it", i, network))?;
+    Ok(address.script_pubkey())
+}
 
-    // Sign each input
-    for (i, sign_input) in request.inputs.iter().enumerate() {
-        // Parse the private key from WIF
-        let privkey = PrivateKey::from_wif(&sign_input.private_key_wif)
-            .map_err(|e| format!("Invalid WIF for input {}: {}", i, e))?;
+// Amount spent by input `i`: checked against its previous transaction when given, else as claimed
+fn spent_amount(tx: &Transaction, i: usize, sign_input: &SignInput, script_pubkey: &Script) -> Result<Option<Amount>, String> {
+    let Some(prev_tx_hex) = &sign_input.prev_tx_hex else {
+        return Ok(sign_input.amount.map(Amount::from_sat));
+    };
+    let outpoint = tx.input[i].previous_output;
+    let prev_tx = parse_transaction_hex(prev_tx_hex).map_err(|e| format!("Invalid prev_tx_hex for input {}: {}", i, e))?;
+    let txid = prev_tx.compute_txid();
+    if txid != outpoint.txid {
+        return Err(format!("prev_tx_hex for input {} is transaction {}, but the input spends {}", i, txid, outpoint.txid));
+    }
+    let output = prev_tx.output.get(outpoint.vout as usize).ok_or_else(|| {
+        format!("Input {} spends output {} of {}, which has only {} outputs", i, outpoint.vout, txid, prev_tx.output.len())
+    })?;
+    if output.script_pubkey != *script_pubkey {
+        return Err(format!(
+            "Address for input {} gives script {}, but the output it spends, {}, has script {}",
+            i,
+            script_pubkey.to_hex_string(),
+            outpoint,
+            output.script_pubkey.to_hex_string()
+        ));
+    }
+    match sign_input.amount {
+        Some(claimed) if claimed != output.value.to_sat() => Err(format!(
+            "Input {} claims an amount of {} sat, but the output it spends, {}, holds {} sat",
+            i,
+            claimed,
+            outpoint,
+            output.value.to_sat()
+        )),
+        _ => Ok(Some(output.value)),
+    }
+}
 
-        // Verify network matches
-        if privkey.network != network {
-            return Err(format!(
-                "Private key network mismatch for input {}: {:?} vs {:?}",
-                i, privkey.network, network
-            ));
+// Fill in prev_tx_hex from the backend for every input without one
+fn fetch_prevouts(request: &mut SignTxRequest, client: &BlockstreamClient) -> Result<(), String> {
+    let tx = parse_transaction_hex(&request.unsigned_tx_hex).map_err(|e| format!("Failed to deserialize transaction: {}", e))?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|e| format!("Cannot start the runtime: {}", e))?;
+    for (input, sign_input) in tx.input.iter().zip(request.inputs.iter_mut()) {
+        if sign_input.prev_tx_hex.is_none() {
+            let txid = input.previous_output.txid.to_string();
+            let hex = runtime
+                .block_on(client.get_transaction_hex(&txid))
+                .map_err(|e| format!("Cannot fetch previous transaction {}: {}", txid, e))?;
+            sign_input.prev_tx_hex = Some(hex);
         }
+    }
+    Ok(())
+}
 
-        // Parse the address to get scriptPubKey
-        let address = Address::from_str(&sign_input.address)
-            .map_err(|e| format!("Invalid address for input {}: {}", i, e))?;
+#[derive(Serialize)]
+struct InputPlan {
+    index: usize,
+    outpoint: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    script_pubkey: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    script_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sighash_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sighash: Option<String>, // The 32-byte digest the signature is made over
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pubkey: Option<String>, // Key the signature must verify under: the request's, or a taproot output key
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>, // Why the input cannot be signed as given
+}
 
-        if address.network != network {
-            return Err(format!(
-                "Address network mismatch for input {}: {:?} vs {:?}",
-                i, address.network, network
-            ));
+#[derive(Serialize)]
+struct SigningPlan {
+    network: BtcNetwork,
+    txid: String,
+    estimated_vsize: Option<u64>, // Upper bound once signed; null when an input cannot be signed
+    inputs: Vec<InputPlan>,
+}
+
+// What signing would commit to, input by input, computed without any keys
+fn plan_signing(request: &SignTxRequest, network: BtcNetwork, low_r: bool) -> Result<SigningPlan, String> {
+    let tx = parse_transaction_hex(&request.unsigned_tx_hex).map_err(|e| format!("Failed to deserialize transaction: {}", e))?;
+    check_input_count(&tx, request.inputs.len())?;
+
+    // Every script first, since a taproot sighash commits to all spent outputs
+    let spent: Vec<Result<(ScriptBuf, Option<Amount>), String>> = request
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(i, input)| {
+            let script = address_script(&input.address, i, network)?;
+            let amount = spent_amount(&tx, i, input, &script)?;
+            Ok((script, amount))
+        })
+        .collect();
+    let prevouts: Option<Vec<TxOut>> = spent
+        .iter()
+        .map(|spent| {
+            let (script_pubkey, amount) = spent.as_ref().ok()?;
+            Some(TxOut { value: (*amount)?, script_pubkey: script_pubkey.clone() })
+        })
+        .collect();
+
+    let mut cache = SighashCache::new(&tx);
+    let mut input_types = Some(Vec::with_capacity(tx.input.len()));
+    let mut inputs = Vec::with_capacity(tx.input.len());
+    for (i, (sign_input, spent)) in request.inputs.iter().zip(&spent).enumerate() {
+        let mut plan = InputPlan {
+            index: i,
+            outpoint: tx.input[i].previous_output.to_string(),
+            script_pubkey: spent.as_ref().ok().map(|(script, _)| script.to_hex_string()),
+            script_type: None,
+            sighash_type: None,
+            sighash: None,
+            pubkey: None,
+            error: None,
+        };
+        let planned = spent
+            .clone()
+            .and_then(|(script, amount)| plan_input(&mut cache, i, sign_input, &script, amount, prevouts.as_deref()));
+        match planned {
+            Ok((script_type, input_type, sighash_type, sighash)) => {
+                plan.script_type = Some(script_type);
+                plan.sighash_type = Some(sighash_type);
+                plan.sighash = Some(sighash.to_lower_hex_string());
+                // A taproot key-path signature verifies under the output key in the script
+                plan.pubkey = match spent {
+                    Ok((script, _)) if script.is_p2tr() => Some(script.as_bytes()[2..].to_lower_hex_string()),
+                    _ => sign_input.pubkey.as_ref().map(|pubkey| pubkey.trim().to_lowercase()),
+                };
+                if let Some(types) = input_types.as_mut() {
+                    types.push(input_type);
+                }
+            }
+            Err(e) => {
+                plan.error = Some(e);
+                input_types = None;
+            }
+        }
+        inputs.push(plan);
+    }
+
+    let estimated_vsize = input_types.map(|types: Vec<InputType>| {
+        let outputs = tx.output.iter().map(|output| output.script_pubkey.len());
+        // Ground signatures are at most 71 bytes, one less than the largest
+        let prediction = if low_r { InputType::low_r_weight_prediction } else { InputType::weight_prediction };
+        predict_weight(types.into_iter().map(prediction), outputs).to_vbytes_ceil()
+    });
+    Ok(SigningPlan { network, txid: tx.compute_txid().to_string(), estimated_vsize, inputs })
+}
+
+// Script type, size class, sighash type name and sighash of input `i`
+fn plan_input(
+    cache: &mut SighashCache<&Transaction>,
+    i: usize,
+    sign_input: &SignInput,
+    script: &Script,
+    amount: Option<Amount>,
+    prevouts: Option<&[TxOut]>,
+) -> Result<(&'static str, InputType, String, [u8; 32]), String> {
+    let sighash_type = match &sign_input.sighash {
+        Some(sighash) => Some(parse_sighash_type(sighash).map_err(|e| format!("Invalid sighash: {}", e))?),
+        None => None,
+    };
+    let ecdsa_type = sighash_type.unwrap_or(EcdsaSighashType::All);
+    if matches!(ecdsa_type, EcdsaSighashType::Single | EcdsaSighashType::SinglePlusAnyoneCanPay)
+        && i >= cache.transaction().output.len()
+    {
+        return Err(format!("SIGHASH_SINGLE has no output {} to commit to", i));
+    }
+    let sighash_error = |e: &dyn std::fmt::Display| format!("Cannot compute sighash: {}", e);
+    let segwit_amount = || amount.ok_or("Segwit input; the amount it spends is required");
+    let pubkey = match &sign_input.pubkey {
+        Some(hex) => Some(PublicKey::from_str(hex.trim()).map_err(|e| format!("Invalid pubkey: {}", e))?),
+        None => None,
+    };
+    let pubkey_mismatch = || "The pubkey does not control the output it spends".to_string();
+
+    if let Some(hex) = &sign_input.redeem_script {
+        let redeem_script = parse_redeem_script(hex, i)?;
+        if *script != redeem_script.to_p2sh() {
+            return Err("Redeem script does not hash to the output it spends".to_string());
+        }
+        let multisig = MultisigScript::parse(&redeem_script).map_err(|e| e.to_string())?;
+        if pubkey.is_some_and(|pubkey| !multisig.pubkeys.contains(&pubkey)) {
+            return Err("The pubkey is not one of the redeem script's keys".to_string());
+        }
+        let sighash = cache.legacy_signature_hash(i, &redeem_script, ecdsa_type.to_u32()).map_err(|e| sighash_error(&e))?;
+        // Multisig inputs are limited to 15 keys, so the counts fit
+        let input_type = InputType::P2shMultisig { required: multisig.required as u8, keys: multisig.pubkeys.len() as u8 };
+        Ok(("p2sh-multisig", input_type, ecdsa_type.to_string(), sighash.to_byte_array()))
+    } else if script.is_p2pkh() {
+        if pubkey.is_some_and(|pubkey| *script != ScriptBuf::new_p2pkh(&pubkey.pubkey_hash())) {
+            return Err(pubkey_mismatch());
+        }
+        let sighash = cache.legacy_signature_hash(i, script, ecdsa_type.to_u32()).map_err(|e| sighash_error(&e))?;
+        Ok(("p2pkh", InputType::P2pkh, ecdsa_type.to_string(), sighash.to_byte_array()))
+    } else if script.is_p2wpkh() {
+        if pubkey.is_some_and(|pubkey| p2wpkh_program(&pubkey).as_deref() != Some(script)) {
+            return Err(pubkey_mismatch());
         }
+        let sighash = cache.p2wpkh_signature_hash(i, script, segwit_amount()?, ecdsa_type).map_err(|e| sighash_error(&e))?;
+        Ok(("p2wpkh", InputType::P2wpkh, ecdsa_type.to_string(), sighash.to_byte_array()))
+    } else if script.is_p2sh() {
+        // The key tells P2SH-P2WPKH apart from other P2SH scripts
+        let Some(pubkey) = pubkey else {
+            return Err("P2SH output without a redeem_script or pubkey; P2SH-P2WPKH cannot be told apart from other P2SH scripts \
+                 without the key"
+                .to_string());
+        };
+        let program = p2wpkh_program(&pubkey).filter(|program| program.to_p2sh() == *script).ok_or_else(pubkey_mismatch)?;
+        let sighash = cache.p2wpkh_signature_hash(i, &program, segwit_amount()?, ecdsa_type).map_err(|e| sighash_error(&e))?;
+        Ok(("p2sh-p2wpkh", InputType::P2shP2wpkh, ecdsa_type.to_string(), sighash.to_byte_array()))
+    } else if script.is_p2tr() {
+        let prevouts = prevouts.ok_or("Taproot input; every input needs a valid address and its amount")?;
+        let tap_type = match sighash_type {
+            Some(sighash_type) => TapSighashType::from_consensus_u8(sighash_type.to_u32() as u8).expect("ECDSA types exist in taproot"),
+            None => TapSighashType::Default,
+        };
+        let sighash = cache
+            .taproot_key_spend_signature_hash(i, &Prevouts::All(prevouts), tap_type)
+            .map_err(|e| sighash_error(&e))?;
+        Ok(("p2tr", InputType::P2tr, tap_type.to_string(), sighash.to_byte_array()))
+    } else {
+        Err("Unsupported script type".to_string())
+    }
+}
+
+// Put signatures made elsewhere into the scriptSigs and witnesses, as signing here would, after
+// checking each against its input's sighash; every problem is reported, one per input
+fn apply_signatures(request: &SignTxRequest, signatures: &[DetachedSignature], network: BtcNetwork) -> Result<Transaction, Vec<String>> {
+    let plan = plan_signing(request, network, true).map_err(|e| vec![e])?;
+    let mut tx = parse_transaction_hex(&request.unsigned_tx_hex).map_err(|e| vec![e.to_string()])?;
+    let mut errors: Vec<String> = plan
+        .inputs
+        .iter()
+        .filter_map(|input| input.error.as_ref().map(|error| format!("Input {} cannot be signed: {}", input.index, error)))
+        .collect();
+    let mut by_input: Vec<Vec<&DetachedSignature>> = vec![Vec::new(); tx.input.len()];
+    for (n, signature) in signatures.iter().enumerate() {
+        match by_input.get_mut(signature.input_index) {
+            Some(input) => input.push(signature),
+            None => errors.push(format!(
+                "Signature {} is for input {}, but the transaction has {} inputs",
+                n,
+                signature.input_index,
+                tx.input.len()
+            )),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let secp = Secp256k1::verification_only();
+    for (i, (input_plan, signatures)) in plan.inputs.iter().zip(by_input).enumerate() {
+        let sign_input = &request.inputs[i];
+        let script = address_script(&sign_input.address, i, network).map_err(|e| vec![e])?;
+        let sighash = <[u8; 32]>::from_hex(input_plan.sighash.as_deref().expect("planned")).expect("32-byte hex");
+        let message = Message::from_digest(sighash);
+        let ecdsa_type = match &sign_input.sighash {
+            Some(sighash) => parse_sighash_type(sighash).expect("checked when planning"),
+            None => EcdsaSighashType::All,
+        };
+        let multisig = sign_input.redeem_script.as_ref().map(|hex| parse_redeem_script(hex, i).expect("checked when planning"));
+        match signatures.len() {
+            0 => {
+                errors.push(format!("Input {} has no signature", i));
+                continue;
+            }
+            1 => {}
+            count if multisig.is_none() => {
+                errors.push(format!("Input {} has {} signatures, but only multisig inputs take more than one", i, count));
+                continue;
+            }
+            _ => {}
+        }
+
+        if script.is_p2tr() {
+            let signature = signatures[0];
+            let output_key = XOnlyPublicKey::from_slice(&script.as_bytes()[2..]).expect("32-byte taproot output key");
+            let checked = signature
+                .schnorr_signature_hex
+                .as_deref()
+                .ok_or("taproot inputs need a schnorr_signature_hex".to_string())
+                .and_then(|hex| schnorr::Signature::from_str(hex).map_err(|e| format!("invalid Schnorr signature: {}", e)))
+                .and_then(|schnorr| {
+                    if signature.pubkey_hex.trim().to_lowercase() != output_key.to_string() {
+                        return Err(format!("pubkey {} is not the output key {}", signature.pubkey_hex.trim(), output_key));
+                    }
+                    secp.verify_schnorr(&schnorr, &message, &output_key)
+                        .map_err(|_| "the signature does not verify against the input's sighash".to_string())?;
+                    Ok(schnorr)
+                });
+            match checked {
+                Ok(schnorr) => {
+                    let sighash_type = match &sign_input.sighash {
+                        Some(_) => TapSighashType::from_consensus_u8(ecdsa_type.to_u32() as u8).expect("ECDSA types exist in taproot"),
+                        None => TapSighashType::Default,
+                    };
+                    let signature = taproot::Signature { signature: schnorr, sighash_type };
+                    tx.input[i].witness = Witness::p2tr_key_spend(&signature);
+                }
+                Err(e) => errors.push(format!("Input {}: {}", i, e)),
+            }
+            continue;
+        }
+
+        let mut checked = Vec::with_capacity(signatures.len());
+        for signature in &signatures {
+            let verified = PublicKey::from_str(signature.pubkey_hex.trim())
+                .map_err(|e| format!("invalid pubkey: {}", e))
+                .and_then(|pubkey| {
+                    let der = signature.signature_der_hex.as_deref().ok_or("ECDSA inputs need a signature_der_hex")?;
+                    let der = Vec::<u8>::from_hex(der.trim()).map_err(|e| format!("invalid signature hex: {}", e))?;
+                    let der = secp256k1::ecdsa::Signature::from_der(&der).map_err(|e| format!("invalid DER signature: {}", e))?;
+                    secp.verify_ecdsa(&message, &der, &pubkey.inner).map_err(|_| {
+                        format!("the signature does not verify against the input's sighash under pubkey {}", pubkey)
+                    })?;
+                    Ok((pubkey, ecdsa::Signature { signature: der, sighash_type: ecdsa_type }))
+                });
+            match verified {
+                Ok(verified) => checked.push(verified),
+                Err(e) => errors.push(format!("Input {}: {}", i, e)),
+            }
+        }
+        if checked.len() != signatures.len() {
+            continue;
+        }
+
+        let assembled = match &multisig {
+            Some(redeem_script) => MultisigInput::from_input(&tx, i, redeem_script).map_err(|e| e.to_string()).and_then(|mut multisig| {
+                for (pubkey, signature) in &checked {
+                    let key = multisig.script.pubkeys.iter().position(|key| key == pubkey);
+                    let key = key.ok_or_else(|| format!("pubkey {} is not one of the redeem script's keys", pubkey))?;
+                    multisig.signatures[key] = Some(*signature);
+                }
+                Ok((multisig.script_sig().map_err(|e| e.to_string())?, Witness::new()))
+            }),
+            None => {
+                let (pubkey, signature) = checked[0];
+                if script.is_p2pkh() && script == ScriptBuf::new_p2pkh(&pubkey.pubkey_hash()) {
+                    Ok((Builder::new().push_slice(signature.serialize()).push_key(&pubkey).into_script(), Witness::new()))
+                } else if let Some(program) = p2wpkh_program(&pubkey).filter(|program| *program == script || program.to_p2sh() == script) {
+                    let compressed = CompressedPublicKey::try_from(pubkey).expect("has a P2WPKH program");
+                    let script_sig = if script.is_p2sh() {
+                        let program = PushBytesBuf::try_from(program.to_bytes()).expect("22 bytes");
+                        Builder::new().push_slice(program).into_script()
+                    } else {
+                        ScriptBuf::new()
+                    };
+                    Ok((script_sig, Witness::p2wpkh(&signature, &compressed.0)))
+                } else {
+                    Err(format!("pubkey {} does not control the output it spends", pubkey))
+                }
+            }
+        };
+        match assembled {
+            Ok((script_sig, witness)) => {
+                tx.input[i].script_sig = script_sig;
+                tx.input[i].witness = witness;
+            }
+            Err(e) => errors.push(format!("Input {}: {}", i, e)),
+        }
+    }
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    // The same final check as the normal path
+    let spent = request
+        .inputs
+        .iter()
+        .enumerate()
+        .map(|(i, sign_input)| {
+            let script_pubkey = address_script(&sign_input.address, i, network)?;
+            let amount = spent_amount(&tx, i, sign_input, &script_pubkey)?;
+            Ok(SpentOutput { script_pubkey, amount })
+        })
+        .collect::<Result<Vec<_>, String>>()
+        .map_err(|e| vec![e])?;
+    signing::verify_transaction(&tx, &spent).map_err(|e| vec![e.to_string()])?;
+    Ok(tx)
+}
+
+// The P2WPKH script of a compressed key
+fn p2wpkh_program(pubkey: &PublicKey) -> Option<ScriptBuf> {
+    let pubkey = CompressedPublicKey::try_from(*pubkey).ok()?;
+    Some(ScriptBuf::new_p2wpkh(&pubkey.wpubkey_hash()))
+}
+
+// The compressed or uncompressed form of the key, whichever the spent P2PKH output or redeem
+// script commits to; other outputs take the key as given
+fn match_key_form(
+    private_key: PrivateKey,
+    script_pubkey: &Script,
+    redeem_script: Option<&ScriptBuf>,
+    i: usize,
+) -> Result<PrivateKey, String> {
+    let secp = Secp256k1::new();
+    let multisig = match redeem_script.map(|redeem_script| MultisigScript::parse(redeem_script)) {
+        Some(Ok(multisig)) => Some(multisig),
+        None if script_pubkey.is_p2pkh() => None,
+        // The signer reports what is wrong with a bad redeem script
+        _ => return Ok(private_key),
+    };
+    let commits_to = |key: &PublicKey| match &multisig {
+        Some(multisig) => multisig.pubkeys.contains(key),
+        None => *script_pubkey == ScriptBuf::new_p2pkh(&key.pubkey_hash()),
+    };
+    // The given or inferred form first
+    [private_key, PrivateKey { compressed: !private_key.compressed, ..private_key }]
+        .into_iter()
+        .find(|key| commits_to(&key.public_key(&secp)))
+        .ok_or_else(|| format!("Private key for input {} does not match the output it spends, compressed or uncompressed", i))
+}
+
+// Derive the key for input `i` and check that it controls the output before anything is signed
+fn derive_key(xpriv: &SigningXpriv, path: &str, i: usize, script_pubkey: &Script, multisig: bool) -> Result<PrivateKey, String> {
+    let path = DerivationPath::from_str(path).map_err(|e| format!("Invalid derivation path for input {}: {}", i, e))?;
+    let private_key = xpriv.derive(&path).map_err(|e| format!("Cannot derive the key for input {}: {}", i, e))?;
+    // The signer checks multisig keys against the redeem script
+    if multisig {
+        return Ok(private_key);
+    }
+    let kind = ScriptKind::from_script(script_pubkey)
+        .ok_or_else(|| format!("Input {} spends a script type that keys cannot be derived for", i))?;
+    let public_key = CompressedPublicKey::from_private_key(&Secp256k1::new(), &private_key).expect("derived keys are compressed");
+    let derived = kind.script_pubkey(public_key);
+    if derived != *script_pubkey {
+        return Err(format!(
+            "Key derived at {} for input {} does not control the {} output it spends: derived {}, expected {}",
+            path,
+            i,
+            kind,
+            script_fingerprint(&derived),
+            script_fingerprint(script_pubkey)
+        ));
+    }
+    Ok(private_key)
+}
 
-        let script_pubkey = address.script_pubkey();
+// The hash or key a locking script pays to, in hex
+fn script_fingerprint(script: &Script) -> String {
+    script
+        .instructions()
+        .filter_map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(bytes)) if !bytes.is_empty() => Some(bytes.as_bytes().to_lower_hex_string()),
+            _ => None,
+        })
+        .last()
+        .unwrap_or_default()
+}
 
-        // Get the secret key
-        let secret_key = privkey.inner;
+// Run each input through libbitcoinconsensus as well. Its taproot checks need every spent output,
+// which its script-level API does not take, so P2TR inputs rely on the Schnorr check alone.
+#[cfg(feature = "bitcoinconsensus")]
+fn verify_with_consensus(tx: &Transaction, spent: &[SpentOutput]) -> Result<(), String> {
+    let tx_bytes = bitcoin::consensus::serialize(tx);
+    for (i, output) in spent.iter().enumerate().filter(|(_, output)| !output.script_pubkey.is_p2tr()) {
+        // The amount only matters to segwit inputs, which cannot be signed without it
+        let amount = output.amount.unwrap_or(Amount::ZERO);
+        bitcoin::consensus::verify_script(&output.script_pubkey, i, amount, &tx_bytes)
+            .map_err(|e| format!("Input {} failed consensus verification: {}", i, e))?;
+    }
+    Ok(())
+}
+
+fn parse_redeem_script(hex: &str, input: usize) -> Result<ScriptBuf, String> {
+    ScriptBuf::from_hex(hex).map_err(|e| format!("Invalid redeem script for input {}: {}", input, e))
+}
+
+// Merge the signatures in every copy of a transaction, returning it with one status line per multisig input
+fn collect_signatures(request: CollectRequest) -> Result<(String, Vec<String>), String> {
+    let copies = request
+        .partial_tx_hexes
+        .iter()
+        .enumerate()
+        .map(|(n, hex)| parse_transaction_hex(hex).map_err(|e| format!("Failed to deserialize transaction {}: {}", n, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut tx = copies.first().ok_or("No transactions to collect signatures from")?.clone();
+    if let Some(n) = copies.iter().position(|copy| without_signatures(copy) != without_signatures(&tx)) {
+        return Err(format!("Transaction {} is not a copy of transaction 0", n));
+    }
+    if request.inputs.len() != tx.input.len() {
+        return Err(format!(
+            "Input count mismatch: transaction has {} inputs, but {} collect inputs provided",
+            tx.input.len(),
+            request.inputs.len()
+        ));
+    }
 
-        // Compute the signature hash (SIGHASH_ALL = 0x01)
-        let sighash = tx.signature_hash(i, &script_pubkey, 0x01);
+    let mut status = Vec::new();
+    for (i, collect_input) in request.inputs.iter().enumerate() {
+        match &collect_input.redeem_script {
+            Some(hex) => {
+                let redeem_script = parse_redeem_script(hex, i)?;
+                let mut multisig = MultisigInput::from_input(&copies[0], i, &redeem_script).map_err(|e| e.to_string())?;
+                for copy in &copies[1..] {
+                    let other = MultisigInput::from_input(copy, i, &redeem_script).map_err(|e| e.to_string())?;
+                    multisig.merge(&other).map_err(|e| e.to_string())?;
+                }
+                tx.input[i].script_sig = multisig.script_sig().map_err(|e| e.to_string())?;
+                status.push(format!(
+                    "Input {}: {} of {} required signatures{}",
+                    i,
+                    multisig.signature_count().min(multisig.script.required),
+                    multisig.script.required,
+                    if multisig.is_complete() { ", complete" } else { "" }
+                ));
+            }
+            None => {
+                if let Some(copy) = copies.iter().find(|copy| !copy.input[i].script_sig.is_empty() || !copy.input[i].witness.is_empty()) {
+                    tx.input[i].script_sig = copy.input[i].script_sig.clone();
+                    tx.input[i].witness = copy.input[i].witness.clone();
+                }
+            }
+        }
+    }
+    Ok((serialize_hex(&tx), status))
+}
 
-        // Create a message from the sighash
-        let msg = Message::from_slice(&sighash.as_hash().into_inner())
-            .map_err(|e| format!("Failed to create message for input {}: {}", i, e))?;
+// Merge two partially signed copies of a transaction, taking each input's scriptSig and witness
+// from whichever copy has them and merging the signatures of multisig inputs both have signed
+fn combine_transactions(first_hex: &str, second_hex: &str) -> Result<(String, Vec<String>), String> {
+    let first = parse_transaction_hex(first_hex).map_err(|e| format!("Failed to deserialize transaction 1: {}", e))?;
+    let second = parse_transaction_hex(second_hex).map_err(|e| format!("Failed to deserialize transaction 2: {}", e))?;
+    let (unsigned, other) = (without_signatures(&first), without_signatures(&second));
+    if unsigned != other {
+        return Err(format!(
+            "Transaction 2 is not a copy of transaction 1: their unsigned txids are {} and {}",
+            unsigned.compute_txid(),
+            other.compute_txid()
+        ));
+    }
 
-        // Sign the message
-        let sig = secp.sign_ecdsa(&msg, &secret_key);
+    let mut tx = first.clone();
+    let mut status = Vec::with_capacity(tx.input.len());
+    for (i, (mine, theirs)) in first.input.iter().zip(&second.input).enumerate() {
+        let signed = |input: &TxIn| !input.script_sig.is_empty() || !input.witness.is_empty();
+        if signed(mine) && signed(theirs) && (mine.script_sig != theirs.script_sig || mine.witness != theirs.witness) {
+            // Only multisig partials of the same script can be merged; anything else is a conflict
+            let redeem_script = match (multisig_redeem_script(mine), multisig_redeem_script(theirs)) {
+                (Some(mine), Some(theirs)) if mine == theirs => mine,
+                _ => return Err(format!("Input {} is signed differently in the two transactions", i)),
+            };
+            let mut multisig = MultisigInput::from_input(&first, i, &redeem_script).map_err(|e| e.to_string())?;
+            let other = MultisigInput::from_input(&second, i, &redeem_script).map_err(|e| e.to_string())?;
+            multisig.merge(&other).map_err(|e| e.to_string())?;
+            tx.input[i].script_sig = multisig.script_sig().map_err(|e| e.to_string())?;
+        } else if !signed(mine) {
+            tx.input[i].script_sig = theirs.script_sig.clone();
+            tx.input[i].witness = theirs.witness.clone();
+        }
 
-        // Get the public key
-        let public_key = secp256k1::PublicKey::from_secret_key(&secp, &secret_key);
-        let public_key_bytes = public_key.serialize();
+        status.push(match multisig_redeem_script(&tx.input[i]) {
+            Some(redeem_script) => {
+                let multisig = MultisigInput::from_input(&tx, i, &redeem_script).map_err(|e| e.to_string())?;
+                format!(
+                    "Input {}: {} of {} required signatures{}",
+                    i,
+                    multisig.signature_count().min(multisig.script.required),
+                    multisig.script.required,
+                    if multisig.is_complete() { ", complete" } else { "" }
+                )
+            }
+            None if signed(&tx.input[i]) => format!("Input {}: signed", i),
+            None => format!("Input {}: unsigned", i),
+        });
+    }
+    Ok((serialize_hex(&tx), status))
+}
 
-        // Create scriptSig: <signature> <public_key>
-        // Signature needs SIGHASH_ALL byte appended (0x01)
-        let mut sig_bytes = sig.serialize_der().to_vec();
-        sig_bytes.push(0x01); // Append SIGHASH_ALL
+// The multisig redeem script ending a P2SH scriptSig, if the input has one
+fn multisig_redeem_script(input: &TxIn) -> Option<ScriptBuf> {
+    let last = input.script_sig.instructions().last()?.ok()?;
+    let redeem_script = ScriptBuf::from_bytes(last.push_bytes()?.as_bytes().to_vec());
+    MultisigScript::parse(&redeem_script).ok()?;
+    Some(redeem_script)
+}
 
-        // Build scriptSig bytes manually
-        let mut script_sig_bytes = Vec::new();
-        script_sig_bytes.extend_from_slice(&push_data(&sig_bytes));
-        script_sig_bytes.extend_from_slice(&push_data(&public_key_bytes));
+fn without_signatures(tx: &Transaction) -> Transaction {
+    let mut tx = tx.clone();
+    for input in &mut tx.input {
+        input.script_sig = ScriptBuf::new();
+        input.witness = Witness::new();
+    }
+    tx
+}
 
-        // Create Script from bytes
-        let script_sig = Script::from(script_sig_bytes);
+// Sign every input with ALL|ANYONECANPAY, so the transaction is a fragment other inputs can join
+fn contribute(request: &mut SignTxRequest) -> Result<(), String> {
+    for (i, sign_input) in request.inputs.iter_mut().enumerate() {
+        if let Some(sighash) = &sign_input.sighash {
+            let sighash_type = parse_sighash_type(sighash).map_err(|e| format!("Invalid sighash for input {}: {}", i, e))?;
+            if sighash_type != EcdsaSighashType::AllPlusAnyoneCanPay {
+                return Err(format!("Input {} sets sighash {}, but contributed inputs are signed with ALL|ANYONECANPAY", i, sighash));
+            }
+        }
+        sign_input.sighash = Some("ALL|ANYONECANPAY".to_string());
+    }
+    Ok(())
+}
 
-        // Update the transaction input with the scriptSig
-        tx.input[i].script_sig = script_sig;
+// Join the inputs of crowdfunding fragments, which must agree on everything else
+fn assemble_fragments(request: AssembleRequest) -> Result<Transaction, String> {
+    let fragments = request
+        .fragment_hexes
+        .iter()
+        .enumerate()
+        .map(|(n, hex)| parse_transaction_hex(hex).map_err(|e| format!("Failed to deserialize fragment {}: {}", n, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let first = fragments.first().ok_or("No fragments to assemble")?;
+    let outputs = serialize_hex(&first.output);
+    let mut tx = Transaction { input: Vec::new(), ..first.clone() };
+    let mut contributed = HashMap::new();
+    for (n, fragment) in fragments.iter().enumerate() {
+        if fragment.version != first.version {
+            return Err(format!("Fragment {} has version {}, but fragment 0 has {}", n, fragment.version, first.version));
+        }
+        if fragment.lock_time != first.lock_time {
+            return Err(format!("Fragment {} has lock time {}, but fragment 0 has {}", n, fragment.lock_time, first.lock_time));
+        }
+        if serialize_hex(&fragment.output) != outputs {
+            return Err(format!("Fragment {} has different outputs from fragment 0", n));
+        }
+        if fragment.input.is_empty() {
+            return Err(format!("Fragment {} has no inputs", n));
+        }
+        for (i, input) in fragment.input.iter().enumerate() {
+            if let Some(other) = contributed.insert(input.previous_output, n) {
+                return Err(format!("Input {} of fragment {} spends {}, as fragment {} does", i, n, input.previous_output, other));
+            }
+            let sighash_types = signature_sighash_types(input);
+            if sighash_types.is_empty() {
+                return Err(format!("Input {} of fragment {} is not signed", i, n));
+            }
+            // With any other type the signature would not survive other inputs joining
+            if sighash_types.iter().any(|&sighash_type| sighash_type != EcdsaSighashType::AllPlusAnyoneCanPay as u8) {
+                return Err(format!("Input {} of fragment {} is not signed with ALL|ANYONECANPAY", i, n));
+            }
+            tx.input.push(input.clone());
+        }
     }
+    Ok(tx)
+}
 
-    // Serialize the signed transaction
-    let signed_tx_bytes = serialize(&tx);
-    let signed_tx_hex = hex::encode(signed_tx_bytes);
+// Sighash type bytes of the signatures in a signed input: the first witness element, or the
+// signature pushes of a scriptSig
+fn signature_sighash_types(input: &TxIn) -> Vec<u8> {
+    if let Some(signature) = input.witness.nth(0) {
+        return match signature.len() {
+            // A taproot signature with the default type
+            64 => vec![0],
+            65 => vec![signature[64]],
+            _ => ecdsa::Signature::from_slice(signature).map(|signature| vec![signature.sighash_type as u8]).unwrap_or_default(),
+        };
+    }
+    input
+        .script_sig
+        .instructions()
+        .filter_map(|instruction| match instruction {
+            Ok(Instruction::PushBytes(push)) => ecdsa::Signature::from_slice(push.as_bytes()).ok(),
+            _ => None,
+        })
+        .map(|signature| signature.sighash_type as u8)
+        .collect()
+}
 
-    Ok(signed_tx_hex)
+/// What error messages say of a key that did not parse: its length, never any of its characters
+fn redact(key: &str) -> String {
+    format!("{} characters", key.trim().chars().count())
+}
+
+/// Reads the request from a file, with a warning when other users can read it
+fn read_input_file(path: &str) -> Result<(Zeroizing<String>, Option<String>), String> {
+    let mut file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+    let metadata = file.metadata().map_err(|e| format!("Failed to read metadata of {}: {}", path, e))?;
+    let mut contents = Zeroizing::new(String::with_capacity(metadata.len() as usize));
+    file.read_to_string(&mut contents).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    Ok((contents, permissions_warning(path, &metadata)))
+}
+
+/// Reads a bearer token from a file, kept off the command line like keys are
+fn read_token_file(path: &str) -> Result<(Zeroizing<String>, Option<String>), String> {
+    let (contents, warning) = read_input_file(path)?;
+    let token = Zeroizing::new(contents.trim().to_string());
+    if token.is_empty() {
+        return Err(format!("{} holds no token", path));
+    }
+    Ok((token, warning))
+}
+
+#[cfg(unix)]
+fn permissions_warning(path: &str, metadata: &std::fs::Metadata) -> Option<String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = metadata.permissions().mode();
+    (mode & 0o004 != 0).then(|| {
+        format!("Warning: {} is readable by all users (mode {:o}) and may hold keys; run chmod 600 on it", path, mode & 0o777)
+    })
+}
+
+#[cfg(not(unix))]
+fn permissions_warning(_path: &str, _metadata: &std::fs::Metadata) -> Option<String> {
+    None
+}
+
+// How many of the ways to give an input's key it uses
+fn key_sources(sign_input: &SignInput) -> usize {
+    [
+        sign_input.private_key_wif.is_some(),
+        sign_input.private_key_hex.is_some(),
+        sign_input.key_index.is_some(),
+        sign_input.derivation_path.is_some(),
+    ]
+    .into_iter()
+    .filter(|&given| given)
+    .count()
+}
+
+/// Asks for the key of each input that has none; 64 hex characters are taken as a raw key, anything else as a WIF
+fn prompt_keys(request: &mut SignTxRequest, mut prompt: impl FnMut(&str) -> io::Result<String>) -> Result<(), String> {
+    for (i, sign_input) in request.inputs.iter_mut().enumerate() {
+        if key_sources(sign_input) > 0 {
+            continue;
+        }
+        let answer = Zeroizing::new(
+            prompt(&format!("Private key for input {} ({}): ", i, sign_input.address))
+                .map_err(|e| format!("Failed to read the key for input {}: {}", i, e))?,
+        );
+        let key = Zeroizing::new(answer.trim().to_string());
+        if key.is_empty() {
+            return Err(format!("No key given for input {}", i));
+        }
+        if key.len() == 64 && key.bytes().all(|b| b.is_ascii_hexdigit()) {
+            sign_input.private_key_hex = Some(key);
+        } else {
+            sign_input.private_key_wif = Some(key);
+        }
+    }
+    Ok(())
+}
+
+fn print_usage(program: &str, network: BtcNetwork) {
+    eprintln!(
+        "Usage: {} [--network <mainnet|testnet|signet|regtest>] [--fetch-prevouts [--esplora-url <url>] [--esplora-token-file <path>]] [--dry-run] [--no-verify] [--collect] \
+         [--input-file <path>] [--prompt-keys] [--json] [--no-low-r] \
+         [--contribute] [--assemble] \
+         [--combine <tx1_hex> <tx2_hex>] [--fail-fast] \
+         [--emit-sighashes] [--apply-signatures <path>]",
+        program
+    );
+    eprintln!("  --network: network the keys and addresses belong to (default: ${} or mainnet)", NETWORK_ENV);
+    eprintln!("  --fetch-prevouts: check each input against its previous transaction, fetched from Esplora");
+    eprintln!("  --esplora-url: Esplora base URL for --fetch-prevouts (default: the network's from the environment)");
+    eprintln!("  --esplora-token-file: file holding the Esplora server's bearer token (default: $BTCX_ESPLORA_TOKEN)");
+    eprintln!("  --dry-run: print each input's sighash and the estimated vsize as JSON; keys are not needed");
+    eprintln!("  --no-verify: print the signed transaction without checking each input's signature first");
+    eprintln!("  --collect: merge and order the multisig signatures in partially signed copies, signing nothing");
+    eprintln!("  --combine: merge the scriptSigs and witnesses of two partially signed copies of a transaction");
+    eprintln!("  --contribute: sign every input with ALL|ANYONECANPAY; the signed transaction is a fragment for --assemble");
+    eprintln!("  --assemble: join the inputs of fragments that agree on version, lock time and outputs, signing nothing");
+    eprintln!("  --input-file: read the request from this file instead of stdin; keep it readable only by you");
+    eprintln!("  --prompt-keys: ask for the key of each input that has none, without echoing it");
+    eprintln!("  --no-low-r: sign with the plain RFC6979 nonce instead of grinding for a 32-byte R, to compare with other signers");
+    eprintln!("  --json: print the signed transaction with its txid, size, weight and fee as JSON, and errors as JSON on stderr");
+    eprintln!("  --emit-sighashes: print each input's outpoint, sighash and expected pubkey as JSON, for a signer elsewhere");
+    eprintln!("  --apply-signatures: put the signatures in this JSON file, made over the emitted sighashes, into the transaction");
+    eprintln!("  --fail-fast: stop a batch request at the first transaction that fails to sign");
+    eprintln!("Signing for {}. The request is read from stdin unless --input-file is given.", network);
+    eprintln!("Example JSON:");
+    eprintln!(
+        r#"{{"unsigned_tx_hex": "...", "inputs": [{{"private_key_wif": "5K...", "address": "1A1z..."}}, {{"private_key_wif": "K...", "address": "bc1q...", "amount": 50000}}]}}"#
+    );
+    eprintln!("Example batch JSON, printing a JSON array of results:");
+    eprintln!(
+        r#"{{"keys": ["K..."], "transactions": [{{"unsigned_tx_hex": "...", "inputs": [{{"key_index": 0, "address": "bc1q...", "amount": 50000}}]}}]}}"#
+    );
+    eprintln!("Example JSON with --collect:");
+    eprintln!(r#"{{"partial_tx_hexes": ["...", "..."], "inputs": [{{"redeem_script": "5221..."}}]}}"#);
+    eprintln!("Example JSON with --assemble:");
+    eprintln!(r#"{{"fragment_hexes": ["...", "..."]}}"#);
 }
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
 
-    // Determine if we should read from stdin or use command-line argument
-    let json_input = if args.len() > 1 {
-        args[1].clone()
-    } else {
-        let mut buffer = String::new();
-        io::stdin()
-            .read_to_string(&mut buffer)
-            .expect("Failed to read from stdin");
-        buffer
+    let mut network_flag: Option<&str> = None;
+    let mut collect = false;
+    let mut verify = true;
+    let mut dry_run = false;
+    let mut fetch = false;
+    let mut esplora_url: Option<&str> = None;
+    let mut esplora_token_file: Option<&str> = None;
+    let mut input_file: Option<&str> = None;
+    let mut prompt = false;
+    let mut json = false;
+    let mut low_r = true;
+    let mut contributing = false;
+    let mut assemble = false;
+    let mut combine: Option<(&String, &String)> = None;
+    let mut fail_fast = false;
+    let mut emit_sighashes = false;
+    let mut signatures_file: Option<&str> = None;
+    let mut rest = args.iter().skip(1);
+    while let Some(arg) = rest.next() {
+        match arg.as_str() {
+            "--network" => match rest.next() {
+                Some(value) => network_flag = Some(value),
+                None => {
+                    eprintln!("Error: --network requires a value");
+                    std::process::exit(1);
+                }
+            },
+            "--collect" => collect = true,
+            "--combine" => match (rest.next(), rest.next()) {
+                (Some(first), Some(second)) => combine = Some((first, second)),
+                _ => {
+                    eprintln!("Error: --combine requires two transaction hexes");
+                    std::process::exit(1);
+                }
+            },
+            "--no-verify" => verify = false,
+            "--dry-run" => dry_run = true,
+            "--fetch-prevouts" => fetch = true,
+            "--esplora-url" => match rest.next() {
+                Some(value) => esplora_url = Some(value),
+                None => {
+                    eprintln!("Error: --esplora-url requires a value");
+                    std::process::exit(1);
+                }
+            },
+            "--esplora-token-file" => match rest.next() {
+                Some(value) => esplora_token_file = Some(value),
+                None => {
+                    eprintln!("Error: --esplora-token-file requires a value");
+                    std::process::exit(1);
+                }
+            },
+            "--input-file" => match rest.next() {
+                Some(value) => input_file = Some(value),
+                None => {
+                    eprintln!("Error: --input-file requires a value");
+                    std::process::exit(1);
+                }
+            },
+            "--prompt-keys" => prompt = true,
+            "--json" => json = true,
+            "--no-low-r" => low_r = false,
+            "--contribute" => contributing = true,
+            "--assemble" => assemble = true,
+            "--fail-fast" => fail_fast = true,
+            "--emit-sighashes" => emit_sighashes = true,
+            "--apply-signatures" => match rest.next() {
+                Some(value) => signatures_file = Some(value),
+                None => {
+                    eprintln!("Error: --apply-signatures requires a file of signatures");
+                    std::process::exit(1);
+                }
+            },
+            "--help" | "-h" => {
+                print_usage(&args[0], resolve_network(None, std::env::var(NETWORK_ENV).ok()).unwrap_or_default());
+                return;
+            }
+            _ => {
+                // Keys in arguments end up in shell history and process listings
+                eprintln!("Error: unexpected argument; pass the request on stdin or with --input-file, not as an argument");
+                print_usage(&args[0], resolve_network(None, std::env::var(NETWORK_ENV).ok()).unwrap_or_default());
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let network = match resolve_network(network_flag, std::env::var(NETWORK_ENV).ok()) {
+        Ok(network) => network,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    // Signed transactions hold no keys, so these come as arguments
+    if let Some((first, second)) = combine {
+        match combine_transactions(first, second) {
+            Ok((tx_hex, status)) => {
+                for line in status {
+                    eprintln!("{}", line);
+                }
+                println!("{}", tx_hex);
+            }
+            Err(e) => fail(json, "combining transactions", &e),
+        }
+        return;
+    }
+
+    // Read the request from the file if given, otherwise from stdin
+    let json_input = match input_file {
+        Some(path) => match read_input_file(path) {
+            Ok((contents, warning)) => {
+                if let Some(warning) = warning {
+                    eprintln!("{}", warning);
+                }
+                contents
+            }
+            Err(e) => fail(json, "reading the request", &e),
+        },
+        None => {
+            let mut buffer = Zeroizing::new(String::new());
+            io::stdin()
+                .read_to_string(&mut buffer)
+                .expect("Failed to read from stdin");
+            buffer
+        }
     };
 
+    if collect {
+        let request: CollectRequest = match serde_json::from_str(&json_input) {
+            Ok(req) => req,
+            Err(e) if json => fail(json, "parsing JSON", &e.to_string()),
+            Err(e) => {
+                eprintln!("Error parsing JSON: {}", e);
+                print_usage(&args[0], network);
+                std::process::exit(1);
+            }
+        };
+        match collect_signatures(request) {
+            Ok((tx_hex, status)) => {
+                for line in status {
+                    eprintln!("{}", line);
+                }
+                println!("{}", tx_hex);
+            }
+            Err(e) => fail(json, "collecting signatures", &e),
+        }
+        return;
+    }
+
+    if assemble {
+        let request: AssembleRequest = match serde_json::from_str(&json_input) {
+            Ok(req) => req,
+            Err(e) if json => fail(json, "parsing JSON", &e.to_string()),
+            Err(e) => {
+                eprintln!("Error parsing JSON: {}", e);
+                print_usage(&args[0], network);
+                std::process::exit(1);
+            }
+        };
+        match assemble_fragments(request) {
+            Ok(tx) => println!("{}", serialize_hex(&tx)),
+            Err(e) => fail(json, "assembling fragments", &e),
+        }
+        return;
+    }
+
+    // A batch request holds several transactions, signed in turn
+    if matches!(serde_json::from_str(&json_input), Ok(RequestShape { transactions: Some(_) })) {
+        if dry_run || prompt || fetch || contributing {
+            fail(json, "reading the request", "--dry-run, --prompt-keys, --fetch-prevouts and --contribute take a single transaction");
+        }
+        let request: BatchRequest = match serde_json::from_str(&json_input) {
+            Ok(req) => req,
+            Err(e) => fail(json, "parsing JSON", &e.to_string()),
+        };
+        match sign_batch(request, network, verify, low_r, fail_fast) {
+            Ok(results) => {
+                println!("{}", serde_json::to_string_pretty(&results).expect("results serialize"));
+                // Still a failure for scripts checking only the exit status
+                if results.iter().any(|result| matches!(result, BatchResult::Failed { .. })) {
+                    std::process::exit(1);
+                }
+            }
+            Err(e) => fail(json, &format!("signing batch for {}", network), &e),
+        }
+        return;
+    }
+
     // Parse JSON input
-    let request: SignTxRequest = match serde_json::from_str(&json_input) {
+    let mut request: SignTxRequest = match serde_json::from_str(&json_input) {
         Ok(req) => req,
+        Err(e) if json => fail(json, "parsing JSON", &e.to_string()),
         Err(e) => {
             eprintln!("Error parsing JSON: {}", e);
-            eprintln!("Usage: {} [json_input]", args[0]);
-            eprintln!("Example JSON:");
-            eprintln!(r#"{{"unsigned_tx_hex": "...", "inputs": [{{"private_key_wif": "5K...", "address": "1A1z..."}}]}}"#);
+            print_usage(&args[0], network);
             std::process::exit(1);
         }
     };
 
-    // Use Bitcoin mainnet
-    let network = Network::Bitcoin;
+    if fetch {
+        let mut builder = BlockstreamClient::builder(network);
+        if let Some(url) = esplora_url {
+            builder = builder.base_url(url);
+        }
+        if let Some(path) = esplora_token_file {
+            match read_token_file(path) {
+                Ok((token, warning)) => {
+                    if let Some(warning) = warning {
+                        eprintln!("{}", warning);
+                    }
+                    builder = builder.bearer_token(token.as_str());
+                }
+                Err(e) => fail(json, "reading the Esplora token", &e),
+            }
+        }
+        let fetched = builder.build().map_err(|e| e.to_string()).and_then(|client| fetch_prevouts(&mut request, &client));
+        if let Err(e) = fetched {
+            fail(json, &format!("fetching previous transactions from {}", network), &e);
+        }
+    }
+
+    if contributing {
+        if let Err(e) = contribute(&mut request) {
+            fail(json, "preparing the contribution", &e);
+        }
+    }
+
+    if prompt && !dry_run {
+        if let Err(e) = prompt_keys(&mut request, |prompt| rpassword::prompt_password(prompt)) {
+            fail(json, "reading keys", &e);
+        }
+    }
+
+    // The keys are elsewhere: hand over the digests to sign, or take back the signatures
+    if emit_sighashes {
+        let plan = match plan_signing(&request, network, low_r) {
+            Ok(plan) => plan,
+            Err(e) => fail(json, &format!("planning transaction for {}", network), &e),
+        };
+        let errors: Vec<String> = plan
+            .inputs
+            .iter()
+            .filter_map(|input| input.error.as_ref().map(|error| format!("Input {} cannot be signed: {}", input.index, error)))
+            .collect();
+        if !errors.is_empty() {
+            fail_all(json, "emitting sighashes", &errors);
+        }
+        println!("{}", serde_json::to_string_pretty(&plan).expect("plan serializes"));
+        return;
+    }
+    if let Some(path) = signatures_file {
+        let signatures: Vec<DetachedSignature> = match std::fs::read_to_string(path) {
+            Ok(contents) => match serde_json::from_str(&contents) {
+                Ok(signatures) => signatures,
+                Err(e) => fail(json, &format!("parsing signatures in {}", path), &e.to_string()),
+            },
+            Err(e) => fail(json, &format!("reading signatures from {}", path), &e.to_string()),
+        };
+        match apply_signatures(&request, &signatures, network) {
+            Ok(tx) => println!("{}", serialize_hex(&tx)),
+            Err(errors) => fail_all(json, "applying signatures", &errors),
+        }
+        return;
+    }
+
+    if dry_run {
+        match plan_signing(&request, network, low_r) {
+            Ok(plan) => println!("{}", serde_json::to_string_pretty(&plan).expect("plan serializes")),
+            Err(e) => fail(json, &format!("planning transaction for {}", network), &e),
+        }
+        return;
+    }
 
     // Sign the transaction
-    match sign_transaction(request, network) {
+    if json {
+        match sign_request(request, &[], network, verify, low_r) {
+            Ok((tx, signers)) => {
+                let signed = signed_output(&tx, &signers);
+                println!("{}", serde_json::to_string_pretty(&signed).expect("output serializes"));
+            }
+            Err(e) => fail(json, &format!("signing transaction for {}", network), &e),
+        }
+        return;
+    }
+    match sign_transaction(request, network, verify, low_r) {
         Ok(signed_tx_hex) => {
             println!("{}", signed_tx_hex);
         }
         Err(e) => {
-            eprintln!("Error signing transaction: {}", e);
+            eprintln!("Error signing transaction for {}: {}", network, e);
             std::process::exit(1);
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::bip32::Xpriv;
+    use bitcoin::secp256k1::Message;
+    use bitcoin::{absolute, transaction, OutPoint, Sequence, TxIn, Txid};
+
+    fn unsigned_tx_hex() -> String {
+        let txid = Txid::from_str("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16").unwrap();
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn { previous_output: OutPoint { txid, vout: 0 }, sequence: Sequence::MAX, ..Default::default() }],
+            output: vec![TxOut { value: Amount::from_sat(90_000), script_pubkey: ScriptBuf::new_op_return([]) }],
+        };
+        serialize_hex(&tx)
+    }
+
+    // A WIF and the P2PKH address of the same key, on `network`
+    fn key_and_address(network: Network) -> (String, String) {
+        let private_key = PrivateKey::new(SecretKey::from_slice(&[7; 32]).unwrap(), network);
+        let address = Address::p2pkh(private_key.public_key(&Secp256k1::new()), network);
+        (private_key.to_wif(), address.to_string())
+    }
+
+    fn input(private_key_wif: String, address: String, amount: Option<u64>) -> SignInput {
+        let private_key_wif = Some(private_key_wif.into());
+        SignInput {
+            private_key_wif,
+            private_key_hex: None,
+            derivation_path: None,
+            compressed: None,
+            address,
+            amount,
+            sighash: None,
+            prev_tx_hex: None,
+            redeem_script: None,
+            key_index: None,
+            pubkey: None,
+        }
+    }
+
+    fn request(wif: String, address: String) -> SignTxRequest {
+        SignTxRequest {
+            unsigned_tx_hex: unsigned_tx_hex(),
+            xprv: None,
+            inputs: vec![input(wif, address, None)],
+        }
+    }
+
+    #[test]
+    fn test_signs_on_regtest() {
+        let (wif, address) = key_and_address(Network::Regtest);
+        let signed = sign_transaction(request(wif, address), BtcNetwork::Regtest, true, true).unwrap();
+        let tx = parse_transaction_hex(&signed).unwrap();
+        assert!(!tx.input[0].script_sig.is_empty());
+
+        // Signet shares testnet's WIF version byte and address encoding
+        let (wif, address) = key_and_address(Network::Signet);
+        assert!(sign_transaction(request(wif, address), BtcNetwork::Testnet, true, true).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_other_network_keys() {
+        let (wif, _) = key_and_address(Network::Bitcoin);
+        let (_, address) = key_and_address(Network::Regtest);
+        let error = sign_transaction(request(wif.clone(), address), BtcNetwork::Regtest, true, true).unwrap_err();
+        assert_eq!(error, "Private key for input 0 is a mainnet key, but signing for regtest; pass --network to change it");
+        assert!(!error.contains(&wif));
+
+        let (wif, _) = key_and_address(Network::Regtest);
+        let (_, address) = key_and_address(Network::Bitcoin);
+        let error = sign_transaction(request(wif, address), BtcNetwork::Regtest, true, true).unwrap_err();
+        assert!(error.contains("is not a regtest address"), "{}", error);
+    }
+
+    // Native P2WPKH example from BIP143
+    const BIP143_UNSIGNED: &str = "0100000002fff7f7881a8099afa6940d42d1e7f6362bec38171ea3edf433541db4e4ad969f0000000000eeffffffef51e1b804cc89d182d279655c3aa89e815b1b309fe287d9b2b55d57b90ec68a0100000000ffffffff02202cb206000000001976a9148280b37df378db99f66f85c95a783a76ac7a6d5988ac9093510d000000001976a9143bde42dbee7e4dbe6a21b2d50ce2f0167faa815988ac11000000";
+    const BIP143_SEGWIT_KEY: &str = "619c335025c7f4012e556c2a58b2506e30b8511b53ade95ea316fd8c3286feb9";
+    const BIP143_WITNESS: [&str; 2] = [
+        "304402203609e17b84f6a7d30c80bfa610b5b4542f32a8a0d5447a12fb1366d7f01cc44a0220573a954c4518331561406f90300e8f3358f51928d43c212a8caed02de67eebee01",
+        "025476c2e83188368da1ff3e292e7acafcdb3566bb0ad253f62fc70f07aeee6357",
+    ];
+
+    #[test]
+    fn test_signs_p2wpkh_like_bip143() {
+        // The vector's first input is P2PK; a P2PKH key stands in for it, which leaves the
+        // BIP143 sighash of the second input unchanged
+        let (legacy_wif, legacy_address) = key_and_address(Network::Bitcoin);
+        let segwit_key = PrivateKey::new(SecretKey::from_str(BIP143_SEGWIT_KEY).unwrap(), Network::Bitcoin);
+        let segwit_address = Address::p2wpkh(&segwit_key.public_key(&Secp256k1::new()).try_into().unwrap(), Network::Bitcoin);
+        assert_eq!(segwit_address.script_pubkey().to_hex_string(), "00141d0f172a0ecb48aee1be1f2687d2963ae33f71a1");
+        let request = SignTxRequest {
+            unsigned_tx_hex: BIP143_UNSIGNED.to_string(),
+            xprv: None,
+            inputs: vec![
+                input(legacy_wif, legacy_address, None),
+                input(segwit_key.to_wif(), segwit_address.to_string(), Some(600_000_000)),
+            ],
+        };
+
+        let tx = parse_transaction_hex(&sign_transaction(request, BtcNetwork::Bitcoin, true, true).unwrap()).unwrap();
+        assert!(!tx.input[0].script_sig.is_empty() && tx.input[0].witness.is_empty());
+        assert!(tx.input[1].script_sig.is_empty());
+        let witness: Vec<String> = tx.input[1].witness.iter().map(|item| item.to_lower_hex_string()).collect();
+        assert_eq!(witness, BIP143_WITNESS);
+    }
+
+    #[test]
+    fn test_signs_mixed_p2pkh_and_p2wpkh_inputs() {
+        let secp = Secp256k1::new();
+        let (legacy_wif, legacy_address) = key_and_address(Network::Regtest);
+        let segwit_key = PrivateKey::new(SecretKey::from_slice(&[8; 32]).unwrap(), Network::Regtest);
+        let segwit_pubkey = segwit_key.public_key(&secp).try_into().unwrap();
+        let segwit_address = Address::p2wpkh(&segwit_pubkey, Network::Regtest);
+        let mut tx = parse_transaction_hex(&unsigned_tx_hex()).unwrap();
+        tx.input.push(TxIn { previous_output: OutPoint { vout: 1, ..tx.input[0].previous_output }, ..tx.input[0].clone() });
+        let segwit = |amount| input(segwit_key.to_wif(), segwit_address.to_string(), amount);
+        let mixed = |amount| SignTxRequest {
+            unsigned_tx_hex: serialize_hex(&tx),
+            xprv: None,
+            inputs: vec![input(legacy_wif.clone(), legacy_address.clone(), None), segwit(amount)],
+        };
+
+        let signed = parse_transaction_hex(&sign_transaction(mixed(Some(100_000)), BtcNetwork::Regtest, true, true).unwrap()).unwrap();
+        assert!(!signed.input[0].script_sig.is_empty() && signed.input[0].witness.is_empty());
+        assert!(signed.input[1].script_sig.is_empty());
+
+        // The witness signature commits to the amount
+        let sighash = SighashCache::new(&tx)
+            .p2wpkh_signature_hash(1, &segwit_address.script_pubkey(), Amount::from_sat(100_000), EcdsaSighashType::All)
+            .unwrap();
+        let signature = bitcoin::ecdsa::Signature::from_slice(signed.input[1].witness.nth(0).unwrap()).unwrap();
+        let message = Message::from_digest(sighash.to_byte_array());
+        assert!(secp.verify_ecdsa(&message, &signature.signature, &segwit_pubkey.0).is_ok());
+        assert_eq!(signed.input[1].witness.nth(1).unwrap(), segwit_pubkey.to_bytes());
+
+        let error = sign_transaction(mixed(None), BtcNetwork::Regtest, true, true).unwrap_err();
+        assert!(error.contains("amount it spends is required"), "{}", error);
+    }
+
+    // The checks above use the library's own verifier; libbitcoinconsensus runs the scripts as a
+    // node would, against the outputs of a real funding transaction
+    #[test]
+    #[cfg(feature = "bitcoinconsensus")]
+    fn test_mixed_inputs_pass_libbitcoinconsensus() {
+        let (legacy_wif, legacy_address) = key_and_address(Network::Regtest);
+        let segwit_key = PrivateKey::new(SecretKey::from_slice(&[8; 32]).unwrap(), Network::Regtest);
+        let segwit_address = Address::p2wpkh(&segwit_key.public_key(&Secp256k1::new()).try_into().unwrap(), Network::Regtest);
+        let legacy_script = Address::from_str(&legacy_address).unwrap().assume_checked().script_pubkey();
+
+        let mut funding = parse_transaction_hex(&unsigned_tx_hex()).unwrap();
+        funding.output = vec![
+            TxOut { value: Amount::from_sat(50_000), script_pubkey: legacy_script.clone() },
+            TxOut { value: Amount::from_sat(100_000), script_pubkey: segwit_address.script_pubkey() },
+        ];
+        let funding_txid = funding.compute_txid();
+        let mut spend = funding.clone();
+        spend.input = (0..2)
+            .map(|vout| TxIn { previous_output: OutPoint { txid: funding_txid, vout }, sequence: Sequence::MAX, ..Default::default() })
+            .collect();
+        spend.output = vec![TxOut { value: Amount::from_sat(140_000), script_pubkey: legacy_script }];
+
+        let with_prev = |input: SignInput| SignInput { prev_tx_hex: Some(serialize_hex(&funding)), ..input };
+        let request = SignTxRequest {
+            unsigned_tx_hex: serialize_hex(&spend),
+            xprv: None,
+            inputs: vec![
+                with_prev(input(legacy_wif, legacy_address, None)),
+                with_prev(input(segwit_key.to_wif(), segwit_address.to_string(), None)),
+            ],
+        };
+        // Signing verifies with libbitcoinconsensus too when the feature is on
+        let (signed, _) = sign_request(request, &[], BtcNetwork::Regtest, true, true).unwrap();
+
+        let spent: Vec<SpentOutput> = funding
+            .output
+            .iter()
+            .map(|output| SpentOutput { script_pubkey: output.script_pubkey.clone(), amount: Some(output.value) })
+            .collect();
+        verify_with_consensus(&signed, &spent).unwrap();
+
+        // The witness signature commits to the amount, and both to the outputs
+        let wrong_amount = [spent[0].clone(), SpentOutput { amount: Some(Amount::from_sat(99_999)), ..spent[1].clone() }];
+        let error = verify_with_consensus(&signed, &wrong_amount).unwrap_err();
+        assert!(error.starts_with("Input 1 failed consensus verification"), "{}", error);
+        let mut tampered = signed.clone();
+        tampered.output[0].value = Amount::from_sat(139_000);
+        let error = verify_with_consensus(&tampered, &spent).unwrap_err();
+        assert!(error.starts_with("Input 0 failed consensus verification"), "{}", error);
+    }
+
+    #[test]
+    fn test_json_output() {
+        let (legacy_wif, legacy_address) = key_and_address(Network::Regtest);
+        let segwit_key = PrivateKey::new(SecretKey::from_slice(&[8; 32]).unwrap(), Network::Regtest);
+        let segwit_address = Address::p2wpkh(&segwit_key.public_key(&Secp256k1::new()).try_into().unwrap(), Network::Regtest);
+        let mut tx = parse_transaction_hex(&unsigned_tx_hex()).unwrap();
+        tx.input.push(TxIn { previous_output: OutPoint { vout: 1, ..tx.input[0].previous_output }, ..tx.input[0].clone() });
+        let request = |legacy_amount| SignTxRequest {
+            unsigned_tx_hex: serialize_hex(&tx),
+            xprv: None,
+            inputs: vec![
+                input(legacy_wif.clone(), legacy_address.clone(), legacy_amount),
+                SignInput {
+                    sighash: Some("NONE|ANYONECANPAY".to_string()),
+                    ..input(segwit_key.to_wif(), segwit_address.to_string(), Some(100_000))
+                },
+            ],
+        };
+
+        let (signed, signers) = sign_request(request(Some(50_000)), &[], BtcNetwork::Regtest, true, true).unwrap();
+        let output = serde_json::to_value(signed_output(&signed, &signers)).unwrap();
+        let keys: Vec<&str> = output.as_object().unwrap().keys().map(String::as_str).collect();
+        assert_eq!(keys, ["fee", "fee_rate", "inputs", "signed_tx_hex", "size", "txid", "vsize", "weight"]);
+        assert_eq!(output["signed_tx_hex"], serialize_hex(&signed));
+        assert_eq!(output["txid"], signed.compute_txid().to_string());
+        assert_eq!(output["size"], signed.total_size());
+        assert_eq!(output["vsize"], signed.vsize());
+        assert_eq!(output["weight"], signed.weight().to_wu());
+        // 50,000 + 100,000 in, 90,000 out
+        assert_eq!(output["fee"], 60_000);
+        let fee_rate = output["fee_rate"].as_f64().unwrap();
+        assert!((fee_rate - 60_000.0 / signed.vsize() as f64).abs() < 0.01, "{}", fee_rate);
+        assert_eq!(
+            output["inputs"],
+            serde_json::json!([
+                {"index": 0, "type": "p2pkh", "sighash": "SIGHASH_ALL"},
+                {"index": 1, "type": "p2wpkh", "sighash": "SIGHASH_NONE|SIGHASH_ANYONECANPAY"},
+            ])
+        );
+
+        // Without the legacy input's amount the fee is unknown
+        let (signed, signers) = sign_request(request(None), &[], BtcNetwork::Regtest, true, true).unwrap();
+        let output = serde_json::to_value(signed_output(&signed, &signers)).unwrap();
+        assert!(output["fee"].is_null() && output["fee_rate"].is_null());
+    }
+
+    #[test]
+    fn test_error_input_index() {
+        let (wif, address) = key_and_address(Network::Regtest);
+        let error = sign_transaction(request(wif, address), BtcNetwork::Bitcoin, true, true).unwrap_err();
+        assert_eq!(input_index(&error), Some(0));
+        assert_eq!(input_index("Input 1 is taproot, which needs the amount of every input; input 0 has none"), Some(1));
+        assert_eq!(input_index("Invalid sighash for input 12: unknown"), Some(12));
+        assert_eq!(input_index("Input count mismatch: transaction has 2 inputs, but 1 signing inputs provided"), None);
+    }
+
+    #[test]
+    fn test_signs_p2tr_key_path() {
+        let secp = Secp256k1::new();
+        let private_key = PrivateKey::new(SecretKey::from_slice(&[9; 32]).unwrap(), Network::Regtest);
+        let (internal_key, _) = private_key.inner.x_only_public_key(&secp);
+        let address = Address::p2tr(&secp, internal_key, None, Network::Regtest);
+        let taproot = |amount| SignTxRequest {
+            unsigned_tx_hex: unsigned_tx_hex(),
+            xprv: None,
+            inputs: vec![input(private_key.to_wif(), address.to_string(), amount)],
+        };
+
+        let tx = parse_transaction_hex(&sign_transaction(taproot(Some(100_000)), BtcNetwork::Regtest, true, true).unwrap()).unwrap();
+        assert!(tx.input[0].script_sig.is_empty());
+        assert_eq!(tx.input[0].witness.len(), 1);
+        assert_eq!(tx.input[0].witness.nth(0).unwrap().len(), 64);
+
+        let error = sign_transaction(taproot(None), BtcNetwork::Regtest, true, true).unwrap_err();
+        assert!(error.contains("needs the amount of every input"), "{}", error);
+    }
+
+    #[test]
+    fn test_sighash_per_input() {
+        let (wif, address) = key_and_address(Network::Regtest);
+        let mut single = request(wif.clone(), address.clone());
+        single.inputs[0].sighash = Some("single|anyonecanpay".to_string());
+        let tx = parse_transaction_hex(&sign_transaction(single, BtcNetwork::Regtest, true, true).unwrap()).unwrap();
+        let script_sig = tx.input[0].script_sig.as_bytes();
+        // <len> <DER signature + sighash byte> <len> <pubkey>
+        assert_eq!(script_sig[usize::from(script_sig[0])], 0x83);
+
+        let mut unknown = request(wif, address);
+        unknown.inputs[0].sighash = Some("EVERYTHING".to_string());
+        let error = sign_transaction(unknown, BtcNetwork::Regtest, true, true).unwrap_err();
+        assert!(error.starts_with("Invalid sighash for input 0: "), "{}", error);
+    }
+
+    #[test]
+    fn test_signs_multisig_on_two_machines() {
+        let secp = Secp256k1::new();
+        let keys: Vec<PrivateKey> =
+            (1..=3).map(|byte| PrivateKey::new(SecretKey::from_slice(&[byte; 32]).unwrap(), Network::Regtest)).collect();
+        let script = MultisigScript { required: 2, pubkeys: keys.iter().map(|key| key.public_key(&secp)).collect() };
+        let redeem_script = script.to_script();
+        let address = Address::p2sh(&redeem_script, Network::Regtest).unwrap();
+        let sign = |tx_hex: &str, key: &PrivateKey| {
+            let mut multisig = input(key.to_wif(), address.to_string(), None);
+            multisig.redeem_script = Some(redeem_script.to_hex_string());
+            let request = SignTxRequest { unsigned_tx_hex: tx_hex.to_string(), xprv: None, inputs: vec![multisig] };
+            sign_transaction(request, BtcNetwork::Regtest, true, true)
+        };
+
+        // One key per machine, the partially signed transaction passed between them
+        let partial = sign(&unsigned_tx_hex(), &keys[0]).unwrap();
+        let signed = parse_transaction_hex(&sign(&partial, &keys[2]).unwrap()).unwrap();
+        let pushes: Vec<Vec<u8>> = signed.input[0]
+            .script_sig
+            .instructions()
+            .map(|instruction| instruction.unwrap().push_bytes().unwrap().as_bytes().to_vec())
+            .collect();
+        assert_eq!(pushes.len(), 4);
+        assert!(pushes[0].is_empty());
+        assert_eq!(pushes[3], redeem_script.to_bytes());
+
+        // Each signature is valid for the key in its place
+        let sighash = SighashCache::new(&parse_transaction_hex(&unsigned_tx_hex()).unwrap())
+            .legacy_signature_hash(0, &redeem_script, 0x01)
+            .unwrap();
+        let message = Message::from_digest(sighash.to_byte_array());
+        for (signature, key) in pushes[1..3].iter().zip([&keys[0], &keys[2]]) {
+            let signature = bitcoin::ecdsa::Signature::from_slice(signature).unwrap();
+            assert!(secp.verify_ecdsa(&message, &signature.signature, &key.public_key(&secp).inner).is_ok());
+        }
+
+        // Signed in parallel instead, then collected
+        let collect = |partial_tx_hexes: Vec<String>| {
+            collect_signatures(CollectRequest {
+                partial_tx_hexes,
+                inputs: vec![CollectInput { redeem_script: Some(redeem_script.to_hex_string()) }],
+            })
+        };
+        let (collected, status) = collect(vec![sign(&unsigned_tx_hex(), &keys[2]).unwrap(), partial.clone()]).unwrap();
+        assert_eq!(parse_transaction_hex(&collected).unwrap(), signed);
+        assert_eq!(status, ["Input 0: 2 of 2 required signatures, complete"]);
+        let (_, status) = collect(vec![partial]).unwrap();
+        assert_eq!(status, ["Input 0: 1 of 2 required signatures"]);
+
+        let error = sign(&serialize_hex(&signed), &keys[1]).unwrap_err();
+        assert!(error.contains("already has the 2 signatures"), "{}", error);
+        let error = collect(vec![unsigned_tx_hex(), BIP143_UNSIGNED.to_string()]).unwrap_err();
+        assert_eq!(error, "Transaction 1 is not a copy of transaction 0");
+    }
+
+    #[test]
+    fn test_combines_partially_signed_copies() {
+        let secp = Secp256k1::new();
+        let keys: Vec<PrivateKey> =
+            (1..=3).map(|byte| PrivateKey::new(SecretKey::from_slice(&[byte; 32]).unwrap(), Network::Regtest)).collect();
+        let redeem_script = MultisigScript { required: 2, pubkeys: keys.iter().map(|key| key.public_key(&secp)).collect() }.to_script();
+        let address = Address::p2sh(&redeem_script, Network::Regtest).unwrap();
+        let (legacy_wif, legacy_address) = key_and_address(Network::Regtest);
+        let mut tx = parse_transaction_hex(&unsigned_tx_hex()).unwrap();
+        tx.input.push(TxIn { previous_output: OutPoint { vout: 1, ..tx.input[0].previous_output }, ..tx.input[0].clone() });
+        // The multisig input with one key, and the P2PKH input
+        let sign = |key: &PrivateKey, legacy_sighash: &str| {
+            let mut multisig = input(key.to_wif(), address.to_string(), None);
+            multisig.redeem_script = Some(redeem_script.to_hex_string());
+            let legacy = SignInput { sighash: Some(legacy_sighash.to_string()), ..input(legacy_wif.clone(), legacy_address.clone(), None) };
+            let request = SignTxRequest { unsigned_tx_hex: serialize_hex(&tx), xprv: None, inputs: vec![multisig, legacy] };
+            parse_transaction_hex(&sign_transaction(request, BtcNetwork::Regtest, true, true).unwrap()).unwrap()
+        };
+
+        // Complementary halves: one copy has the P2PKH signature, each a multisig signature
+        let first = sign(&keys[0], "ALL");
+        let mut second = sign(&keys[2], "ALL");
+        second.input[1].script_sig = ScriptBuf::new();
+        let (combined, status) = combine_transactions(&serialize_hex(&first), &serialize_hex(&second)).unwrap();
+        let combined = parse_transaction_hex(&combined).unwrap();
+        assert_eq!(status, ["Input 0: 2 of 2 required signatures, complete", "Input 1: signed"]);
+        assert_eq!(combined.input[1], first.input[1]);
+        let spent = [
+            SpentOutput { script_pubkey: address.script_pubkey(), amount: None },
+            SpentOutput { script_pubkey: Address::from_str(&legacy_address).unwrap().assume_checked().script_pubkey(), amount: None },
+        ];
+        signing::verify_transaction(&combined, &spent).unwrap();
+        // The same the other way round
+        let (reversed, _) = combine_transactions(&serialize_hex(&second), &serialize_hex(&first)).unwrap();
+        assert_eq!(parse_transaction_hex(&reversed).unwrap(), combined);
+
+        let (_, status) = combine_transactions(&serialize_hex(&second), &serialize_hex(&tx)).unwrap();
+        assert_eq!(status, ["Input 0: 1 of 2 required signatures", "Input 1: unsigned"]);
+
+        // Different single signatures for one input cannot both be kept
+        let error = combine_transactions(&serialize_hex(&first), &serialize_hex(&sign(&keys[2], "NONE"))).unwrap_err();
+        assert_eq!(error, "Input 1 is signed differently in the two transactions");
+
+        let mut other = second.clone();
+        other.output[0].value = Amount::from_sat(80_000);
+        let error = combine_transactions(&serialize_hex(&first), &serialize_hex(&other)).unwrap_err();
+        assert!(error.starts_with("Transaction 2 is not a copy of transaction 1: their unsigned txids are"), "{}", error);
+    }
+
+    // BIP32 test vector 1 master key, testnet encoding
+    const TPRV: &str =
+        "tprv8ZgxMBicQKsPeDgjzdC36fs6bMjGApWDNLR9erAXMs5skhMv36j9MV5ecvfavji5khqjWaWSFhN3YcCUUdiKH6isR4Pwy3U5y5egddBr16m";
+
+    #[test]
+    fn test_signs_with_xprv_derivation() {
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(Network::Regtest, &[0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]).unwrap();
+        assert_eq!(master.to_string(), TPRV);
+        let derive = |path: &str| master.derive_priv(&secp, &DerivationPath::from_str(path).unwrap()).unwrap().to_priv();
+        let compressed = |path| CompressedPublicKey::from_private_key(&secp, &derive(path)).unwrap();
+        let segwit_address = Address::p2wpkh(&compressed("m/84'/1'/0'/0/5"), Network::Regtest);
+        let legacy_address = Address::p2pkh(compressed("m/44'/1'/0'/1/0"), Network::Regtest);
+        let derived = |path: &str, address: &Address, amount| SignInput {
+            private_key_wif: None,
+            derivation_path: Some(path.to_string()),
+            ..input(String::new(), address.to_string(), amount)
+        };
+
+        // Two derived inputs and one WIF input in the same request
+        let (wif, wif_address) = key_and_address(Network::Regtest);
+        let mut tx = parse_transaction_hex(&unsigned_tx_hex()).unwrap();
+        for vout in 1..3 {
+            tx.input.push(TxIn { previous_output: OutPoint { vout, ..tx.input[0].previous_output }, ..tx.input[0].clone() });
+        }
+        let request = |xprv: Option<&str>, segwit_path: &str| SignTxRequest {
+            unsigned_tx_hex: serialize_hex(&tx),
+            xprv: xprv.map(|xprv| xprv.to_string().into()),
+            inputs: vec![
+                derived(segwit_path, &segwit_address, Some(50_000)),
+                input(wif.clone(), wif_address.clone(), None),
+                derived("m/44'/1'/0'/1/0", &legacy_address, None),
+            ],
+        };
+        let signed = sign_transaction(request(Some(TPRV), "m/84'/1'/0'/0/5"), BtcNetwork::Regtest, true, true).unwrap();
+        let signed = parse_transaction_hex(&signed).unwrap();
+        assert_eq!(signed.input[0].witness.nth(1).unwrap(), compressed("m/84'/1'/0'/0/5").to_bytes());
+        assert!(!signed.input[1].script_sig.is_empty() && !signed.input[2].script_sig.is_empty());
+
+        // An account xprv takes the same full paths
+        let account = master.derive_priv(&secp, &DerivationPath::from_str("m/84'/1'/0'").unwrap()).unwrap();
+        let mut account_request = request(Some(&account.to_string()), "m/84'/1'/0'/0/5");
+        account_request.inputs.truncate(2);
+        account_request.inputs.push(input(derive("m/44'/1'/0'/1/0").to_wif(), legacy_address.to_string(), None));
+        assert_eq!(sign_transaction(account_request, BtcNetwork::Regtest, true, true).unwrap(), serialize_hex(&signed));
+
+        // The derived key must control the output, and shows what it hashes to
+        let error = sign_transaction(request(Some(TPRV), "m/84'/1'/0'/0/6"), BtcNetwork::Regtest, true, true).unwrap_err();
+        let derived_hash = compressed("m/84'/1'/0'/0/6").wpubkey_hash().to_string();
+        let expected_hash = compressed("m/84'/1'/0'/0/5").wpubkey_hash().to_string();
+        assert_eq!(
+            error,
+            format!(
+                "Key derived at 84'/1'/0'/0/6 for input 0 does not control the p2wpkh output it spends: derived {}, expected {}",
+                derived_hash, expected_hash
+            )
+        );
+        assert!(!error.contains(TPRV));
+
+        let error = sign_transaction(request(None, "m/84'/1'/0'/0/5"), BtcNetwork::Regtest, true, true).unwrap_err();
+        assert_eq!(error, "Input 0 has a derivation_path, but the request has no xprv");
+        let error = sign_transaction(request(Some(TPRV), "m/84'/1'/0'/0/5"), BtcNetwork::Bitcoin, true, true).unwrap_err();
+        assert!(error.contains("pass --network") && !error.contains(TPRV), "{}", error);
+    }
+
+    #[test]
+    fn test_dry_run_sighashes() {
+        let (_, legacy_address) = key_and_address(Network::Bitcoin);
+        let segwit_key = PrivateKey::new(SecretKey::from_str(BIP143_SEGWIT_KEY).unwrap(), Network::Bitcoin);
+        let segwit_address = Address::p2wpkh(&segwit_key.public_key(&Secp256k1::new()).try_into().unwrap(), Network::Bitcoin);
+        // No keys at all
+        let keyless = |address: &Address, amount| SignInput { private_key_wif: None, ..input(String::new(), address.to_string(), amount) };
+        let legacy_address = Address::from_str(&legacy_address).unwrap().assume_checked();
+        let mut request = SignTxRequest {
+            unsigned_tx_hex: BIP143_UNSIGNED.to_string(),
+            xprv: None,
+            inputs: vec![keyless(&legacy_address, None), keyless(&segwit_address, Some(600_000_000))],
+        };
+        let plan = serde_json::to_value(plan_signing(&request, BtcNetwork::Bitcoin, true).unwrap()).unwrap();
+
+        // Legacy sighash by hand: the spent script in place of this input's scriptSig, the others
+        // emptied, then the sighash type as four bytes, double SHA256
+        let mut stripped = parse_transaction_hex(BIP143_UNSIGNED).unwrap();
+        stripped.input[0].script_sig = legacy_address.script_pubkey();
+        let mut preimage = bitcoin::consensus::serialize(&stripped);
+        preimage.extend(1u32.to_le_bytes());
+        let legacy_sighash = bitcoin::hashes::sha256d::Hash::hash(&preimage).to_byte_array().to_lower_hex_string();
+        // Native P2WPKH sighash given in BIP143
+        let segwit_sighash = "c37af31116d1b27caf68aae9e3ac82f1477929014d5b917657d0eb49478cb670";
+
+        assert_eq!(plan["inputs"][0]["script_type"], "p2pkh");
+        assert_eq!(plan["inputs"][0]["sighash_type"], "SIGHASH_ALL");
+        assert_eq!(plan["inputs"][0]["sighash"], legacy_sighash.as_str());
+        assert_eq!(plan["inputs"][0]["script_pubkey"], legacy_address.script_pubkey().to_hex_string());
+        assert_eq!(plan["inputs"][1]["script_type"], "p2wpkh");
+        assert_eq!(plan["inputs"][1]["sighash"], segwit_sighash);
+        // A byte less per signature than the largest, which signers that do not grind can reach
+        assert_eq!(plan["estimated_vsize"], 10 + 147 + 68 + 2 * 34 + 1);
+        let plain = plan_signing(&request, BtcNetwork::Bitcoin, false).unwrap();
+        assert_eq!(plain.estimated_vsize, Some(10 + 148 + 68 + 2 * 34 + 1));
+
+        // The ground signatures make the signed transaction the estimated size
+        let wif = |address: &Address, amount, key: PrivateKey| SignInput {
+            private_key_wif: Some(key.to_wif().into()),
+            ..keyless(address, amount)
+        };
+        let (legacy_wif, _) = key_and_address(Network::Bitcoin);
+        let signing = SignTxRequest {
+            unsigned_tx_hex: BIP143_UNSIGNED.to_string(),
+            xprv: None,
+            inputs: vec![
+                wif(&legacy_address, None, PrivateKey::from_wif(&legacy_wif).unwrap()),
+                wif(&segwit_address, Some(600_000_000), segwit_key),
+            ],
+        };
+        let signed = parse_transaction_hex(&sign_transaction(signing, BtcNetwork::Bitcoin, true, true).unwrap()).unwrap();
+        assert_eq!(signed.vsize() as u64, plan["estimated_vsize"].as_u64().unwrap());
+
+        // An input it cannot classify is reported, and the others still planned
+        let multisig = ScriptBuf::new_op_return([]).to_p2sh();
+        request.inputs[0] = keyless(&Address::from_script(&multisig, Network::Bitcoin).unwrap(), None);
+        request.inputs[1].amount = None;
+        let plan = serde_json::to_value(plan_signing(&request, BtcNetwork::Bitcoin, true).unwrap()).unwrap();
+        assert!(plan["inputs"][0]["error"].as_str().unwrap().contains("without a redeem_script"));
+        assert!(plan["inputs"][0].get("sighash").is_none());
+        assert!(plan["inputs"][1]["error"].as_str().unwrap().contains("amount it spends is required"));
+        assert_eq!(plan["estimated_vsize"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_checks_prevouts() {
+        let secp = Secp256k1::new();
+        let (legacy_wif, legacy_address) = key_and_address(Network::Regtest);
+        let segwit_key = PrivateKey::new(SecretKey::from_slice(&[8; 32]).unwrap(), Network::Regtest);
+        let segwit_address = Address::p2wpkh(&segwit_key.public_key(&secp).try_into().unwrap(), Network::Regtest);
+        let legacy_script = Address::from_str(&legacy_address).unwrap().assume_checked().script_pubkey();
+        let mut prev_tx = parse_transaction_hex(&unsigned_tx_hex()).unwrap();
+        prev_tx.output = vec![
+            TxOut { value: Amount::from_sat(100_000), script_pubkey: legacy_script },
+            TxOut { value: Amount::from_sat(50_000), script_pubkey: segwit_address.script_pubkey() },
+        ];
+        let spending = |vout| {
+            let mut tx = parse_transaction_hex(&unsigned_tx_hex()).unwrap();
+            tx.input[0].previous_output = OutPoint { txid: prev_tx.compute_txid(), vout };
+            serialize_hex(&tx)
+        };
+        let request = |vout, wif: &str, address: &str, prev_tx: &Transaction| SignTxRequest {
+            unsigned_tx_hex: spending(vout),
+            xprv: None,
+            inputs: vec![SignInput { prev_tx_hex: Some(serialize_hex(prev_tx)), ..input(wif.to_string(), address.to_string(), None) }],
+        };
+
+        // The segwit amount comes from the previous transaction
+        let segwit = request(1, &segwit_key.to_wif(), &segwit_address.to_string(), &prev_tx);
+        let signed = parse_transaction_hex(&sign_transaction(segwit, BtcNetwork::Regtest, true, true).unwrap()).unwrap();
+        assert_eq!(signed.input[0].witness.len(), 2);
+        let keyless = request(1, "", &segwit_address.to_string(), &prev_tx);
+        let plan = serde_json::to_value(plan_signing(&keyless, BtcNetwork::Regtest, true).unwrap()).unwrap();
+        assert_eq!(plan["inputs"][0]["script_type"], "p2wpkh");
+
+        let mut other_tx = prev_tx.clone();
+        other_tx.lock_time = absolute::LockTime::from_consensus(1);
+        let mut wrong_amount = request(1, &segwit_key.to_wif(), &segwit_address.to_string(), &prev_tx);
+        wrong_amount.inputs[0].amount = Some(60_000);
+        let cases = [
+            (request(0, &segwit_key.to_wif(), &segwit_address.to_string(), &prev_tx), "Address for input 0 gives script 0014"),
+            (request(2, &legacy_wif, &legacy_address, &prev_tx), "spends output 2 of"),
+            (request(0, &legacy_wif, &legacy_address, &other_tx), "but the input spends"),
+            (wrong_amount, "claims an amount of 60000 sat, but the output it spends"),
+        ];
+        for (request, expected) in cases {
+            let error = sign_transaction(request, BtcNetwork::Regtest, true, true).unwrap_err();
+            assert!(error.contains(expected), "{}", error);
+        }
+        let error = sign_transaction(request(2, &legacy_wif, &legacy_address, &prev_tx), BtcNetwork::Regtest, true, true).unwrap_err();
+        assert!(error.ends_with("which has only 2 outputs"), "{}", error);
+        let segwit = request(0, &segwit_key.to_wif(), &segwit_address.to_string(), &prev_tx);
+        let error = sign_transaction(segwit, BtcNetwork::Regtest, true, true).unwrap_err();
+        assert!(error.ends_with(&format!("has script {}", prev_tx.output[0].script_pubkey.to_hex_string())), "{}", error);
+
+        // More signing inputs than the transaction has, the extra one with a previous transaction
+        let mut extra = request(1, &segwit_key.to_wif(), &segwit_address.to_string(), &prev_tx);
+        extra.inputs.push(request(1, &segwit_key.to_wif(), &segwit_address.to_string(), &prev_tx).inputs.remove(0));
+        let error = sign_transaction(extra, BtcNetwork::Regtest, true, true).unwrap_err();
+        assert_eq!(error, "Input count mismatch: transaction has 1 inputs, but 2 signing inputs provided");
+    }
+
+    #[test]
+    fn test_hex_keys_and_uncompressed_p2pkh() {
+        // Private key 1, whose uncompressed and compressed P2PKH addresses are well known
+        let hex = "0000000000000000000000000000000000000000000000000000000000000001";
+        let uncompressed = "1EHNa6Q4Jz2uvNExL497mE43ikXhwF6kZm";
+        let compressed = "1BgGZ9tcN4rm9KBzDn7KprQz87SZ26SAMH";
+        let hex_input = |address: &str| SignInput {
+            private_key_wif: None,
+            private_key_hex: Some(hex.to_string().into()),
+            ..input(String::new(), address.to_string(), None)
+        };
+        let sign = |sign_input: SignInput| {
+            let request = SignTxRequest { unsigned_tx_hex: unsigned_tx_hex(), xprv: None, inputs: vec![sign_input] };
+            sign_transaction(request, BtcNetwork::Bitcoin, true, true).map(|signed| parse_transaction_hex(&signed).unwrap())
+        };
+        let public_key_len = |tx: &Transaction| tx.input[0].script_sig.instructions().last().unwrap().unwrap().push_bytes().unwrap().len();
+
+        // Hex keys default to compressed, yet sign for the uncompressed address
+        let signed = sign(hex_input(uncompressed)).unwrap();
+        assert_eq!(public_key_len(&signed), 65);
+        assert_eq!(public_key_len(&sign(hex_input(compressed)).unwrap()), 33);
+
+        // A compressed WIF for the uncompressed address, and the flag overriding either default
+        let key = PrivateKey::new(SecretKey::from_str(hex).unwrap(), Network::Bitcoin);
+        assert_eq!(sign(input(key.to_wif(), uncompressed.to_string(), None)).unwrap(), signed);
+        let explicit = SignInput { compressed: Some(false), ..hex_input(uncompressed) };
+        assert_eq!(sign(explicit).unwrap(), signed);
+
+        let (_, other_address) = key_and_address(Network::Bitcoin);
+        let error = sign(hex_input(&other_address)).unwrap_err();
+        assert_eq!(error, "Private key for input 0 does not match the output it spends, compressed or uncompressed");
+        let error = sign(SignInput { private_key_hex: Some(format!("{}zz", &hex[2..]).into()), ..hex_input(compressed) }).unwrap_err();
+        assert!(error.starts_with("Invalid private_key_hex for input 0") && !error.contains(&hex[2..]), "{}", error);
+        let error = sign(SignInput { private_key_wif: Some(key.to_wif().into()), ..hex_input(compressed) }).unwrap_err();
+        assert!(error.contains("more than one of"), "{}", error);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_reads_input_file() {
+        use std::io::Write;
+        use std::os::unix::fs::PermissionsExt;
+
+        let (wif, address) = key_and_address(Network::Regtest);
+        let json = serde_json::json!({"unsigned_tx_hex": unsigned_tx_hex(), "inputs": [{"private_key_wif": wif, "address": address}]});
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "{}", json).unwrap();
+        let path = file.path().to_str().unwrap();
+
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        let (contents, warning) = read_input_file(path).unwrap();
+        assert_eq!(warning, None);
+        let request: SignTxRequest = serde_json::from_str(&contents).unwrap();
+        assert_eq!(request.inputs[0].private_key_wif.as_deref().map(String::as_str), Some(wif.as_str()));
+        assert!(sign_transaction(request, BtcNetwork::Regtest, true, true).is_ok());
+
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let (_, warning) = read_input_file(path).unwrap();
+        assert!(warning.unwrap().contains("readable by all users (mode 644)"));
+
+        let error = read_input_file("/nonexistent/request.json").unwrap_err();
+        assert!(error.starts_with("Failed to open /nonexistent/request.json"), "{}", error);
+
+        // A token file is trimmed, must not be empty, and gets the same warning
+        let mut token_file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(token_file, "  s3cret-token ").unwrap();
+        let token_path = token_file.path().to_str().unwrap();
+        std::fs::set_permissions(token_path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let (token, warning) = read_token_file(token_path).unwrap();
+        assert_eq!(token.as_str(), "s3cret-token");
+        assert!(warning.is_some());
+        std::fs::write(token_path, "\n").unwrap();
+        assert!(read_token_file(token_path).unwrap_err().contains("holds no token"));
+    }
+
+    #[test]
+    fn test_prompts_for_missing_keys() {
+        let (wif, address) = key_and_address(Network::Regtest);
+        let mut request = request(wif.clone(), address.clone());
+        request.inputs[0].private_key_wif = None;
+        let mut prompts = Vec::new();
+        prompt_keys(&mut request, |prompt| {
+            prompts.push(prompt.to_string());
+            Ok(format!("{}\n", wif))
+        })
+        .unwrap();
+        assert_eq!(prompts, vec![format!("Private key for input 0 ({}): ", address)]);
+        assert!(sign_transaction(request, BtcNetwork::Regtest, true, true).is_ok());
+
+        let hex = "0000000000000000000000000000000000000000000000000000000000000001";
+        let mut request = SignTxRequest {
+            unsigned_tx_hex: unsigned_tx_hex(),
+            xprv: None,
+            inputs: vec![SignInput { private_key_wif: None, ..input(wif, address, None) }],
+        };
+        prompt_keys(&mut request, |_| Ok(hex.to_string())).unwrap();
+        assert_eq!(request.inputs[0].private_key_hex.as_deref().map(String::as_str), Some(hex));
+
+        // Inputs that already have a key are not prompted for
+        prompt_keys(&mut request, |_| panic!("prompted for a key")).unwrap();
+        request.inputs[0].private_key_hex = None;
+        assert_eq!(prompt_keys(&mut request, |_| Ok(" \n".to_string())).unwrap_err(), "No key given for input 0");
+    }
+
+    #[test]
+    fn test_redacts_keys_in_errors() {
+        let (wif, address) = key_and_address(Network::Regtest);
+        // Change one character so the checksum fails
+        let last = if wif.ends_with('1') { '2' } else { '1' };
+        let bad_wif = format!("{}{}", &wif[..wif.len() - 1], last);
+        let error = sign_transaction(request(bad_wif.clone(), address), BtcNetwork::Regtest, true, true).unwrap_err();
+        assert_eq!(error, format!("Invalid WIF for input 0 (52 characters): {}", WIF_HINT));
+        for start in 0..bad_wif.len() - 3 {
+            assert!(!error.contains(&bad_wif[start..start + 4]), "{}", error);
+        }
+
+        // A bad character is not quoted either
+        let (_, address) = key_and_address(Network::Regtest);
+        let error = sign_transaction(request(format!("{}0", &wif[..wif.len() - 1]), address), BtcNetwork::Regtest, true, true)
+            .unwrap_err();
+        assert_eq!(error, format!("Invalid WIF for input 0 (52 characters): {}", WIF_HINT));
+        assert_eq!(redact(" short "), "5 characters");
+    }
+
+    #[test]
+    fn test_assembles_crowdfunding_fragments() {
+        let (legacy_wif, legacy_address) = key_and_address(Network::Regtest);
+        let segwit_key = PrivateKey::new(SecretKey::from_slice(&[8; 32]).unwrap(), Network::Regtest);
+        let segwit_address = Address::p2wpkh(&segwit_key.public_key(&Secp256k1::new()).try_into().unwrap(), Network::Regtest);
+        let template = parse_transaction_hex(&unsigned_tx_hex()).unwrap();
+        // Each contributor's transaction holds only their own input, toward the same outputs
+        let fragment = |vout: u32, sign_input: SignInput, contributing: bool| {
+            let mut tx = template.clone();
+            tx.input[0].previous_output.vout = vout;
+            let mut request = SignTxRequest { unsigned_tx_hex: serialize_hex(&tx), xprv: None, inputs: vec![sign_input] };
+            if contributing {
+                contribute(&mut request).unwrap();
+            }
+            sign_transaction(request, BtcNetwork::Regtest, true, true).unwrap()
+        };
+        let legacy = fragment(0, input(legacy_wif.clone(), legacy_address.clone(), None), true);
+        let segwit = fragment(1, input(segwit_key.to_wif(), segwit_address.to_string(), Some(100_000)), true);
+
+        let assemble = |fragment_hexes: Vec<&String>| {
+            assemble_fragments(AssembleRequest { fragment_hexes: fragment_hexes.into_iter().cloned().collect() })
+        };
+        let tx = assemble(vec![&legacy, &segwit]).unwrap();
+        assert_eq!(tx.input.len(), 2);
+        assert_eq!(tx.output, template.output);
+        let legacy_script = Address::from_str(&legacy_address).unwrap().assume_checked().script_pubkey();
+        let spent = [
+            SpentOutput { script_pubkey: legacy_script, amount: None },
+            SpentOutput { script_pubkey: segwit_address.script_pubkey(), amount: Some(Amount::from_sat(100_000)) },
+        ];
+        signing::verify_transaction(&tx, &spent).unwrap();
+
+        // Every fragment must pay the same outputs
+        let mut divergent = parse_transaction_hex(&segwit).unwrap();
+        divergent.output[0].value = Amount::from_sat(80_000);
+        let error = assemble(vec![&legacy, &serialize_hex(&divergent)]).unwrap_err();
+        assert_eq!(error, "Fragment 1 has different outputs from fragment 0");
+
+        let error = assemble(vec![&legacy, &legacy]).unwrap_err();
+        assert!(error.starts_with("Input 0 of fragment 1 spends") && error.ends_with("as fragment 0 does"), "{}", error);
+        let plain = fragment(1, input(segwit_key.to_wif(), segwit_address.to_string(), Some(100_000)), false);
+        let error = assemble(vec![&legacy, &plain]).unwrap_err();
+        assert_eq!(error, "Input 0 of fragment 1 is not signed with ALL|ANYONECANPAY");
+
+        let mut request = request(legacy_wif, legacy_address);
+        request.inputs[0].sighash = Some("NONE".to_string());
+        let error = contribute(&mut request).unwrap_err();
+        assert_eq!(error, "Input 0 sets sighash NONE, but contributed inputs are signed with ALL|ANYONECANPAY");
+    }
+
+    #[test]
+    fn test_signs_batch() {
+        let secp = Secp256k1::new();
+        let keys: Vec<PrivateKey> =
+            (1..=3).map(|byte| PrivateKey::new(SecretKey::from_slice(&[byte; 32]).unwrap(), Network::Regtest)).collect();
+        let addresses: Vec<Address> =
+            keys.iter().map(|key| Address::p2wpkh(&key.public_key(&secp).try_into().unwrap(), Network::Regtest)).collect();
+        let template = parse_transaction_hex(&unsigned_tx_hex()).unwrap();
+        let transaction = |n: usize, key_index: usize| {
+            let mut tx = template.clone();
+            tx.input[0].previous_output.vout = n as u32;
+            let address = addresses[key_index % addresses.len()].to_string();
+            let sign_input = SignInput { private_key_wif: None, key_index: Some(key_index), ..input(String::new(), address, Some(100_000)) };
+            SignTxRequest { unsigned_tx_hex: serialize_hex(&tx), xprv: None, inputs: vec![sign_input] }
+        };
+        let batch = |bad: Option<usize>| BatchRequest {
+            // One WIF and two hex keys
+            keys: [keys[0].to_wif(), keys[1].inner.display_secret().to_string(), keys[2].inner.display_secret().to_string()]
+                .map(Zeroizing::new)
+                .into(),
+            transactions: (0..100).map(|n| transaction(n, if Some(n) == bad { 9 } else { n % 3 })).collect(),
+        };
+
+        let started = std::time::Instant::now();
+        let results = sign_batch(batch(None), BtcNetwork::Regtest, true, true, false).unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_secs(5), "{:?}", started.elapsed());
+        assert_eq!(results.len(), 100);
+        for (n, result) in results.iter().enumerate() {
+            let BatchResult::Signed(signed) = result else { panic!("transaction {} failed", n) };
+            let tx = parse_transaction_hex(&signed.signed_tx_hex).unwrap();
+            assert_eq!(tx.input[0].previous_output.vout, n as u32);
+            assert_eq!(tx.input[0].witness.nth(1).unwrap(), keys[n % 3].public_key(&secp).to_bytes());
+            assert_eq!(signed.fee, Some(10_000));
+        }
+
+        // A failure is reported in its place without stopping the others
+        let results = sign_batch(batch(Some(50)), BtcNetwork::Regtest, true, true, false).unwrap();
+        let output = serde_json::to_value(&results).unwrap();
+        let missing_key = "Input 0 uses key_index 9, but the request has 3 shared keys";
+        assert_eq!(output[50], serde_json::json!({"error": missing_key, "input_index": 0}));
+        assert!(output[49]["signed_tx_hex"].is_string() && output[51]["signed_tx_hex"].is_string());
+        let error = sign_batch(batch(Some(50)), BtcNetwork::Regtest, true, true, true).unwrap_err();
+        assert_eq!(error, format!("Transaction 50: {}", missing_key));
+
+        let mut bad_key = batch(None);
+        bad_key.keys[0] = Zeroizing::new(keys[0].to_wif().replace('c', "d"));
+        let error = sign_batch(bad_key, BtcNetwork::Regtest, true, true, false).unwrap_err();
+        assert!(error.starts_with("Invalid shared key 0 (") && !error.contains(&keys[0].to_wif()[4..48]), "{}", error);
+    }
+
+    #[test]
+    fn test_external_signer_round_trip() {
+        use bitcoin::key::{Keypair, TapTweak};
+
+        let secp = Secp256k1::new();
+        let keys: Vec<PrivateKey> =
+            (1..=7).map(|byte| PrivateKey::new(SecretKey::from_slice(&[byte; 32]).unwrap(), Network::Regtest)).collect();
+        let pubkeys: Vec<PublicKey> = keys.iter().map(|key| key.public_key(&secp)).collect();
+        let compressed = |n: usize| CompressedPublicKey::try_from(pubkeys[n]).unwrap();
+        let redeem_script = MultisigScript { required: 2, pubkeys: pubkeys[4..7].to_vec() }.to_script();
+        let internal_key = Keypair::from_secret_key(&secp, &keys[3].inner).x_only_public_key().0;
+        let addresses = [
+            Address::p2pkh(pubkeys[0], Network::Regtest),
+            Address::p2wpkh(&compressed(1), Network::Regtest),
+            Address::p2shwpkh(&compressed(2), Network::Regtest),
+            Address::p2tr(&secp, internal_key, None, Network::Regtest),
+            Address::p2sh(&redeem_script, Network::Regtest).unwrap(),
+        ];
+        let mut tx = parse_transaction_hex(&unsigned_tx_hex()).unwrap();
+        let template = tx.input[0].clone();
+        tx.input = (0..5).map(|vout| TxIn { previous_output: OutPoint { vout, ..template.previous_output }, ..template.clone() }).collect();
+        // No keys, only the pubkeys they match
+        let keyless = |n: usize| SignInput {
+            private_key_wif: None,
+            pubkey: (n < 3).then(|| pubkeys[n].to_string()),
+            redeem_script: (n == 4).then(|| redeem_script.to_hex_string()),
+            ..input(String::new(), addresses[n].to_string(), Some(100_000))
+        };
+        let request = SignTxRequest { unsigned_tx_hex: serialize_hex(&tx), xprv: None, inputs: (0..5).map(keyless).collect() };
+
+        let plan = plan_signing(&request, BtcNetwork::Regtest, true).unwrap();
+        assert!(plan.inputs.iter().all(|input| input.error.is_none()));
+        let types: Vec<_> = plan.inputs.iter().map(|input| input.script_type.unwrap()).collect();
+        assert_eq!(types, ["p2pkh", "p2wpkh", "p2sh-p2wpkh", "p2tr", "p2sh-multisig"]);
+        assert_eq!(plan.inputs[2].outpoint, format!("{}:2", tx.input[0].previous_output.txid));
+        assert_eq!(plan.inputs[0].pubkey, Some(pubkeys[0].to_string()));
+        let output_key = internal_key.tap_tweak(&secp, None).0.to_x_only_public_key();
+        assert_eq!(plan.inputs[3].pubkey, Some(output_key.to_string()));
+
+        // Signed elsewhere with nothing but the digests
+        let digest = |n: usize| Message::from_digest(<[u8; 32]>::from_hex(plan.inputs[n].sighash.as_ref().unwrap()).unwrap());
+        let ecdsa = |n: usize, key: usize| DetachedSignature {
+            input_index: n,
+            signature_der_hex: Some(secp.sign_ecdsa_low_r(&digest(n), &keys[key].inner).serialize_der().to_string()),
+            schnorr_signature_hex: None,
+            pubkey_hex: pubkeys[key].to_string(),
+        };
+        let tweaked = Keypair::from_secret_key(&secp, &keys[3].inner).tap_tweak(&secp, None).to_keypair();
+        let schnorr = DetachedSignature {
+            input_index: 3,
+            signature_der_hex: None,
+            schnorr_signature_hex: Some(secp.sign_schnorr_no_aux_rand(&digest(3), &tweaked).to_string()),
+            pubkey_hex: output_key.to_string(),
+        };
+        let signatures = vec![ecdsa(0, 0), ecdsa(1, 1), ecdsa(2, 2), schnorr, ecdsa(4, 6), ecdsa(4, 4)];
+        let signed = apply_signatures(&request, &signatures, BtcNetwork::Regtest).unwrap();
+
+        // The single-key inputs come out as signing here makes them
+        let with_keys = SignTxRequest {
+            unsigned_tx_hex: serialize_hex(&tx),
+            xprv: None,
+            inputs: (0..5).map(|n| SignInput { private_key_wif: Some(keys[n].to_wif().into()), ..keyless(n) }).collect(),
+        };
+        let (normal, _) = sign_request(with_keys, &[], BtcNetwork::Regtest, true, true).unwrap();
+        assert_eq!(signed.input[..3], normal.input[..3]);
+        assert_eq!(MultisigInput::from_input(&signed, 4, &redeem_script).unwrap().signature_count(), 2);
+
+        // Every bad input is reported
+        let mut bad = vec![ecdsa(0, 0), ecdsa(2, 1), ecdsa(4, 4)];
+        bad[0].signature_der_hex = ecdsa(1, 0).signature_der_hex;
+        let errors = apply_signatures(&request, &bad, BtcNetwork::Regtest).unwrap_err();
+        assert_eq!(
+            errors,
+            [
+                format!("Input 0: the signature does not verify against the input's sighash under pubkey {}", pubkeys[0]),
+                "Input 1 has no signature".to_string(),
+                // A valid signature, by the wrong key
+                format!("Input 2: pubkey {} does not control the output it spends", pubkeys[1]),
+                "Input 3 has no signature".to_string(),
+            ]
+        );
+        let errors = apply_signatures(&request, &[DetachedSignature { input_index: 5, ..ecdsa(0, 0) }], BtcNetwork::Regtest).unwrap_err();
+        assert_eq!(errors, ["Signature 0 is for input 5, but the transaction has 5 inputs"]);
+
+        let mut wrong_pubkey = request;
+        wrong_pubkey.inputs[1].pubkey = Some(pubkeys[0].to_string());
+        let plan = plan_signing(&wrong_pubkey, BtcNetwork::Regtest, true).unwrap();
+        assert_eq!(plan.inputs[1].error.as_deref(), Some("The pubkey does not control the output it spends"));
+    }
+
+    #[test]
+    fn test_resolve_network() {
+        assert_eq!(resolve_network(None, None), Ok(BtcNetwork::Bitcoin));
+        assert_eq!(resolve_network(None, Some("signet".to_string())), Ok(BtcNetwork::Signet));
+        assert_eq!(resolve_network(Some("regtest"), Some("signet".to_string())), Ok(BtcNetwork::Regtest));
+        assert!(resolve_network(Some("liquid"), None).unwrap_err().contains("liquid"));
+    }
+}
@@ -0,0 +1,329 @@
+//! Broadcasting a transaction to several Esplora endpoints at once
+
+use std::time::{Duration, Instant};
+
+use bitcoin::{consensus, Transaction, Txid};
+use futures::future::join_all;
+
+use crate::error::{Error, Result};
+use crate::network::{BlockstreamClient, BroadcastRejection};
+use crate::types::BtcNetwork;
+
+/// Default per-endpoint timeout
+pub const DEFAULT_ENDPOINT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Outcome of a broadcast attempt against one endpoint
+#[derive(Debug)]
+pub enum EndpointOutcome {
+    /// The endpoint accepted the transaction and returned the expected txid
+    Accepted,
+    /// The endpoint already knew the transaction (mempool or chain)
+    AlreadyKnown,
+    /// The endpoint returned a txid different from the local one
+    TxidMismatch(String),
+    /// The endpoint rejected the transaction or could not be reached
+    Failed(Error),
+    /// The endpoint did not answer within the timeout
+    TimedOut,
+}
+
+impl EndpointOutcome {
+    /// Whether the endpoint has the transaction after the attempt
+    pub fn is_success(&self) -> bool {
+        matches!(self, EndpointOutcome::Accepted | EndpointOutcome::AlreadyKnown)
+    }
+}
+
+/// Result of a broadcast attempt against one endpoint
+#[derive(Debug)]
+pub struct EndpointReport {
+    /// Base URL of the endpoint, with any credentials redacted
+    pub base_url: String,
+    /// What happened
+    pub outcome: EndpointOutcome,
+    /// Time spent on the attempt
+    pub elapsed: Duration,
+}
+
+/// Per-endpoint results of a multi-endpoint broadcast
+#[derive(Debug)]
+pub struct BroadcastReport {
+    /// The locally computed txid
+    pub txid: Txid,
+    /// One report per attempted endpoint, in endpoint order
+    pub endpoints: Vec<EndpointReport>,
+}
+
+impl BroadcastReport {
+    /// Whether at least one endpoint has the transaction
+    pub fn is_success(&self) -> bool {
+        self.endpoints.iter().any(|e| e.outcome.is_success())
+    }
+
+    /// Number of endpoints that have the transaction
+    pub fn success_count(&self) -> usize {
+        self.endpoints.iter().filter(|e| e.outcome.is_success()).count()
+    }
+}
+
+/// Broadcaster pushing one transaction to several independent endpoints
+#[derive(Debug, Clone)]
+pub struct MultiBroadcaster {
+    clients: Vec<BlockstreamClient>,
+    timeout: Duration,
+}
+
+impl MultiBroadcaster {
+    /// Create a broadcaster for the given clients
+    pub fn new(clients: Vec<BlockstreamClient>) -> Self {
+        MultiBroadcaster {
+            clients,
+            timeout: DEFAULT_ENDPOINT_TIMEOUT,
+        }
+    }
+
+    /// Create a broadcaster from Esplora base URLs
+    pub fn from_urls(network: BtcNetwork, urls: &[&str]) -> Result<Self> {
+        let clients = urls
+            .iter()
+            .map(|url| BlockstreamClient::with_base_url(network, url))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self::new(clients))
+    }
+
+    /// Set the timeout applied to each endpoint
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Broadcast to all endpoints concurrently
+    pub async fn broadcast(&self, tx_hex: &str) -> Result<BroadcastReport> {
+        let txid = local_txid(tx_hex)?;
+        let attempts = self
+            .clients
+            .iter()
+            .map(|client| self.attempt(client, tx_hex, txid));
+        let endpoints = join_all(attempts).await;
+        Ok(BroadcastReport { txid, endpoints })
+    }
+
+    /// Broadcast to endpoints one at a time, stopping at the first success
+    pub async fn broadcast_sequential(&self, tx_hex: &str) -> Result<BroadcastReport> {
+        let txid = local_txid(tx_hex)?;
+        let mut endpoints = Vec::new();
+        for client in &self.clients {
+            let report = self.attempt(client, tx_hex, txid).await;
+            let done = report.outcome.is_success();
+            endpoints.push(report);
+            if done {
+                break;
+            }
+        }
+        Ok(BroadcastReport { txid, endpoints })
+    }
+
+    /// Blocking version of [`broadcast`](Self::broadcast)
+    ///
+    /// Runs on a private runtime, so it must not be called from async code.
+    pub fn broadcast_blocking(&self, tx_hex: &str) -> Result<BroadcastReport> {
+        block_on(self.broadcast(tx_hex))
+    }
+
+    /// Blocking version of [`broadcast_sequential`](Self::broadcast_sequential)
+    ///
+    /// Runs on a private runtime, so it must not be called from async code.
+    pub fn broadcast_sequential_blocking(&self, tx_hex: &str) -> Result<BroadcastReport> {
+        block_on(self.broadcast_sequential(tx_hex))
+    }
+
+    async fn attempt(&self, client: &BlockstreamClient, tx_hex: &str, txid: Txid) -> EndpointReport {
+        let started = Instant::now();
+        let outcome = match tokio::time::timeout(self.timeout, client.broadcast_transaction(tx_hex)).await {
+            Err(_) => EndpointOutcome::TimedOut,
+            Ok(Ok(returned)) if returned == txid.to_string() => EndpointOutcome::Accepted,
+            Ok(Ok(returned)) => EndpointOutcome::TxidMismatch(returned),
+            Ok(Err(e)) => match BroadcastRejection::from_error(&e) {
+                Some(BroadcastRejection::AlreadyKnown) => EndpointOutcome::AlreadyKnown,
+                _ => EndpointOutcome::Failed(e),
+            },
+        };
+        let base_url = client.display_url();
+        if !outcome.is_success() {
+            log::warn!("Broadcast to {} failed: {:?}", base_url, outcome);
+        }
+        EndpointReport {
+            base_url,
+            outcome,
+            elapsed: started.elapsed(),
+        }
+    }
+}
+
+/// Run a broadcast to completion on a fresh single-threaded runtime
+fn block_on(broadcast: impl std::future::Future<Output = Result<BroadcastReport>>) -> Result<BroadcastReport> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(broadcast)
+}
+
+/// Decode the transaction hex and compute its txid
+fn local_txid(tx_hex: &str) -> Result<Txid> {
+    let tx: Transaction = consensus::encode::deserialize_hex(tx_hex.trim())
+        .map_err(|e| Error::InvalidTransaction(format!("Cannot decode transaction: {}", e)))?;
+    Ok(tx.compute_txid())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::{absolute, transaction, Amount, OutPoint, ScriptBuf, Sequence, TxIn, TxOut, Witness};
+
+    fn test_tx_hex() -> (String, Txid) {
+        let tx = Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn {
+                previous_output: OutPoint::null(),
+                script_sig: ScriptBuf::new(),
+                sequence: Sequence::MAX,
+                witness: Witness::new(),
+            }],
+            output: vec![TxOut {
+                value: Amount::from_sat(1_000),
+                script_pubkey: ScriptBuf::new(),
+            }],
+        };
+        (consensus::encode::serialize_hex(&tx), tx.compute_txid())
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_with_failing_hanging_and_working_endpoints() {
+        let (tx_hex, txid) = test_tx_hex();
+
+        let mut failing = mockito::Server::new_async().await;
+        failing
+            .mock("POST", "/tx")
+            .with_status(400)
+            .with_body(r#"sendrawtransaction RPC error: {"code":-25,"message":"bad-txns-inputs-missingorspent"}"#)
+            .create_async()
+            .await;
+
+        // Accepts connections but never answers
+        let hanging = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let hanging_url = format!("http://{}", hanging.local_addr().unwrap());
+
+        let mut working = mockito::Server::new_async().await;
+        working
+            .mock("POST", "/tx")
+            .with_body(txid.to_string())
+            .create_async()
+            .await;
+
+        // Credentials in an endpoint URL stay out of the report
+        let failing_url = failing.url().replace("http://", "http://u:secret@");
+
+        let broadcaster = MultiBroadcaster::from_urls(
+            BtcNetwork::Regtest,
+            &[&failing_url, &hanging_url, &working.url()],
+        )
+        .unwrap()
+        .with_timeout(Duration::from_millis(500));
+
+        let report = broadcaster.broadcast(&tx_hex).await.unwrap();
+        assert_eq!(report.txid, txid);
+        assert!(report.is_success());
+        assert_eq!(report.success_count(), 1);
+        assert!(matches!(report.endpoints[0].outcome, EndpointOutcome::Failed(_)));
+        assert!(matches!(report.endpoints[1].outcome, EndpointOutcome::TimedOut));
+        assert!(matches!(report.endpoints[2].outcome, EndpointOutcome::Accepted));
+        assert_eq!(report.endpoints[0].base_url, failing.url().replace("http://", "http://<redacted>@"));
+        assert!(!format!("{:?}", report).contains("secret"));
+    }
+
+    #[tokio::test]
+    async fn test_already_known_counts_as_success() {
+        let (tx_hex, _) = test_tx_hex();
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/tx")
+            .with_status(400)
+            .with_body(r#"sendrawtransaction RPC error: {"code":-27,"message":"Transaction already in block chain"}"#)
+            .create_async()
+            .await;
+
+        let broadcaster = MultiBroadcaster::from_urls(BtcNetwork::Regtest, &[&server.url()]).unwrap();
+        let report = broadcaster.broadcast(&tx_hex).await.unwrap();
+        assert!(report.is_success());
+        assert!(matches!(report.endpoints[0].outcome, EndpointOutcome::AlreadyKnown));
+    }
+
+    #[tokio::test]
+    async fn test_txid_mismatch_is_not_success() {
+        let (tx_hex, _) = test_tx_hex();
+        let mut server = mockito::Server::new_async().await;
+        server
+            .mock("POST", "/tx")
+            .with_body("00".repeat(32))
+            .create_async()
+            .await;
+
+        let broadcaster = MultiBroadcaster::from_urls(BtcNetwork::Regtest, &[&server.url()]).unwrap();
+        let report = broadcaster.broadcast(&tx_hex).await.unwrap();
+        assert!(!report.is_success());
+        assert!(matches!(report.endpoints[0].outcome, EndpointOutcome::TxidMismatch(_)));
+    }
+
+    #[tokio::test]
+    async fn test_sequential_stops_at_first_success() {
+        let (tx_hex, txid) = test_tx_hex();
+        let mut first = mockito::Server::new_async().await;
+        first
+            .mock("POST", "/tx")
+            .with_body(txid.to_string())
+            .create_async()
+            .await;
+        let mut second = mockito::Server::new_async().await;
+        let unused = second.mock("POST", "/tx").expect(0).create_async().await;
+
+        let broadcaster =
+            MultiBroadcaster::from_urls(BtcNetwork::Regtest, &[&first.url(), &second.url()]).unwrap();
+        let report = broadcaster.broadcast_sequential(&tx_hex).await.unwrap();
+        assert_eq!(report.endpoints.len(), 1);
+        assert!(report.is_success());
+        unused.assert_async().await;
+    }
+
+    #[test]
+    fn test_sequential_blocking_falls_back() {
+        let (tx_hex, txid) = test_tx_hex();
+        let mut failing = mockito::Server::new();
+        failing
+            .mock("POST", "/tx")
+            .with_status(503)
+            .with_body("unavailable")
+            .create();
+        let mut working = mockito::Server::new();
+        working.mock("POST", "/tx").with_body(txid.to_string()).create();
+
+        let broadcaster =
+            MultiBroadcaster::from_urls(BtcNetwork::Regtest, &[&failing.url(), &working.url()]).unwrap();
+        let report = broadcaster.broadcast_sequential_blocking(&tx_hex).unwrap();
+        assert_eq!(report.endpoints.len(), 2);
+        assert!(matches!(report.endpoints[0].outcome, EndpointOutcome::Failed(_)));
+        assert!(matches!(report.endpoints[1].outcome, EndpointOutcome::Accepted));
+
+        let report = broadcaster.broadcast_blocking(&tx_hex).unwrap();
+        assert_eq!(report.success_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_hex_is_rejected_locally() {
+        let broadcaster = MultiBroadcaster::new(Vec::new());
+        assert!(matches!(
+            broadcaster.broadcast("zz").await,
+            Err(Error::InvalidTransaction(_))
+        ));
+    }
+}
@@ -0,0 +1,210 @@
+//! In-memory TTL cache for Esplora responses
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Cache configuration for `BlockstreamClient`
+#[derive(Debug, Clone, Copy)]
+pub struct CacheConfig {
+    /// Lifetime of cached fee estimates
+    pub fee_ttl: Duration,
+    /// Lifetime of cached chain tip height
+    pub tip_ttl: Duration,
+    /// Lifetime of cached confirmed transactions (immutable, so can be long)
+    pub tx_ttl: Duration,
+    /// Lifetime of cached UTXO sets; `None` (the default) disables caching them
+    pub utxo_ttl: Option<Duration>,
+    /// Maximum number of cached responses
+    pub max_entries: usize,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            fee_ttl: Duration::from_secs(30),
+            tip_ttl: Duration::from_secs(10),
+            tx_ttl: Duration::from_secs(600),
+            utxo_ttl: None,
+            max_entries: 1_000,
+        }
+    }
+}
+
+/// Cache hit/miss counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Lookups answered from the cache
+    pub hits: u64,
+    /// Lookups that went to the network
+    pub misses: u64,
+    /// Number of entries currently stored
+    pub entries: usize,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    json: String,
+    expires_at: Instant,
+}
+
+/// Response cache shared by a client and its clones
+#[derive(Debug)]
+pub struct ResponseCache {
+    config: CacheConfig,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl ResponseCache {
+    /// Create an empty cache
+    pub fn new(config: CacheConfig) -> Self {
+        ResponseCache {
+            config,
+            entries: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the cache configuration
+    pub fn config(&self) -> &CacheConfig {
+        &self.config
+    }
+
+    /// Look up a value, counting the hit or miss
+    pub(crate) fn get<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let entries = self.entries.lock().unwrap();
+        let value = entries
+            .get(key)
+            .filter(|entry| entry.expires_at > Instant::now())
+            .and_then(|entry| serde_json::from_str(&entry.json).ok());
+        match value {
+            Some(_) => self.hits.fetch_add(1, Ordering::Relaxed),
+            None => self.misses.fetch_add(1, Ordering::Relaxed),
+        };
+        value
+    }
+
+    /// Store a value for `ttl`
+    ///
+    /// When full, expired entries are dropped first, then those expiring
+    /// soonest, whatever order they were inserted in.
+    pub(crate) fn insert<T: Serialize>(&self, key: &str, value: &T, ttl: Duration) {
+        let Ok(json) = serde_json::to_string(value) else {
+            return;
+        };
+        let mut entries = self.entries.lock().unwrap();
+        let now = Instant::now();
+        if entries.len() >= self.config.max_entries && !entries.contains_key(key) {
+            entries.retain(|_, entry| entry.expires_at > now);
+            while entries.len() >= self.config.max_entries {
+                let soonest = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.expires_at)
+                    .map(|(k, _)| k.clone());
+                match soonest {
+                    Some(k) => entries.remove(&k),
+                    None => break,
+                };
+            }
+        }
+        if self.config.max_entries > 0 {
+            entries.insert(key.to_string(), CacheEntry { json, expires_at: now + ttl });
+        }
+    }
+
+    /// Drop every cached response for a transaction
+    pub fn invalidate(&self, txid: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(&tx_key(txid));
+        entries.remove(&tx_hex_key(txid));
+    }
+
+    /// Drop every cached response
+    pub fn clear(&self) {
+        self.entries.lock().unwrap().clear();
+    }
+
+    /// Get the hit/miss counters
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.entries.lock().unwrap().len(),
+        }
+    }
+}
+
+pub(crate) const FEES_KEY: &str = "fees";
+pub(crate) const TIP_KEY: &str = "tip";
+
+pub(crate) fn tx_key(txid: &str) -> String {
+    format!("tx:{}", txid)
+}
+
+pub(crate) fn tx_hex_key(txid: &str) -> String {
+    format!("txhex:{}", txid)
+}
+
+pub(crate) fn utxo_key(address: &str) -> String {
+    format!("utxo:{}", address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expired_entries_are_misses() {
+        let cache = ResponseCache::new(CacheConfig::default());
+        cache.insert("a", &1u32, Duration::from_millis(0));
+        cache.insert("b", &2u32, Duration::from_secs(60));
+        assert_eq!(cache.get::<u32>("a"), None);
+        assert_eq!(cache.get::<u32>("b"), Some(2));
+        assert_eq!(cache.stats().hits, 1);
+        assert_eq!(cache.stats().misses, 1);
+    }
+
+    #[test]
+    fn test_eviction_keeps_max_entries() {
+        let cache = ResponseCache::new(CacheConfig {
+            max_entries: 2,
+            ..Default::default()
+        });
+        cache.insert("a", &1u32, Duration::from_secs(10));
+        cache.insert("b", &2u32, Duration::from_secs(20));
+        cache.insert("c", &3u32, Duration::from_secs(30));
+        assert_eq!(cache.stats().entries, 2);
+        assert_eq!(cache.get::<u32>("a"), None);
+        assert_eq!(cache.get::<u32>("c"), Some(3));
+    }
+
+    #[test]
+    fn test_eviction_drops_soonest_expiring_not_oldest() {
+        let cache = ResponseCache::new(CacheConfig {
+            max_entries: 2,
+            ..Default::default()
+        });
+        cache.insert("old-long", &1u32, Duration::from_secs(600));
+        cache.insert("new-short", &2u32, Duration::from_secs(10));
+        cache.insert("c", &3u32, Duration::from_secs(30));
+        assert_eq!(cache.get::<u32>("new-short"), None);
+        assert_eq!(cache.get::<u32>("old-long"), Some(1));
+        assert_eq!(cache.get::<u32>("c"), Some(3));
+
+        // An expired entry goes before any live one, even one expiring sooner than the new entry
+        cache.clear();
+        cache.insert("live", &4u32, Duration::from_secs(1));
+        cache.insert("expired", &5u32, Duration::from_millis(0));
+        cache.insert("d", &6u32, Duration::from_secs(600));
+        assert_eq!(cache.get::<u32>("live"), Some(4));
+        assert_eq!(cache.get::<u32>("d"), Some(6));
+        assert_eq!(cache.stats().entries, 2);
+    }
+}
@@ -2,6 +2,9 @@
 
 use std::fmt;
 use bitcoin::{address, bip32, psbt};
+use serde::{Deserialize, Serialize};
+
+use crate::types::BtcNetwork;
 
 /// Common error type for the library
 #[derive(Debug, thiserror::Error)]
@@ -22,14 +25,37 @@ pub enum Error {
     #[error("JSON error: {0}")]
     Json(#[from] serde_json::Error),
     
-    /// Network-related errors
+    /// Network-related errors; the request URL in the message has its
+    /// credentials removed
     #[error("Network error: {0}")]
-    Network(#[from] reqwest::Error),
+    Network(#[source] reqwest::Error),
+
+    /// Non-success HTTP response, with the body the server sent
+    #[error("HTTP {status} from {url}: {body}")]
+    Http {
+        /// HTTP status code
+        status: u16,
+        /// Response body text
+        body: String,
+        /// Requested URL
+        url: String,
+        /// Bitcoin Core RPC error, when the body embeds one
+        rpc_error: Option<RpcError>,
+    },
     
     /// Invalid network specification
     #[error("Invalid network: {0}")]
     InvalidNetwork(String),
     
+    /// Address whose encoding is shared by several networks
+    #[error("Address {address} is valid on several networks ({candidates:?}); select one explicitly")]
+    AmbiguousNetwork {
+        /// The address
+        address: String,
+        /// Networks the address is valid on
+        candidates: Vec<BtcNetwork>,
+    },
+
     /// Invalid Bitcoin address
     #[error("Invalid address: {0}")]
     InvalidAddress(String),
@@ -37,14 +63,28 @@ pub enum Error {
     /// Invalid transaction data
     #[error("Invalid transaction: {0}")]
     InvalidTransaction(String),
+
+    /// Raw transaction bytes that do not decode
+    #[error("Cannot decode transaction at byte {offset}: {message}")]
+    TransactionDecode {
+        /// Offset of the offending byte
+        offset: usize,
+        /// What went wrong
+        message: String,
+    },
     
     /// Transaction signing errors
     #[error("Transaction signing failed: {0}")]
     SigningError(String),
     
     /// Insufficient funds for transaction
-    #[error("Insufficient funds")]
-    InsufficientFunds,
+    #[error("Insufficient funds: {} sat needed, {} sat available", needed.to_sat(), available.to_sat())]
+    InsufficientFunds {
+        /// Outputs plus fee
+        needed: bitcoin::Amount,
+        /// Value of the spendable UTXOs
+        available: bitcoin::Amount,
+    },
     
     /// Configuration errors
     #[error("Configuration error: {0}")]
@@ -69,6 +109,69 @@ pub enum Error {
     /// Invalid parameter
     #[error("Invalid parameter: {0}")]
     InvalidParameter(String),
+
+    /// The server does not implement the requested endpoint
+    #[error("Unsupported by backend: {0}")]
+    UnsupportedByBackend(String),
+
+    /// Proof-of-work, header chain or merkle proof verification failed
+    #[error("Verification failed: {0}")]
+    VerificationFailed(String),
+
+    /// Entropy or a phrase that does not make a valid BIP39 mnemonic
+    #[error("Invalid mnemonic: {0}")]
+    Mnemonic(#[from] crate::mnemonic::MnemonicError),
+}
+
+impl Error {
+    /// HTTP status code, for `Error::Http`
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            Error::Http { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    /// Whether the server answered 404 Not Found
+    pub fn is_not_found(&self) -> bool {
+        self.status() == Some(404)
+    }
+
+    /// Whether the server answered 429 Too Many Requests
+    pub fn is_rate_limited(&self) -> bool {
+        self.status() == Some(429)
+    }
+
+    /// Whether a retry may succeed: rate limiting, a server error (5xx), or no response at all
+    pub fn is_transient(&self) -> bool {
+        self.is_rate_limited() || matches!(self.status(), Some(500..=599)) || matches!(self, Error::Network(_))
+    }
+
+    /// Whether the server rejected the request itself (4xx)
+    pub fn is_client_error(&self) -> bool {
+        matches!(self.status(), Some(400..=499))
+    }
+}
+
+/// Bitcoin Core RPC error as relayed by Esplora
+///
+/// Esplora forwards node rejections as bodies like
+/// `sendrawtransaction RPC error: {"code":-26,"message":"min relay fee not met"}`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RpcError {
+    /// RPC error code (e.g. -26 for a rejected transaction)
+    pub code: i32,
+    /// RPC error message (e.g. `bad-txns-inputs-missingorspent`)
+    pub message: String,
+}
+
+impl RpcError {
+    /// Extract the RPC error JSON embedded in a response body, if any
+    pub fn parse(body: &str) -> Option<Self> {
+        let start = body.find('{')?;
+        let end = body.rfind('}')?;
+        serde_json::from_str(body.get(start..=end)?).ok()
+    }
 }
 
 /// Type alias for Result<T, Error>
@@ -86,6 +189,17 @@ impl From<&str> for Error {
     }
 }
 
+impl From<reqwest::Error> for Error {
+    fn from(mut e: reqwest::Error) -> Self {
+        // reqwest prints the request URL, which may embed `user:password@`
+        if let Some(url) = e.url_mut() {
+            let _ = url.set_username("");
+            let _ = url.set_password(None);
+        }
+        Error::Network(e)
+    }
+}
+
 impl From<address::Error> for Error {
     fn from(e: address::Error) -> Self {
         Error::InvalidAddress(e.to_string())
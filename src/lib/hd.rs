@@ -0,0 +1,514 @@
+//! HD derivation from account extended keys
+//!
+//! Accounts are given as an xpub, optionally with its key origin in descriptor
+//! form (`[d34db33f/84'/0'/0']xpub...`), plus the script type to derive. Only
+//! normal (unhardened) children are derived, so no private key is ever needed.
+//! Signing tools derive input keys from an xprv with [`SigningXpriv`] instead.
+//! Accounts are written back out as descriptors with [`ScriptKind::descriptor`],
+//! checksummed as Bitcoin Core expects.
+
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::bip32::{ChildNumber, DerivationPath, Fingerprint, Xpriv, Xpub};
+use bitcoin::secp256k1::Secp256k1;
+use bitcoin::{Address, CompressedPublicKey, NetworkKind, PrivateKey, Script, ScriptBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::types::BtcNetwork;
+
+/// Script type of derived addresses
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScriptKind {
+    /// Legacy pay-to-pubkey-hash (BIP 44)
+    P2pkh,
+    /// P2WPKH nested in P2SH (BIP 49)
+    P2shP2wpkh,
+    /// Native segwit v0 (BIP 84)
+    P2wpkh,
+    /// Taproot key path spend, no script tree (BIP 86)
+    P2tr,
+}
+
+impl FromStr for ScriptKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "p2pkh" => Ok(ScriptKind::P2pkh),
+            "p2sh-p2wpkh" => Ok(ScriptKind::P2shP2wpkh),
+            "p2wpkh" => Ok(ScriptKind::P2wpkh),
+            "p2tr" => Ok(ScriptKind::P2tr),
+            _ => Err(Error::InvalidParameter(format!(
+                "Unknown script type {:?}; use p2pkh, p2sh-p2wpkh, p2wpkh or p2tr",
+                s
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for ScriptKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ScriptKind::P2pkh => "p2pkh",
+            ScriptKind::P2shP2wpkh => "p2sh-p2wpkh",
+            ScriptKind::P2wpkh => "p2wpkh",
+            ScriptKind::P2tr => "p2tr",
+        })
+    }
+}
+
+impl ScriptKind {
+    /// Script type of a locking script, if it is one of the single-key types
+    ///
+    /// Any P2SH script is taken for P2SH-P2WPKH, since the hash does not tell.
+    pub fn from_script(script: &Script) -> Option<Self> {
+        if script.is_p2pkh() {
+            Some(ScriptKind::P2pkh)
+        } else if script.is_p2sh() {
+            Some(ScriptKind::P2shP2wpkh)
+        } else if script.is_p2wpkh() {
+            Some(ScriptKind::P2wpkh)
+        } else if script.is_p2tr() {
+            Some(ScriptKind::P2tr)
+        } else {
+            None
+        }
+    }
+
+    /// Output descriptor of this type for a key expression, with its checksum
+    ///
+    /// `key` is anything a descriptor takes as a key, e.g.
+    /// `[d34db33f/84h/0h/0h]xpub.../0/*`.
+    pub fn descriptor(&self, key: &str) -> Result<String> {
+        let descriptor = match self {
+            ScriptKind::P2pkh => format!("pkh({})", key),
+            ScriptKind::P2shP2wpkh => format!("sh(wpkh({}))", key),
+            ScriptKind::P2wpkh => format!("wpkh({})", key),
+            ScriptKind::P2tr => format!("tr({})", key),
+        };
+        let checksum = descriptor_checksum(&descriptor)?;
+        Ok(format!("{}#{}", descriptor, checksum))
+    }
+
+    /// Locking script of this type for `public_key`
+    pub fn script_pubkey(&self, public_key: CompressedPublicKey) -> ScriptBuf {
+        match self {
+            ScriptKind::P2pkh => ScriptBuf::new_p2pkh(&public_key.pubkey_hash()),
+            ScriptKind::P2shP2wpkh => ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash()).to_p2sh(),
+            ScriptKind::P2wpkh => ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash()),
+            ScriptKind::P2tr => ScriptBuf::new_p2tr(&Secp256k1::verification_only(), public_key.0.x_only_public_key().0, None),
+        }
+    }
+}
+
+// SLIP-132 version bytes of single-key extended public keys, the plain xpub
+// version of the same network kind, and the script type the prefix implies
+const SLIP132_VERSIONS: [([u8; 4], [u8; 4], Option<ScriptKind>); 6] = [
+    ([0x04, 0x88, 0xb2, 0x1e], [0x04, 0x88, 0xb2, 0x1e], None), // xpub
+    ([0x04, 0x9d, 0x7c, 0xb2], [0x04, 0x88, 0xb2, 0x1e], Some(ScriptKind::P2shP2wpkh)), // ypub
+    ([0x04, 0xb2, 0x47, 0x46], [0x04, 0x88, 0xb2, 0x1e], Some(ScriptKind::P2wpkh)), // zpub
+    ([0x04, 0x35, 0x87, 0xcf], [0x04, 0x35, 0x87, 0xcf], None), // tpub
+    ([0x04, 0x4a, 0x52, 0x62], [0x04, 0x35, 0x87, 0xcf], Some(ScriptKind::P2shP2wpkh)), // upub
+    ([0x04, 0x5f, 0x1c, 0xf6], [0x04, 0x35, 0x87, 0xcf], Some(ScriptKind::P2wpkh)), // vpub
+];
+
+/// Parse an extended public key in any SLIP-132 single-key encoding
+///
+/// Besides `xpub` and `tpub`, takes `ypub`/`upub` (P2SH-P2WPKH) and
+/// `zpub`/`vpub` (P2WPKH), returning the script type the prefix implies.
+/// The key itself is the same whatever the prefix.
+pub fn parse_slip132(encoded: &str) -> Result<(Xpub, Option<ScriptKind>)> {
+    let mut data = bitcoin::base58::decode_check(encoded.trim())
+        .map_err(|e| Error::DescriptorError(format!("Invalid extended public key: {}", e)))?;
+    let Some(&(_, plain, kind)) = SLIP132_VERSIONS.iter().find(|(version, _, _)| data.starts_with(version)) else {
+        return Err(Error::DescriptorError(
+            "Not an extended public key; expected an xpub, ypub, zpub, tpub, upub or vpub".to_string(),
+        ));
+    };
+    data[..4].copy_from_slice(&plain);
+    let xpub = Xpub::decode(&data).map_err(|e| Error::DescriptorError(format!("Invalid extended public key: {}", e)))?;
+    Ok((xpub, kind))
+}
+
+// Characters an output descriptor may hold, in the order giving their checksum symbols, and the
+// characters of the checksum itself (BIP 380)
+const DESCRIPTOR_INPUT_CHARSET: &str =
+    "0123456789()[],'/*abcdefgh@:$%{}IJKLMNOPQRSTUVWXYZ&+-.;<=>?!^_|~ijklmnopqrstuvwxyzABCDEFGH`#\"\\ ";
+const DESCRIPTOR_CHECKSUM_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+// Function to feed one symbol to the descriptor checksum's BCH code
+fn descriptor_polymod(c: u64, value: u64) -> u64 {
+    const GENERATOR: [u64; 5] = [0xf5dee51989, 0xa9fdca3312, 0x1bab10e32d, 0x3706b1677a, 0x644d626ffd];
+    let top = c >> 35;
+    let mut c = ((c & 0x7ffffffff) << 5) ^ value;
+    for (bit, generator) in GENERATOR.iter().enumerate() {
+        if (top >> bit) & 1 == 1 {
+            c ^= generator;
+        }
+    }
+    c
+}
+
+/// Checksum of an output descriptor, the eight characters Bitcoin Core
+/// expects after its `#` (BIP 380)
+pub fn descriptor_checksum(descriptor: &str) -> Result<String> {
+    let mut c = 1;
+    let mut classes = 0;
+    let mut grouped = 0;
+    for ch in descriptor.chars() {
+        let position = DESCRIPTOR_INPUT_CHARSET
+            .find(ch)
+            .ok_or_else(|| Error::DescriptorError(format!("{:?} cannot appear in a descriptor", ch)))? as u64;
+        c = descriptor_polymod(c, position & 31);
+        classes = classes * 3 + (position >> 5);
+        grouped += 1;
+        if grouped == 3 {
+            c = descriptor_polymod(c, classes);
+            classes = 0;
+            grouped = 0;
+        }
+    }
+    if grouped > 0 {
+        c = descriptor_polymod(c, classes);
+    }
+    for _ in 0..8 {
+        c = descriptor_polymod(c, 0);
+    }
+    c ^= 1;
+    Ok((0..8).map(|j| DESCRIPTOR_CHECKSUM_CHARSET[((c >> (5 * (7 - j))) & 31) as usize] as char).collect())
+}
+
+/// Key origin in descriptor form, `[d34db33f/84h/0h/0h]`
+pub fn key_origin(fingerprint: Fingerprint, path: &DerivationPath) -> String {
+    let mut origin = format!("[{}", fingerprint);
+    for step in path {
+        match step {
+            ChildNumber::Normal { index } => origin.push_str(&format!("/{}", index)),
+            ChildNumber::Hardened { index } => origin.push_str(&format!("/{}h", index)),
+        }
+    }
+    origin.push(']');
+    origin
+}
+
+/// An extended private key that signing keys are derived from, erased on drop
+#[derive(Clone)]
+pub struct SigningXpriv {
+    xpriv: Xpriv,
+}
+
+impl fmt::Debug for SigningXpriv {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigningXpriv")
+            .field("xpriv", &"<redacted>")
+            .field("depth", &self.xpriv.depth)
+            .finish()
+    }
+}
+
+impl Drop for SigningXpriv {
+    fn drop(&mut self) {
+        self.xpriv.private_key.non_secure_erase();
+    }
+}
+
+impl SigningXpriv {
+    /// Parse an xprv (mainnet) or tprv (every test network) for `network`
+    ///
+    /// Errors never include the key.
+    pub fn parse(key: &str, network: BtcNetwork) -> Result<Self> {
+        let xpriv = Xpriv::from_str(key.trim()).map_err(|e| Error::InvalidParameter(format!("Invalid xprv: {}", e)))?;
+        let expected = match network {
+            BtcNetwork::Bitcoin => NetworkKind::Main,
+            _ => NetworkKind::Test,
+        };
+        if xpriv.network != expected {
+            return Err(Error::InvalidNetwork(format!(
+                "{} xprv used on {}",
+                if xpriv.network == NetworkKind::Main { "Mainnet" } else { "Test network" },
+                network
+            )));
+        }
+        Ok(SigningXpriv { xpriv })
+    }
+
+    /// Derive the private key at `path`
+    ///
+    /// The path is relative to this key. A path written from the master key
+    /// (`m/84'/1'/0'/0/5`) also works for an account key: when it is longer
+    /// than the key's depth and its step at that depth is the key's own child
+    /// number, the steps down to the key are dropped. Callers should check
+    /// the result against the output it signs for, since the earlier steps
+    /// cannot be checked.
+    pub fn derive(&self, path: &DerivationPath) -> Result<PrivateKey> {
+        let depth = usize::from(self.xpriv.depth);
+        let steps: &[ChildNumber] = path.as_ref();
+        let relative = if depth > 0 && steps.len() > depth && steps[depth - 1] == self.xpriv.child_number {
+            &steps[depth..]
+        } else {
+            steps
+        };
+        let secp = Secp256k1::new();
+        let child = self
+            .xpriv
+            .derive_priv(&secp, &relative)
+            .map_err(|e| Error::SigningError(format!("Derivation at {} failed: {}", path, e)))?;
+        Ok(child.to_priv())
+    }
+}
+
+/// An account xpub with the script type its addresses use
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountXpub {
+    xpub: Xpub,
+    origin: Option<DerivationPath>,
+    kind: ScriptKind,
+    network: BtcNetwork,
+}
+
+/// An address derived from an account
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivedAddress {
+    /// The address
+    pub address: Address,
+    /// Full path from the master key when the origin is known, else from the xpub
+    pub path: DerivationPath,
+    /// Locking script paying the address
+    pub script_pubkey: ScriptBuf,
+}
+
+impl AccountXpub {
+    /// Parse `[fingerprint/path]xpub` or a bare xpub for `network`
+    ///
+    /// Fails when the key's version prefix belongs to another network kind
+    /// (`xpub` is mainnet, `tpub` every test network).
+    pub fn parse(key: &str, kind: ScriptKind, network: BtcNetwork) -> Result<Self> {
+        let key = key.trim();
+        let (origin, encoded) = match key.strip_prefix('[') {
+            Some(rest) => {
+                let (origin, encoded) = rest
+                    .split_once(']')
+                    .ok_or_else(|| Error::DescriptorError(format!("Unclosed key origin in {:?}", key)))?;
+                let (fingerprint, path) = origin.split_once('/').unwrap_or((origin, ""));
+                Fingerprint::from_str(fingerprint)
+                    .map_err(|e| Error::DescriptorError(format!("Invalid origin fingerprint {:?}: {}", fingerprint, e)))?;
+                let path = DerivationPath::from_str(path)
+                    .map_err(|e| Error::DescriptorError(format!("Invalid origin path {:?}: {}", path, e)))?;
+                (Some(path), encoded)
+            }
+            None => (None, key),
+        };
+        // A private key pasted by mistake is refused without echoing it
+        if Xpriv::from_str(encoded).is_ok() {
+            return Err(Error::DescriptorError("Expected an extended public key, got a private one".to_string()));
+        }
+        let xpub = Xpub::from_str(encoded).map_err(|e| Error::DescriptorError(format!("Invalid xpub: {}", e)))?;
+        let expected = match network {
+            BtcNetwork::Bitcoin => NetworkKind::Main,
+            _ => NetworkKind::Test,
+        };
+        if xpub.network != expected {
+            return Err(Error::InvalidNetwork(format!(
+                "{} key used on {}",
+                if xpub.network == NetworkKind::Main { "Mainnet" } else { "Test network" },
+                network
+            )));
+        }
+        Ok(AccountXpub { xpub, origin, kind, network })
+    }
+
+    /// Path of the account key from the master key, when given
+    pub fn origin(&self) -> Option<&DerivationPath> {
+        self.origin.as_ref()
+    }
+
+    /// Script type of the account's addresses
+    pub fn kind(&self) -> ScriptKind {
+        self.kind
+    }
+
+    /// Derive the address at `branch/index`; branch 0 receives and 1 takes change
+    pub fn derive(&self, branch: u32, index: u32) -> Result<DerivedAddress> {
+        let normal = |n: u32| {
+            ChildNumber::from_normal_idx(n)
+                .map_err(|_| Error::InvalidParameter(format!("Index {} is out of range for unhardened derivation", n)))
+        };
+        let relative = [normal(branch)?, normal(index)?];
+        let secp = Secp256k1::verification_only();
+        let child = self
+            .xpub
+            .derive_pub(&secp, &relative)
+            .map_err(|e| Error::DescriptorError(format!("Derivation failed: {}", e)))?;
+        let public_key = CompressedPublicKey(child.public_key);
+        let network = bitcoin::Network::from(self.network);
+        let address = match self.kind {
+            ScriptKind::P2pkh => Address::p2pkh(public_key, network),
+            ScriptKind::P2shP2wpkh => Address::p2shwpkh(&public_key, network),
+            ScriptKind::P2wpkh => Address::p2wpkh(&public_key, network),
+            ScriptKind::P2tr => Address::p2tr(&secp, child.public_key.x_only_public_key().0, None, network),
+        };
+        let path = match &self.origin {
+            Some(origin) => origin.extend(relative),
+            None => DerivationPath::from(relative.to_vec()),
+        };
+        Ok(DerivedAddress { script_pubkey: address.script_pubkey(), address, path })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // BIP 32 test vector 1 seed
+    const SEED: &str = "000102030405060708090a0b0c0d0e0f";
+
+    // Derive the account xpub and, along the private path, the expected address
+    fn account(network: BtcNetwork, account_path: &str) -> (Xpriv, Xpub) {
+        let secp = Secp256k1::new();
+        let master = Xpriv::new_master(bitcoin::Network::from(network), &hex::decode(SEED).unwrap()).unwrap();
+        let account = master.derive_priv(&secp, &DerivationPath::from_str(account_path).unwrap()).unwrap();
+        (master, Xpub::from_priv(&secp, &account))
+    }
+
+    #[test]
+    fn test_derive_matches_private_derivation() {
+        let secp = Secp256k1::new();
+        let (master, xpub) = account(BtcNetwork::Bitcoin, "m/84'/0'/0'");
+        let key = format!("[{}/84'/0'/0']{}", master.fingerprint(&secp), xpub);
+        let account = AccountXpub::parse(&key, ScriptKind::P2wpkh, BtcNetwork::Bitcoin).unwrap();
+        for index in 0..4 {
+            let path = DerivationPath::from_str(&format!("m/84'/0'/0'/0/{}", index)).unwrap();
+            let private = master.derive_priv(&secp, &path).unwrap().to_priv();
+            let expected = Address::p2wpkh(&CompressedPublicKey::from_private_key(&secp, &private).unwrap(), bitcoin::Network::Bitcoin);
+            let derived = account.derive(0, index).unwrap();
+            assert_eq!(derived.address, expected);
+            assert_eq!(derived.path, path);
+            assert_eq!(derived.script_pubkey, expected.script_pubkey());
+        }
+
+        // Without an origin the path is relative to the xpub
+        let bare = AccountXpub::parse(&xpub.to_string(), ScriptKind::P2pkh, BtcNetwork::Bitcoin).unwrap();
+        let derived = bare.derive(1, 7).unwrap();
+        assert_eq!(derived.path.to_string(), "1/7");
+        assert!(derived.address.to_string().starts_with('1'));
+    }
+
+    #[test]
+    fn test_script_kinds() {
+        let (_, xpub) = account(BtcNetwork::Testnet, "m/86'/1'/0'");
+        let prefixes = [
+            (ScriptKind::P2pkh, "m"),
+            (ScriptKind::P2shP2wpkh, "2"),
+            (ScriptKind::P2wpkh, "tb1q"),
+            (ScriptKind::P2tr, "tb1p"),
+        ];
+        for (kind, prefix) in prefixes {
+            let account = AccountXpub::parse(&xpub.to_string(), kind, BtcNetwork::Testnet).unwrap();
+            let address = account.derive(0, 0).unwrap().address.to_string();
+            assert!(address.starts_with(prefix) || (prefix == "m" && address.starts_with('n')), "{} {}", kind, address);
+            assert_eq!(kind.to_string().parse::<ScriptKind>().unwrap(), kind);
+        }
+    }
+
+    #[test]
+    fn test_signing_xpriv() {
+        let secp = Secp256k1::new();
+        let (master, _) = account(BtcNetwork::Regtest, "m");
+        let path = DerivationPath::from_str("m/84'/1'/0'/0/5").unwrap();
+        let expected = master.derive_priv(&secp, &path).unwrap().to_priv();
+        let from_master = SigningXpriv::parse(&master.to_string(), BtcNetwork::Regtest).unwrap();
+        assert_eq!(from_master.derive(&path).unwrap(), expected);
+
+        // An account key takes the full path or the part below it
+        let account_key = master.derive_priv(&secp, &DerivationPath::from_str("m/84'/1'/0'").unwrap()).unwrap();
+        let account = SigningXpriv::parse(&account_key.to_string(), BtcNetwork::Signet).unwrap();
+        assert_eq!(account.derive(&path).unwrap(), expected);
+        assert_eq!(account.derive(&DerivationPath::from_str("m/0/5").unwrap()).unwrap(), expected);
+        assert!(!format!("{:?}", account).contains(&account_key.to_string()[4..]));
+
+        let public_key = CompressedPublicKey::from_private_key(&secp, &expected).unwrap();
+        for kind in [ScriptKind::P2pkh, ScriptKind::P2shP2wpkh, ScriptKind::P2wpkh, ScriptKind::P2tr] {
+            let script = kind.script_pubkey(public_key);
+            assert_eq!(ScriptKind::from_script(&script), Some(kind));
+        }
+        assert_eq!(ScriptKind::from_script(&ScriptBuf::new_op_return([])), None);
+
+        assert!(matches!(SigningXpriv::parse(&master.to_string(), BtcNetwork::Bitcoin), Err(Error::InvalidNetwork(_))));
+        let error = SigningXpriv::parse(&master.to_string()[..100], BtcNetwork::Regtest).unwrap_err();
+        assert!(!error.to_string().contains(&master.to_string()[4..100]));
+    }
+
+    #[test]
+    fn test_parse_slip132() {
+        // The account key of the BIP 84 test vector, as xpub and zpub
+        let zpub = "zpub6rFR7y4Q2AijBEqTUquhVz398htDFrtymD9xYYfG1m4wAcvPhXNfE3EfH1r1ADqtfSdVCToUG868RvUUkgDKf31mGDtKsAYz2oz2AGutZYs";
+        let (xpub, kind) = parse_slip132(zpub).unwrap();
+        assert_eq!(kind, Some(ScriptKind::P2wpkh));
+        assert_eq!(xpub.network, NetworkKind::Main);
+        let (plain, kind) = parse_slip132(&xpub.to_string()).unwrap();
+        assert_eq!((plain, kind), (xpub, None));
+        let bip84 = AccountXpub::parse(&xpub.to_string(), ScriptKind::P2wpkh, BtcNetwork::Bitcoin).unwrap();
+        assert_eq!(bip84.derive(0, 0).unwrap().address.to_string(), "bc1qcr8te4kr609gcawutmrza0j4xv80jy8z306fyu");
+
+        let (_, tpub) = account(BtcNetwork::Testnet, "m/49'/1'/0'");
+        let (parsed, kind) = parse_slip132(&tpub.to_string()).unwrap();
+        assert_eq!((parsed, kind), (tpub, None));
+
+        let (master, _) = account(BtcNetwork::Bitcoin, "m");
+        let error = parse_slip132(&master.to_string()).unwrap_err();
+        assert!(!error.to_string().contains(&master.to_string()[4..]));
+        assert!(parse_slip132(&zpub[..zpub.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_bad_keys() {
+        let (_, mainnet) = account(BtcNetwork::Bitcoin, "m/84'/0'/0'");
+        let (_, testnet) = account(BtcNetwork::Testnet, "m/84'/1'/0'");
+        assert!(matches!(
+            AccountXpub::parse(&mainnet.to_string(), ScriptKind::P2wpkh, BtcNetwork::Testnet),
+            Err(Error::InvalidNetwork(_))
+        ));
+        assert!(matches!(
+            AccountXpub::parse(&testnet.to_string(), ScriptKind::P2wpkh, BtcNetwork::Bitcoin),
+            Err(Error::InvalidNetwork(_))
+        ));
+        assert!(AccountXpub::parse(&testnet.to_string(), ScriptKind::P2wpkh, BtcNetwork::Signet).is_ok());
+        assert!(matches!(
+            AccountXpub::parse("xpub-nonsense", ScriptKind::P2wpkh, BtcNetwork::Bitcoin),
+            Err(Error::DescriptorError(_))
+        ));
+        assert!(matches!(
+            AccountXpub::parse(&format!("[d34db33f/84'{}", mainnet), ScriptKind::P2wpkh, BtcNetwork::Bitcoin),
+            Err(Error::DescriptorError(_))
+        ));
+
+        let (master, _) = account(BtcNetwork::Bitcoin, "m");
+        let error = AccountXpub::parse(&master.to_string(), ScriptKind::P2wpkh, BtcNetwork::Bitcoin).unwrap_err();
+        assert!(!error.to_string().contains(&master.to_string()[4..]));
+
+        let account = AccountXpub::parse(&mainnet.to_string(), ScriptKind::P2wpkh, BtcNetwork::Bitcoin).unwrap();
+        assert!(matches!(account.derive(0, 1 << 31), Err(Error::InvalidParameter(_))));
+        assert!("p2sh".parse::<ScriptKind>().is_err());
+    }
+
+    #[test]
+    fn test_descriptor_checksum() {
+        // BIP 380, and the example of Bitcoin Core's deriveaddresses help
+        assert_eq!(descriptor_checksum("raw(deadbeef)").unwrap(), "89f8spxm");
+        let key = "[d34db33f/84h/0h/0h]xpub6DJ2dNUysrn5Vt36jH2KLBT2i1auw1tTSSomg8PhqNiUtx8QX2SvC9nrHu81fT41fvDUnhMjEzQgXnQjKEu3oaqMSzhSrHMxyyoEAmUHQbY/0/*";
+        assert_eq!(
+            ScriptKind::P2wpkh.descriptor(key).unwrap(),
+            format!("wpkh({})#cjjspncu", key)
+        );
+        assert!(matches!(descriptor_checksum("raw(dé)"), Err(Error::DescriptorError(_))));
+
+        let fingerprint = Fingerprint::from_str("d34db33f").unwrap();
+        let path = DerivationPath::from_str("m/84'/0'/0'/1").unwrap();
+        assert_eq!(key_origin(fingerprint, &path), "[d34db33f/84h/0h/0h/1]");
+        assert_eq!(key_origin(fingerprint, &DerivationPath::master()), "[d34db33f]");
+        assert!(ScriptKind::P2shP2wpkh.descriptor("xpub").unwrap().starts_with("sh(wpkh(xpub))#"));
+    }
+}
@@ -1,8 +1,22 @@
 //! Bitcoin Tools Library
 //! Common types and utilities for Bitcoin-related command-line tools.
 
+pub mod broadcast;
+pub mod cache;
+pub mod config;
 pub mod error;
+pub mod hd;
+pub mod mnemonic;
+pub mod multisig;
+pub mod network;
+pub mod psbt;
+pub mod signing;
+pub mod spv;
+pub mod transaction_builder;
 pub mod types;
+pub mod utils;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
 // Re-exports
 pub use bitcoin::{
@@ -10,5 +24,5 @@ pub use bitcoin::{
     Transaction, TxIn, TxOut, Txid, Witness, consensus, hashes::hex::FromHex, secp256k1,
     Network, PublicKey,
 };
-pub use error::{Error, Result};
+pub use error::{Error, Result, RpcError};
 pub use types::*;
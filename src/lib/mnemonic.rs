@@ -0,0 +1,660 @@
+//! BIP39 mnemonics
+//!
+//! Entropy of 16 to 32 bytes, plus a checksum of its SHA-256, is written as
+//! 12 to 24 words from one of the official 2048-word lists, which are built
+//! into the library. The seed is PBKDF2 over the NFKD-normalized phrase.
+//! Lower-level functions take the wordlist as a slice, for callers with a
+//! list of their own.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use bitcoin::secp256k1::rand::rngs::OsRng;
+use bitcoin::secp256k1::rand::RngCore;
+use bitvec::prelude::*;
+use pbkdf2::pbkdf2_hmac;
+use sha2::{Digest, Sha256, Sha512};
+use unicode_normalization::UnicodeNormalization;
+use zeroize::Zeroizing;
+
+use crate::error::{Error, Result};
+
+/// Numbers of words a mnemonic may have
+pub const WORD_COUNTS: [usize; 5] = [12, 15, 18, 21, 24];
+
+/// Words in every BIP39 list
+pub const WORDLIST_LENGTH: usize = 2048;
+
+const PBKDF2_ROUNDS: u32 = 2048;
+
+/// An official BIP39 wordlist
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Language {
+    /// English, the default
+    English,
+    /// Spanish
+    Spanish,
+    /// French
+    French,
+    /// Italian
+    Italian,
+    /// Japanese, whose phrases are joined with the ideographic space
+    Japanese,
+    /// Korean
+    Korean,
+    /// Simplified Chinese
+    ChineseSimplified,
+    /// Traditional Chinese
+    ChineseTraditional,
+    /// Czech
+    Czech,
+    /// Portuguese
+    Portuguese,
+}
+
+// Name, list (one word per line), and SHA-256 of the official file with a trailing newline
+struct LanguageData {
+    name: &'static str,
+    words: &'static str,
+    sha256: &'static str,
+}
+
+const LANGUAGE_DATA: [LanguageData; 10] = [
+    LanguageData {
+        name: "english",
+        words: include_str!("../../wordlists/english.txt"),
+        sha256: "2f5eed53a4727b4bf8880d8f3f199efc90e58503646d9ff8eff3a2ed3b24dbda",
+    },
+    LanguageData {
+        name: "spanish",
+        words: include_str!("../../wordlists/spanish.txt"),
+        sha256: "46846a5a0139d1e3cb77293e521c2865f7bcdb82c44e8d0a06a2cd0ecba48c0b",
+    },
+    LanguageData {
+        name: "french",
+        words: include_str!("../../wordlists/french.txt"),
+        sha256: "ebc3959ab7801a1df6bac4fa7d970652f1df76b683cd2f4003c941c63d517e59",
+    },
+    LanguageData {
+        name: "italian",
+        words: include_str!("../../wordlists/italian.txt"),
+        sha256: "d392c49fdb700a24cd1fceb237c1f65dcc128f6b34a8aacb58b59384b5c648c2",
+    },
+    LanguageData {
+        name: "japanese",
+        words: include_str!("../../wordlists/japanese.txt"),
+        sha256: "2eed0aef492291e061633d7ad8117f1a2b03eb80a29d0e4e3117ac2528d05ffd",
+    },
+    LanguageData {
+        name: "korean",
+        words: include_str!("../../wordlists/korean.txt"),
+        sha256: "9e95f86c167de88f450f0aaf89e87f6624a57f973c67b516e338e8e8b8897f60",
+    },
+    LanguageData {
+        name: "chinese_simplified",
+        words: include_str!("../../wordlists/chinese_simplified.txt"),
+        sha256: "5c5942792bd8340cb8b27cd592f1015edf56a8c5b26276ee18a482428e7c5726",
+    },
+    LanguageData {
+        name: "chinese_traditional",
+        words: include_str!("../../wordlists/chinese_traditional.txt"),
+        sha256: "417b26b3d8500a4ae3d59717d7011952db6fc2fb84b807f3f94ac734e89c1b5f",
+    },
+    LanguageData {
+        name: "czech",
+        words: include_str!("../../wordlists/czech.txt"),
+        sha256: "7e80e161c3e93d9554c2efb78d4e3cebf8fc727e9c52e03b83b94406bdcc95fc",
+    },
+    LanguageData {
+        name: "portuguese",
+        words: include_str!("../../wordlists/portuguese.txt"),
+        sha256: "2685e9c194c82ae67e10ba59d9ea5345a23dc093e92276fc5361f6667d79cd3f",
+    },
+];
+
+impl Language {
+    /// Every language, in the order of the BIP39 repository
+    pub const ALL: [Language; 10] = [
+        Language::English,
+        Language::Spanish,
+        Language::French,
+        Language::Italian,
+        Language::Japanese,
+        Language::Korean,
+        Language::ChineseSimplified,
+        Language::ChineseTraditional,
+        Language::Czech,
+        Language::Portuguese,
+    ];
+
+    fn data(self) -> &'static LanguageData {
+        &LANGUAGE_DATA[self as usize]
+    }
+
+    /// Name of the list's file, e.g. `chinese_simplified`
+    pub fn name(self) -> &'static str {
+        self.data().name
+    }
+
+    /// SHA-256 of the official list file
+    pub fn sha256(self) -> &'static str {
+        self.data().sha256
+    }
+
+    /// String joining the words of a phrase
+    pub fn separator(self) -> &'static str {
+        match self {
+            Language::Japanese => "\u{3000}",
+            _ => " ",
+        }
+    }
+
+    /// The 2048 words, parsed on first use
+    pub fn wordlist(self) -> &'static [&'static str] {
+        static WORDLISTS: [OnceLock<Vec<&'static str>>; 10] = [const { OnceLock::new() }; 10];
+        WORDLISTS[self as usize].get_or_init(|| parse_wordlist(self.data().words).expect("built-in wordlists have 2048 words"))
+    }
+
+    /// The language whose list holds the most of the phrase's words, the
+    /// first listed winning ties
+    pub fn detect(phrase: &str) -> Language {
+        let normalized = normalize(phrase);
+        let mut best = (Language::English, 0);
+        for language in Language::ALL {
+            let indexes = word_indexes(language.wordlist());
+            let known = normalized.split_whitespace().filter(|word| indexes.contains_key(*word)).count();
+            if known > best.1 {
+                best = (language, known);
+            }
+        }
+        best.0
+    }
+}
+
+impl FromStr for Language {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let name = s.trim().to_ascii_lowercase();
+        Language::ALL.into_iter().find(|language| language.name() == name).ok_or_else(|| {
+            let names: Vec<&str> = Language::ALL.iter().map(|language| language.name()).collect();
+            Error::InvalidParameter(format!("Unknown language {:?}; use {}", s, names.join(", ")))
+        })
+    }
+}
+
+impl fmt::Display for Language {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Why entropy or a phrase does not make a valid mnemonic
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum MnemonicError {
+    /// Entropy that is not 16, 20, 24, 28 or 32 bytes
+    #[error("the entropy is {0} bytes; it must be 16, 20, 24, 28 or 32")]
+    EntropyLength(usize),
+
+    /// A phrase or request with an unsupported number of words
+    #[error("the phrase has {0} words; a mnemonic has 12, 15, 18, 21 or 24")]
+    WordCount(usize),
+
+    /// A word missing from the wordlist, 0-based, with a listed word one edit away
+    #[error("word {} is not in the wordlist{}", index + 1, suggestion.as_ref().map(|word| format!("; did you mean \"{}\"?", word)).unwrap_or_default())]
+    UnknownWord {
+        /// Position of the word in the phrase
+        index: usize,
+        /// A listed word one insertion, deletion or substitution away
+        suggestion: Option<String>,
+    },
+
+    /// Words that do not carry the checksum of the entropy they give
+    #[error("the checksum does not match; a word is wrong or the words are out of order")]
+    Checksum,
+
+    /// A wordlist without exactly 2048 words
+    #[error("Wordlist must contain exactly 2048 words, not {0}")]
+    WordlistLength(usize),
+}
+
+/// A valid mnemonic: its entropy and the language of its words
+///
+/// The phrase, entropy and seed are secrets; `Debug` shows only the language
+/// and word count, and the buffers are zeroized on drop.
+#[derive(Clone)]
+pub struct Mnemonic {
+    language: Language,
+    entropy: Zeroizing<Vec<u8>>,
+    phrase: Zeroizing<String>,
+}
+
+impl Mnemonic {
+    /// The phrase, its words joined with the language's separator
+    pub fn phrase(&self) -> &str {
+        &self.phrase
+    }
+
+    /// Language of the words
+    pub fn language(&self) -> Language {
+        self.language
+    }
+
+    /// Number of words
+    pub fn word_count(&self) -> usize {
+        self.entropy.len() * 3 / 4
+    }
+
+    /// The entropy the words encode
+    pub fn to_entropy(&self) -> Zeroizing<Vec<u8>> {
+        self.entropy.clone()
+    }
+
+    /// BIP39 seed for a passphrase (empty for none)
+    pub fn to_seed(&self, passphrase: &str) -> Zeroizing<[u8; 64]> {
+        mnemonic_to_seed(&self.phrase, passphrase)
+    }
+}
+
+impl fmt::Debug for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mnemonic")
+            .field("language", &self.language)
+            .field("words", &self.word_count())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Generate a mnemonic of `word_count` words from the operating system's
+/// random number generator
+pub fn generate(word_count: usize, language: Language) -> Result<Mnemonic> {
+    if !WORD_COUNTS.contains(&word_count) {
+        return Err(MnemonicError::WordCount(word_count).into());
+    }
+    let mut entropy = Zeroizing::new(vec![0u8; word_count / 3 * 4]);
+    OsRng
+        .try_fill_bytes(&mut entropy)
+        .map_err(|e| Error::Custom(format!("OS random number generator failed: {}", e)))?;
+    from_entropy(&entropy, language)
+}
+
+/// The mnemonic for exactly these 16, 20, 24, 28 or 32 bytes
+pub fn from_entropy(entropy: &[u8], language: Language) -> Result<Mnemonic> {
+    let words = entropy_to_words(entropy, language.wordlist())?;
+    Ok(Mnemonic {
+        language,
+        entropy: Zeroizing::new(entropy.to_vec()),
+        phrase: Zeroizing::new(words.join(language.separator())),
+    })
+}
+
+/// Check a phrase in a language: its length, every word, then the checksum.
+/// Case, Unicode normalization and the kind of whitespace between words do
+/// not matter; the mnemonic keeps the canonical phrase
+pub fn validate(phrase: &str, language: Language) -> Result<Mnemonic> {
+    let entropy = words_to_entropy(phrase, language.wordlist())?;
+    from_entropy(&entropy, language)
+}
+
+/// Split a wordlist into words, one per line
+pub fn parse_wordlist(text: &str) -> std::result::Result<Vec<&str>, MnemonicError> {
+    let wordlist: Vec<&str> = text.lines().map(|line| line.trim_end_matches('\r')).collect();
+    if wordlist.len() != WORDLIST_LENGTH {
+        return Err(MnemonicError::WordlistLength(wordlist.len()));
+    }
+    Ok(wordlist)
+}
+
+/// SHA-256 of the words as the official files lay them out, so a missing
+/// final newline or CRLF line endings do not count as a difference
+pub fn wordlist_digest(wordlist: &[&str]) -> String {
+    let mut hasher = Sha256::new();
+    for word in wordlist {
+        hasher.update(word.as_bytes());
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+/// Normalize a phrase for lookups: NFKD, as BIP39 requires, so composed and
+/// decomposed accents and kana match, and lower case
+pub fn normalize(phrase: &str) -> String {
+    phrase.nfkd().collect::<String>().to_lowercase()
+}
+
+// Map each normalized word to its index
+fn word_indexes(wordlist: &[&str]) -> HashMap<String, usize> {
+    wordlist.iter().enumerate().map(|(i, word)| (normalize(word), i)).collect()
+}
+
+// Append the checksum to the entropy: the first ENT/32 bits of its SHA-256.
+// Read 11 at a time, these bits are the mnemonic's word indexes
+fn checksummed_bits(entropy: &[u8]) -> BitVec<u8, Msb0> {
+    let hash = Sha256::digest(entropy);
+    let cs_bits = entropy.len() * 8 / 32;
+    let mut bits = BitVec::<u8, Msb0>::from_slice(entropy);
+    bits.extend_from_bitslice(&BitVec::<u8, Msb0>::from_slice(&hash)[0..cs_bits]);
+    bits
+}
+
+/// Turn entropy into words by splitting the checksummed bits into 11-bit
+/// indexes into the wordlist
+pub fn entropy_to_words<'a>(entropy: &[u8], wordlist: &[&'a str]) -> std::result::Result<Vec<&'a str>, MnemonicError> {
+    if ![16, 20, 24, 28, 32].contains(&entropy.len()) {
+        return Err(MnemonicError::EntropyLength(entropy.len()));
+    }
+    if wordlist.len() != WORDLIST_LENGTH {
+        return Err(MnemonicError::WordlistLength(wordlist.len()));
+    }
+    Ok(checksummed_bits(entropy)
+        .chunks_exact(11)
+        .map(|chunk| wordlist[chunk.load_be::<u16>() as usize])
+        .collect())
+}
+
+/// Read the entropy back out of a phrase, checking its length, every word,
+/// then the checksum. Whitespace of any kind, including the ideographic
+/// space, separates words
+pub fn words_to_entropy(phrase: &str, wordlist: &[&str]) -> std::result::Result<Zeroizing<Vec<u8>>, MnemonicError> {
+    let word_to_index = word_indexes(wordlist);
+    let normalized = Zeroizing::new(normalize(phrase));
+    let words: Vec<&str> = normalized.split_whitespace().collect();
+    if !WORD_COUNTS.contains(&words.len()) {
+        return Err(MnemonicError::WordCount(words.len()));
+    }
+    let ent_bytes = words.len() / 3 * 4;
+
+    let mut bits = BitVec::<u8, Msb0>::new();
+    for (position, &word) in words.iter().enumerate() {
+        let Some(&index) = word_to_index.get(word) else {
+            let suggestion = suggest_word(word, wordlist).map(str::to_string);
+            return Err(MnemonicError::UnknownWord { index: position, suggestion });
+        };
+        bits.extend_from_bitslice(&(index as u16).view_bits::<Msb0>()[5..]);
+    }
+
+    // The entropy is all but the checksum bits, and must checksum to the bits it came with
+    let entropy = Zeroizing::new(bits[0..ent_bytes * 8].to_bitvec().into_vec());
+    if checksummed_bits(&entropy) != bits {
+        return Err(MnemonicError::Checksum);
+    }
+    Ok(entropy)
+}
+
+/// A listed word one insertion, deletion or substitution away from `word`
+pub fn suggest_word<'a>(word: &str, wordlist: &[&'a str]) -> Option<&'a str> {
+    let typed: Vec<char> = word.chars().collect();
+    let one_edit = |listed: &str| {
+        let listed: Vec<char> = normalize(listed).chars().collect();
+        let (shorter, longer) = if typed.len() <= listed.len() { (&typed, &listed) } else { (&listed, &typed) };
+        if longer.len() - shorter.len() > 1 {
+            return false;
+        }
+        let prefix = shorter.iter().zip(longer.iter()).take_while(|(a, b)| a == b).count();
+        // Past the first difference, the rest must match after skipping one character of the
+        // longer word, or one of each for a substitution
+        let skip = if shorter.len() == longer.len() { prefix + 1 } else { prefix };
+        prefix < longer.len() && shorter.get(skip..) == longer.get(prefix + 1..)
+    };
+    wordlist.iter().copied().find(|listed| one_edit(listed))
+}
+
+/// BIP39 seed: PBKDF2-HMAC-SHA512 over the NFKD phrase, salted with
+/// "mnemonic" and the NFKD passphrase
+pub fn mnemonic_to_seed(phrase: &str, passphrase: &str) -> Zeroizing<[u8; 64]> {
+    let phrase = Zeroizing::new(phrase.nfkd().collect::<String>());
+    let salt = Zeroizing::new(format!("mnemonic{}", passphrase.nfkd().collect::<String>()));
+    let mut seed = Zeroizing::new([0u8; 64]);
+    pbkdf2_hmac::<Sha512>(phrase.as_bytes(), salt.as_bytes(), PBKDF2_ROUNDS, seed.as_mut());
+    seed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::hex::{DisplayHex, FromHex};
+
+    fn entropy(entropy_hex: &str) -> Vec<u8> {
+        Vec::from_hex(entropy_hex).unwrap()
+    }
+
+    #[test]
+    fn test_wordlists() {
+        let wordlist = Language::English.wordlist();
+        assert_eq!(wordlist[0], "abandon");
+        assert_eq!(wordlist[2047], "zoo");
+        for language in Language::ALL {
+            assert_eq!(language.wordlist().len(), WORDLIST_LENGTH);
+            assert_eq!(wordlist_digest(language.wordlist()), language.sha256(), "{}", language);
+            assert_eq!(language.name().parse::<Language>().unwrap(), language);
+        }
+        assert_eq!("Japanese".parse::<Language>().unwrap(), Language::Japanese);
+        assert!("klingon".parse::<Language>().is_err());
+
+        // Line endings do not change the digest, but the order does
+        let crlf = LANGUAGE_DATA[0].words.replace('\n', "\r\n");
+        assert_eq!(wordlist_digest(&parse_wordlist(&crlf).unwrap()), Language::English.sha256());
+        let mut reordered = wordlist.to_vec();
+        reordered.swap(0, 1);
+        assert_ne!(wordlist_digest(&reordered), Language::English.sha256());
+        assert_eq!(parse_wordlist("abandon\nability\n"), Err(MnemonicError::WordlistLength(2)));
+    }
+
+    #[test]
+    fn test_bip39_vectors() {
+        // The English BIP39 test vectors, both ways
+        let vectors = [
+            (
+                "00000000000000000000000000000000",
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about",
+            ),
+            ("7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f", "legal winner thank year wave sausage worth useful legal winner thank yellow"),
+            ("80808080808080808080808080808080", "letter advice cage absurd amount doctor acoustic avoid letter advice cage above"),
+            ("ffffffffffffffffffffffffffffffff", "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo wrong"),
+            (
+                "000000000000000000000000000000000000000000000000",
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon agent",
+            ),
+            (
+                "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+                "legal winner thank year wave sausage worth useful legal winner thank year wave sausage worth useful legal will",
+            ),
+            (
+                "808080808080808080808080808080808080808080808080",
+                "letter advice cage absurd amount doctor acoustic avoid letter advice cage absurd amount doctor acoustic avoid letter always",
+            ),
+            (
+                "ffffffffffffffffffffffffffffffffffffffffffffffff",
+                "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo when",
+            ),
+            (
+                "0000000000000000000000000000000000000000000000000000000000000000",
+                "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon art",
+            ),
+            (
+                "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+                "legal winner thank year wave sausage worth useful legal winner thank year wave sausage worth useful legal winner thank year wave sausage worth title",
+            ),
+            (
+                "8080808080808080808080808080808080808080808080808080808080808080",
+                "letter advice cage absurd amount doctor acoustic avoid letter advice cage absurd amount doctor acoustic avoid letter advice cage absurd amount doctor acoustic bless",
+            ),
+            (
+                "ffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffff",
+                "zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo zoo vote",
+            ),
+            ("9e885d952ad362caeb4efe34a8e91bd2", "ozone drill grab fiber curtain grace pudding thank cruise elder eight picnic"),
+            (
+                "6610b25967cdcca9d59875f5cb50b0ea75433311869e930b",
+                "gravity machine north sort system female filter attitude volume fold club stay feature office ecology stable narrow fog",
+            ),
+            (
+                "68a79eaca2324873eacc50cb9c6eca8cc68ea5d936f98787c60c7ebc74e6ce7c",
+                "hamster diagram private dutch cause delay private meat slide toddler razor book happy fancy gospel tennis maple dilemma loan word shrug inflict delay length",
+            ),
+            ("c0ba5a8e914111210f2bd131f3d5e08d", "scheme spot photo card baby mountain device kick cradle pact join borrow"),
+            (
+                "6d9be1ee6ebd27a258115aad99b7317b9c8d28b6d76431c3",
+                "horn tenant knee talent sponsor spell gate clip pulse soap slush warm silver nephew swap uncle crack brave",
+            ),
+            (
+                "9f6a2878b2520799a44ef18bc7df394e7061a224d2c33cd015b157d746869863",
+                "panda eyebrow bullet gorilla call smoke muffin taste mesh discover soft ostrich alcohol speed nation flash devote level hobby quick inner drive ghost inside",
+            ),
+            ("23db8160a31d3e0dca3688ed941adbf3", "cat swing flag economy stadium alone churn speed unique patch report train"),
+            (
+                "8197a4a47f0425faeaa69deebc05ca29c0a5b5cc76ceacc0",
+                "light rule cinnamon wrap drastic word pride squirrel upgrade then income fatal apart sustain crack supply proud access",
+            ),
+            (
+                "066dca1a2bb7e8a1db2832148ce9933eea0f3ac9548d793112d9a95c9407efad",
+                "all hour make first leader extend hole alien behind guard gospel lava path output census museum junior mass reopen famous sing advance salt reform",
+            ),
+            ("f30f8c1da665478f49b001d94c5fc452", "vessel ladder alter error federal sibling chat ability sun glass valve picture"),
+            (
+                "c10ec20dc3cd9f652c7fac2f1230f7a3c828389a14392f05",
+                "scissors invite lock maple supreme raw rapid void congress muscle digital elegant little brisk hair mango congress clump",
+            ),
+            (
+                "f585c11aec520db57dd353c69554b21a89b20fb0650966fa0a9d6f74fd989d8f",
+                "void come effort suffer camp survey warrior heavy shoot primary clutch crush open amazing screen patrol group space point ten exist slush involve unfold",
+            ),
+        ];
+        for (entropy_hex, expected) in vectors {
+            let mnemonic = from_entropy(&entropy(entropy_hex), Language::English).unwrap();
+            assert_eq!(mnemonic.phrase(), expected);
+            assert_eq!(mnemonic.word_count(), expected.split(' ').count());
+            let parsed = validate(expected, Language::English).unwrap();
+            assert_eq!(*parsed.to_entropy(), entropy(entropy_hex));
+        }
+        let abandon = ["abandon"; 12].join(" ");
+        assert!(matches!(validate(&abandon, Language::English), Err(Error::Mnemonic(MnemonicError::Checksum))));
+
+        // Only whole BIP39 entropy lengths
+        for length in [0, 15, 17, 33] {
+            let err = from_entropy(&vec![0; length], Language::English).unwrap_err();
+            assert!(matches!(err, Error::Mnemonic(MnemonicError::EntropyLength(l)) if l == length));
+        }
+        assert!(matches!(generate(13, Language::English), Err(Error::Mnemonic(MnemonicError::WordCount(13)))));
+        for words in WORD_COUNTS {
+            let mnemonic = generate(words, Language::Czech).unwrap();
+            assert_eq!(mnemonic.word_count(), words);
+            assert_eq!(mnemonic.language(), Language::Czech);
+            assert!(validate(mnemonic.phrase(), Language::Czech).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_other_languages() {
+        // The Japanese vectors from the BIP39 reference test set, joined with U+3000
+        let vectors = [
+            (
+                "00000000000000000000000000000000",
+                "あいこくしん　あいこくしん　あいこくしん　あいこくしん　あいこくしん　あいこくしん　あいこくしん　あいこくしん　あいこくしん　あいこくしん　あいこくしん　あおぞら",
+            ),
+            (
+                "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+                "そつう　れきだい　ほんやく　わかす　りくつ　ばいか　ろせん　やちん　そつう　れきだい　ほんやく　わかめ",
+            ),
+        ];
+        for (entropy_hex, expected) in vectors {
+            let mnemonic = from_entropy(&entropy(entropy_hex), Language::Japanese).unwrap();
+            // The list is stored decomposed, the vectors composed
+            assert_eq!(mnemonic.phrase().nfc().collect::<String>(), expected);
+            assert!(validate(expected, Language::Japanese).is_ok());
+            assert!(validate(mnemonic.phrase(), Language::Japanese).is_ok());
+        }
+
+        // A 15-word Japanese phrase
+        let mnemonic = from_entropy(&[0x5a; 20], Language::Japanese).unwrap();
+        assert_eq!(mnemonic.phrase().split('\u{3000}').count(), 15);
+        assert!(!mnemonic.phrase().contains(' '));
+        assert!(validate(mnemonic.phrase(), Language::English).is_err());
+
+        // Accented words match however they are composed
+        let mnemonic = from_entropy(&[0; 16], Language::Spanish).unwrap();
+        let composed = mnemonic.phrase().nfc().collect::<String>();
+        assert_eq!(composed, format!("{} abierto", ["ábaco"; 11].join(" ")));
+        assert!(validate(&composed, Language::Spanish).is_ok());
+
+        for (language, last) in [(Language::Czech, "agrese"), (Language::ChineseSimplified, "在")] {
+            assert_eq!(entropy_to_words(&[0; 16], language.wordlist()).unwrap()[11], last);
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let wordlist = Language::English.wordlist();
+        let phrase = "legal winner thank year wave sausage worth useful legal winner thank yellow";
+        assert!(words_to_entropy(phrase, wordlist).is_ok());
+        // Case and spacing do not matter, and the mnemonic keeps the canonical phrase
+        let messy = "  Legal WINNER thank\tyear wave sausage worth useful legal winner thank yellow\n";
+        assert_eq!(validate(messy, Language::English).unwrap().phrase(), phrase);
+        assert_eq!(Language::detect(phrase), Language::English);
+
+        let typo = phrase.replace("sausage", "sausge");
+        let expected = MnemonicError::UnknownWord { index: 5, suggestion: Some("sausage".to_string()) };
+        assert_eq!(words_to_entropy(&typo, wordlist), Err(expected));
+        let err = validate(&typo, Language::English).unwrap_err();
+        assert_eq!(err.to_string(), "Invalid mnemonic: word 6 is not in the wordlist; did you mean \"sausage\"?");
+        let far = phrase.replace("sausage", "xyzzy");
+        let expected = MnemonicError::UnknownWord { index: 5, suggestion: None };
+        assert_eq!(words_to_entropy(&far, wordlist), Err(expected));
+        assert_eq!(suggest_word("abandonn", wordlist), Some("abandon"));
+        assert_eq!(suggest_word("bandon", wordlist), Some("abandon"));
+
+        // Every word is listed, but swapping two changes the checksum
+        let mut shuffled: Vec<&str> = phrase.split(' ').collect();
+        shuffled.swap(0, 1);
+        assert_eq!(words_to_entropy(&shuffled.join(" "), wordlist), Err(MnemonicError::Checksum));
+
+        let short: Vec<&str> = phrase.split(' ').take(11).collect();
+        assert_eq!(words_to_entropy(&short.join(" "), wordlist), Err(MnemonicError::WordCount(11)));
+
+        let mnemonic = from_entropy(&[0x7f; 16], Language::Japanese).unwrap();
+        assert_eq!(Language::detect(mnemonic.phrase()), Language::Japanese);
+    }
+
+    #[test]
+    fn test_seed() {
+        // From the BIP39 test vectors, all with the passphrase "TREZOR"
+        let vectors = [
+            (
+                "00000000000000000000000000000000",
+                "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04",
+            ),
+            (
+                "7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f7f",
+                "2e8905819b8723fe2c1d161860e5ee1830318dbf49a83bd451cfb8440c28bd6fa457fe1296106559a3c80937a1c1069be3a3a5bd381ee6260e8d9739fce1f607",
+            ),
+            (
+                "80808080808080808080808080808080",
+                "d71de856f81a8acc65e6fc851a38d4d7ec216fd0796d0a6827a3ad6ed5511a30fa280f12eb2e47ed2ac03b5c462a0358d18d69fe4f985ec81778c1b370b652a8",
+            ),
+            (
+                "ffffffffffffffffffffffffffffffff",
+                "ac27495480225222079d7be181583751e86f571027b0497b5b5d11218e0a8a13332572917f0f8e5a589620c6f15b11c61dee327651a14c34e18231052e48c069",
+            ),
+            (
+                "68a79eaca2324873eacc50cb9c6eca8cc68ea5d936f98787c60c7ebc74e6ce7c",
+                "64c87cde7e12ecf6704ab95bb1408bef047c22db4cc7491c4271d170a1b213d20b385bc1588d9c7b38f1b39d415665b8a9030c9ec653d75e65f847d8fc1fc440",
+            ),
+        ];
+        for (entropy_hex, seed_hex) in vectors {
+            let mnemonic = from_entropy(&entropy(entropy_hex), Language::English).unwrap();
+            assert_eq!(mnemonic.to_seed("TREZOR").to_lower_hex_string(), seed_hex);
+        }
+
+        // The Japanese vector: the ideographic spaces and the passphrase are NFKD normalized
+        let mnemonic = from_entropy(&[0; 16], Language::Japanese).unwrap();
+        assert_eq!(
+            mnemonic.to_seed("㍍ガバヴァぱばぐゞちぢ十人十色").to_lower_hex_string(),
+            "a262d6fb6122ecf45be09c50492b31f92e9beb7d9a845987a02cefda57a15f9c467a17872029a9e92299b5cbdf306e3a0ee620245cbd508959b6cb7ca637bd55"
+        );
+
+        // Secrets stay out of Debug
+        assert_eq!(format!("{:?}", mnemonic), "Mnemonic { language: Japanese, words: 12, .. }");
+    }
+}
@@ -0,0 +1,336 @@
+//! Bare `m`-of-`n` CHECKMULTISIG redeem scripts spent through P2SH
+//!
+//! Signatures are gathered one signer at a time in the input's scriptSig, in
+//! the form `OP_0 <sig>... <redeem script>`. The leading `OP_0` is the extra
+//! item CHECKMULTISIG pops, and signatures follow the order of their keys in
+//! the redeem script, as CHECKMULTISIG requires.
+//!
+//! [`MultisigScript::sorted`] builds the BIP 67 `sortedmulti` form, which
+//! also serves as a P2WSH witness script.
+
+use bitcoin::hashes::Hash;
+use bitcoin::opcodes::all::OP_CHECKMULTISIG;
+use bitcoin::opcodes::{self, Class, ClassifyContext};
+use bitcoin::script::{Builder, Instruction, PushBytesBuf};
+use bitcoin::secp256k1::{Message, Secp256k1};
+use bitcoin::sighash::SighashCache;
+use bitcoin::{ecdsa, PublicKey, Script, ScriptBuf, Transaction};
+
+use crate::error::{Error, Result};
+
+/// Keys and threshold of a multisig redeem script
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MultisigScript {
+    /// Signatures needed to spend
+    pub required: usize,
+    /// Keys that may sign, in script order
+    pub pubkeys: Vec<PublicKey>,
+}
+
+impl MultisigScript {
+    /// Parse `OP_m <pubkey>... OP_n OP_CHECKMULTISIG`
+    pub fn parse(redeem_script: &Script) -> Result<Self> {
+        let invalid = || Error::InvalidParameter("Redeem script is not a bare m-of-n CHECKMULTISIG script".to_string());
+        let number = |instruction: Option<std::result::Result<Instruction, _>>| match instruction {
+            Some(Ok(Instruction::Op(op))) => match op.classify(ClassifyContext::Legacy) {
+                Class::PushNum(n) if n > 0 => Some(n as usize),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let mut instructions = redeem_script.instructions().peekable();
+        let required = number(instructions.next()).ok_or_else(invalid)?;
+        let mut pubkeys = Vec::new();
+        while let Some(Ok(Instruction::PushBytes(bytes))) = instructions.peek() {
+            pubkeys.push(PublicKey::from_slice(bytes.as_bytes()).map_err(|_| invalid())?);
+            instructions.next();
+        }
+        let keys = number(instructions.next()).ok_or_else(invalid)?;
+        match (instructions.next(), instructions.next()) {
+            (Some(Ok(Instruction::Op(OP_CHECKMULTISIG))), None) => {}
+            _ => return Err(invalid()),
+        }
+        if keys != pubkeys.len() || required > keys {
+            return Err(invalid());
+        }
+        Ok(MultisigScript { required, pubkeys })
+    }
+
+    /// A `sortedmulti` script: `pubkeys` sorted by their serialization, as
+    /// BIP 67 and `sortedmulti()` descriptors order them
+    ///
+    /// At most 16 keys, the most [`parse`](Self::parse) takes back.
+    pub fn sorted(required: usize, mut pubkeys: Vec<PublicKey>) -> Result<Self> {
+        if pubkeys.len() > 16 || required == 0 || required > pubkeys.len() {
+            return Err(Error::InvalidParameter(format!(
+                "A multisig script needs 1 to 16 keys and 1 to that many signatures, not {} of {}",
+                required,
+                pubkeys.len()
+            )));
+        }
+        pubkeys.sort_by_key(|key| key.to_bytes());
+        Ok(MultisigScript { required, pubkeys })
+    }
+
+    /// The redeem script
+    pub fn to_script(&self) -> ScriptBuf {
+        let builder = self.pubkeys.iter().fold(Builder::new().push_int(self.required as i64), |builder, key| builder.push_key(key));
+        builder.push_int(self.pubkeys.len() as i64).push_opcode(OP_CHECKMULTISIG).into_script()
+    }
+}
+
+/// Signatures gathered so far for one multisig input
+#[derive(Debug, Clone)]
+pub struct MultisigInput {
+    /// The redeem script, parsed
+    pub script: MultisigScript,
+    redeem_script: ScriptBuf,
+    /// One slot per key, in script order
+    pub signatures: Vec<Option<ecdsa::Signature>>,
+}
+
+impl MultisigInput {
+    /// Read the signatures already in input `index` of `tx`, each checked and matched to its key
+    ///
+    /// An empty scriptSig has none. Anything else must be the partial form
+    /// ending with this redeem script.
+    pub fn from_input(tx: &Transaction, index: usize, redeem_script: &Script) -> Result<Self> {
+        let script = MultisigScript::parse(redeem_script)?;
+        let mut input = MultisigInput {
+            signatures: vec![None; script.pubkeys.len()],
+            script,
+            redeem_script: redeem_script.to_owned(),
+        };
+        let script_sig = &tx
+            .input
+            .get(index)
+            .ok_or_else(|| Error::SigningError(format!("Transaction has no input {}", index)))?
+            .script_sig;
+        if script_sig.is_empty() {
+            return Ok(input);
+        }
+
+        let malformed = |reason: &str| Error::SigningError(format!("Input {} has a malformed multisig scriptSig: {}", index, reason));
+        let mut pushes = Vec::new();
+        for instruction in script_sig.instructions() {
+            match instruction {
+                Ok(Instruction::PushBytes(bytes)) => pushes.push(bytes.as_bytes().to_vec()),
+                _ => return Err(malformed("it holds more than pushes")),
+            }
+        }
+        match (pushes.first(), pushes.last()) {
+            (Some(dummy), Some(redeem)) if pushes.len() >= 2 && dummy.is_empty() && redeem == redeem_script.as_bytes() => {}
+            _ => return Err(malformed("expected OP_0, the signatures, then this redeem script")),
+        }
+
+        let secp = Secp256k1::verification_only();
+        let cache = SighashCache::new(tx);
+        for bytes in &pushes[1..pushes.len() - 1] {
+            // Placeholders some signers leave for missing signatures
+            if bytes.is_empty() {
+                continue;
+            }
+            let signature = ecdsa::Signature::from_slice(bytes).map_err(|e| malformed(&e.to_string()))?;
+            let sighash = cache
+                .legacy_signature_hash(index, redeem_script, signature.sighash_type.to_u32())
+                .map_err(|e| Error::SigningError(format!("Cannot compute sighash for input {}: {}", index, e)))?;
+            let message = Message::from_digest(sighash.to_byte_array());
+            let key = input
+                .script
+                .pubkeys
+                .iter()
+                .position(|key| secp.verify_ecdsa(&message, &signature.signature, &key.inner).is_ok())
+                .ok_or_else(|| {
+                    Error::SigningError(format!("Input {} has a signature that matches none of the redeem script's keys", index))
+                })?;
+            input.signatures[key] = Some(signature);
+        }
+        Ok(input)
+    }
+
+    /// Add the signatures `other` holds for keys that have none here
+    ///
+    /// For copies of one transaction signed in parallel.
+    pub fn merge(&mut self, other: &MultisigInput) -> Result<()> {
+        if other.redeem_script != self.redeem_script {
+            return Err(Error::SigningError("Cannot merge signatures made for different redeem scripts".to_string()));
+        }
+        for (signature, theirs) in self.signatures.iter_mut().zip(&other.signatures) {
+            if signature.is_none() {
+                *signature = *theirs;
+            }
+        }
+        Ok(())
+    }
+
+    /// Number of signatures gathered
+    pub fn signature_count(&self) -> usize {
+        self.signatures.iter().flatten().count()
+    }
+
+    /// Whether enough signatures are gathered to spend
+    pub fn is_complete(&self) -> bool {
+        self.signature_count() >= self.script.required
+    }
+
+    /// `OP_0 <sig>... <redeem script>`, signatures in key order
+    ///
+    /// Once complete, only the first `required` signatures are kept, since
+    /// CHECKMULTISIG fails on extra stack items under standardness rules.
+    pub fn script_sig(&self) -> Result<ScriptBuf> {
+        let redeem_script = PushBytesBuf::try_from(self.redeem_script.to_bytes())
+            .map_err(|_| Error::SigningError("Redeem script exceeds the 520-byte push limit".to_string()))?;
+        let builder = self
+            .signatures
+            .iter()
+            .flatten()
+            .take(self.script.required)
+            .fold(Builder::new().push_opcode(opcodes::OP_0), |builder, signature| builder.push_slice(signature.serialize()));
+        Ok(builder.push_slice(redeem_script).into_script())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signing::{sign_transaction, InputSigner};
+    use crate::types::BtcNetwork;
+    use bitcoin::secp256k1::SecretKey;
+    use bitcoin::{absolute, transaction, Amount, NetworkKind, OutPoint, PrivateKey, Sequence, TxIn, TxOut, Txid};
+    use std::str::FromStr;
+
+    fn key(byte: u8) -> PrivateKey {
+        PrivateKey::new(SecretKey::from_slice(&[byte; 32]).unwrap(), NetworkKind::Test)
+    }
+
+    fn two_of_three() -> MultisigScript {
+        let secp = Secp256k1::new();
+        MultisigScript { required: 2, pubkeys: (1..=3).map(|byte| key(byte).public_key(&secp)).collect() }
+    }
+
+    fn unsigned_tx() -> Transaction {
+        let txid = Txid::from_str("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16").unwrap();
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn { previous_output: OutPoint { txid, vout: 0 }, sequence: Sequence::MAX, ..Default::default() }],
+            output: vec![TxOut { value: Amount::from_sat(90_000), script_pubkey: ScriptBuf::new_op_return([]) }],
+        }
+    }
+
+    // `tx` with one more signature on its only input, from the key made of `byte`
+    fn signed_by(tx: &Transaction, byte: u8, redeem_script: &Script) -> Transaction {
+        let signer = InputSigner {
+            private_key: key(byte),
+            script_pubkey: redeem_script.to_p2sh(),
+            amount: None,
+            sighash_type: None,
+            redeem_script: Some(redeem_script.to_owned()),
+        };
+        let mut tx = tx.clone();
+        sign_transaction(&mut tx, &[signer], BtcNetwork::Regtest).unwrap();
+        tx
+    }
+
+    fn pushes(script: &Script) -> Vec<Vec<u8>> {
+        script
+            .instructions()
+            .map(|instruction| match instruction.unwrap() {
+                Instruction::PushBytes(bytes) => bytes.as_bytes().to_vec(),
+                Instruction::Op(op) => panic!("unexpected {}", op),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        let script = two_of_three();
+        let redeem_script = script.to_script();
+        assert_eq!(redeem_script.len(), 3 + 34 * 3);
+        assert!(redeem_script.is_multisig());
+        assert_eq!(MultisigScript::parse(&redeem_script).unwrap(), script);
+
+        let too_many_required = MultisigScript { required: 4, ..two_of_three() }.to_script();
+        let p2wpkh = ScriptBuf::new_p2wpkh(&script.pubkeys[0].wpubkey_hash().unwrap());
+        for script in [too_many_required, p2wpkh, ScriptBuf::new()] {
+            assert!(MultisigScript::parse(&script).is_err(), "{}", script);
+        }
+    }
+
+    #[test]
+    fn test_reorders_and_merges_signatures() {
+        let redeem_script = two_of_three().to_script();
+        let unsigned = unsigned_tx();
+        let by_first = signed_by(&unsigned, 1, &redeem_script);
+        let by_third = signed_by(&unsigned, 3, &redeem_script);
+        let first = &pushes(&by_first.input[0].script_sig)[1];
+        let third = &pushes(&by_third.input[0].script_sig)[1];
+
+        // Signatures out of key order, as a hand-combined scriptSig might have them
+        let mut combined = unsigned.clone();
+        combined.input[0].script_sig = Builder::new()
+            .push_opcode(opcodes::OP_0)
+            .push_slice(PushBytesBuf::try_from(third.clone()).unwrap())
+            .push_slice(PushBytesBuf::try_from(first.clone()).unwrap())
+            .push_slice(PushBytesBuf::try_from(redeem_script.to_bytes()).unwrap())
+            .into_script();
+        let input = MultisigInput::from_input(&combined, 0, &redeem_script).unwrap();
+        assert!(input.is_complete());
+        assert_eq!(input.signatures.iter().map(Option::is_some).collect::<Vec<_>>(), [true, false, true]);
+        let reordered = pushes(&input.script_sig().unwrap());
+        assert_eq!(reordered, [vec![], first.clone(), third.clone(), redeem_script.to_bytes()]);
+
+        // Copies signed in parallel merge into the same scriptSig
+        let mut merged = MultisigInput::from_input(&by_third, 0, &redeem_script).unwrap();
+        assert!(!merged.is_complete());
+        merged.merge(&MultisigInput::from_input(&by_first, 0, &redeem_script).unwrap()).unwrap();
+        assert_eq!(pushes(&merged.script_sig().unwrap()), reordered);
+
+        let other_script = MultisigScript { required: 1, ..two_of_three() }.to_script();
+        assert!(merged.merge(&MultisigInput::from_input(&unsigned, 0, &other_script).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_rejects_foreign_signatures() {
+        let redeem_script = two_of_three().to_script();
+        // Signed by the key made of 4, which is not in the script
+        let other_script = MultisigScript { required: 1, pubkeys: vec![key(4).public_key(&Secp256k1::new())] }.to_script();
+        let mut tx = signed_by(&unsigned_tx(), 4, &other_script);
+        let signature = PushBytesBuf::try_from(pushes(&tx.input[0].script_sig)[1].clone()).unwrap();
+        let redeem = PushBytesBuf::try_from(redeem_script.to_bytes()).unwrap();
+        tx.input[0].script_sig =
+            Builder::new().push_opcode(opcodes::OP_0).push_slice(signature).push_slice(&redeem).into_script();
+        match MultisigInput::from_input(&tx, 0, &redeem_script) {
+            Err(Error::SigningError(message)) => assert!(message.contains("matches none"), "{}", message),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Missing the OP_0 CHECKMULTISIG pops
+        tx.input[0].script_sig = Builder::new().push_slice(redeem).into_script();
+        match MultisigInput::from_input(&tx, 0, &redeem_script) {
+            Err(Error::SigningError(message)) => assert!(message.contains("malformed"), "{}", message),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_sorted_matches_bip67() {
+        // BIP 67 test vector 1, keys given out of order
+        let keys = ["02ff12471208c14bd580709cb2358d98975247d8765f92bc25eab3b2763ed605f8", "02fe6f0a5a297eb38c391581c4413e084773ea23954d93f7753db7dc0adc188b2f"];
+        let pubkeys = keys.iter().map(|key| PublicKey::from_str(key).unwrap()).collect();
+        let script = MultisigScript::sorted(2, pubkeys).unwrap().to_script();
+        assert_eq!(
+            script.to_hex_string(),
+            "522102fe6f0a5a297eb38c391581c4413e084773ea23954d93f7753db7dc0adc188b2f2102ff12471208c14bd580709cb2358d98975247d8765f92bc25eab3b2763ed605f852ae"
+        );
+        let address = bitcoin::Address::p2sh(&script, bitcoin::Network::Bitcoin).unwrap();
+        assert_eq!(address.to_string(), "39bgKC7RFbpoCRbtD5KEdkYKtNyhpsNa3Z");
+        assert_eq!(MultisigScript::parse(&script).unwrap().pubkeys[0].to_string(), keys[1]);
+
+        let secp = Secp256k1::new();
+        let three: Vec<PublicKey> = (1..=3).map(|byte| key(byte).public_key(&secp)).collect();
+        assert!(MultisigScript::sorted(0, three.clone()).is_err());
+        assert!(MultisigScript::sorted(4, three).is_err());
+    }
+}
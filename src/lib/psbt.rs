@@ -0,0 +1,263 @@
+//! Partially signed transactions (BIP 174) for hardware and external signers
+//!
+//! `build_psbt` wraps an unsigned transaction with what signers need to check
+//! and sign each input; `finalize_psbt` turns collected signatures into final
+//! scripts. Finalizing covers single-key inputs: P2PKH, P2WPKH, P2SH-P2WPKH
+//! and P2TR key path.
+
+use std::collections::BTreeMap;
+
+use bitcoin::bip32::{KeySource, Xpub};
+use bitcoin::psbt::{self, Psbt};
+use bitcoin::script::{Builder, PushBytesBuf};
+use bitcoin::secp256k1;
+use bitcoin::{Transaction, TxOut, Witness};
+
+use crate::error::{Error, Result};
+
+/// What the PSBT records about the output an input spends
+#[derive(Debug, Clone)]
+pub struct PsbtInputData {
+    /// The spent output
+    pub spent_output: TxOut,
+    /// The whole transaction holding the spent output; required for legacy inputs
+    pub previous_tx: Option<Transaction>,
+    /// Origins of the keys that can sign this input
+    pub bip32_derivation: BTreeMap<secp256k1::PublicKey, KeySource>,
+}
+
+/// Build a PSBT for `tx`, one `PsbtInputData` per input
+///
+/// Segwit inputs get `witness_utxo`, plus `non_witness_utxo` when the previous
+/// transaction is supplied. Legacy and P2SH inputs get `non_witness_utxo` and
+/// fail without it. Previous transactions must hash to the spent txid and pay
+/// the stated output. Inputs given `None` are left without UTXO data, which
+/// most signers refuse.
+pub fn build_psbt(
+    tx: Transaction,
+    inputs: Vec<Option<PsbtInputData>>,
+    xpubs: BTreeMap<Xpub, KeySource>,
+) -> Result<Psbt> {
+    if inputs.len() != tx.input.len() {
+        return Err(Error::PsbtError(format!(
+            "Transaction has {} inputs, but data for {} given",
+            tx.input.len(),
+            inputs.len()
+        )));
+    }
+    let outpoints: Vec<_> = tx.input.iter().map(|input| input.previous_output).collect();
+    let mut psbt = Psbt::from_unsigned_tx(tx)?;
+    psbt.xpub = xpubs;
+
+    for (i, (data, outpoint)) in inputs.into_iter().zip(outpoints).enumerate() {
+        let Some(data) = data else {
+            continue;
+        };
+        if let Some(previous_tx) = &data.previous_tx {
+            if previous_tx.compute_txid() != outpoint.txid {
+                return Err(Error::PsbtError(format!("Previous transaction for input {} is not {}", i, outpoint.txid)));
+            }
+            if previous_tx.output.get(outpoint.vout as usize) != Some(&data.spent_output) {
+                return Err(Error::PsbtError(format!("Previous transaction for input {} does not pay the spent output", i)));
+            }
+        }
+        let input = &mut psbt.inputs[i];
+        if data.spent_output.script_pubkey.is_witness_program() {
+            input.witness_utxo = Some(data.spent_output);
+        } else if data.previous_tx.is_none() {
+            return Err(Error::PsbtError(format!("Input {} is not segwit and needs its previous transaction", i)));
+        }
+        input.non_witness_utxo = data.previous_tx;
+        input.bip32_derivation = data.bip32_derivation;
+    }
+    Ok(psbt)
+}
+
+/// Finalize every input that has the signature it needs
+///
+/// Returns the indices of the inputs left incomplete; the PSBT is ready for
+/// `extract_tx` when the list is empty. Signatures are not verified here.
+pub fn finalize_psbt(psbt: &mut Psbt) -> Vec<usize> {
+    let mut incomplete = Vec::new();
+    for i in 0..psbt.inputs.len() {
+        if !finalize_input(psbt, i) {
+            incomplete.push(i);
+        }
+    }
+    incomplete
+}
+
+// Finalize one input; true if it is final afterwards
+fn finalize_input(psbt: &mut Psbt, index: usize) -> bool {
+    let Ok(spent) = psbt.spend_utxo(index).cloned() else {
+        return false;
+    };
+    let input = &mut psbt.inputs[index];
+    if input.final_script_sig.is_some() || input.final_script_witness.is_some() {
+        return true;
+    }
+
+    let script = &spent.script_pubkey;
+    if script.is_p2tr() {
+        let Some(signature) = input.tap_key_sig else {
+            return false;
+        };
+        input.final_script_witness = Some(Witness::p2tr_key_spend(&signature));
+    } else {
+        // Every other supported type is one ECDSA signature by one key
+        let Some((public_key, signature)) = single_signature(&input.partial_sigs) else {
+            return false;
+        };
+        if script.is_p2pkh() {
+            let script_sig = Builder::new().push_slice(signature.serialize()).push_key(&public_key).into_script();
+            input.final_script_sig = Some(script_sig);
+        } else if script.is_p2wpkh() {
+            input.final_script_witness = Some(Witness::p2wpkh(&signature, &public_key.inner));
+        } else if let Some(redeem_script) = input.redeem_script.clone().filter(|s| script.is_p2sh() && s.is_p2wpkh()) {
+            let Ok(redeem_script) = PushBytesBuf::try_from(redeem_script.into_bytes()) else {
+                return false;
+            };
+            input.final_script_sig = Some(Builder::new().push_slice(redeem_script).into_script());
+            input.final_script_witness = Some(Witness::p2wpkh(&signature, &public_key.inner));
+        } else {
+            return false;
+        }
+    }
+
+    // BIP 174: the finalizer clears everything but the UTXOs and final scripts
+    input.partial_sigs.clear();
+    input.sighash_type = None;
+    input.redeem_script = None;
+    input.witness_script = None;
+    input.bip32_derivation.clear();
+    input.tap_key_sig = None;
+    input.tap_key_origins.clear();
+    input.tap_internal_key = None;
+    input.tap_merkle_root = None;
+    true
+}
+
+fn single_signature(
+    signatures: &BTreeMap<bitcoin::PublicKey, bitcoin::ecdsa::Signature>,
+) -> Option<(bitcoin::PublicKey, bitcoin::ecdsa::Signature)> {
+    match signatures.len() {
+        1 => signatures.iter().next().map(|(key, sig)| (*key, *sig)),
+        _ => None,
+    }
+}
+
+impl From<psbt::ExtractTxError> for Error {
+    fn from(e: psbt::ExtractTxError) -> Self {
+        Error::PsbtError(e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::signing::{sign_transaction, InputSigner};
+    use crate::types::BtcNetwork;
+    use bitcoin::bip32::{DerivationPath, Fingerprint};
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use bitcoin::{
+        absolute, transaction, Amount, CompressedPublicKey, Network, OutPoint, PrivateKey, ScriptBuf, Sequence, TxIn,
+    };
+    use std::str::FromStr;
+
+    fn key() -> PrivateKey {
+        PrivateKey::new(SecretKey::from_slice(&[9; 32]).unwrap(), Network::Regtest)
+    }
+
+    fn spend(previous_output: OutPoint) -> Transaction {
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: vec![TxIn { previous_output, sequence: Sequence::MAX, ..Default::default() }],
+            output: vec![TxOut { value: Amount::from_sat(40_000), script_pubkey: ScriptBuf::new_op_return([1]) }],
+        }
+    }
+
+    fn funding(script_pubkey: ScriptBuf) -> Transaction {
+        let mut tx = spend(OutPoint::null());
+        tx.output = vec![TxOut { value: Amount::from_sat(50_000), script_pubkey }];
+        tx
+    }
+
+    // Sign `tx` with the test key and copy the signature into the PSBT, as a signer would
+    fn add_signature(psbt: &mut Psbt, spent: &TxOut) {
+        let mut signed = psbt.unsigned_tx.clone();
+        let signer = InputSigner {
+            private_key: key(),
+            script_pubkey: spent.script_pubkey.clone(),
+            amount: Some(spent.value),
+            sighash_type: None,
+            redeem_script: None,
+        };
+        sign_transaction(&mut signed, &[signer], BtcNetwork::Regtest).unwrap();
+        let public_key = key().public_key(&Secp256k1::new());
+        let signature = if spent.script_pubkey.is_p2wpkh() {
+            signed.input[0].witness.nth(0).unwrap().to_vec()
+        } else {
+            signed.input[0].script_sig.instructions().next().unwrap().unwrap().push_bytes().unwrap().as_bytes().to_vec()
+        };
+        psbt.inputs[0]
+            .partial_sigs
+            .insert(public_key, bitcoin::ecdsa::Signature::from_slice(&signature).unwrap());
+    }
+
+    #[test]
+    fn test_segwit_round_trip() {
+        let secp = Secp256k1::new();
+        let public_key = CompressedPublicKey::from_private_key(&secp, &key()).unwrap();
+        let funding = funding(ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash()));
+        let outpoint = OutPoint::new(funding.compute_txid(), 0);
+        let origin = (Fingerprint::from_str("d34db33f").unwrap(), DerivationPath::from_str("m/84'/1'/0'/0/0").unwrap());
+        let data = PsbtInputData {
+            spent_output: funding.output[0].clone(),
+            previous_tx: None,
+            bip32_derivation: BTreeMap::from([(public_key.0, origin.clone())]),
+        };
+        let psbt = build_psbt(spend(outpoint), vec![Some(data)], BTreeMap::new()).unwrap();
+        assert_eq!(psbt.inputs[0].witness_utxo.as_ref(), Some(&funding.output[0]));
+        assert!(psbt.inputs[0].non_witness_utxo.is_none());
+        assert_eq!(psbt.inputs[0].bip32_derivation[&public_key.0], origin);
+
+        // Through base64 and back, as a hardware wallet would receive it
+        let mut psbt = Psbt::from_str(&psbt.to_string()).unwrap();
+        assert_eq!(finalize_psbt(&mut psbt), vec![0]);
+        add_signature(&mut psbt, &funding.output[0]);
+        assert!(finalize_psbt(&mut psbt).is_empty());
+        assert!(psbt.inputs[0].partial_sigs.is_empty() && psbt.inputs[0].bip32_derivation.is_empty());
+        let tx = psbt.extract_tx().unwrap();
+        assert_eq!(tx.input[0].witness.len(), 2);
+
+        // Without data the input is left for the signer to fill in
+        let psbt = build_psbt(spend(outpoint), vec![None], BTreeMap::new()).unwrap();
+        assert!(psbt.inputs[0].witness_utxo.is_none() && psbt.inputs[0].non_witness_utxo.is_none());
+    }
+
+    #[test]
+    fn test_legacy_inputs_need_previous_tx() {
+        let public_key = key().public_key(&Secp256k1::new());
+        let funding = funding(ScriptBuf::new_p2pkh(&public_key.pubkey_hash()));
+        let outpoint = OutPoint::new(funding.compute_txid(), 0);
+        let data = PsbtInputData {
+            spent_output: funding.output[0].clone(),
+            previous_tx: None,
+            bip32_derivation: BTreeMap::new(),
+        };
+        let err = build_psbt(spend(outpoint), vec![Some(data.clone())], BTreeMap::new()).unwrap_err();
+        assert!(err.to_string().contains("previous transaction"));
+
+        // A previous transaction with another txid is refused
+        let other = PsbtInputData { previous_tx: Some(spend(outpoint)), ..data.clone() };
+        assert!(build_psbt(spend(outpoint), vec![Some(other)], BTreeMap::new()).is_err());
+
+        let data = PsbtInputData { previous_tx: Some(funding.clone()), ..data };
+        let mut psbt = build_psbt(spend(outpoint), vec![Some(data)], BTreeMap::new()).unwrap();
+        assert!(psbt.inputs[0].witness_utxo.is_none());
+        add_signature(&mut psbt, &funding.output[0]);
+        assert!(finalize_psbt(&mut psbt).is_empty());
+        assert!(!psbt.extract_tx().unwrap().input[0].script_sig.is_empty());
+    }
+}
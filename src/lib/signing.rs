@@ -0,0 +1,962 @@
+//! Transaction input signing with private keys
+
+use std::fmt;
+
+use bitcoin::hashes::Hash;
+use bitcoin::key::{Keypair, TapTweak};
+use bitcoin::script::{Builder, Instruction, PushBytesBuf};
+use bitcoin::secp256k1::{self, All, Message, Secp256k1, SecretKey, XOnlyPublicKey};
+use bitcoin::sighash::{Prevouts, SighashCache, TapSighashType};
+use bitcoin::{
+    ecdsa, taproot, Amount, CompressedPublicKey, EcdsaSighashType, Network, NetworkKind, PrivateKey, PublicKey, Script,
+    ScriptBuf, Transaction, TxOut, Witness,
+};
+
+use crate::error::{Error, Result};
+use crate::multisig::MultisigInput;
+use crate::types::BtcNetwork;
+
+/// Key and spent output needed to sign one input
+///
+/// The private key is overwritten when the signer is dropped.
+#[derive(Clone)]
+pub struct InputSigner {
+    /// Key controlling the spent output
+    pub private_key: PrivateKey,
+    /// Locking script of the spent output
+    pub script_pubkey: ScriptBuf,
+    /// Value of the spent output, required for segwit inputs, and for every
+    /// input once one is taproot
+    pub amount: Option<Amount>,
+    /// What the signature commits to; `None` signs everything, with the
+    /// 64-byte default signature on taproot inputs
+    pub sighash_type: Option<EcdsaSighashType>,
+    /// Multisig script behind a P2SH output; the signature joins any already
+    /// in the input's scriptSig
+    pub redeem_script: Option<ScriptBuf>,
+}
+
+impl Drop for InputSigner {
+    fn drop(&mut self) {
+        self.private_key.inner.non_secure_erase();
+    }
+}
+
+impl fmt::Debug for InputSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InputSigner")
+            .field("private_key", &"<redacted>")
+            .field("script_pubkey", &self.script_pubkey)
+            .field("amount", &self.amount)
+            .field("sighash_type", &self.sighash_type)
+            .field("redeem_script", &self.redeem_script)
+            .finish()
+    }
+}
+
+/// Sign every input of `tx`, one signer per input, with SIGHASH_ALL unless the signer says otherwise
+///
+/// Supports P2PKH, P2WPKH, P2SH-P2WPKH and P2TR key-path outputs, and P2SH
+/// multisig given the redeem script. Fails without touching `tx` if a key
+/// belongs to another network or does not control the output it signs for.
+/// ECDSA nonces are ground to a 32-byte R, as Bitcoin Core does, so every
+/// signature is at most 71 bytes with its sighash byte.
+pub fn sign_transaction(tx: &mut Transaction, signers: &[InputSigner], network: BtcNetwork) -> Result<()> {
+    sign_transaction_with(tx, signers, network, true)
+}
+
+/// [`sign_transaction`], with `low_r` false signing with the plain RFC6979
+/// nonce, to compare signatures with signers that do not grind
+pub fn sign_transaction_with(tx: &mut Transaction, signers: &[InputSigner], network: BtcNetwork, low_r: bool) -> Result<()> {
+    if signers.len() != tx.input.len() {
+        return Err(Error::SigningError(format!(
+            "Input count mismatch: transaction has {} inputs, but {} signing inputs provided",
+            tx.input.len(),
+            signers.len()
+        )));
+    }
+
+    let secp = Secp256k1::new();
+    let network_kind = NetworkKind::from(Network::from(network));
+    let mut cache = SighashCache::new(&*tx);
+    let mut signed = Vec::with_capacity(signers.len());
+    // A taproot signature commits to every spent output
+    let spent: Vec<SpentOutput> = signers.iter().map(SpentOutput::from).collect();
+    let prevouts = taproot_prevouts(&spent)?;
+
+    for (i, signer) in signers.iter().enumerate() {
+        if signer.private_key.network != network_kind {
+            return Err(Error::SigningError(format!("Private key for input {} is not a {} key", i, network)));
+        }
+        let public_key = signer.private_key.public_key(&secp);
+        let script = &signer.script_pubkey;
+        let sighash_type = signer.sighash_type.unwrap_or(EcdsaSighashType::All);
+        // Legacy signing would commit to the constant 1 instead of an output
+        if matches!(sighash_type, EcdsaSighashType::Single | EcdsaSighashType::SinglePlusAnyoneCanPay)
+            && i >= tx.output.len()
+        {
+            return Err(Error::SigningError(format!(
+                "Input {} signs with SIGHASH_SINGLE, but the transaction has no output {} to commit to",
+                i, i
+            )));
+        }
+
+        if let Some(redeem_script) = &signer.redeem_script {
+            if !script.is_p2sh() {
+                return Err(Error::SigningError(format!("Input {} has a redeem script but does not spend a P2SH output", i)));
+            }
+            if *script != redeem_script.to_p2sh() {
+                return Err(Error::SigningError(format!("Redeem script for input {} does not hash to the output it spends", i)));
+            }
+            // Signatures from other signers are checked and kept in key order
+            let mut multisig = MultisigInput::from_input(tx, i, redeem_script)?;
+            let key = multisig.script.pubkeys.iter().position(|key| *key == public_key).ok_or_else(|| {
+                Error::SigningError(format!("Private key for input {} is not one of its redeem script's keys", i))
+            })?;
+            if multisig.is_complete() && multisig.signatures[key].is_none() {
+                return Err(Error::SigningError(format!(
+                    "Input {} already has the {} signatures its redeem script needs",
+                    i, multisig.script.required
+                )));
+            }
+            let sighash = cache
+                .legacy_signature_hash(i, redeem_script, sighash_type.to_u32())
+                .map_err(|e| Error::SigningError(format!("Cannot compute sighash for input {}: {}", i, e)))?;
+            let message = Message::from_digest(sighash.to_byte_array());
+            multisig.signatures[key] = Some(ecdsa::Signature {
+                signature: sign_ecdsa(&secp, &message, &signer.private_key.inner, low_r),
+                sighash_type,
+            });
+            signed.push((multisig.script_sig()?, Witness::new()));
+        } else if script.is_p2pkh() {
+            if *script != ScriptBuf::new_p2pkh(&public_key.pubkey_hash()) {
+                return Err(key_mismatch(i));
+            }
+            let sighash = cache
+                .legacy_signature_hash(i, script, sighash_type.to_u32())
+                .map_err(|e| Error::SigningError(format!("Cannot compute sighash for input {}: {}", i, e)))?;
+            let message = Message::from_digest(sighash.to_byte_array());
+            let signature = ecdsa::Signature {
+                signature: sign_ecdsa(&secp, &message, &signer.private_key.inner, low_r),
+                sighash_type,
+            };
+            let script_sig = Builder::new()
+                .push_slice(signature.serialize())
+                .push_key(&public_key)
+                .into_script();
+            signed.push((script_sig, Witness::new()));
+        } else if script.is_p2wpkh() || script.is_p2sh() {
+            let public_key = match CompressedPublicKey::try_from(public_key) {
+                Ok(public_key) => public_key,
+                Err(_) if script.is_p2sh() => return Err(redeem_script_required(i)),
+                Err(_) => return Err(Error::SigningError(format!("Input {} is segwit and needs a compressed key", i))),
+            };
+            let witness_program = ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash());
+            let script_sig = if script.is_p2wpkh() {
+                if *script != witness_program {
+                    return Err(key_mismatch(i));
+                }
+                ScriptBuf::new()
+            } else if *script == witness_program.to_p2sh() {
+                // P2SH-P2WPKH: a single push of the witness program, the redeem script
+                let redeem_script = PushBytesBuf::try_from(witness_program.to_bytes()).expect("22 bytes");
+                Builder::new().push_slice(redeem_script).into_script()
+            } else {
+                return Err(redeem_script_required(i));
+            };
+            let amount = signer.amount.ok_or_else(|| {
+                Error::SigningError(format!("Input {} is segwit; the amount it spends is required", i))
+            })?;
+            let sighash = cache
+                .p2wpkh_signature_hash(i, &witness_program, amount, sighash_type)
+                .map_err(|e| Error::SigningError(format!("Cannot compute sighash for input {}: {}", i, e)))?;
+            let message = Message::from_digest(sighash.to_byte_array());
+            let signature = ecdsa::Signature {
+                signature: sign_ecdsa(&secp, &message, &signer.private_key.inner, low_r),
+                sighash_type,
+            };
+            signed.push((script_sig, Witness::p2wpkh(&signature, &public_key.0)));
+        } else if script.is_p2tr() {
+            let prevouts = prevouts.as_deref().expect("collected when any input is taproot");
+            // Key path only: the output key is the internal key tweaked with no script tree
+            let keypair = Keypair::from_secret_key(&secp, &signer.private_key.inner);
+            let (internal_key, _) = keypair.x_only_public_key();
+            if *script != ScriptBuf::new_p2tr(&secp, internal_key, None) {
+                return Err(key_mismatch(i));
+            }
+            let sighash_type = match signer.sighash_type {
+                Some(sighash_type) => TapSighashType::from_consensus_u8(sighash_type.to_u32() as u8)
+                    .expect("every ECDSA sighash type exists in taproot"),
+                None => TapSighashType::Default,
+            };
+            let sighash = cache
+                .taproot_key_spend_signature_hash(i, &Prevouts::All(prevouts), sighash_type)
+                .map_err(|e| Error::SigningError(format!("Cannot compute sighash for input {}: {}", i, e)))?;
+            let message = Message::from_digest(sighash.to_byte_array());
+            let tweaked = keypair.tap_tweak(&secp, None);
+            // 64 bytes with the default sighash type, 65 with any other
+            let signature = taproot::Signature {
+                signature: secp.sign_schnorr(&message, &tweaked.to_keypair()),
+                sighash_type,
+            };
+            signed.push((ScriptBuf::new(), Witness::p2tr_key_spend(&signature)));
+        } else {
+            return Err(Error::SigningError(format!(
+                "Input {} spends an unsupported script type (only P2PKH, P2WPKH, P2SH-P2WPKH, P2SH multisig and P2TR are supported)",
+                i
+            )));
+        }
+    }
+
+    for (input, (script_sig, witness)) in tx.input.iter_mut().zip(signed) {
+        input.script_sig = script_sig;
+        input.witness = witness;
+    }
+    Ok(())
+}
+
+// ECDSA signature, retried with counter entropy until R fits 32 bytes when `low_r` is set
+fn sign_ecdsa(secp: &Secp256k1<All>, message: &Message, key: &SecretKey, low_r: bool) -> secp256k1::ecdsa::Signature {
+    let signature = if low_r { secp.sign_ecdsa_low_r(message, key) } else { secp.sign_ecdsa(message, key) };
+    // libsecp256k1 only makes low-S signatures, the only ones relayed
+    let mut normalized = signature;
+    normalized.normalize_s();
+    assert_eq!(normalized, signature, "libsecp256k1 made a high-S signature");
+    signature
+}
+
+/// Output spent by one input, as [`verify_transaction`] needs it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpentOutput {
+    /// Locking script of the spent output
+    pub script_pubkey: ScriptBuf,
+    /// Value of the spent output, required for the same inputs as when signing
+    pub amount: Option<Amount>,
+}
+
+impl From<&InputSigner> for SpentOutput {
+    fn from(signer: &InputSigner) -> Self {
+        SpentOutput { script_pubkey: signer.script_pubkey.clone(), amount: signer.amount }
+    }
+}
+
+/// Check every input of a signed `tx` against the output it spends
+///
+/// The public key in each scriptSig or witness must hash to the spent script,
+/// and each signature must verify against the sighash it claims. This covers
+/// the script types [`sign_transaction`] produces; a multisig input may still
+/// be short of signatures, but every signature it has must be valid.
+pub fn verify_transaction(tx: &Transaction, spent: &[SpentOutput]) -> Result<()> {
+    if spent.len() != tx.input.len() {
+        return Err(Error::SigningError(format!(
+            "Input count mismatch: transaction has {} inputs, but {} spent outputs provided",
+            tx.input.len(),
+            spent.len()
+        )));
+    }
+    let secp = Secp256k1::verification_only();
+    let prevouts = taproot_prevouts(spent)?;
+    let mut cache = SighashCache::new(tx);
+    for (i, output) in spent.iter().enumerate() {
+        verify_input(&secp, tx, &mut cache, i, output, prevouts.as_deref())
+            .map_err(|reason| Error::SigningError(format!("Input {} failed verification: {}", i, reason)))?;
+    }
+    Ok(())
+}
+
+fn verify_input<C: bitcoin::secp256k1::Verification>(
+    secp: &Secp256k1<C>,
+    tx: &Transaction,
+    cache: &mut SighashCache<&Transaction>,
+    i: usize,
+    spent: &SpentOutput,
+    prevouts: Option<&[TxOut]>,
+) -> std::result::Result<(), String> {
+    let input = &tx.input[i];
+    let script = &spent.script_pubkey;
+    let mut pushes = Vec::new();
+    for instruction in input.script_sig.instructions() {
+        match instruction {
+            Ok(Instruction::PushBytes(bytes)) => pushes.push(bytes.as_bytes()),
+            _ => return Err("the scriptSig holds more than pushes".to_string()),
+        }
+    }
+    let bad_signature = |e: &dyn fmt::Display| format!("malformed signature: {}", e);
+    let sighash_error = |e: &dyn fmt::Display| format!("cannot compute sighash: {}", e);
+    let public_key_mismatch = || "the public key used does not match the spent output".to_string();
+    let wrapped_program = match pushes.as_slice() {
+        [program] if script.is_p2sh() => Some(Script::from_bytes(program)).filter(|program| program.is_p2wpkh()),
+        _ => None,
+    };
+
+    if script.is_p2pkh() {
+        let [signature, public_key] = pushes.as_slice() else {
+            return Err("the scriptSig is not <signature> <public key>".to_string());
+        };
+        let public_key = PublicKey::from_slice(public_key).map_err(|e| format!("malformed public key: {}", e))?;
+        if *script != ScriptBuf::new_p2pkh(&public_key.pubkey_hash()) {
+            return Err(public_key_mismatch());
+        }
+        let signature = ecdsa::Signature::from_slice(signature).map_err(|e| bad_signature(&e))?;
+        let sighash = cache.legacy_signature_hash(i, script, signature.sighash_type.to_u32()).map_err(|e| sighash_error(&e))?;
+        secp.verify_ecdsa(&Message::from_digest(sighash.to_byte_array()), &signature.signature, &public_key.inner)
+            .map_err(|_| "the signature does not verify".to_string())
+    } else if script.is_p2wpkh() || wrapped_program.is_some() {
+        if script.is_p2wpkh() && !pushes.is_empty() {
+            return Err("a native segwit input must have an empty scriptSig".to_string());
+        }
+        let (Some(signature), Some(public_key), 2) = (input.witness.nth(0), input.witness.nth(1), input.witness.len()) else {
+            return Err("the witness is not <signature> <public key>".to_string());
+        };
+        let public_key = CompressedPublicKey::from_slice(public_key).map_err(|e| format!("malformed public key: {}", e))?;
+        let witness_program = ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash());
+        let expected = if script.is_p2wpkh() { script.as_script() } else { wrapped_program.expect("checked above") };
+        if witness_program != *expected || (script.is_p2sh() && *script != witness_program.to_p2sh()) {
+            return Err(public_key_mismatch());
+        }
+        let amount = spent.amount.ok_or("the amount it spends is required to check a segwit signature")?;
+        let signature = ecdsa::Signature::from_slice(signature).map_err(|e| bad_signature(&e))?;
+        let sighash =
+            cache.p2wpkh_signature_hash(i, &witness_program, amount, signature.sighash_type).map_err(|e| sighash_error(&e))?;
+        secp.verify_ecdsa(&Message::from_digest(sighash.to_byte_array()), &signature.signature, &public_key.0)
+            .map_err(|_| "the signature does not verify".to_string())
+    } else if script.is_p2sh() {
+        let redeem_script = Script::from_bytes(pushes.last().ok_or("the scriptSig is empty")?);
+        if *script != redeem_script.to_p2sh() {
+            return Err("the redeem script does not match the spent output".to_string());
+        }
+        // Checks each signature against the key it belongs to
+        MultisigInput::from_input(tx, i, redeem_script).map(|_| ()).map_err(|e| e.to_string())
+    } else if script.is_p2tr() {
+        let prevouts = prevouts.expect("collected when any input is taproot");
+        let (Some(signature), 1) = (input.witness.nth(0), input.witness.len()) else {
+            return Err("the witness is not a single key-path signature".to_string());
+        };
+        let signature = taproot::Signature::from_slice(signature).map_err(|e| bad_signature(&e))?;
+        let output_key = XOnlyPublicKey::from_slice(&script.as_bytes()[2..]).map_err(|e| format!("invalid output key: {}", e))?;
+        let sighash = cache
+            .taproot_key_spend_signature_hash(i, &Prevouts::All(prevouts), signature.sighash_type)
+            .map_err(|e| sighash_error(&e))?;
+        secp.verify_schnorr(&signature.signature, &Message::from_digest(sighash.to_byte_array()), &output_key)
+            .map_err(|_| "the signature does not verify".to_string())
+    } else {
+        Err("unsupported script type".to_string())
+    }
+}
+
+fn key_mismatch(input: usize) -> Error {
+    Error::SigningError(format!("Private key for input {} does not match the output it spends", input))
+}
+
+/// Parse a sighash type written as `ALL`, `NONE` or `SINGLE`, optionally with `|ANYONECANPAY`
+pub fn parse_sighash_type(s: &str) -> Result<EcdsaSighashType> {
+    let unknown = || {
+        Error::InvalidParameter(format!("Unknown sighash type {:?}; use ALL, NONE or SINGLE, optionally with |ANYONECANPAY", s))
+    };
+    let normalized = s.to_ascii_uppercase();
+    let mut parts = normalized.split('|').map(|part| part.trim().trim_start_matches("SIGHASH_"));
+    let base = parts.next().ok_or_else(unknown)?;
+    let anyone_can_pay = match (parts.next(), parts.next()) {
+        (None, _) => false,
+        (Some("ANYONECANPAY"), None) => true,
+        _ => return Err(unknown()),
+    };
+    Ok(match (base, anyone_can_pay) {
+        ("ALL", false) => EcdsaSighashType::All,
+        ("NONE", false) => EcdsaSighashType::None,
+        ("SINGLE", false) => EcdsaSighashType::Single,
+        ("ALL", true) => EcdsaSighashType::AllPlusAnyoneCanPay,
+        ("NONE", true) => EcdsaSighashType::NonePlusAnyoneCanPay,
+        ("SINGLE", true) => EcdsaSighashType::SinglePlusAnyoneCanPay,
+        _ => return Err(unknown()),
+    })
+}
+
+// Outputs spent by every input, which a taproot signature commits to; `None` without taproot inputs
+fn taproot_prevouts(spent: &[SpentOutput]) -> Result<Option<Vec<TxOut>>> {
+    let Some(taproot_input) = spent.iter().position(|output| output.script_pubkey.is_p2tr()) else {
+        return Ok(None);
+    };
+    spent
+        .iter()
+        .enumerate()
+        .map(|(i, output)| {
+            let value = output.amount.ok_or_else(|| {
+                Error::SigningError(format!(
+                    "Input {} is taproot, which needs the amount of every input; input {} has none",
+                    taproot_input, i
+                ))
+            })?;
+            Ok(TxOut { value, script_pubkey: output.script_pubkey.clone() })
+        })
+        .collect::<Result<_>>()
+        .map(Some)
+}
+
+// Only P2SH-P2WPKH can be signed from the key alone
+fn redeem_script_required(input: usize) -> Error {
+    Error::SigningError(format!(
+        "Input {} spends a P2SH output that is not P2SH-P2WPKH for this key; other P2SH scripts, such as multisig, need their redeem script",
+        input
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::multisig::MultisigScript;
+    use bitcoin::bip32::{DerivationPath, Xpriv};
+    use bitcoin::hex::{DisplayHex, FromHex};
+    use bitcoin::secp256k1::{ecdsa::Signature, SecretKey};
+    use bitcoin::Address;
+    use bitcoin::{absolute, transaction, OutPoint, Sequence, TxIn, TxOut, Txid};
+    use std::collections::HashSet;
+    use std::str::FromStr;
+
+    fn key(byte: u8, network: NetworkKind) -> PrivateKey {
+        PrivateKey::new(SecretKey::from_slice(&[byte; 32]).unwrap(), network)
+    }
+
+    fn unsigned_tx(inputs: usize) -> Transaction {
+        let txid = Txid::from_str("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16").unwrap();
+        Transaction {
+            version: transaction::Version::TWO,
+            lock_time: absolute::LockTime::ZERO,
+            input: (0..inputs)
+                .map(|vout| TxIn {
+                    previous_output: OutPoint { txid, vout: vout as u32 },
+                    sequence: Sequence::MAX,
+                    ..Default::default()
+                })
+                .collect(),
+            output: vec![TxOut {
+                value: Amount::from_sat(90_000),
+                script_pubkey: ScriptBuf::new_op_return([]),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_sign_p2pkh_and_p2wpkh() {
+        let secp = Secp256k1::new();
+        let legacy = key(1, NetworkKind::Test);
+        let segwit = key(2, NetworkKind::Test);
+        let segwit_pub = CompressedPublicKey::from_private_key(&secp, &segwit).unwrap();
+        let signers = [
+            InputSigner {
+                private_key: legacy,
+                script_pubkey: ScriptBuf::new_p2pkh(&legacy.public_key(&secp).pubkey_hash()),
+                amount: None,
+                sighash_type: None,
+                redeem_script: None,
+            },
+            InputSigner {
+                private_key: segwit,
+                script_pubkey: ScriptBuf::new_p2wpkh(&segwit_pub.wpubkey_hash()),
+                amount: Some(Amount::from_sat(50_000)),
+                sighash_type: None,
+                redeem_script: None,
+            },
+        ];
+
+        let mut tx = unsigned_tx(2);
+        sign_transaction(&mut tx, &signers, BtcNetwork::Regtest).unwrap();
+        assert!(!tx.input[0].script_sig.is_empty());
+        assert!(tx.input[0].witness.is_empty());
+        assert!(tx.input[1].script_sig.is_empty());
+        assert_eq!(tx.input[1].witness.len(), 2);
+
+        // The witness signature commits to the amount
+        let mut cache = SighashCache::new(&tx);
+        let sighash = cache
+            .p2wpkh_signature_hash(1, &signers[1].script_pubkey, Amount::from_sat(50_000), EcdsaSighashType::All)
+            .unwrap();
+        let der = tx.input[1].witness.nth(0).unwrap();
+        let signature = Signature::from_der(&der[..der.len() - 1]).unwrap();
+        let message = Message::from_digest(sighash.to_byte_array());
+        assert!(secp.verify_ecdsa(&message, &signature, &segwit_pub.0).is_ok());
+    }
+
+    #[test]
+    fn test_low_r_signatures() {
+        let secp = Secp256k1::new();
+        let private_key = key(3, NetworkKind::Test);
+        let public_key = CompressedPublicKey::from_private_key(&secp, &private_key).unwrap();
+        let signers = [InputSigner {
+            private_key,
+            script_pubkey: ScriptBuf::new_p2wpkh(&public_key.wpubkey_hash()),
+            amount: Some(Amount::from_sat(100_000)),
+            sighash_type: None,
+            redeem_script: None,
+        }];
+        let signature = |value: u64, low_r: bool| {
+            let mut tx = unsigned_tx(1);
+            tx.output[0].value = Amount::from_sat(value);
+            sign_transaction_with(&mut tx, &signers, BtcNetwork::Regtest, low_r).unwrap();
+            verify_transaction(&tx, &[SpentOutput::from(&signers[0])]).unwrap();
+            tx.input[0].witness.nth(0).unwrap().to_vec()
+        };
+
+        // Each value changes the sighash, and about half of plain RFC6979 nonces have a high R
+        let ground: Vec<Vec<u8>> = (0..300).map(|value| signature(value, true)).collect();
+        assert!(ground.iter().all(|signature| signature.len() <= 71));
+        let plain: Vec<Vec<u8>> = (0..300).map(|value| signature(value, false)).collect();
+        assert!(plain.iter().any(|signature| signature.len() == 72));
+        // Grinding keeps the RFC6979 signature when its R is already low; a high R is
+        // 33 bytes in DER, its length being the fourth byte
+        for (ground, plain) in ground.iter().zip(&plain) {
+            assert!(plain[3] == 33 || ground == plain);
+        }
+    }
+
+    #[test]
+    fn test_sign_p2sh_p2wpkh_like_bip143() {
+        // P2SH-P2WPKH example from BIP143
+        let unsigned = "0100000001db6b1b20aa0fd7b23880be2ecbd4a98130974cf4748fb66092ac4d3ceb1a54770100000000feffffff02b8b4eb0b000000001976a914a457b684d7f0d539a46a45bbc043f35b59d0d96388ac0008af2f000000001976a914fd270b1ee6abcaea97fea7ad0402e8bd8ad6d77c88ac92040000";
+        let secret = SecretKey::from_str("eb696a065ef48a2192da5b28b694f87544b30fae8327c4510137a922f32c6dcf").unwrap();
+        let signer = InputSigner {
+            private_key: PrivateKey::new(secret, NetworkKind::Main),
+            script_pubkey: ScriptBuf::from_hex("a9144733f37cf4db86fbc2efed2500b4f4e49f31202387").unwrap(),
+            amount: Some(Amount::from_sat(1_000_000_000)),
+            sighash_type: None,
+            redeem_script: None,
+        };
+
+        let mut tx: Transaction = bitcoin::consensus::encode::deserialize_hex(unsigned).unwrap();
+        sign_transaction(&mut tx, &[signer], BtcNetwork::Bitcoin).unwrap();
+        assert_eq!(tx.input[0].script_sig.to_hex_string(), "16001479091972186c449eb1ded22b78e40d009bdf0089");
+        let witness: Vec<String> = tx.input[0].witness.iter().map(|item| item.to_lower_hex_string()).collect();
+        assert_eq!(
+            witness,
+            [
+                "3044022047ac8e878352d3ebbde1c94ce3a10d057c24175747116f8288e5d794d12d482f0220217f36a485cae903c713331d877c1f64677e3622ad4010726870540656fe9dcb01",
+                "03ad1d8e89212f0b92c74d23bb710c00662ad1470198ac48c43f7d6f93a2a26873",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sign_p2tr_key_path() {
+        let secp = Secp256k1::new();
+
+        // BIP341 key-path output for an internal key without a script tree
+        let internal_key =
+            XOnlyPublicKey::from_str("d6889cb081036e0faefa3a35157ad71086b123b2b144b649798b494c300a961d").unwrap();
+        assert_eq!(
+            ScriptBuf::new_p2tr(&secp, internal_key, None).to_hex_string(),
+            "512053a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343"
+        );
+
+        // First receive key of the BIP86 test mnemonic ("abandon" x11, "about")
+        let seed = Vec::from_hex(
+            "5eb00bbddcf069084889a8ab9155568165f5c453ccb85e70811aaed6f6da5fc19a5ac40b389cd370d086206dec8aa6c43daea6690f20ad3d8d48b2d2ce9e38e4",
+        )
+        .unwrap();
+        let path = DerivationPath::from_str("m/86'/0'/0'/0/0").unwrap();
+        let xpriv = Xpriv::new_master(Network::Bitcoin, &seed).unwrap().derive_priv(&secp, &path).unwrap();
+        let taproot_key = xpriv.to_priv();
+        let address = Address::from_str("bc1p5cyxnuxmeuwuvkwfem96lqzszd02n6xdcjrs20cac6yqjjwudpxqkedrcr").unwrap().assume_checked();
+        let segwit = key(2, NetworkKind::Main);
+        let signers = [
+            InputSigner {
+                private_key: taproot_key,
+                script_pubkey: address.script_pubkey(),
+                amount: Some(Amount::from_sat(70_000)),
+                sighash_type: None,
+                redeem_script: None,
+            },
+            InputSigner {
+                private_key: segwit,
+                script_pubkey: ScriptBuf::new_p2wpkh(&CompressedPublicKey::from_private_key(&secp, &segwit).unwrap().wpubkey_hash()),
+                amount: Some(Amount::from_sat(30_000)),
+                sighash_type: None,
+                redeem_script: None,
+            },
+        ];
+
+        let unsigned = unsigned_tx(2);
+        let mut tx = unsigned.clone();
+        sign_transaction(&mut tx, &signers, BtcNetwork::Bitcoin).unwrap();
+        assert!(tx.input[0].script_sig.is_empty());
+        assert_eq!(tx.input[0].witness.len(), 1);
+        let signature = taproot::Signature::from_slice(tx.input[0].witness.nth(0).unwrap()).unwrap();
+        assert_eq!((tx.input[0].witness.nth(0).unwrap().len(), signature.sighash_type), (64, TapSighashType::Default));
+
+        // Valid for the tweaked output key, committing to both spent outputs
+        let prevouts: Vec<TxOut> =
+            signers.iter().map(|s| TxOut { value: s.amount.unwrap(), script_pubkey: s.script_pubkey.clone() }).collect();
+        let sighash = SighashCache::new(&unsigned)
+            .taproot_key_spend_signature_hash(0, &Prevouts::All(&prevouts), TapSighashType::Default)
+            .unwrap();
+        let output_key = XOnlyPublicKey::from_slice(&address.script_pubkey().as_bytes()[2..]).unwrap();
+        let message = Message::from_digest(sighash.to_byte_array());
+        assert!(secp.verify_schnorr(&signature.signature, &message, &output_key).is_ok());
+
+        // Any explicit sighash type is appended, even ALL
+        let mut explicit = signers.clone();
+        explicit[0].sighash_type = Some(EcdsaSighashType::All);
+        let mut tx = unsigned.clone();
+        sign_transaction(&mut tx, &explicit, BtcNetwork::Bitcoin).unwrap();
+        let signature = tx.input[0].witness.nth(0).unwrap();
+        assert_eq!((signature.len(), signature[64]), (65, 0x01));
+
+        // The output key must be this key's, and every amount is needed
+        let mut wrong_key = signers.clone();
+        wrong_key[0].private_key = key(9, NetworkKind::Main);
+        let mut missing_amount = signers.clone();
+        missing_amount[1].amount = None;
+        let cases = [(wrong_key, "does not match"), (missing_amount, "input 1 has none")];
+        for (signers, expected) in cases {
+            let mut tx = unsigned.clone();
+            match sign_transaction(&mut tx, &signers, BtcNetwork::Bitcoin) {
+                Err(Error::SigningError(message)) => assert!(message.contains(expected), "{}", message),
+                other => panic!("unexpected result: {:?}", other),
+            }
+            assert_eq!(tx, unsigned);
+        }
+    }
+
+    #[test]
+    fn test_bip341_key_path_spending_vectors() {
+        // keyPathSpending from BIP341's wallet-test-vectors.json: inputs 0, 1, 4 and 8, which spend
+        // with SINGLE (no script tree), SINGLE|ANYONECANPAY, DEFAULT and ALL|ANYONECANPAY
+        let unsigned: Transaction = bitcoin::consensus::encode::deserialize_hex(
+            "02000000097de20cbff686da83a54981d2b9bab3586f4ca7e48f57f5b55963115f3b334e9c010000000000000000d7b7cab57b1393ace2d064f4d4a2cb8af6def61273e127517d44759b6dafdd990000000000fffffffff8e1f583384333689228c5d28eac13366be082dc57441760d957275419a418420000000000fffffffff0689180aa63b30cb162a73c6d2a38b7eeda2a83ece74310fda0843ad604853b0100000000feffffffaa5202bdf6d8ccd2ee0f0202afbbb7461d9264a25e5bfd3c5a52ee1239e0ba6c0000000000feffffff956149bdc66faa968eb2be2d2faa29718acbfe3941215893a2a3446d32acd050000000000000000000e664b9773b88c09c32cb70a2a3e4da0ced63b7ba3b22f848531bbb1d5d5f4c94010000000000000000e9aa6b8e6c9de67619e6a3924ae25696bb7b694bb677a632a74ef7eadfd4eabf0000000000ffffffffa778eb6a263dc090464cd125c466b5a99667720b1c110468831d058aa1b82af10100000000ffffffff0200ca9a3b000000001976a91406afd46bcdfd22ef94ac122aa11f241244a37ecc88ac807840cb0000000020ac9a87f5594be208f8532db38cff670c450ed2fea8fcdefcc9a663f78bab962b0065cd1d",
+        )
+        .unwrap();
+        let utxos_spent = [
+            ("512053a1f6e454df1aa2776a2814a721372d6258050de330b3c6d10ee8f4e0dda343", 420_000_000),
+            ("5120147c9c57132f6e7ecddba9800bb0c4449251c92a1e60371ee77557b6620f3ea3", 462_000_000),
+            ("76a914751e76e8199196d454941c45d1b3a323f1433bd688ac", 294_000_000),
+            ("5120e4d810fd50586274face62b8a807eb9719cef49c04177cc6b76a9a4251d5450e", 504_000_000),
+            ("512091b64d5324723a985170e4dc5a0f84c041804f2cd12660fa5dec09fc21783605", 630_000_000),
+            ("00147dd65592d0ab2fe0d0257d571abf032cd9db93dc", 378_000_000),
+            ("512075169f4001aa68f15bbed28b218df1d0a62cbbcf1188c6665110c293c907b831", 672_000_000),
+            ("5120712447206d7a5238acc7ff53fbe94a3b64539ad291c7cdbc490b7577e4b17df5", 546_000_000),
+            ("512077e30a5522dd9f894c3f8b8bd4c4b2cf82ca7da8a3ea6a239655c39c050ab220", 588_000_000),
+        ];
+        let prevouts: Vec<TxOut> = utxos_spent
+            .iter()
+            .map(|(script, amount)| TxOut { value: Amount::from_sat(*amount), script_pubkey: ScriptBuf::from_hex(script).unwrap() })
+            .collect();
+
+        // (input, internal private key, merkle root, hash type, tweaked private key, sighash, witness)
+        let cases = [
+            (
+                0,
+                "6b973d88838f27366ed61c9ad6367663045cb456e28335c109e30717ae0c6baa",
+                None,
+                TapSighashType::Single,
+                "2405b971772ad26915c8dcdf10f238753a9b837e5f8e6a86fd7c0cce5b7296d9",
+                "2514a6272f85cfa0f45eb907fcb0d121b808ed37c6ea160a5a9046ed5526d555",
+                "ed7c1647cb97379e76892be0cacff57ec4a7102aa24296ca39af7541246d8ff14d38958d4cc1e2e478e4d4a764bbfd835b16d4e314b72937b29833060b87276c03",
+            ),
+            (
+                1,
+                "1e4da49f6aaf4e5cd175fe08a32bb5cb4863d963921255f33d3bc31e1343907f",
+                Some("5b75adecf53548f3ec6ad7d78383bf84cc57b55a3127c72b9a2481752dd88b21"),
+                TapSighashType::SinglePlusAnyoneCanPay,
+                "ea260c3b10e60f6de018455cd0278f2f5b7e454be1999572789e6a9565d26080",
+                "325a644af47e8a5a2591cda0ab0723978537318f10e6a63d4eed783b96a71a4d",
+                "052aedffc554b41f52b521071793a6b88d6dbca9dba94cf34c83696de0c1ec35ca9c5ed4ab28059bd606a4f3a657eec0bb96661d42921b5f50a95ad33675b54f83",
+            ),
+            (
+                4,
+                "f36bb07a11e469ce941d16b63b11b9b9120a84d9d87cff2c84a8d4affb438f4e",
+                Some("ccbd66c6f7e8fdab47b3a486f59d28262be857f30d4773f2d5ea47f7761ce0e2"),
+                TapSighashType::Default,
+                "a8e7aa924f0d58854185a490e6c41f6efb7b675c0f3331b7f14b549400b4d501",
+                "4f900a0bae3f1446fd48490c2958b5a023228f01661cda3496a11da502a7f7ef",
+                "b4010dd48a617db09926f729e79c33ae0b4e94b79f04a1ae93ede6315eb3669de185a17d2b0ac9ee09fd4c64b678a0b61a0a86fa888a273c8511be83bfd6810f",
+            ),
+            (
+                8,
+                "77863416be0d0665e517e1c375fd6f75839544eca553675ef7fdf4949518ebaa",
+                Some("ab179431c28d3b68fb798957faf5497d69c883c6fb1e1cd9f81483d87bac90cc"),
+                TapSighashType::AllPlusAnyoneCanPay,
+                "ec18ce6af99f43815db543f47b8af5ff5df3b2cb7315c955aa4a86e8143d2bf5",
+                "cccb739eca6c13a8a89e6e5cd317ffe55669bbda23f2fd37b0f18755e008edd2",
+                "bbc9584a11074e83bc8c6759ec55401f0ae7b03ef290c3139814f545b58a9f8127258000874f44bc46db7646322107d4d86aec8e73b8719a61fff761d75b5dd981",
+            ),
+        ];
+
+        let secp = Secp256k1::new();
+        let mut cache = SighashCache::new(&unsigned);
+        for (input, internal_key, merkle_root, sighash_type, tweaked_key, sighash, witness) in cases {
+            let keypair = Keypair::from_seckey_str(&secp, internal_key).unwrap();
+            let merkle_root = merkle_root.map(|root| taproot::TapNodeHash::from_str(root).unwrap());
+            let tweaked = keypair.tap_tweak(&secp, merkle_root).to_keypair();
+            assert_eq!(tweaked.secret_key().display_secret().to_string(), tweaked_key, "input {}", input);
+            assert_eq!(tweaked.x_only_public_key().0.serialize(), prevouts[input].script_pubkey.as_bytes()[2..], "input {}", input);
+
+            let computed = cache.taproot_key_spend_signature_hash(input, &Prevouts::All(&prevouts), sighash_type).unwrap();
+            assert_eq!(computed.to_string(), sighash, "input {}", input);
+
+            // BIP340 signatures are deterministic for a given aux_rand, all zeros here
+            let message = Message::from_digest(computed.to_byte_array());
+            let signature = taproot::Signature { signature: secp.sign_schnorr_with_aux_rand(&message, &tweaked, &[0; 32]), sighash_type };
+            assert_eq!(signature.to_vec().to_lower_hex_string(), witness, "input {}", input);
+        }
+    }
+
+    #[test]
+    fn test_sign_p2sh_multisig_in_turns() {
+        let secp = Secp256k1::new();
+        let keys = [key(1, NetworkKind::Test), key(2, NetworkKind::Test), key(3, NetworkKind::Test)];
+        let redeem_script =
+            MultisigScript { required: 2, pubkeys: keys.iter().map(|key| key.public_key(&secp)).collect() }.to_script();
+        let signer = |private_key| InputSigner {
+            private_key,
+            script_pubkey: redeem_script.to_p2sh(),
+            amount: None,
+            sighash_type: None,
+            redeem_script: Some(redeem_script.clone()),
+        };
+        let sign = |tx: &Transaction, private_key| {
+            let mut tx = tx.clone();
+            sign_transaction(&mut tx, &[signer(private_key)], BtcNetwork::Regtest).map(|_| tx)
+        };
+
+        // Built independently: OP_0 for CHECKMULTISIG's extra pop, then signatures in key order
+        let unsigned = unsigned_tx(1);
+        let sighash = SighashCache::new(&unsigned).legacy_signature_hash(0, &redeem_script, 0x01).unwrap();
+        let message = Message::from_digest(sighash.to_byte_array());
+        let signature = |key: &PrivateKey| ecdsa::Signature::sighash_all(secp.sign_ecdsa(&message, &key.inner)).serialize();
+        let redeem = PushBytesBuf::try_from(redeem_script.to_bytes()).unwrap();
+        let expected = Builder::new()
+            .push_opcode(bitcoin::opcodes::OP_0)
+            .push_slice(signature(&keys[0]))
+            .push_slice(signature(&keys[1]))
+            .push_slice(&redeem)
+            .into_script();
+
+        // Partially signed by the second key alone
+        let partial = sign(&unsigned, keys[1]).unwrap();
+        let partial_script_sig =
+            Builder::new().push_opcode(bitcoin::opcodes::OP_0).push_slice(signature(&keys[1])).push_slice(&redeem).into_script();
+        assert_eq!(partial.input[0].script_sig, partial_script_sig);
+        assert!(partial.input[0].witness.is_empty());
+
+        // Either signing order ends with the same scriptSig
+        let first_then_second = sign(&sign(&unsigned, keys[0]).unwrap(), keys[1]).unwrap();
+        let second_then_first = sign(&partial, keys[0]).unwrap();
+        assert_eq!(first_then_second.input[0].script_sig, expected);
+        assert_eq!(second_then_first.input[0].script_sig, expected);
+        let spent = [SpentOutput { script_pubkey: redeem_script.to_p2sh(), amount: None }];
+        verify_transaction(&partial, &spent).unwrap();
+        verify_transaction(&second_then_first, &spent).unwrap();
+
+        // A third signature, a key outside the script and a redeem script for another output
+        let mut other_output = signer(keys[0]);
+        other_output.script_pubkey = ScriptBuf::new_op_return([]).to_p2sh();
+        let cases = [
+            (sign(&first_then_second, keys[2]), "already has the 2 signatures"),
+            (sign(&unsigned, key(4, NetworkKind::Test)), "not one of its redeem script's keys"),
+            (sign_transaction(&mut unsigned.clone(), &[other_output], BtcNetwork::Regtest).map(|_| unsigned.clone()), "does not hash"),
+        ];
+        for (result, expected) in cases {
+            match result {
+                Err(Error::SigningError(message)) => assert!(message.contains(expected), "{}", message),
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_verify_transaction() {
+        let secp = Secp256k1::new();
+        let legacy = key(1, NetworkKind::Test);
+        let segwit = key(2, NetworkKind::Test);
+        let taproot_key = key(3, NetworkKind::Test);
+        let p2pkh = |key: &PrivateKey| ScriptBuf::new_p2pkh(&key.public_key(&secp).pubkey_hash());
+        let signer = |private_key, script_pubkey, amount| InputSigner {
+            private_key,
+            script_pubkey,
+            amount: Some(Amount::from_sat(amount)),
+            sighash_type: None,
+            redeem_script: None,
+        };
+        let signers = [
+            signer(legacy, p2pkh(&legacy), 10_000),
+            signer(segwit, ScriptBuf::new_p2wpkh(&CompressedPublicKey::from_private_key(&secp, &segwit).unwrap().wpubkey_hash()), 20_000),
+            signer(taproot_key, ScriptBuf::new_p2tr(&secp, taproot_key.inner.x_only_public_key(&secp).0, None), 70_000),
+        ];
+        let spent: Vec<SpentOutput> = signers.iter().map(SpentOutput::from).collect();
+        let mut tx = unsigned_tx(3);
+        sign_transaction(&mut tx, &signers, BtcNetwork::Regtest).unwrap();
+        verify_transaction(&tx, &spent).unwrap();
+
+        // Input 0 signed by another key, under the right public key
+        let stranger = key(4, NetworkKind::Test);
+        let mut by_stranger = unsigned_tx(3);
+        let mut stranger_signers = signers.clone();
+        stranger_signers[0] = signer(stranger, p2pkh(&stranger), 10_000);
+        sign_transaction(&mut by_stranger, &stranger_signers, BtcNetwork::Regtest).unwrap();
+        let Some(Ok(Instruction::PushBytes(signature))) = by_stranger.input[0].script_sig.instructions().next() else {
+            panic!("no signature in the scriptSig");
+        };
+        let mut wrong_signature = tx.clone();
+        wrong_signature.input[0].script_sig =
+            Builder::new().push_slice(signature).push_key(&legacy.public_key(&secp)).into_script();
+
+        // Claimed prevouts that differ from what was signed
+        let mut wrong_script = spent.clone();
+        wrong_script[0].script_pubkey = p2pkh(&stranger);
+        let mut wrong_amount = spent.clone();
+        wrong_amount[1].amount = Some(Amount::from_sat(20_001));
+        let mut wrong_taproot_amount = spent.clone();
+        wrong_taproot_amount[2].amount = Some(Amount::from_sat(69_999));
+        let cases = [
+            (&wrong_signature, &spent, "Input 0 failed verification: the signature does not verify"),
+            (&tx, &wrong_script, "Input 0 failed verification: the public key used does not match the spent output"),
+            (&tx, &wrong_amount, "Input 1 failed verification: the signature does not verify"),
+            (&tx, &wrong_taproot_amount, "Input 2 failed verification: the signature does not verify"),
+            (&unsigned_tx(3), &spent, "Input 0 failed verification: the scriptSig is not <signature> <public key>"),
+        ];
+        for (tx, spent, expected) in cases {
+            match verify_transaction(tx, spent) {
+                Err(Error::SigningError(message)) => assert_eq!(message, expected),
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_sighash_types() {
+        let secp = Secp256k1::new();
+        let legacy = key(1, NetworkKind::Test);
+        let segwit = key(2, NetworkKind::Test);
+        let segwit_pub = CompressedPublicKey::from_private_key(&secp, &segwit).unwrap();
+        let scripts = [
+            ScriptBuf::new_p2pkh(&legacy.public_key(&secp).pubkey_hash()),
+            ScriptBuf::new_p2wpkh(&segwit_pub.wpubkey_hash()),
+        ];
+        let amount = Amount::from_sat(50_000);
+        // SIGHASH_SINGLE on the second input needs a second output
+        let mut unsigned = unsigned_tx(2);
+        unsigned.output.push(TxOut { value: Amount::from_sat(1_000), script_pubkey: scripts[0].clone() });
+
+        let names = ["ALL", "NONE", "SINGLE", "ALL|ANYONECANPAY", "NONE|ANYONECANPAY", "SINGLE|ANYONECANPAY"];
+        let mut sighashes = HashSet::new();
+        for name in names {
+            let sighash_type = parse_sighash_type(name).unwrap();
+            let signer = |private_key, input: usize, amount| InputSigner {
+                private_key,
+                script_pubkey: scripts[input].clone(),
+                amount,
+                sighash_type: Some(sighash_type),
+                redeem_script: None,
+            };
+            let signers = [signer(legacy, 0, None), signer(segwit, 1, Some(amount))];
+            let mut tx = unsigned.clone();
+            sign_transaction(&mut tx, &signers, BtcNetwork::Regtest).unwrap();
+
+            let mut cache = SighashCache::new(&unsigned);
+            let legacy_sighash = cache.legacy_signature_hash(0, &scripts[0], sighash_type.to_u32()).unwrap().to_byte_array();
+            let segwit_sighash = cache.p2wpkh_signature_hash(1, &scripts[1], amount, sighash_type).unwrap().to_byte_array();
+            let Some(Ok(Instruction::PushBytes(legacy_signature))) = tx.input[0].script_sig.instructions().next() else {
+                panic!("no signature in the scriptSig");
+            };
+            let checks = [
+                (legacy_signature.as_bytes(), legacy_sighash, legacy.public_key(&secp).inner),
+                (tx.input[1].witness.nth(0).unwrap(), segwit_sighash, segwit_pub.0),
+            ];
+            for (signature, sighash, public_key) in checks {
+                // The appended byte is the type the sighash was computed with
+                assert_eq!(*signature.last().unwrap(), sighash_type.to_u32() as u8, "{}", name);
+                let signature = ecdsa::Signature::from_slice(signature).unwrap();
+                assert_eq!(signature.sighash_type, sighash_type);
+                let message = Message::from_digest(sighash);
+                assert!(secp.verify_ecdsa(&message, &signature.signature, &public_key).is_ok(), "{}", name);
+                sighashes.insert(sighash);
+            }
+        }
+        assert_eq!(sighashes.len(), 2 * names.len());
+
+        // No output for SIGHASH_SINGLE to commit to
+        let signers = [
+            InputSigner {
+                private_key: legacy,
+                script_pubkey: scripts[0].clone(),
+                amount: None,
+                sighash_type: None,
+                redeem_script: None,
+            },
+            InputSigner {
+                private_key: segwit,
+                script_pubkey: scripts[1].clone(),
+                amount: Some(amount),
+                sighash_type: Some(EcdsaSighashType::SinglePlusAnyoneCanPay),
+                redeem_script: None,
+            },
+        ];
+        let mut tx = unsigned_tx(2);
+        match sign_transaction(&mut tx, &signers, BtcNetwork::Regtest) {
+            Err(Error::SigningError(message)) => assert!(message.contains("no output 1 to commit to"), "{}", message),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(tx, unsigned_tx(2));
+    }
+
+    #[test]
+    fn test_parse_sighash_type() {
+        let cases = [
+            ("all", EcdsaSighashType::All),
+            ("SIGHASH_NONE", EcdsaSighashType::None),
+            ("single | anyonecanpay", EcdsaSighashType::SinglePlusAnyoneCanPay),
+            ("SIGHASH_ALL|SIGHASH_ANYONECANPAY", EcdsaSighashType::AllPlusAnyoneCanPay),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(parse_sighash_type(name).unwrap(), expected);
+        }
+        for name in ["", "ANYONECANPAY", "ALL|ALL", "ALL|ANYONECANPAY|NONE", "DEFAULT"] {
+            assert!(matches!(parse_sighash_type(name), Err(Error::InvalidParameter(_))), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_signers() {
+        let secp = Secp256k1::new();
+        let signer = |private_key: PrivateKey, amount| InputSigner {
+            private_key,
+            script_pubkey: ScriptBuf::new_p2wpkh(
+                &CompressedPublicKey::from_private_key(&secp, &key(3, NetworkKind::Test)).unwrap().wpubkey_hash(),
+            ),
+            amount,
+            sighash_type: None,
+            redeem_script: None,
+        };
+        let amount = Some(Amount::from_sat(1_000));
+        // A P2SH output whose redeem script is not the key's witness program
+        let multisig = InputSigner {
+            private_key: key(3, NetworkKind::Test),
+            script_pubkey: ScriptBuf::new_op_return([]).to_p2sh(),
+            amount,
+            sighash_type: None,
+            redeem_script: None,
+        };
+        let cases = [
+            (vec![], "Input count mismatch"),
+            (vec![signer(key(3, NetworkKind::Main), amount)], "is not a regtest key"),
+            (vec![signer(key(4, NetworkKind::Test), amount)], "does not match"),
+            (vec![signer(key(3, NetworkKind::Test), None)], "amount it spends is required"),
+            (vec![multisig], "need their redeem script"),
+        ];
+        for (signers, expected) in cases {
+            let mut tx = unsigned_tx(1);
+            match sign_transaction(&mut tx, &signers, BtcNetwork::Regtest) {
+                Err(Error::SigningError(message)) => assert!(message.contains(expected), "{}", message),
+                other => panic!("unexpected result: {:?}", other),
+            }
+            assert_eq!(tx, unsigned_tx(1));
+        }
+    }
+
+    #[test]
+    fn test_debug_redacts_key() {
+        let private_key = key(5, NetworkKind::Main);
+        let signer =
+            InputSigner { private_key, script_pubkey: ScriptBuf::new(), amount: None, sighash_type: None, redeem_script: None };
+        assert!(!format!("{:?}", signer).contains(&private_key.to_wif()));
+    }
+}
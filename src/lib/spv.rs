@@ -0,0 +1,220 @@
+//! Header and merkle proof verification, to check confirmations without trusting the API
+
+use bitcoin::block::Header as BlockHeader;
+use bitcoin::hashes::{sha256d, Hash, HashEngine};
+use bitcoin::pow::CompactTarget;
+use bitcoin::{Target, TxMerkleNode, Txid};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+
+/// Merkle inclusion proof as returned by `/tx/{txid}/merkle-proof`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MerkleProof {
+    /// Height of the block containing the transaction
+    pub block_height: u32,
+    /// Sibling hashes from the leaf up (served as display-order hex)
+    pub merkle: Vec<sha256d::Hash>,
+    /// Position of the transaction in the block
+    pub pos: u32,
+}
+
+/// Decode the compact `nBits` target, rejecting negative, zero and overflowing encodings
+///
+/// Bitcoin Core treats such encodings as invalid rather than as a target, so a
+/// header carrying one can never satisfy proof of work.
+pub fn decode_compact_target(bits: CompactTarget) -> Result<Target> {
+    let bits = bits.to_consensus();
+    let exponent = bits >> 24;
+    let mantissa = bits & 0x007f_ffff;
+
+    if bits & 0x0080_0000 != 0 && mantissa != 0 {
+        return Err(Error::VerificationFailed(format!("Negative target in nBits {:#010x}", bits)));
+    }
+    let overflows =
+        exponent > 34 || (mantissa > 0xff && exponent > 33) || (mantissa > 0xffff && exponent > 32);
+    if mantissa != 0 && overflows {
+        return Err(Error::VerificationFailed(format!("Overflowing target in nBits {:#010x}", bits)));
+    }
+
+    let target = Target::from_compact(CompactTarget::from_consensus(bits));
+    if target == Target::ZERO {
+        return Err(Error::VerificationFailed(format!("Zero target in nBits {:#010x}", bits)));
+    }
+    Ok(target)
+}
+
+/// Check that a header hashes below the target its `nBits` claims
+pub fn verify_header_pow(header: &BlockHeader) -> Result<()> {
+    let target = decode_compact_target(header.bits)?;
+    header.validate_pow(target).map_err(|_| {
+        Error::VerificationFailed(format!("Block {} does not meet its target", header.block_hash()))
+    })?;
+    Ok(())
+}
+
+/// Check proof of work of every header and that each links to the previous one
+///
+/// Only checks each header against its own `nBits`; difficulty adjustments are
+/// not recomputed.
+pub fn verify_header_chain(headers: &[BlockHeader]) -> Result<()> {
+    for header in headers {
+        verify_header_pow(header)?;
+    }
+    for pair in headers.windows(2) {
+        if pair[1].prev_blockhash != pair[0].block_hash() {
+            return Err(Error::VerificationFailed(format!(
+                "Block {} does not build on {}",
+                pair[1].block_hash(),
+                pair[0].block_hash()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Compute the merkle root a proof leads to from a txid
+pub fn merkle_root_from_proof(txid: &Txid, proof: &MerkleProof) -> Result<TxMerkleNode> {
+    if proof.merkle.len() < 32 && proof.pos >> proof.merkle.len() != 0 {
+        return Err(Error::VerificationFailed(format!(
+            "Position {} does not fit a merkle branch of depth {}",
+            proof.pos,
+            proof.merkle.len()
+        )));
+    }
+
+    let mut current = txid.to_raw_hash();
+    let mut pos = proof.pos;
+    for sibling in &proof.merkle {
+        let mut engine = sha256d::Hash::engine();
+        if pos & 1 == 1 {
+            engine.input(sibling.as_byte_array());
+            engine.input(current.as_byte_array());
+        } else {
+            engine.input(current.as_byte_array());
+            engine.input(sibling.as_byte_array());
+        }
+        current = sha256d::Hash::from_engine(engine);
+        pos >>= 1;
+    }
+    Ok(TxMerkleNode::from_raw_hash(current))
+}
+
+/// Check that a merkle proof connects a txid to a merkle root
+pub fn verify_merkle_proof(txid: &Txid, proof: &MerkleProof, merkle_root: &TxMerkleNode) -> Result<()> {
+    if merkle_root_from_proof(txid, proof)? != *merkle_root {
+        return Err(Error::VerificationFailed(format!(
+            "Merkle proof for {} does not match root {}",
+            txid, merkle_root
+        )));
+    }
+    Ok(())
+}
+
+/// Whether a merkle proof places a txid in the block with this header
+///
+/// Returns `Ok(false)` when the branch leads to a different root, and an
+/// error when the proof itself is malformed. Proof of work is not checked.
+pub fn verify_tx_in_block(txid: &Txid, proof: &MerkleProof, header: &BlockHeader) -> Result<bool> {
+    Ok(merkle_root_from_proof(txid, proof)? == header.merkle_root)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin::consensus::encode::deserialize_hex;
+
+    // Mainnet blocks 0, 1 and 2
+    const GENESIS: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
+    const BLOCK_1: &str = "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299";
+    const BLOCK_2: &str = "010000004860eb18bf1b1620e37e9490fc8a427514416fd75159ab86688e9a8300000000d5fdcc541e25de1c7a5addedf24858b8bb665c9f36ef744ee42c316022c90f9bb0bc6649ffff001d08d2bd61";
+
+    fn header(hex: &str) -> BlockHeader {
+        deserialize_hex(hex).unwrap()
+    }
+
+    #[test]
+    fn test_real_header_chain_verifies() {
+        let headers = [header(GENESIS), header(BLOCK_1), header(BLOCK_2)];
+        assert_eq!(
+            headers[2].block_hash().to_string(),
+            "000000006a625f06636b8bb6ac7b960a8d03705d1ace08b1a19da3fdcc99ddbd"
+        );
+        verify_header_chain(&headers).unwrap();
+    }
+
+    #[test]
+    fn test_corrupted_header_fails_pow() {
+        let mut corrupted = header(BLOCK_1);
+        corrupted.nonce ^= 1;
+        assert!(matches!(
+            verify_header_chain(&[header(GENESIS), corrupted]),
+            Err(Error::VerificationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_broken_linkage_fails() {
+        assert!(matches!(
+            verify_header_chain(&[header(GENESIS), header(BLOCK_2)]),
+            Err(Error::VerificationFailed(_))
+        ));
+    }
+
+    #[test]
+    fn test_decode_compact_quirks() {
+        let max = decode_compact_target(CompactTarget::from_consensus(0x1d00ffff)).unwrap();
+        assert_eq!(max, Target::MAX_ATTAINABLE_MAINNET);
+        // Sign bit set
+        assert!(decode_compact_target(CompactTarget::from_consensus(0x1d80ffff)).is_err());
+        // Exponent too large for 256 bits
+        assert!(decode_compact_target(CompactTarget::from_consensus(0xff123456)).is_err());
+        assert!(decode_compact_target(CompactTarget::from_consensus(0x2200ffff)).is_err());
+        // Zero mantissa
+        assert!(decode_compact_target(CompactTarget::from_consensus(0x1d000000)).is_err());
+    }
+
+    #[test]
+    fn test_merkle_proof() {
+        // Block 1 holds only its coinbase, so the txid is the merkle root
+        let block = header(BLOCK_1);
+        let coinbase: Txid = "0e3e2357e806b6cdb1f70b54c3a3a17b6714ee1f0e68bebb44a74b1efd512098"
+            .parse()
+            .unwrap();
+        let proof = MerkleProof { block_height: 1, merkle: Vec::new(), pos: 0 };
+        verify_merkle_proof(&coinbase, &proof, &block.merkle_root).unwrap();
+
+        // Two-leaf tree: root = H(a || b)
+        let a = Txid::from_byte_array([1; 32]);
+        let b = Txid::from_byte_array([2; 32]);
+        let mut engine = sha256d::Hash::engine();
+        engine.input(&[1; 32]);
+        engine.input(&[2; 32]);
+        let root = TxMerkleNode::from_raw_hash(sha256d::Hash::from_engine(engine));
+
+        let proof_b = MerkleProof { block_height: 1, merkle: vec![a.to_raw_hash()], pos: 1 };
+        verify_merkle_proof(&b, &proof_b, &root).unwrap();
+        let wrong_pos = MerkleProof { pos: 0, ..proof_b.clone() };
+        assert!(verify_merkle_proof(&b, &wrong_pos, &root).is_err());
+        let out_of_range = MerkleProof { pos: 2, ..proof_b };
+        assert!(merkle_root_from_proof(&b, &out_of_range).is_err());
+    }
+
+    // Block 170: the coinbase and the first person-to-person transaction
+    const BLOCK_170_MERKLE_ROOT: &str = "7dac2c5666815c17a3b36427de37bb9d2e2c5ccec3f8633eb91a4205cb4c10ff";
+    const BLOCK_170_PROOF: &str = r#"{"block_height":170,"merkle":["b1fea52486ce0c62bb442b530a3f0132b826c74e473d1f2c220bfa78111c5082"],"pos":1}"#;
+    const BLOCK_170_TX: &str = "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16";
+
+    #[test]
+    fn test_verify_tx_in_block_with_captured_proof() {
+        let mut block = header(GENESIS);
+        block.merkle_root = BLOCK_170_MERKLE_ROOT.parse().unwrap();
+        let proof: MerkleProof = serde_json::from_str(BLOCK_170_PROOF).unwrap();
+        let txid: Txid = BLOCK_170_TX.parse().unwrap();
+        assert!(verify_tx_in_block(&txid, &proof, &block).unwrap());
+
+        let mut tampered = proof.clone();
+        tampered.merkle[0] = sha256d::Hash::from_byte_array([7; 32]);
+        assert!(!verify_tx_in_block(&txid, &tampered, &block).unwrap());
+    }
+}
@@ -1,57 +1,41 @@
 //! Transaction builder for creating and signing Bitcoin transactions
+//!
+//! Selects coins from a set of UTXOs, pays the requested outputs, adds change
+//! when it is worth keeping and reports what it chose. Fees are computed from
+//! the predicted size of the signed transaction, so P2PKH, P2SH-P2WPKH,
+//! P2WPKH and P2TR key-path inputs are supported.
 
+use std::collections::HashSet;
 use std::str::FromStr;
 
+use bitcoin::secp256k1::rand::seq::SliceRandom;
+use bitcoin::transaction::{self, predict_weight, InputWeightPrediction};
 use bitcoin::{
-    absolute, secp256k1, Address, Amount, EcdsaSighashType, OutPoint, Script,
-    Sequence, Transaction, TxIn, TxOut, Txid, Witness,
+    absolute, Address, Amount, Network, OutPoint, PrivateKey, ScriptBuf, Sequence, Transaction, TxIn, TxOut,
+    Witness,
 };
-use rand::seq::SliceRandom;
 use serde::{Deserialize, Serialize};
 
 use crate::error::{Error, Result};
-use crate::types::{
-    BtcNetwork, CoinSelectionStrategy, OutputTarget, SigningInput,
-    SignedTransaction, Utxo,
-};
+use crate::signing::{sign_transaction, InputSigner};
+use crate::types::{BtcNetwork, CoinSelectionStrategy, OutputTarget, SignedTransaction, TxBuilderConfig, Utxo};
 
-/// Transaction builder configuration
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TxBuilderConfig {
-    /// The network to use
-    pub network: BtcNetwork,
-    /// The fee rate in satoshis per virtual byte
-    pub fee_rate: f32,
-    /// The dust threshold in satoshis
-    pub dust_limit: u64,
-    /// Whether to use RBF (Replace-By-Fee)
-    pub rbf: bool,
-    /// The sequence number to use for RBF
-    pub rbf_sequence: u32,
-    /// The minimum change amount to keep as change (otherwise add to fee)
-    pub min_change: Amount,
-    /// The coin selection strategy to use
-    pub coin_selection: CoinSelectionStrategy,
-    /// Whether to shuffle inputs for privacy
-    pub shuffle_inputs: bool,
-    /// Whether to shuffle outputs for privacy
-    pub shuffle_outputs: bool,
-}
+/// Most combinations branch and bound tries before falling back to largest first
+const BNB_MAX_TRIES: usize = 100_000;
 
-impl Default for TxBuilderConfig {
-    fn default() -> Self {
-        TxBuilderConfig {
-            network: BtcNetwork::Bitcoin,
-            fee_rate: 1.0,
-            dust_limit: 546, // Standard dust limit
-            rbf: false,
-            rbf_sequence: 0xFFFFFFFD, // Enable RBF with nSequence
-            min_change: Amount::from_sat(1_000), // 0.00001 BTC
-            coin_selection: CoinSelectionStrategy::BranchAndBound,
-            shuffle_inputs: true,
-            shuffle_outputs: true,
-        }
-    }
+/// What the builder chose
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BuildReport {
+    /// UTXOs spent, in input order
+    pub selected: Vec<Utxo>,
+    /// Fee paid, including any excess too small to keep as change
+    #[serde(with = "bitcoin::amount::serde::as_sat")]
+    pub fee: Amount,
+    /// Value of the change output, the last output, if one was added
+    #[serde(with = "bitcoin::amount::serde::as_sat::opt")]
+    pub change: Option<Amount>,
+    /// Predicted virtual size once signed
+    pub vsize: u64,
 }
 
 /// Transaction builder
@@ -59,7 +43,6 @@ pub struct TransactionBuilder {
     config: TxBuilderConfig,
     utxos: Vec<Utxo>,
     outputs: Vec<OutputTarget>,
-    inputs: Vec<SigningInput>,
     change_address: Option<Address>,
     lock_time: Option<u32>,
     version: i32,
@@ -75,7 +58,6 @@ impl TransactionBuilder {
             },
             utxos: Vec::new(),
             outputs: Vec::new(),
-            inputs: Vec::new(),
             change_address: None,
             lock_time: None,
             version: 2, // Default to version 2 for BIP68
@@ -96,7 +78,7 @@ impl TransactionBuilder {
 
     /// Add an output to the transaction
     pub fn add_output(&mut self, address: Address, amount: Amount) -> &mut Self {
-        self.outputs.push(OutputTarget::new(address, amount));
+        self.outputs.push(OutputTarget::new(address.to_string(), amount));
         self
     }
 
@@ -113,6 +95,8 @@ impl TransactionBuilder {
     }
 
     /// Set the lock time
+    ///
+    /// Inputs then get a non-final sequence so the lock time is enforced.
     pub fn set_lock_time(&mut self, lock_time: u32) -> &mut Self {
         self.lock_time = Some(lock_time);
         self
@@ -126,344 +110,391 @@ impl TransactionBuilder {
 
     /// Build an unsigned transaction
     pub fn build_unsigned(&self) -> Result<Transaction> {
+        self.build_with_report().map(|(tx, _)| tx)
+    }
+
+    /// Build an unsigned transaction and report the coins, fee and change chosen
+    ///
+    /// Fails with `Error::InsufficientFunds` when the UTXOs cannot pay the
+    /// outputs plus the fee, and with `Error::InvalidParameter` when change is
+    /// due but no change address is set.
+    pub fn build_with_report(&self) -> Result<(Transaction, BuildReport)> {
         if self.outputs.is_empty() {
-            return Err(Error::Custom("No outputs specified".into()));
+            return Err(Error::InvalidParameter("No outputs specified".into()));
+        }
+        let fee_rate = self.config.fee_rate;
+        if !fee_rate.is_finite() || fee_rate <= 0.0 {
+            return Err(Error::InvalidParameter(format!("Fee rate must be positive, got {}", fee_rate)));
         }
 
-        // Select UTXOs
-        let selected_utxos = self.select_utxos()?;
-        let total_input = selected_utxos.iter().map(|u| u.amount).sum::<Amount>();
-        
-        // Calculate total output amount
-        let total_output = self
-            .outputs
+        let outputs = self.payment_outputs()?;
+        let target = outputs
             .iter()
-            .filter(|o| !o.is_change)
-            .map(|o| o.amount)
-            .sum::<Amount>();
-
-        // Calculate fee
-        let tx = self.create_unsigned_tx(&selected_utxos, None)?;
-        let weight = tx.weight().to_wu() as usize;
-        let tx_vsize = ((weight + 3) / 4) as u64;
-        let fee = (tx_vsize as f32 * self.config.fee_rate).ceil() as u64;
-        
-        // Check if we have enough funds
-        if total_input < total_output + Amount::from_sat(fee) {
-            return Err(Error::InsufficientFunds);
-        }
+            .try_fold(Amount::ZERO, |total, output| total.checked_add(output.value))
+            .filter(|total| *total <= Amount::MAX_MONEY)
+            .ok_or_else(|| Error::InvalidParameter("Outputs add up to more than 21M BTC".into()))?;
 
-        // Calculate change
-        let change_amount = total_input - total_output - Amount::from_sat(fee);
-        
-        // Add change output if needed
-        let mut final_tx = if change_amount >= self.config.min_change {
-            let change_address = self.change_address.clone().ok_or_else(|| {
-                Error::Custom("Change address not specified".into())
-            })?;
-            
-            let mut outputs = self.outputs.clone();
-            outputs.push(OutputTarget::new_change(change_address, change_amount));
-            
-            self.create_unsigned_tx(&selected_utxos, Some(&outputs[..]))?
-        } else {
-            tx
-        };
+        let candidates = self.candidates()?;
+        let selection = Selection { builder: self, outputs: &outputs, candidates: &candidates, target };
+        let selected = selection.select()?;
 
-        // Set lock time if specified
-        if let Some(lock_time) = self.lock_time {
-            final_tx.lock_time = absolute::LockTime::from(lock_time);
-        }
+        let total_in: Amount = selected.iter().map(|&i| candidates[i].0.amount).sum();
+        let predictions: Vec<_> = selected.iter().map(|&i| candidates[i].1).collect();
+        let fee_without_change = selection.fee(&predictions, false);
+        let excess = total_in - target - fee_without_change;
 
-        // Set version
-        final_tx.version = self.version;
+        let mut tx_outputs = outputs.clone();
+        let mut change = None;
+        if excess >= self.min_change() {
+            let change_address = self.change_address.as_ref().ok_or_else(|| {
+                Error::InvalidParameter(format!("{} sat of change is due but no change address is set", excess.to_sat()))
+            })?;
+            let value = total_in - target - selection.fee(&predictions, true);
+            if value >= self.min_change() {
+                tx_outputs.push(TxOut { value, script_pubkey: change_address.script_pubkey() });
+                change = Some(value);
+            }
+        }
 
-        Ok(final_tx)
+        let tx = self.create_unsigned_tx(selected.iter().map(|&i| &candidates[i].0), tx_outputs);
+        let fee = total_in - tx.output.iter().map(|o| o.value).sum();
+        let vsize = predict_weight(predictions, tx.output.iter().map(|o| o.script_pubkey.len())).to_vbytes_ceil();
+        let report = BuildReport {
+            selected: selected.iter().map(|&i| candidates[i].0.clone()).collect(),
+            fee,
+            change,
+            vsize,
+        };
+        Ok((tx, report))
     }
 
-    /// Build and sign a transaction
-    pub fn build_signed<F>(&self, signer: F) -> Result<SignedTransaction>
+    /// Build and sign a transaction, asking `key_for` for the key of each selected UTXO
+    ///
+    /// Signing supports P2PKH, P2WPKH, P2SH-P2WPKH and P2TR key-path inputs.
+    pub fn build_signed<F>(&self, key_for: F) -> Result<SignedTransaction>
     where
-        F: Fn(&Script, u64, &[u8]) -> Result<(Vec<Vec<u8>>, Script)>,
+        F: Fn(&Utxo) -> Result<PrivateKey>,
     {
-        let unsigned_tx = self.build_unsigned()?;
-        let mut signed_tx = unsigned_tx.clone();
-        
-        // Sign each input
-        for (i, input) in signed_tx.input.iter_mut().enumerate() {
-            let prevout_script = self.inputs[i].script_pubkey.clone();
-            let amount = self.inputs[i].amount;
-            
-            // Create the signature hash
-            let sighash = signed_tx.signature_hash(
-                i,
-                &prevout_script,
-                amount.to_sat(),
-                EcdsaSighashType::All,
-            )?;
-            
-            // Get the signatures and witness script
-            let (signatures, witness_script) = signer(&prevout_script, amount.to_sat(), &sighash)?;
-            
-            // Add signatures to the witness
-            let mut witness = Witness::new();
-            for sig in signatures {
-                witness.push(sig);
-            }
-            witness.push(witness_script.into_bytes());
-            input.witness = witness;
-        }
-        
-        // Create signed transaction
-        let signed_tx = SignedTransaction::new(signed_tx, self.calculate_fee(&unsigned_tx)?, true)?;
-        
-        Ok(signed_tx)
+        let (mut tx, report) = self.build_with_report()?;
+        let signers = report
+            .selected
+            .iter()
+            .map(|utxo| {
+                Ok(InputSigner {
+                    private_key: key_for(utxo)?,
+                    script_pubkey: utxo.script_pubkey.clone(),
+                    amount: Some(utxo.amount),
+                    sighash_type: None,
+                    redeem_script: None,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        sign_transaction(&mut tx, &signers, self.config.network)?;
+        SignedTransaction::new(tx, report.fee, true)
     }
 
-    /// Select UTXOs to spend using the configured strategy
-    fn select_utxos(&self) -> Result<Vec<Utxo>> {
-        match self.config.coin_selection {
-            CoinSelectionStrategy::SmallestFirst => self.select_utxos_smallest_first(),
-            CoinSelectionStrategy::LargestFirst => self.select_utxos_largest_first(),
-            CoinSelectionStrategy::Random => self.select_utxos_random(),
-            CoinSelectionStrategy::BranchAndBound => self.select_utxos_branch_and_bound(),
-        }
+    // Smallest change output worth creating
+    fn min_change(&self) -> Amount {
+        self.config.min_change.max(Amount::from_sat(self.config.dust_limit))
     }
 
-    /// Select UTXOs by smallest first (maximizes privacy)
-    fn select_utxos_smallest_first(&self) -> Result<Vec<Utxo>> {
-        let mut utxos = self.utxos.clone();
-        utxos.sort_by_key(|u| u.amount);
-        self.select_utxos_greedy(&utxos)
+    // Requested outputs, with addresses checked against the network
+    fn payment_outputs(&self) -> Result<Vec<TxOut>> {
+        let network = Network::from(self.config.network);
+        self.outputs
+            .iter()
+            .filter(|o| !o.is_change)
+            .map(|o| {
+                let address = Address::from_str(&o.address)
+                    .map_err(|e| Error::InvalidAddress(format!("{}: {}", o.address, e)))?
+                    .require_network(network)
+                    .map_err(|_| Error::InvalidAddress(format!("{} is not a {} address", o.address, self.config.network)))?;
+                Ok(TxOut { value: o.amount, script_pubkey: address.script_pubkey() })
+            })
+            .collect()
     }
 
-    /// Select UTXOs by largest first (minimizes fees)
-    fn select_utxos_largest_first(&self) -> Result<Vec<Utxo>> {
-        let mut utxos = self.utxos.clone();
-        utxos.sort_by_key(|u| std::cmp::Reverse(u.amount));
-        self.select_utxos_greedy(&utxos)
+    // Spendable UTXOs paired with the predicted size of the input spending them
+    fn candidates(&self) -> Result<Vec<(Utxo, InputWeightPrediction)>> {
+        let mut seen = HashSet::new();
+        let mut candidates = Vec::new();
+        for utxo in self.utxos.iter().filter(|u| u.spendable) {
+            if !seen.insert(utxo.outpoint()) {
+                return Err(Error::InvalidParameter(format!("UTXO {} is listed twice", utxo.outpoint())));
+            }
+            candidates.push((utxo.clone(), input_prediction(&utxo.script_pubkey)?));
+        }
+        Ok(candidates)
     }
 
-    /// Select UTXOs randomly (good for privacy)
-    fn select_utxos_random(&self) -> Result<Vec<Utxo>> {
-        use rand::thread_rng;
-        
-        let mut utxos = self.utxos.clone();
-        let mut rng = thread_rng();
-        utxos.shuffle(&mut rng);
-        
-        self.select_utxos_greedy(&utxos)
-    }
+    /// Create an unsigned transaction spending `utxos` to `outputs`
+    fn create_unsigned_tx<'a>(&self, utxos: impl Iterator<Item = &'a Utxo>, outputs: Vec<TxOut>) -> Transaction {
+        let sequence = if self.config.rbf {
+            Sequence(self.config.rbf_sequence)
+        } else if self.lock_time.is_some() {
+            Sequence::ENABLE_LOCKTIME_NO_RBF
+        } else {
+            Sequence::MAX
+        };
 
-    /// Select UTXOs using a greedy algorithm
-    fn select_utxos_greedy(&self, sorted_utxos: &[Utxo]) -> Result<Vec<Utxo>> {
-        let total_output: Amount = self.outputs.iter().map(|o| o.amount).sum();
-        let mut selected = Vec::new();
-        let mut total_selected = Amount::from_sat(0);
-        
-        // Estimate the size of the transaction with a single input and output
-        let base_tx_size = 10; // Version + lock_time + input count + output count
-        let input_size = 150; // Approximate size of an input
-        let output_size = 34; // Approximate size of an output (P2PKH)
-        
-        // Calculate the minimum amount needed including fees
-        let min_amount = total_output + Amount::from_sat(
-            (base_tx_size + output_size * self.outputs.len()) as u64 * self.config.fee_rate as u64
-        );
-        
-        for utxo in sorted_utxos {
-            if total_selected >= min_amount {
-                break;
-            }
-            
-            selected.push(utxo.clone());
-            total_selected += utxo.amount;
-        }
-        
-        if total_selected < min_amount {
-            return Err(Error::InsufficientFunds);
-        }
-        
-        // Add inputs for signing
-        self.inputs = selected
-            .iter()
-            .map(|utxo| {
-                SigningInput::new(
-                    utxo.txid,
-                    utxo.vout,
-                    utxo.amount,
-                    utxo.script_pubkey.clone(),
-                )
+        // Create inputs
+        let inputs = utxos
+            .map(|utxo| TxIn {
+                previous_output: OutPoint::new(utxo.txid, utxo.vout),
+                script_sig: ScriptBuf::new(),
+                sequence,
+                witness: Witness::new(),
             })
             .collect();
-        
-        Ok(selected)
+
+        // Create transaction
+        Transaction {
+            version: transaction::Version(self.version),
+            lock_time: absolute::LockTime::from_consensus(self.lock_time.unwrap_or(0)),
+            input: inputs,
+            output: outputs,
+        }
     }
+}
 
-    /// Select UTXOs using the branch and bound algorithm (for exact matches)
-    fn select_utxos_branch_and_bound(&self) -> Result<Vec<Utxo>> {
-        // Implementation of the branch and bound algorithm for coin selection
-        // This is a simplified version - a full implementation would be more complex
-        
-        let target = self
+// Size of the input spending `script_pubkey` once signed
+fn input_prediction(script_pubkey: &ScriptBuf) -> Result<InputWeightPrediction> {
+    if script_pubkey.is_p2wpkh() {
+        Ok(InputWeightPrediction::P2WPKH_MAX)
+    } else if script_pubkey.is_p2pkh() {
+        Ok(InputWeightPrediction::P2PKH_COMPRESSED_MAX)
+    } else if script_pubkey.is_p2sh() {
+        // Assumed to wrap P2WPKH: a 22-byte redeem script pushed in the scriptSig
+        Ok(InputWeightPrediction::new(23, [72, 33]))
+    } else if script_pubkey.is_p2tr() {
+        Ok(InputWeightPrediction::P2TR_KEY_DEFAULT_SIGHASH)
+    } else {
+        Err(Error::InvalidParameter(format!(
+            "Cannot estimate the size of an input spending {}",
+            crate::utils::script_type(script_pubkey)
+        )))
+    }
+}
+
+// Coin selection over the candidates of one build
+struct Selection<'a> {
+    builder: &'a TransactionBuilder,
+    outputs: &'a [TxOut],
+    candidates: &'a [(Utxo, InputWeightPrediction)],
+    target: Amount,
+}
+
+impl Selection<'_> {
+    /// Fee for spending `inputs` to the outputs, with or without a change output
+    fn fee(&self, inputs: &[InputWeightPrediction], with_change: bool) -> Amount {
+        let change_len = self.builder.change_address.as_ref().map_or(0, |a| a.script_pubkey().len());
+        let output_lens = self
             .outputs
             .iter()
-            .filter(|o| !o.is_change)
-            .map(|o| o.amount)
-            .sum::<Amount>();
-        
-        // Sort UTXOs by descending amount for better performance
-        let mut utxos = self.utxos.clone();
-        utxos.sort_by_key(|u| std::cmp::Reverse(u.amount));
-        
-        let mut best_selection = Vec::new();
-        let mut best_amount = Amount::from_sat(0);
-        
-        // Try to find an exact match
-        if let Some(selection) = self.find_exact_match(&utxos, target) {
-            return Ok(selection);
-        }
-        
-        // If no exact match, fall back to greedy selection
-        self.select_utxos_largest_first()
+            .map(|o| o.script_pubkey.len())
+            .chain(with_change.then_some(change_len));
+        let vsize = predict_weight(inputs.iter().copied(), output_lens).to_vbytes_ceil();
+        Amount::from_sat((vsize as f64 * self.builder.config.fee_rate as f64).ceil() as u64)
     }
-    
-    /// Helper function to find an exact match for the target amount
-    fn find_exact_match(&self, utxos: &[Utxo], target: Amount) -> Option<Vec<Utxo>> {
-        // This is a simplified version - a full implementation would use dynamic programming
-        // or a more sophisticated algorithm for large sets of UTXOs
-        
-        for i in 0..utxos.len() {
-            let mut sum = Amount::from_sat(0);
-            let mut selection = Vec::new();
-            
-            for utxo in &utxos[i..] {
-                if sum + utxo.amount <= target {
-                    sum += utxo.amount;
-                    selection.push(utxo.clone());
-                    
-                    if sum == target {
-                        return Some(selection);
-                    }
+
+    /// Indices of the chosen candidates, in input order
+    fn select(&self) -> Result<Vec<usize>> {
+        let mut order: Vec<usize> = (0..self.candidates.len()).collect();
+        match self.builder.config.coin_selection {
+            CoinSelectionStrategy::SmallestFirst => order.sort_by_key(|&i| self.candidates[i].0.amount),
+            CoinSelectionStrategy::LargestFirst => order.sort_by_key(|&i| std::cmp::Reverse(self.candidates[i].0.amount)),
+            CoinSelectionStrategy::Random => order.shuffle(&mut bitcoin::secp256k1::rand::thread_rng()),
+            CoinSelectionStrategy::BranchAndBound => {
+                if let Some(selection) = self.branch_and_bound() {
+                    return Ok(selection);
                 }
+                // No changeless combination, fall back to largest first
+                order.sort_by_key(|&i| std::cmp::Reverse(self.candidates[i].0.amount));
             }
         }
-        
-        None
+        self.greedy(&order)
     }
 
-    /// Create an unsigned transaction with the given UTXOs and outputs
-    fn create_unsigned_tx(
-        &self,
-        utxos: &[Utxo],
-        outputs: Option<&[OutputTarget]>,
-    ) -> Result<Transaction> {
-        let outputs = outputs.unwrap_or(&self.outputs);
-        
-        // Create inputs
-        let inputs: Vec<TxIn> = utxos
-            .iter()
-            .map(|utxo| {
-                let sequence = if self.config.rbf {
-                    self.config.rbf_sequence
-                } else {
-                    Sequence::MAX
-                };
-                
-                TxIn {
-                    previous_output: OutPoint::new(utxo.txid, utxo.vout),
-                    script_sig: Script::new(),
-                    sequence: Sequence(sequence),
-                    witness: Witness::new(),
-                }
-            })
-            .collect();
-        
-        // Create outputs
-        let outputs: Vec<TxOut> = outputs
+    /// Take candidates in `order` until they pay for the outputs and the fee
+    fn greedy(&self, order: &[usize]) -> Result<Vec<usize>> {
+        let mut selected = Vec::new();
+        let mut predictions = Vec::new();
+        let mut total = Amount::ZERO;
+        for &i in order {
+            selected.push(i);
+            predictions.push(self.candidates[i].1);
+            total += self.candidates[i].0.amount;
+            if total >= self.target + self.fee(&predictions, false) {
+                return Ok(selected);
+            }
+        }
+        Err(Error::InsufficientFunds { needed: self.target + self.fee(&predictions, false), available: total })
+    }
+
+    /// Search for inputs that pay the outputs and fee with too little left for change
+    fn branch_and_bound(&self) -> Option<Vec<usize>> {
+        // Value of each candidate after paying for its own input
+        let rate = self.builder.config.fee_rate as f64;
+        let mut pool: Vec<(usize, i64)> = self
+            .candidates
             .iter()
-            .map(|output| TxOut {
-                value: output.amount.to_sat(),
-                script_pubkey: output.address.script_pubkey(),
+            .enumerate()
+            .map(|(i, (utxo, prediction))| {
+                let input_fee = (prediction.weight().to_wu() as f64 / 4.0 * rate).ceil() as i64;
+                (i, utxo.amount.to_sat() as i64 - input_fee)
             })
+            .filter(|&(_, value)| value > 0)
             .collect();
-        
-        // Create transaction
-        let tx = Transaction {
-            version: self.version,
-            lock_time: absolute::LockTime::ZERO,
-            input: inputs,
-            output: outputs,
-        };
-        
-        Ok(tx)
+        pool.sort_by_key(|&(_, value)| std::cmp::Reverse(value));
+
+        // Fee of the transaction without inputs, then how much excess a changeless result may burn
+        let low = (self.target + self.fee(&[], false)).to_sat() as i64;
+        let cost_of_change = self.fee(&[], true) - self.fee(&[], false) + self.builder.min_change();
+        let high = low + cost_of_change.to_sat() as i64;
+
+        let mut remaining: Vec<i64> = pool.iter().rev().scan(0, |sum, &(_, v)| { *sum += v; Some(*sum) }).collect();
+        remaining.reverse();
+
+        let mut chosen = Vec::new();
+        let mut tries = 0;
+        let found = self.search(&pool, &remaining, 0, 0, low, high, &mut chosen, &mut tries);
+        if !found {
+            return None;
+        }
+        // Effective values are an estimate; confirm with the exact fee
+        let mut selected: Vec<usize> = chosen.iter().map(|&k| pool[k].0).collect();
+        selected.sort_unstable();
+        let predictions: Vec<_> = selected.iter().map(|&i| self.candidates[i].1).collect();
+        let total: Amount = selected.iter().map(|&i| self.candidates[i].0.amount).sum();
+        let needed = self.target + self.fee(&predictions, false);
+        (total >= needed && total - needed < cost_of_change).then_some(selected)
     }
-    
-    /// Calculate the fee for a transaction
-    fn calculate_fee(&self, tx: &Transaction) -> Result<Amount> {
-        // Calculate the total input amount
-        let input_amount: Amount = self.inputs.iter().map(|i| i.amount).sum();
-        
-        // Calculate the total output amount
-        let output_amount: Amount = tx.output.iter().map(|o| Amount::from_sat(o.value)).sum();
-        
-        // The fee is the difference between inputs and outputs
-        Ok(input_amount - output_amount)
+
+    // Depth-first include/exclude search, largest values first
+    #[allow(clippy::too_many_arguments)]
+    fn search(
+        &self,
+        pool: &[(usize, i64)],
+        remaining: &[i64],
+        index: usize,
+        sum: i64,
+        low: i64,
+        high: i64,
+        chosen: &mut Vec<usize>,
+        tries: &mut usize,
+    ) -> bool {
+        *tries += 1;
+        if sum > high || *tries > BNB_MAX_TRIES {
+            return false;
+        }
+        if sum >= low {
+            return true;
+        }
+        if index == pool.len() || sum + remaining[index] < low {
+            return false;
+        }
+        chosen.push(index);
+        if self.search(pool, remaining, index + 1, sum + pool[index].1, low, high, chosen, tries) {
+            return true;
+        }
+        chosen.pop();
+        self.search(pool, remaining, index + 1, sum, low, high, chosen, tries)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bitcoin::secp256k1::Secp256k1;
-    use bitcoin::PrivateKey;
-    
+    use bitcoin::secp256k1::{Secp256k1, SecretKey};
+    use bitcoin::{CompressedPublicKey, Txid};
+
+    fn key() -> PrivateKey {
+        PrivateKey::new(SecretKey::from_slice(&[7; 32]).unwrap(), Network::Regtest)
+    }
+
+    fn address() -> Address {
+        let public_key = CompressedPublicKey::from_private_key(&Secp256k1::new(), &key()).unwrap();
+        Address::p2wpkh(&public_key, Network::Regtest)
+    }
+
+    fn outpoints(utxos: &[Utxo]) -> Vec<OutPoint> {
+        utxos.iter().map(Utxo::outpoint).collect()
+    }
+
+    fn utxo(vout: u32, sat: u64) -> Utxo {
+        let txid = Txid::from_str("f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16").unwrap();
+        Utxo::new(txid, vout, Amount::from_sat(sat), address().script_pubkey(), None)
+    }
+
+    fn builder(utxos: Vec<Utxo>, strategy: CoinSelectionStrategy) -> TransactionBuilder {
+        let config = TxBuilderConfig { network: BtcNetwork::Regtest, coin_selection: strategy, ..Default::default() };
+        let mut builder = TransactionBuilder::new(BtcNetwork::Regtest).with_config(config).with_utxos(utxos);
+        builder.add_output(address(), Amount::from_sat(50_000)).set_change_address(address());
+        builder
+    }
+
     #[test]
-    fn test_transaction_builder() {
-        // Create a test network
-        let network = BtcNetwork::Regtest;
-        
-        // Create a test key pair
-        let secp = Secp256k1::new();
-        let private_key = PrivateKey::new(
-            secp256k1::SecretKey::new(&mut rand::thread_rng()),
-            network.into(),
-        );
-        let public_key = private_key.public_key(&secp);
-        let address = Address::p2pkh(&public_key, network.into());
-        
-        // Create a test UTXO
-        let utxo = Utxo {
-            txid: Txid::from_hex(
-                "0000000000000000000000000000000000000000000000000000000000000000",
-            )
-            .unwrap(),
-            vout: 0,
-            amount: Amount::from_btc(1.0).unwrap(),
-            script_pubkey: address.script_pubkey(),
-            address: Some(address.clone()),
-            confirmations: Some(6),
-            block_height: Some(100),
-            spendable: true,
-        };
-        
-        // Create a transaction builder
-        let mut builder = TransactionBuilder::new(network)
-            .with_utxos(vec![utxo])
-            .add_output(address.clone(), Amount::from_btc(0.5).unwrap())
-            .set_change_address(address);
-            
-        // Build an unsigned transaction
-        let unsigned_tx = builder.build_unsigned();
-        assert!(unsigned_tx.is_ok());
-        
-        // Build and sign the transaction
-        let signer = |script: &Script, amount: u64, sighash: &[u8]| {
-            // In a real implementation, this would sign the transaction
-            Ok((vec![vec![0; 72]], script.clone()))
-        };
-        
-        let signed_tx = builder.build_signed(signer);
-        assert!(signed_tx.is_ok());
+    fn test_change_and_fee() {
+        let (tx, report) = builder(vec![utxo(0, 30_000), utxo(1, 100_000)], CoinSelectionStrategy::LargestFirst)
+            .build_with_report()
+            .unwrap();
+        assert_eq!(outpoints(&report.selected), outpoints(&[utxo(1, 100_000)]));
+        // One P2WPKH input and two P2WPKH outputs: 141 vB at 1 sat/vB
+        assert_eq!(report.vsize, 141);
+        assert_eq!(report.fee, Amount::from_sat(141));
+        assert_eq!(report.change, Some(Amount::from_sat(100_000 - 50_000 - 141)));
+        assert_eq!(tx.output.len(), 2);
+        assert_eq!(tx.output[1].value, report.change.unwrap());
+        assert_eq!(tx.version, transaction::Version::TWO);
+        assert_eq!(tx.input[0].sequence, Sequence::MAX);
+    }
+
+    #[test]
+    fn test_branch_and_bound_avoids_change() {
+        // One input and one output: 110 vB, so 50_110 pays exactly
+        let utxos = vec![utxo(0, 100_000), utxo(1, 50_110), utxo(2, 30_000)];
+        let (tx, report) = builder(utxos, CoinSelectionStrategy::BranchAndBound).build_with_report().unwrap();
+        assert_eq!(outpoints(&report.selected), outpoints(&[utxo(1, 50_110)]));
+        assert_eq!(report.change, None);
+        assert_eq!(report.fee, Amount::from_sat(110));
+        assert_eq!(tx.output.len(), 1);
+    }
+
+    #[test]
+    fn test_insufficient_funds() {
+        let err = builder(vec![utxo(0, 30_000), utxo(1, 20_000)], CoinSelectionStrategy::SmallestFirst)
+            .build_with_report()
+            .unwrap_err();
+        match err {
+            Error::InsufficientFunds { needed, available } => {
+                assert_eq!(available, Amount::from_sat(50_000));
+                assert!(needed > Amount::from_sat(50_000));
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_rbf_and_lock_time() {
+        let mut builder = builder(vec![utxo(0, 100_000)], CoinSelectionStrategy::LargestFirst);
+        builder.set_lock_time(840_000);
+        let tx = builder.build_unsigned().unwrap();
+        assert_eq!(tx.input[0].sequence, Sequence::ENABLE_LOCKTIME_NO_RBF);
+        assert_eq!(tx.lock_time, absolute::LockTime::from_height(840_000).unwrap());
+
+        let config = TxBuilderConfig { network: BtcNetwork::Regtest, rbf: true, ..Default::default() };
+        let tx = builder.with_config(config).build_unsigned().unwrap();
+        assert!(tx.is_explicitly_rbf());
+    }
+
+    #[test]
+    fn test_build_signed() {
+        let signed = builder(vec![utxo(0, 100_000)], CoinSelectionStrategy::LargestFirst)
+            .build_signed(|_| Ok(key()))
+            .unwrap();
+        assert!(signed.is_complete);
+        assert_eq!(signed.tx.input[0].witness.len(), 2);
+        // The prediction assumes the longest signature, so it is never below the real size
+        assert!(signed.vsize as u64 <= 141);
     }
 }
@@ -246,11 +246,14 @@ impl fmt::Display for CoinSelectionStrategy {
     }
 }
 
-/// Fee estimation
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+/// Fee estimation for a single confirmation target
+///
+/// Used both for configured fee rates and for rates derived from the Esplora
+/// `/fee-estimates` map (see `network::FeePriorities`).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub struct FeeEstimate {
     /// Fee rate in satoshis per virtual byte
-    pub sat_per_vbyte: f32,
+    pub sat_per_vbyte: f64,
     /// Target number of blocks for confirmation
     pub blocks: u32,
 }
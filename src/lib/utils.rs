@@ -0,0 +1,760 @@
+//! Small helpers shared by the tools
+
+use std::fmt;
+use std::str::FromStr;
+
+use bitcoin::consensus::Decodable;
+use bitcoin::hex::{DisplayHex, FromHex};
+use bitcoin::transaction::{predict_weight, InputWeightPrediction};
+use bitcoin::amount::Denomination;
+use bitcoin::{io, Address, Amount, Network, Script, ScriptBuf, Transaction};
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::types::BtcNetwork;
+
+const ALL_NETWORKS: [BtcNetwork; 4] = [
+    BtcNetwork::Bitcoin,
+    BtcNetwork::Testnet,
+    BtcNetwork::Signet,
+    BtcNetwork::Regtest,
+];
+
+/// List the networks an address is valid on, judging by its prefix
+///
+/// Mainnet addresses map to one network, but test networks share encodings:
+/// `tb1…` is valid on testnet and signet, and base58 `m…`/`n…`/`2…` addresses
+/// on testnet, signet and regtest.
+pub fn networks_for_address(address: &str) -> Result<Vec<BtcNetwork>> {
+    let parsed = Address::from_str(address.trim())
+        .map_err(|e| Error::InvalidAddress(format!("{:?}: {}", address, e)))?;
+    Ok(ALL_NETWORKS
+        .into_iter()
+        .filter(|network| parsed.is_valid_for_network((*network).into()))
+        .collect())
+}
+
+/// Get the single network an address belongs to
+///
+/// Fails with `Error::AmbiguousNetwork` when the encoding is shared by several
+/// networks, in which case the caller has to pick one explicitly.
+pub fn network_for_address(address: &str) -> Result<BtcNetwork> {
+    match networks_for_address(address)?.as_slice() {
+        [network] => Ok(*network),
+        candidates => Err(Error::AmbiguousNetwork {
+            address: address.trim().to_string(),
+            candidates: candidates.to_vec(),
+        }),
+    }
+}
+
+/// Parse an address and check that it is valid on `network`
+///
+/// Shared test encodings pass on every network using them, so a `tb1…` address
+/// is accepted for testnet and signet. A malformed address is reported with its
+/// `AddressProblem`, a valid one on the wrong network with the networks it is for.
+pub fn parse_address(address: &str, network: BtcNetwork) -> Result<Address> {
+    let address = address.trim();
+    let inspection =
+        inspect_address(address).map_err(|problem| Error::InvalidAddress(format!("{:?}: {}", address, problem)))?;
+    if !inspection.networks.contains(&network) {
+        let networks: Vec<String> = inspection.networks.iter().map(BtcNetwork::to_string).collect();
+        return Err(Error::InvalidAddress(format!(
+            "{} is a {} address, not {}",
+            address,
+            networks.join("/"),
+            network
+        )));
+    }
+    Address::from_str(address)
+        .and_then(|parsed| parsed.require_network(network.into()))
+        .map_err(|e| Error::InvalidAddress(format!("{:?}: {}", address, e)))
+}
+
+/// Units `parse_amount` accepts, in any case
+pub const AMOUNT_UNITS: [&str; 5] = ["BTC", "mBTC", "uBTC", "bits", "sat"];
+
+/// Parse an amount written with its unit, like `0.015 BTC` or `1_500_000 sat`
+///
+/// The decimal is converted exactly rather than through a float, and
+/// underscores may group digits. A number without a unit is taken as BTC when
+/// it has a decimal point, and refused otherwise, since `1500` could be meant
+/// as either BTC or satoshis.
+pub fn parse_amount(s: &str) -> Result<Amount> {
+    let invalid = |reason: String| Error::InvalidParameter(format!("Invalid amount {:?}: {}", s.trim(), reason));
+    let trimmed = s.trim();
+    let (number, unit) = trimmed.split_at(trimmed.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(trimmed.len()));
+    let number: String = number.trim().chars().filter(|c| *c != '_').collect();
+    if number.is_empty() {
+        return Err(invalid("no number".to_string()));
+    }
+    let denomination = match unit.trim().to_ascii_lowercase().as_str() {
+        "" if number.contains('.') => Denomination::Bitcoin,
+        "" => return Err(invalid("give a unit, such as BTC or sat".to_string())),
+        "btc" => Denomination::Bitcoin,
+        "mbtc" => Denomination::MilliBitcoin,
+        "ubtc" => Denomination::MicroBitcoin,
+        "bit" | "bits" => Denomination::Bit,
+        "sat" | "sats" | "satoshi" | "satoshis" => Denomination::Satoshi,
+        other => return Err(invalid(format!("unknown unit {:?}; use {}", other, AMOUNT_UNITS.join(", ")))),
+    };
+    let amount = Amount::from_str_in(&number, denomination).map_err(|e| invalid(e.to_string()))?;
+    if amount > Amount::MAX_MONEY {
+        return Err(invalid("more than 21M BTC".to_string()));
+    }
+    Ok(amount)
+}
+
+/// Why a string is not a Bitcoin address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressProblem {
+    /// Nothing but whitespace
+    Empty,
+    /// A bech32 address mixing upper and lower case
+    MixedCase,
+    /// A witness version 1+ address with a bech32 checksum; BIP 350 requires bech32m
+    Bech32mRequired,
+    /// A witness version 0 address with a bech32m checksum; BIP 173 requires bech32
+    Bech32Required,
+    /// The base58 or bech32 checksum does not match, usually a typo
+    InvalidChecksum,
+    /// A well-formed bech32 string holding an invalid witness program
+    InvalidWitnessProgram,
+    /// Neither a base58 nor a bech32 address
+    InvalidEncoding,
+}
+
+impl fmt::Display for AddressProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AddressProblem::Empty => "The address is empty",
+            AddressProblem::MixedCase => "Bech32 addresses must be all lowercase or all uppercase",
+            AddressProblem::Bech32mRequired => "Witness version 1 and later addresses must use the bech32m checksum",
+            AddressProblem::Bech32Required => "Witness version 0 addresses must use the bech32 checksum, not bech32m",
+            AddressProblem::InvalidChecksum => "The checksum does not match; check for typos",
+            AddressProblem::InvalidWitnessProgram => "The witness version or program length is invalid",
+            AddressProblem::InvalidEncoding => "Not a base58 or bech32 Bitcoin address",
+        })
+    }
+}
+
+/// What a valid address is
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressInspection {
+    /// Networks the address is valid on
+    pub networks: Vec<BtcNetwork>,
+    /// `p2pkh`, `p2sh`, `p2wpkh`, `p2wsh`, `p2tr`, or `witness_v<n>` for future versions
+    pub address_type: String,
+    /// Locking script paying the address
+    pub script_pubkey: ScriptBuf,
+    /// Witness version of segwit addresses
+    pub witness_version: Option<u8>,
+}
+
+/// Inspect an address on any network, or tell why it is not one
+///
+/// Unlike parsing, this separates the common mistakes: mixed case in bech32,
+/// a bech32 checksum on a taproot address (or bech32m on v0), and typos that
+/// break the checksum.
+pub fn inspect_address(address: &str) -> std::result::Result<AddressInspection, AddressProblem> {
+    let address = address.trim();
+    if address.is_empty() {
+        return Err(AddressProblem::Empty);
+    }
+    let parsed = match Address::from_str(address) {
+        Ok(parsed) => parsed,
+        Err(_) => return Err(address_problem(address)),
+    };
+    let networks = ALL_NETWORKS
+        .into_iter()
+        .filter(|network| parsed.is_valid_for_network((*network).into()))
+        .collect();
+    let script_pubkey = parsed.assume_checked_ref().script_pubkey();
+    let witness_version = script_pubkey.witness_version().map(|version| version.to_num());
+    let address_type = match (parsed.assume_checked_ref().address_type(), witness_version) {
+        (Some(address_type), _) => address_type.to_string(),
+        (None, Some(version)) => format!("witness_v{}", version),
+        (None, None) => "unknown".to_string(),
+    };
+    Ok(AddressInspection { networks, address_type, script_pubkey, witness_version })
+}
+
+// Classify a string rust-bitcoin rejected as an address
+fn address_problem(address: &str) -> AddressProblem {
+    use bitcoin::bech32::primitives::decode::CheckedHrpstring;
+    use bitcoin::bech32::{Bech32, Bech32m};
+
+    const BECH32_CHARSET: &str = "qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+    let lower = address.to_ascii_lowercase();
+    if ["bc1", "tb1", "bcrt1"].iter().any(|hrp| lower.starts_with(hrp)) {
+        if address.chars().any(|c| c.is_ascii_lowercase()) && address.chars().any(|c| c.is_ascii_uppercase()) {
+            return AddressProblem::MixedCase;
+        }
+        // The witness version is the first data character, right after the separator
+        let version = lower
+            .rfind('1')
+            .and_then(|separator| lower[separator + 1..].chars().next())
+            .and_then(|c| BECH32_CHARSET.find(c));
+        let bech32 = CheckedHrpstring::new::<Bech32>(&lower).is_ok();
+        let bech32m = CheckedHrpstring::new::<Bech32m>(&lower).is_ok();
+        return match (bech32, bech32m, version) {
+            (true, _, Some(version)) if version > 0 => AddressProblem::Bech32mRequired,
+            (_, true, Some(0)) => AddressProblem::Bech32Required,
+            (false, false, _) => AddressProblem::InvalidChecksum,
+            _ => AddressProblem::InvalidWitnessProgram,
+        };
+    }
+    match (bitcoin::base58::decode(address), bitcoin::base58::decode_check(address)) {
+        (Ok(_), Err(_)) => AddressProblem::InvalidChecksum,
+        _ => AddressProblem::InvalidEncoding,
+    }
+}
+
+/// Input of a decoded transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecodedInput {
+    /// The transaction ID of the spent output
+    pub txid: String,
+    /// The index of the spent output
+    pub vout: u32,
+    /// The sequence number
+    pub sequence: u32,
+    /// Whether the sequence signals replaceability (BIP125)
+    pub rbf: bool,
+    /// The unlocking script as hex
+    pub script_sig: String,
+    /// Witness items as hex
+    pub witness: Vec<String>,
+}
+
+/// Output of a decoded transaction
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecodedOutput {
+    /// The amount in satoshis
+    pub value: u64,
+    /// The locking script as hex
+    pub script_pubkey: String,
+    /// The script template (`p2pkh`, `p2wpkh`, `op_return`, ...)
+    pub script_type: String,
+    /// The address paid, when the script has one
+    pub address: Option<String>,
+}
+
+/// Structured view of a raw transaction, signed or not
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DecodedTransaction {
+    /// The transaction ID
+    pub txid: String,
+    /// The transaction version
+    pub version: i32,
+    /// The lock time
+    pub locktime: u32,
+    /// Serialized size in bytes
+    pub size: usize,
+    /// Virtual size in vbytes
+    pub vsize: usize,
+    /// Weight in weight units
+    pub weight: u64,
+    /// Whether any input signals replaceability
+    pub rbf: bool,
+    /// The inputs
+    pub inputs: Vec<DecodedInput>,
+    /// The outputs
+    pub outputs: Vec<DecodedOutput>,
+}
+
+/// Parse a raw transaction from hex, reporting the byte offset of bad data
+pub fn parse_transaction_hex(hex: &str) -> Result<Transaction> {
+    let hex = hex.trim();
+    if let Some(pos) = hex.find(|c: char| !c.is_ascii_hexdigit()) {
+        return Err(Error::TransactionDecode {
+            offset: pos / 2,
+            message: "invalid hex character".to_string(),
+        });
+    }
+    let bytes = Vec::<u8>::from_hex(hex).map_err(|_| Error::TransactionDecode {
+        offset: hex.len() / 2,
+        message: "odd number of hex digits".to_string(),
+    })?;
+
+    let mut cursor = io::Cursor::new(&bytes);
+    let tx = Transaction::consensus_decode(&mut cursor).map_err(|e| Error::TransactionDecode {
+        offset: cursor.position() as usize,
+        message: e.to_string(),
+    })?;
+    if (cursor.position() as usize) < bytes.len() {
+        return Err(Error::TransactionDecode {
+            offset: cursor.position() as usize,
+            message: "trailing data after the transaction".to_string(),
+        });
+    }
+    Ok(tx)
+}
+
+/// Decode a raw transaction, deriving output addresses for `network`
+pub fn decode_transaction(hex: &str, network: BtcNetwork) -> Result<DecodedTransaction> {
+    let tx = parse_transaction_hex(hex)?;
+    Ok(DecodedTransaction {
+        txid: tx.compute_txid().to_string(),
+        version: tx.version.0,
+        locktime: tx.lock_time.to_consensus_u32(),
+        size: tx.total_size(),
+        vsize: tx.vsize(),
+        weight: tx.weight().to_wu(),
+        rbf: tx.is_explicitly_rbf(),
+        inputs: tx
+            .input
+            .iter()
+            .map(|input| DecodedInput {
+                txid: input.previous_output.txid.to_string(),
+                vout: input.previous_output.vout,
+                sequence: input.sequence.0,
+                rbf: input.sequence.is_rbf(),
+                script_sig: input.script_sig.to_hex_string(),
+                witness: input.witness.iter().map(|item| item.to_lower_hex_string()).collect(),
+            })
+            .collect(),
+        outputs: tx
+            .output
+            .iter()
+            .map(|output| DecodedOutput {
+                value: output.value.to_sat(),
+                script_pubkey: output.script_pubkey.to_hex_string(),
+                script_type: script_type(&output.script_pubkey).to_string(),
+                address: Address::from_script(&output.script_pubkey, Network::from(network))
+                    .ok()
+                    .map(|address| address.to_string()),
+            })
+            .collect(),
+    })
+}
+
+/// Name the standard template a locking script follows
+pub fn script_type(script: &Script) -> &'static str {
+    if script.is_p2pkh() {
+        "p2pkh"
+    } else if script.is_p2sh() {
+        "p2sh"
+    } else if script.is_p2wpkh() {
+        "p2wpkh"
+    } else if script.is_p2wsh() {
+        "p2wsh"
+    } else if script.is_p2tr() {
+        "p2tr"
+    } else if script.is_op_return() {
+        "op_return"
+    } else if script.is_p2pk() {
+        "p2pk"
+    } else if script.is_multisig() {
+        "multisig"
+    } else {
+        "nonstandard"
+    }
+}
+
+/// Input types `InputType` parses, as written
+pub const INPUT_TYPES: [&str; 6] =
+    ["p2pkh", "p2sh_p2wpkh", "p2wpkh", "p2tr", "p2sh_multisig:<m>of<n>", "p2wsh_multisig:<m>of<n>"];
+
+/// Output types `OutputType` parses
+pub const OUTPUT_TYPES: [&str; 5] = ["p2pkh", "p2sh", "p2wpkh", "p2wsh", "p2tr"];
+
+/// What an input spends, for size estimates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InputType {
+    /// Pay-to-pubkey-hash with a compressed key
+    P2pkh,
+    /// P2WPKH nested in P2SH
+    P2shP2wpkh,
+    /// Native segwit v0 pubkey hash
+    P2wpkh,
+    /// Taproot key path with the default sighash
+    P2tr,
+    /// Bare `m`-of-`n` CHECKMULTISIG in P2SH
+    P2shMultisig { required: u8, keys: u8 },
+    /// Bare `m`-of-`n` CHECKMULTISIG in P2WSH
+    P2wshMultisig { required: u8, keys: u8 },
+}
+
+impl InputType {
+    /// Size of the signed input, with every signature at its largest
+    pub fn weight_prediction(self) -> InputWeightPrediction {
+        // OP_m <n pubkeys> OP_n OP_CHECKMULTISIG
+        let multisig_script_len = |keys: u8| 3 + 34 * usize::from(keys);
+        match self {
+            InputType::P2pkh => InputWeightPrediction::P2PKH_COMPRESSED_MAX,
+            InputType::P2shP2wpkh => InputWeightPrediction::new(23, [72, 33]),
+            InputType::P2wpkh => InputWeightPrediction::P2WPKH_MAX,
+            InputType::P2tr => InputWeightPrediction::P2TR_KEY_DEFAULT_SIGHASH,
+            InputType::P2shMultisig { required, keys } => {
+                // OP_0, the signatures, then the redeem script pushed with OP_PUSHDATA1
+                let script_len = multisig_script_len(keys);
+                let push_len = if script_len <= 75 { 1 } else { 2 };
+                InputWeightPrediction::new(1 + 73 * usize::from(required) + push_len + script_len, [0usize; 0])
+            }
+            InputType::P2wshMultisig { required, keys } => {
+                // An empty element for the CHECKMULTISIG bug, the signatures, then the script
+                let mut elements = vec![0];
+                elements.extend(std::iter::repeat_n(72, usize::from(required)));
+                elements.push(multisig_script_len(keys));
+                InputWeightPrediction::new(0, elements)
+            }
+        }
+    }
+
+    /// Size of the signed input with low-R signatures, 71 bytes each with the
+    /// sighash byte, as signers that grind their nonces produce
+    pub fn low_r_weight_prediction(self) -> InputWeightPrediction {
+        let multisig_script_len = |keys: u8| 3 + 34 * usize::from(keys);
+        match self {
+            InputType::P2pkh => InputWeightPrediction::ground_p2pkh_compressed(1),
+            InputType::P2shP2wpkh => InputWeightPrediction::new(23, [71, 33]),
+            InputType::P2wpkh => InputWeightPrediction::ground_p2wpkh(1),
+            InputType::P2tr => InputWeightPrediction::P2TR_KEY_DEFAULT_SIGHASH,
+            InputType::P2shMultisig { required, keys } => {
+                let script_len = multisig_script_len(keys);
+                let push_len = if script_len <= 75 { 1 } else { 2 };
+                InputWeightPrediction::new(1 + 72 * usize::from(required) + push_len + script_len, [0usize; 0])
+            }
+            InputType::P2wshMultisig { required, keys } => {
+                let mut elements = vec![0];
+                elements.extend(std::iter::repeat_n(71, usize::from(required)));
+                elements.push(multisig_script_len(keys));
+                InputWeightPrediction::new(0, elements)
+            }
+        }
+    }
+}
+
+impl FromStr for InputType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let unknown = || {
+            Error::InvalidParameter(format!("Unknown input type {:?}; supported: {}", s, INPUT_TYPES.join(", ")))
+        };
+        let normalized = s.trim().to_ascii_lowercase();
+        match normalized.split_once(':') {
+            None => match normalized.as_str() {
+                "p2pkh" => Ok(InputType::P2pkh),
+                "p2sh_p2wpkh" => Ok(InputType::P2shP2wpkh),
+                "p2wpkh" => Ok(InputType::P2wpkh),
+                "p2tr" => Ok(InputType::P2tr),
+                _ => Err(unknown()),
+            },
+            Some((kind, threshold)) => {
+                // P2SH redeem scripts are capped at 520 bytes, CHECKMULTISIG at 20 keys
+                let max_keys = match kind {
+                    "p2sh_multisig" => 15,
+                    "p2wsh_multisig" => 20,
+                    _ => return Err(unknown()),
+                };
+                let (required, keys) = threshold
+                    .split_once("of")
+                    .and_then(|(m, n)| Some((m.parse::<u8>().ok()?, n.parse::<u8>().ok()?)))
+                    .ok_or_else(unknown)?;
+                if required == 0 || required > keys || keys > max_keys {
+                    return Err(Error::InvalidParameter(format!(
+                        "Invalid multisig {}of{}: need 1 <= m <= n <= {}",
+                        required, keys, max_keys
+                    )));
+                }
+                Ok(match kind {
+                    "p2sh_multisig" => InputType::P2shMultisig { required, keys },
+                    _ => InputType::P2wshMultisig { required, keys },
+                })
+            }
+        }
+    }
+}
+
+impl fmt::Display for InputType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputType::P2pkh => f.write_str("p2pkh"),
+            InputType::P2shP2wpkh => f.write_str("p2sh_p2wpkh"),
+            InputType::P2wpkh => f.write_str("p2wpkh"),
+            InputType::P2tr => f.write_str("p2tr"),
+            InputType::P2shMultisig { required, keys } => write!(f, "p2sh_multisig:{}of{}", required, keys),
+            InputType::P2wshMultisig { required, keys } => write!(f, "p2wsh_multisig:{}of{}", required, keys),
+        }
+    }
+}
+
+/// What an output pays to, for size estimates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputType {
+    P2pkh,
+    P2sh,
+    P2wpkh,
+    P2wsh,
+    P2tr,
+}
+
+impl OutputType {
+    /// Length of the locking script
+    pub fn script_pubkey_len(self) -> usize {
+        match self {
+            OutputType::P2pkh => 25,
+            OutputType::P2sh => 23,
+            OutputType::P2wpkh => 22,
+            OutputType::P2wsh | OutputType::P2tr => 34,
+        }
+    }
+}
+
+impl FromStr for OutputType {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "p2pkh" => Ok(OutputType::P2pkh),
+            "p2sh" => Ok(OutputType::P2sh),
+            "p2wpkh" => Ok(OutputType::P2wpkh),
+            "p2wsh" => Ok(OutputType::P2wsh),
+            "p2tr" => Ok(OutputType::P2tr),
+            _ => Err(Error::InvalidParameter(format!(
+                "Unknown output type {:?}; supported: {}",
+                s,
+                OUTPUT_TYPES.join(", ")
+            ))),
+        }
+    }
+}
+
+impl fmt::Display for OutputType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            OutputType::P2pkh => "p2pkh",
+            OutputType::P2sh => "p2sh",
+            OutputType::P2wpkh => "p2wpkh",
+            OutputType::P2wsh => "p2wsh",
+            OutputType::P2tr => "p2tr",
+        })
+    }
+}
+
+/// Predicted size of a signed transaction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SizeEstimate {
+    /// Virtual size in vbytes, rounded up
+    pub vsize: u64,
+    /// Weight in weight units
+    pub weight: u64,
+}
+
+/// Predict the size of a transaction once signed, from its input and output types
+///
+/// ECDSA signatures are counted at their largest DER length, so the estimate
+/// is an upper bound, at most a vbyte or so over per signature.
+pub fn estimate_vsize(inputs: &[InputType], outputs: &[OutputType]) -> SizeEstimate {
+    let weight = predict_weight(
+        inputs.iter().map(|input| input.weight_prediction()),
+        outputs.iter().map(|output| output.script_pubkey_len()),
+    );
+    SizeEstimate { vsize: weight.to_vbytes_ceil(), weight: weight.to_wu() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inspect_address() {
+        let cases = [
+            ("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2", "p2pkh", None),
+            ("3J98t1WpEZ73CNmQviecrnyiWrnqRhWNLy", "p2sh", None),
+            ("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq", "p2wpkh", Some(0)),
+            ("bc1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3qccfmv3", "p2wsh", Some(0)),
+            ("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqzk5jj0", "p2tr", Some(1)),
+            ("BC1SW50QGDZ25J", "witness_v16", Some(16)),
+        ];
+        for (address, address_type, witness_version) in cases {
+            let inspection = inspect_address(address).unwrap();
+            assert_eq!(inspection.address_type, address_type, "{}", address);
+            assert_eq!(inspection.witness_version, witness_version, "{}", address);
+            assert_eq!(inspection.networks, [BtcNetwork::Bitcoin], "{}", address);
+        }
+        let p2wpkh = inspect_address("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq").unwrap();
+        assert_eq!(p2wpkh.script_pubkey.to_hex_string(), "0014e8df018c7e326cc253faac7e46cdc51e68542c42");
+        assert_eq!(
+            inspect_address("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap().networks,
+            [BtcNetwork::Testnet, BtcNetwork::Signet]
+        );
+    }
+
+    #[test]
+    fn test_address_problems() {
+        let cases = [
+            ("  ", AddressProblem::Empty),
+            ("tb1qrp33g0q5c5txsp9arysrx4k6zdkfs4nce4xj0gdcccefvpysxf3q0sL5k7", AddressProblem::MixedCase),
+            // BIP 350 test vectors: the checksum of the other variant
+            ("bc1p0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vqh2y7hd", AddressProblem::Bech32mRequired),
+            ("BC1S0XLXVLHEMJA6C4DQV22UAPCTQUPFHLXM9H8Z3K2E72Q4K9HCZ7VQ54WELL", AddressProblem::Bech32mRequired),
+            ("tb1q0xlxvlhemja6c4dqv22uapctqupfhlxm9h8z3k2e72q4k9hcz7vq24jc47", AddressProblem::Bech32Required),
+            ("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdr", AddressProblem::InvalidChecksum),
+            ("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN3", AddressProblem::InvalidChecksum),
+            ("bc1pw5dgrnzv", AddressProblem::InvalidWitnessProgram),
+            ("not-an-address", AddressProblem::InvalidEncoding),
+        ];
+        for (address, problem) in cases {
+            assert_eq!(inspect_address(address), Err(problem), "{}", address);
+        }
+    }
+
+    #[test]
+    fn test_networks_for_address() {
+        assert_eq!(
+            networks_for_address("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq").unwrap(),
+            vec![BtcNetwork::Bitcoin]
+        );
+        assert_eq!(
+            networks_for_address("1BvBMSEYstWetqTFn5Au4m4GFg7xJaNVN2").unwrap(),
+            vec![BtcNetwork::Bitcoin]
+        );
+        assert_eq!(
+            networks_for_address("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx").unwrap(),
+            vec![BtcNetwork::Testnet, BtcNetwork::Signet]
+        );
+        assert_eq!(
+            networks_for_address("bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080").unwrap(),
+            vec![BtcNetwork::Regtest]
+        );
+        assert_eq!(
+            networks_for_address("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn").unwrap(),
+            vec![BtcNetwork::Testnet, BtcNetwork::Signet, BtcNetwork::Regtest]
+        );
+        assert!(matches!(networks_for_address("not-an-address"), Err(Error::InvalidAddress(_))));
+
+        assert!(matches!(
+            network_for_address("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx"),
+            Err(Error::AmbiguousNetwork { .. })
+        ));
+    }
+
+    #[test]
+    fn test_parse_address() {
+        let regtest = parse_address(" bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080 ", BtcNetwork::Regtest).unwrap();
+        assert_eq!(regtest.to_string(), "bcrt1qw508d6qejxtdg4y5r3zarvary0c5xw7kygt080");
+        for network in [BtcNetwork::Testnet, BtcNetwork::Signet] {
+            assert!(parse_address("tb1qw508d6qejxtdg4y5r3zarvary0c5xw7kxpjzsx", network).is_ok());
+        }
+        assert!(parse_address("mipcBbFg9gMiCh81Kj8tqqdgoZub1ZJRfn", BtcNetwork::Regtest).is_ok());
+
+        match parse_address("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq", BtcNetwork::Regtest) {
+            Err(Error::InvalidAddress(message)) => {
+                assert_eq!(message, "bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdq is a bitcoin address, not regtest")
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match parse_address("bc1qar0srrr7xfkvy5l643lydnw9re59gtzzwf5mdr", BtcNetwork::Bitcoin) {
+            Err(Error::InvalidAddress(message)) => assert!(message.contains("checksum"), "{}", message),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_amount() {
+        let cases = [
+            ("0.015 BTC", 1_500_000),
+            ("1_500_000 sat", 1_500_000),
+            ("1500000sats", 1_500_000),
+            ("0.1 btc", 10_000_000),
+            // 0.29 is 0.28999999999999998 as a float
+            ("0.29", 29_000_000),
+            ("21000000 BTC", 2_100_000_000_000_000),
+            ("2.5 mBTC", 250_000),
+            ("10 uBTC", 1_000),
+            ("1_000 bits", 100_000),
+        ];
+        for (text, sats) in cases {
+            assert_eq!(parse_amount(text).unwrap(), Amount::from_sat(sats), "{}", text);
+        }
+        for text in ["1500", "1.5 sat", "0.000000001 BTC", "21000001 BTC", "-1 sat", "5 dollars", "BTC", ""] {
+            assert!(matches!(parse_amount(text), Err(Error::InvalidParameter(_))), "{}", text);
+        }
+        assert_eq!(
+            parse_amount("1500").unwrap_err().to_string(),
+            Error::InvalidParameter("Invalid amount \"1500\": give a unit, such as BTC or sat".to_string()).to_string()
+        );
+    }
+
+    // Block 170: the first bitcoin transfer, paying two P2PK outputs
+    const LEGACY_TX: &str = "0100000001c997a5e56e104102fa209c6a852dd90660a20b2d9c352423edce25857fcd3704000000004847304402204e45e16932b8af514961a1d3a1a25fdf3f4f7732e9d624c6c61548ab5fb8cd410220181522ec8eca07de4860a4acdd12909d831cc56cbbac4622082221a8768d1d0901ffffffff0200ca9a3b00000000434104ae1a62fe09c5f51b13905f07f06b99a2f7159b2225f374cd378d71302fa28414e7aab37397f554a7df5f142c21c1b7303b8a0626f1baded5c72a704f7e6cd84cac00286bee0000000043410411db93e1dcdb8a016b49840f8c53bc1eb68a382e97b1482ecad7b148a6909a5cb2e0eaddfb84ccf9744464f82e160bfa9b8b64f9d4c03f999b8643f656b412a3ac00000000";
+    // P2WPKH spend with placeholder signature and key bytes, signalling RBF
+    const SEGWIT_TX: &str = "02000000000101169e1e83e930853391bc6f35f605c6754cfead57cf8387639d3b4096c54f18f40100000000fdffffff0250c3000000000000160014333333333333333333333333333333333333333358980000000000001600144444444444444444444444444444444444444444024730441111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111111012102222222222222222222222222222222222222222222222222222222222222222240d10c00";
+
+    #[test]
+    fn test_decode_transaction() {
+        let legacy = decode_transaction(LEGACY_TX, BtcNetwork::Bitcoin).unwrap();
+        assert_eq!(legacy.txid, "f4184fc596403b9d638783cf57adfe4c75c605f6356fbc91338530e9831e9e16");
+        assert_eq!((legacy.size, legacy.vsize, legacy.weight), (275, 275, 1100));
+        assert!(!legacy.rbf);
+        assert!(legacy.inputs[0].witness.is_empty());
+        assert_eq!(legacy.outputs[1].value, 4_000_000_000);
+        assert_eq!(legacy.outputs[1].script_type, "p2pk");
+        assert_eq!(legacy.outputs[1].address, None);
+
+        let segwit = decode_transaction(SEGWIT_TX, BtcNetwork::Bitcoin).unwrap();
+        assert_eq!(segwit.txid, "849df045ec7a18a2888bc2f85679b262a4ff890e2c6c912f4c970384cebd6bbe");
+        assert_eq!((segwit.locktime, segwit.weight), (840_000, 561));
+        assert!(segwit.rbf && segwit.inputs[0].rbf);
+        assert_eq!(segwit.inputs[0].witness.len(), 2);
+        assert_eq!(segwit.outputs[0].script_type, "p2wpkh");
+        assert!(segwit.outputs[0].address.as_deref().unwrap().starts_with("bc1q"));
+    }
+
+    #[test]
+    fn test_estimate_vsize() {
+        use InputType::*;
+        use OutputType as Out;
+
+        // Pure segwit, mixed, and multisig, worked out by hand from BIP 141
+        let cases: [(&[InputType], &[OutputType], u64, u64); 4] = [
+            (&[P2wpkh], &[Out::P2wpkh, Out::P2wpkh], 562, 141),
+            (&[P2pkh, P2wpkh], &[Out::P2tr, Out::P2pkh], 1215, 304),
+            (&[P2wshMultisig { required: 2, keys: 3 }], &[Out::P2wpkh, Out::P2wsh], 756, 189),
+            (&[P2shMultisig { required: 2, keys: 3 }], &[Out::P2pkh], 1364, 341),
+        ];
+        for (inputs, outputs, weight, vsize) in cases {
+            assert_eq!(estimate_vsize(inputs, outputs), SizeEstimate { vsize, weight }, "{:?} -> {:?}", inputs, outputs);
+        }
+
+        // A real transaction of the first shape, with a 71-byte signature, is within the estimate
+        let real = decode_transaction(SEGWIT_TX, BtcNetwork::Bitcoin).unwrap();
+        assert_eq!((real.inputs.len(), real.outputs.len()), (1, 2));
+        assert!(real.weight <= 562 && real.vsize == 141);
+    }
+
+    #[test]
+    fn test_size_types_parse() {
+        for name in ["p2pkh", "p2sh_p2wpkh", "p2wpkh", "p2tr", "p2sh_multisig:2of3", "p2wsh_multisig:11of20"] {
+            assert_eq!(name.parse::<InputType>().unwrap().to_string(), name);
+        }
+        for name in OUTPUT_TYPES {
+            assert_eq!(name.parse::<OutputType>().unwrap().to_string(), name);
+        }
+        assert_eq!("P2WSH_MULTISIG:2of3".parse::<InputType>().unwrap(), InputType::P2wshMultisig { required: 2, keys: 3 });
+
+        let unknown = "p2wsh".parse::<InputType>().unwrap_err().to_string();
+        assert!(unknown.contains("p2wsh_multisig:<m>of<n>"), "{}", unknown);
+        assert!("p2wsh_multisig:3of2".parse::<InputType>().is_err());
+        assert!("p2sh_multisig:1of16".parse::<InputType>().is_err());
+        assert!("p2wsh_multisig:0of1".parse::<InputType>().is_err());
+        assert!("p2wsh_multisig:2-3".parse::<InputType>().is_err());
+        assert!("p2pk".parse::<OutputType>().is_err());
+    }
+
+    #[test]
+    fn test_parse_errors_carry_offset() {
+        let offset = |hex: &str| match parse_transaction_hex(hex) {
+            Err(Error::TransactionDecode { offset, .. }) => offset,
+            other => panic!("unexpected result: {:?}", other),
+        };
+        assert_eq!(offset("0100zz"), 2);
+        assert_eq!(offset("010"), 1);
+        assert_eq!(offset(&LEGACY_TX[..100]), 50);
+        assert_eq!(offset(&format!("{}00", LEGACY_TX)), 275);
+    }
+}
@@ -0,0 +1,511 @@
+//! mempool.space WebSocket subscriptions for new blocks, addresses and transactions
+//!
+//! Enabled with the `websocket` feature.
+
+use std::collections::BTreeSet;
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::sync::mpsc;
+use tokio_tungstenite::tungstenite::Message;
+use tokio_tungstenite::{connect_async, MaybeTlsStream, WebSocketStream};
+
+use crate::error::{Error, Result};
+use crate::network::{BlockstreamClient, TxStatus};
+
+/// WebSocket endpoint of the public mempool.space instance (mainnet)
+pub const MEMPOOL_SPACE_WS_URL: &str = "wss://mempool.space/api/v1/ws";
+
+type WsStream = WebSocketStream<MaybeTlsStream<tokio::net::TcpStream>>;
+
+/// Block summary pushed by mempool.space
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockInfo {
+    /// The block hash
+    pub id: String,
+    /// The block height
+    pub height: u32,
+    /// The block timestamp
+    pub timestamp: u64,
+    /// Number of transactions in the block
+    #[serde(default)]
+    pub tx_count: u32,
+}
+
+/// Event pushed by the server
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /// A new block was mined
+    NewBlock(BlockInfo),
+    /// A transaction touching a tracked address was seen or confirmed
+    AddressTx {
+        /// The tracked address
+        address: String,
+        /// The transaction ID
+        txid: String,
+        /// Whether the transaction is confirmed
+        confirmed: bool,
+    },
+    /// The tracked transaction confirmed
+    TxConfirmed {
+        /// The transaction ID
+        txid: String,
+        /// Height of the including block, when the server sent it
+        block_height: Option<u32>,
+    },
+    /// The connection dropped and was re-established; subscriptions were restored
+    Reconnected,
+}
+
+/// Reconnection policy after a dropped connection
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectConfig {
+    /// Consecutive failed reconnection attempts before giving up
+    pub max_retries: u32,
+    /// Delay before the first reconnection attempt, doubled after each failure
+    pub initial_backoff: Duration,
+    /// Upper bound on the delay between attempts
+    pub max_backoff: Duration,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        ReconnectConfig {
+            max_retries: 10,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl ReconnectConfig {
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+        self.initial_backoff.saturating_mul(factor).min(self.max_backoff)
+    }
+}
+
+#[derive(Debug)]
+enum Command {
+    SubscribeBlocks,
+    TrackAddress(String),
+    TrackTx(String),
+}
+
+/// Everything the client is subscribed to, replayed after a reconnection
+#[derive(Debug, Default)]
+struct Subscriptions {
+    blocks: bool,
+    addresses: BTreeSet<String>,
+    tx: Option<String>,
+}
+
+impl Subscriptions {
+    fn apply(&mut self, command: Command) -> String {
+        match command {
+            Command::SubscribeBlocks => {
+                self.blocks = true;
+                want_blocks_message()
+            }
+            Command::TrackAddress(address) => {
+                self.addresses.insert(address);
+                track_addresses_message(&self.addresses)
+            }
+            Command::TrackTx(txid) => {
+                let message = track_tx_message(&txid);
+                self.tx = Some(txid);
+                message
+            }
+        }
+    }
+
+    fn messages(&self) -> Vec<String> {
+        let mut messages = Vec::new();
+        if self.blocks {
+            messages.push(want_blocks_message());
+        }
+        if !self.addresses.is_empty() {
+            messages.push(track_addresses_message(&self.addresses));
+        }
+        if let Some(txid) = &self.tx {
+            messages.push(track_tx_message(txid));
+        }
+        messages
+    }
+}
+
+fn want_blocks_message() -> String {
+    json!({ "action": "want", "data": ["blocks"] }).to_string()
+}
+
+fn track_addresses_message(addresses: &BTreeSet<String>) -> String {
+    json!({ "track-addresses": addresses }).to_string()
+}
+
+fn track_tx_message(txid: &str) -> String {
+    json!({ "track-tx": txid }).to_string()
+}
+
+/// Client for the mempool.space WebSocket API
+///
+/// The connection runs in a background task that reconnects with backoff and
+/// restores every subscription. Events are read with [`next_event`](Self::next_event).
+#[derive(Debug)]
+pub struct MempoolWsClient {
+    commands: mpsc::UnboundedSender<Command>,
+    events: mpsc::UnboundedReceiver<Event>,
+}
+
+impl MempoolWsClient {
+    /// Connect with the default reconnection policy
+    pub async fn connect(url: &str) -> Result<Self> {
+        Self::connect_with(url, ReconnectConfig::default()).await
+    }
+
+    /// Connect with a custom reconnection policy
+    pub async fn connect_with(url: &str, reconnect: ReconnectConfig) -> Result<Self> {
+        let stream = open(url).await?;
+        let (commands_tx, commands_rx) = mpsc::unbounded_channel();
+        let (events_tx, events_rx) = mpsc::unbounded_channel();
+        tokio::spawn(run(url.to_string(), reconnect, stream, commands_rx, events_tx));
+        Ok(MempoolWsClient {
+            commands: commands_tx,
+            events: events_rx,
+        })
+    }
+
+    /// Receive new block events
+    pub fn subscribe_blocks(&self) -> Result<()> {
+        self.send(Command::SubscribeBlocks)
+    }
+
+    /// Receive events for transactions touching an address
+    pub fn track_address(&self, address: &str) -> Result<()> {
+        self.send(Command::TrackAddress(address.to_string()))
+    }
+
+    /// Receive an event when a transaction confirms
+    ///
+    /// The server tracks one transaction per connection, so this replaces any
+    /// previously tracked transaction.
+    pub fn track_tx(&self, txid: &str) -> Result<()> {
+        self.send(Command::TrackTx(txid.to_string()))
+    }
+
+    /// Wait for the next event
+    ///
+    /// Returns `None` once the connection is lost for good (reconnection
+    /// retries exhausted).
+    pub async fn next_event(&mut self) -> Option<Event> {
+        self.events.recv().await
+    }
+
+    fn send(&self, command: Command) -> Result<()> {
+        self.commands
+            .send(command)
+            .map_err(|_| Error::Custom("WebSocket connection closed".to_string()))
+    }
+}
+
+async fn open(url: &str) -> Result<WsStream> {
+    let (stream, _) = connect_async(url)
+        .await
+        .map_err(|e| Error::Custom(format!("WebSocket connection to {} failed: {}", url, e)))?;
+    Ok(stream)
+}
+
+/// Background task owning the connection
+async fn run(
+    url: String,
+    reconnect: ReconnectConfig,
+    stream: WsStream,
+    mut commands: mpsc::UnboundedReceiver<Command>,
+    events: mpsc::UnboundedSender<Event>,
+) {
+    let mut subscriptions = Subscriptions::default();
+    let mut stream = Some(stream);
+
+    loop {
+        if let Some(mut ws) = stream.take() {
+            if !serve(&mut ws, &mut subscriptions, &mut commands, &events).await {
+                return;
+            }
+            log::warn!("WebSocket connection to {} dropped", url);
+        }
+
+        let mut attempt = 0;
+        while stream.is_none() {
+            attempt += 1;
+            if attempt > reconnect.max_retries {
+                log::warn!("Giving up on {} after {} reconnection attempts", url, reconnect.max_retries);
+                return;
+            }
+            tokio::time::sleep(reconnect.backoff(attempt)).await;
+            match open(&url).await {
+                Ok(mut ws) => {
+                    let mut restored = true;
+                    for message in subscriptions.messages() {
+                        restored &= ws.send(Message::Text(message)).await.is_ok();
+                    }
+                    if restored {
+                        if events.send(Event::Reconnected).is_err() {
+                            return;
+                        }
+                        stream = Some(ws);
+                    }
+                }
+                Err(e) => log::debug!("Reconnection attempt {} failed: {}", attempt, e),
+            }
+        }
+    }
+}
+
+/// Pump commands and messages until the connection drops
+///
+/// Returns `false` when the client was dropped and the task should stop.
+async fn serve(
+    ws: &mut WsStream,
+    subscriptions: &mut Subscriptions,
+    commands: &mut mpsc::UnboundedReceiver<Command>,
+    events: &mpsc::UnboundedSender<Event>,
+) -> bool {
+    loop {
+        tokio::select! {
+            command = commands.recv() => {
+                let Some(command) = command else {
+                    let _ = ws.close(None).await;
+                    return false;
+                };
+                let message = subscriptions.apply(command);
+                if ws.send(Message::Text(message)).await.is_err() {
+                    return true;
+                }
+            }
+            message = ws.next() => match message {
+                Some(Ok(Message::Text(text))) => {
+                    for event in parse_message(&text) {
+                        if events.send(event).is_err() {
+                            return false;
+                        }
+                    }
+                }
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return true,
+                Some(Ok(_)) => {}
+            }
+        }
+    }
+}
+
+/// Turn one server message into events
+fn parse_message(text: &str) -> Vec<Event> {
+    let Ok(value) = serde_json::from_str::<Value>(text) else {
+        log::debug!("Ignoring non-JSON WebSocket message");
+        return Vec::new();
+    };
+    let mut events = Vec::new();
+
+    let block: Option<BlockInfo> = value
+        .get("block")
+        .and_then(|block| serde_json::from_value(block.clone()).ok());
+
+    if let Some(txid) = value.get("txConfirmed").and_then(Value::as_str) {
+        events.push(Event::TxConfirmed {
+            txid: txid.to_string(),
+            block_height: block.as_ref().map(|b| b.height),
+        });
+    }
+
+    if let Some(addresses) = value.get("multi-address-transactions").and_then(Value::as_object) {
+        for (address, activity) in addresses {
+            for (key, confirmed) in [("mempool", false), ("confirmed", true)] {
+                let txs = activity.get(key).and_then(Value::as_array);
+                for txid in txs.into_iter().flatten().filter_map(|tx| tx.get("txid")?.as_str()) {
+                    events.push(Event::AddressTx {
+                        address: address.clone(),
+                        txid: txid.to_string(),
+                        confirmed,
+                    });
+                }
+            }
+        }
+    }
+
+    if let Some(block) = block {
+        events.push(Event::NewBlock(block));
+    }
+    events
+}
+
+/// Wait for a transaction to confirm, pushed over WebSocket when available
+///
+/// Without a WebSocket client, or if its connection is lost for good, this
+/// falls back to polling `client` every `poll_interval`.
+pub async fn wait_for_confirmation(
+    client: &BlockstreamClient,
+    ws: Option<&mut MempoolWsClient>,
+    txid: &str,
+    poll_interval: Duration,
+    timeout: Duration,
+) -> Result<TxStatus> {
+    let started = tokio::time::Instant::now();
+
+    if let Some(ws) = ws {
+        let pushed = async {
+            ws.track_tx(txid)?;
+            // It may have confirmed before the subscription took effect
+            if let Ok(tx) = client.get_transaction(txid).await {
+                if tx.status.confirmed {
+                    return Ok(Some(tx.status));
+                }
+            }
+            while let Some(event) = ws.next_event().await {
+                if matches!(&event, Event::TxConfirmed { txid: confirmed, .. } if confirmed == txid) {
+                    return client.get_transaction(txid).await.map(|tx| Some(tx.status));
+                }
+            }
+            Ok(None)
+        };
+        match tokio::time::timeout(timeout, pushed).await {
+            Ok(Ok(Some(status))) => return Ok(status),
+            Ok(Ok(None)) | Ok(Err(_)) => log::warn!("WebSocket unavailable, falling back to polling"),
+            Err(_) => return Err(Error::Custom(format!("Timed out waiting for {} to confirm", txid))),
+        }
+    }
+
+    let remaining = timeout.saturating_sub(started.elapsed());
+    client.wait_for_confirmation(txid, poll_interval, remaining).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::accept_async;
+
+    const TXID: &str = "3832f861eb0fd967fd079da2ee90e415d295dbc81bfb895b73a220aa689c89eb";
+
+    fn block_message(height: u32) -> String {
+        json!({ "block": { "id": "00".repeat(32), "height": height, "timestamp": 1_700_000_000u64, "tx_count": 3 } })
+            .to_string()
+    }
+
+    async fn read_text(ws: &mut WebSocketStream<tokio::net::TcpStream>) -> String {
+        loop {
+            match ws.next().await.unwrap().unwrap() {
+                Message::Text(text) => return text,
+                _ => continue,
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_address_and_confirmation_messages() {
+        let message = json!({
+            "multi-address-transactions": {
+                "bc1qexample": {
+                    "mempool": [{ "txid": "aa" }],
+                    "confirmed": [{ "txid": "bb" }],
+                    "removed": [{ "txid": "cc" }]
+                }
+            }
+        });
+        assert_eq!(
+            parse_message(&message.to_string()),
+            vec![
+                Event::AddressTx { address: "bc1qexample".into(), txid: "aa".into(), confirmed: false },
+                Event::AddressTx { address: "bc1qexample".into(), txid: "bb".into(), confirmed: true },
+            ]
+        );
+
+        let confirmed = json!({
+            "txConfirmed": TXID,
+            "block": { "id": "00", "height": 878309, "timestamp": 1u64 }
+        });
+        let events = parse_message(&confirmed.to_string());
+        assert_eq!(
+            events[0],
+            Event::TxConfirmed { txid: TXID.into(), block_height: Some(878309) }
+        );
+        assert!(matches!(events[1], Event::NewBlock(_)));
+        assert!(parse_message("not json").is_empty());
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_max() {
+        let config = ReconnectConfig {
+            max_retries: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+        };
+        assert_eq!(config.backoff(1), Duration::from_millis(100));
+        assert_eq!(config.backoff(2), Duration::from_millis(200));
+        assert_eq!(config.backoff(3), Duration::from_millis(300));
+    }
+
+    #[tokio::test]
+    async fn test_resubscribes_after_dropped_connection() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            // First connection: one block, then drop
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws = accept_async(socket).await.unwrap();
+            assert!(read_text(&mut ws).await.contains("blocks"));
+            assert!(read_text(&mut ws).await.contains("track-tx"));
+            ws.send(Message::Text(block_message(100))).await.unwrap();
+            drop(ws);
+
+            // Second connection: subscriptions are replayed, then the tx confirms
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut ws = accept_async(socket).await.unwrap();
+            assert!(read_text(&mut ws).await.contains("blocks"));
+            assert!(read_text(&mut ws).await.contains(TXID));
+            let confirmed = json!({ "txConfirmed": TXID });
+            ws.send(Message::Text(confirmed.to_string())).await.unwrap();
+            let _ = ws.next().await;
+        });
+
+        let reconnect = ReconnectConfig {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(50),
+        };
+        let mut client = MempoolWsClient::connect_with(&url, reconnect).await.unwrap();
+        client.subscribe_blocks().unwrap();
+        client.track_tx(TXID).unwrap();
+
+        assert!(matches!(client.next_event().await, Some(Event::NewBlock(b)) if b.height == 100));
+        assert_eq!(client.next_event().await, Some(Event::Reconnected));
+        assert_eq!(
+            client.next_event().await,
+            Some(Event::TxConfirmed { txid: TXID.into(), block_height: None })
+        );
+        drop(client);
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_gives_up_after_max_retries() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            drop(accept_async(socket).await.unwrap());
+            // Listener dropped here, so every reconnection is refused
+        });
+
+        let reconnect = ReconnectConfig {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff: Duration::from_millis(10),
+        };
+        let mut client = MempoolWsClient::connect_with(&url, reconnect).await.unwrap();
+        server.await.unwrap();
+        assert_eq!(client.next_event().await, None);
+        assert!(client.subscribe_blocks().is_err());
+    }
+}